@@ -0,0 +1,19 @@
+//! Generates the gRPC peer service from `proto/peillute.proto`
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/peillute.proto");
+
+    // Only the server talks to the other sites
+    if std::env::var_os("CARGO_FEATURE_SERVER").is_none() {
+        return Ok(());
+    }
+
+    // Use a bundled protoc so that building does not require installing it
+    if std::env::var_os("PROTOC").is_none() {
+        // SAFETY: the build script does not spawn any thread
+        unsafe { std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?) };
+    }
+
+    tonic_build::configure().compile_protos(&["proto/peillute.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,278 @@
+//! Admin socket of the site
+//!
+//! A site run as a service has no terminal to type the CLI commands in. It
+//! listens on a UNIX domain socket, `peillute_<site-id>.sock` by default,
+//! which accepts the same commands, one per line:
+//!
+//! ```sh
+//! echo '/info' | nc -U peillute_A.sock
+//! echo '/deposit alice 10' | nc -U peillute_A.sock
+//! ```
+//!
+//! The words following a command answer its prompts in order. Arguments
+//! containing spaces are sent as a JSON object instead, which is answered
+//! with a JSON object holding the output lines or the error:
+//!
+//! ```sh
+//! echo '{"command": "/comment", "args": ["alice", "12", "A", "Thanks!"]}' | nc -U peillute_A.sock
+//! ```
+//!
+//! With an operators file, each connection starts with the default role of
+//! the file, and `/login <token>` gives it the role of an operator for its
+//! next commands, see [`crate::operators`].
+//!
+//! When started by systemd socket activation, the site listens on the socket
+//! passed by systemd instead of creating one. The socket is only accessible
+//! to the user running the site.
+
+#![cfg(all(feature = "server", unix))]
+
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// Path of the socket created by the site, removed when it stops
+static SOCKET_PATH: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+/// Command received on the admin socket, in the JSON protocol
+#[derive(Debug, PartialEq, serde::Deserialize)]
+pub struct AdminRequest {
+    /// Command, as typed in the CLI
+    pub command: String,
+    /// Answers to the prompts of the command, in order
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Answer to a command of the JSON protocol
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct AdminResponse {
+    /// True if the command succeeded
+    pub ok: bool,
+    /// Lines written by the command
+    pub output: Vec<String>,
+    /// Error of the command, if it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Returns the default path of the admin socket of a site
+pub fn default_path(site_id: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from(format!("peillute_{}.sock", site_id))
+}
+
+/// Splits a line of the admin socket into a command and the answers to its
+/// prompts
+///
+/// The commands taking their argument on the same line, such as
+/// `/balance <user>`, keep it.
+pub fn parse_line(line: &str) -> Result<(crate::control::Command, Vec<String>), String> {
+    use crate::control::{Command, parse_command};
+
+    let line = line.trim();
+    if line.starts_with('{') {
+        let request: AdminRequest =
+            serde_json::from_str(line).map_err(|e| format!("Invalid request: {}", e))?;
+        return Ok((parse_command(Ok(Some(request.command))), request.args));
+    }
+    let command = parse_command(Ok(Some(line.to_string())));
+    if !matches!(command, Command::Unknown(_)) {
+        return Ok((command, Vec::new()));
+    }
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default().to_string();
+    Ok((
+        parse_command(Ok(Some(name))),
+        words.map(str::to_string).collect(),
+    ))
+}
+
+/// Returns the listener passed by systemd socket activation, if any
+fn activated_listener() -> std::io::Result<Option<tokio::net::UnixListener>> {
+    use std::os::fd::FromRawFd;
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let nb_fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<u32>().ok())
+        .unwrap_or(0);
+    if !for_us || nb_fds == 0 {
+        return Ok(None);
+    }
+    // SAFETY: systemd passes the listening sockets from SD_LISTEN_FDS_START
+    // on, and the site takes ownership of the first one only
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(tokio::net::UnixListener::from_std(listener)?))
+}
+
+/// Creates the admin socket, replacing the one left by a previous run
+fn bind(path: &std::path::Path) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path)
+        && metadata.file_type().is_socket()
+    {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    let _ = SOCKET_PATH.set(path.to_path_buf());
+    Ok(listener)
+}
+
+/// Starts accepting the commands of the admin socket
+///
+/// The socket passed by systemd is used if any, otherwise the socket is
+/// created at the given path.
+pub fn start(path: &std::path::Path) -> std::io::Result<()> {
+    let listener = match activated_listener()? {
+        Some(listener) => {
+            log::info!("Admin socket passed by systemd");
+            listener
+        }
+        None => {
+            let listener = bind(path)?;
+            log::info!("Admin socket listening on {}", path.display());
+            listener
+        }
+    };
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_connection(stream));
+                }
+                Err(e) => log::error!("Cannot accept an admin connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Removes the admin socket created by the site
+pub fn remove_socket() {
+    if let Some(path) = SOCKET_PATH.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Executes the commands of an admin connection until it is closed
+async fn serve_connection(stream: tokio::net::UnixStream) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    let mut role = crate::operators::default_role();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = execute_line(&line, &mut role).await;
+        if let Err(e) = writer.write_all(answer.as_bytes()).await {
+            log::warn!("Cannot answer an admin command: {}", e);
+            return;
+        }
+    }
+}
+
+/// Runs a line of the admin protocol with the role of its connection,
+/// returns the lines written by the command
+///
+/// The command writes JSON if the line is a JSON object.
+pub async fn run_line(
+    line: &str,
+    role: &mut crate::operators::Role,
+) -> Result<Vec<String>, String> {
+    let json = line.trim_start().starts_with('{');
+    let (command, args) = parse_line(line)?;
+    crate::console::run_in_session(command, args, json, role).await
+}
+
+/// Executes a line of the admin socket, returns the text to answer
+async fn execute_line(line: &str, role: &mut crate::operators::Role) -> String {
+    let json = line.trim_start().starts_with('{');
+    let result = run_line(line, role).await;
+    if !json {
+        return match result {
+            Ok(output) => output.iter().map(|line| format!("{}\n", line)).collect(),
+            Err(e) => format!("❌ Error: {}\n", e),
+        };
+    }
+    let response = match result {
+        Ok(output) => AdminResponse {
+            ok: true,
+            output,
+            error: None,
+        },
+        Err(e) => AdminResponse {
+            ok: false,
+            output: Vec::new(),
+            error: Some(e),
+        },
+    };
+    serde_json::to_string(&response).unwrap_or_default() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Command;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("/info").unwrap(), (Command::Info, Vec::new()));
+        assert_eq!(
+            parse_line("/balance alice").unwrap(),
+            (Command::Balance("alice".to_string()), Vec::new())
+        );
+        assert_eq!(
+            parse_line(" /deposit alice 10 ").unwrap(),
+            (
+                Command::Deposit,
+                vec!["alice".to_string(), "10".to_string()]
+            )
+        );
+        assert_eq!(
+            parse_line(r#"{"command": "/comment", "args": ["alice", "12", "A", "Thanks!"]}"#)
+                .unwrap()
+                .1[3],
+            "Thanks!"
+        );
+        assert_eq!(
+            parse_line(r#"{"command": "/whoami"}"#).unwrap(),
+            (Command::WhoAmI, Vec::new())
+        );
+        assert!(parse_line("{not json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_line() {
+        let mut role = crate::operators::Role::Admin;
+        assert!(
+            execute_line("/help", &mut role)
+                .await
+                .starts_with("📜 Command list:\n")
+        );
+        assert_eq!(
+            execute_line("/deposit", &mut role).await,
+            "❌ Error: Missing argument: Username\n"
+        );
+        let response =
+            execute_line(r#"{"command": "/deposit", "args": ["alice"]}"#, &mut role).await;
+        assert_eq!(
+            response,
+            "{\"ok\":false,\"output\":[],\"error\":\"Missing argument: Deposit amount\"}\n"
+        );
+
+        let mut viewer = crate::operators::Role::Viewer;
+        assert!(
+            execute_line("/deposit alice 10", &mut viewer)
+                .await
+                .starts_with("❌ Error: This command needs the operator role")
+        );
+    }
+}
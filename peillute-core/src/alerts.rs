@@ -0,0 +1,507 @@
+//! Alerts on large transactions
+//!
+//! A site started with `--alerts-file alerts.toml` alerts its operators of
+//! every recorded transaction of at least a given amount, whether it was
+//! created on this site or received from the network:
+//!
+//! ```toml
+//! threshold = 500.0
+//! batch_window = 30
+//!
+//! [[channels]]
+//! kind = "smtp"
+//! server = "localhost:25"
+//! from = "peillute@example.com"
+//! to = ["treasurer@example.com"]
+//!
+//! [[channels]]
+//! kind = "matrix"
+//! url = "https://hookshot.example.com/webhook/abcdef"
+//! ```
+//!
+//! The alerts give the users, the amount, the site that created the
+//! transaction and its clock. They are gathered for `batch_window` seconds
+//! (10 by default) and sent together, so that the synchronization of a site
+//! receiving many large transactions sends one message per channel instead of
+//! one per transaction. The SMTP channel speaks plain SMTP without
+//! authentication, to a relay such as the local mail server. The Matrix
+//! channel posts to an incoming webhook, such as the generic webhooks of
+//! hookshot, the text of the alert as `{"text": ...}`.
+
+#![cfg(feature = "server")]
+
+/// Default time the alerts are gathered before being sent, in seconds
+const DEFAULT_BATCH_WINDOW: u64 = 10;
+
+/// Number of transactions detailed in a message, the others being counted
+const MAX_LISTED: usize = 20;
+
+/// Number of attempts to send a message on a channel
+const DELIVERY_ATTEMPTS: u32 = 3;
+
+/// Deadline of each delivery attempt
+const DELIVERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Maximum number of alerts waiting to be gathered
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Way the alerts are sent
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Channel {
+    /// Email sent through an SMTP relay
+    Smtp {
+        /// Address and port of the relay
+        server: String,
+        /// Sender of the emails
+        from: String,
+        /// Recipients of the emails
+        to: Vec<String>,
+    },
+    /// Message posted to a Matrix room through an incoming webhook
+    Matrix {
+        /// URL of the webhook
+        url: String,
+    },
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channel::Smtp { server, .. } => write!(f, "SMTP relay {}", server),
+            Channel::Matrix { url } => write!(f, "Matrix webhook {}", url),
+        }
+    }
+}
+
+/// Content of an alert configuration file
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct AlertConfig {
+    /// Smallest amount of the transactions alerted
+    pub threshold: f64,
+    /// Time the alerts are gathered before being sent, in seconds
+    #[serde(default = "default_batch_window")]
+    pub batch_window: u64,
+    /// Channels the alerts are sent on
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+}
+
+fn default_batch_window() -> u64 {
+    DEFAULT_BATCH_WINDOW
+}
+
+impl AlertConfig {
+    /// Parses and validates the content of an alert configuration file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: AlertConfig =
+            toml::from_str(content).map_err(|e| format!("invalid alert file: {}", e))?;
+        if !config.threshold.is_finite() || config.threshold <= 0.0 {
+            return Err(format!("invalid alert threshold {}", config.threshold));
+        }
+        for channel in &config.channels {
+            match channel {
+                Channel::Smtp { server, from, to } => {
+                    if server.is_empty() || !from.contains('@') || to.is_empty() {
+                        return Err(format!("incomplete SMTP channel '{}'", server));
+                    }
+                    if let Some(recipient) = to.iter().find(|recipient| !recipient.contains('@')) {
+                        return Err(format!("invalid recipient '{}'", recipient));
+                    }
+                }
+                Channel::Matrix { url } => {
+                    if !url.starts_with("http://") && !url.starts_with("https://") {
+                        return Err(format!("invalid Matrix webhook URL '{}'", url));
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads and validates an alert configuration file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read alert file {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+}
+
+/// Alerts gathered during a batch window
+#[derive(Debug, Default)]
+struct Batch {
+    /// Transactions detailed in the message
+    listed: Vec<crate::db::Transaction>,
+    /// Number of transactions alerted, listed or not
+    count: usize,
+    /// Sum of the amounts of the transactions alerted
+    total: f64,
+}
+
+impl Batch {
+    fn push(&mut self, transaction: crate::db::Transaction) {
+        self.count += 1;
+        self.total += transaction.amount;
+        if self.listed.len() < MAX_LISTED {
+            self.listed.push(transaction);
+        }
+    }
+}
+
+/// Describes an alerted transaction
+fn describe(transaction: &crate::db::Transaction) -> String {
+    use crate::webhooks::EventType;
+
+    let operation = match EventType::of(transaction) {
+        EventType::Deposit => format!(
+            "Deposit of {:.2} € on the account of {}",
+            transaction.amount, transaction.to_user
+        ),
+        EventType::Withdraw => format!(
+            "Withdrawal of {:.2} € from the account of {}",
+            transaction.amount, transaction.from_user
+        ),
+        EventType::Transfer => format!(
+            "Transfer of {:.2} € from {} to {}",
+            transaction.amount, transaction.from_user, transaction.to_user
+        ),
+    };
+    let mut clock: Vec<_> = transaction.vector_clock.iter().collect();
+    clock.sort();
+    let clock = clock
+        .iter()
+        .map(|(site, time)| format!("{}: {}", site, time))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "{}, created on site {} at Lamport time {} (vector clock {{{}}})",
+        operation, transaction.source_node, transaction.lamport_time, clock
+    )
+}
+
+/// Returns the subject and the text of the message of a batch
+fn compose(site_id: &str, batch: &Batch) -> (String, String) {
+    let subject = if batch.count == 1 {
+        format!("[Peillute] Large transaction recorded on site {}", site_id)
+    } else {
+        format!(
+            "[Peillute] {} large transactions recorded on site {}",
+            batch.count, site_id
+        )
+    };
+    let mut text = format!(
+        "Site {} recorded {} transaction(s) above the alert threshold, {:.2} € in total:\n",
+        site_id, batch.count, batch.total
+    );
+    for transaction in &batch.listed {
+        text.push_str(&format!("- {}\n", describe(transaction)));
+    }
+    if batch.count > batch.listed.len() {
+        text.push_str(&format!(
+            "... and {} more\n",
+            batch.count - batch.listed.len()
+        ));
+    }
+    (subject, text)
+}
+
+/// Alerting state of the site, set when alerts are configured
+struct Alerts {
+    threshold: f64,
+    queue: tokio::sync::mpsc::Sender<crate::db::Transaction>,
+}
+
+static ALERTS: std::sync::OnceLock<Alerts> = std::sync::OnceLock::new();
+
+/// Starts the task gathering and sending the alerts
+///
+/// Does nothing if the configuration has no channel.
+pub fn start(config: AlertConfig) {
+    if config.channels.is_empty() {
+        return;
+    }
+    let (queue, mut receiver) = tokio::sync::mpsc::channel(QUEUE_CAPACITY);
+    let alerts = Alerts {
+        threshold: config.threshold,
+        queue,
+    };
+    if ALERTS.set(alerts).is_err() {
+        log::warn!("Alerts are already started");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .expect("cannot build the alert HTTP client");
+        let window = std::time::Duration::from_secs(config.batch_window);
+        while let Some(first) = receiver.recv().await {
+            let mut batch = Batch::default();
+            batch.push(first);
+            let deadline = tokio::time::sleep(window);
+            tokio::pin!(deadline);
+            loop {
+                tokio::select! {
+                    _ = &mut deadline => break,
+                    next = receiver.recv() => match next {
+                        Some(transaction) => batch.push(transaction),
+                        None => break,
+                    },
+                }
+            }
+
+            let site_id = crate::state::SITE_INFO.read().await.get_site_id();
+            let (subject, text) = compose(&site_id, &batch);
+            for channel in &config.channels {
+                deliver(&client, channel, &site_id, &subject, &text).await;
+            }
+        }
+    });
+}
+
+/// Queues a recorded transaction for the alerts if its amount reaches the
+/// threshold
+///
+/// The alert is dropped if the alerts are not started or too many alerts are
+/// already waiting, so that recording a transaction never waits for them.
+pub fn notify(transaction: &crate::db::Transaction) {
+    let Some(alerts) = ALERTS.get() else {
+        return;
+    };
+    if transaction.amount < alerts.threshold {
+        return;
+    }
+    if let Err(e) = alerts.queue.try_send(transaction.clone()) {
+        log::warn!("Large transaction alert dropped: {}", e);
+    }
+}
+
+/// Sends a message on a channel, retrying when the delivery fails
+async fn deliver(
+    client: &reqwest::Client,
+    channel: &Channel,
+    site_id: &str,
+    subject: &str,
+    text: &str,
+) {
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        let result = match channel {
+            Channel::Smtp { server, from, to } => {
+                let data = mail_data(from, to, subject, text);
+                tokio::time::timeout(
+                    DELIVERY_TIMEOUT,
+                    send_mail(server, from, to, site_id, &data),
+                )
+                .await
+                .unwrap_or_else(|_| Err("timed out".to_string()))
+            }
+            Channel::Matrix { url } => post_matrix(client, url, subject, text).await,
+        };
+        let Err(error) = result else {
+            return;
+        };
+        log::warn!(
+            "Alert on {} failed (attempt {}/{}): {}",
+            channel,
+            attempt,
+            DELIVERY_ATTEMPTS,
+            error
+        );
+        if attempt < DELIVERY_ATTEMPTS {
+            tokio::time::sleep(crate::network::backoff_delay(attempt, attempt as u64)).await;
+        }
+    }
+    log::error!("Alert on {} abandoned", channel);
+}
+
+/// Posts a message to a Matrix webhook
+async fn post_matrix(
+    client: &reqwest::Client,
+    url: &str,
+    subject: &str,
+    text: &str,
+) -> Result<(), String> {
+    let body = serde_json::json!({ "text": format!("{}\n\n{}", subject, text) });
+    let request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string());
+    match request.send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("status {}", response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Returns the content of an email, with its headers, in the form sent after
+/// the DATA command of SMTP, ending with the final dot
+fn mail_data(from: &str, to: &[String], subject: &str, text: &str) -> String {
+    let mut data = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+        Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        from,
+        to.join(", "),
+        subject,
+        chrono::Local::now().to_rfc2822()
+    );
+    for line in text.lines() {
+        // A line starting with a dot is doubled, the single dot ending the data
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push_str(".\r\n");
+    data
+}
+
+/// Reads a reply of an SMTP server, failing unless its code is expected
+async fn smtp_reply<R>(reader: &mut R, expected: u16) -> Result<(), String>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    use tokio::io::AsyncBufReadExt;
+
+    loop {
+        let mut line = String::new();
+        if reader
+            .read_line(&mut line)
+            .await
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            return Err("connection closed by the server".to_string());
+        }
+        // The lines of a multiline reply but the last one have a dash after the code
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        return match line.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+            Some(code) if code == expected => Ok(()),
+            _ => Err(format!("unexpected reply '{}'", line.trim_end())),
+        };
+    }
+}
+
+/// Sends an email through an SMTP relay
+async fn send_mail(
+    server: &str,
+    from: &str,
+    to: &[String],
+    site_id: &str,
+    data: &str,
+) -> Result<(), String> {
+    use tokio::io::AsyncWriteExt;
+
+    let stream = tokio::net::TcpStream::connect(server)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = tokio::io::BufReader::new(reader);
+    smtp_reply(&mut reader, 220).await?;
+
+    let mut commands = vec![
+        (format!("EHLO peillute-{}\r\n", site_id), 250),
+        (format!("MAIL FROM:<{}>\r\n", from), 250),
+    ];
+    commands.extend(
+        to.iter()
+            .map(|recipient| (format!("RCPT TO:<{}>\r\n", recipient), 250)),
+    );
+    commands.push(("DATA\r\n".to_string(), 354));
+    commands.push((data.to_string(), 250));
+    commands.push(("QUIT\r\n".to_string(), 221));
+    for (command, expected) in commands {
+        writer
+            .write_all(command.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        smtp_reply(&mut reader, expected).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(from: &str, to: &str, amount: f64) -> crate::db::Transaction {
+        crate::db::Transaction {
+            from_user: from.to_string(),
+            to_user: to.to_string(),
+            amount,
+            lamport_time: 12,
+            source_node: "B".to_string(),
+            optional_msg: None,
+            vector_clock: [("B".to_string(), 12), ("A".to_string(), 3)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = AlertConfig::parse(
+            r#"
+            threshold = 500.0
+
+            [[channels]]
+            kind = "smtp"
+            server = "localhost:25"
+            from = "peillute@example.com"
+            to = ["treasurer@example.com"]
+
+            [[channels]]
+            kind = "matrix"
+            url = "https://hookshot.example.com/webhook/abc"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.batch_window, DEFAULT_BATCH_WINDOW);
+        assert_eq!(config.channels.len(), 2);
+
+        assert!(AlertConfig::parse("threshold = 0.0").is_err());
+        assert!(
+            AlertConfig::parse(
+                "threshold = 1.0\n[[channels]]\nkind = \"matrix\"\nurl = \"matrix.org\""
+            )
+            .is_err()
+        );
+        assert!(
+            AlertConfig::parse(
+                "threshold = 1.0\n[[channels]]\nkind = \"smtp\"\nserver = \"localhost:25\"\nfrom = \"a@b\"\nto = []"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_compose() {
+        let mut batch = Batch::default();
+        for _ in 0..MAX_LISTED + 2 {
+            batch.push(transaction("alice", "bob", 600.0));
+        }
+        batch.push(transaction("NULL", "carol", 1000.0));
+        let (subject, text) = compose("A", &batch);
+        assert_eq!(
+            subject,
+            "[Peillute] 23 large transactions recorded on site A"
+        );
+        assert!(text.contains("14200.00 € in total"));
+        assert!(text.contains(
+            "- Transfer of 600.00 € from alice to bob, created on site B at Lamport time 12 (vector clock {A: 3, B: 12})"
+        ));
+        assert!(!text.contains("carol"));
+        assert!(text.ends_with("... and 3 more\n"));
+
+        let data = mail_data(
+            "peillute@example.com",
+            &["treasurer@example.com".to_string()],
+            &subject,
+            "First line\n.hidden\n",
+        );
+        assert!(data.contains("\r\n\r\nFirst line\r\n..hidden\r\n.\r\n"));
+    }
+}
@@ -0,0 +1,124 @@
+//! Human-friendly aliases of the sites
+//!
+//! Site IDs are generated from the MAC address and the process ID, which makes
+//! them hard to tell apart. A site started with `--site-alias <name>` sends its
+//! alias to the sites it discovers and to the ones discovering it. The aliases
+//! known by the site are kept in the peer state and saved in the SiteAliases
+//! table, so that they survive a restart, and are shown instead of the site IDs
+//! in the web interface and the CLI tables.
+
+/// Maximum length of an alias, in characters
+pub const MAX_ALIAS_LEN: usize = 32;
+
+/// Returns the name to show for a site: its alias if known, its ID otherwise
+pub fn display_site(site_id: &str, aliases: &std::collections::HashMap<String, String>) -> String {
+    aliases
+        .get(site_id)
+        .cloned()
+        .unwrap_or_else(|| site_id.to_string())
+}
+
+#[cfg(feature = "server")]
+/// Checks that an alias can be shown in the interfaces
+pub fn validate_alias(alias: &str) -> Result<(), String> {
+    if alias.trim().is_empty() {
+        return Err("The alias cannot be empty".to_string());
+    }
+    if alias.chars().count() > MAX_ALIAS_LEN {
+        return Err(format!(
+            "The alias cannot exceed {} characters",
+            MAX_ALIAS_LEN
+        ));
+    }
+    if alias.chars().any(char::is_control) {
+        return Err("The alias cannot contain control characters".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Saves the alias of a site, replacing its previous one
+fn set_alias_in(conn: &rusqlite::Connection, site_id: &str, alias: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO SiteAliases (site_id, alias) VALUES (?1, ?2)
+        ON CONFLICT(site_id) DO UPDATE SET alias = excluded.alias",
+        rusqlite::params![site_id, alias],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the known aliases, by site ID
+fn aliases_in(
+    conn: &rusqlite::Connection,
+) -> rusqlite::Result<std::collections::HashMap<String, String>> {
+    let mut stmt = conn.prepare("SELECT site_id, alias FROM SiteAliases")?;
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the known aliases, by site ID
+pub fn get_aliases() -> rusqlite::Result<std::collections::HashMap<String, String>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    aliases_in(&conn)
+}
+
+#[cfg(feature = "server")]
+/// Records the alias of a site, in the peer state and in the database
+///
+/// An invalid alias is ignored.
+pub async fn record(site_id: &str, alias: &str) {
+    if let Err(e) = validate_alias(alias) {
+        log::warn!("Ignored the alias of site {}: {}", site_id, e);
+        return;
+    }
+    crate::state::PEERS
+        .write()
+        .await
+        .set_site_alias(site_id.to_string(), alias.to_string());
+    let saved = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        set_alias_in(&conn, site_id, alias)
+    };
+    if let Err(e) = saved {
+        log::error!("Cannot save the alias of site {}: {}", site_id, e);
+    }
+}
+
+#[cfg(feature = "server")]
+/// Loads the aliases saved in the database into the peer state
+pub async fn load() -> rusqlite::Result<()> {
+    let aliases = get_aliases()?;
+    let mut peers = crate::state::PEERS.write().await;
+    for (site_id, alias) in aliases {
+        peers.set_site_alias(site_id, alias);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases() {
+        assert!(validate_alias("Cafeteria").is_ok());
+        assert!(validate_alias("  ").is_err());
+        assert!(validate_alias(&"a".repeat(MAX_ALIAS_LEN + 1)).is_err());
+        assert!(validate_alias("bar\nA").is_err());
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        set_alias_in(&conn, "a4:5e:60:01_812", "Cafeteria").unwrap();
+        set_alias_in(&conn, "b8:27:eb:02_91", "Library").unwrap();
+        set_alias_in(&conn, "a4:5e:60:01_812", "Foyer").unwrap();
+
+        let aliases = aliases_in(&conn).unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(display_site("a4:5e:60:01_812", &aliases), "Foyer");
+        assert_eq!(display_site("unknown_1", &aliases), "unknown_1");
+    }
+}
@@ -0,0 +1,415 @@
+//! Validation of the monetary amounts
+//!
+//! The amounts typed in the forms and on the command line, or received by the
+//! API, are parsed and checked here before any command is submitted. An amount
+//! holds at most two decimals, lies between the limits of the site, and is
+//! never `NaN` nor infinite: `str::parse::<f64>` accepts `"NaN"` and `"inf"`,
+//! and such an amount would corrupt every balance computed from its
+//! transaction.
+//!
+//! Both the point and the comma are accepted as decimal separator, so that
+//! `12.50` and `12,50` are the same amount. The thousands may be separated by
+//! spaces, apostrophes, or the separator that is not the decimal one, as in
+//! `1 234,50`, `1'234.50` or `1.234,50`.
+//!
+//! The forms and the CLI also accept a small arithmetic expression, such as
+//! `12.5+3` or `(4,50+3)*2`, evaluated by [`evaluate`] without any `eval`: the
+//! same code checks the amount in the browser and on the site.
+
+/// Smallest amount of an operation by default
+pub const DEFAULT_MIN_AMOUNT: f64 = 0.01;
+
+/// Greatest amount of an operation by default
+pub const DEFAULT_MAX_AMOUNT: f64 = 100_000.0;
+
+/// Bounds of the accepted amounts, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AmountLimits {
+    /// Smallest amount accepted
+    pub min: f64,
+    /// Greatest amount accepted
+    pub max: f64,
+}
+
+impl AmountLimits {
+    /// Limits of the amounts that are not operations, such as thresholds
+    pub const UNBOUNDED: AmountLimits = AmountLimits {
+        min: 0.0,
+        max: f64::MAX,
+    };
+}
+
+impl Default for AmountLimits {
+    fn default() -> Self {
+        Self {
+            min: DEFAULT_MIN_AMOUNT,
+            max: DEFAULT_MAX_AMOUNT,
+        }
+    }
+}
+
+/// Characters separating the thousands, besides the point and the comma
+const THOUSANDS_SEPARATORS: [char; 4] = [' ', '\u{a0}', '\u{202f}', '\''];
+
+/// Checks an amount and rounds it to the cent
+pub fn validate(amount: f64, limits: &AmountLimits) -> Result<f64, String> {
+    if !amount.is_finite() {
+        return Err(format!("{} is not a valid amount.", amount));
+    }
+    if amount < 0.0 {
+        return Err("Amount cannot be negative.".to_string());
+    }
+    let cents = (amount * 100.0).round();
+    if (amount * 100.0 - cents).abs() > 1e-6 {
+        return Err(format!("{} has more than two decimals.", amount));
+    }
+    let amount = cents / 100.0;
+    if amount < limits.min {
+        return Err(format!("Amount must be at least {:.2}.", limits.min));
+    }
+    if amount > limits.max {
+        return Err(format!("Amount cannot exceed {:.2}.", limits.max));
+    }
+    Ok(amount)
+}
+
+/// Parses an amount typed by a user, with a point or a comma as decimal
+/// separator, and checks it
+pub fn parse(input: &str, limits: &AmountLimits) -> Result<f64, String> {
+    let input = input.trim();
+    if let Some(rest) = input.strip_prefix('-')
+        && parse_number(rest).is_ok()
+    {
+        return Err("Amount cannot be negative.".to_string());
+    }
+    validate(parse_number(input)?, limits)
+}
+
+/// Parses a positive number written like an amount, without checking it
+fn parse_number(input: &str) -> Result<f64, String> {
+    let input = input.trim();
+    let invalid = || format!("'{}' is not a valid amount.", input);
+
+    // the decimal separator is the last separator, unless it appears several
+    // times and thus separates thousands
+    let (integer, fraction) = match input.rfind(['.', ',']) {
+        Some(at) if input.matches(&input[at..at + 1]).count() == 1 => {
+            (&input[..at], &input[at + 1..])
+        }
+        _ => (input, ""),
+    };
+    let groups: Vec<&str> = integer
+        .split(|c: char| c == '.' || c == ',' || THOUSANDS_SEPARATORS.contains(&c))
+        .collect();
+    let grouped = groups.len() > 1
+        && (1..=3).contains(&groups[0].len())
+        && groups[1..].iter().all(|group| group.len() == 3);
+    if groups.len() > 1 && !grouped {
+        return Err(invalid());
+    }
+    let integer = groups.concat();
+    if (integer.is_empty() && fraction.is_empty())
+        || !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    if fraction.len() > 2 {
+        return Err(format!("'{}' has more than two decimals.", input));
+    }
+
+    format!("{}.{}", integer, fraction)
+        .parse::<f64>()
+        .map_err(|_| invalid())
+}
+
+/// Operators and parentheses of an expression
+const OPERATORS: [char; 6] = ['+', '-', '*', '/', '(', ')'];
+
+/// Greatest nesting of parentheses in an expression
+const MAX_DEPTH: usize = 8;
+
+/// Token of an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    /// Amount
+    Number(f64),
+    /// Operator or parenthesis
+    Operator(char),
+}
+
+/// Splits an expression into amounts and operators
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut number = String::new();
+    for c in input.chars() {
+        if OPERATORS.contains(&c) {
+            if !number.trim().is_empty() {
+                tokens.push(Token::Number(parse_number(&number)?));
+            }
+            number.clear();
+            tokens.push(Token::Operator(c));
+        } else {
+            number.push(c);
+        }
+    }
+    if !number.trim().is_empty() {
+        tokens.push(Token::Number(parse_number(&number)?));
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser of an expression
+struct Expression<'a> {
+    /// Tokens of the expression
+    tokens: &'a [Token],
+    /// Index of the next token
+    position: usize,
+    /// Nesting of the parentheses at the next token
+    depth: usize,
+}
+
+impl Expression<'_> {
+    /// Returns the next token without consuming it
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).copied()
+    }
+
+    /// Evaluates a sum or a difference of terms
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        while let Some(Token::Operator(operator @ ('+' | '-'))) = self.peek() {
+            self.position += 1;
+            let term = self.product()?;
+            value = if operator == '+' {
+                value + term
+            } else {
+                value - term
+            };
+        }
+        Ok(value)
+    }
+
+    /// Evaluates a product or a quotient of factors
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.factor()?;
+        while let Some(Token::Operator(operator @ ('*' | '/'))) = self.peek() {
+            self.position += 1;
+            let factor = self.factor()?;
+            if operator == '/' && factor == 0.0 {
+                return Err("Division by zero.".to_string());
+            }
+            value = if operator == '*' {
+                value * factor
+            } else {
+                value / factor
+            };
+        }
+        Ok(value)
+    }
+
+    /// Evaluates an amount, a negated factor or an expression in parentheses
+    fn factor(&mut self) -> Result<f64, String> {
+        let token = self.peek().ok_or("Incomplete expression.")?;
+        self.position += 1;
+        match token {
+            Token::Number(value) => Ok(value),
+            Token::Operator('-') => {
+                // the signs are folded in a loop rather than by recursion, so
+                // that a long run of them cannot overflow the stack
+                let mut negated = true;
+                while self.peek() == Some(Token::Operator('-')) {
+                    self.position += 1;
+                    negated = !negated;
+                }
+                let value = self.factor()?;
+                Ok(if negated { -value } else { value })
+            }
+            Token::Operator('(') if self.depth < MAX_DEPTH => {
+                self.depth += 1;
+                let value = self.sum()?;
+                if self.peek() != Some(Token::Operator(')')) {
+                    return Err("Missing closing parenthesis.".to_string());
+                }
+                self.position += 1;
+                self.depth -= 1;
+                Ok(value)
+            }
+            Token::Operator('(') => Err("Too many nested parentheses.".to_string()),
+            Token::Operator(operator) => Err(format!("Unexpected '{}'.", operator)),
+        }
+    }
+}
+
+/// Returns true if an amount typed by a user is an expression rather than a
+/// single amount
+pub fn is_expression(input: &str) -> bool {
+    input.trim().trim_start_matches('-').contains(OPERATORS)
+}
+
+/// Evaluates an amount typed by a user, which may be an arithmetic
+/// expression, and checks it
+///
+/// An expression adds, subtracts, multiplies and divides amounts written as
+/// [`parse`] accepts them, with parentheses, and its result is rounded to the
+/// cent.
+pub fn evaluate(input: &str, limits: &AmountLimits) -> Result<f64, String> {
+    if !is_expression(input) {
+        return parse(input, limits);
+    }
+    let tokens = tokenize(input)?;
+    let mut expression = Expression {
+        tokens: &tokens,
+        position: 0,
+        depth: 0,
+    };
+    let value = expression.sum()?;
+    if let Some(token) = expression.peek() {
+        return Err(match token {
+            Token::Operator(operator) => format!("Unexpected '{}'.", operator),
+            Token::Number(_) => format!("'{}' is not a valid expression.", input.trim()),
+        });
+    }
+    if !value.is_finite() {
+        return Err(format!("'{}' is not a valid expression.", input.trim()));
+    }
+    validate((value * 100.0).round() / 100.0, limits)
+}
+
+#[cfg(feature = "server")]
+/// Smallest amount of an operation, in cents, set from the command line at startup
+static MIN_CENTS: std::sync::atomic::AtomicU64 =
+    std::sync::atomic::AtomicU64::new((DEFAULT_MIN_AMOUNT * 100.0) as u64);
+
+#[cfg(feature = "server")]
+/// Greatest amount of an operation, in cents, set from the command line at startup
+static MAX_CENTS: std::sync::atomic::AtomicU64 =
+    std::sync::atomic::AtomicU64::new((DEFAULT_MAX_AMOUNT * 100.0) as u64);
+
+#[cfg(feature = "server")]
+/// Sets the bounds of the amounts of the operations submitted to the site
+pub fn set_limits(min: f64, max: f64) {
+    MIN_CENTS.store(
+        (min * 100.0).round() as u64,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+    MAX_CENTS.store(
+        (max * 100.0).round() as u64,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+}
+
+#[cfg(feature = "server")]
+/// Returns the bounds of the amounts of the operations submitted to the site
+pub fn limits() -> AmountLimits {
+    AmountLimits {
+        min: MIN_CENTS.load(std::sync::atomic::Ordering::Relaxed) as f64 / 100.0,
+        max: MAX_CENTS.load(std::sync::atomic::Ordering::Relaxed) as f64 / 100.0,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Checks the amount of an operation against the limits of the site, and
+/// rounds it to the cent
+pub fn check(amount: f64) -> Result<f64, String> {
+    validate(amount, &limits())
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let limits = AmountLimits::default();
+        assert_eq!(parse("12.5", &limits), Ok(12.5));
+        assert_eq!(parse(" 12,50 ", &limits), Ok(12.5));
+        assert_eq!(parse("1 234,56", &limits), Ok(1234.56));
+        assert_eq!(parse("1.234,56", &limits), Ok(1234.56));
+        assert_eq!(parse("1,234.56", &limits), Ok(1234.56));
+        assert_eq!(parse("1'234", &limits), Ok(1234.0));
+        assert_eq!(parse("1.234.567", &AmountLimits::UNBOUNDED), Ok(1234567.0));
+        assert_eq!(parse(",5", &limits), Ok(0.5));
+
+        for input in [
+            "", "NaN", "inf", "-inf", "1e3", "12a", "1.2.3,4", "12,34,5", ".",
+        ] {
+            assert!(parse(input, &limits).is_err(), "{input} was accepted");
+        }
+        assert_eq!(
+            parse("-5", &limits),
+            Err("Amount cannot be negative.".to_string())
+        );
+        assert_eq!(
+            parse("1,234", &limits),
+            Err("'1,234' has more than two decimals.".to_string())
+        );
+        assert_eq!(
+            parse("0", &limits),
+            Err("Amount must be at least 0.01.".to_string())
+        );
+        assert!(parse("100000.01", &limits).is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let limits = AmountLimits::default();
+        assert_eq!(evaluate("12.5+3", &limits), Ok(15.5));
+        assert_eq!(evaluate(" (4,50 + 3) * 2 ", &limits), Ok(15.0));
+        assert_eq!(evaluate("1 234,50 - 34.5", &limits), Ok(1200.0));
+        assert_eq!(evaluate("10/3", &limits), Ok(3.33));
+        assert_eq!(evaluate("-2+5", &limits), Ok(3.0));
+        assert_eq!(evaluate("12,50", &limits), Ok(12.5));
+        assert!(is_expression("12.5+3"));
+        assert!(!is_expression("-12.5"));
+
+        assert_eq!(
+            evaluate("-5", &limits),
+            Err("Amount cannot be negative.".to_string())
+        );
+        assert_eq!(
+            evaluate("2-5", &limits),
+            Err("Amount cannot be negative.".to_string())
+        );
+        assert_eq!(
+            evaluate("5/0", &limits),
+            Err("Division by zero.".to_string())
+        );
+        assert_eq!(
+            evaluate("(5+3", &limits),
+            Err("Missing closing parenthesis.".to_string())
+        );
+        assert_eq!(
+            evaluate("5+", &limits),
+            Err("Incomplete expression.".to_string())
+        );
+        assert_eq!(evaluate("5)", &limits), Err("Unexpected ')'.".to_string()));
+        assert_eq!(evaluate("10--5", &limits), Ok(15.0));
+        let signs = "-".repeat(100_000);
+        assert_eq!(evaluate(&format!("1+{signs}5"), &limits), Ok(6.0));
+        assert_eq!(evaluate(&format!("10+-{signs}5"), &limits), Ok(5.0));
+        for input in [
+            "NaN+1",
+            "1e3*2",
+            "5 3+1",
+            "((((((((((1))))))))))",
+            "1.234+1",
+        ] {
+            assert!(evaluate(input, &limits).is_err(), "{input} was accepted");
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let limits = AmountLimits::default();
+        assert_eq!(validate(0.1 + 0.2, &limits), Ok(0.3));
+        assert!(validate(f64::NAN, &limits).is_err());
+        assert!(validate(f64::INFINITY, &limits).is_err());
+        assert!(validate(-1.0, &limits).is_err());
+        assert!(validate(1.005, &limits).is_err());
+        assert_eq!(validate(0.0, &AmountLimits::UNBOUNDED), Ok(0.0));
+    }
+}
@@ -0,0 +1,191 @@
+//! Operations offered to the users of a site
+//!
+//! The web interface, the HTTP API and the CLI submit the same operations:
+//! this module checks their arguments once and turns them into critical
+//! commands, returning the ticket to follow their confirmation with
+//! [`crate::confirmation::wait_for_confirmation`]. The checks depending on the
+//! state of the database are made again by [`crate::control::execute_critical`]
+//! once the global mutex is acquired.
+
+#![cfg(feature = "server")]
+
+use crate::control::{CriticalCommands, enqueue_critical};
+
+/// Result of an operation, the ticket of its critical command
+pub type Ticket = Result<u64, Box<dyn std::error::Error>>;
+
+/// Creates a user in a ledger, the default one if empty
+///
+/// An unknown ledger is created with its first user.
+pub async fn create_user(name: String, ledger: String) -> Ticket {
+    if name.is_empty() {
+        return Err("User name cannot be empty.".into());
+    }
+    let ledger = if ledger.is_empty() {
+        crate::ledger::DEFAULT_LEDGER.to_string()
+    } else {
+        ledger
+    };
+    crate::ledger::validate_name(&ledger)?;
+    enqueue_critical(CriticalCommands::CreateUser { name, ledger }).await
+}
+
+/// Archives a user, who keeps its transactions
+///
+/// A snapshot is saved first, see [`crate::audit_log`].
+pub async fn archive_user(name: String) -> Ticket {
+    crate::audit_log::before(crate::audit_log::RiskyOperation::ArchiveUser, &name).await?;
+    enqueue_critical(CriticalCommands::DeactivateUser { name }).await
+}
+
+/// Restores an archived user
+pub async fn restore_user(name: String) -> Ticket {
+    enqueue_critical(CriticalCommands::ReactivateUser { name }).await
+}
+
+/// Deposits money on the account of a user
+pub async fn deposit(name: String, amount: f64) -> Ticket {
+    let amount = crate::amount::check(amount)?;
+    enqueue_critical(CriticalCommands::Deposit { name, amount }).await
+}
+
+/// Withdraws money from the account of a user
+pub async fn withdraw(name: String, amount: f64) -> Ticket {
+    let amount = crate::amount::check(amount)?;
+    enqueue_critical(CriticalCommands::Withdraw { name, amount }).await
+}
+
+/// Transfers money between two users
+pub async fn transfer(from: String, to: String, amount: f64) -> Ticket {
+    let amount = crate::amount::check(amount)?;
+    enqueue_critical(CriticalCommands::Transfer { from, to, amount }).await
+}
+
+/// Makes a payment, with a tip in percent, split with the co-payers if any
+///
+/// The co-payers must belong to the ledger of the user.
+pub async fn pay(name: String, amount: f64, tip_percent: f64, split_with: Vec<String>) -> Ticket {
+    let amount = crate::amount::check(amount)?;
+    if !tip_percent.is_finite() || tip_percent < 0.0 {
+        return Err("Tip cannot be negative.".into());
+    }
+    if !split_with.is_empty() {
+        let ledger = crate::ledger::ledger_of(&name)?;
+        for co_payer in &split_with {
+            if crate::ledger::ledger_of(co_payer)? != ledger {
+                return Err(
+                    format!("'{}' does not belong to ledger '{}'.", co_payer, ledger).into(),
+                );
+            }
+        }
+    }
+    enqueue_critical(CriticalCommands::Pay {
+        name,
+        amount,
+        tip_percent,
+        split_with,
+    })
+    .await
+}
+
+/// Splits a bill between several users, with the amount of each
+pub async fn split_bill(from: String, transfers: Vec<(String, f64)>) -> Ticket {
+    if transfers.is_empty() {
+        return Err("At least one beneficiary is required.".into());
+    }
+    let transfers = transfers
+        .into_iter()
+        .map(|(to, amount)| crate::amount::check(amount).map(|amount| (to, amount)))
+        .collect::<Result<Vec<_>, _>>()?;
+    enqueue_critical(CriticalCommands::BatchTransfer { from, transfers }).await
+}
+
+/// Runs an operation made of several steps, compensated if one of them fails
+pub async fn run_saga(steps: Vec<crate::saga::SagaStep>) -> Ticket {
+    crate::saga::validate(&steps)?;
+    enqueue_critical(CriticalCommands::Saga { steps }).await
+}
+
+/// Refunds a transaction, or asks its recipient to approve the refund
+pub async fn refund(name: String, lamport: i64, node: String) -> Ticket {
+    let cmd = crate::control::refund_command(name, lamport, node)?;
+    enqueue_critical(cmd).await
+}
+
+/// Comments or reacts to a transaction
+pub async fn comment(
+    lamport_time: i64,
+    source_node: String,
+    author: String,
+    text: String,
+    reaction: bool,
+) -> Ticket {
+    if text.trim().is_empty() {
+        return Err("A comment cannot be empty.".into());
+    }
+    enqueue_critical(CriticalCommands::Comment {
+        lamport_time,
+        source_node,
+        author,
+        text,
+        reaction,
+    })
+    .await
+}
+
+/// Takes a snapshot of the network, written to a file with an optional label
+pub async fn snapshot(label: Option<String>) -> Ticket {
+    if let Some(label) = &label {
+        crate::snapshot::validate_label(label)?;
+    }
+    enqueue_critical(CriticalCommands::FileSnapshot { label }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_checks() {
+        let error = |ticket: Ticket| ticket.unwrap_err().to_string();
+        assert_eq!(
+            error(create_user(String::new(), String::new()).await),
+            "User name cannot be empty."
+        );
+        assert!(
+            create_user("alice".to_string(), "Club Bar".to_string())
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            error(deposit("alice".to_string(), -1.0).await),
+            "Amount cannot be negative."
+        );
+        assert!(withdraw("alice".to_string(), f64::NAN).await.is_err());
+        assert_eq!(
+            error(pay("alice".to_string(), 0.0, 0.0, Vec::new()).await),
+            "Amount must be at least 0.01."
+        );
+        assert_eq!(
+            error(pay("alice".to_string(), 5.0, -10.0, Vec::new()).await),
+            "Tip cannot be negative."
+        );
+        assert_eq!(
+            error(split_bill("alice".to_string(), Vec::new()).await),
+            "At least one beneficiary is required."
+        );
+        assert_eq!(
+            error(
+                comment(
+                    1,
+                    "A".to_string(),
+                    "alice".to_string(),
+                    " ".to_string(),
+                    false
+                )
+                .await
+            ),
+            "A comment cannot be empty."
+        );
+    }
+}
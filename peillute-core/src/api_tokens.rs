@@ -0,0 +1,390 @@
+//! API tokens of the machine clients
+//!
+//! Scripts can call a few server functions of the web server, such as
+//! deposits, without going through the web interface. An administrator mints
+//! a token for each script, from the Tokens page or the `/mint_token` CLI
+//! command, with one of three scopes: read-only, transact as a given user, or
+//! admin, which may shut the site down or restart its networking.
+//! The token is shown once, only its SHA-256 hash being stored in the
+//! ApiTokens table, and is sent in an `Authorization: Bearer <token>` header.
+//!
+//! Requests carrying this header are checked before reaching the server
+//! functions: the token must be valid and not revoked, and the endpoint must be
+//! part of the API available to its scope. A token scoped to a user can then
+//! only operate on the account of this user. Requests without the header are
+//! those of the web interface and are not affected.
+
+/// What a token allows its holder to do
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TokenScope {
+    /// Read the balances and transactions of every user
+    ReadOnly,
+    /// Read the account of a user and submit operations on it
+    TransactAs(String),
+    /// Read the data of the site, shut it down and restart its networking
+    Admin,
+}
+
+impl std::fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = match self {
+            TokenScope::ReadOnly => "read-only".to_string(),
+            TokenScope::TransactAs(user) => format!("transact as {}", user),
+            TokenScope::Admin => "admin".to_string(),
+        };
+        f.pad(&scope)
+    }
+}
+
+/// API token, without its secret
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ApiToken {
+    /// Identifier of the token
+    pub id: i64,
+    /// Name given to the token, usually the script using it
+    pub label: String,
+    /// What the token allows
+    pub scope: TokenScope,
+    /// Local date at which the token was minted
+    pub created_at: String,
+    /// Local date at which the token was last used
+    pub last_used_at: Option<String>,
+    /// Whether the token was revoked
+    pub revoked: bool,
+}
+
+#[cfg(feature = "server")]
+/// Beginning of every token, to recognise them in scripts and logs
+const TOKEN_PREFIX: &str = "plt_";
+
+#[cfg(feature = "server")]
+/// Kind of access needed by an endpoint of the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Reads data
+    Read,
+    /// Submits an operation on the account of a user
+    Transact,
+    /// Manages the site itself
+    Admin,
+}
+
+#[cfg(feature = "server")]
+/// Endpoints available to the API tokens, with the access they need
+const API_ENDPOINTS: &[(&str, Access)] = &[
+    ("/api/balance", Access::Read),
+    ("/api/transactions", Access::Read),
+    ("/api/wait_for_confirmation", Access::Read),
+    ("/api/transaction_graph", Access::Read),
+    // mutations are checked by the resolvers, see [`crate::graphql`]
+    ("/graphql", Access::Read),
+    ("/api/deposit", Access::Transact),
+    ("/api/withdraw", Access::Transact),
+    ("/api/pay", Access::Transact),
+    ("/api/transfer", Access::Transact),
+    ("/api/shutdown_node", Access::Admin),
+    ("/api/restart_networking", Access::Admin),
+    ("/api/set_maintenance", Access::Admin),
+    // terminals attached with `peillute attach`, see [`crate::attach`]
+    ("/admin/events", Access::Admin),
+    ("/admin/command", Access::Admin),
+];
+
+#[cfg(feature = "server")]
+/// Returns the access needed by an endpoint, None if tokens cannot call it
+pub fn endpoint_access(path: &str) -> Option<Access> {
+    API_ENDPOINTS
+        .iter()
+        .find(|(endpoint, _)| *endpoint == path)
+        .map(|(_, access)| *access)
+}
+
+#[cfg(feature = "server")]
+/// Returns true if a token scope grants an access
+pub fn allows(scope: &TokenScope, access: Access) -> bool {
+    match scope {
+        TokenScope::ReadOnly => access == Access::Read,
+        TokenScope::TransactAs(_) => access != Access::Admin,
+        TokenScope::Admin => access != Access::Transact,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the hexadecimal SHA-256 hash of a token, which is what is stored
+fn hash(secret: &str) -> String {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Generates the secret of a new token from the random source of the system
+fn generate_secret() -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}{}", TOKEN_PREFIX, hex))
+}
+
+#[cfg(feature = "server")]
+/// Reads the scope stored in the scope and unique_name columns
+fn scope_from_row(scope: &str, user: Option<String>) -> TokenScope {
+    match (scope, user) {
+        ("transact", Some(user)) => TokenScope::TransactAs(user),
+        ("admin", _) => TokenScope::Admin,
+        _ => TokenScope::ReadOnly,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Stores a new token, returns its identifier
+fn insert_in(
+    conn: &rusqlite::Connection,
+    label: &str,
+    scope: &TokenScope,
+    secret: &str,
+) -> rusqlite::Result<i64> {
+    let (scope, user) = match scope {
+        TokenScope::ReadOnly => ("read", None),
+        TokenScope::TransactAs(user) => ("transact", Some(user.as_str())),
+        TokenScope::Admin => ("admin", None),
+    };
+    conn.execute(
+        "INSERT INTO ApiTokens (label, token_hash, scope, unique_name, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            label,
+            hash(secret),
+            scope,
+            user,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+#[cfg(feature = "server")]
+/// Returns the tokens of the site, most recent first
+fn tokens_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<ApiToken>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, label, scope, unique_name, created_at, last_used_at, revoked
+        FROM ApiTokens ORDER BY id DESC",
+    )?;
+    stmt.query_map([], |row| {
+        let scope: String = row.get(2)?;
+        Ok(ApiToken {
+            id: row.get(0)?,
+            label: row.get(1)?,
+            scope: scope_from_row(&scope, row.get(3)?),
+            created_at: row.get(4)?,
+            last_used_at: row.get(5)?,
+            revoked: row.get(6)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Revokes a token, returns false if there is no such token
+fn revoke_in(conn: &rusqlite::Connection, id: i64) -> rusqlite::Result<bool> {
+    let updated = conn.execute("UPDATE ApiTokens SET revoked = 1 WHERE id = ?1", [id])?;
+    Ok(updated > 0)
+}
+
+#[cfg(feature = "server")]
+/// Returns the scope of a valid token and records its use, None if the token
+/// is unknown or revoked
+fn authenticate_in(
+    conn: &rusqlite::Connection,
+    secret: &str,
+) -> rusqlite::Result<Option<TokenScope>> {
+    use rusqlite::OptionalExtension;
+
+    let token_hash = hash(secret);
+    let found = conn
+        .query_row(
+            "SELECT scope, unique_name FROM ApiTokens WHERE token_hash = ?1 AND revoked = 0",
+            [&token_hash],
+            |row| Ok(scope_from_row(&row.get::<_, String>(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    if found.is_some() {
+        conn.execute(
+            "UPDATE ApiTokens SET last_used_at = ?1 WHERE token_hash = ?2",
+            rusqlite::params![
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                token_hash
+            ],
+        )?;
+    }
+    Ok(found)
+}
+
+#[cfg(feature = "server")]
+/// Mints a new token, returns its secret, which cannot be retrieved later
+pub fn mint(label: &str, scope: &TokenScope) -> Result<String, Box<dyn std::error::Error>> {
+    if label.trim().is_empty() {
+        return Err("The label of the token cannot be empty".into());
+    }
+    if let TokenScope::TransactAs(user) = scope
+        && (crate::db::is_system_account(user) || !crate::db::user_exists(user)?)
+    {
+        return Err(format!("User '{}' does not exist.", user).into());
+    }
+    let secret = generate_secret()?;
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    insert_in(&conn, label.trim(), scope, &secret)?;
+    Ok(secret)
+}
+
+#[cfg(feature = "server")]
+/// Returns the tokens of the site, most recent first
+pub fn get_tokens() -> rusqlite::Result<Vec<ApiToken>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    tokens_in(&conn)
+}
+
+#[cfg(feature = "server")]
+/// Revokes a token, returns false if there is no such token
+pub fn revoke(id: i64) -> rusqlite::Result<bool> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    revoke_in(&conn, id)
+}
+
+#[cfg(feature = "server")]
+/// Returns the scope of a valid token and records its use, None if the token
+/// is unknown or revoked
+pub fn authenticate(secret: &str) -> rusqlite::Result<Option<TokenScope>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    authenticate_in(&conn, secret.trim())
+}
+
+#[cfg(feature = "server")]
+/// Checks the API token of a request to the web server, if it carries one
+///
+/// The scope of a valid token is added to the request, for the server
+/// functions to check the user they operate on.
+pub async fn authenticate_request(
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+
+    let Some(header) = request.headers().get(axum::http::header::AUTHORIZATION) else {
+        return next.run(request).await;
+    };
+    let Some(secret) = header
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return (StatusCode::UNAUTHORIZED, "Expected a Bearer token").into_response();
+    };
+    let scope = match authenticate(secret) {
+        Ok(Some(scope)) => scope,
+        Ok(None) => {
+            return (StatusCode::UNAUTHORIZED, "Invalid or revoked API token").into_response();
+        }
+        Err(e) => {
+            log::error!("Cannot check an API token: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match endpoint_access(request.uri().path()) {
+        Some(access) if allows(&scope, access) => {
+            request.extensions_mut().insert(scope);
+            next.run(request).await
+        }
+        Some(_) => (
+            StatusCode::FORBIDDEN,
+            format!("A {} token cannot call this endpoint", scope),
+        )
+            .into_response(),
+        None => (
+            StatusCode::FORBIDDEN,
+            "This endpoint is not available to API tokens",
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Checks that a token may operate on the account of a user
+///
+/// To be called by the server functions of the API taking a user, with the
+/// scope added to the request.
+pub fn authorize_user(scope: &TokenScope, user: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let TokenScope::TransactAs(allowed) = scope else {
+        // read-only and admin tokens cannot submit operations
+        return Ok(());
+    };
+    if crate::db::resolve_user(user)? == *allowed {
+        Ok(())
+    } else {
+        Err(format!("This token can only operate on the account of {}", allowed).into())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_tokens() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+
+        let secret = generate_secret().unwrap();
+        assert!(secret.starts_with(TOKEN_PREFIX));
+        let scope = TokenScope::TransactAs("alice".to_string());
+        let id = insert_in(&conn, "vending machine", &scope, &secret).unwrap();
+        insert_in(&conn, "dashboard", &TokenScope::ReadOnly, "plt_other").unwrap();
+
+        assert_eq!(
+            authenticate_in(&conn, &secret).unwrap(),
+            Some(scope.clone())
+        );
+        assert_eq!(authenticate_in(&conn, "plt_unknown").unwrap(), None);
+        let tokens = tokens_in(&conn).unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[1].scope, scope);
+        assert!(tokens[1].last_used_at.is_some());
+        assert!(tokens[0].last_used_at.is_none());
+
+        assert!(revoke_in(&conn, id).unwrap());
+        assert_eq!(authenticate_in(&conn, &secret).unwrap(), None);
+        assert!(!revoke_in(&conn, 42).unwrap());
+
+        insert_in(&conn, "kiosk", &TokenScope::Admin, "plt_admin").unwrap();
+        assert_eq!(
+            authenticate_in(&conn, "plt_admin").unwrap(),
+            Some(TokenScope::Admin)
+        );
+    }
+
+    #[test]
+    fn test_endpoint_access() {
+        assert_eq!(endpoint_access("/api/deposit"), Some(Access::Transact));
+        assert_eq!(endpoint_access("/api/balance"), Some(Access::Read));
+        assert_eq!(endpoint_access("/api/add_user"), None);
+        assert_eq!(endpoint_access("/graphql"), Some(Access::Read));
+
+        let alice = TokenScope::TransactAs("alice".to_string());
+        assert!(allows(&alice, Access::Transact));
+        assert!(allows(&TokenScope::ReadOnly, Access::Read));
+        assert!(!allows(&TokenScope::ReadOnly, Access::Transact));
+        assert_eq!(endpoint_access("/api/shutdown_node"), Some(Access::Admin));
+        assert!(allows(&TokenScope::Admin, Access::Admin));
+        assert!(allows(&TokenScope::Admin, Access::Read));
+        assert!(!allows(&TokenScope::Admin, Access::Transact));
+        assert!(!allows(&alice, Access::Admin));
+    }
+}
@@ -0,0 +1,271 @@
+//! Portable archive of the database of a site
+//!
+//! `/export_db <file>` writes the users, the transactions with their vector
+//! clocks and the clock of the site to a gzipped JSON archive.
+//! `/import_db <file>` merges such an archive into the database of the site,
+//! which is how a site is moved to new hardware: the archive of the old site
+//! is imported into the new one before it joins the network.
+//!
+//! The transactions already known by the site are skipped, like during the
+//! synchronization snapshot, and the other ones are applied in causal order.
+//! An archive whose clock is ahead of the site for the events of the site
+//! itself is refused, as it would hold transactions the site never made.
+
+#![cfg(feature = "server")]
+
+use crate::db::Transaction;
+
+/// Version of the format of the archives
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// User of an archive
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ArchivedUser {
+    /// Name of the user
+    pub name: String,
+    /// False if the user was archived
+    pub active: bool,
+}
+
+/// Content of an archive
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DatabaseArchive {
+    /// Version of the format of the archive
+    pub version: u32,
+    /// ID of the site the archive was exported from
+    pub site_id: String,
+    /// Clock of the site when the archive was exported
+    pub clock: crate::clock::Clock,
+    /// Users of the site
+    pub users: Vec<ArchivedUser>,
+    /// Transactions of the site, with their vector clocks
+    pub transactions: Vec<Transaction>,
+}
+
+/// Result of an import
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// Number of users created
+    pub users: usize,
+    /// Number of transactions applied
+    pub transactions: usize,
+    /// Number of transactions already known by the site
+    pub known: usize,
+    /// Transactions that could not be applied, with the reason
+    pub failed: Vec<(i64, String, String)>,
+}
+
+/// Writes an archive as gzipped JSON
+fn write_archive(path: &std::path::Path, archive: &DatabaseArchive) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    serde_json::to_writer(&mut encoder, archive)?;
+    encoder.finish()?.flush()
+}
+
+/// Reads an archive written by `write_archive`
+fn read_archive(path: &std::path::Path) -> Result<DatabaseArchive, Box<dyn std::error::Error>> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let archive: DatabaseArchive = serde_json::from_reader(flate2::read::GzDecoder::new(file))?;
+    if archive.version != ARCHIVE_VERSION {
+        return Err(format!(
+            "archive version {} is not supported, expected {}",
+            archive.version, ARCHIVE_VERSION
+        )
+        .into());
+    }
+    Ok(archive)
+}
+
+/// Checks that an archive can be merged into the database of a site
+///
+/// The archive must not know more events of the site than the site itself,
+/// unless it was exported from a site with the same ID, and the clocks of its
+/// transactions must not be ahead of the clock of the archive.
+pub fn validate(
+    archive: &DatabaseArchive,
+    local_site_id: &str,
+    local_clock: &crate::clock::Clock,
+) -> Result<(), String> {
+    let archive_vc = archive.clock.get_vector_clock_map();
+    if archive.site_id != local_site_id {
+        let known = archive_vc.get(local_site_id).copied().unwrap_or(0);
+        let local = local_clock
+            .get_vector_clock_map()
+            .get(local_site_id)
+            .copied()
+            .unwrap_or(0);
+        if known > local {
+            return Err(format!(
+                "the archive of site {} knows {} events of this site, which only made {}",
+                archive.site_id, known, local
+            ));
+        }
+    }
+    // a Lamport clock has no vector to compare the transactions with
+    if archive_vc.is_empty() {
+        return Ok(());
+    }
+    for tx in &archive.transactions {
+        let ahead = tx
+            .vector_clock
+            .iter()
+            .any(|(site, value)| archive_vc.get(site).copied().unwrap_or(0) < *value);
+        if ahead {
+            return Err(format!(
+                "transaction ({}, {}) is ahead of the clock of the archive",
+                tx.lamport_time, tx.source_node
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Exports the database of the site to an archive, returns it
+pub async fn export(path: &str) -> Result<DatabaseArchive, Box<dyn std::error::Error>> {
+    let (users, transactions) = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        (
+            crate::replay::load_users(&conn)?,
+            crate::replay::load_transactions(&conn)?,
+        )
+    };
+    let archive = DatabaseArchive {
+        version: ARCHIVE_VERSION,
+        site_id: crate::state::local_site().await.0,
+        clock: crate::state::get_clock().await,
+        users: users
+            .into_iter()
+            .map(|(name, _, active)| ArchivedUser { name, active })
+            .collect(),
+        transactions,
+    };
+    write_archive(std::path::Path::new(path), &archive)?;
+    Ok(archive)
+}
+
+/// Merges an archive into the database of the site
+///
+/// The clock of the site is then merged with the clock of the archive, so
+/// that the next events of the site come after the imported ones.
+pub async fn import(path: &str) -> Result<ImportReport, Box<dyn std::error::Error>> {
+    use crate::snapshot::TxSummary;
+
+    let archive = read_archive(std::path::Path::new(path))?;
+    let local_site_id = crate::state::local_site().await.0;
+    validate(&archive, &local_site_id, &crate::state::get_clock().await)?;
+    crate::audit_log::before(crate::audit_log::RiskyOperation::ImportDb, path).await?;
+
+    let mut report = ImportReport::default();
+    for user in &archive.users {
+        if crate::db::user_exists(&user.name)? {
+            continue;
+        }
+        crate::db::create_user(&user.name)?;
+        if !user.active {
+            crate::db::set_user_active(&user.name, false)?;
+        }
+        report.users += 1;
+    }
+
+    let known: std::collections::HashSet<TxSummary> = crate::db::get_local_transaction_log()?
+        .iter()
+        .map(TxSummary::from)
+        .collect();
+    let (missing, already_known): (Vec<Transaction>, Vec<Transaction>) = archive
+        .transactions
+        .into_iter()
+        .partition(|tx| !known.contains(&TxSummary::from(tx)));
+    report.known = already_known.len();
+
+    let (order, _) = crate::replay::causal_order(&missing);
+    for i in order {
+        let tx = &missing[i];
+        match crate::db::create_transaction(
+            &tx.from_user,
+            &tx.to_user,
+            tx.amount,
+            &tx.lamport_time,
+            &tx.source_node,
+            tx.optional_msg.as_deref().unwrap_or(""),
+            &tx.vector_clock,
+        ) {
+            Ok(()) => report.transactions += 1,
+            Err(e) => report
+                .failed
+                .push((tx.lamport_time, tx.source_node.clone(), e.to_string())),
+        }
+    }
+
+    crate::state::update_clock(Some(&archive.clock)).await;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(site_id: &str, vc: &[(&str, i64)], txs: Vec<Transaction>) -> DatabaseArchive {
+        let vc: std::collections::HashMap<String, i64> =
+            vc.iter().map(|(s, v)| (s.to_string(), *v)).collect();
+        DatabaseArchive {
+            version: ARCHIVE_VERSION,
+            site_id: site_id.to_string(),
+            clock: crate::clock::Clock::new_with_values(3, vc),
+            users: vec![ArchivedUser {
+                name: "alice".to_string(),
+                active: true,
+            }],
+            transactions: txs,
+        }
+    }
+
+    fn tx(source_node: &str, vc: &[(&str, i64)]) -> Transaction {
+        Transaction {
+            from_user: "NULL".to_string(),
+            to_user: "alice".to_string(),
+            amount: 10.0,
+            lamport_time: 1,
+            source_node: source_node.to_string(),
+            optional_msg: None,
+            vector_clock: vc.iter().map(|(s, v)| (s.to_string(), *v)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let local =
+            crate::clock::Clock::new_with_values(2, [("A".to_string(), 2)].into_iter().collect());
+
+        let ok = archive("B", &[("A", 2), ("B", 1)], vec![tx("B", &[("B", 1)])]);
+        assert!(validate(&ok, "A", &local).is_ok());
+
+        let ahead = archive("B", &[("A", 3), ("B", 1)], Vec::new());
+        assert!(validate(&ahead, "A", &local).is_err());
+        // the archive of the former hardware of the site may be ahead
+        let same_site = archive("A", &[("A", 5)], Vec::new());
+        assert!(validate(&same_site, "A", &local).is_ok());
+
+        let corrupt = archive("B", &[("B", 1)], vec![tx("B", &[("B", 2)])]);
+        assert!(validate(&corrupt, "A", &local).is_err());
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let path = std::env::temp_dir().join(format!(
+            "peillute_test_archive_{}.json.gz",
+            std::process::id()
+        ));
+        let written = archive("B", &[("B", 1)], vec![tx("B", &[("B", 1)])]);
+        write_archive(&path, &written).unwrap();
+        let read = read_archive(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.site_id, "B");
+        assert_eq!(read.users, written.users);
+        assert_eq!(read.transactions, written.transactions);
+        assert_eq!(read.clock.get_vector_clock_map()["B"], 1);
+    }
+}
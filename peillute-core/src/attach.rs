@@ -0,0 +1,428 @@
+//! Remote attach of a terminal to a running site
+//!
+//! A headless site, run as a service on another machine, can be followed and
+//! administered from a terminal with `peillute attach <host:port>`, the
+//! address of its web server, and an admin API token given with `--token`:
+//!
+//! ```sh
+//! peillute attach 10.0.0.2:11001 --token plt_...
+//! ```
+//!
+//! The terminal follows the `/admin/events` stream of the site, server-sent
+//! events carrying its log records, the progress of the waves going through
+//! it, the transactions it records and the results of the commands run by the
+//! attached terminals. Each line typed is sent to `/admin/command`, which runs
+//! it as a line of the admin socket, see [`crate::admin`], and publishes its
+//! result on the stream.
+//!
+//! Both endpoints are only available to admin tokens. The records logged at
+//! the info level and above are streamed, whatever the `RUST_LOG` filter of
+//! the site.
+
+#![cfg(feature = "server")]
+
+/// Level of the log records streamed to the attached terminals, and above
+const STREAMED_LEVEL: log::Level = log::Level::Info;
+
+/// Log record of the site
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LogRecord {
+    /// Level of the record
+    pub level: String,
+    /// Module that logged the record
+    pub target: String,
+    /// Text of the record
+    pub message: String,
+}
+
+/// Step of a wave going through the site
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaveStep {
+    /// The site initiated the wave
+    Started,
+    /// The wave reached the site, which diffuses it to its other neighbours
+    Diffused,
+    /// The site acknowledged a message of the wave to its sender
+    Acknowledged,
+    /// Every child of the site answered, the site answers its parent
+    Forwarded,
+    /// Every neighbour of the initiator answered, the wave ended
+    Completed,
+}
+
+/// Wave going through the site
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WaveActivity {
+    /// Site that initiated the wave
+    pub initiator: String,
+    /// Code of the diffused message
+    pub code: crate::message::NetworkMessageCode,
+    /// Step reached on the site
+    pub step: WaveStep,
+}
+
+/// Result of a command run by an attached terminal
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CommandResult {
+    /// Line sent by the terminal
+    pub line: String,
+    /// Lines written by the command
+    pub output: Vec<String>,
+    /// Error of the command, if it failed
+    pub error: Option<String>,
+}
+
+/// Publishes the step of the wave of a message to the attached terminals, and
+/// records it in the span of the wave, see [`crate::telemetry`]
+pub fn wave_step(message: &crate::message::Message, step: WaveStep) {
+    crate::telemetry::wave_step(message, step);
+    crate::node::emit(crate::node::NodeEvent::Wave(WaveActivity {
+        initiator: message.message_initiator_id.clone(),
+        code: message.code.clone(),
+        step,
+    }));
+}
+
+/// Logger of the site, which also streams the records to the attached
+/// terminals
+struct AttachLogger {
+    /// Logger writing the records on the terminal of the site
+    inner: Box<dyn log::Log>,
+}
+
+impl log::Log for AttachLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= STREAMED_LEVEL || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+        // the records of the libraries, such as the web server, are not ours
+        if record.level() <= STREAMED_LEVEL && record.target().starts_with("peillute") {
+            crate::node::emit(crate::node::NodeEvent::Log(LogRecord {
+                level: record.level().to_string(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            }));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger of the process, writing the records enabled by the
+/// given filter with the given logger and streaming the others to the
+/// attached terminals
+pub fn install_logger(
+    inner: Box<dyn log::Log>,
+    filter: log::LevelFilter,
+) -> Result<(), log::SetLoggerError> {
+    log::set_logger(Box::leak(Box::new(AttachLogger { inner })))?;
+    log::set_max_level(filter.max(STREAMED_LEVEL.to_level_filter()));
+    Ok(())
+}
+
+/// Returns the name and the data of the server-sent event of an event of the
+/// node, None if it is not streamed
+fn sse_event(event: &crate::node::NodeEvent) -> Option<(&'static str, String)> {
+    use crate::node::NodeEvent;
+
+    let (name, data) = match event {
+        NodeEvent::Transaction(transaction) => ("transaction", serde_json::to_string(transaction)),
+        NodeEvent::Log(record) => ("log", serde_json::to_string(record)),
+        NodeEvent::Wave(activity) => ("wave", serde_json::to_string(activity)),
+        NodeEvent::Command(result) => ("command", serde_json::to_string(result)),
+        NodeEvent::Live(_) => return None,
+    };
+    Some((name, data.ok()?))
+}
+
+/// Answer to the requests that do not carry an admin token
+///
+/// The requests carrying another token are refused by
+/// [`crate::api_tokens::authenticate_request`] already.
+fn admin_token_required() -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    (
+        axum::http::StatusCode::UNAUTHORIZED,
+        "An admin API token is required",
+    )
+        .into_response()
+}
+
+/// Streams the events of the site to an attached terminal
+async fn events(
+    scope: Option<axum::Extension<crate::api_tokens::TokenScope>>,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use tokio_stream::StreamExt;
+    use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+
+    if scope.is_none() {
+        return admin_token_required();
+    }
+    let stream = tokio_stream::wrappers::BroadcastStream::new(crate::node::subscribe()).filter_map(
+        |event| {
+            let event = match event {
+                Ok(event) => {
+                    let (name, data) = sse_event(&event)?;
+                    Event::default().event(name).data(data)
+                }
+                Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                    Event::default().event("lagged").data(missed.to_string())
+                }
+            };
+            Some(Ok::<_, std::convert::Infallible>(event))
+        },
+    );
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[cfg(unix)]
+/// Runs a command sent by an attached terminal and publishes its result
+async fn command(
+    scope: Option<axum::Extension<crate::api_tokens::TokenScope>>,
+    line: String,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    if scope.is_none() {
+        return admin_token_required();
+    }
+    let line = line.trim().to_string();
+    log::info!("Running '{}' for an attached terminal", line);
+    // the admin token of the terminal gives it the admin role
+    let mut role = crate::operators::Role::Admin;
+    let result = match crate::admin::run_line(&line, &mut role).await {
+        Ok(output) => CommandResult {
+            line,
+            output,
+            error: None,
+        },
+        Err(e) => CommandResult {
+            line,
+            output: Vec::new(),
+            error: Some(e),
+        },
+    };
+    crate::node::emit(crate::node::NodeEvent::Command(result.clone()));
+    axum::Json(result).into_response()
+}
+
+/// Returns the routes of the attached terminals, to add to the web server
+pub fn router() -> axum::Router {
+    let router = axum::Router::new().route("/admin/events", axum::routing::get(events));
+    #[cfg(unix)]
+    let router = router.route("/admin/command", axum::routing::post(command));
+    router
+}
+
+/// Reads the server-sent events of a stream, received in chunks
+#[derive(Debug, Default)]
+struct SseParser {
+    /// Bytes received after the last complete line
+    pending: Vec<u8>,
+    /// Name of the event being read
+    name: String,
+    /// Data lines of the event being read
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Reads a chunk of the stream, returns the name and the data of the
+    /// events it completes
+    fn feed(&mut self, chunk: &[u8]) -> Vec<(String, String)> {
+        self.pending.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    let name = std::mem::take(&mut self.name);
+                    let name = if name.is_empty() {
+                        "message".to_string()
+                    } else {
+                        name
+                    };
+                    events.push((name, std::mem::take(&mut self.data).join("\n")));
+                }
+                self.name.clear();
+                continue;
+            }
+            // lines starting with a colon are comments, such as keep-alives
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.name = value.to_string(),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// Returns the text printed by the terminal for an event of the stream, None
+/// if the event is unknown
+fn format_event(name: &str, data: &str) -> Option<String> {
+    match name {
+        "log" => {
+            let record: LogRecord = serde_json::from_str(data).ok()?;
+            Some(format!(
+                "[{} {}] {}",
+                record.level, record.target, record.message
+            ))
+        }
+        "wave" => {
+            let activity: WaveActivity = serde_json::from_str(data).ok()?;
+            Some(format!(
+                "🌊 {:?} wave of {}: {:?}",
+                activity.code, activity.initiator, activity.step
+            ))
+        }
+        "transaction" => {
+            let transaction: crate::db::Transaction = serde_json::from_str(data).ok()?;
+            Some(format!(
+                "💸 {} → {}: {:.2} (lamport {} of {})",
+                transaction.from_user,
+                transaction.to_user,
+                transaction.amount,
+                transaction.lamport_time,
+                transaction.source_node
+            ))
+        }
+        "command" => {
+            let result: CommandResult = serde_json::from_str(data).ok()?;
+            let mut lines = vec![format!("> {}", result.line)];
+            lines.extend(result.output);
+            lines.extend(result.error.map(|e| format!("❌ Error: {}", e)));
+            Some(lines.join("\n"))
+        }
+        "lagged" => Some(format!("⚠️ {} events were missed", data)),
+        _ => None,
+    }
+}
+
+/// Attaches the terminal to a running site, until the site closes the stream
+/// or the standard input is closed
+pub async fn run_client(addr: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::AsyncBufReadExt;
+
+    let base = if addr.contains("://") {
+        addr.trim_end_matches('/').to_string()
+    } else {
+        format!("http://{}", addr)
+    };
+    let client = reqwest::Client::new();
+    let mut stream = client
+        .get(format!("{}/admin/events", base))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    if !stream.status().is_success() {
+        return Err(format!(
+            "cannot attach to {}: {} {}",
+            addr,
+            stream.status(),
+            stream.text().await.unwrap_or_default()
+        )
+        .into());
+    }
+    println!(
+        "📡 Attached to {}. Type the commands to run on the site, Ctrl-D to detach.",
+        addr
+    );
+
+    let mut parser = SseParser::default();
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        tokio::select! {
+            chunk = stream.chunk() => {
+                let Some(chunk) = chunk? else {
+                    println!("The site closed the stream.");
+                    return Ok(());
+                };
+                for (name, data) in parser.feed(&chunk) {
+                    if let Some(text) = format_event(&name, &data) {
+                        println!("{}", text);
+                    }
+                }
+            }
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // the result is printed when it comes back on the stream
+                let answer = client
+                    .post(format!("{}/admin/command", base))
+                    .bearer_auth(token)
+                    .body(line)
+                    .send()
+                    .await?;
+                if !answer.status().is_success() {
+                    eprintln!("❌ Error: {}", answer.text().await.unwrap_or_default());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b": keep-alive\n\nevent: wave\nda").is_empty());
+        assert_eq!(
+            parser.feed(b"ta: {\"a\":1}\r\n\ndata: first\ndata: second\n\n"),
+            vec![
+                ("wave".to_string(), "{\"a\":1}".to_string()),
+                ("message".to_string(), "first\nsecond".to_string()),
+            ]
+        );
+        assert!(parser.pending.is_empty());
+    }
+
+    #[test]
+    fn test_events() {
+        let activity = crate::node::NodeEvent::Wave(WaveActivity {
+            initiator: "A".to_string(),
+            code: crate::message::NetworkMessageCode::Transaction,
+            step: WaveStep::Completed,
+        });
+        let (name, data) = sse_event(&activity).unwrap();
+        assert_eq!(name, "wave");
+        assert_eq!(
+            format_event(name, &data).unwrap(),
+            "🌊 Transaction wave of A: Completed"
+        );
+
+        let result = crate::node::NodeEvent::Command(CommandResult {
+            line: "/balance bob".to_string(),
+            output: Vec::new(),
+            error: Some("unknown user".to_string()),
+        });
+        let (name, data) = sse_event(&result).unwrap();
+        assert_eq!(
+            format_event(name, &data).unwrap(),
+            "> /balance bob\n❌ Error: unknown user"
+        );
+        assert_eq!(format_event("other", "{}"), None);
+    }
+}
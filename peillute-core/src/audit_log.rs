@@ -0,0 +1,163 @@
+//! Log of the risky administration operations
+//!
+//! Archiving a user, importing a database archive and forcing the release of
+//! the global mutex change the state of the network in ways that are hard to
+//! undo. Before running them, the site saves a snapshot to a file and waits
+//! until it is written, then records the operation in the AuditLog table
+//! with the name of the file, the rollback point to restore if the operation
+//! went wrong. The operation is refused if the snapshot cannot be saved.
+//!
+//! The snapshot is a global one, taken through the global mutex, except
+//! before a forced release: the mutex is then held by the crashed site, so
+//! only the state of the local site is saved. A client site, whose operations
+//! are run by its full node, records the operation without a rollback point.
+//!
+//! `/audit_log` prints the log, most recent first.
+
+#![cfg(feature = "server")]
+
+/// Time waited for the rollback point of an operation to be saved
+pub const ROLLBACK_POINT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Number of entries printed by `/audit_log`
+pub const DEFAULT_ENTRIES: usize = 50;
+
+/// Administration operation preceded by a rollback point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskyOperation {
+    /// Archiving a user, on every site
+    ArchiveUser,
+    /// Merging a database archive into the database of the site
+    ImportDb,
+    /// Clearing the global mutex request of a crashed site, on every site
+    ForceRelease,
+}
+
+impl RiskyOperation {
+    /// Returns the name of the operation, as recorded in the log
+    pub fn name(&self) -> &'static str {
+        match self {
+            RiskyOperation::ArchiveUser => "archive_user",
+            RiskyOperation::ImportDb => "import_db",
+            RiskyOperation::ForceRelease => "force_release",
+        }
+    }
+}
+
+/// Operation recorded in the audit log
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AuditEntry {
+    /// Time the operation was started
+    pub at: String,
+    /// Name of the operation
+    pub operation: String,
+    /// User, archive or site the operation applies to
+    pub target: String,
+    /// Snapshot file saved before the operation, if any
+    pub rollback: Option<String>,
+}
+
+/// Records an operation in the audit log of a database
+fn record_in(conn: &rusqlite::Connection, entry: &AuditEntry) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO AuditLog (at, operation, target, rollback) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![entry.at, entry.operation, entry.target, entry.rollback],
+    )?;
+    Ok(())
+}
+
+/// Returns the most recent entries of the audit log of a database, most
+/// recent first
+fn entries_in(conn: &rusqlite::Connection, limit: usize) -> rusqlite::Result<Vec<AuditEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT at, operation, target, rollback FROM AuditLog ORDER BY id DESC LIMIT ?1",
+    )?;
+    stmt.query_map([limit as i64], |row| {
+        Ok(AuditEntry {
+            at: row.get(0)?,
+            operation: row.get(1)?,
+            target: row.get(2)?,
+            rollback: row.get(3)?,
+        })
+    })?
+    .collect()
+}
+
+/// Returns the most recent entries of the audit log, most recent first
+pub fn entries(limit: usize) -> rusqlite::Result<Vec<AuditEntry>> {
+    entries_in(&crate::db::DB_CONN.lock().unwrap(), limit)
+}
+
+/// Saves the rollback point of an operation and records the operation in the
+/// audit log
+///
+/// Returns the name of the snapshot file, None on a client site. The
+/// operation must not run if an error is returned.
+pub async fn before(
+    operation: RiskyOperation,
+    target: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let label = Some(format!("before-{}", operation.name()));
+    let rollback = if crate::client::full_node().is_some() {
+        None
+    } else if operation == RiskyOperation::ForceRelease {
+        Some(crate::snapshot::persist_local(label).await?)
+    } else {
+        Some(
+            crate::snapshot::snapshot_and_wait(label, ROLLBACK_POINT_TIMEOUT)
+                .await
+                .map_err(|e| format!("Cannot save the rollback point: {}", e))?,
+        )
+    };
+
+    let entry = AuditEntry {
+        at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        operation: operation.name().to_string(),
+        target: target.to_string(),
+        rollback: rollback.clone(),
+    };
+    record_in(&crate::db::DB_CONN.lock().unwrap(), &entry)?;
+    log::info!(
+        "{} on {}, rollback point: {}",
+        entry.operation,
+        entry.target,
+        rollback.as_deref().unwrap_or("none")
+    );
+    Ok(rollback)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        for (i, operation) in [RiskyOperation::ArchiveUser, RiskyOperation::ForceRelease]
+            .iter()
+            .enumerate()
+        {
+            record_in(
+                &conn,
+                &AuditEntry {
+                    at: format!("2025-01-0{} 10:00:00", i + 1),
+                    operation: operation.name().to_string(),
+                    target: "alice".to_string(),
+                    rollback: Some(format!("snapshot_A_2025010{}_100000.json.gz", i + 1)),
+                },
+            )
+            .unwrap();
+        }
+
+        let entries = entries_in(&conn, 10).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].operation, "force_release");
+        assert_eq!(
+            entries[1].rollback.as_deref(),
+            Some("snapshot_A_20250101_100000.json.gz")
+        );
+        assert_eq!(entries_in(&conn, 1).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+//! Read-through cache of the account balances
+//!
+//! The balance of an account is the sum of its postings, a table growing with
+//! the history of the site, and it is read on every user page and before every
+//! transaction. The balances read are kept in memory, in cents, and the
+//! postings of each new transaction are added to the cached balances of its
+//! accounts instead of summing the table again. The cache is emptied when the
+//! postings change in any other way: another database opened, a user renamed,
+//! or a split payment or batch transfer rolled back.
+//!
+//! The cache is only read and written while the database of the site is
+//! locked, so that it follows the order of the writes. `/check_balance_cache`
+//! compares the cached balances with the postings and drops the stale ones.
+
+#![cfg(feature = "server")]
+
+/// Cached balance that differs from the postings of its account
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CacheMismatch {
+    /// Account of the balance
+    pub account: String,
+    /// Balance in the cache
+    pub cached: f64,
+    /// Balance computed from the postings
+    pub actual: f64,
+}
+
+/// Result of the check of the cache
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct CacheCheck {
+    /// Number of balances in the cache
+    pub cached: usize,
+    /// Cached balances that were stale, dropped from the cache
+    pub mismatches: Vec<CacheMismatch>,
+}
+
+lazy_static::lazy_static! {
+    /// Balance of the accounts read since the cache was emptied, in cents
+    static ref BALANCES: std::sync::Mutex<std::collections::HashMap<String, i64>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+/// Returns the cached balance of an account, in cents
+pub fn get(account: &str) -> Option<i64> {
+    BALANCES.lock().unwrap().get(account).copied()
+}
+
+/// Caches the balance of an account, in cents
+pub fn insert(account: &str, cents: i64) {
+    BALANCES.lock().unwrap().insert(account.to_string(), cents);
+}
+
+/// Adds a posting to the balance of an account, if cached
+pub fn add(account: &str, cents: i64) {
+    if let Some(balance) = BALANCES.lock().unwrap().get_mut(account) {
+        *balance += cents;
+    }
+}
+
+/// Empties the cache
+pub fn clear() {
+    BALANCES.lock().unwrap().clear();
+}
+
+/// Compares the cached balances with the postings of a database, dropping the
+/// stale ones
+fn check_in(conn: &rusqlite::Connection) -> rusqlite::Result<CacheCheck> {
+    let mut balances = BALANCES.lock().unwrap();
+    let mut mismatches = Vec::new();
+    for (account, cached) in balances.iter() {
+        let actual = crate::db::account_cents(conn, account)?;
+        if actual != *cached {
+            mismatches.push(CacheMismatch {
+                account: account.clone(),
+                cached: *cached as f64 / 100.0,
+                actual: actual as f64 / 100.0,
+            });
+        }
+    }
+    for mismatch in &mismatches {
+        balances.remove(&mismatch.account);
+    }
+    mismatches.sort_by(|a, b| a.account.cmp(&b.account));
+    Ok(CacheCheck {
+        cached: balances.len() + mismatches.len(),
+        mismatches,
+    })
+}
+
+/// Compares the cached balances with the postings, dropping the stale ones
+pub fn check() -> rusqlite::Result<CacheCheck> {
+    check_in(&crate::db::DB_CONN.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        crate::db::insert_postings(&conn, "NULL", "cache-alice", 10.0, 1, "A", "Deposit").unwrap();
+        insert("cache-alice", 1000);
+
+        crate::db::insert_postings(&conn, "cache-alice", "cache-bob", 2.5, 2, "A", "").unwrap();
+        add("cache-alice", -250);
+        add("cache-bob", 250);
+        assert_eq!(get("cache-alice"), Some(750));
+        // only the balances read are cached
+        assert_eq!(get("cache-bob"), None);
+
+        insert("cache-bob", 100);
+        let check = check_in(&conn).unwrap();
+        assert_eq!(
+            check.mismatches,
+            vec![CacheMismatch {
+                account: "cache-bob".to_string(),
+                cached: 1.0,
+                actual: 2.5,
+            }]
+        );
+        assert_eq!(get("cache-alice"), Some(750));
+        assert_eq!(get("cache-bob"), None);
+    }
+}
@@ -0,0 +1,507 @@
+//! Load test of running sites
+//!
+//! `--bench <bench.toml>` drives sites started beforehand through the server
+//! functions of their web interface, as the browsers of many users would, and
+//! prints a JSON report to compare the performances of two versions. Each
+//! user acts from a browser session of its own on each site, see
+//! [`crate::sessions`]. The report gives:
+//!
+//! - the throughput of the confirmed transactions
+//! - the latency of the submission of a transaction, until its ticket is
+//!   returned, and of its diffusion, until every site acknowledged it
+//! - the mean time each site spent writing a transaction to its database,
+//!   read from the `/metrics` endpoint before and after the run
+//!
+//! The load is written in TOML:
+//!
+//! ```toml
+//! # web servers of the sites, the transactions being spread over them
+//! nodes = ["http://127.0.0.1:8080", "http://127.0.0.1:8081"]
+//! # existing users, a transfer going from a user to the next one
+//! users = ["alice", "bob"]
+//! operation = "transfer"
+//! transactions = 1000
+//! # transactions in progress at the same time
+//! concurrency = 16
+//! amount = 0.01
+//! ```
+
+#![cfg(feature = "server")]
+
+use crate::confirmation::ConfirmationStatus;
+
+/// Maximum time of a request to a site, above the poll of a confirmation
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Transaction submitted by the load test
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Deposit on the account of a user
+    #[default]
+    Deposit,
+    /// Transfer from a user to the next one
+    Transfer,
+}
+
+/// Load to submit to the sites
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct BenchConfig {
+    /// Base URLs of the web servers of the sites
+    pub nodes: Vec<String>,
+    /// Users the transactions are made for
+    pub users: Vec<String>,
+    /// Transaction submitted
+    #[serde(default)]
+    pub operation: Operation,
+    /// Number of transactions to submit
+    #[serde(default = "default_transactions")]
+    pub transactions: usize,
+    /// Number of transactions in progress at the same time
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Amount of each transaction
+    #[serde(default = "default_amount")]
+    pub amount: f64,
+}
+
+fn default_transactions() -> usize {
+    100
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_amount() -> f64 {
+    1.0
+}
+
+impl BenchConfig {
+    /// Parses and validates the content of a load file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: BenchConfig =
+            toml::from_str(content).map_err(|e| format!("invalid load: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads and validates a load file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read load {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nodes.is_empty() {
+            return Err("the load has no node".to_string());
+        }
+        if self.users.is_empty() {
+            return Err("the load has no user".to_string());
+        }
+        if self.operation == Operation::Transfer && self.users.len() < 2 {
+            return Err("transfers need at least two users".to_string());
+        }
+        if self.concurrency == 0 {
+            return Err("the concurrency must be at least 1".to_string());
+        }
+        if self.amount <= 0.0 {
+            return Err("the amount must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Percentiles of a latency, in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct Latencies {
+    /// Median
+    pub p50_ms: f64,
+    /// 95th percentile
+    pub p95_ms: f64,
+    /// 99th percentile
+    pub p99_ms: f64,
+    /// Slowest
+    pub max_ms: f64,
+}
+
+impl Latencies {
+    /// Computes the percentiles of the given durations
+    fn of(mut durations: Vec<std::time::Duration>) -> Self {
+        durations.sort();
+        let ms = |p| percentile(&durations, p).as_secs_f64() * 1000.0;
+        Latencies {
+            p50_ms: ms(50.0),
+            p95_ms: ms(95.0),
+            p99_ms: ms(99.0),
+            max_ms: ms(100.0),
+        }
+    }
+}
+
+/// Database writes of a site during the run
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct NodeWrites {
+    /// Base URL of the web server of the site
+    pub node: String,
+    /// Transactions written, received from the other sites included
+    pub writes: u64,
+    /// Mean time of a write in milliseconds, unknown if the metrics are unavailable
+    pub mean_ms: Option<f64>,
+}
+
+/// Result of a load test
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct Report {
+    /// Transaction submitted
+    pub operation: Operation,
+    /// Transactions in progress at the same time
+    pub concurrency: usize,
+    /// Transactions submitted
+    pub transactions: usize,
+    /// Transactions acknowledged by every site
+    pub confirmed: usize,
+    /// Transactions refused or failed
+    pub failed: usize,
+    /// Distinct errors of the failed transactions
+    pub errors: Vec<String>,
+    /// Duration of the run, in seconds
+    pub duration_secs: f64,
+    /// Confirmed transactions per second
+    pub throughput: f64,
+    /// Latency of the submissions
+    pub submit_latency: Latencies,
+    /// Latency of the confirmations, from the submission
+    pub diffusion_latency: Latencies,
+    /// Database writes of each site
+    pub db_writes: Vec<NodeWrites>,
+}
+
+/// Returns the duration below which `p` percent of the sorted durations are
+fn percentile(sorted: &[std::time::Duration], p: f64) -> std::time::Duration {
+    if sorted.is_empty() {
+        return std::time::Duration::ZERO;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Returns the value of a metric in a Prometheus text document
+fn parse_metric(text: &str, name: &str) -> Option<f64> {
+    text.lines().find_map(|line| {
+        let (metric, value) = line.split_once(' ')?;
+        if metric == name {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Opens a browser session acting as a user on a site, see
+/// [`crate::sessions`], and returns its cookie
+async fn enter_user(client: &reqwest::Client, node: &str, user: &str) -> Result<String, String> {
+    let response = client
+        .post(format!("{}/api/enter_user", node.trim_end_matches('/')))
+        .form(&[("name", user)])
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let cookie = response
+        .headers()
+        .get(reqwest::header::SET_COOKIE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::to_string);
+    match cookie {
+        Some(cookie) if status.is_success() => Ok(cookie),
+        _ => Err(format!(
+            "cannot act as {} on {}: {}",
+            user,
+            node,
+            response.text().await.unwrap_or_default()
+        )),
+    }
+}
+
+/// Calls a server function of a site, in a session if given, and decodes
+/// its JSON result
+async fn call<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    node: &str,
+    endpoint: &str,
+    form: &[(&str, String)],
+    session: Option<&str>,
+) -> Result<T, String> {
+    let mut request = client
+        .post(format!("{}/api/{}", node.trim_end_matches('/'), endpoint))
+        .form(form);
+    if let Some(cookie) = session {
+        request = request.header(reqwest::header::COOKIE, cookie);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("{} returned {}: {}", endpoint, status, body));
+    }
+    serde_json::from_str(&body).map_err(|e| format!("invalid {} result: {}", endpoint, e))
+}
+
+/// Returns the number of transactions written by a site and the time spent writing them
+async fn write_stats(client: &reqwest::Client, node: &str) -> Result<(u64, f64), String> {
+    let text = client
+        .get(format!("{}/metrics", node.trim_end_matches('/')))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    let count = parse_metric(&text, "peillute_db_write_seconds_count")
+        .ok_or("the site does not report its database writes")?;
+    let sum = parse_metric(&text, "peillute_db_write_seconds_sum")
+        .ok_or("the site does not report its database writes")?;
+    Ok((count as u64, sum))
+}
+
+/// Cookies of the sessions acting as each user, by site and user
+type Sessions = std::collections::HashMap<(String, String), String>;
+
+/// Submits a transaction and waits for its confirmation
+///
+/// Returns the latency of the submission and of the confirmation.
+async fn run_one(
+    client: &reqwest::Client,
+    config: &BenchConfig,
+    sessions: &Sessions,
+    index: usize,
+) -> Result<(std::time::Duration, std::time::Duration), String> {
+    let node = &config.nodes[index % config.nodes.len()];
+    let user = &config.users[index % config.users.len()];
+    let session = sessions
+        .get(&(node.clone(), user.clone()))
+        .map(String::as_str);
+    let start = std::time::Instant::now();
+    let ticket: u64 = match config.operation {
+        Operation::Deposit => {
+            call(
+                client,
+                node,
+                "deposit",
+                &[
+                    ("user", user.clone()),
+                    ("amount", config.amount.to_string()),
+                ],
+                session,
+            )
+            .await?
+        }
+        Operation::Transfer => {
+            let to = &config.users[(index + 1) % config.users.len()];
+            call(
+                client,
+                node,
+                "transfer",
+                &[
+                    ("from_user", user.clone()),
+                    ("to_user", to.clone()),
+                    ("amount", config.amount.to_string()),
+                    ("_optional_message", String::new()),
+                ],
+                session,
+            )
+            .await?
+        }
+    };
+    let submitted = start.elapsed();
+
+    loop {
+        let status: ConfirmationStatus = call(
+            client,
+            node,
+            "wait_for_confirmation",
+            &[("ticket", ticket.to_string())],
+            None,
+        )
+        .await?;
+        match status {
+            ConfirmationStatus::Confirmed { .. } => return Ok((submitted, start.elapsed())),
+            ConfirmationStatus::Failed(e) => return Err(e),
+            ConfirmationStatus::Unknown => return Err(format!("ticket {} was lost", ticket)),
+            ConfirmationStatus::Queued
+            | ConfirmationStatus::Offline
+            | ConfirmationStatus::Diffusing => {}
+        }
+    }
+}
+
+/// Runs a load test against the sites
+pub async fn run(config: &BenchConfig) -> Result<Report, String> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut before = Vec::new();
+    for node in &config.nodes {
+        before.push(write_stats(&client, node).await);
+    }
+
+    // each user acts from its own browser session on each site
+    let mut sessions = Sessions::new();
+    for node in &config.nodes {
+        for user in &config.users {
+            let cookie = enter_user(&client, node, user).await?;
+            sessions.insert((node.clone(), user.clone()), cookie);
+        }
+    }
+    let sessions = std::sync::Arc::new(sessions);
+
+    let config_shared = std::sync::Arc::new(config.clone());
+    let next = std::sync::Arc::new(AtomicUsize::new(0));
+    let start = std::time::Instant::now();
+    let mut workers = tokio::task::JoinSet::new();
+    for _ in 0..config.concurrency {
+        let client = client.clone();
+        let config = config_shared.clone();
+        let sessions = sessions.clone();
+        let next = next.clone();
+        workers.spawn(async move {
+            let mut results = Vec::new();
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= config.transactions {
+                    return results;
+                }
+                results.push(run_one(&client, &config, &sessions, index).await);
+            }
+        });
+    }
+
+    let mut submit_latencies = Vec::new();
+    let mut diffusion_latencies = Vec::new();
+    let mut errors = Vec::new();
+    let mut failed = 0;
+    while let Some(results) = workers.join_next().await {
+        for result in results.map_err(|e| e.to_string())? {
+            match result {
+                Ok((submitted, confirmed)) => {
+                    submit_latencies.push(submitted);
+                    diffusion_latencies.push(confirmed);
+                }
+                Err(e) => {
+                    failed += 1;
+                    if !errors.contains(&e) {
+                        errors.push(e);
+                    }
+                }
+            }
+        }
+    }
+    let duration = start.elapsed();
+
+    let mut db_writes = Vec::new();
+    for (node, before) in config.nodes.iter().zip(before) {
+        let after = write_stats(&client, node).await;
+        let (writes, mean_ms) = match (before, after) {
+            (Ok((count_before, sum_before)), Ok((count_after, sum_after))) => {
+                let writes = count_after.saturating_sub(count_before);
+                let mean_ms =
+                    (writes > 0).then(|| (sum_after - sum_before) * 1000.0 / writes as f64);
+                (writes, mean_ms)
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                log::warn!("Cannot measure the database writes of {}: {}", node, e);
+                (0, None)
+            }
+        };
+        db_writes.push(NodeWrites {
+            node: node.clone(),
+            writes,
+            mean_ms,
+        });
+    }
+
+    let confirmed = diffusion_latencies.len();
+    Ok(Report {
+        operation: config.operation,
+        concurrency: config.concurrency,
+        transactions: config.transactions,
+        confirmed,
+        failed,
+        errors,
+        duration_secs: duration.as_secs_f64(),
+        throughput: confirmed as f64 / duration.as_secs_f64().max(f64::EPSILON),
+        submit_latency: Latencies::of(submit_latencies),
+        diffusion_latency: Latencies::of(diffusion_latencies),
+        db_writes,
+    })
+}
+
+/// Loads a load file and runs it against the sites
+pub async fn run_file(path: &str) -> Result<Report, String> {
+    let config = BenchConfig::load(path)?;
+    run(&config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = BenchConfig::parse(
+            r#"
+            nodes = ["http://127.0.0.1:8080"]
+            users = ["alice", "bob"]
+            operation = "transfer"
+            concurrency = 16
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.operation, Operation::Transfer);
+        assert_eq!(config.concurrency, 16);
+        assert_eq!(config.transactions, 100);
+        assert_eq!(config.amount, 1.0);
+
+        assert!(BenchConfig::parse("nodes = []\nusers = [\"alice\"]").is_err());
+        assert!(
+            BenchConfig::parse(
+                "nodes = [\"http://a\"]\nusers = [\"alice\"]\noperation = \"transfer\""
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_percentiles_and_metrics() {
+        let durations: Vec<_> = (1..=100)
+            .rev()
+            .map(std::time::Duration::from_millis)
+            .collect();
+        let latencies = Latencies::of(durations);
+        assert_eq!(latencies.p50_ms, 50.0);
+        assert_eq!(latencies.p95_ms, 95.0);
+        assert_eq!(latencies.p99_ms, 99.0);
+        assert_eq!(latencies.max_ms, 100.0);
+        assert_eq!(Latencies::of(Vec::new()), Latencies::default());
+
+        let text = "# TYPE peillute_db_write_seconds_sum counter\n\
+            peillute_db_write_seconds_sum 0.25\n\
+            peillute_db_write_seconds_count 10\n";
+        assert_eq!(
+            parse_metric(text, "peillute_db_write_seconds_sum"),
+            Some(0.25)
+        );
+        assert_eq!(
+            parse_metric(text, "peillute_db_write_seconds_count"),
+            Some(10.0)
+        );
+        assert_eq!(parse_metric(text, "peillute_db_size_bytes"), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Clock drift metrics and causality violation detection
+//!
+//! This module compares the clocks carried by the diffused transactions with
+//! the local clocks. A received vector clock with an entry greater than the
+//! local one for a site other than the initiator implies events that this site
+//! has not seen yet, which usually means that a diffusion was lost on the way.
+//! Such anomalies are counted, logged and displayed on the Info page. Only the
+//! vector clock strategy can reveal them, the other ones carry no vector.
+
+/// Clock metrics displayed on the Info page
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ClockMetrics {
+    /// Number of diffused transactions checked
+    pub messages_observed: u64,
+    /// Number of messages whose clock implies unseen prior events
+    pub causality_violations: u64,
+    /// Lamport drift of the last message (received minus local)
+    pub last_lamport_drift: i64,
+    /// Largest Lamport drift observed, in absolute value
+    pub max_lamport_drift: i64,
+    /// Number of unseen events per site, over all the violations
+    pub unseen_events_per_site: Vec<(String, i64)>,
+    /// Description of the most recent anomalies, newest first
+    pub recent_anomalies: Vec<String>,
+}
+
+#[cfg(feature = "server")]
+/// Number of anomalies kept for display
+const MAX_RECENT_ANOMALIES: usize = 20;
+
+#[cfg(feature = "server")]
+/// Result of the comparison of a received clock with the local one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockObservation {
+    /// Received Lamport time minus local Lamport time
+    pub lamport_drift: i64,
+    /// Sites for which the received clock is ahead of the local clock,
+    /// with the number of unseen events
+    pub unseen: Vec<(String, i64)>,
+}
+
+#[cfg(feature = "server")]
+/// Compares the clock of a message initiated by `initiator` with the local clock
+pub fn compare_clocks(
+    initiator: &str,
+    local: &crate::clock::Clock,
+    received: &crate::clock::Clock,
+) -> ClockObservation {
+    let local_vc = local.get_vector_clock_map();
+    let mut unseen: Vec<(String, i64)> = received
+        .get_vector_clock_map()
+        .iter()
+        .filter(|(site, _)| site.as_str() != initiator)
+        .filter_map(|(site, value)| {
+            let known = local_vc.get(site).copied().unwrap_or(0);
+            (*value > known).then(|| (site.clone(), value - known))
+        })
+        .collect();
+    unseen.sort();
+
+    ClockObservation {
+        lamport_drift: received.get_lamport() - local.get_lamport(),
+        unseen,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Aggregates the clock observations
+#[derive(Default)]
+pub struct CausalityMonitor {
+    metrics: ClockMetrics,
+    unseen_per_site: std::collections::HashMap<String, i64>,
+}
+
+#[cfg(feature = "server")]
+impl CausalityMonitor {
+    /// Records an observation, returns true if it is a causality violation
+    pub fn record(&mut self, initiator: &str, observation: &ClockObservation) -> bool {
+        self.metrics.messages_observed += 1;
+        self.metrics.last_lamport_drift = observation.lamport_drift;
+        self.metrics.max_lamport_drift = self
+            .metrics
+            .max_lamport_drift
+            .max(observation.lamport_drift.abs());
+
+        if observation.unseen.is_empty() {
+            return false;
+        }
+
+        self.metrics.causality_violations += 1;
+        for (site, nb) in &observation.unseen {
+            *self.unseen_per_site.entry(site.clone()).or_insert(0) += nb;
+        }
+        self.metrics.recent_anomalies.insert(
+            0,
+            format!(
+                "message from {} implies unseen events: {:?}",
+                initiator, observation.unseen
+            ),
+        );
+        self.metrics.recent_anomalies.truncate(MAX_RECENT_ANOMALIES);
+        true
+    }
+
+    /// Returns the current metrics
+    pub fn metrics(&self) -> ClockMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.unseen_events_per_site = self
+            .unseen_per_site
+            .iter()
+            .map(|(site, nb)| (site.clone(), *nb))
+            .collect();
+        metrics.unseen_events_per_site.sort();
+        metrics
+    }
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    pub static ref CAUSALITY_MONITOR: std::sync::Mutex<CausalityMonitor> =
+        std::sync::Mutex::new(CausalityMonitor::default());
+}
+
+#[cfg(feature = "server")]
+/// Checks the clock of a diffused message against the local clock
+///
+/// Must be called before the local clock is updated with the received one.
+pub fn observe(initiator: &str, local: &crate::clock::Clock, received: &crate::clock::Clock) {
+    let observation = compare_clocks(initiator, local, received);
+    let violation = CAUSALITY_MONITOR
+        .lock()
+        .unwrap()
+        .record(initiator, &observation);
+    if violation {
+        log::warn!(
+            "Causality anomaly: message from {} implies unseen events {:?} (Lamport drift {})",
+            initiator,
+            observation.unseen,
+            observation.lamport_drift
+        );
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    fn clock(lamport: i64, vc: &[(&str, i64)]) -> Clock {
+        Clock::new_with_values(
+            lamport,
+            vc.iter().map(|(s, v)| (s.to_string(), *v)).collect(),
+        )
+    }
+
+    #[test]
+    fn initiator_entry_is_ignored() {
+        let local = clock(3, &[("A", 1), ("B", 2)]);
+        let received = clock(5, &[("A", 4), ("B", 2)]);
+        let obs = compare_clocks("A", &local, &received);
+        assert!(obs.unseen.is_empty());
+        assert_eq!(obs.lamport_drift, 2);
+    }
+
+    #[test]
+    fn unseen_events_are_detected() {
+        let local = clock(3, &[("A", 1), ("B", 2)]);
+        let received = clock(2, &[("A", 4), ("B", 3), ("C", 2)]);
+        let obs = compare_clocks("A", &local, &received);
+        assert_eq!(obs.unseen, vec![("B".to_string(), 1), ("C".to_string(), 2)]);
+        assert_eq!(obs.lamport_drift, -1);
+
+        let mut monitor = CausalityMonitor::default();
+        assert!(monitor.record("A", &obs));
+        assert!(!monitor.record("A", &compare_clocks("A", &local, &local)));
+        let metrics = monitor.metrics();
+        assert_eq!(metrics.messages_observed, 2);
+        assert_eq!(metrics.causality_violations, 1);
+        assert_eq!(metrics.max_lamport_drift, 1);
+        assert_eq!(metrics.unseen_events_per_site.len(), 2);
+        assert_eq!(metrics.recent_anomalies.len(), 1);
+    }
+}
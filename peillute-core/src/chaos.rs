@@ -0,0 +1,171 @@
+//! Failure injection for chaos testing
+//!
+//! Only built with the `chaos` feature, for staging clusters. Admin commands
+//! set at runtime the share of the outgoing messages that are dropped, a delay
+//! added to the messages sent to a peer, and whether the control worker runs
+//! the critical commands, so that the wave, mutex and snapshot algorithms can
+//! be checked under adverse conditions:
+//!
+//! ```sh
+//! echo '/chaos_drop 20' | nc -U peillute_A.sock
+//! echo '/chaos_delay 127.0.0.1:10001 500' | nc -U peillute_A.sock
+//! echo '/chaos_pause' | nc -U peillute_A.sock
+//! ```
+//!
+//! Every knob is off when the site starts, and `/chaos_reset` turns them off.
+
+#![cfg(feature = "chaos")]
+
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Percentage of the outgoing messages dropped
+static DROP_PERCENT: AtomicU8 = AtomicU8::new(0);
+
+/// True while the control worker is paused
+static WORKER_PAUSED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    /// Delay added to the messages sent to each peer
+    static ref PEER_DELAYS: std::sync::Mutex<std::collections::HashMap<std::net::SocketAddr, std::time::Duration>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+    /// Random source deciding which messages are dropped
+    static ref RNG: std::sync::Mutex<crate::sim::SimRng> = std::sync::Mutex::new(crate::sim::SimRng(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default(),
+    ));
+    /// Wakes up the control worker when it is resumed
+    static ref WORKER_RESUMED: tokio::sync::Notify = tokio::sync::Notify::new();
+}
+
+/// Knobs currently set, printed by `/chaos`
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct ChaosStatus {
+    /// Percentage of the outgoing messages dropped
+    pub drop_percent: u8,
+    /// Peers whose messages are delayed, with the delay in milliseconds
+    pub delays: Vec<(String, u64)>,
+    /// True while the control worker is paused
+    pub worker_paused: bool,
+}
+
+/// Sets the percentage of the outgoing messages dropped, 0 to drop none
+pub fn set_drop_percent(percent: u8) -> Result<(), String> {
+    if percent > 100 {
+        return Err(format!("{}% is not a percentage", percent));
+    }
+    DROP_PERCENT.store(percent, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns true if the next outgoing message must be dropped
+pub fn should_drop() -> bool {
+    let percent = DROP_PERCENT.load(Ordering::Relaxed);
+    percent > 0 && RNG.lock().unwrap().chance(f64::from(percent) / 100.0)
+}
+
+/// Delays the messages sent to a peer, a zero delay removes it
+pub fn set_delay(peer: std::net::SocketAddr, delay: std::time::Duration) {
+    let mut delays = PEER_DELAYS.lock().unwrap();
+    if delay.is_zero() {
+        delays.remove(&peer);
+    } else {
+        delays.insert(peer, delay);
+    }
+}
+
+/// Returns the delay added to the messages sent to a peer, if any
+pub fn delay_for(peer: &std::net::SocketAddr) -> Option<std::time::Duration> {
+    PEER_DELAYS.lock().unwrap().get(peer).copied()
+}
+
+/// Pauses the control worker: the critical commands queue up until it is resumed
+pub fn pause_worker() {
+    WORKER_PAUSED.store(true, Ordering::Relaxed);
+}
+
+/// Resumes the control worker
+pub fn resume_worker() {
+    WORKER_PAUSED.store(false, Ordering::Relaxed);
+    WORKER_RESUMED.notify_waiters();
+}
+
+/// Waits until the control worker is not paused
+pub async fn wait_while_paused() {
+    loop {
+        let resumed = WORKER_RESUMED.notified();
+        tokio::pin!(resumed);
+        // registered before checking the flag, so that a resume is not missed
+        resumed.as_mut().enable();
+        if !WORKER_PAUSED.load(Ordering::Relaxed) {
+            return;
+        }
+        log::warn!("Chaos: the control worker is paused");
+        resumed.await;
+    }
+}
+
+/// Turns every knob off
+pub fn reset() {
+    DROP_PERCENT.store(0, Ordering::Relaxed);
+    PEER_DELAYS.lock().unwrap().clear();
+    resume_worker();
+}
+
+/// Returns the knobs currently set
+pub fn status() -> ChaosStatus {
+    let mut delays: Vec<(String, u64)> = PEER_DELAYS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(peer, delay)| (peer.to_string(), delay.as_millis() as u64))
+        .collect();
+    delays.sort();
+    ChaosStatus {
+        drop_percent: DROP_PERCENT.load(Ordering::Relaxed),
+        delays,
+        worker_paused: WORKER_PAUSED.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_knobs() {
+        assert!(set_drop_percent(101).is_err());
+        set_drop_percent(100).unwrap();
+        assert!(should_drop());
+
+        let peer: std::net::SocketAddr = "127.0.0.1:10001".parse().unwrap();
+        set_delay(peer, std::time::Duration::from_millis(250));
+        assert_eq!(
+            delay_for(&peer),
+            Some(std::time::Duration::from_millis(250))
+        );
+
+        pause_worker();
+        let waiter = tokio::spawn(wait_while_paused());
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        assert_eq!(
+            status(),
+            ChaosStatus {
+                drop_percent: 100,
+                delays: vec![("127.0.0.1:10001".to_string(), 250)],
+                worker_paused: true,
+            }
+        );
+
+        reset();
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!should_drop());
+        assert_eq!(delay_for(&peer), None);
+        assert_eq!(status(), ChaosStatus::default());
+    }
+}
@@ -0,0 +1,272 @@
+//! Lightweight client sites
+//!
+//! A site started with `--client-of <addr>` does not take part in the wave
+//! diffusion and global mutex protocols: it connects to a single full node and
+//! forwards it every critical command submitted on the site, such as the
+//! payments of a kiosk running the Pay page. The full node executes the command
+//! as if it was submitted locally, then reports its final status, which the
+//! client shows as the status of its own ticket.
+//!
+//! A full node only executes the commands of the clients listed with
+//! `--trusted-clients`, the other ones being refused.
+
+#![cfg(feature = "server")]
+
+/// Maximum time a full node waits for a forwarded command to be confirmed
+pub const FORWARD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Number of forwarded commands waiting to be queued by the full node
+const QUEUE_CAPACITY: usize = 256;
+
+/// Full node this site forwards its commands to, set at startup in client mode
+static FULL_NODE: std::sync::OnceLock<std::net::SocketAddr> = std::sync::OnceLock::new();
+
+/// Site IDs of the clients allowed to forward commands to this site
+static TRUSTED_CLIENTS: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+
+/// Queue of the commands forwarded by the clients, set when clients are trusted
+static QUEUE: std::sync::OnceLock<
+    tokio::sync::mpsc::Sender<(std::net::SocketAddr, crate::message::ForwardCommand)>,
+> = std::sync::OnceLock::new();
+
+/// Makes this site a client forwarding its commands to a full node
+pub fn set_full_node(addr: std::net::SocketAddr) {
+    let _ = FULL_NODE.set(addr);
+}
+
+/// Returns the full node this site forwards its commands to, if it is a client
+pub fn full_node() -> Option<std::net::SocketAddr> {
+    FULL_NODE.get().copied()
+}
+
+/// Starts executing the commands forwarded by the given clients
+///
+/// Does nothing if no client is trusted.
+pub fn accept_clients(clients: Vec<String>) {
+    if clients.is_empty() {
+        return;
+    }
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(QUEUE_CAPACITY);
+    if TRUSTED_CLIENTS.set(clients).is_err() || QUEUE.set(sender).is_err() {
+        log::warn!("Clients are already accepted");
+        return;
+    }
+
+    tokio::spawn(async move {
+        while let Some((client_addr, forwarded)) = receiver.recv().await {
+            // each command waits for its own confirmation
+            tokio::spawn(execute_forwarded(client_addr, forwarded));
+        }
+    });
+}
+
+/// Returns true if a site is allowed to forward commands to this site
+pub fn is_trusted(site_id: &str) -> bool {
+    TRUSTED_CLIENTS
+        .get()
+        .is_some_and(|clients| clients.iter().any(|client| client == site_id))
+}
+
+/// Returns true if a command can be forwarded by a client to its full node
+///
+/// Snapshots and consistency checks concern the state of the site running
+/// them, which a client does not replicate.
+pub fn is_forwardable(cmd: &crate::control::CriticalCommands) -> bool {
+    use crate::control::CriticalCommands;
+
+    !matches!(
+        cmd,
+        CriticalCommands::FileSnapshot { .. }
+            | CriticalCommands::SyncSnapshot
+            | CriticalCommands::CheckConsistency
+            | CriticalCommands::ClusterStats
+    )
+}
+
+/// Forwards a command to the full node of this client site
+///
+/// Returns the local ticket of the command, whose status is updated when the
+/// full node reports the result. With the offline queue, a transaction that
+/// cannot reach the full node is queued instead, see [`crate::offline`].
+pub async fn forward(
+    full_node: std::net::SocketAddr,
+    cmd: crate::control::CriticalCommands,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    if !is_forwardable(&cmd) {
+        return Err("This command is not available on a client site".into());
+    }
+    let ticket = crate::confirmation::CONFIRMATIONS
+        .lock()
+        .unwrap()
+        .register();
+    let queueable = crate::offline::is_enabled() && cmd.records_transaction();
+    let queued = queueable.then(|| cmd.clone());
+    match send_forward(full_node, ticket, cmd).await {
+        Ok(()) => crate::offline::set_full_node_reachable(true),
+        Err(e) => match queued {
+            Some(cmd) => {
+                crate::offline::set_full_node_reachable(false);
+                crate::offline::store(ticket, &cmd)?;
+            }
+            None => crate::confirmation::CONFIRMATIONS
+                .lock()
+                .unwrap()
+                .failed(ticket, format!("Full node unreachable: {}", e)),
+        },
+    }
+    Ok(ticket)
+}
+
+/// Sends a command to the full node, under a ticket of this site
+pub(crate) async fn send_forward(
+    full_node: std::net::SocketAddr,
+    ticket: u64,
+    cmd: crate::control::CriticalCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{ForwardCommand, MessageInfo, NetworkMessageCode};
+
+    let (site_id, site_addr) = crate::state::local_site().await;
+    let clock = crate::state::get_clock().await;
+    crate::network::send_message(
+        full_node,
+        MessageInfo::ForwardCommand(ForwardCommand {
+            ticket,
+            command: cmd,
+        }),
+        None,
+        NetworkMessageCode::ForwardCommand,
+        site_addr,
+        &site_id,
+        &site_id,
+        site_addr,
+        clock,
+    )
+    .await
+}
+
+/// Handles a command forwarded by a client
+///
+/// The command is queued for execution if the client is trusted, and refused
+/// right away otherwise.
+pub async fn submit(
+    client_id: &str,
+    client_addr: std::net::SocketAddr,
+    forwarded: crate::message::ForwardCommand,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ticket = forwarded.ticket;
+    let reason = if !is_trusted(client_id) {
+        log::warn!(
+            "Refused a command forwarded by the untrusted site {}",
+            client_id
+        );
+        format!("Site {} is not a trusted client", client_id)
+    } else if !is_forwardable(&forwarded.command) {
+        "This command cannot be forwarded".to_string()
+    } else if QUEUE
+        .get()
+        .is_some_and(|queue| queue.try_send((client_addr, forwarded)).is_ok())
+    {
+        return Ok(());
+    } else {
+        "System busy, try again later".to_string()
+    };
+    send_result(
+        client_addr,
+        ticket,
+        crate::confirmation::ConfirmationStatus::Failed(reason),
+    )
+    .await
+}
+
+/// Executes a command forwarded by a client, then reports its status to it
+///
+/// The result is sent once the command is confirmed or failed, or when
+/// [`FORWARD_TIMEOUT`] expires.
+async fn execute_forwarded(
+    client_addr: std::net::SocketAddr,
+    forwarded: crate::message::ForwardCommand,
+) {
+    use crate::confirmation::ConfirmationStatus;
+
+    let crate::message::ForwardCommand { ticket, command } = forwarded;
+    let queued = crate::control::enqueue_critical(command)
+        .await
+        .map_err(|e| e.to_string());
+    let status = match queued {
+        Ok(local_ticket) => {
+            crate::confirmation::wait_for_confirmation(local_ticket, FORWARD_TIMEOUT).await
+        }
+        Err(e) => ConfirmationStatus::Failed(e),
+    };
+    if let Err(e) = send_result(client_addr, ticket, status).await {
+        log::error!(
+            "Cannot report a forwarded command to {}: {}",
+            client_addr,
+            e
+        );
+    }
+}
+
+/// Sends the status of a forwarded command to the client that submitted it
+async fn send_result(
+    client_addr: std::net::SocketAddr,
+    ticket: u64,
+    status: crate::confirmation::ConfirmationStatus,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{ForwardResult, MessageInfo, NetworkMessageCode};
+
+    let (site_id, site_addr) = crate::state::local_site().await;
+    let clock = crate::state::get_clock().await;
+    crate::network::send_message(
+        client_addr,
+        MessageInfo::ForwardResult(ForwardResult { ticket, status }),
+        None,
+        NetworkMessageCode::ForwardResult,
+        site_addr,
+        &site_id,
+        &site_id,
+        site_addr,
+        clock,
+    )
+    .await
+}
+
+/// Records the status of a command forwarded by this client site
+///
+/// Results that do not come from the full node of the site are ignored.
+pub fn handle_result(sender_addr: std::net::SocketAddr, result: crate::message::ForwardResult) {
+    if full_node() != Some(sender_addr) {
+        log::warn!("Ignored a forwarded command result from {}", sender_addr);
+        return;
+    }
+    crate::confirmation::CONFIRMATIONS
+        .lock()
+        .unwrap()
+        .resolve(result.ticket, result.status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::CriticalCommands;
+
+    #[test]
+    fn test_forwardable_commands() {
+        assert!(is_forwardable(&CriticalCommands::Pay {
+            name: "alice".to_string(),
+            amount: 2.5,
+            tip_percent: 0.0,
+            split_with: Vec::new(),
+        }));
+        assert!(is_forwardable(&CriticalCommands::CreateUser {
+            name: "bob".to_string(),
+            ledger: crate::ledger::DEFAULT_LEDGER.to_string(),
+        }));
+        assert!(!is_forwardable(&CriticalCommands::FileSnapshot {
+            label: None
+        }));
+        assert!(!is_forwardable(&CriticalCommands::SyncSnapshot));
+        assert!(!is_forwardable(&CriticalCommands::CheckConsistency));
+        assert!(!is_forwardable(&CriticalCommands::ClusterStats));
+    }
+}
@@ -0,0 +1,484 @@
+//! Logical clock implementation for distributed synchronization
+//!
+//! This module provides the logical clocks used to order the events of the
+//! distributed system. Every strategy implements the [`LogicalClock`] trait:
+//! - [`VectorClock`]: a Lamport clock combined with a vector clock, the default
+//! - [`LamportClock`]: a Lamport clock only, without the per-site vector
+//! - [`HybridLogicalClock`]: a Lamport clock that follows the wall-clock time
+//!
+//! The strategy is selected at startup. Messages and the database carry a
+//! [`Clock`], which wraps any strategy and can be merged with a clock of
+//! another strategy.
+
+/// Strategy of the logical clock of the site
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockStrategy {
+    /// Lamport clock combined with a vector clock, the most precise
+    #[default]
+    Vector,
+    /// Lamport clock only, constant size but no causal ordering
+    Lamport,
+    /// Hybrid logical clock, constant size and close to the wall-clock time
+    Hybrid,
+}
+
+#[cfg(feature = "server")]
+/// Strategy used by the clocks created on this site, set at startup
+static CLOCK_STRATEGY: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+#[cfg(feature = "server")]
+/// Sets the strategy used by the clocks created on this site
+pub fn set_clock_strategy(strategy: ClockStrategy) {
+    CLOCK_STRATEGY.store(strategy as u8, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Returns the strategy used by the clocks created on this site
+pub fn clock_strategy() -> ClockStrategy {
+    match CLOCK_STRATEGY.load(std::sync::atomic::Ordering::Relaxed) {
+        1 => ClockStrategy::Lamport,
+        2 => ClockStrategy::Hybrid,
+        _ => ClockStrategy::Vector,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Common interface of the logical clock strategies
+pub trait LogicalClock {
+    /// Records a local event
+    fn tick(&mut self, local_site_id: &str);
+
+    /// Records the reception of a message stamped with the given clock values
+    fn merge(
+        &mut self,
+        local_site_id: &str,
+        received_lamport: i64,
+        received_vector: &std::collections::HashMap<String, i64>,
+    );
+
+    /// Returns the scalar timestamp, used to totally order the events
+    fn get_lamport(&self) -> &i64;
+
+    /// Returns the vector clock, empty for the strategies without one
+    fn get_vector_clock_map(&self) -> &std::collections::HashMap<String, i64>;
+}
+
+#[cfg(feature = "server")]
+/// Lamport clock combined with a vector clock
+///
+/// The Lamport clock gives a total ordering of the events and the vector
+/// clock their causal ordering.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct VectorClock {
+    /// Lamport clock value for total ordering of events
+    lamport_clock: i64,
+    /// Vector clock mapping site IDs to their clock values
+    ///
+    /// Site_id -> clock value
+    vector_clock: std::collections::HashMap<String, i64>,
+}
+
+#[cfg(feature = "server")]
+impl VectorClock {
+    /// Increments the Lamport clock and returns the new value
+    fn increment_lamport(&mut self) {
+        self.lamport_clock += 1;
+    }
+
+    /// Increments the vector clock for a specific site and returns the new value
+    fn increment_vector(&mut self, site_id: &str) {
+        let clock = self.vector_clock.entry(site_id.to_string()).or_insert(0);
+        *clock += 1;
+    }
+
+    /// Updates the vector clock with received values, taking the maximum of local and received values
+    fn update_vector(&mut self, received_vc: &std::collections::HashMap<String, i64>) {
+        for (site_id, clock_value) in received_vc {
+            let current_value = self.vector_clock.entry(site_id.clone()).or_insert(0);
+            *current_value = (*current_value).max(*clock_value) + 1;
+        }
+    }
+
+    /// Updates the lamport clock with received value, taking the maximum of local and received values
+    fn update_lamport(&mut self, received_lc: &i64) {
+        self.lamport_clock = (self.lamport_clock).max(*received_lc) + 1;
+    }
+}
+
+#[cfg(feature = "server")]
+impl LogicalClock for VectorClock {
+    fn tick(&mut self, local_site_id: &str) {
+        self.increment_lamport();
+        self.increment_vector(local_site_id);
+    }
+
+    fn merge(
+        &mut self,
+        _local_site_id: &str,
+        received_lamport: i64,
+        received_vector: &std::collections::HashMap<String, i64>,
+    ) {
+        self.update_vector(received_vector);
+        self.update_lamport(&received_lamport);
+    }
+
+    fn get_lamport(&self) -> &i64 {
+        &self.lamport_clock
+    }
+
+    fn get_vector_clock_map(&self) -> &std::collections::HashMap<String, i64> {
+        &self.vector_clock
+    }
+}
+
+#[cfg(feature = "server")]
+/// Lamport clock without vector clock
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct LamportClock {
+    /// Lamport clock value
+    lamport_clock: i64,
+    /// Always empty, returned as the vector clock
+    #[serde(skip)]
+    no_vector: std::collections::HashMap<String, i64>,
+}
+
+#[cfg(feature = "server")]
+impl LogicalClock for LamportClock {
+    fn tick(&mut self, _local_site_id: &str) {
+        self.lamport_clock += 1;
+    }
+
+    fn merge(
+        &mut self,
+        _local_site_id: &str,
+        received_lamport: i64,
+        _received_vector: &std::collections::HashMap<String, i64>,
+    ) {
+        self.lamport_clock = self.lamport_clock.max(received_lamport) + 1;
+    }
+
+    fn get_lamport(&self) -> &i64 {
+        &self.lamport_clock
+    }
+
+    fn get_vector_clock_map(&self) -> &std::collections::HashMap<String, i64> {
+        &self.no_vector
+    }
+}
+
+#[cfg(feature = "server")]
+/// Number of low bits of a hybrid timestamp holding the logical counter
+const HLC_COUNTER_BITS: u32 = 16;
+
+#[cfg(feature = "server")]
+/// Hybrid logical clock
+///
+/// The timestamp holds the wall-clock time in milliseconds in its high bits
+/// and a logical counter in its low bits. It never goes backward and stays
+/// close to the wall-clock time, so the transactions of different sites are
+/// ordered roughly by their real date.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct HybridLogicalClock {
+    /// Encoded hybrid timestamp
+    timestamp: i64,
+    /// Always empty, returned as the vector clock
+    #[serde(skip)]
+    no_vector: std::collections::HashMap<String, i64>,
+}
+
+#[cfg(feature = "server")]
+impl HybridLogicalClock {
+    /// Wall-clock time encoded as a hybrid timestamp with a zero counter
+    fn physical_now() -> i64 {
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        millis << HLC_COUNTER_BITS
+    }
+
+    /// Advances the timestamp past `floor` and the wall-clock time
+    fn advance(&mut self, floor: i64) {
+        self.timestamp = Self::physical_now().max(floor + 1);
+    }
+}
+
+#[cfg(feature = "server")]
+impl LogicalClock for HybridLogicalClock {
+    fn tick(&mut self, _local_site_id: &str) {
+        self.advance(self.timestamp);
+    }
+
+    fn merge(
+        &mut self,
+        _local_site_id: &str,
+        received_lamport: i64,
+        _received_vector: &std::collections::HashMap<String, i64>,
+    ) {
+        self.advance(self.timestamp.max(received_lamport));
+    }
+
+    fn get_lamport(&self) -> &i64 {
+        &self.timestamp
+    }
+
+    fn get_vector_clock_map(&self) -> &std::collections::HashMap<String, i64> {
+        &self.no_vector
+    }
+}
+
+#[cfg(feature = "server")]
+/// Clock of a site, carried by the messages and stored in the database
+///
+/// Wraps the clock strategy selected at startup. Clocks of different
+/// strategies can be merged, only the values they have in common are used.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum Clock {
+    /// Lamport clock combined with a vector clock
+    Vector(VectorClock),
+    /// Lamport clock only
+    Lamport(LamportClock),
+    /// Hybrid logical clock
+    Hybrid(HybridLogicalClock),
+}
+
+#[cfg(feature = "server")]
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "server")]
+impl Clock {
+    /// Creates a new clock of the strategy selected for this site
+    pub fn new() -> Self {
+        Self::with_strategy(clock_strategy())
+    }
+
+    /// Creates a new clock of the given strategy
+    pub fn with_strategy(strategy: ClockStrategy) -> Self {
+        Self::from_parts_with_strategy(strategy, 0, std::collections::HashMap::new())
+    }
+
+    /// Rebuilds a clock of the strategy selected for this site from stored values
+    pub fn from_parts(
+        lamport_clock: i64,
+        vector_clock: std::collections::HashMap<String, i64>,
+    ) -> Self {
+        Self::from_parts_with_strategy(clock_strategy(), lamport_clock, vector_clock)
+    }
+
+    /// Rebuilds a clock of the given strategy from stored values
+    ///
+    /// The vector clock is dropped by the strategies without one.
+    pub fn from_parts_with_strategy(
+        strategy: ClockStrategy,
+        lamport_clock: i64,
+        vector_clock: std::collections::HashMap<String, i64>,
+    ) -> Self {
+        match strategy {
+            ClockStrategy::Vector => Clock::Vector(VectorClock {
+                lamport_clock,
+                vector_clock,
+            }),
+            ClockStrategy::Lamport => Clock::Lamport(LamportClock {
+                lamport_clock,
+                ..Default::default()
+            }),
+            ClockStrategy::Hybrid => Clock::Hybrid(HybridLogicalClock {
+                timestamp: lamport_clock,
+                ..Default::default()
+            }),
+        }
+    }
+
+    ///Creates a new Clock instance with initialized clocks, used for testing
+    #[cfg(test)]
+    pub fn new_with_values(lamport: i64, vector: std::collections::HashMap<String, i64>) -> Self {
+        Self::from_parts_with_strategy(ClockStrategy::Vector, lamport, vector)
+    }
+
+    /// Returns the wrapped clock
+    fn inner(&self) -> &dyn LogicalClock {
+        match self {
+            Clock::Vector(c) => c,
+            Clock::Lamport(c) => c,
+            Clock::Hybrid(c) => c,
+        }
+    }
+
+    /// Returns the wrapped clock, mutably
+    fn inner_mut(&mut self) -> &mut dyn LogicalClock {
+        match self {
+            Clock::Vector(c) => c,
+            Clock::Lamport(c) => c,
+            Clock::Hybrid(c) => c,
+        }
+    }
+
+    /// Returns a reference to the Lamport clock value
+    ///
+    /// For a hybrid logical clock, this is the encoded hybrid timestamp.
+    pub fn get_lamport(&self) -> &i64 {
+        self.inner().get_lamport()
+    }
+
+    /// Returns a reference to the vector clock
+    pub fn get_vector_clock_map(&self) -> &std::collections::HashMap<String, i64> {
+        self.inner().get_vector_clock_map()
+    }
+
+    /// Returns the vector clock as a list of values
+    pub fn get_vector_clock_values(&self) -> Vec<i64> {
+        self.get_vector_clock_map().values().copied().collect()
+    }
+
+    /// Update the current clock value with an optional clock
+    ///
+    /// Without received clock, a local event is recorded: the clock ticks.
+    ///
+    /// Otherwise the clock is merged with the received one
+    pub fn update_clock(&mut self, local_site_id: &str, received_clock: Option<&Self>) {
+        if let Some(rc) = received_clock {
+            self.inner_mut()
+                .merge(local_site_id, *rc.get_lamport(), rc.get_vector_clock_map());
+        } else {
+            self.inner_mut().tick(local_site_id);
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_clock_initialization() {
+        let clock = Clock::with_strategy(ClockStrategy::Vector);
+        assert_eq!(*clock.get_lamport(), 0);
+        assert!(clock.get_vector_clock_map().is_empty());
+    }
+
+    #[test]
+    fn test_increment_lamport() {
+        let mut clock = VectorClock::default();
+        clock.increment_lamport();
+        assert_eq!(*clock.get_lamport(), 1);
+        clock.increment_lamport();
+        assert_eq!(*clock.get_lamport(), 2);
+    }
+
+    #[test]
+    fn test_increment_vector() {
+        let mut clock = VectorClock::default();
+        clock.increment_vector("A");
+        clock.increment_vector("A");
+        clock.increment_vector("B");
+
+        let vc = clock.get_vector_clock_map();
+        assert_eq!(vc.get("A"), Some(&2));
+        assert_eq!(vc.get("B"), Some(&1));
+    }
+
+    #[test]
+    fn test_get_vector_clock_values() {
+        let mut vc = std::collections::HashMap::new();
+        vc.insert("A".to_string(), 1);
+        vc.insert("B".to_string(), 2);
+        let clock = Clock::new_with_values(0, vc);
+
+        let mut values = clock.get_vector_clock_values();
+        values.sort(); // Order not guaranteed by HashMap
+        assert_eq!(values, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_update_vector_clock() {
+        let mut local = VectorClock::default();
+        local.increment_vector("A"); // A:1
+
+        let mut incoming = VectorClock::default();
+        incoming.increment_vector("A"); // A:1
+        incoming.increment_vector("A"); // A:2
+        incoming.increment_vector("B"); // B:1
+
+        local.update_vector(incoming.get_vector_clock_map());
+
+        let local_vc = local.get_vector_clock_map();
+        assert_eq!(local_vc.get("A"), Some(&3));
+        assert_eq!(local_vc.get("B"), Some(&2));
+    }
+
+    #[test]
+    fn test_update_clock_with_none() {
+        let mut clock = Clock::with_strategy(ClockStrategy::Vector);
+        clock.update_clock("A", None);
+
+        assert_eq!(*clock.get_lamport(), 1);
+        assert_eq!(clock.get_vector_clock_map().get("A"), Some(&1));
+    }
+
+    #[test]
+    fn test_update_clock_with_received_clock() {
+        let mut vc = std::collections::HashMap::new();
+        vc.insert("A".to_string(), 2);
+        let mut local = Clock::new_with_values(1, vc);
+
+        let mut vc = std::collections::HashMap::new();
+        vc.insert("A".to_string(), 1);
+        vc.insert("B".to_string(), 1);
+        let received = Clock::new_with_values(2, vc);
+
+        // Here local is A:2 before
+        // Then updated with received (A:1), taking the max plus one
+        // So should be A:3 after
+        // Lamport was 1 before, so should be 3 after
+        local.update_clock("A", Some(&received));
+
+        // Lamport clock should be max(received, local) + 1
+        assert_eq!(*local.get_lamport(), 3);
+        let vc = local.get_vector_clock_map();
+        assert_eq!(vc.get("A"), Some(&3)); // Incremented locally + merged max
+        assert_eq!(vc.get("B"), Some(&2));
+    }
+
+    #[test]
+    fn test_lamport_clock_has_no_vector() {
+        let mut clock = Clock::with_strategy(ClockStrategy::Lamport);
+        clock.update_clock("A", None);
+        assert_eq!(*clock.get_lamport(), 1);
+        assert!(clock.get_vector_clock_map().is_empty());
+
+        let mut vc = std::collections::HashMap::new();
+        vc.insert("B".to_string(), 4);
+        let received = Clock::new_with_values(5, vc);
+        clock.update_clock("A", Some(&received));
+        assert_eq!(*clock.get_lamport(), 6);
+        assert!(clock.get_vector_clock_map().is_empty());
+    }
+
+    #[test]
+    fn test_hybrid_clock_is_monotonic_and_follows_wall_clock() {
+        let mut clock = Clock::with_strategy(ClockStrategy::Hybrid);
+        clock.update_clock("A", None);
+        let first = *clock.get_lamport();
+        assert!(first > 0);
+        if let Clock::Hybrid(hlc) = &clock {
+            let now = chrono::Utc::now().timestamp_millis();
+            assert!((now - (*hlc.get_lamport() >> HLC_COUNTER_BITS)).abs() < 10_000);
+        }
+
+        // A clock far in the future is followed, never the other way around
+        let received = Clock::from_parts_with_strategy(
+            ClockStrategy::Hybrid,
+            first + (1 << 40),
+            Default::default(),
+        );
+        clock.update_clock("A", Some(&received));
+        assert_eq!(*clock.get_lamport(), first + (1 << 40) + 1);
+        clock.update_clock("A", None);
+        assert_eq!(*clock.get_lamport(), first + (1 << 40) + 2);
+    }
+}
@@ -0,0 +1,232 @@
+//! Statistics of the whole network
+//!
+//! `/cluster_stats` and the **Cluster** page run a wave through the network
+//! collecting the counters of each reachable site: its number of users and of
+//! transactions, its last Lamport time and the size of its database. The
+//! counters are gathered back to the initiator, which shows them in a single
+//! table, so that an operator sees at a glance the sites of the deployment
+//! and the ones lagging behind. Like the consistency check, the wave holds
+//! the global mutex, so no transaction is in flight while it is collected.
+
+/// Counters of a site
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SiteStats {
+    /// ID of the site
+    pub site_id: String,
+    /// Alias of the site, if it has one
+    pub alias: Option<String>,
+    /// Number of active users
+    pub nb_users: i64,
+    /// Number of transactions recorded by the site
+    pub nb_transactions: i64,
+    /// Greatest Lamport time of the transactions of the site
+    pub last_lamport: i64,
+    /// Size of the database, in bytes
+    pub db_size: u64,
+}
+
+/// Counters collected from the sites of the network
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ClusterStats {
+    /// ID of the site that collected the counters
+    pub initiator: String,
+    /// Local time of the collection
+    pub collected_at: String,
+    /// Counters of each site that answered, by site ID
+    pub sites: Vec<SiteStats>,
+}
+
+impl ClusterStats {
+    /// Returns the greatest number of transactions recorded by a site
+    pub fn max_transactions(&self) -> i64 {
+        self.sites
+            .iter()
+            .map(|site| site.nb_transactions)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the greatest Lamport time of the sites
+    pub fn last_lamport(&self) -> i64 {
+        self.sites
+            .iter()
+            .map(|site| site.last_lamport)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the size of the databases of all the sites, in bytes
+    pub fn total_db_size(&self) -> u64 {
+        self.sites.iter().map(|site| site.db_size).sum()
+    }
+
+    /// Returns the sites holding fewer transactions than the most up to date one
+    pub fn lagging(&self) -> Vec<&str> {
+        let max = self.max_transactions();
+        self.sites
+            .iter()
+            .filter(|site| site.nb_transactions < max)
+            .map(|site| site.site_id.as_str())
+            .collect()
+    }
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    /// Counters collected during the current wave
+    static ref COLLECTED_STATS: tokio::sync::Mutex<Vec<SiteStats>> =
+        tokio::sync::Mutex::new(Vec::new());
+    /// Counters of the last completed wave
+    static ref LAST_STATS: tokio::sync::watch::Sender<Option<ClusterStats>> =
+        tokio::sync::watch::channel(None).0;
+}
+
+#[cfg(feature = "server")]
+/// Computes the counters of the site
+pub async fn local_stats() -> Result<SiteStats, Box<dyn std::error::Error>> {
+    let (site_id, alias) = {
+        let info = crate::state::SITE_INFO.read().await;
+        (info.get_site_id(), info.get_site_alias())
+    };
+    let nb_users = crate::db::get_users()?.len() as i64;
+    let (nb_transactions, last_lamport) = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        conn.query_row(
+            "SELECT COUNT(*), COALESCE(MAX(lamport_time), 0) FROM Transactions",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?
+    };
+    let (_, db_size) = crate::db::database_file()?;
+
+    Ok(SiteStats {
+        site_id,
+        alias,
+        nb_users,
+        nb_transactions,
+        last_lamport,
+        db_size: db_size.max(0) as u64,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Starts collecting the counters of a new wave with the ones of the site
+pub async fn start() -> Result<(), Box<dyn std::error::Error>> {
+    let stats = local_stats().await?;
+    *COLLECTED_STATS.lock().await = vec![stats];
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Adds the counters received from a child of the wave
+pub async fn collect(stats: Vec<SiteStats>) {
+    COLLECTED_STATS.lock().await.extend(stats);
+}
+
+#[cfg(feature = "server")]
+/// Takes the counters collected so far, to send them to the parent of the wave
+pub async fn take() -> Vec<SiteStats> {
+    std::mem::take(&mut *COLLECTED_STATS.lock().await)
+}
+
+#[cfg(feature = "server")]
+/// Ends the wave on the initiator, prints and keeps the counters of the sites
+pub async fn complete(stats: Vec<SiteStats>) -> ClusterStats {
+    collect(stats).await;
+    let mut sites = take().await;
+    sites.sort_by(|a, b| a.site_id.cmp(&b.site_id));
+    let cluster = ClusterStats {
+        initiator: crate::state::local_site().await.0,
+        collected_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        sites,
+    };
+    print_stats(&cluster);
+    LAST_STATS.send_replace(Some(cluster.clone()));
+    cluster
+}
+
+#[cfg(feature = "server")]
+/// Returns the counters of the last completed wave, if any
+pub fn last() -> Option<ClusterStats> {
+    LAST_STATS.borrow().clone()
+}
+
+#[cfg(feature = "server")]
+/// Runs a new wave and waits for its counters
+///
+/// Returns None if the wave did not complete before the timeout.
+pub async fn refresh(
+    timeout: std::time::Duration,
+) -> Result<Option<ClusterStats>, Box<dyn std::error::Error>> {
+    let mut receiver = LAST_STATS.subscribe();
+    receiver.mark_unchanged();
+    crate::control::enqueue_critical(crate::control::CriticalCommands::ClusterStats).await?;
+    match tokio::time::timeout(timeout, receiver.changed()).await {
+        Ok(Ok(())) => Ok(receiver.borrow().clone()),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Prints the counters of the sites on the CLI
+fn print_stats(cluster: &ClusterStats) {
+    if crate::control::output_format() == crate::control::OutputFormat::Json {
+        crate::control::print_json(cluster);
+        return;
+    }
+    println!(
+        "{:<20} {:>6} {:>13} {:>13} {:>10}",
+        "Site", "Users", "Transactions", "Last Lamport", "DB (KiB)"
+    );
+    for site in &cluster.sites {
+        let name = match &site.alias {
+            Some(alias) => format!("{} ({})", alias, site.site_id),
+            None => site.site_id.clone(),
+        };
+        println!(
+            "{:<20} {:>6} {:>13} {:>13} {:>10}",
+            name,
+            site.nb_users,
+            site.nb_transactions,
+            site.last_lamport,
+            site.db_size / 1024
+        );
+    }
+    println!(
+        "📊 {} sites, last Lamport time {}, {} KiB of databases",
+        cluster.sites.len(),
+        cluster.last_lamport(),
+        cluster.total_db_size() / 1024
+    );
+    let lagging = cluster.lagging();
+    if !lagging.is_empty() {
+        println!("⏳ Lagging behind: {}", lagging.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_totals() {
+        let site = |site_id: &str, nb_transactions, db_size| SiteStats {
+            site_id: site_id.to_string(),
+            alias: None,
+            nb_users: 3,
+            nb_transactions,
+            last_lamport: nb_transactions * 2,
+            db_size,
+        };
+        let cluster = ClusterStats {
+            initiator: "A".to_string(),
+            collected_at: String::new(),
+            sites: vec![site("A", 12, 4096), site("B", 12, 8192), site("C", 9, 2048)],
+        };
+        assert_eq!(cluster.max_transactions(), 12);
+        assert_eq!(cluster.last_lamport(), 24);
+        assert_eq!(cluster.total_db_size(), 14336);
+        assert_eq!(cluster.lagging(), vec!["C"]);
+        assert!(ClusterStats::default().lagging().is_empty());
+    }
+}
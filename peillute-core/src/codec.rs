@@ -0,0 +1,176 @@
+//! Encoding of the messages exchanged with the peers
+//!
+//! Every frame sent to a peer carries the version of the peer protocol and
+//! the format its message is encoded in, so that a site can read the frames of
+//! a peer using another format and reject the ones of an unsupported version
+//! instead of misreading them. The messages are encoded in MessagePack by
+//! default, JSON being easier to inspect while debugging.
+//!
+//! The version used with a peer is negotiated during the Discovery
+//! handshake: the discovering site offers the range of versions it supports
+//! and the discovered site answers with the highest one they have in common.
+//! Until then, the lowest supported version is used.
+
+/// Format of the messages sent to the peers
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    /// Compact binary encoding
+    #[default]
+    Msgpack,
+    /// Human-readable encoding, larger and slower
+    Json,
+}
+
+#[cfg(feature = "server")]
+/// Versions of the peer protocol supported by this site, the highest being preferred
+pub const PROTOCOL_VERSIONS: std::ops::RangeInclusive<u8> = 1..=1;
+
+#[cfg(feature = "server")]
+/// Encoder of the messages exchanged with the peers
+pub trait WireCodec {
+    /// Encodes a value
+    fn encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, String>;
+    /// Decodes a value
+    fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, String>;
+}
+
+#[cfg(feature = "server")]
+/// MessagePack encoding
+pub struct MsgpackCodec;
+
+#[cfg(feature = "server")]
+impl WireCodec for MsgpackCodec {
+    fn encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, String> {
+        rmp_serde::encode::to_vec(value).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+        rmp_serde::decode::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "server")]
+/// JSON encoding
+pub struct JsonCodec;
+
+#[cfg(feature = "server")]
+impl WireCodec for JsonCodec {
+    fn encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(value).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "server")]
+impl WireFormat {
+    /// Returns the format identified by a byte of a frame header
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(WireFormat::Msgpack),
+            1 => Some(WireFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Returns the byte identifying the format in a frame header
+    pub fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    /// Encodes a value in this format
+    pub fn encode<T: serde::Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            WireFormat::Msgpack => MsgpackCodec::encode(value),
+            WireFormat::Json => JsonCodec::encode(value),
+        }
+    }
+
+    /// Decodes a value encoded in this format
+    pub fn decode<T: serde::de::DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            WireFormat::Msgpack => MsgpackCodec::decode(bytes),
+            WireFormat::Json => JsonCodec::decode(bytes),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Format of the messages sent by this site, set from the command line at startup
+static WIRE_FORMAT: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+#[cfg(feature = "server")]
+/// Sets the format of the messages sent by this site
+pub fn set_wire_format(format: WireFormat) {
+    WIRE_FORMAT.store(format.to_byte(), std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Returns the format of the messages sent by this site
+pub fn wire_format() -> WireFormat {
+    WireFormat::from_byte(WIRE_FORMAT.load(std::sync::atomic::Ordering::Relaxed))
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "server")]
+/// Returns true if this site can read the frames of a version of the protocol
+pub fn is_supported(version: u8) -> bool {
+    PROTOCOL_VERSIONS.contains(&version)
+}
+
+#[cfg(feature = "server")]
+/// Returns the highest version of the protocol supported by both ranges
+fn negotiate_between(
+    local: &std::ops::RangeInclusive<u8>,
+    remote: &std::ops::RangeInclusive<u8>,
+) -> Option<u8> {
+    let version = (*local.end()).min(*remote.end());
+    (version >= *local.start() && version >= *remote.start()).then_some(version)
+}
+
+#[cfg(feature = "server")]
+/// Returns the version of the protocol to use with a peer supporting `min..=max`
+pub fn negotiate(min: u8, max: u8) -> Option<u8> {
+    negotiate_between(&PROTOCOL_VERSIONS, &(min..=max))
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_highest_common_version() {
+        assert_eq!(negotiate_between(&(1..=3), &(2..=5)), Some(3));
+        assert_eq!(negotiate_between(&(2..=5), &(1..=3)), Some(3));
+        assert_eq!(negotiate_between(&(1..=1), &(1..=4)), Some(1));
+        assert_eq!(negotiate_between(&(1..=2), &(3..=4)), None);
+        assert_eq!(negotiate_between(&(3..=4), &(1..=2)), None);
+        assert_eq!(
+            negotiate(*PROTOCOL_VERSIONS.start(), *PROTOCOL_VERSIONS.end()),
+            Some(*PROTOCOL_VERSIONS.end())
+        );
+    }
+
+    #[test]
+    fn test_formats_roundtrip() {
+        let value = std::collections::BTreeMap::from([("alice".to_string(), 1250i64)]);
+        for format in [WireFormat::Msgpack, WireFormat::Json] {
+            assert_eq!(WireFormat::from_byte(format.to_byte()), Some(format));
+            let bytes = format.encode(&value).unwrap();
+            assert_eq!(
+                format
+                    .decode::<std::collections::BTreeMap<String, i64>>(&bytes)
+                    .unwrap(),
+                value
+            );
+        }
+        assert_eq!(
+            WireFormat::Json.encode(&value).unwrap(),
+            br#"{"alice":1250}"#
+        );
+        assert_eq!(WireFormat::from_byte(2), None);
+    }
+}
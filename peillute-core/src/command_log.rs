@@ -0,0 +1,234 @@
+//! Log of the critical commands waiting for the global mutex
+//!
+//! A critical command waits in memory until the site holds the global mutex:
+//! if the site stopped before executing it, the command would be lost without
+//! its user knowing. Each command is stored in the PendingCommands table when
+//! it is enqueued, and removed once it is executed and diffused, or rejected.
+//! Right before it is applied, the command records the Lamport time it is
+//! executed at, which is its idempotency key: the first transaction it
+//! records holds that time.
+//!
+//! On startup, the commands left in the table are enqueued again, in their
+//! order of submission. A command whose execution had started is enqueued
+//! again only if it records transactions and the site holds no transaction at
+//! its Lamport time; an applied command is not applied twice, and if it was
+//! not diffused, the other sites get it with their next snapshot. The other
+//! started commands, which may have been applied, are dropped with a warning.
+//!
+//! The snapshots, consistency checks and cluster statistics are not logged:
+//! they are requested again when needed.
+
+#![cfg(feature = "server")]
+
+lazy_static::lazy_static! {
+    /// Logged commands by ticket, since the site started
+    static ref ENTRIES: std::sync::Mutex<std::collections::HashMap<u64, i64>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+/// Logged command left by a previous run of the site
+#[derive(Debug, Clone, PartialEq)]
+struct Unfinished {
+    /// Identifier of the command in the log
+    id: i64,
+    /// Command, encoded in JSON
+    command: String,
+    /// Lamport time and site of its execution, if it had started
+    started: Option<(i64, String)>,
+}
+
+/// Returns true if a command is logged until it is executed
+pub fn is_logged(cmd: &crate::control::CriticalCommands) -> bool {
+    use crate::control::CriticalCommands;
+
+    !matches!(
+        cmd,
+        CriticalCommands::FileSnapshot { .. }
+            | CriticalCommands::SyncSnapshot
+            | CriticalCommands::CheckConsistency
+            | CriticalCommands::ClusterStats
+    )
+}
+
+/// Stores a command, returns its identifier
+fn store_in(conn: &rusqlite::Connection, command: &str) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO PendingCommands (command, queued_at) VALUES (?1, ?2)",
+        rusqlite::params![
+            command,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Records the Lamport time a command is executed at, None to clear it
+fn start_in(
+    conn: &rusqlite::Connection,
+    id: i64,
+    started: Option<(i64, &str)>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE PendingCommands SET started_lamport = ?2, started_node = ?3 WHERE id = ?1",
+        rusqlite::params![
+            id,
+            started.map(|(lamport, _)| lamport),
+            started.map(|(_, node)| node)
+        ],
+    )?;
+    Ok(())
+}
+
+/// Removes a command from the log
+fn remove_in(conn: &rusqlite::Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM PendingCommands WHERE id = ?1",
+        rusqlite::params![id],
+    )?;
+    Ok(())
+}
+
+/// Returns the commands left in the log, oldest first
+fn unfinished_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<Unfinished>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, command, started_lamport, started_node FROM PendingCommands ORDER BY id",
+    )?;
+    stmt.query_map([], |row| {
+        let lamport: Option<i64> = row.get(2)?;
+        let node: Option<String> = row.get(3)?;
+        Ok(Unfinished {
+            id: row.get(0)?,
+            command: row.get(1)?,
+            started: lamport.zip(node),
+        })
+    })?
+    .collect()
+}
+
+/// Returns true if a site recorded a transaction at a Lamport time
+fn applied_in(conn: &rusqlite::Connection, lamport: i64, node: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM Transactions WHERE lamport_time = ?1 AND source_node = ?2)",
+        rusqlite::params![lamport, node],
+        |row| row.get(0),
+    )
+}
+
+/// Stores a command enqueued under a ticket
+pub fn record(
+    ticket: u64,
+    cmd: &crate::control::CriticalCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let id = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        store_in(&conn, &serde_json::to_string(cmd)?)?
+    };
+    ENTRIES.lock().unwrap().insert(ticket, id);
+    Ok(())
+}
+
+/// Attaches a command enqueued again to its entry in the log
+pub(crate) fn attach(ticket: u64, id: i64) {
+    ENTRIES.lock().unwrap().insert(ticket, id);
+}
+
+/// Records the Lamport time the command of a ticket is executed at
+pub fn started(ticket: u64, lamport: i64, site_id: &str) -> rusqlite::Result<()> {
+    let Some(id) = ENTRIES.lock().unwrap().get(&ticket).copied() else {
+        return Ok(());
+    };
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    start_in(&conn, id, Some((lamport, site_id)))
+}
+
+/// Removes the command of a ticket from the log, once executed or rejected
+pub fn finished(ticket: u64) -> rusqlite::Result<()> {
+    let Some(id) = ENTRIES.lock().unwrap().remove(&ticket) else {
+        return Ok(());
+    };
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    remove_in(&conn, id)
+}
+
+/// Enqueues again the commands a previous run of the site left unfinished
+///
+/// Returns the number of commands enqueued.
+pub async fn replay() -> Result<usize, Box<dyn std::error::Error>> {
+    let unfinished = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        unfinished_in(&conn)?
+    };
+    let mut replayed = 0;
+    for entry in unfinished {
+        let cmd = match serde_json::from_str::<crate::control::CriticalCommands>(&entry.command) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                log::error!("Dropped the unreadable pending command {}: {}", entry.id, e);
+                let conn = crate::db::DB_CONN.lock().unwrap();
+                remove_in(&conn, entry.id)?;
+                continue;
+            }
+        };
+        if let Some((lamport, node)) = &entry.started {
+            let applied = {
+                let conn = crate::db::DB_CONN.lock().unwrap();
+                applied_in(&conn, *lamport, node)?
+            };
+            if applied || !cmd.records_transaction() {
+                if applied {
+                    log::info!("Pending command {:?} was already applied", cmd);
+                } else {
+                    log::warn!(
+                        "Dropped the pending command {:?}, interrupted while executed: check whether it was applied",
+                        cmd
+                    );
+                }
+                let conn = crate::db::DB_CONN.lock().unwrap();
+                remove_in(&conn, entry.id)?;
+                continue;
+            }
+            let conn = crate::db::DB_CONN.lock().unwrap();
+            start_in(&conn, entry.id, None)?;
+        }
+        log::info!("Replaying the pending command {:?}", cmd);
+        crate::control::push_critical(cmd, Some(entry.id)).await?;
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_commands() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+
+        let first = store_in(&conn, "\"SyncSnapshot\"").unwrap();
+        let second = store_in(&conn, "\"CheckConsistency\"").unwrap();
+        start_in(&conn, first, Some((7, "A"))).unwrap();
+        let unfinished = unfinished_in(&conn).unwrap();
+        assert_eq!(unfinished.len(), 2);
+        assert_eq!(unfinished[0].started, Some((7, "A".to_string())));
+        assert_eq!(unfinished[1].id, second);
+        assert_eq!(unfinished[1].started, None);
+
+        assert!(!applied_in(&conn, 7, "A").unwrap());
+        conn.execute(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('NULL', 'alice', 10, 7, 0, 'A', 'Deposit')",
+            [],
+        )
+        .unwrap();
+        assert!(applied_in(&conn, 7, "A").unwrap());
+        assert!(!applied_in(&conn, 7, "B").unwrap());
+
+        start_in(&conn, first, None).unwrap();
+        assert_eq!(unfinished_in(&conn).unwrap()[0].started, None);
+        remove_in(&conn, first).unwrap();
+        assert_eq!(unfinished_in(&conn).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+//! Statistics about the concurrency between the sites
+//!
+//! Every site may initiate transactions at any time, so the operations of the
+//! sites interleave. This module counts how often it shows: diffused
+//! transactions whose vector clock is incomparable with the local one, copies
+//! of a transaction received twice, received transactions refused because
+//! they would overdraw an account, and the time taken by the waves initiated
+//! by this site to complete. The statistics are displayed in the Consistency
+//! section of the Info page and exported by `GET /metrics`.
+
+/// Concurrency statistics displayed on the Info page
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ConcurrencyMetrics {
+    /// Diffused transactions whose vector clock is incomparable with the local one
+    pub concurrent_transactions: u64,
+    /// Transactions received again and skipped
+    pub duplicate_transactions: u64,
+    /// Received transactions refused for insufficient funds
+    pub overdraft_conflicts: u64,
+    /// Waves initiated by this site that completed
+    pub completed_waves: u64,
+    /// Average time taken by these waves to complete, in milliseconds
+    pub average_wave_millis: Option<f64>,
+}
+
+#[cfg(feature = "server")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "server")]
+/// Number of diffused transactions with a clock incomparable with the local one
+static CONCURRENT_TRANSACTIONS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Number of transactions received again
+static DUPLICATE_TRANSACTIONS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Number of received transactions refused for insufficient funds
+static OVERDRAFT_CONFLICTS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Number of waves initiated by this site that completed
+static COMPLETED_WAVES: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Time taken by these waves to complete, in microseconds
+static WAVE_MICROS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Returns true if neither vector clock happened before the other
+///
+/// Always false for the clock strategies carrying no vector.
+pub fn is_concurrent(
+    local: &std::collections::HashMap<String, i64>,
+    received: &std::collections::HashMap<String, i64>,
+) -> bool {
+    let ahead = |a: &std::collections::HashMap<String, i64>,
+                 b: &std::collections::HashMap<String, i64>| {
+        a.iter()
+            .any(|(site, value)| *value > b.get(site).copied().unwrap_or(0))
+    };
+    ahead(local, received) && ahead(received, local)
+}
+
+#[cfg(feature = "server")]
+/// Checks the clock of a diffused transaction against the local clock
+///
+/// Must be called before the local clock is updated with the received one.
+pub fn observe(local: &crate::clock::Clock, received: &crate::clock::Clock) {
+    if is_concurrent(
+        local.get_vector_clock_map(),
+        received.get_vector_clock_map(),
+    ) {
+        CONCURRENT_TRANSACTIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "server")]
+/// Records a transaction received again
+pub fn record_duplicate() {
+    DUPLICATE_TRANSACTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Records the failure of a received transaction, counted if it was refused
+/// for insufficient funds
+pub fn record_failure(error: &str) {
+    if error.contains("Insufficient funds") {
+        OVERDRAFT_CONFLICTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "server")]
+/// Records the time taken by a wave initiated by this site to complete
+pub fn record_wave(duration: std::time::Duration) {
+    COMPLETED_WAVES.fetch_add(1, Ordering::Relaxed);
+    WAVE_MICROS.fetch_add(
+        u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+        Ordering::Relaxed,
+    );
+}
+
+#[cfg(feature = "server")]
+/// Returns the number of waves completed since the site started and the time they took
+pub fn wave_stats() -> (u64, std::time::Duration) {
+    (
+        COMPLETED_WAVES.load(Ordering::Relaxed),
+        std::time::Duration::from_micros(WAVE_MICROS.load(Ordering::Relaxed)),
+    )
+}
+
+#[cfg(feature = "server")]
+/// Returns the statistics gathered since the site started
+pub fn metrics() -> ConcurrencyMetrics {
+    let (completed_waves, wave_time) = wave_stats();
+    ConcurrencyMetrics {
+        concurrent_transactions: CONCURRENT_TRANSACTIONS.load(Ordering::Relaxed),
+        duplicate_transactions: DUPLICATE_TRANSACTIONS.load(Ordering::Relaxed),
+        overdraft_conflicts: OVERDRAFT_CONFLICTS.load(Ordering::Relaxed),
+        completed_waves,
+        average_wave_millis: (completed_waves > 0)
+            .then(|| wave_time.as_secs_f64() * 1000.0 / completed_waves as f64),
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    fn vc(values: &[(&str, i64)]) -> std::collections::HashMap<String, i64> {
+        values.iter().map(|(s, v)| (s.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn test_is_concurrent() {
+        let local = vc(&[("A", 2), ("B", 1)]);
+        assert!(!is_concurrent(&local, &vc(&[("A", 2), ("B", 2)])));
+        assert!(!is_concurrent(&local, &vc(&[("A", 1), ("B", 1)])));
+        assert!(is_concurrent(&local, &vc(&[("A", 1), ("B", 2)])));
+        assert!(is_concurrent(&local, &vc(&[("C", 1)])));
+        assert!(!is_concurrent(&vc(&[]), &vc(&[])));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let before = metrics();
+        record_duplicate();
+        record_failure("Insufficient funds: 'alice' has less than 10.");
+        record_failure("User 'bob' does not exist.");
+        record_wave(std::time::Duration::from_millis(4));
+        let after = metrics();
+        assert!(after.duplicate_transactions > before.duplicate_transactions);
+        assert!(after.overdraft_conflicts > before.overdraft_conflicts);
+        assert!(after.completed_waves > before.completed_waves);
+        assert!(after.average_wave_millis.is_some());
+    }
+}
@@ -0,0 +1,310 @@
+//! Confirmation of the transactions initiated from this site
+//!
+//! A critical command is only queued when the web client submits it: it is
+//! applied locally once this site holds the global mutex, then diffused to the
+//! other sites. Each queued command gets a ticket whose status follows these
+//! steps, up to the end of its diffusion wave, so that the web interface can
+//! tell how many sites confirmed the transaction.
+//!
+//! The transactions recorded by a diffused command are Pending in the database
+//! until the end of their wave, then Confirmed. A reaper flags as Failed those
+//! whose wave did not complete within [`WAVE_TIMEOUT`]; they become Confirmed
+//! if the wave completes later.
+
+/// Status of a critical command initiated from this site
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ConfirmationStatus {
+    /// Waiting for the global mutex
+    Queued,
+    /// Stored locally until the network is back, see `crate::offline`
+    Offline,
+    /// Applied locally, waiting for the acknowledgements of the other sites
+    Diffusing,
+    /// Acknowledged by every reachable site, including this one
+    Confirmed {
+        /// Number of sites that applied the transaction
+        sites: i64,
+    },
+    /// The command could not be applied
+    Failed(String),
+    /// The ticket is unknown, or too old to be remembered
+    Unknown,
+}
+
+impl ConfirmationStatus {
+    /// Returns true once the status will not change anymore
+    pub fn is_final(&self) -> bool {
+        !matches!(
+            self,
+            ConfirmationStatus::Queued
+                | ConfirmationStatus::Offline
+                | ConfirmationStatus::Diffusing
+        )
+    }
+}
+
+#[cfg(feature = "server")]
+/// Number of tickets remembered for clients that are waiting
+const MAX_TICKETS: usize = 256;
+
+#[cfg(feature = "server")]
+/// Time after which a wave that did not complete is considered failed
+pub const WAVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[cfg(feature = "server")]
+/// Transaction wave initiated by this site and not completed yet
+struct DiffusingWave {
+    /// Ticket of the command diffused
+    ticket: u64,
+    /// Lamport times of the transactions recorded by the command
+    lamports: std::ops::RangeInclusive<i64>,
+    /// Time at which the wave started
+    started: std::time::Instant,
+    /// True once the reaper flagged the wave as failed
+    expired: bool,
+}
+
+#[cfg(feature = "server")]
+/// Status of the recent tickets
+pub struct Confirmations {
+    /// Identifier given to the next ticket
+    next_ticket: u64,
+    /// Recent tickets with their status, oldest first
+    tickets: std::collections::VecDeque<(u64, ConfirmationStatus)>,
+    /// Transaction waves in progress, in diffusion order
+    diffusing: std::collections::VecDeque<DiffusingWave>,
+    /// Wakes up the clients waiting for a status change
+    pub notify: std::sync::Arc<tokio::sync::Notify>,
+}
+
+#[cfg(feature = "server")]
+impl Default for Confirmations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "server")]
+impl Confirmations {
+    /// Creates an empty tracker
+    pub fn new() -> Self {
+        Self {
+            next_ticket: 1,
+            tickets: std::collections::VecDeque::new(),
+            diffusing: std::collections::VecDeque::new(),
+            notify: std::sync::Arc::new(tokio::sync::Notify::new()),
+        }
+    }
+
+    /// Creates a ticket for a command that was just queued
+    pub fn register(&mut self) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.tickets.push_back((ticket, ConfirmationStatus::Queued));
+        while self.tickets.len() > MAX_TICKETS {
+            if let Some((old, _)) = self.tickets.pop_front() {
+                self.diffusing.retain(|wave| wave.ticket != old);
+            }
+        }
+        ticket
+    }
+
+    /// Returns the status of a ticket
+    pub fn status(&self, ticket: u64) -> ConfirmationStatus {
+        self.tickets
+            .iter()
+            .find(|(t, _)| *t == ticket)
+            .map(|(_, status)| status.clone())
+            .unwrap_or(ConfirmationStatus::Unknown)
+    }
+
+    fn set_status(&mut self, ticket: u64, status: ConfirmationStatus) {
+        if let Some((_, current)) = self.tickets.iter_mut().find(|(t, _)| *t == ticket) {
+            *current = status;
+            self.notify.notify_waiters();
+        }
+    }
+
+    /// Records that the command of a ticket was applied and is being diffused
+    ///
+    /// `lamports` are the Lamport times of the transactions it recorded.
+    pub fn diffusing(&mut self, ticket: u64, lamports: std::ops::RangeInclusive<i64>) {
+        self.diffusing.push_back(DiffusingWave {
+            ticket,
+            lamports,
+            started: std::time::Instant::now(),
+            expired: false,
+        });
+        self.set_status(ticket, ConfirmationStatus::Diffusing);
+    }
+
+    /// Records that the command of a ticket is confirmed by the given number of sites
+    pub fn confirmed(&mut self, ticket: u64, sites: i64) {
+        self.set_status(ticket, ConfirmationStatus::Confirmed { sites });
+    }
+
+    /// Records that the command of a ticket failed
+    pub fn failed(&mut self, ticket: u64, reason: String) {
+        self.diffusing.retain(|wave| wave.ticket != ticket);
+        self.set_status(ticket, ConfirmationStatus::Failed(reason));
+    }
+
+    /// Records the status reported by the full node for a forwarded command
+    pub fn resolve(&mut self, ticket: u64, status: ConfirmationStatus) {
+        self.set_status(ticket, status);
+    }
+
+    /// Records the end of the oldest transaction wave initiated by this site
+    ///
+    /// Our waves are diffused one after the other, so they come back in the
+    /// same order. Returns the Lamport times of the transactions of the wave.
+    pub fn wave_completed(&mut self, sites: i64) -> Option<std::ops::RangeInclusive<i64>> {
+        let wave = self.diffusing.pop_front()?;
+        crate::concurrency::record_wave(wave.started.elapsed());
+        self.confirmed(wave.ticket, sites);
+        Some(wave.lamports)
+    }
+
+    /// Flags the waves started more than `timeout` ago as failed
+    ///
+    /// The waves stay in the diffusion order, so that a late end still
+    /// confirms the right ticket. Returns the Lamport times of the
+    /// transactions of the waves newly flagged.
+    pub fn expire(&mut self, timeout: std::time::Duration) -> Vec<std::ops::RangeInclusive<i64>> {
+        let mut expired = Vec::new();
+        let mut tickets = Vec::new();
+        for wave in self.diffusing.iter_mut() {
+            if !wave.expired && wave.started.elapsed() >= timeout {
+                wave.expired = true;
+                tickets.push(wave.ticket);
+                expired.push(wave.lamports.clone());
+            }
+        }
+        for ticket in tickets {
+            self.set_status(
+                ticket,
+                ConfirmationStatus::Failed("The diffusion did not complete".to_string()),
+            );
+        }
+        expired
+    }
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    pub static ref CONFIRMATIONS: std::sync::Mutex<Confirmations> =
+        std::sync::Mutex::new(Confirmations::new());
+}
+
+#[cfg(feature = "server")]
+/// Waits until the status of a ticket is final
+///
+/// Returns the current status if it is still pending when the timeout expires.
+pub async fn wait_for_confirmation(
+    ticket: u64,
+    timeout: std::time::Duration,
+) -> ConfirmationStatus {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let notify = {
+            let confirmations = CONFIRMATIONS.lock().unwrap();
+            let status = confirmations.status(ticket);
+            if status.is_final() {
+                return status;
+            }
+            confirmations.notify.clone()
+        };
+        let notified = notify.notified();
+        if tokio::time::timeout_at(deadline, notified).await.is_err() {
+            return CONFIRMATIONS.lock().unwrap().status(ticket);
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Periodically flags the transactions whose wave did not complete as failed
+pub fn start_reaper() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(WAVE_TIMEOUT / 4);
+        loop {
+            interval.tick().await;
+            let expired = CONFIRMATIONS.lock().unwrap().expire(WAVE_TIMEOUT);
+            if expired.is_empty() {
+                continue;
+            }
+            let (site_id, _) = crate::state::local_site().await;
+            for lamports in expired {
+                log::warn!(
+                    "The wave of the transactions {}-{:?} did not complete",
+                    site_id,
+                    lamports
+                );
+                if let Err(e) = crate::db::set_transactions_status(
+                    &site_id,
+                    &lamports,
+                    crate::db::TransactionStatus::Failed,
+                ) {
+                    log::error!("Cannot flag the transactions as failed: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waves_confirm_tickets_in_order() {
+        let mut confirmations = Confirmations::new();
+        let first = confirmations.register();
+        let second = confirmations.register();
+        let third = confirmations.register();
+        assert_eq!(confirmations.status(first), ConfirmationStatus::Queued);
+
+        confirmations.diffusing(first, 4..=4);
+        confirmations.diffusing(second, 5..=7);
+        confirmations.failed(third, "User 'A' is archived.".to_string());
+        assert_eq!(confirmations.status(first), ConfirmationStatus::Diffusing);
+        assert!(confirmations.status(third).is_final());
+
+        assert_eq!(confirmations.wave_completed(3), Some(4..=4));
+        assert_eq!(
+            confirmations.status(first),
+            ConfirmationStatus::Confirmed { sites: 3 }
+        );
+        assert_eq!(confirmations.status(second), ConfirmationStatus::Diffusing);
+        assert_eq!(confirmations.status(42), ConfirmationStatus::Unknown);
+    }
+
+    #[test]
+    fn stuck_waves_expire_until_they_complete() {
+        let mut confirmations = Confirmations::new();
+        let first = confirmations.register();
+        let second = confirmations.register();
+        confirmations.diffusing(first, 1..=1);
+        assert!(
+            confirmations
+                .expire(std::time::Duration::from_secs(60))
+                .is_empty()
+        );
+
+        assert_eq!(confirmations.expire(std::time::Duration::ZERO), vec![1..=1]);
+        assert!(matches!(
+            confirmations.status(first),
+            ConfirmationStatus::Failed(_)
+        ));
+        // a wave is only flagged once
+        confirmations.diffusing(second, 2..=3);
+        assert_eq!(confirmations.expire(std::time::Duration::ZERO), vec![2..=3]);
+
+        // a late end confirms the expired wave, in order
+        assert_eq!(confirmations.wave_completed(2), Some(1..=1));
+        assert_eq!(
+            confirmations.status(first),
+            ConfirmationStatus::Confirmed { sites: 2 }
+        );
+    }
+}
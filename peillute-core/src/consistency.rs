@@ -0,0 +1,212 @@
+//! Cross-site consistency check
+//!
+//! `/check_consistency` runs a wave through the network collecting a digest
+//! of the state of each site: its number of transactions, its greatest
+//! Lamport time and the balance of each user. The digests are gathered back
+//! to the initiator, which compares them to its own and reports the sites
+//! that diverge and for which users. The check holds the global mutex like
+//! the other waves, so no transaction is in flight while the digests are
+//! taken, and is much cheaper than a snapshot since no transaction is sent.
+
+#![cfg(feature = "server")]
+
+/// Digest of the state of a site
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SiteDigest {
+    /// ID of the site
+    pub site_id: String,
+    /// Number of transactions recorded by the site
+    pub nb_transactions: i64,
+    /// Greatest Lamport time of the transactions of the site
+    pub max_lamport: i64,
+    /// Balance of each user, in cents
+    pub balances: std::collections::BTreeMap<String, i64>,
+}
+
+/// Site whose digest differs from the one of the initiator
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Divergence {
+    /// ID of the diverging site
+    pub site_id: String,
+    /// Number of transactions recorded by the site
+    pub nb_transactions: i64,
+    /// Greatest Lamport time of the transactions of the site
+    pub max_lamport: i64,
+    /// Users whose balance differs from the initiator, or missing on either side
+    pub users: Vec<String>,
+}
+
+/// Result of a consistency check
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ConsistencyReport {
+    /// ID of the site that started the check
+    pub initiator: String,
+    /// Number of sites that sent their digest, the initiator included
+    pub nb_sites: usize,
+    /// Sites whose state differs from the initiator
+    pub divergences: Vec<Divergence>,
+}
+
+impl ConsistencyReport {
+    /// Returns true if every site has the same state as the initiator
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Compares the digests of the sites to the one of the initiator
+pub fn compare(reference: &SiteDigest, digests: &[SiteDigest]) -> Vec<Divergence> {
+    let mut divergences = Vec::new();
+    for digest in digests {
+        if digest.site_id == reference.site_id {
+            continue;
+        }
+        let users: std::collections::BTreeSet<&String> = reference
+            .balances
+            .keys()
+            .chain(digest.balances.keys())
+            .filter(|user| reference.balances.get(*user) != digest.balances.get(*user))
+            .collect();
+        if users.is_empty() && digest.nb_transactions == reference.nb_transactions {
+            continue;
+        }
+        divergences.push(Divergence {
+            site_id: digest.site_id.clone(),
+            nb_transactions: digest.nb_transactions,
+            max_lamport: digest.max_lamport,
+            users: users.into_iter().cloned().collect(),
+        });
+    }
+    divergences.sort_by(|a, b| a.site_id.cmp(&b.site_id));
+    divergences
+}
+
+lazy_static::lazy_static! {
+    /// Digests collected during the current consistency check
+    static ref COLLECTED_DIGESTS: tokio::sync::Mutex<Vec<SiteDigest>> =
+        tokio::sync::Mutex::new(Vec::new());
+}
+
+/// Computes the digest of the state of the site
+pub async fn local_digest() -> rusqlite::Result<SiteDigest> {
+    let site_id = crate::state::local_site().await.0;
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    let (nb_transactions, max_lamport) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(MAX(lamport_time), 0) FROM Transactions",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let mut stmt = conn.prepare("SELECT unique_name, solde FROM User")?;
+    let balances = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                (row.get::<_, f64>(1)? * 100.0).round() as i64,
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    Ok(SiteDigest {
+        site_id,
+        nb_transactions,
+        max_lamport,
+        balances,
+    })
+}
+
+/// Starts collecting the digests of a new check with the one of the site
+pub async fn start() -> rusqlite::Result<()> {
+    let digest = local_digest().await?;
+    *COLLECTED_DIGESTS.lock().await = vec![digest];
+    Ok(())
+}
+
+/// Adds the digests received from a child of the wave
+pub async fn collect(digests: Vec<SiteDigest>) {
+    COLLECTED_DIGESTS.lock().await.extend(digests);
+}
+
+/// Takes the digests collected so far, to send them to the parent of the wave
+pub async fn take() -> Vec<SiteDigest> {
+    std::mem::take(&mut *COLLECTED_DIGESTS.lock().await)
+}
+
+/// Ends the check on the initiator and prints the sites that diverge
+pub async fn complete(digests: Vec<SiteDigest>) -> ConsistencyReport {
+    collect(digests).await;
+    let digests = take().await;
+    let initiator = crate::state::local_site().await.0;
+    let report = match digests.iter().find(|digest| digest.site_id == initiator) {
+        Some(reference) => ConsistencyReport {
+            initiator,
+            nb_sites: digests.len(),
+            divergences: compare(reference, &digests),
+        },
+        None => {
+            log::error!("The consistency check ended without the digest of the initiator");
+            ConsistencyReport {
+                initiator,
+                nb_sites: digests.len(),
+                divergences: Vec::new(),
+            }
+        }
+    };
+    print_report(&report);
+    report
+}
+
+/// Prints the result of a consistency check on the CLI
+fn print_report(report: &ConsistencyReport) {
+    if crate::control::output_format() == crate::control::OutputFormat::Json {
+        crate::control::print_json(report);
+        return;
+    }
+    if report.is_consistent() {
+        println!(
+            "✅ The {} sites have the same transactions and balances",
+            report.nb_sites
+        );
+        return;
+    }
+    for divergence in &report.divergences {
+        println!(
+            "❌ Site {} diverges ({} transactions, last Lamport time {}), users: {}",
+            divergence.site_id,
+            divergence.nb_transactions,
+            divergence.max_lamport,
+            divergence.users.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_digests() {
+        let digest = |site: &str, nb_transactions, balances: &[(&str, i64)]| SiteDigest {
+            site_id: site.to_string(),
+            nb_transactions,
+            max_lamport: nb_transactions,
+            balances: balances
+                .iter()
+                .map(|(user, balance)| (user.to_string(), *balance))
+                .collect(),
+        };
+        let reference = digest("A", 3, &[("alice", 1000), ("bob", 250)]);
+        let digests = vec![
+            reference.clone(),
+            digest("B", 3, &[("alice", 1000), ("bob", 250)]),
+            digest("D", 4, &[("alice", 1000), ("bob", 200), ("carol", 50)]),
+            digest("C", 2, &[("alice", 1000), ("bob", 250)]),
+        ];
+
+        let divergences = compare(&reference, &digests);
+        assert_eq!(divergences.len(), 2);
+        assert_eq!(divergences[0].site_id, "C");
+        assert!(divergences[0].users.is_empty());
+        assert_eq!(divergences[1].site_id, "D");
+        assert_eq!(divergences[1].users, vec!["bob", "carol"]);
+    }
+}
@@ -0,0 +1,179 @@
+//! Console of the CLI commands
+//!
+//! The CLI commands print their output on the terminal and prompt for their
+//! arguments on stdin. When run for a session of the admin socket, they write
+//! their output to the session and take their arguments from the ones sent
+//! with the command instead, so that the same handlers serve both.
+
+#![cfg(feature = "server")]
+
+/// Admin session running a CLI command
+#[derive(Debug, Default)]
+struct Session {
+    /// Answers to the prompts of the command, in order
+    answers: std::collections::VecDeque<String>,
+    /// Lines written by the command
+    output: Vec<String>,
+    /// True if the client asked for JSON output
+    json: bool,
+    /// Role of the connection running the command, see [`crate::operators`]
+    role: Option<crate::operators::Role>,
+}
+
+tokio::task_local! {
+    static SESSION: std::cell::RefCell<Session>;
+}
+
+/// Runs a CLI command for an admin session, returns the lines it wrote
+///
+/// The command runs with the role of the connection, which it may change by
+/// logging in or out. Fails if the command did, the lines written before the
+/// error being lost.
+pub async fn run_in_session(
+    command: crate::control::Command,
+    answers: Vec<String>,
+    json: bool,
+    role: &mut crate::operators::Role,
+) -> Result<Vec<String>, String> {
+    let session = Session {
+        answers: answers.into(),
+        output: Vec::new(),
+        json,
+        role: Some(*role),
+    };
+    SESSION
+        .scope(std::cell::RefCell::new(session), async move {
+            crate::control::process_cli_command(command)
+                .await
+                .map_err(|e| e.to_string())?;
+            SESSION.with(|session| {
+                let mut session = session.borrow_mut();
+                *role = session.role.unwrap_or(*role);
+                Ok::<_, String>(std::mem::take(&mut session.output))
+            })
+        })
+        .await
+}
+
+/// Returns the role of the admin session running the command, None if the
+/// command was typed on the terminal
+pub fn session_role() -> Option<crate::operators::Role> {
+    SESSION
+        .try_with(|session| {
+            session
+                .borrow()
+                .role
+                .unwrap_or_else(crate::operators::default_role)
+        })
+        .ok()
+}
+
+/// Changes the role of the admin session running the command, returns false
+/// if the command was typed on the terminal
+pub fn set_session_role(role: crate::operators::Role) -> bool {
+    SESSION
+        .try_with(|session| session.borrow_mut().role = Some(role))
+        .is_ok()
+}
+
+/// Returns true if the running command writes to an admin session asking for
+/// JSON output
+pub fn session_wants_json() -> bool {
+    SESSION
+        .try_with(|session| session.borrow().json)
+        .unwrap_or(false)
+}
+
+/// Writes a line of output, to the admin session running the command if any
+pub fn write_line(line: String) {
+    let mut line = Some(line);
+    let _ = SESSION.try_with(|session| session.borrow_mut().output.extend(line.take()));
+    // no session, the command was typed on the terminal
+    if let Some(line) = line {
+        println!("{}", line);
+    }
+}
+
+/// Prompts for an argument of a command
+///
+/// In an admin session, the next argument sent with the command is taken
+/// instead, and a missing argument fails the command.
+pub fn prompt(label: &str) -> Result<String, Box<dyn std::error::Error>> {
+    use std::io::{self, Write};
+
+    if let Ok(answer) = SESSION.try_with(|session| session.borrow_mut().answers.pop_front()) {
+        return answer
+            .map(|answer| answer.trim().to_string())
+            .ok_or_else(|| format!("Missing argument: {}", label).into());
+    }
+    print!("{}: ", label);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
+}
+
+/// Prompts for an argument of a command and parses it to a specific type
+///
+/// On the terminal, the argument is asked again until it parses.
+pub fn prompt_parse<T: std::str::FromStr>(label: &str) -> Result<T, Box<dyn std::error::Error>>
+where
+    T::Err: std::fmt::Debug,
+{
+    let in_session = SESSION.try_with(|_| ()).is_ok();
+    loop {
+        match prompt(label)?.parse() {
+            Ok(value) => return Ok(value),
+            Err(e) if in_session => {
+                return Err(format!("Invalid {}: {:?}", label.to_lowercase(), e).into());
+            }
+            Err(e) => cli_println!("Invalid input: {:?}", e),
+        }
+    }
+}
+
+/// Prompts for an amount, or an expression such as `12.5+3`, checked against
+/// the limits of the site
+///
+/// On the terminal, the amount is asked again until it is valid.
+pub fn prompt_amount(label: &str) -> Result<f64, Box<dyn std::error::Error>> {
+    let in_session = SESSION.try_with(|_| ()).is_ok();
+    loop {
+        match crate::amount::evaluate(&prompt(label)?, &crate::amount::limits()) {
+            Ok(amount) => return Ok(amount),
+            Err(e) if in_session => return Err(e.into()),
+            Err(e) => cli_println!("Invalid input: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Command;
+
+    #[tokio::test]
+    async fn test_session_prompts() {
+        let answers = vec!["alice".to_string(), "ten".to_string()];
+        let session = Session {
+            answers: answers.into(),
+            ..Session::default()
+        };
+        SESSION
+            .scope(std::cell::RefCell::new(session), async {
+                assert_eq!(prompt("Username").unwrap(), "alice");
+                assert!(prompt_parse::<f64>("Amount").is_err());
+                assert!(prompt("Username").is_err());
+                write_line("done".to_string());
+                SESSION.with(|session| assert_eq!(session.borrow().output, vec!["done"]));
+            })
+            .await;
+
+        let mut role = crate::operators::Role::Viewer;
+        let output = run_in_session(Command::Help, Vec::new(), false, &mut role)
+            .await
+            .unwrap();
+        assert_eq!(output[0], "📜 Command list:");
+        assert!(!session_wants_json());
+    }
+}
@@ -0,0 +1,4144 @@
+//! Database management for the Peillute application
+//!
+//! This module handles all database operations, including user management,
+//! transaction processing, and state synchronization. It uses SQLite as the
+//! underlying database engine.
+
+/// Represents a transaction in the system
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Transaction {
+    /// Source user of the transaction
+    pub from_user: String,
+    /// Destination user of the transaction
+    pub to_user: String,
+    /// Transaction amount
+    pub amount: f64,
+    /// Lamport timestamp of the transaction
+    pub lamport_time: i64,
+    /// ID of the node that created the transaction
+    pub source_node: String,
+    /// Optional message associated with the transaction
+    pub optional_msg: Option<String>,
+    /// Vector clock state at the time of the transaction
+    pub vector_clock: std::collections::HashMap<String, i64>,
+}
+
+/// Filters of a transaction search, every filter is optional
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionQuery {
+    /// User on either side of the transaction
+    pub user: Option<String>,
+    /// Minimum amount, inclusive
+    pub min_amount: Option<f64>,
+    /// Maximum amount, inclusive
+    pub max_amount: Option<f64>,
+    /// Substring of the transaction message
+    pub message: Option<String>,
+    /// ID of the node that created the transaction
+    pub node: Option<String>,
+    /// Minimum Lamport time, inclusive
+    pub min_lamport: Option<i64>,
+    /// Maximum Lamport time, inclusive
+    pub max_lamport: Option<i64>,
+    /// Page to return, starting at 0
+    pub page: usize,
+    /// Number of transactions per page, 0 for the default
+    pub page_size: usize,
+}
+
+/// Page of results of a transaction search
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionPage {
+    /// Transactions of the page, most recent first
+    pub transactions: Vec<Transaction>,
+    /// Number of transactions matching the filters
+    pub total: usize,
+    /// Page returned, starting at 0
+    pub page: usize,
+    /// Number of transactions per page
+    pub page_size: usize,
+}
+
+/// Product of the catalog shown on the payment page
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Product {
+    /// Unique name of the product
+    pub name: String,
+    /// Unit price of the product
+    pub price: f64,
+    /// Image of the product as a data URL, if one was uploaded
+    pub image: Option<String>,
+}
+
+/// Comment or emoji reaction attached to a transaction
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionComment {
+    /// User who wrote the comment
+    pub author: String,
+    /// Text of the comment, or the emoji of the reaction
+    pub text: String,
+    /// Whether the comment is an emoji reaction
+    pub reaction: bool,
+    /// Lamport timestamp of the comment
+    pub lamport_time: i64,
+    /// ID of the node where the comment was written
+    pub source_node: String,
+}
+
+/// Concurrent creations of the same user name, merged into a single account
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UserConflict {
+    /// Name created twice
+    pub user: String,
+    /// Site whose creation of the user was kept
+    pub winner_node: String,
+    /// Lamport time of the creation kept
+    pub winner_lamport: i64,
+    /// Site whose creation became an alias of the kept one
+    pub loser_node: String,
+    /// Lamport time of the creation that became an alias
+    pub loser_lamport: i64,
+    /// Local date at which the conflict was resolved
+    pub resolved_at: String,
+}
+
+/// Transaction of a user with the reason it cannot be refunded, if any
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RefundableTransaction {
+    /// Transaction of the user
+    pub transaction: Transaction,
+    /// Why the transaction cannot be refunded, None if it can
+    pub blocked_by: Option<String>,
+}
+
+impl RefundableTransaction {
+    /// Returns true if the transaction can be refunded
+    pub fn is_refundable(&self) -> bool {
+        self.blocked_by.is_none()
+    }
+}
+
+/// Replication state of a transaction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TransactionStatus {
+    /// Initiated by this site, its diffusion wave is in progress
+    Pending,
+    /// Applied by the reachable sites, or received from another site
+    #[default]
+    Confirmed,
+    /// Initiated by this site, its diffusion wave did not complete in time
+    Failed,
+}
+
+impl TransactionStatus {
+    /// Returns the value stored in the status column
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransactionStatus::Pending => "Pending",
+            TransactionStatus::Confirmed => "Confirmed",
+            TransactionStatus::Failed => "Failed",
+        }
+    }
+
+    /// Parses a value of the status column
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Pending" => Some(TransactionStatus::Pending),
+            "Confirmed" => Some(TransactionStatus::Confirmed),
+            "Failed" => Some(TransactionStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Order in which the history of a user is displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HistoryOrder {
+    /// Order in which the site recorded the transactions
+    #[default]
+    Recorded,
+    /// Lamport timestamp, ties broken by source site
+    Lamport,
+    /// Source site, then Lamport timestamp within each site
+    Site,
+    /// Topological order of the vector clocks, each transaction after its causes
+    Causal,
+}
+
+impl HistoryOrder {
+    /// Every order, as listed by the sort selector of the History page
+    pub const ALL: [HistoryOrder; 4] = [
+        HistoryOrder::Recorded,
+        HistoryOrder::Lamport,
+        HistoryOrder::Site,
+        HistoryOrder::Causal,
+    ];
+
+    /// Returns the value of the order in the sort selector
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryOrder::Recorded => "Recorded",
+            HistoryOrder::Lamport => "Lamport",
+            HistoryOrder::Site => "Site",
+            HistoryOrder::Causal => "Causal",
+        }
+    }
+
+    /// Parses a value of the sort selector
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Recorded" => Some(HistoryOrder::Recorded),
+            "Lamport" => Some(HistoryOrder::Lamport),
+            "Site" => Some(HistoryOrder::Site),
+            "Causal" => Some(HistoryOrder::Causal),
+            _ => None,
+        }
+    }
+
+    /// Returns the label of the order shown to the user
+    pub fn label(self) -> &'static str {
+        match self {
+            HistoryOrder::Recorded => "Recorded by this site",
+            HistoryOrder::Lamport => "Lamport time",
+            HistoryOrder::Site => "Source site",
+            HistoryOrder::Causal => "Causal order",
+        }
+    }
+}
+
+/// Emojis users can react with
+pub const REACTIONS: &[&str] = &["👍", "❤️", "😂", "🎉", "😮", "🍕"];
+
+/// Maximum length of a comment, in characters
+pub const MAX_COMMENT_LEN: usize = 280;
+
+#[cfg(feature = "server")]
+/// Products of the catalog of a new site
+const DEFAULT_PRODUCTS: &[(&str, f64)] = &[
+    ("Coca", 1.50),
+    ("Chips", 2.00),
+    ("Sandwich", 4.50),
+    ("Coffee", 1.20),
+];
+
+#[cfg(feature = "server")]
+/// Number of transactions per page when the query does not specify it
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+#[cfg(feature = "server")]
+impl TransactionQuery {
+    /// Builds the WHERE clause of the query and its parameters
+    fn to_sql_filter(&self) -> (String, Vec<rusqlite::types::Value>) {
+        use rusqlite::types::Value;
+
+        let mut clauses: Vec<&str> = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(user) = self.user.as_ref().filter(|u| !u.is_empty()) {
+            clauses.push("(from_user = ? OR to_user = ?)");
+            values.push(Value::Text(user.clone()));
+            values.push(Value::Text(user.clone()));
+        }
+        if let Some(min) = self.min_amount {
+            clauses.push("amount >= ?");
+            values.push(Value::Real(min));
+        }
+        if let Some(max) = self.max_amount {
+            clauses.push("amount <= ?");
+            values.push(Value::Real(max));
+        }
+        if let Some(message) = self.message.as_ref().filter(|m| !m.is_empty()) {
+            clauses.push("instr(IFNULL(optional_msg, ''), ?) > 0");
+            values.push(Value::Text(message.clone()));
+        }
+        if let Some(node) = self.node.as_ref().filter(|n| !n.is_empty()) {
+            clauses.push("source_node = ?");
+            values.push(Value::Text(node.clone()));
+        }
+        if let Some(min) = self.min_lamport {
+            clauses.push("lamport_time >= ?");
+            values.push(Value::Integer(min));
+        }
+        if let Some(max) = self.max_lamport {
+            clauses.push("lamport_time <= ?");
+            values.push(Value::Integer(max));
+        }
+
+        if clauses.is_empty() {
+            (String::new(), values)
+        } else {
+            (format!("WHERE {}", clauses.join(" AND ")), values)
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Connection to the database of the site, opened at startup with [`open`]
+pub struct Database {
+    conn: std::sync::Mutex<Option<rusqlite::Connection>>,
+}
+
+#[cfg(feature = "server")]
+/// Locked connection to the database of the site
+pub struct DatabaseGuard<'a>(std::sync::MutexGuard<'a, Option<rusqlite::Connection>>);
+
+#[cfg(feature = "server")]
+impl std::ops::Deref for DatabaseGuard<'_> {
+    type Target = rusqlite::Connection;
+
+    fn deref(&self) -> &rusqlite::Connection {
+        self.0
+            .as_ref()
+            .expect("the database is not opened, db::open must be called at startup")
+    }
+}
+
+#[cfg(feature = "server")]
+impl std::ops::DerefMut for DatabaseGuard<'_> {
+    fn deref_mut(&mut self) -> &mut rusqlite::Connection {
+        self.0
+            .as_mut()
+            .expect("the database is not opened, db::open must be called at startup")
+    }
+}
+
+#[cfg(feature = "server")]
+impl Database {
+    /// Locks the connection to the database
+    ///
+    /// Using the connection panics if no database was opened with [`open`].
+    pub fn lock(&self) -> std::sync::LockResult<DatabaseGuard<'_>> {
+        self.conn
+            .lock()
+            .map(DatabaseGuard)
+            .map_err(|e| std::sync::PoisonError::new(DatabaseGuard(e.into_inner())))
+    }
+}
+
+#[cfg(feature = "server")]
+/// Database of the site
+pub static DB_CONN: Database = Database {
+    conn: std::sync::Mutex::new(None),
+};
+
+#[cfg(feature = "server")]
+/// Returns the path of the database of a site when `--db-path` is not given
+pub fn default_path(db_id: u16) -> std::path::PathBuf {
+    std::path::PathBuf::from(format!("peillute_{}.db", db_id))
+}
+
+#[cfg(feature = "server")]
+/// Opens the database at `path`, closing the database opened before if any
+///
+/// Must be called before any other function of this module using the
+/// database of the site.
+pub fn open(path: &std::path::Path) -> rusqlite::Result<()> {
+    let conn = rusqlite::Connection::open(path)?;
+    set_connection(conn, false);
+    log::debug!("Database opened at {}", path.display());
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Opens a database held in memory, lost when the site stops
+///
+/// The database is named after `db_id` and opened with a shared cache, so that
+/// every connection of the process to it sees the same data. The local state
+/// of an ephemeral site is not saved: it starts as a new site every time and
+/// gets the transactions of the network from the synchronization snapshot.
+pub fn open_ephemeral(db_id: u16) -> rusqlite::Result<()> {
+    use rusqlite::OpenFlags;
+
+    let uri = format!("file:peillute_{}?mode=memory&cache=shared", db_id);
+    let conn = rusqlite::Connection::open_with_flags(
+        &uri,
+        OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI,
+    )?;
+    set_connection(conn, true);
+    log::debug!("Database opened in memory");
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// True if the database of the site is held in memory
+static EPHEMERAL: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(feature = "server")]
+/// Replaces the connection to the database of the site
+fn set_connection(conn: rusqlite::Connection, ephemeral: bool) {
+    let mut current = DB_CONN.conn.lock().unwrap_or_else(|e| e.into_inner());
+    *current = Some(conn);
+    EPHEMERAL.store(ephemeral, std::sync::atomic::Ordering::Relaxed);
+    crate::balances::clear();
+}
+
+#[cfg(feature = "server")]
+/// Returns true if a database was opened with [`open`] or [`open_ephemeral`]
+pub fn is_open() -> bool {
+    DB_CONN
+        .conn
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some()
+}
+
+#[cfg(feature = "server")]
+/// Returns true if the database of the site is held in memory
+pub fn is_ephemeral() -> bool {
+    EPHEMERAL.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+#[cfg(feature = "server")]
+/// Returns the name of the database file of the site, to display it
+pub fn database_name() -> String {
+    if is_ephemeral() {
+        return "in memory".to_string();
+    }
+    let conn = DB_CONN.lock().unwrap();
+    let path = conn.path().unwrap_or_default();
+    // keep only the name of the file (after the last "/")
+    path.split('/').next_back().unwrap_or_default().to_string()
+}
+
+#[cfg(feature = "server")]
+/// Special value representing a null user
+pub(crate) const NULL: &str = "NULL";
+
+#[cfg(feature = "server")]
+/// Message of the withdrawals, paid to the null user
+pub(crate) const WITHDRAW_MSG: &str = "Withdraw";
+
+#[cfg(feature = "server")]
+/// System account debited by the deposits, for the money entering Peillute
+pub const CASH_IN: &str = "@cash-in";
+
+#[cfg(feature = "server")]
+/// System account credited by the withdrawals and payments, for the money leaving Peillute
+pub const CASH_OUT: &str = "@cash-out";
+
+#[cfg(feature = "server")]
+/// Returns true if a name is the one of a system account, which no user can take
+pub fn is_system_account(name: &str) -> bool {
+    name == CASH_IN || name == CASH_OUT || crate::system_accounts::current().contains(name)
+}
+
+#[cfg(feature = "server")]
+/// Returns the user of an account, the null user for the system accounts
+pub fn user_of_account(account: &str) -> &str {
+    if is_system_account(account) {
+        NULL
+    } else {
+        account
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the account debited when money is taken from a user, `NULL` being cash in
+///
+/// These are the accounts of the snapshots, the same on every site; the
+/// postings use [`crate::system_accounts`].
+pub fn debit_account(user: &str) -> &str {
+    if user == NULL { CASH_IN } else { user }
+}
+
+#[cfg(feature = "server")]
+/// Returns the account credited when money is given to a user, `NULL` being cash out
+pub fn credit_account(user: &str) -> &str {
+    if user == NULL { CASH_OUT } else { user }
+}
+
+#[cfg(feature = "server")]
+/// Initializes the database schema
+pub fn init_db() -> rusqlite::Result<()> {
+    create_schema(&DB_CONN.lock().unwrap())?;
+    upgrade_db()?;
+
+    log::debug!("Database initialized successfully.");
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Creates the initial schema of a database
+pub fn create_schema(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    // Create VectorClock table for storing vector clock states
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS VectorClock (
+        id INTEGER PRIMARY KEY AUTOINCREMENT
+    );",
+        [],
+    )?;
+
+    // Create VectorClockEntry table for storing individual vector clock entries
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS VectorClockEntry (
+            vector_clock_id INTEGER,
+            site_id TEXT,
+            value INTEGER NOT NULL,
+            PRIMARY KEY(vector_clock_id, site_id),
+            FOREIGN KEY(vector_clock_id) REFERENCES VectorClock(id) ON DELETE CASCADE
+        );
+        ",
+        [],
+    )?;
+
+    // Create User table for storing user accounts
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS User (
+        unique_name TEXT PRIMARY KEY,
+        solde FLOAT NOT NULL,
+        is_active INTEGER NOT NULL DEFAULT 1
+    )",
+        [],
+    )?;
+
+    // Create Transactions table for storing transaction history
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Transactions (
+            from_user TEXT,
+            to_user TEXT NOT NULL,
+            amount FLOAT NOT NULL,
+            lamport_time INTEGER NOT NULL,
+            vector_clock_id INTEGER NOT NULL,
+            source_node TEXT NOT NULL,
+            optional_msg TEXT,
+            FOREIGN KEY(from_user) REFERENCES User(unique_name),
+            FOREIGN KEY(to_user) REFERENCES User(unique_name),
+            FOREIGN KEY(vector_clock_id) REFERENCES VectorClock(id),
+            PRIMARY KEY(lamport_time, source_node)
+        );",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS LocalState (
+        site_id TEXT PRIMARY KEY,
+        lamport_time INTEGER NOT NULL,
+        vector_clock_id INTEGER NOT NULL,
+        FOREIGN KEY(vector_clock_id) REFERENCES VectorClock(id)
+    );",
+        [],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Creates the tables added after the initial schema
+///
+/// Called on every start so that databases created by an older version of
+/// Peillute gain the new tables without being reset. The statistics of the
+/// query planner are refreshed at the same time, so that it picks the indexes
+/// suited to the current content of the tables.
+pub fn upgrade_db() -> rusqlite::Result<()> {
+    let conn = DB_CONN.lock().unwrap();
+    upgrade_schema(&conn)?;
+    conn.execute_batch("ANALYZE")
+}
+
+#[cfg(feature = "server")]
+/// Creates the tables added after the initial schema in a database
+pub fn upgrade_schema(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    // Archived users are kept in the User table, flagged as inactive
+    let has_is_active: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('User') WHERE name = 'is_active')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_is_active {
+        conn.execute(
+            "ALTER TABLE User ADD COLUMN is_active INTEGER NOT NULL DEFAULT 1",
+            [],
+        )?;
+    }
+
+    // Create UserSettings table for storing per-user preferences
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS UserSettings (
+            unique_name TEXT PRIMARY KEY,
+            alert_threshold FLOAT,
+            FOREIGN KEY(unique_name) REFERENCES User(unique_name)
+        );",
+        [],
+    )?;
+    // Store the UI preferences of each user next to its alert threshold
+    let has_dark_mode: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('UserSettings') WHERE name = 'dark_mode')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_dark_mode {
+        conn.execute("ALTER TABLE UserSettings ADD COLUMN dark_mode INTEGER", [])?;
+        conn.execute("ALTER TABLE UserSettings ADD COLUMN currency TEXT", [])?;
+        conn.execute(
+            "ALTER TABLE UserSettings ADD COLUMN history_page_size INTEGER",
+            [],
+        )?;
+    }
+
+    // Create Product table for storing the catalog, filled with the default
+    // products the first time so that every site starts with the same menu
+    let has_products: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'Product')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_products {
+        conn.execute(
+            "CREATE TABLE Product (
+                name TEXT PRIMARY KEY,
+                price FLOAT NOT NULL,
+                image TEXT
+            );",
+            [],
+        )?;
+        for (name, price) in DEFAULT_PRODUCTS {
+            conn.execute(
+                "INSERT INTO Product (name, price) VALUES (?1, ?2)",
+                rusqlite::params![name, price],
+            )?;
+        }
+    }
+
+    // Create Statements table for storing the closed accounting periods, and
+    // StatementTransaction for the transactions covered by each statement
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Statements (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            unique_name TEXT NOT NULL,
+            period TEXT NOT NULL,
+            closed_at TEXT NOT NULL,
+            opening_balance FLOAT NOT NULL,
+            total_in FLOAT NOT NULL,
+            total_out FLOAT NOT NULL,
+            closing_balance FLOAT NOT NULL,
+            FOREIGN KEY(unique_name) REFERENCES User(unique_name)
+        );",
+        [],
+    )?;
+    // Create UserAlias table for storing the former names of renamed users
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS UserAlias (
+            old_name TEXT PRIMARY KEY,
+            new_name TEXT NOT NULL,
+            lamport_time INTEGER NOT NULL,
+            source_node TEXT NOT NULL
+        );",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS StatementTransaction (
+            statement_id INTEGER NOT NULL,
+            lamport_time INTEGER NOT NULL,
+            source_node TEXT NOT NULL,
+            PRIMARY KEY(statement_id, lamport_time, source_node),
+            FOREIGN KEY(statement_id) REFERENCES Statements(id) ON DELETE CASCADE
+        );",
+        [],
+    )?;
+    // Create Policies and ForbiddenBeneficiary tables for storing the limits
+    // of each user, DailyTransfers for the amount transferred by each user
+    // per day and PolicyViolations for the flagged network operations
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Policies (
+            unique_name TEXT PRIMARY KEY,
+            max_withdrawal FLOAT,
+            daily_transfer_cap FLOAT
+        );",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ForbiddenBeneficiary (
+            unique_name TEXT NOT NULL,
+            beneficiary TEXT NOT NULL,
+            PRIMARY KEY(unique_name, beneficiary)
+        );",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS DailyTransfers (
+            unique_name TEXT NOT NULL,
+            day TEXT NOT NULL,
+            amount FLOAT NOT NULL,
+            PRIMARY KEY(unique_name, day)
+        );",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS PolicyViolations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            unique_name TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            lamport_time INTEGER NOT NULL,
+            source_node TEXT NOT NULL,
+            flagged_at TEXT NOT NULL
+        );",
+        [],
+    )?;
+    // Create TransactionComments table for storing the comments and reactions
+    // of the transactions, identified by their own lamport time and node
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS TransactionComments (
+            lamport_time INTEGER NOT NULL,
+            source_node TEXT NOT NULL,
+            author TEXT NOT NULL,
+            content TEXT NOT NULL,
+            is_reaction INTEGER NOT NULL,
+            comment_lamport_time INTEGER NOT NULL,
+            comment_source_node TEXT NOT NULL,
+            PRIMARY KEY(comment_lamport_time, comment_source_node)
+        );",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_transaction_comments
+        ON TransactionComments(lamport_time, source_node)",
+        [],
+    )?;
+    // Create Favorites table for storing the transfer templates of each user
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Favorites (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            unique_name TEXT NOT NULL,
+            beneficiary TEXT NOT NULL,
+            amount FLOAT NOT NULL,
+            message TEXT
+        );",
+        [],
+    )?;
+    // Create Notifications table for storing the events shown to each user
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Notifications (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            unique_name TEXT NOT NULL,
+            message TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            is_read INTEGER NOT NULL DEFAULT 0
+        );",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_notifications_user
+        ON Notifications(unique_name, is_read)",
+        [],
+    )?;
+    // Record the site and Lamport time of the creation of each user, to
+    // resolve the concurrent creations of the same name
+    let has_created_by: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('User') WHERE name = 'created_by')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_created_by {
+        conn.execute("ALTER TABLE User ADD COLUMN created_by TEXT", [])?;
+        conn.execute("ALTER TABLE User ADD COLUMN created_lamport INTEGER", [])?;
+    }
+    // and its vector clock, to tell the concurrent creations from the causal ones
+    let has_created_clock: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('User') WHERE name = 'created_clock_id')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_created_clock {
+        conn.execute("ALTER TABLE User ADD COLUMN created_clock_id INTEGER", [])?;
+    }
+    // Create UserConflicts table for storing the resolved concurrent creations
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS UserConflicts (
+            unique_name TEXT NOT NULL,
+            winner_node TEXT NOT NULL,
+            winner_lamport INTEGER NOT NULL,
+            loser_node TEXT NOT NULL,
+            loser_lamport INTEGER NOT NULL,
+            resolved_at TEXT NOT NULL,
+            PRIMARY KEY(unique_name, loser_node, loser_lamport)
+        );",
+        [],
+    )?;
+    // Create Postings table for storing the debit and the credit of each
+    // transaction, the balances being computed from it. The transactions
+    // recorded before are posted once when the table is created
+    let has_postings: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'Postings')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_postings {
+        conn.execute(
+            "CREATE TABLE Postings (
+                lamport_time INTEGER NOT NULL,
+                source_node TEXT NOT NULL,
+                account TEXT NOT NULL,
+                side TEXT NOT NULL CHECK(side IN ('debit', 'credit')),
+                amount_in_cent INTEGER NOT NULL,
+                PRIMARY KEY(lamport_time, source_node, side),
+                FOREIGN KEY(lamport_time, source_node) REFERENCES Transactions(lamport_time, source_node)
+            );",
+            [],
+        )?;
+        conn.execute("CREATE INDEX idx_postings_account ON Postings(account)", [])?;
+        conn.execute(
+            "INSERT INTO Postings (lamport_time, source_node, account, side, amount_in_cent)
+            SELECT lamport_time, source_node,
+                CASE WHEN from_user IS NULL OR from_user = ?1 THEN ?2 ELSE from_user END,
+                'debit', CAST(ROUND(amount * 100) AS INTEGER)
+            FROM Transactions",
+            rusqlite::params![NULL, CASH_IN],
+        )?;
+        conn.execute(
+            "INSERT INTO Postings (lamport_time, source_node, account, side, amount_in_cent)
+            SELECT lamport_time, source_node,
+                CASE WHEN to_user = ?1 THEN ?2 ELSE to_user END,
+                'credit', CAST(ROUND(amount * 100) AS INTEGER)
+            FROM Transactions",
+            rusqlite::params![NULL, CASH_OUT],
+        )?;
+    }
+    // Link each refund to the transaction it refunds, so that finding the
+    // refund of a transaction does not scan the messages
+    let has_refund_of: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('Transactions') WHERE name = 'refund_of_node')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_refund_of {
+        conn.execute(
+            "ALTER TABLE Transactions ADD COLUMN refund_of_node TEXT",
+            [],
+        )?;
+        conn.execute(
+            "ALTER TABLE Transactions ADD COLUMN refund_of_lamport INTEGER",
+            [],
+        )?;
+        let refunds = {
+            let mut stmt = conn.prepare(
+                "SELECT lamport_time, source_node, optional_msg FROM Transactions
+                WHERE optional_msg LIKE 'Refund transaction %'",
+            )?;
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        for (lamport_time, source_node, msg) in refunds {
+            if let Some((refunded_node, refunded_time)) = refunded_transaction(&msg) {
+                conn.execute(
+                    "UPDATE Transactions SET refund_of_node = ?1, refund_of_lamport = ?2
+                    WHERE lamport_time = ?3 AND source_node = ?4",
+                    rusqlite::params![refunded_node, refunded_time, lamport_time, source_node],
+                )?;
+            }
+        }
+    }
+    // Track the replication of the transactions initiated by this site, the
+    // existing ones being settled
+    let has_status: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('Transactions') WHERE name = 'status')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_status {
+        conn.execute(
+            "ALTER TABLE Transactions ADD COLUMN status TEXT NOT NULL DEFAULT 'Confirmed'",
+            [],
+        )?;
+    }
+    // Date the transactions when they are recorded, for the refund window;
+    // the window of the existing ones starts now
+    let has_created_at: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('Transactions') WHERE name = 'created_at')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_created_at {
+        conn.execute("ALTER TABLE Transactions ADD COLUMN created_at INTEGER", [])?;
+        conn.execute(
+            "UPDATE Transactions SET created_at = CAST(strftime('%s', 'now') AS INTEGER)",
+            [],
+        )?;
+    }
+    // Keep several independent ledgers, the existing users and transactions
+    // belonging to the default one
+    let has_ledger: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('User') WHERE name = 'ledger')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_ledger {
+        conn.execute(
+            "ALTER TABLE User ADD COLUMN ledger TEXT NOT NULL DEFAULT 'default'",
+            [],
+        )?;
+        conn.execute(
+            "ALTER TABLE Transactions ADD COLUMN ledger TEXT NOT NULL DEFAULT 'default'",
+            [],
+        )?;
+    }
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_user_ledger ON User(ledger)",
+        [],
+    )?;
+    // Create SiteAliases table for storing the aliases of the known sites
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS SiteAliases (
+            site_id TEXT PRIMARY KEY,
+            alias TEXT NOT NULL
+        );",
+        [],
+    )?;
+    // Create Quarantine table for storing the operations received from the
+    // network that could not be applied yet
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Quarantine (
+            lamport_time INTEGER NOT NULL,
+            source_node TEXT NOT NULL,
+            message TEXT NOT NULL,
+            clock TEXT NOT NULL,
+            description TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            quarantined_at TEXT NOT NULL,
+            PRIMARY KEY(lamport_time, source_node)
+        );",
+        [],
+    )?;
+    // Create ApiTokens table for storing the hashes of the tokens of the
+    // machine clients, with the user a token may operate on
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ApiTokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            label TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            scope TEXT NOT NULL,
+            unique_name TEXT,
+            created_at TEXT NOT NULL,
+            last_used_at TEXT,
+            revoked INTEGER NOT NULL DEFAULT 0
+        );",
+        [],
+    )?;
+    // Create RefundRequests table for storing the refunds waiting for the
+    // approval of the recipient of the transaction
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS RefundRequests (
+            lamport_time INTEGER NOT NULL,
+            source_node TEXT NOT NULL,
+            transac_lamport INTEGER NOT NULL,
+            transac_node TEXT NOT NULL,
+            requester TEXT NOT NULL,
+            approver TEXT NOT NULL,
+            amount REAL NOT NULL,
+            status TEXT NOT NULL,
+            requested_at TEXT NOT NULL,
+            PRIMARY KEY(lamport_time, source_node)
+        );",
+        [],
+    )?;
+    // Create PendingLocal table for storing the transactions submitted while
+    // the site was offline, until they are flushed
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS PendingLocal (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            unique_name TEXT NOT NULL,
+            command TEXT NOT NULL,
+            description TEXT NOT NULL,
+            queued_at TEXT NOT NULL,
+            error TEXT
+        );",
+        [],
+    )?;
+    // Create KnownPeers table for storing the peers reached by the site, tried
+    // first when it starts again
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS KnownPeers (
+            addr TEXT PRIMARY KEY,
+            last_seen INTEGER NOT NULL,
+            failures INTEGER NOT NULL DEFAULT 0
+        );",
+        [],
+    )?;
+    // Create AuditLog table for storing the risky administration operations
+    // with the snapshot saved before each of them
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS AuditLog (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at TEXT NOT NULL,
+            operation TEXT NOT NULL,
+            target TEXT NOT NULL,
+            rollback TEXT
+        );",
+        [],
+    )?;
+    // Create PendingCommands table for storing the critical commands waiting
+    // for the global mutex, replayed if the site stops before executing them
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS PendingCommands (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            command TEXT NOT NULL,
+            queued_at TEXT NOT NULL,
+            started_lamport INTEGER,
+            started_node TEXT
+        );",
+        [],
+    )?;
+    // Create Sagas table for storing the operations made of several steps,
+    // with the step that failed if they were compensated
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Sagas (
+            lamport_time INTEGER NOT NULL,
+            source_node TEXT NOT NULL,
+            steps TEXT NOT NULL,
+            status TEXT NOT NULL,
+            failed_step INTEGER,
+            reason TEXT,
+            recorded_at TEXT NOT NULL,
+            PRIMARY KEY(lamport_time, source_node)
+        );",
+        [],
+    )?;
+    // Index the lookups of the transactions of a user, of a site and of the
+    // refund of a transaction
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_transactions_from ON Transactions(from_user);
+        CREATE INDEX IF NOT EXISTS idx_transactions_to ON Transactions(to_user);
+        CREATE INDEX IF NOT EXISTS idx_transactions_node ON Transactions(source_node, lamport_time);
+        CREATE INDEX IF NOT EXISTS idx_transactions_refund
+        ON Transactions(refund_of_node, refund_of_lamport);",
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Beginning of the message of a refund, followed by `<node>-<lamport time>`
+/// of the refunded transaction
+const REFUND_PREFIX: &str = "Refund transaction ";
+
+#[cfg(feature = "server")]
+/// Returns the source node and Lamport time of the transaction refunded by a
+/// transaction with this message, if it is a refund
+pub fn refunded_transaction(optional_msg: &str) -> Option<(&str, i64)> {
+    let (node, lamport_time) = optional_msg.strip_prefix(REFUND_PREFIX)?.rsplit_once('-')?;
+    Some((node, lamport_time.parse().ok()?))
+}
+
+#[cfg(feature = "server")]
+/// Records the debit of `from_user` and the credit of `to_user` for a transaction
+pub fn insert_postings(
+    conn: &rusqlite::Connection,
+    from_user: &str,
+    to_user: &str,
+    amount: f64,
+    lamport_time: i64,
+    source_node: &str,
+    optional_msg: &str,
+) -> rusqlite::Result<()> {
+    let amount_in_cent = (amount * 100.0).round() as i64;
+    let (debit, credit) =
+        crate::system_accounts::current().posting_accounts(from_user, to_user, optional_msg);
+    for (account, side) in [(debit, "debit"), (credit, "credit")] {
+        conn.execute(
+            "INSERT INTO Postings (lamport_time, source_node, account, side, amount_in_cent)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![lamport_time, source_node, account, side, amount_in_cent],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Update the local state of the site
+pub fn update_local_state(site_id: &str, clock: crate::clock::Clock) -> rusqlite::Result<()> {
+    use rusqlite::params;
+
+    if is_ephemeral() {
+        // an ephemeral site is never reloaded, its state would be lost anyway
+        return Ok(());
+    }
+
+    let lamport_time = clock.get_lamport();
+    let vc_clock = clock.get_vector_clock_map();
+
+    let conn = DB_CONN.lock().unwrap();
+    conn.execute("INSERT INTO VectorClock DEFAULT VALUES", [])?;
+    let vector_clock_id = conn.last_insert_rowid();
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO VectorClockEntry (vector_clock_id, site_id, value) VALUES (?1, ?2, ?3)",
+    )?;
+    for (site, value) in vc_clock.iter() {
+        stmt.execute(params![vector_clock_id, site, value])?;
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO LocalState (site_id, lamport_time, vector_clock_id)
+        VALUES (?1, ?2, ?3)",
+        params![site_id, lamport_time, vector_clock_id],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Update the database with a snapshot
+///
+/// The users and balances are reconciled once the transactions are applied,
+/// see [`reconcile_after_sync`].
+pub fn update_db_with_snapshot(
+    snapshot: &crate::snapshot::GlobalSnapshot,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> SyncReconciliation {
+    log::info!("Applying snapshot to database");
+    apply_snapshot_transactions(snapshot, vector_clock);
+    match reconcile_after_sync() {
+        Ok(reconciliation) => reconciliation,
+        Err(e) => {
+            log::error!("Cannot reconcile the users after the sync: {}", e);
+            SyncReconciliation::default()
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Applies the transactions missing from the site in a snapshot, in Lamport order
+fn apply_snapshot_transactions(
+    snapshot: &crate::snapshot::GlobalSnapshot,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) {
+    if let Err(e) = crate::ledger::apply_user_ledgers(&snapshot.ledgers) {
+        log::error!(
+            "Cannot put the users of the snapshot in their ledger: {}",
+            e
+        );
+    }
+
+    if snapshot.missing.is_empty() {
+        log::info!("No missing transactions, nothing to do");
+        return;
+    }
+
+    // sort tsx actions by lamport time
+    let mut sorted_txs: Vec<_> = snapshot
+        .missing
+        .values()
+        .flat_map(|txs| txs.iter())
+        .collect();
+    sorted_txs.sort_by_key(|tx| tx.lamport_time);
+
+    for tx in sorted_txs {
+        let optional_msg = "";
+
+        let _ = crate::db::create_transaction(
+            &tx.from_user,
+            &tx.to_user,
+            (tx.amount_in_cent as f64) / 100.0,
+            &tx.lamport_time,
+            &tx.source_node,
+            optional_msg,
+            vector_clock,
+        );
+    }
+}
+
+#[cfg(feature = "server")]
+/// Result of the reconciliation of the users after a synchronization
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct SyncReconciliation {
+    /// Users referenced by the transactions that did not exist on the site
+    pub created_users: Vec<String>,
+    /// Users whose stored balance changed, with their former and new balances
+    pub changed_balances: Vec<(String, f64, f64)>,
+}
+
+#[cfg(feature = "server")]
+impl SyncReconciliation {
+    /// Returns true if the sync left the users and their balances untouched
+    pub fn is_empty(&self) -> bool {
+        self.created_users.is_empty() && self.changed_balances.is_empty()
+    }
+}
+
+#[cfg(feature = "server")]
+/// Creates the users referenced by the transactions of the site and
+/// recomputes every stored balance from the postings
+///
+/// A snapshot may bring transactions of users whose creation the site never
+/// received, or apply a transaction whose balance update was already
+/// counted. Each user changed is logged and printed on the CLI.
+pub fn reconcile_after_sync() -> rusqlite::Result<SyncReconciliation> {
+    let reconciliation = reconcile_users_in(&DB_CONN.lock().unwrap())?;
+    // the cached balances were computed before the reconciliation
+    crate::balances::clear();
+    for user in &reconciliation.created_users {
+        log::warn!(
+            "Created the user {}, referenced by synced transactions",
+            user
+        );
+        cli_println!(
+            "👤 Created the user {}, referenced by synced transactions",
+            user
+        );
+    }
+    for (user, before, after) in &reconciliation.changed_balances {
+        log::warn!(
+            "Balance of {} corrected from {:.2} to {:.2} after the sync",
+            user,
+            before,
+            after
+        );
+        cli_println!(
+            "⚖️ Balance of {} corrected from {:.2} to {:.2} after the sync",
+            user,
+            before,
+            after
+        );
+    }
+    Ok(reconciliation)
+}
+
+#[cfg(feature = "server")]
+/// Reconciles the users of a database, see [`reconcile_after_sync`]
+fn reconcile_users_in(conn: &rusqlite::Connection) -> rusqlite::Result<SyncReconciliation> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT name FROM (
+            SELECT from_user AS name FROM Transactions
+            UNION SELECT to_user FROM Transactions
+        ) WHERE name IS NOT NULL AND name != ?1
+            AND name NOT IN (SELECT unique_name FROM User)
+        ORDER BY name",
+    )?;
+    let created_users = stmt
+        .query_map(rusqlite::params![NULL], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|name| !is_system_account(name))
+        .collect::<Vec<_>>();
+    for user in &created_users {
+        conn.execute(
+            "INSERT INTO User (unique_name, solde) VALUES (?1, 0)",
+            rusqlite::params![user],
+        )?;
+    }
+
+    let mut stmt = conn.prepare("SELECT unique_name, solde FROM User ORDER BY unique_name")?;
+    let users = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut changed_balances = Vec::new();
+    for (user, stored) in users {
+        let balance = account_balance(conn, &user)?;
+        if (balance - stored).abs() >= 0.005 {
+            conn.execute(
+                "UPDATE User SET solde = ?1 WHERE unique_name = ?2",
+                rusqlite::params![balance, user],
+            )?;
+            changed_balances.push((user, stored, balance));
+        }
+    }
+
+    Ok(SyncReconciliation {
+        created_users,
+        changed_balances,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Get the local state of the site
+pub fn get_local_state() -> rusqlite::Result<(String, crate::clock::Clock)> {
+    use rusqlite::params;
+    let conn = DB_CONN.lock().unwrap();
+    let mut stmt =
+        conn.prepare("SELECT site_id, lamport_time, vector_clock_id FROM LocalState LIMIT 1")?;
+
+    let row = stmt.query_row([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)));
+
+    let (site_id, lamport_time, vector_clock_id): (String, i64, i64) = match row {
+        Ok(data) => data,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut clock_map = std::collections::HashMap::new();
+    let mut vc_stmt =
+        conn.prepare("SELECT site_id, value FROM VectorClockEntry WHERE vector_clock_id = ?1")?;
+    let mut rows = vc_stmt.query(params![vector_clock_id])?;
+    while let Some(vc_row) = rows.next()? {
+        let site: String = vc_row.get(0)?;
+        let value: i64 = vc_row.get(1)?;
+        clock_map.insert(site, value);
+    }
+
+    let c = crate::clock::Clock::from_parts(lamport_time, clock_map);
+
+    Ok((site_id, c))
+}
+
+#[cfg(feature = "server")]
+/// Checks if the database has been initialized
+pub fn is_database_initialized() -> rusqlite::Result<bool> {
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'Transactions')",
+        )?;
+        let exists: bool = stmt.query_row([], |row| row.get(0))?;
+        Ok(exists)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Check if a transaction exists in the database
+pub fn transaction_exists(lamport_time: i64, source_node: &str) -> rusqlite::Result<bool> {
+    use rusqlite::params;
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT EXISTS(SELECT 1 FROM Transactions WHERE lamport_time = ?1 AND source_node = ?2)",
+        )?;
+        let exists: bool = stmt.query_row(params![lamport_time, source_node], |row| row.get(0))?;
+        Ok(exists)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Checks if a user exists in the database
+pub fn user_exists(name: &str) -> rusqlite::Result<bool> {
+    user_exists_in(&DB_CONN.lock().unwrap(), name)
+}
+
+#[cfg(feature = "server")]
+/// Checks if a user exists in a database
+fn user_exists_in(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<bool> {
+    use rusqlite::params;
+    let mut stmt = conn.prepare("SELECT EXISTS(SELECT 1 FROM User WHERE unique_name = ?1)")?;
+    let exists: bool = stmt.query_row(params![name], |row| row.get(0))?;
+    Ok(exists)
+}
+
+#[cfg(feature = "server")]
+/// Creates a new user with zero balance
+pub fn create_user(unique_name: &str) -> rusqlite::Result<()> {
+    create_user_in(&DB_CONN.lock().unwrap(), unique_name)
+}
+
+#[cfg(feature = "server")]
+/// Creates a new user with zero balance in a database
+fn create_user_in(conn: &rusqlite::Connection, unique_name: &str) -> rusqlite::Result<()> {
+    use rusqlite::params;
+    if user_exists_in(conn, unique_name)? {
+        log::warn!("User '{}' already exists.", unique_name);
+        return Ok(());
+    }
+
+    log::debug!("Ajout de l'utilisateur {}", unique_name);
+    conn.execute(
+        "INSERT INTO User (unique_name, solde) VALUES (?1, 0)",
+        params![unique_name],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Creates a new user with an initial balance
+///
+/// The balance is recorded as a deposit, so that it appears in the history
+/// of the user and is shared by the snapshots like any transaction.
+pub fn create_user_with_solde(
+    unique_name: &str,
+    solde: f64,
+    lamport_time: &i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    // the user may already be created in its ledger, see [`create_user_from`]
+    if !user_exists(unique_name)? {
+        create_user(unique_name)?;
+    }
+    if solde > 0.0 {
+        create_transaction(
+            NULL,
+            unique_name,
+            solde,
+            lamport_time,
+            source_node,
+            "Initial balance",
+            vector_clock,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Creates a user in a ledger on behalf of the site that initiated its creation
+///
+/// Returns the conflict resolved if the name was already created concurrently
+/// by another site, see [`create_user_from_in`].
+pub fn create_user_from(
+    unique_name: &str,
+    ledger: &str,
+    lamport_time: i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<Option<UserConflict>> {
+    let conn = DB_CONN.lock().unwrap();
+    create_user_from_in(
+        &conn,
+        unique_name,
+        ledger,
+        lamport_time,
+        source_node,
+        vector_clock,
+    )
+}
+
+#[cfg(feature = "server")]
+/// Stores a vector clock, returns its ID
+fn insert_vector_clock(
+    conn: &rusqlite::Connection,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<i64> {
+    conn.execute("INSERT INTO VectorClock DEFAULT VALUES", [])?;
+    let vector_clock_id = conn.last_insert_rowid();
+    let mut stmt = conn.prepare(
+        "INSERT INTO VectorClockEntry (vector_clock_id, site_id, value) VALUES (?1, ?2, ?3)",
+    )?;
+    for (site_id, value) in vector_clock {
+        stmt.execute(rusqlite::params![vector_clock_id, site_id, value])?;
+    }
+    Ok(vector_clock_id)
+}
+
+#[cfg(feature = "server")]
+/// Creates a user in a database, recording the creation it comes from
+///
+/// When two sites create the same name concurrently, both creations are merged
+/// into a single account: the creation of the site with the lowest site ID
+/// (then the lowest Lamport time) is kept as the origin of the account on
+/// every site, whatever the order in which the creations are received, and the
+/// other one is recorded as an alias of it in the UserConflicts table. The
+/// account belongs to the ledger of the creation kept.
+///
+/// The creations are concurrent when neither vector clock dominates the other,
+/// or when one of them carries no vector. Otherwise the creation that happened
+/// first is kept as the origin, and no conflict is recorded.
+fn create_user_from_in(
+    conn: &rusqlite::Connection,
+    unique_name: &str,
+    ledger: &str,
+    lamport_time: i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<Option<UserConflict>> {
+    use rusqlite::{OptionalExtension, params};
+
+    let origin: Option<(Option<String>, Option<i64>, Option<i64>)> = conn
+        .query_row(
+            "SELECT created_by, created_lamport, created_clock_id FROM User WHERE unique_name = ?1",
+            params![unique_name],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    let (kept_node, kept_lamport, kept_clock_id) = match origin {
+        None => {
+            let clock_id = insert_vector_clock(conn, vector_clock)?;
+            conn.execute(
+                "INSERT INTO User (unique_name, solde, created_by, created_lamport, created_clock_id, ledger)
+                VALUES (?1, 0, ?2, ?3, ?4, ?5)",
+                params![unique_name, source_node, lamport_time, clock_id, ledger],
+            )?;
+            return Ok(None);
+        }
+        Some((Some(node), Some(lamport), clock_id)) => (node, lamport, clock_id),
+        // created before the origins were recorded, or by a snapshot
+        Some(_) => return Ok(None),
+    };
+    if (kept_node.as_str(), kept_lamport) == (source_node, lamport_time) {
+        return Ok(None);
+    }
+
+    let kept_clock = match kept_clock_id {
+        Some(id) => read_vector_clock(conn, id)?,
+        None => std::collections::HashMap::new(),
+    };
+    if !kept_clock.is_empty()
+        && !vector_clock.is_empty()
+        && !crate::concurrency::is_concurrent(&kept_clock, vector_clock)
+    {
+        let new_first = vector_clock
+            .iter()
+            .all(|(site, value)| kept_clock.get(site).copied().unwrap_or(0) >= *value);
+        if new_first {
+            // the creation received last happened first, it is the origin
+            let clock_id = insert_vector_clock(conn, vector_clock)?;
+            conn.execute(
+                "UPDATE User SET created_by = ?2, created_lamport = ?3, created_clock_id = ?4,
+                ledger = ?5 WHERE unique_name = ?1",
+                params![unique_name, source_node, lamport_time, clock_id, ledger],
+            )?;
+        }
+        return Ok(None);
+    }
+
+    let new_wins = (source_node, lamport_time) < (kept_node.as_str(), kept_lamport);
+    let ((winner_node, winner_lamport), (loser_node, loser_lamport)) = if new_wins {
+        (
+            (source_node, lamport_time),
+            (kept_node.as_str(), kept_lamport),
+        )
+    } else {
+        (
+            (kept_node.as_str(), kept_lamport),
+            (source_node, lamport_time),
+        )
+    };
+    let resolved_at = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+
+    let sql_tx = conn.unchecked_transaction()?;
+    if new_wins {
+        let clock_id = insert_vector_clock(&sql_tx, vector_clock)?;
+        sql_tx.execute(
+            "UPDATE User SET created_by = ?2, created_lamport = ?3, created_clock_id = ?4,
+            ledger = ?5 WHERE unique_name = ?1",
+            params![unique_name, winner_node, winner_lamport, clock_id, ledger],
+        )?;
+        // the creations that lost against the former origin lose against this one
+        sql_tx.execute(
+            "UPDATE UserConflicts SET winner_node = ?2, winner_lamport = ?3 WHERE unique_name = ?1",
+            params![unique_name, winner_node, winner_lamport],
+        )?;
+    }
+    sql_tx.execute(
+        "INSERT OR IGNORE INTO UserConflicts
+        (unique_name, winner_node, winner_lamport, loser_node, loser_lamport, resolved_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            unique_name,
+            winner_node,
+            winner_lamport,
+            loser_node,
+            loser_lamport,
+            resolved_at
+        ],
+    )?;
+    sql_tx.commit()?;
+
+    log::warn!(
+        "User '{}' was created concurrently by sites {} and {}, the creation of {} is kept",
+        unique_name,
+        winner_node,
+        loser_node,
+        winner_node
+    );
+    Ok(Some(UserConflict {
+        user: unique_name.to_string(),
+        winner_node: winner_node.to_string(),
+        winner_lamport,
+        loser_node: loser_node.to_string(),
+        loser_lamport,
+        resolved_at,
+    }))
+}
+
+#[cfg(feature = "server")]
+/// Returns the resolved concurrent creations of users, the most recent first
+pub fn get_user_conflicts() -> rusqlite::Result<Vec<UserConflict>> {
+    let conn = DB_CONN.lock().unwrap();
+    user_conflicts_in(&conn)
+}
+
+#[cfg(feature = "server")]
+/// Returns the resolved concurrent creations of users of a database
+fn user_conflicts_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<UserConflict>> {
+    let mut stmt = conn.prepare(
+        "SELECT unique_name, winner_node, winner_lamport, loser_node, loser_lamport, resolved_at
+        FROM UserConflicts ORDER BY resolved_at DESC, unique_name, loser_node",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(UserConflict {
+            user: row.get(0)?,
+            winner_node: row.get(1)?,
+            winner_lamport: row.get(2)?,
+            loser_node: row.get(3)?,
+            loser_lamport: row.get(4)?,
+            resolved_at: row.get(5)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Archives or restores a user
+///
+/// Archived users keep their transactions and balance but are hidden from the
+/// user lists and cannot start new operations.
+pub fn set_user_active(name: &str, active: bool) -> rusqlite::Result<()> {
+    use rusqlite::params;
+    if !user_exists(name)? {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("User '{}' does not exist.", name)),
+        );
+
+        log::error!("User '{}' does not exist.", name);
+        return Err(err);
+    }
+    {
+        let conn = DB_CONN.lock().unwrap();
+        conn.execute(
+            "UPDATE User SET is_active = ?1 WHERE unique_name = ?2",
+            params![active, name],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+/// Archives a user
+pub fn deactivate_user(name: &str) -> rusqlite::Result<()> {
+    set_user_active(name, false)
+}
+
+#[cfg(feature = "server")]
+/// Restores an archived user
+pub fn reactivate_user(name: &str) -> rusqlite::Result<()> {
+    set_user_active(name, true)
+}
+
+#[cfg(feature = "server")]
+/// Renames a user and rewrites its name in the transactions, settings and
+/// statements
+///
+/// Returns false if the rename was already applied, or lost a conflict
+/// against a concurrent rename of the same user.
+pub fn rename_user(
+    name: &str,
+    new_name: &str,
+    lamport_time: &i64,
+    source_node: &str,
+) -> rusqlite::Result<bool> {
+    let conn = DB_CONN.lock().unwrap();
+    let renamed = rename_user_in(&conn, name, new_name, *lamport_time, source_node)?;
+    if renamed {
+        // the postings of the user moved to its new name
+        crate::balances::clear();
+    }
+    Ok(renamed)
+}
+
+#[cfg(feature = "server")]
+/// Renames a user in a database
+///
+/// When two sites rename the same user concurrently, the rename with the
+/// greatest (Lamport time, source node) wins on every site, whatever the
+/// order in which the renames are received.
+fn rename_user_in(
+    conn: &rusqlite::Connection,
+    name: &str,
+    new_name: &str,
+    lamport_time: i64,
+    source_node: &str,
+) -> rusqlite::Result<bool> {
+    use rusqlite::{OptionalExtension, params};
+
+    let rename_error = |msg: String| {
+        log::error!("{}", msg);
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(msg),
+        )
+    };
+    let exists = |user: &str| -> rusqlite::Result<bool> {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM User WHERE unique_name = ?1)",
+            params![user],
+            |row| row.get(0),
+        )
+    };
+
+    if new_name.is_empty() || new_name == NULL {
+        return Err(rename_error(format!("Invalid user name '{}'.", new_name)));
+    }
+    if name == new_name {
+        return Err(rename_error(format!(
+            "User '{}' already has this name.",
+            name
+        )));
+    }
+
+    let mut current = name.to_string();
+    if !exists(name)? {
+        let previous: Option<(String, i64, String)> = conn
+            .query_row(
+                "SELECT new_name, lamport_time, source_node FROM UserAlias WHERE old_name = ?1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        match previous {
+            Some((renamed_to, time, node))
+                if (time, node.as_str()) > (lamport_time, source_node) =>
+            {
+                log::warn!(
+                    "Rename of '{}' to '{}' ignored, '{}' was renamed to '{}' by a later rename",
+                    name,
+                    new_name,
+                    name,
+                    renamed_to
+                );
+                return Ok(false);
+            }
+            Some((renamed_to, _, _)) => current = renamed_to,
+            None => return Err(rename_error(format!("User '{}' does not exist.", name))),
+        }
+    }
+    if current == new_name {
+        return Ok(false);
+    }
+    if exists(new_name)? {
+        return Err(rename_error(format!("User '{}' already exists.", new_name)));
+    }
+
+    let sql_tx = conn.unchecked_transaction()?;
+    for statement in [
+        "UPDATE User SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE Transactions SET from_user = ?2 WHERE from_user = ?1",
+        "UPDATE Transactions SET to_user = ?2 WHERE to_user = ?1",
+        "UPDATE Postings SET account = ?2 WHERE account = ?1",
+        "UPDATE UserSettings SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE Statements SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE Policies SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE ForbiddenBeneficiary SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE ForbiddenBeneficiary SET beneficiary = ?2 WHERE beneficiary = ?1",
+        "UPDATE DailyTransfers SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE PolicyViolations SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE TransactionComments SET author = ?2 WHERE author = ?1",
+        "UPDATE Notifications SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE Favorites SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE Favorites SET beneficiary = ?2 WHERE beneficiary = ?1",
+        "UPDATE UserConflicts SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE ApiTokens SET unique_name = ?2 WHERE unique_name = ?1",
+        "UPDATE RefundRequests SET requester = ?2 WHERE requester = ?1",
+        "UPDATE RefundRequests SET approver = ?2 WHERE approver = ?1",
+        // The former names of the user follow it to its new name
+        "UPDATE UserAlias SET new_name = ?2 WHERE new_name = ?1",
+        // A user taking back a former name no longer needs its alias
+        "DELETE FROM UserAlias WHERE old_name = ?2",
+    ] {
+        sql_tx.execute(statement, params![current, new_name])?;
+    }
+    let mut aliases = vec![name];
+    if current != name {
+        aliases.push(current.as_str());
+    }
+    for alias in aliases {
+        sql_tx.execute(
+            "INSERT INTO UserAlias (old_name, new_name, lamport_time, source_node)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(old_name) DO UPDATE SET new_name = excluded.new_name,
+                lamport_time = excluded.lamport_time, source_node = excluded.source_node",
+            params![alias, new_name, lamport_time, source_node],
+        )?;
+    }
+    sql_tx.commit()?;
+    Ok(true)
+}
+
+#[cfg(feature = "server")]
+/// Returns the current name of a user, following its renames
+///
+/// The name of an existing user is returned as is, so that a new user can
+/// take the former name of a renamed one.
+pub fn resolve_user(name: &str) -> rusqlite::Result<String> {
+    resolve_user_in(&DB_CONN.lock().unwrap(), name)
+}
+
+#[cfg(feature = "server")]
+/// Returns the current name of a user in a database, following its renames
+fn resolve_user_in(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<String> {
+    use rusqlite::{OptionalExtension, params};
+
+    if user_exists_in(conn, name)? {
+        return Ok(name.to_string());
+    }
+    let renamed: Option<String> = conn
+        .query_row(
+            "SELECT new_name FROM UserAlias WHERE old_name = ?1",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(renamed.unwrap_or_else(|| name.to_string()))
+}
+
+#[cfg(feature = "server")]
+/// Checks if a user exists and is not archived
+pub fn is_user_active(name: &str) -> rusqlite::Result<bool> {
+    {
+        use rusqlite::params;
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT EXISTS(SELECT 1 FROM User WHERE unique_name = ?1 AND is_active = 1)",
+        )?;
+        let active: bool = stmt.query_row(params![name], |row| row.get(0))?;
+        Ok(active)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Calculates the current balance for a user
+///
+/// The balance is read from the cache of [`crate::balances`], and computed
+/// from the postings only the first time.
+pub fn calculate_solde(name: &str) -> rusqlite::Result<f64> {
+    calculate_solde_in(&DB_CONN.lock().unwrap(), name)
+}
+
+#[cfg(feature = "server")]
+/// Calculates the current balance for a user of a database
+fn calculate_solde_in(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<f64> {
+    let cents = match crate::balances::get(name) {
+        Some(cents) => cents,
+        None => {
+            let cents = account_cents(conn, name)?;
+            crate::balances::insert(name, cents);
+            cents
+        }
+    };
+    Ok(cents as f64 / 100.0)
+}
+
+#[cfg(feature = "server")]
+/// Computes the balance of an account from its postings, credits minus debits
+fn account_balance(conn: &rusqlite::Connection, account: &str) -> rusqlite::Result<f64> {
+    Ok(account_cents(conn, account)? as f64 / 100.0)
+}
+
+#[cfg(feature = "server")]
+/// Computes the balance of an account from its postings, in cents
+pub(crate) fn account_cents(conn: &rusqlite::Connection, account: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT IFNULL(SUM(CASE side WHEN 'credit' THEN amount_in_cent ELSE -amount_in_cent END), 0)
+        FROM Postings WHERE account = ?1",
+        rusqlite::params![account],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(feature = "server")]
+/// Result of the audit of the ledger
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct LedgerAudit {
+    /// Balance of every account computed from its postings, system accounts included
+    pub balances: Vec<(String, f64)>,
+    /// Users whose stored balance differs from their postings
+    pub mismatches: Vec<String>,
+    /// Transactions whose debit and credit are missing or of different amounts
+    pub unbalanced: Vec<(i64, String)>,
+}
+
+#[cfg(feature = "server")]
+impl LedgerAudit {
+    /// Returns true if every transaction balances and every stored balance matches
+    pub fn is_balanced(&self) -> bool {
+        self.mismatches.is_empty() && self.unbalanced.is_empty()
+    }
+}
+
+#[cfg(feature = "server")]
+/// Audits the postings of every account of the site
+pub fn audit_ledger() -> rusqlite::Result<LedgerAudit> {
+    audit_ledger_in(&DB_CONN.lock().unwrap())
+}
+
+#[cfg(feature = "server")]
+/// Audits the postings of every account of a database
+fn audit_ledger_in(conn: &rusqlite::Connection) -> rusqlite::Result<LedgerAudit> {
+    let mut stmt = conn.prepare(
+        "SELECT account,
+            SUM(CASE side WHEN 'credit' THEN amount_in_cent ELSE -amount_in_cent END)
+        FROM Postings GROUP BY account ORDER BY account",
+    )?;
+    let balances = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)? as f64 / 100.0,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut stmt = conn.prepare("SELECT unique_name, solde FROM User ORDER BY unique_name")?;
+    let users = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut mismatches = Vec::new();
+    for (user, stored) in users {
+        if (account_balance(conn, &user)? - stored).abs() >= 0.005 {
+            mismatches.push(user);
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT t.lamport_time, t.source_node FROM Transactions t
+        WHERE (SELECT COUNT(*) FROM Postings p
+                WHERE p.lamport_time = t.lamport_time AND p.source_node = t.source_node) != 2
+            OR (SELECT SUM(CASE p.side WHEN 'credit' THEN p.amount_in_cent ELSE -p.amount_in_cent END)
+                FROM Postings p
+                WHERE p.lamport_time = t.lamport_time AND p.source_node = t.source_node) != 0
+        ORDER BY t.lamport_time, t.source_node",
+    )?;
+    let unbalanced = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(LedgerAudit {
+        balances,
+        mismatches,
+        unbalanced,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Updates the stored balance for a user
+pub fn update_solde(name: &str) -> rusqlite::Result<()> {
+    update_solde_in(&DB_CONN.lock().unwrap(), name)
+}
+
+#[cfg(feature = "server")]
+/// Updates the stored balance for a user of a database
+fn update_solde_in(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<()> {
+    use rusqlite::params;
+
+    if !user_exists_in(conn, name)? {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("User '{}' does not exist.", name)),
+        );
+
+        return Err(err);
+    }
+    let solde = calculate_solde_in(conn, name)?;
+    conn.execute(
+        "UPDATE User SET solde = ?1 WHERE unique_name = ?2",
+        params![solde, name],
+    )?;
+    log::debug!("Updated solde for {} to {}", name, solde);
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Ensures a user exists, creating it if necessary
+pub fn ensure_user(name: &str) -> rusqlite::Result<()> {
+    ensure_user_in(&DB_CONN.lock().unwrap(), name)
+}
+
+#[cfg(feature = "server")]
+/// Ensures a user of a database exists, creating it if necessary
+fn ensure_user_in(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<()> {
+    if name != NULL && !user_exists_in(conn, name)? {
+        create_user_in(conn, name)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Number of transactions written to the database since the site started
+static WRITE_COUNT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Time spent writing the transactions to the database, in microseconds
+static WRITE_MICROS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Returns the number of transactions written since the site started and the time spent writing them
+pub fn write_stats() -> (u64, std::time::Duration) {
+    use std::sync::atomic::Ordering;
+
+    (
+        WRITE_COUNT.load(Ordering::Relaxed),
+        std::time::Duration::from_micros(WRITE_MICROS.load(Ordering::Relaxed)),
+    )
+}
+
+#[cfg(feature = "server")]
+/// Creates a new transaction between users
+///
+/// Once the transaction is recorded, the users it concerns are notified and
+/// it is sent to the configured webhooks.
+pub fn create_transaction(
+    from_user: &str,
+    to_user: &str,
+    amount: f64,
+    lamport_time: &i64,
+    source_node: &str,
+    optional_msg: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    let transaction = record_transaction(
+        from_user,
+        to_user,
+        amount,
+        lamport_time,
+        source_node,
+        optional_msg,
+        vector_clock,
+    )?;
+    crate::notifications::notify_transaction(&transaction);
+    crate::node::emit(crate::node::NodeEvent::Transaction(transaction.clone()));
+    crate::alerts::notify(&transaction);
+    crate::webhooks::notify(transaction);
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Records a new transaction between users, without notifying the webhooks
+fn record_transaction(
+    from_user: &str,
+    to_user: &str,
+    amount: f64,
+    lamport_time: &i64,
+    source_node: &str,
+    optional_msg: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<Transaction> {
+    let conn = DB_CONN.lock().unwrap();
+    record_transaction_in(
+        &conn,
+        from_user,
+        to_user,
+        amount,
+        lamport_time,
+        source_node,
+        optional_msg,
+        vector_clock,
+    )
+}
+
+#[cfg(feature = "server")]
+/// Records a new transaction between users in a database, without notifying
+/// the webhooks
+#[allow(clippy::too_many_arguments)]
+fn record_transaction_in(
+    conn: &rusqlite::Connection,
+    from_user: &str,
+    to_user: &str,
+    amount: f64,
+    lamport_time: &i64,
+    source_node: &str,
+    optional_msg: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<Transaction> {
+    use rusqlite::params;
+
+    // a NaN or infinite amount would corrupt every balance computed from it
+    if !amount.is_finite() {
+        log::error!("Invalid transaction amount: {}", amount);
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("Invalid transaction amount: {}", amount)),
+        ));
+    }
+
+    // Transactions diffused before a rename may still use the former names
+    let from_user = resolve_user_in(conn, user_of_account(from_user))?;
+    let from_user = from_user.as_str();
+    let to_user = resolve_user_in(conn, user_of_account(to_user))?;
+    let to_user = to_user.as_str();
+
+    // money never moves from a ledger to another
+    let ledger = crate::ledger::transaction_ledger_in(conn, from_user, to_user)
+        .inspect_err(|e| log::error!("{}", e))?;
+
+    if from_user != NULL && calculate_solde_in(conn, from_user)? < amount {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!(
+                "Insufficient funds: '{}' has less than {}.",
+                from_user, amount
+            )),
+        );
+
+        log::error!(
+            "Insufficient funds: '{}' has less than {}.",
+            from_user,
+            amount
+        );
+        return Err(err);
+    }
+
+    ensure_user_in(conn, from_user)?;
+    ensure_user_in(conn, to_user)?;
+
+    // Balance before the transaction, to detect a crossing of the alert threshold
+    let alert_threshold = if from_user != NULL {
+        get_alert_threshold_in(conn, from_user)?
+    } else {
+        None
+    };
+    let solde_before = match alert_threshold {
+        Some(_) => Some(calculate_solde_in(conn, from_user)?),
+        None => None,
+    };
+
+    log::debug!(
+        "Creating transaction from {} to {} with amount {}",
+        from_user,
+        to_user,
+        amount
+    );
+
+    let write_start = std::time::Instant::now();
+    {
+        conn.execute("INSERT INTO VectorClock DEFAULT VALUES", [])?;
+        let vector_clock_id = conn.last_insert_rowid();
+
+        let mut stmt = conn.prepare(
+            "INSERT INTO VectorClockEntry (vector_clock_id, site_id, value) VALUES (?1, ?2, ?3)",
+        )?;
+        for (site_id, value) in vector_clock.iter() {
+            stmt.execute(params![vector_clock_id, site_id, value])?;
+        }
+
+        let refund_of = refunded_transaction(optional_msg);
+        conn.execute(
+        "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg, refund_of_node, refund_of_lamport, created_at, ledger)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            from_user,
+            to_user,
+            amount,
+            lamport_time,
+            vector_clock_id,
+            source_node,
+            optional_msg,
+            refund_of.map(|(node, _)| node),
+            refund_of.map(|(_, time)| time),
+            unix_now(),
+            ledger
+        ],
+    )?;
+        insert_postings(
+            conn,
+            from_user,
+            to_user,
+            amount,
+            *lamport_time,
+            source_node,
+            optional_msg,
+        )?;
+        let amount_in_cent = (amount * 100.0).round() as i64;
+        let (debit, credit) =
+            crate::system_accounts::current().posting_accounts(from_user, to_user, optional_msg);
+        crate::balances::add(debit, -amount_in_cent);
+        crate::balances::add(credit, amount_in_cent);
+    }
+    WRITE_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    WRITE_MICROS.fetch_add(
+        write_start.elapsed().as_micros() as u64,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+
+    if from_user != NULL {
+        update_solde_in(conn, from_user)?;
+    }
+    if to_user != NULL {
+        update_solde_in(conn, to_user)?;
+    }
+
+    if let (Some(threshold), Some(before)) = (alert_threshold, solde_before) {
+        let after = calculate_solde_in(conn, from_user)?;
+        if let Some(alert) = crate::live::balance_alert(from_user, before, after, threshold) {
+            log::warn!(
+                "Low balance alert: '{}' has {} left, below the threshold of {}.",
+                from_user,
+                after,
+                threshold
+            );
+            crate::live::publish(alert);
+        }
+    }
+
+    Ok(Transaction {
+        from_user: from_user.to_string(),
+        to_user: to_user.to_string(),
+        amount,
+        lamport_time: *lamport_time,
+        source_node: source_node.to_string(),
+        optional_msg: (!optional_msg.is_empty()).then(|| optional_msg.to_string()),
+        vector_clock: vector_clock.clone(),
+    })
+}
+
+#[cfg(feature = "server")]
+/// Creates the payments of a bill split between several users
+///
+/// The payment of the i-th user is recorded at Lamport time
+/// `first_lamport + i`. Either all payments are recorded or none of them.
+pub fn create_split_payment(
+    shares: &[(String, f64)],
+    first_lamport: i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    let payment_error = |msg: String| {
+        log::error!("{}", msg);
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(msg),
+        )
+    };
+
+    if shares.is_empty() {
+        return Err(payment_error("Split payment without payer.".to_string()));
+    }
+    if let Some((user, amount)) = shares.iter().find(|(_, amount)| *amount <= 0.0) {
+        return Err(payment_error(format!(
+            "Invalid amount {} for payer '{}'.",
+            amount, user
+        )));
+    }
+    for (i, (user, _)) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|(other, _)| other == user) {
+            return Err(payment_error(format!(
+                "'{}' pays twice the same bill.",
+                user
+            )));
+        }
+    }
+
+    // the connection stays locked for the whole payment, so that no other
+    // write lands in its transaction and is rolled back with it
+    let transactions = {
+        let conn = DB_CONN.lock().unwrap();
+        let sql_tx = conn.unchecked_transaction()?;
+        for (user, amount) in shares {
+            if calculate_solde_in(&sql_tx, user)? < *amount {
+                return Err(payment_error(format!(
+                    "Insufficient funds: '{}' has less than {}.",
+                    user, amount
+                )));
+            }
+        }
+
+        let result = shares
+            .iter()
+            .enumerate()
+            .map(|(i, (user, amount))| {
+                record_transaction_in(
+                    &sql_tx,
+                    user,
+                    NULL,
+                    *amount,
+                    &(first_lamport + i as i64),
+                    source_node,
+                    "Split payment",
+                    vector_clock,
+                )
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .and_then(|transactions| sql_tx.commit().map(|()| transactions));
+        if result.is_err() {
+            // the cached balances include the payments rolled back
+            crate::balances::clear();
+        }
+        result?
+    };
+
+    // The users, the subscribers and the webhooks are only notified once every payment is recorded
+    for transaction in transactions {
+        crate::notifications::notify_transaction(&transaction);
+        crate::node::emit(crate::node::NodeEvent::Transaction(transaction.clone()));
+        crate::alerts::notify(&transaction);
+        crate::webhooks::notify(transaction);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Creates the transfers of a batch from one payer to several beneficiaries
+///
+/// The transfer to the i-th beneficiary is recorded at Lamport time
+/// `first_lamport + i`. Either all transfers are recorded or none of them.
+pub fn create_batch_transfer(
+    from_user: &str,
+    transfers: &[(String, f64)],
+    first_lamport: i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    let batch_error = |msg: String| {
+        log::error!("{}", msg);
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(msg),
+        )
+    };
+
+    if transfers.is_empty() {
+        return Err(batch_error(
+            "Batch transfer without beneficiary.".to_string(),
+        ));
+    }
+    if let Some((to_user, amount)) = transfers.iter().find(|(_, amount)| *amount <= 0.0) {
+        return Err(batch_error(format!(
+            "Invalid amount {} for beneficiary '{}'.",
+            amount, to_user
+        )));
+    }
+    if transfers.iter().any(|(to_user, _)| to_user == from_user) {
+        return Err(batch_error(format!(
+            "'{}' cannot be a beneficiary of its own batch.",
+            from_user
+        )));
+    }
+    let total: f64 = transfers.iter().map(|(_, amount)| amount).sum();
+
+    // the connection stays locked for the whole batch, so that no other write
+    // lands in its transaction and is rolled back with it
+    let transactions = {
+        let conn = DB_CONN.lock().unwrap();
+        let sql_tx = conn.unchecked_transaction()?;
+        if calculate_solde_in(&sql_tx, from_user)? < total {
+            return Err(batch_error(format!(
+                "Insufficient funds: '{}' has less than {}.",
+                from_user, total
+            )));
+        }
+
+        let result = transfers
+            .iter()
+            .enumerate()
+            .map(|(i, (to_user, amount))| {
+                record_transaction_in(
+                    &sql_tx,
+                    from_user,
+                    to_user,
+                    *amount,
+                    &(first_lamport + i as i64),
+                    source_node,
+                    "Split bill",
+                    vector_clock,
+                )
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .and_then(|transactions| sql_tx.commit().map(|()| transactions));
+        if result.is_err() {
+            // the cached balances include the transfers rolled back
+            crate::balances::clear();
+        }
+        result?
+    };
+
+    // The users, the subscribers and the webhooks are only notified once the whole batch is recorded
+    for transaction in transactions {
+        crate::notifications::notify_transaction(&transaction);
+        crate::node::emit(crate::node::NodeEvent::Transaction(transaction.clone()));
+        crate::alerts::notify(&transaction);
+        crate::webhooks::notify(transaction);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Creates several transactions, given as sender, recipient, amount and
+/// message
+///
+/// The i-th transaction is recorded at Lamport time `first_lamport + i`.
+/// Either all transactions are recorded or none of them.
+pub fn create_transactions(
+    transactions: &[(String, String, f64, String)],
+    first_lamport: i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    // the connection stays locked for the whole batch, so that no other
+    // write lands in its transaction and is rolled back with it
+    let result = {
+        let conn = DB_CONN.lock().unwrap();
+        let sql_tx = conn.unchecked_transaction()?;
+        let result = transactions
+            .iter()
+            .enumerate()
+            .map(|(i, (from_user, to_user, amount, message))| {
+                record_transaction_in(
+                    &sql_tx,
+                    from_user,
+                    to_user,
+                    *amount,
+                    &(first_lamport + i as i64),
+                    source_node,
+                    message,
+                    vector_clock,
+                )
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .and_then(|transactions| sql_tx.commit().map(|()| transactions));
+        if result.is_err() {
+            // the cached balances include the transactions rolled back
+            crate::balances::clear();
+        }
+        result
+    };
+
+    for transaction in result? {
+        crate::notifications::notify_transaction(&transaction);
+        crate::node::emit(crate::node::NodeEvent::Transaction(transaction.clone()));
+        crate::alerts::notify(&transaction);
+        crate::webhooks::notify(transaction);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Sets the low balance alert threshold of a user, None disables the alert
+pub fn set_alert_threshold(name: &str, threshold: Option<f64>) -> rusqlite::Result<()> {
+    use rusqlite::params;
+    if !user_exists(name)? {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("User '{}' does not exist.", name)),
+        );
+
+        log::error!("User '{}' does not exist.", name);
+        return Err(err);
+    }
+    {
+        let conn = DB_CONN.lock().unwrap();
+        conn.execute(
+            "INSERT INTO UserSettings (unique_name, alert_threshold) VALUES (?1, ?2)
+            ON CONFLICT(unique_name) DO UPDATE SET alert_threshold = excluded.alert_threshold",
+            params![name, threshold],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the low balance alert threshold of a user, if any
+pub fn get_alert_threshold(name: &str) -> rusqlite::Result<Option<f64>> {
+    get_alert_threshold_in(&DB_CONN.lock().unwrap(), name)
+}
+
+#[cfg(feature = "server")]
+/// Returns the low balance alert threshold of a user of a database, if any
+fn get_alert_threshold_in(
+    conn: &rusqlite::Connection,
+    name: &str,
+) -> rusqlite::Result<Option<f64>> {
+    use rusqlite::{OptionalExtension, params};
+    let threshold: Option<Option<f64>> = conn
+        .query_row(
+            "SELECT alert_threshold FROM UserSettings WHERE unique_name = ?1",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(threshold.flatten())
+}
+
+#[cfg(feature = "server")]
+pub fn deposit(
+    user: &str,
+    amount: f64,
+    lamport_time: &i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    let user = resolve_user(user)?;
+    let user = user.as_str();
+    if !user_exists(user)? {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("Unknown User: {}", user)),
+        );
+
+        log::error!("Unknown User: {}", user);
+        return Err(err);
+    }
+
+    if amount < 0.0 {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("Negative deposit amount: {}", amount)),
+        );
+
+        log::error!("Negative deposit amount: {}", amount);
+        return Err(err);
+    }
+
+    log::debug!("Depositing {} to {}", amount, user);
+
+    create_transaction(
+        NULL,
+        user,
+        amount,
+        lamport_time,
+        source_node,
+        "Deposit",
+        vector_clock,
+    )
+}
+
+#[cfg(feature = "server")]
+pub fn withdraw(
+    user: &str,
+    amount: f64,
+    lamport_time: &i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    if amount < 0.0 {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("Negative withdrawal amount: {}", amount)),
+        );
+        log::error!("Negative withdrawal amount: {}", amount);
+        return Err(err);
+    }
+    let user = resolve_user(user)?;
+    let user = user.as_str();
+    if !user_exists(user)? {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("Unknown user: {}", user)),
+        );
+        log::error!("Unknown user: {}", user);
+        return Err(err);
+    }
+    if calculate_solde(user)? < amount {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!("User {} not enough money", user)),
+        );
+        log::error!("User {} not enough money", user);
+        return Err(err);
+    }
+
+    log::debug!("Withdrawing {} from {}", amount, user);
+
+    create_transaction(
+        user,
+        NULL,
+        amount,
+        lamport_time,
+        source_node,
+        WITHDRAW_MSG,
+        vector_clock,
+    )
+}
+
+#[cfg(feature = "server")]
+/// Returns true if a refund of the transaction was recorded
+fn has_been_refunded_in(
+    conn: &rusqlite::Connection,
+    transac_time: i64,
+    node: &str,
+) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM Transactions
+        WHERE refund_of_node = ?1 AND refund_of_lamport = ?2)",
+        rusqlite::params![node, transac_time],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(feature = "server")]
+pub fn has_been_refunded(transac_time: i64, node: &str) -> rusqlite::Result<bool> {
+    let conn = DB_CONN.lock().unwrap();
+    has_been_refunded_in(&conn, transac_time, node)
+}
+
+#[cfg(feature = "server")]
+/// Sets the status of the transactions of a node in a range of Lamport times
+fn set_transactions_status_in(
+    conn: &rusqlite::Connection,
+    source_node: &str,
+    lamports: &std::ops::RangeInclusive<i64>,
+    status: TransactionStatus,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE Transactions SET status = ?1
+        WHERE source_node = ?2 AND lamport_time BETWEEN ?3 AND ?4",
+        rusqlite::params![
+            status.as_str(),
+            source_node,
+            lamports.start(),
+            lamports.end()
+        ],
+    )
+}
+
+#[cfg(feature = "server")]
+/// Sets the status of the transactions of a node in a range of Lamport times
+pub fn set_transactions_status(
+    source_node: &str,
+    lamports: &std::ops::RangeInclusive<i64>,
+    status: TransactionStatus,
+) -> rusqlite::Result<usize> {
+    let conn = DB_CONN.lock().unwrap();
+    set_transactions_status_in(&conn, source_node, lamports, status)
+}
+
+#[cfg(feature = "server")]
+/// Returns the transactions of a user that are not confirmed, as
+/// (lamport time, node, status)
+fn unsettled_transactions_in(
+    conn: &rusqlite::Connection,
+    name: &str,
+) -> rusqlite::Result<Vec<(i64, String, TransactionStatus)>> {
+    let mut stmt = conn.prepare(
+        "SELECT lamport_time, source_node, status FROM Transactions
+        WHERE status != 'Confirmed' AND (from_user = ?1 OR to_user = ?1)
+        ORDER BY lamport_time, source_node",
+    )?;
+    stmt.query_map([name], |row| {
+        let status: String = row.get(2)?;
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            TransactionStatus::parse(&status).unwrap_or_default(),
+        ))
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the transactions of a user that are not confirmed, as
+/// (lamport time, node, status)
+pub fn get_unsettled_transactions(
+    name: &str,
+) -> rusqlite::Result<Vec<(i64, String, TransactionStatus)>> {
+    let conn = DB_CONN.lock().unwrap();
+    unsettled_transactions_in(&conn, name)
+}
+
+#[cfg(feature = "server")]
+/// Default time after which a transaction can no longer be refunded, in hours
+pub const DEFAULT_REFUND_WINDOW_HOURS: u64 = 48;
+
+#[cfg(feature = "server")]
+/// Time after which a transaction can no longer be refunded, in seconds (0
+/// for no limit), set from the command line at startup
+static REFUND_WINDOW_SECS: std::sync::atomic::AtomicU64 =
+    std::sync::atomic::AtomicU64::new(DEFAULT_REFUND_WINDOW_HOURS * 3600);
+
+#[cfg(feature = "server")]
+/// Sets the time after which a transaction can no longer be refunded, 0 for no limit
+pub fn set_refund_window(hours: u64) {
+    REFUND_WINDOW_SECS.store(
+        hours.saturating_mul(3600),
+        std::sync::atomic::Ordering::Relaxed,
+    );
+}
+
+#[cfg(feature = "server")]
+/// Returns the time after which a transaction can no longer be refunded, in seconds
+fn refund_window_secs() -> u64 {
+    REFUND_WINDOW_SECS.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+#[cfg(feature = "server")]
+/// Returns the current wall-clock time, in seconds since the UNIX epoch
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "server")]
+/// Returns true if a transaction was recorded more than `window` seconds
+/// before `now`
+///
+/// A transaction without a recording date, or an unknown one, is not
+/// immutable, nor is any transaction when the window is 0.
+fn immutable_at(
+    conn: &rusqlite::Connection,
+    transac_time: i64,
+    node: &str,
+    now: i64,
+    window: u64,
+) -> rusqlite::Result<bool> {
+    use rusqlite::OptionalExtension;
+
+    if window == 0 {
+        return Ok(false);
+    }
+    let created_at: Option<i64> = conn
+        .query_row(
+            "SELECT created_at FROM Transactions WHERE lamport_time = ?1 AND source_node = ?2",
+            rusqlite::params![transac_time, node],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(created_at.is_some_and(|created_at| created_at.saturating_add(window as i64) <= now))
+}
+
+#[cfg(feature = "server")]
+/// Returns true if a transaction is older than the refund window, and can no
+/// longer be refunded
+pub(crate) fn is_immutable_in(
+    conn: &rusqlite::Connection,
+    transac_time: i64,
+    node: &str,
+) -> rusqlite::Result<bool> {
+    immutable_at(conn, transac_time, node, unix_now(), refund_window_secs())
+}
+
+#[cfg(feature = "server")]
+/// Returns the transactions of a user older than the refund window, as
+/// (lamport time, node)
+pub fn get_immutable_transactions(name: &str) -> rusqlite::Result<Vec<(i64, String)>> {
+    let window = refund_window_secs();
+    if window == 0 {
+        return Ok(Vec::new());
+    }
+    let conn = DB_CONN.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT lamport_time, source_node FROM Transactions
+        WHERE (from_user = ?1 OR to_user = ?1) AND created_at + ?2 <= ?3
+        ORDER BY lamport_time, source_node",
+    )?;
+    stmt.query_map(rusqlite::params![name, window as i64, unix_now()], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns why a transaction cannot be refunded at `now`, None if it can
+///
+/// These are the checks of [`refund_transaction`], so that the interface
+/// does not offer refunds that would be refused.
+fn refund_blocker_in(
+    conn: &rusqlite::Connection,
+    tx: &Transaction,
+    now: i64,
+    window: u64,
+) -> rusqlite::Result<Option<String>> {
+    if tx
+        .optional_msg
+        .as_deref()
+        .and_then(refunded_transaction)
+        .is_some()
+    {
+        return Ok(Some("A refund cannot be refunded".to_string()));
+    }
+    if has_been_refunded_in(conn, tx.lamport_time, &tx.source_node)? {
+        return Ok(Some("Already refunded".to_string()));
+    }
+    if immutable_at(conn, tx.lamport_time, &tx.source_node, now, window)? {
+        return Ok(Some(format!(
+            "Older than the refund window of {} hours",
+            window / 3600
+        )));
+    }
+    if account_balance(conn, &tx.to_user)? < tx.amount {
+        return Ok(Some(format!(
+            "{} has not enough money to give it back",
+            tx.to_user
+        )));
+    }
+    Ok(None)
+}
+
+#[cfg(feature = "server")]
+/// Returns the transactions of a user with the reason each one cannot be
+/// refunded, if any
+pub fn get_refundable_transactions(name: &str) -> rusqlite::Result<Vec<RefundableTransaction>> {
+    let transactions = get_transactions_for_user(name)?;
+    let conn = DB_CONN.lock().unwrap();
+    let (now, window) = (unix_now(), refund_window_secs());
+    transactions
+        .into_iter()
+        .map(|transaction| {
+            let blocked_by = refund_blocker_in(&conn, &transaction, now, window)?;
+            Ok(RefundableTransaction {
+                transaction,
+                blocked_by,
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Refunds a transaction recorded on this site, within the refund window
+pub fn refund_transaction(
+    transac_time: i64,
+    node: &str,
+    lamport_time: &i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    let immutable = {
+        let conn = DB_CONN.lock().unwrap();
+        is_immutable_in(&conn, transac_time, node)?
+    };
+    if immutable {
+        let message = format!(
+            "Transaction {}-{} is older than the refund window of {} hours",
+            node,
+            transac_time,
+            refund_window_secs() / 3600
+        );
+        log::error!("{}", message);
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(message),
+        ));
+    }
+    apply_refund(transac_time, node, lamport_time, source_node, vector_clock)
+}
+
+#[cfg(feature = "server")]
+/// Records the refund of a transaction, whatever its age
+///
+/// The refunds diffused by the other sites are applied with it: the site that
+/// initiated the refund already checked the refund window.
+pub fn apply_refund(
+    transac_time: i64,
+    node: &str,
+    lamport_time: &i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    if let Some(tx) = get_transaction(transac_time, node)? {
+        if calculate_solde(&tx.to_user)? < tx.amount {
+            let err = rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+                Some(format!(
+                    "User {} has not enough money to give back",
+                    &tx.to_user
+                )),
+            );
+            log::error!("User {} has not enough money to give back", &tx.to_user);
+            return Err(err);
+        }
+
+        if tx
+            .optional_msg
+            .as_deref()
+            .and_then(refunded_transaction)
+            .is_some()
+        {
+            let err = rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+                Some(format!(
+                    "Transaction {}-{} is a refund transaction",
+                    node, transac_time
+                )),
+            );
+            log::error!(
+                "Transaction {}-{} is a refund transaction",
+                node,
+                transac_time
+            );
+            return Err(err);
+        }
+
+        if has_been_refunded(transac_time, node)? {
+            let err = rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+                Some(format!(
+                    "Transaction {}-{} already refunded",
+                    node, transac_time
+                )),
+            );
+            log::error!("Transaction {}-{} already refunded", node, transac_time);
+            return Err(err);
+        }
+
+        create_transaction(
+            &tx.to_user,
+            &tx.from_user,
+            tx.amount,
+            lamport_time,
+            source_node,
+            &format!("{}{}-{}", REFUND_PREFIX, node, transac_time),
+            vector_clock,
+        )?;
+        crate::refund_requests::settle(transac_time, node)?;
+    } else {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+            Some(format!(
+                "No transaction found at time {} from node {}",
+                transac_time, node
+            )),
+        );
+
+        log::error!(
+            "No transaction found at time {} from node {}",
+            transac_time,
+            node
+        );
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+pub fn get_transaction(transac_time: i64, node: &str) -> rusqlite::Result<Option<Transaction>> {
+    use rusqlite::params;
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT from_user, to_user, amount, lamport_time, source_node, optional_msg, vector_clock_id
+        FROM Transactions WHERE lamport_time = ?1 AND source_node = ?2",
+        )?;
+
+        match stmt.query_row(params![transac_time, node], |row| {
+            let from_user: String = row.get(0)?;
+            let to_user: String = row.get(1)?;
+            let amount: f64 = row.get(2)?;
+            let lamport_time: i64 = row.get(3)?;
+            let source_node: String = row.get(4)?;
+            let optional_msg: Option<String> = row.get(5)?;
+            let vector_clock_id: i64 = row.get(6)?;
+
+            let mut clock_map = std::collections::HashMap::new();
+            let mut vc_stmt = conn.prepare(
+                "SELECT site_id, value FROM VectorClockEntry WHERE vector_clock_id = ?1",
+            )?;
+            let mut rows = vc_stmt.query(params![vector_clock_id])?;
+            while let Some(vc_row) = rows.next()? {
+                let site_id: String = vc_row.get(0)?;
+                let value: i64 = vc_row.get(1)?;
+                clock_map.insert(site_id, value);
+            }
+
+            Ok(Transaction {
+                from_user,
+                to_user,
+                amount,
+                lamport_time,
+                source_node,
+                optional_msg,
+                vector_clock: clock_map,
+            })
+        }) {
+            Ok(tx) => Ok(Some(tx)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+pub fn print_users() -> rusqlite::Result<()> {
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT unique_name, solde, is_active FROM User")?;
+        let users = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, bool>(2)?,
+            ))
+        })?;
+
+        let json = crate::control::output_format() == crate::control::OutputFormat::Json;
+        if !json {
+            cli_println!("-- Users --");
+        }
+        for user in users {
+            let (name, solde, active) = user?;
+            if json {
+                crate::control::print_json(&serde_json::json!({
+                    "name": name,
+                    "balance": solde,
+                    "active": active,
+                }));
+            } else if active {
+                cli_println!("{}: {:.2}", name, solde);
+            } else {
+                cli_println!("{}: {:.2} (archived)", name, solde);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the active users
+pub fn get_users() -> rusqlite::Result<Vec<String>> {
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT unique_name FROM User WHERE is_active = 1")?;
+        let users = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut users_vec = Vec::new();
+        for user in users {
+            users_vec.push(user?);
+        }
+        Ok(users_vec)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the archived users
+pub fn get_archived_users() -> rusqlite::Result<Vec<String>> {
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT unique_name FROM User WHERE is_active = 0")?;
+        let users = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut users_vec = Vec::new();
+        for user in users {
+            users_vec.push(user?);
+        }
+        Ok(users_vec)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Creates a product or updates its price
+///
+/// The current image of the product is kept when no image is given.
+pub fn upsert_product(name: &str, price: f64, image: Option<&str>) -> rusqlite::Result<()> {
+    use rusqlite::params;
+    {
+        let conn = DB_CONN.lock().unwrap();
+        conn.execute(
+            "INSERT INTO Product (name, price, image) VALUES (?1, ?2, ?3)
+            ON CONFLICT(name) DO UPDATE SET
+                price = excluded.price,
+                image = COALESCE(excluded.image, Product.image)",
+            params![name, price, image],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+/// Removes a product from the catalog
+pub fn delete_product(name: &str) -> rusqlite::Result<()> {
+    use rusqlite::params;
+    {
+        let conn = DB_CONN.lock().unwrap();
+        conn.execute("DELETE FROM Product WHERE name = ?1", params![name])?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the products of the catalog, sorted by name
+pub fn get_products() -> rusqlite::Result<Vec<Product>> {
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name, price, image FROM Product ORDER BY name")?;
+        let products = stmt.query_map([], |row| {
+            Ok(Product {
+                name: row.get(0)?,
+                price: row.get(1)?,
+                image: row.get(2)?,
+            })
+        })?;
+        let mut products_vec = Vec::new();
+        for product in products {
+            products_vec.push(product?);
+        }
+        Ok(products_vec)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Attaches a comment or a reaction to the transaction (`lamport_time`, `source_node`)
+///
+/// A comment already received is ignored, so that a diffused comment is
+/// recorded once.
+pub fn add_comment(
+    lamport_time: i64,
+    source_node: &str,
+    comment: &TransactionComment,
+) -> rusqlite::Result<()> {
+    let conn = DB_CONN.lock().unwrap();
+    add_comment_in(&conn, lamport_time, source_node, comment)
+}
+
+#[cfg(feature = "server")]
+fn add_comment_in(
+    conn: &rusqlite::Connection,
+    lamport_time: i64,
+    source_node: &str,
+    comment: &TransactionComment,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO TransactionComments (lamport_time, source_node, author, content,
+            is_reaction, comment_lamport_time, comment_source_node)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            lamport_time,
+            source_node,
+            comment.author,
+            comment.text,
+            comment.reaction,
+            comment.lamport_time,
+            comment.source_node
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the comments and reactions of a transaction, oldest first
+pub fn get_comments(
+    lamport_time: i64,
+    source_node: &str,
+) -> rusqlite::Result<Vec<TransactionComment>> {
+    let conn = DB_CONN.lock().unwrap();
+    get_comments_in(&conn, lamport_time, source_node)
+}
+
+#[cfg(feature = "server")]
+fn get_comments_in(
+    conn: &rusqlite::Connection,
+    lamport_time: i64,
+    source_node: &str,
+) -> rusqlite::Result<Vec<TransactionComment>> {
+    let mut stmt = conn.prepare(
+        "SELECT author, content, is_reaction, comment_lamport_time, comment_source_node
+        FROM TransactionComments WHERE lamport_time = ?1 AND source_node = ?2
+        ORDER BY comment_lamport_time, comment_source_node",
+    )?;
+    stmt.query_map(rusqlite::params![lamport_time, source_node], |row| {
+        Ok(TransactionComment {
+            author: row.get(0)?,
+            text: row.get(1)?,
+            reaction: row.get(2)?,
+            lamport_time: row.get(3)?,
+            source_node: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+pub fn print_transactions() -> rusqlite::Result<()> {
+    let aliases = crate::aliases::get_aliases()?;
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT from_user, to_user, amount, lamport_time, source_node, optional_msg, vector_clock_id FROM Transactions",
+        )?;
+        let txs = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        })?;
+
+        let json = crate::control::output_format() == crate::control::OutputFormat::Json;
+        if !json {
+            cli_println!("📜 -- Transactions --");
+            cli_println!(
+                "┌─────────────────┬─────────────────┬────────────┬────────────┬─────────────────┬──────────────────────┬──────────────────────┐"
+            );
+            cli_println!(
+                "│ {:<15} │ {:<15} │ {:<10} │ {:<10} │ {:<15} │ {:<20} │ {:<20} │",
+                "From",
+                "To",
+                "Amount",
+                "Time",
+                "Node",
+                "Message",
+                "Vector Clock"
+            );
+            cli_println!(
+                "├─────────────────┼─────────────────┼────────────┼────────────┼─────────────────┼──────────────────────┼──────────────────────┤"
+            );
+        }
+
+        for tx in txs {
+            let (from, to, amount, time, node, msg, vector_clock_id) = tx?;
+            let mut clock_map = std::collections::HashMap::new();
+            let mut vc_stmt = conn.prepare(
+                "SELECT site_id, value FROM VectorClockEntry WHERE vector_clock_id = ?1",
+            )?;
+            let mut rows = vc_stmt.query(rusqlite::params![vector_clock_id])?;
+            while let Some(vc_row) = rows.next()? {
+                let site_id: String = vc_row.get(0)?;
+                let value: i64 = vc_row.get(1)?;
+                clock_map.insert(site_id, value);
+            }
+            if json {
+                crate::control::print_json(&Transaction {
+                    from_user: from,
+                    to_user: to,
+                    amount,
+                    lamport_time: time,
+                    source_node: node,
+                    optional_msg: msg,
+                    vector_clock: clock_map,
+                });
+                continue;
+            }
+
+            cli_println!(
+                "│ {:<15} │ {:<15} │ {:<10.2} │ {:<10} │ {:<15} │ {:<20} │ {:<20?} │",
+                from,
+                to,
+                amount,
+                time,
+                crate::aliases::display_site(&node, &aliases),
+                msg.unwrap_or_default(),
+                clock_map
+            );
+        }
+
+        if !json {
+            cli_println!(
+                "└─────────────────┴─────────────────┴────────────┴────────────┴─────────────────┴──────────────────────┴──────────────────────┘"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+pub fn print_transaction_for_user(name: &str) -> rusqlite::Result<()> {
+    use rusqlite::params;
+    let aliases = crate::aliases::get_aliases()?;
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT from_user, to_user, amount, lamport_time, source_node, optional_msg, vector_clock_id
+        FROM Transactions WHERE from_user = ?1 OR to_user = ?1",
+        )?;
+
+        let txs = stmt.query_map(params![name], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        })?;
+
+        let json = crate::control::output_format() == crate::control::OutputFormat::Json;
+        if !json {
+            cli_println!("📜 -- Transactions for user {} --", name);
+            cli_println!(
+                "┌─────────────────┬─────────────────┬────────────┬────────────┬─────────────────┬──────────────────────┬──────────────────────┐"
+            );
+            cli_println!(
+                "│ {:<15} │ {:<15} │ {:<10} │ {:<10} │ {:<15} │ {:<20} │ {:<20} │",
+                "From",
+                "To",
+                "Amount",
+                "Time",
+                "Node",
+                "Message",
+                "Vector Clock"
+            );
+            cli_println!(
+                "├─────────────────┼─────────────────┼────────────┼────────────┼─────────────────┼──────────────────────┼──────────────────────┤"
+            );
+        }
+
+        for tx in txs {
+            let (from, to, amount, time, node, msg, vector_clock_id) = tx?;
+            let mut clock_map = std::collections::HashMap::new();
+            let mut vc_stmt = conn.prepare(
+                "SELECT site_id, value FROM VectorClockEntry WHERE vector_clock_id = ?1",
+            )?;
+            let mut rows = vc_stmt.query(rusqlite::params![vector_clock_id])?;
+            while let Some(vc_row) = rows.next()? {
+                let site_id: String = vc_row.get(0)?;
+                let value: i64 = vc_row.get(1)?;
+                clock_map.insert(site_id, value);
+            }
+            if json {
+                crate::control::print_json(&Transaction {
+                    from_user: from,
+                    to_user: to,
+                    amount,
+                    lamport_time: time,
+                    source_node: node,
+                    optional_msg: msg,
+                    vector_clock: clock_map,
+                });
+                continue;
+            }
+            cli_println!(
+                "│ {:<15} │ {:<15} │ {:<10.2} │ {:<10} │ {:<15} │ {:<20} │ {:<20?} │",
+                from,
+                to,
+                amount,
+                time,
+                crate::aliases::display_site(&node, &aliases),
+                msg.unwrap_or_default(),
+                clock_map
+            );
+        }
+
+        if !json {
+            cli_println!(
+                "└─────────────────┴─────────────────┴────────────┴────────────┴─────────────────┴──────────────────────┴──────────────────────┘"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "server")]
+pub fn get_transactions_for_user(name: &str) -> rusqlite::Result<Vec<Transaction>> {
+    use rusqlite::params;
+    {
+        let conn = DB_CONN.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT from_user, to_user, amount, lamport_time, source_node, optional_msg, vector_clock_id
+        FROM Transactions WHERE from_user = ?1 OR to_user = ?1",
+        )?;
+
+        let txs = stmt.query_map(params![name], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        })?;
+
+        let mut txs_vec = Vec::new();
+        for tx in txs {
+            let (from, to, amount, time, node, msg, vector_clock_id) = tx?;
+            let mut clock_map = std::collections::HashMap::new();
+            let mut vc_stmt = conn.prepare(
+                "SELECT site_id, value FROM VectorClockEntry WHERE vector_clock_id = ?1",
+            )?;
+            let mut rows = vc_stmt.query(rusqlite::params![vector_clock_id])?;
+            while let Some(vc_row) = rows.next()? {
+                let site_id: String = vc_row.get(0)?;
+                let value: i64 = vc_row.get(1)?;
+                clock_map.insert(site_id, value);
+            }
+
+            txs_vec.push(Transaction {
+                from_user: from,
+                to_user: to,
+                amount,
+                lamport_time: time,
+                source_node: node,
+                optional_msg: msg,
+                vector_clock: clock_map,
+            });
+        }
+        Ok(txs_vec)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Sorts transactions in a history order
+///
+/// The causal order places each transaction after the ones whose vector
+/// clock precedes it, concurrent transactions being ordered by Lamport time.
+pub fn sort_transactions(transactions: Vec<Transaction>, order: HistoryOrder) -> Vec<Transaction> {
+    let mut transactions = transactions;
+    match order {
+        HistoryOrder::Recorded => {}
+        HistoryOrder::Lamport => transactions.sort_by(|a, b| {
+            (a.lamport_time, &a.source_node).cmp(&(b.lamport_time, &b.source_node))
+        }),
+        HistoryOrder::Site => transactions.sort_by(|a, b| {
+            (&a.source_node, a.lamport_time).cmp(&(&b.source_node, b.lamport_time))
+        }),
+        HistoryOrder::Causal => {
+            let (order, _) = crate::replay::causal_order(&transactions);
+            let mut slots: Vec<Option<Transaction>> = transactions.into_iter().map(Some).collect();
+            transactions = order.into_iter().filter_map(|i| slots[i].take()).collect();
+        }
+    }
+    transactions
+}
+
+#[cfg(feature = "server")]
+/// Returns the transactions of a user in a history order
+pub fn get_transactions_sorted(
+    name: &str,
+    order: HistoryOrder,
+) -> rusqlite::Result<Vec<Transaction>> {
+    Ok(sort_transactions(get_transactions_for_user(name)?, order))
+}
+
+#[cfg(feature = "server")]
+/// Reads the entries of a stored vector clock
+fn read_vector_clock(
+    conn: &rusqlite::Connection,
+    vector_clock_id: i64,
+) -> rusqlite::Result<std::collections::HashMap<String, i64>> {
+    let mut clock_map = std::collections::HashMap::new();
+    let mut vc_stmt =
+        conn.prepare("SELECT site_id, value FROM VectorClockEntry WHERE vector_clock_id = ?1")?;
+    let mut rows = vc_stmt.query(rusqlite::params![vector_clock_id])?;
+    while let Some(vc_row) = rows.next()? {
+        clock_map.insert(vc_row.get(0)?, vc_row.get(1)?);
+    }
+    Ok(clock_map)
+}
+
+#[cfg(feature = "server")]
+/// Searches the transactions of all users
+///
+/// Returns the requested page of the transactions matching every filter of
+/// the query, most recent first, with the total number of matches.
+pub fn search_transactions(query: &TransactionQuery) -> rusqlite::Result<TransactionPage> {
+    let page_size = if query.page_size == 0 {
+        DEFAULT_PAGE_SIZE
+    } else {
+        query.page_size
+    };
+    let (filter, values) = query.to_sql_filter();
+
+    let conn = DB_CONN.lock().unwrap();
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM Transactions {}", filter),
+        rusqlite::params_from_iter(values.iter()),
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT from_user, to_user, amount, lamport_time, source_node, optional_msg, vector_clock_id
+        FROM Transactions {}
+        ORDER BY lamport_time DESC, source_node
+        LIMIT {} OFFSET {}",
+        filter,
+        page_size,
+        query.page * page_size
+    ))?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values.iter()), |row| {
+        Ok((
+            Transaction {
+                from_user: row.get(0)?,
+                to_user: row.get(1)?,
+                amount: row.get(2)?,
+                lamport_time: row.get(3)?,
+                source_node: row.get(4)?,
+                optional_msg: row.get(5)?,
+                vector_clock: std::collections::HashMap::new(),
+            },
+            row.get::<_, i64>(6)?,
+        ))
+    })?;
+
+    let mut transactions = Vec::new();
+    for row in rows {
+        let (mut tx, vector_clock_id) = row?;
+        tx.vector_clock = read_vector_clock(&conn, vector_clock_id)?;
+        transactions.push(tx);
+    }
+
+    Ok(TransactionPage {
+        transactions,
+        total: total as usize,
+        page: query.page,
+        page_size,
+    })
+}
+
+#[cfg(feature = "server")]
+pub fn get_local_transaction_log() -> rusqlite::Result<Vec<Transaction>> {
+    let conn = DB_CONN.lock().unwrap();
+    let mut stmt = conn.prepare(
+    "SELECT from_user, to_user, amount, lamport_time, source_node, optional_msg, vector_clock_id
+        FROM Transactions")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Transaction {
+            from_user: row.get(0)?,
+            to_user: row.get(1)?,
+            amount: row.get(2)?,
+            lamport_time: row.get(3)?,
+            source_node: row.get(4)?,
+            optional_msg: row.get(5)?,
+            vector_clock: std::collections::HashMap::new(),
+        })
+    })?;
+
+    let mut out = Vec::new();
+    for r in rows {
+        out.push(r?);
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "server")]
+/// Interval between two automatic garbage collections of the database
+const GC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+#[cfg(feature = "server")]
+/// Result of a garbage collection of the database
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    /// Number of VectorClock rows removed
+    pub removed_clocks: usize,
+    /// Number of VectorClockEntry rows removed
+    pub removed_entries: usize,
+    /// Size of the database file before the collection, in bytes
+    pub size_before: i64,
+    /// Size of the database file after the collection, in bytes
+    pub size_after: i64,
+}
+
+#[cfg(feature = "server")]
+impl GcReport {
+    /// Number of bytes reclaimed on disk
+    pub fn reclaimed_bytes(&self) -> i64 {
+        (self.size_before - self.size_after).max(0)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the size of the database, in bytes
+fn database_size(conn: &rusqlite::Connection) -> rusqlite::Result<i64> {
+    let page_count: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+    let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+    Ok(page_count * page_size)
+}
+
+#[cfg(feature = "server")]
+/// Returns the path of the database file, empty for an in-memory database,
+/// and the size of the database, in bytes
+pub fn database_file() -> rusqlite::Result<(std::path::PathBuf, i64)> {
+    let conn = DB_CONN.lock().unwrap();
+    let path = std::path::PathBuf::from(conn.path().unwrap_or_default());
+    Ok((path, database_size(&conn)?))
+}
+
+#[cfg(feature = "server")]
+/// Removes the vector clocks referenced by no transaction, no local state and
+/// no creation of a user
+///
+/// Returns the number of VectorClock and VectorClockEntry rows removed.
+fn remove_unreferenced_clocks(conn: &rusqlite::Connection) -> rusqlite::Result<(usize, usize)> {
+    const REFERENCED: &str = "SELECT vector_clock_id FROM Transactions
+        UNION SELECT vector_clock_id FROM LocalState
+        UNION SELECT created_clock_id FROM User WHERE created_clock_id IS NOT NULL";
+
+    let removed_entries = conn.execute(
+        &format!(
+            "DELETE FROM VectorClockEntry WHERE vector_clock_id NOT IN ({})",
+            REFERENCED
+        ),
+        [],
+    )?;
+    let removed_clocks = conn.execute(
+        &format!("DELETE FROM VectorClock WHERE id NOT IN ({})", REFERENCED),
+        [],
+    )?;
+    Ok((removed_clocks, removed_entries))
+}
+
+#[cfg(feature = "server")]
+/// Removes the obsolete vector clocks and vacuums the database
+///
+/// Every update of the local state stores a new vector clock, the previous
+/// ones are no longer referenced and only waste space.
+pub fn gc_db() -> rusqlite::Result<GcReport> {
+    let conn = DB_CONN.lock().unwrap();
+    let size_before = database_size(&conn)?;
+    let (removed_clocks, removed_entries) = remove_unreferenced_clocks(&conn)?;
+    conn.execute("VACUUM", [])?;
+    let size_after = database_size(&conn)?;
+
+    Ok(GcReport {
+        removed_clocks,
+        removed_entries,
+        size_before,
+        size_after,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Spawns a task that periodically garbage collects the database
+pub fn gc_worker() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            match gc_db() {
+                Ok(report) => log::debug!(
+                    "Database GC removed {} vector clocks, reclaimed {} bytes",
+                    report.removed_clocks,
+                    report.reclaimed_bytes()
+                ),
+                Err(e) => log::error!("Database GC failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_filter() {
+        let (filter, values) = TransactionQuery::default().to_sql_filter();
+        assert!(filter.is_empty());
+        assert!(values.is_empty());
+
+        let query = TransactionQuery {
+            user: Some("Alice".to_string()),
+            min_amount: Some(10.0),
+            message: Some(String::new()),
+            max_lamport: Some(42),
+            ..Default::default()
+        };
+        let (filter, values) = query.to_sql_filter();
+        assert_eq!(
+            filter,
+            "WHERE (from_user = ? OR to_user = ?) AND amount >= ? AND lamport_time <= ?"
+        );
+        assert_eq!(values.len(), 4);
+    }
+
+    #[test]
+    fn test_remove_unreferenced_clocks() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE VectorClock (id INTEGER PRIMARY KEY AUTOINCREMENT);
+            CREATE TABLE VectorClockEntry (vector_clock_id INTEGER, site_id TEXT, value INTEGER);
+            CREATE TABLE Transactions (vector_clock_id INTEGER);
+            CREATE TABLE LocalState (vector_clock_id INTEGER);
+            CREATE TABLE User (created_clock_id INTEGER);
+            INSERT INTO VectorClock (id) VALUES (1), (2), (3), (4);
+            INSERT INTO VectorClockEntry VALUES (1, 'A', 1), (2, 'A', 2), (2, 'B', 1), (3, 'A', 3), (4, 'B', 2);
+            INSERT INTO Transactions VALUES (1);
+            INSERT INTO LocalState VALUES (3);
+            INSERT INTO User VALUES (4), (NULL);",
+        )
+        .unwrap();
+
+        assert_eq!(remove_unreferenced_clocks(&conn).unwrap(), (1, 2));
+        assert_eq!(remove_unreferenced_clocks(&conn).unwrap(), (0, 0));
+
+        let remaining: i64 = conn
+            .query_row("SELECT COUNT(*) FROM VectorClockEntry", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(remaining, 3);
+    }
+
+    #[test]
+    fn test_postings_migration_and_audit() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO User (unique_name, solde) VALUES ('alice', 3.5), ('bob', 4.0);
+            INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node)
+            VALUES ('NULL', 'alice', 10.0, 1, 0, 'A'),
+                ('alice', 'bob', 4.0, 2, 0, 'A'),
+                ('alice', 'NULL', 2.5, 3, 0, 'B');",
+        )
+        .unwrap();
+        upgrade_schema(&conn).unwrap();
+
+        assert_eq!(account_balance(&conn, "alice").unwrap(), 3.5);
+        assert_eq!(account_balance(&conn, CASH_IN).unwrap(), -10.0);
+        assert_eq!(account_balance(&conn, CASH_OUT).unwrap(), 2.5);
+        let audit = audit_ledger_in(&conn).unwrap();
+        assert!(audit.is_balanced());
+        assert_eq!(audit.balances.len(), 4);
+        assert_eq!(
+            audit
+                .balances
+                .iter()
+                .map(|(_, balance)| balance)
+                .sum::<f64>(),
+            0.0
+        );
+
+        conn.execute_batch(
+            "DELETE FROM Postings WHERE lamport_time = 2 AND side = 'credit';
+            UPDATE User SET solde = 5.0 WHERE unique_name = 'alice';",
+        )
+        .unwrap();
+        let audit = audit_ledger_in(&conn).unwrap();
+        assert_eq!(audit.mismatches, vec!["alice", "bob"]);
+        assert_eq!(audit.unbalanced, vec![(2, "A".to_string())]);
+    }
+
+    #[test]
+    fn test_reconcile_users() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO User (unique_name, solde) VALUES ('alice', 10.0);
+            INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node)
+            VALUES ('NULL', 'alice', 10.0, 1, 0, 'A'),
+                ('alice', 'carol', 4.0, 2, 0, 'B');",
+        )
+        .unwrap();
+        upgrade_schema(&conn).unwrap();
+
+        let reconciliation = reconcile_users_in(&conn).unwrap();
+        assert_eq!(reconciliation.created_users, vec!["carol"]);
+        assert_eq!(
+            reconciliation.changed_balances,
+            vec![
+                ("alice".to_string(), 10.0, 6.0),
+                ("carol".to_string(), 0.0, 4.0)
+            ]
+        );
+        assert!(audit_ledger_in(&conn).unwrap().is_balanced());
+        assert!(reconcile_users_in(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rename_user_conflict() {
+        let new_db = || {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+            create_schema(&conn).unwrap();
+            upgrade_schema(&conn).unwrap();
+            conn.execute_batch(
+                "INSERT INTO User (unique_name, solde) VALUES ('alice', 10.0);
+                INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node)
+                VALUES ('NULL', 'alice', 10.0, 1, 0, 'A');",
+            )
+            .unwrap();
+            conn
+        };
+        let owner = |conn: &rusqlite::Connection| -> (String, String) {
+            let user = conn
+                .query_row("SELECT unique_name FROM User", [], |row| row.get(0))
+                .unwrap();
+            let to_user = conn
+                .query_row("SELECT to_user FROM Transactions", [], |row| row.get(0))
+                .unwrap();
+            (user, to_user)
+        };
+
+        // Two sites rename alice concurrently and receive the renames in
+        // opposite orders: the rename from B, later, wins on both
+        let site_a = new_db();
+        assert!(rename_user_in(&site_a, "alice", "bob", 5, "A").unwrap());
+        assert!(rename_user_in(&site_a, "alice", "carol", 5, "B").unwrap());
+        let site_b = new_db();
+        assert!(rename_user_in(&site_b, "alice", "carol", 5, "B").unwrap());
+        assert!(!rename_user_in(&site_b, "alice", "bob", 5, "A").unwrap());
+
+        let expected = ("carol".to_string(), "carol".to_string());
+        assert_eq!(owner(&site_a), expected);
+        assert_eq!(owner(&site_b), expected);
+
+        // A rename received twice is applied once
+        assert!(!rename_user_in(&site_a, "alice", "carol", 5, "B").unwrap());
+        assert!(rename_user_in(&site_a, "dave", "eve", 6, "A").is_err());
+        assert!(rename_user_in(&site_a, "carol", "carol", 6, "A").is_err());
+    }
+
+    #[test]
+    fn test_concurrent_user_creation() {
+        let new_db = || {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+            create_schema(&conn).unwrap();
+            upgrade_schema(&conn).unwrap();
+            conn
+        };
+        let state = |conn: &rusqlite::Connection| {
+            let origin: (String, i64) = conn
+                .query_row(
+                    "SELECT created_by, created_lamport FROM User WHERE unique_name = 'alice'",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+            let losers: Vec<(String, String)> = user_conflicts_in(conn)
+                .unwrap()
+                .into_iter()
+                .map(|c| (c.winner_node, c.loser_node))
+                .collect();
+            (origin, losers)
+        };
+
+        let clock = |entries: &[(&str, i64)]| -> std::collections::HashMap<String, i64> {
+            entries.iter().map(|(s, v)| (s.to_string(), *v)).collect()
+        };
+
+        // Three sites create alice concurrently, and each one receives the
+        // creations in a different order: the creation of A is kept everywhere
+        let creations = [("C", 4), ("A", 7), ("B", 2)];
+        let mut states = Vec::new();
+        for order in [[0, 1, 2], [2, 1, 0], [1, 2, 0]] {
+            let conn = new_db();
+            for i in order {
+                let (node, lamport) = creations[i];
+                let vc = clock(&[(node, lamport)]);
+                create_user_from_in(&conn, "alice", &node.to_lowercase(), lamport, node, &vc)
+                    .unwrap();
+            }
+            // the account belongs to the ledger of the creation kept
+            assert_eq!(crate::ledger::ledger_of_in(&conn, "alice").unwrap(), "a");
+            let (origin, mut losers) = state(&conn);
+            losers.sort();
+            states.push((origin, losers));
+        }
+        let expected = (
+            ("A".to_string(), 7),
+            vec![
+                ("A".to_string(), "B".to_string()),
+                ("A".to_string(), "C".to_string()),
+            ],
+        );
+        assert!(states.iter().all(|state| *state == expected));
+
+        // A creation received twice is not a conflict
+        let conn = new_db();
+        let ledger = crate::ledger::DEFAULT_LEDGER;
+        let (b3, a3) = (clock(&[("B", 3)]), clock(&[("A", 3)]));
+        assert_eq!(
+            create_user_from_in(&conn, "bob", ledger, 3, "B", &b3).unwrap(),
+            None
+        );
+        assert_eq!(
+            create_user_from_in(&conn, "bob", ledger, 3, "B", &b3).unwrap(),
+            None
+        );
+        let conflict = create_user_from_in(&conn, "bob", ledger, 3, "A", &a3)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (conflict.winner_node, conflict.loser_node),
+            ("A".to_string(), "B".to_string())
+        );
+        let balance: f64 = conn
+            .query_row(
+                "SELECT solde FROM User WHERE unique_name = 'bob'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(balance, 0.0);
+
+        // A creation that causally follows the first one is not a conflict,
+        // whatever the order in which they are received
+        let (first, second) = (clock(&[("B", 1)]), clock(&[("A", 2), ("B", 1)]));
+        for causal_order in [true, false] {
+            let conn = new_db();
+            let mut creations = [("B", 1, &first), ("A", 2, &second)];
+            if !causal_order {
+                creations.reverse();
+            }
+            for (node, lamport, vc) in creations {
+                assert_eq!(
+                    create_user_from_in(&conn, "alice", ledger, lamport, node, vc).unwrap(),
+                    None
+                );
+            }
+            assert_eq!(state(&conn), (("B".to_string(), 1), Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_open_database() {
+        let path = std::env::temp_dir().join(format!("peillute_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        open(&path).unwrap();
+        assert!(!is_database_initialized().unwrap());
+        init_db().unwrap();
+        assert!(is_database_initialized().unwrap());
+        assert!(
+            database_file()
+                .unwrap()
+                .0
+                .ends_with(path.file_name().unwrap())
+        );
+
+        std::fs::remove_file(&path).unwrap();
+
+        // An ephemeral site is populated by the synchronization snapshot
+        open_ephemeral(u16::MAX).unwrap();
+        assert!(is_ephemeral());
+        assert_eq!(database_name(), "in memory");
+        init_db().unwrap();
+        let tx = |lamport_time, from_user: &str, to_user: &str, amount_in_cent| {
+            crate::snapshot::TxSummary {
+                lamport_time,
+                source_node: "B".to_string(),
+                from_user: from_user.to_string(),
+                to_user: to_user.to_string(),
+                amount_in_cent,
+            }
+        };
+        let snapshot = crate::snapshot::GlobalSnapshot {
+            all_transactions: std::collections::HashSet::new(),
+            missing: std::collections::HashMap::from([(
+                "A".to_string(),
+                std::collections::HashSet::from([
+                    tx(2, "alice", "bob", 400),
+                    tx(1, CASH_IN, "alice", 1000),
+                ]),
+            )]),
+            consistent: true,
+            ledgers: std::collections::HashMap::new(),
+            metadata: crate::snapshot::SnapshotMetadata::default(),
+            certificate: None,
+        };
+        update_db_with_snapshot(&snapshot, &std::collections::HashMap::new());
+        assert_eq!(calculate_solde("alice").unwrap(), 6.0);
+        assert_eq!(calculate_solde("bob").unwrap(), 4.0);
+        update_local_state("A", crate::clock::Clock::new()).unwrap();
+        assert!(get_local_state().is_err());
+    }
+
+    #[test]
+    fn test_transaction_comments() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        upgrade_schema(&conn).unwrap();
+
+        let comment = |author: &str, text: &str, reaction, lamport_time| TransactionComment {
+            author: author.to_string(),
+            text: text.to_string(),
+            reaction,
+            lamport_time,
+            source_node: "B".to_string(),
+        };
+        add_comment_in(&conn, 1, "A", &comment("bob", "🍕", true, 5)).unwrap();
+        add_comment_in(&conn, 1, "A", &comment("alice", "Thanks!", false, 3)).unwrap();
+        // a comment diffused twice is recorded once
+        add_comment_in(&conn, 1, "A", &comment("alice", "Thanks!", false, 3)).unwrap();
+        add_comment_in(&conn, 2, "A", &comment("bob", "Other", false, 6)).unwrap();
+
+        assert_eq!(
+            get_comments_in(&conn, 1, "A").unwrap(),
+            vec![
+                comment("alice", "Thanks!", false, 3),
+                comment("bob", "🍕", true, 5)
+            ]
+        );
+        assert!(get_comments_in(&conn, 1, "B").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_refund_link_and_indexes() {
+        assert_eq!(
+            refunded_transaction("Refund transaction node-A-12"),
+            Some(("node-A", 12))
+        );
+        assert_eq!(refunded_transaction("Refund transaction A"), None);
+        assert_eq!(refunded_transaction("Lunch"), None);
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('alice', 'bob', 4.0, 1, 0, 'A', NULL),
+                ('bob', 'alice', 4.0, 2, 0, 'B', 'Refund transaction A-1');",
+        )
+        .unwrap();
+        // refunds recorded before the link column are linked by the upgrade
+        upgrade_schema(&conn).unwrap();
+        assert!(has_been_refunded_in(&conn, 1, "A").unwrap());
+        assert!(!has_been_refunded_in(&conn, 2, "B").unwrap());
+
+        let plan = |sql: &str| -> String {
+            let mut stmt = conn
+                .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+                .unwrap();
+            stmt.query_map([], |row| row.get::<_, String>(3))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .unwrap()
+                .join("\n")
+        };
+        let user_plan =
+            plan("SELECT * FROM Transactions WHERE from_user = 'alice' OR to_user = 'alice'");
+        assert!(user_plan.contains("idx_transactions_from"), "{}", user_plan);
+        assert!(user_plan.contains("idx_transactions_to"), "{}", user_plan);
+        let refund_plan =
+            plan("SELECT 1 FROM Transactions WHERE refund_of_node = 'A' AND refund_of_lamport = 1");
+        assert!(
+            refund_plan.contains("idx_transactions_refund"),
+            "{}",
+            refund_plan
+        );
+    }
+
+    #[test]
+    fn test_transaction_status() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('alice', 'bob', 4.0, 1, 0, 'A', NULL);",
+        )
+        .unwrap();
+        // transactions recorded before the status column are settled
+        upgrade_schema(&conn).unwrap();
+        assert!(
+            unsettled_transactions_in(&conn, "alice")
+                .unwrap()
+                .is_empty()
+        );
+
+        conn.execute_batch(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('alice', 'carol', 1.0, 2, 0, 'A', NULL),
+                ('carol', 'bob', 1.0, 3, 0, 'A', NULL),
+                ('alice', 'bob', 2.0, 3, 0, 'B', NULL);",
+        )
+        .unwrap();
+        let updated =
+            set_transactions_status_in(&conn, "A", &(2..=3), TransactionStatus::Pending).unwrap();
+        assert_eq!(updated, 2);
+        set_transactions_status_in(&conn, "A", &(3..=3), TransactionStatus::Failed).unwrap();
+        assert_eq!(
+            unsettled_transactions_in(&conn, "carol").unwrap(),
+            vec![
+                (2, "A".to_string(), TransactionStatus::Pending),
+                (3, "A".to_string(), TransactionStatus::Failed)
+            ]
+        );
+        set_transactions_status_in(&conn, "A", &(2..=3), TransactionStatus::Confirmed).unwrap();
+        assert!(
+            unsettled_transactions_in(&conn, "carol")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_refund_window() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('alice', 'bob', 4.0, 1, 0, 'A', NULL);",
+        )
+        .unwrap();
+        // the window of the transactions recorded before the upgrade starts with it
+        upgrade_schema(&conn).unwrap();
+        let upgraded_at: i64 = conn
+            .query_row("SELECT created_at FROM Transactions", [], |row| row.get(0))
+            .unwrap();
+        assert!(!immutable_at(&conn, 1, "A", upgraded_at, 3600).unwrap());
+        assert!(immutable_at(&conn, 1, "A", upgraded_at + 3600, 3600).unwrap());
+        // no limit
+        assert!(!immutable_at(&conn, 1, "A", upgraded_at + 3600, 0).unwrap());
+
+        conn.execute_batch(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('alice', 'bob', 2.0, 2, 0, 'A', NULL);",
+        )
+        .unwrap();
+        // undated or unknown transactions are not immutable
+        assert!(!immutable_at(&conn, 2, "A", upgraded_at + 3600, 3600).unwrap());
+        assert!(!immutable_at(&conn, 3, "A", upgraded_at + 3600, 3600).unwrap());
+    }
+
+    #[test]
+    fn test_refund_blockers() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('NULL', 'alice', 10.0, 1, 0, 'A', NULL),
+                ('alice', 'bob', 4.0, 2, 0, 'A', NULL),
+                ('bob', 'alice', 4.0, 3, 0, 'B', 'Refund transaction A-2'),
+                ('alice', 'carol', 6.0, 4, 0, 'A', NULL),
+                ('carol', 'NULL', 5.0, 5, 0, 'A', NULL),
+                ('alice', 'dave', 1.0, 6, 0, 'A', NULL);",
+        )
+        .unwrap();
+        upgrade_schema(&conn).unwrap();
+        let now: i64 = conn
+            .query_row("SELECT MAX(created_at) FROM Transactions", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        let blocker = |lamport_time, source_node: &str, to_user: &str, amount, now| {
+            let tx = Transaction {
+                from_user: String::new(),
+                to_user: to_user.to_string(),
+                amount,
+                lamport_time,
+                source_node: source_node.to_string(),
+                optional_msg: (source_node == "B").then(|| "Refund transaction A-2".to_string()),
+                vector_clock: std::collections::HashMap::new(),
+            };
+            refund_blocker_in(&conn, &tx, now, 7200).unwrap()
+        };
+
+        assert_eq!(
+            blocker(2, "A", "bob", 4.0, now).as_deref(),
+            Some("Already refunded")
+        );
+        assert_eq!(
+            blocker(3, "B", "alice", 4.0, now).as_deref(),
+            Some("A refund cannot be refunded")
+        );
+        assert_eq!(
+            blocker(4, "A", "carol", 6.0, now).as_deref(),
+            Some("carol has not enough money to give it back")
+        );
+        assert_eq!(blocker(6, "A", "dave", 1.0, now), None);
+        assert_eq!(
+            blocker(6, "A", "dave", 1.0, now + 7200).as_deref(),
+            Some("Older than the refund window of 2 hours")
+        );
+    }
+
+    #[test]
+    fn test_sort_transactions() {
+        let tx = |lamport_time: i64, source_node: &str, vc: &[(&str, i64)]| Transaction {
+            from_user: "NULL".to_string(),
+            to_user: "alice".to_string(),
+            amount: 1.0,
+            lamport_time,
+            source_node: source_node.to_string(),
+            optional_msg: None,
+            vector_clock: vc.iter().map(|(s, v)| (s.to_string(), *v)).collect(),
+        };
+        // B2 was caused by A3, although its Lamport time is lower
+        let recorded = vec![
+            tx(3, "A", &[("A", 2)]),
+            tx(2, "B", &[("A", 2), ("B", 1)]),
+            tx(1, "A", &[("A", 1)]),
+        ];
+        let keys = |txs: Vec<Transaction>| -> Vec<(i64, String)> {
+            txs.into_iter()
+                .map(|tx| (tx.lamport_time, tx.source_node))
+                .collect()
+        };
+        let key = |lamport_time: i64, source_node: &str| (lamport_time, source_node.to_string());
+
+        assert_eq!(
+            keys(sort_transactions(recorded.clone(), HistoryOrder::Recorded)),
+            vec![key(3, "A"), key(2, "B"), key(1, "A")]
+        );
+        assert_eq!(
+            keys(sort_transactions(recorded.clone(), HistoryOrder::Lamport)),
+            vec![key(1, "A"), key(2, "B"), key(3, "A")]
+        );
+        assert_eq!(
+            keys(sort_transactions(recorded.clone(), HistoryOrder::Site)),
+            vec![key(1, "A"), key(3, "A"), key(2, "B")]
+        );
+        assert_eq!(
+            keys(sort_transactions(recorded, HistoryOrder::Causal)),
+            vec![key(1, "A"), key(3, "A"), key(2, "B")]
+        );
+        for order in HistoryOrder::ALL {
+            assert_eq!(HistoryOrder::parse(order.as_str()), Some(order));
+        }
+    }
+}
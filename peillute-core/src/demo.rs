@@ -0,0 +1,203 @@
+//! Demo mode with generated transactions
+//!
+//! `--demo` starts the site with a handful of users, then keeps creating
+//! random deposits, withdrawals, transfers and payments between them at
+//! `--demo-rate` transactions per minute. The transactions go through the
+//! critical commands like the ones typed by a user, so they take the global
+//! mutex and are diffused to the other sites: the web interface can be shown
+//! and a cluster loaded without typing anything.
+//!
+//! Unless a database path is given, a demo site keeps its database in memory
+//! so that the generated data never mixes with real accounts.
+
+#![cfg(feature = "server")]
+
+use crate::control::CriticalCommands;
+use crate::sim::SimRng;
+
+/// Default number of transactions generated per minute
+pub const DEFAULT_DEMO_RATE: u32 = 30;
+
+/// Users created by the demo mode
+pub const DEMO_USERS: [&str; 6] = ["alice", "bob", "carol", "dave", "erin", "frank"];
+
+/// Balance deposited on each demo user when it is created
+const DEMO_INITIAL_BALANCE: f64 = 200.0;
+
+/// Time left to the site to join the network before the users are created
+const DEMO_START_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Balance below which a user only receives money
+const MIN_SPENDING_BALANCE: f64 = 1.0;
+
+/// Returns a random amount between 1 and `max`, rounded to the cent
+fn amount(rng: &mut SimRng, max: f64) -> f64 {
+    let max_cents = (max.min(50.0) * 100.0).floor().max(100.0) as u64;
+    rng.range(100, max_cents) as f64 / 100.0
+}
+
+/// Picks the next transaction to generate, from the users and their balances
+///
+/// A user only spends money it has, so that the generated transactions are
+/// not refused for insufficient funds.
+pub(crate) fn next_command(
+    rng: &mut SimRng,
+    balances: &[(String, f64)],
+) -> Option<CriticalCommands> {
+    if balances.is_empty() {
+        return None;
+    }
+    let (name, balance) = &balances[rng.range(0, balances.len() as u64 - 1) as usize];
+    let name = name.clone();
+    if *balance < MIN_SPENDING_BALANCE {
+        return Some(CriticalCommands::Deposit {
+            amount: amount(rng, DEMO_INITIAL_BALANCE),
+            name,
+        });
+    }
+    let command = match rng.range(0, 9) {
+        0 | 1 => CriticalCommands::Deposit {
+            amount: amount(rng, DEMO_INITIAL_BALANCE),
+            name,
+        },
+        2 => CriticalCommands::Withdraw {
+            amount: amount(rng, *balance),
+            name,
+        },
+        3 | 4 => CriticalCommands::Pay {
+            amount: amount(rng, *balance),
+            name,
+            tip_percent: 0.0,
+            split_with: Vec::new(),
+        },
+        _ => {
+            let others: Vec<&String> = balances
+                .iter()
+                .map(|(other, _)| other)
+                .filter(|other| **other != name)
+                .collect();
+            if others.is_empty() {
+                return Some(CriticalCommands::Pay {
+                    amount: amount(rng, *balance),
+                    name,
+                    tip_percent: 0.0,
+                    split_with: Vec::new(),
+                });
+            }
+            CriticalCommands::Transfer {
+                to: others[rng.range(0, others.len() as u64 - 1) as usize].clone(),
+                amount: amount(rng, *balance),
+                from: name,
+            }
+        }
+    };
+    Some(command)
+}
+
+/// Creates the demo users missing from the database, with their initial balance
+async fn seed_users() -> Result<(), Box<dyn std::error::Error>> {
+    for name in DEMO_USERS {
+        if crate::db::user_exists(name)? {
+            continue;
+        }
+        crate::control::enqueue_critical(CriticalCommands::CreateUser {
+            name: name.to_string(),
+            ledger: crate::ledger::DEFAULT_LEDGER.to_string(),
+        })
+        .await?;
+        crate::control::enqueue_critical(CriticalCommands::Deposit {
+            name: name.to_string(),
+            amount: DEMO_INITIAL_BALANCE,
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// Returns the demo users known by the site, with their balance
+fn balances() -> rusqlite::Result<Vec<(String, f64)>> {
+    let mut balances = Vec::new();
+    for name in DEMO_USERS {
+        if crate::db::user_exists(name)? {
+            balances.push((name.to_string(), crate::db::calculate_solde(name)?));
+        }
+    }
+    Ok(balances)
+}
+
+/// Starts the demo mode, generating `rate` transactions per minute
+pub fn start(rate: u32) {
+    let period = std::time::Duration::from_secs(60) / rate.max(1);
+    log::info!(
+        "Demo mode: generating {} transactions per minute",
+        rate.max(1)
+    );
+    tokio::spawn(async move {
+        tokio::time::sleep(DEMO_START_DELAY).await;
+        if let Err(e) = seed_users().await {
+            log::error!("Cannot create the demo users: {}", e);
+        }
+
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut rng = SimRng(seed);
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            let balances = match balances() {
+                Ok(balances) => balances,
+                Err(e) => {
+                    log::error!("Cannot read the balances of the demo users: {}", e);
+                    continue;
+                }
+            };
+            let Some(command) = next_command(&mut rng, &balances) else {
+                continue;
+            };
+            // a busy site skips a transaction rather than queueing more
+            if let Err(e) = crate::control::enqueue_critical(command).await {
+                log::debug!("Demo transaction skipped: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_command() {
+        let mut rng = SimRng(7);
+        assert!(next_command(&mut rng, &[]).is_none());
+
+        let broke = vec![("alice".to_string(), 0.5)];
+        for _ in 0..20 {
+            assert!(matches!(
+                next_command(&mut rng, &broke),
+                Some(CriticalCommands::Deposit { .. })
+            ));
+        }
+
+        let balances = vec![("alice".to_string(), 30.0), ("bob".to_string(), 5.0)];
+        for _ in 0..200 {
+            match next_command(&mut rng, &balances).unwrap() {
+                CriticalCommands::Withdraw { name, amount }
+                | CriticalCommands::Pay { name, amount, .. } => {
+                    let balance = balances.iter().find(|(n, _)| *n == name).unwrap().1;
+                    assert!(amount >= 1.0 && amount <= balance);
+                }
+                CriticalCommands::Transfer { from, to, amount } => {
+                    let balance = balances.iter().find(|(n, _)| *n == from).unwrap().1;
+                    assert_ne!(from, to);
+                    assert!(amount >= 1.0 && amount <= balance);
+                }
+                CriticalCommands::Deposit { amount, .. } => assert!(amount >= 1.0),
+                other => panic!("unexpected command {:?}", other),
+            }
+        }
+    }
+}
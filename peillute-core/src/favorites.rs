@@ -0,0 +1,129 @@
+//! Favorite transfers
+//!
+//! Users can save the transfers they make often, such as a weekly payment to
+//! a roommate, as templates holding the beneficiary, the amount and a message.
+//! A favorite is executed in one click from the user page, as a regular
+//! transfer. Favorites are local to the site, like the policies.
+
+/// Transfer template saved by a user
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Favorite {
+    /// Identifier of the favorite, ignored when it is created
+    pub id: i64,
+    /// User making the transfer
+    pub user: String,
+    /// User receiving the transfer
+    pub beneficiary: String,
+    /// Amount of the transfer
+    pub amount: f64,
+    /// Message of the transfer
+    pub message: Option<String>,
+}
+
+#[cfg(feature = "server")]
+/// Saves a new favorite, returns its identifier
+fn add_favorite_in(conn: &rusqlite::Connection, favorite: &Favorite) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO Favorites (unique_name, beneficiary, amount, message) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![
+            favorite.user,
+            favorite.beneficiary,
+            favorite.amount,
+            favorite.message
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+#[cfg(feature = "server")]
+/// Returns the favorites of a user, in the order they were saved
+fn favorites_in(conn: &rusqlite::Connection, user: &str) -> rusqlite::Result<Vec<Favorite>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, unique_name, beneficiary, amount, message
+        FROM Favorites WHERE unique_name = ?1 ORDER BY id",
+    )?;
+    stmt.query_map(rusqlite::params![user], |row| {
+        Ok(Favorite {
+            id: row.get(0)?,
+            user: row.get(1)?,
+            beneficiary: row.get(2)?,
+            amount: row.get(3)?,
+            message: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Removes a favorite of a user, returns false if the user has no such favorite
+fn delete_favorite_in(conn: &rusqlite::Connection, user: &str, id: i64) -> rusqlite::Result<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM Favorites WHERE unique_name = ?1 AND id = ?2",
+        rusqlite::params![user, id],
+    )?;
+    Ok(deleted > 0)
+}
+
+#[cfg(feature = "server")]
+/// Saves a new favorite, returns its identifier
+pub fn add_favorite(favorite: &Favorite) -> rusqlite::Result<i64> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    add_favorite_in(&conn, favorite)
+}
+
+#[cfg(feature = "server")]
+/// Returns the favorites of a user, in the order they were saved
+pub fn get_favorites(user: &str) -> rusqlite::Result<Vec<Favorite>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    favorites_in(&conn, user)
+}
+
+#[cfg(feature = "server")]
+/// Returns a favorite of a user
+pub fn get_favorite(user: &str, id: i64) -> rusqlite::Result<Option<Favorite>> {
+    Ok(get_favorites(user)?
+        .into_iter()
+        .find(|favorite| favorite.id == id))
+}
+
+#[cfg(feature = "server")]
+/// Removes a favorite of a user, returns false if the user has no such favorite
+pub fn delete_favorite(user: &str, id: i64) -> rusqlite::Result<bool> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    delete_favorite_in(&conn, user, id)
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_favorites() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+
+        let favorite = |user: &str, beneficiary: &str, amount| Favorite {
+            id: 0,
+            user: user.to_string(),
+            beneficiary: beneficiary.to_string(),
+            amount,
+            message: Some("Rent".to_string()),
+        };
+        let rent = add_favorite_in(&conn, &favorite("alice", "bob", 400.0)).unwrap();
+        let coffee = add_favorite_in(&conn, &favorite("alice", "carol", 2.5)).unwrap();
+        add_favorite_in(&conn, &favorite("bob", "alice", 10.0)).unwrap();
+
+        let favorites = favorites_in(&conn, "alice").unwrap();
+        assert_eq!(favorites.len(), 2);
+        assert_eq!(favorites[0].id, rent);
+        assert_eq!(favorites[0].beneficiary, "bob");
+        assert_eq!(favorites[1].amount, 2.5);
+
+        // a user cannot remove the favorite of another user
+        assert!(!delete_favorite_in(&conn, "bob", coffee).unwrap());
+        assert!(delete_favorite_in(&conn, "alice", coffee).unwrap());
+        assert_eq!(favorites_in(&conn, "alice").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,264 @@
+//! Fees charged on the transfers and payments
+//!
+//! A site may charge a fee on the transfers and the payments submitted on it,
+//! for each ledger, flat, in percent of the amount, or both. The fees are
+//! described in a TOML file:
+//!
+//! ```toml
+//! [[fees]]
+//! operation = "transfer"
+//! flat = 0.10
+//! account = "fees"
+//!
+//! [[fees]]
+//! ledger = "club-bar"
+//! operation = "pay"
+//! percent = 2.5
+//! account = "bar-fees"
+//! ```
+//!
+//! A fee without `ledger` applies to the default ledger. It is paid by the
+//! payer to the fee account, a user of the same ledger, as an extra
+//! transaction recorded right after the operation, and the operation is
+//! rejected when the payer cannot pay both. The fee is computed by the site
+//! where the operation is submitted and carried in the diffused message, so
+//! that every site records the same fee whatever its own configuration.
+
+#![cfg(feature = "server")]
+
+/// Operation charged with a fee
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeOperation {
+    /// Transfer to another user, alone or in a split bill
+    Transfer,
+    /// Payment, alone or split between several payers
+    Pay,
+}
+
+/// Returns the default ledger, for the rules without ledger
+fn default_ledger() -> String {
+    crate::ledger::DEFAULT_LEDGER.to_string()
+}
+
+/// Fee charged on an operation of a ledger
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct FeeRule {
+    /// Ledger of the payers charged
+    #[serde(default = "default_ledger")]
+    pub ledger: String,
+    /// Operation charged
+    pub operation: FeeOperation,
+    /// Amount charged on each operation
+    #[serde(default)]
+    pub flat: f64,
+    /// Percentage of the amount of the operation charged
+    #[serde(default)]
+    pub percent: f64,
+    /// User of the ledger receiving the fees
+    pub account: String,
+}
+
+impl FeeRule {
+    /// Returns the fee charged on an amount, rounded to the cent
+    pub fn fee_of(&self, amount: f64) -> f64 {
+        ((self.flat + amount * self.percent / 100.0) * 100.0).round() / 100.0
+    }
+}
+
+/// Content of a fee configuration file
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+pub struct FeeConfig {
+    /// Fees charged by the site
+    #[serde(default)]
+    pub fees: Vec<FeeRule>,
+}
+
+impl FeeConfig {
+    /// Parses and validates the content of a fee configuration file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: FeeConfig =
+            toml::from_str(content).map_err(|e| format!("invalid fee file: {}", e))?;
+        for (i, rule) in config.fees.iter().enumerate() {
+            crate::ledger::validate_name(&rule.ledger)?;
+            if [rule.flat, rule.percent]
+                .iter()
+                .any(|value| !value.is_finite() || *value < 0.0)
+            {
+                return Err(format!(
+                    "invalid fee on {:?} in ledger '{}'",
+                    rule.operation, rule.ledger
+                ));
+            }
+            if rule.account.is_empty() || crate::db::is_system_account(&rule.account) {
+                return Err(format!("invalid fee account '{}'", rule.account));
+            }
+            if config.fees[..i]
+                .iter()
+                .any(|other| other.ledger == rule.ledger && other.operation == rule.operation)
+            {
+                return Err(format!(
+                    "fee on {:?} defined twice in ledger '{}'",
+                    rule.operation, rule.ledger
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads and validates a fee configuration file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read fee file {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    /// Returns the fee charged on an operation of a ledger, if any
+    pub fn rule(&self, ledger: &str, operation: FeeOperation) -> Option<&FeeRule> {
+        self.fees
+            .iter()
+            .find(|rule| rule.ledger == ledger && rule.operation == operation)
+    }
+}
+
+/// Fees charged by the site, set at startup
+static CONFIG: std::sync::OnceLock<FeeConfig> = std::sync::OnceLock::new();
+
+/// Sets the fees charged on the operations submitted to the site
+pub fn configure(config: FeeConfig) {
+    if CONFIG.set(config).is_err() {
+        log::warn!("Fees are already configured");
+    }
+}
+
+/// Fee paid on an operation, carried in its message
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Fee {
+    /// User paying the fee
+    pub payer: String,
+    /// User receiving the fee
+    pub account: String,
+    /// Amount of the fee
+    pub amount: f64,
+    /// Lamport time of the fee transaction
+    pub lamport: i64,
+}
+
+/// Returns the fee account and the fee the site charges a payer on an
+/// operation, None if the operation is free
+///
+/// Returns an error if the payer cannot pay both the amount and the fee.
+pub fn fee_for(
+    payer: &str,
+    operation: FeeOperation,
+    amount: f64,
+) -> Result<Option<(String, f64)>, Box<dyn std::error::Error>> {
+    let fee = fee_rule(payer, operation, amount)?;
+    if let Some((_, fee)) = &fee
+        && crate::db::calculate_solde(payer)? < amount + fee
+    {
+        return Err(format!(
+            "Insufficient funds: '{}' has less than {} with the fee of {}.",
+            payer,
+            amount + fee,
+            fee
+        )
+        .into());
+    }
+    Ok(fee)
+}
+
+/// Returns the fee account and the fee the site charges a payer on an
+/// operation, whether or not the payer can pay it
+pub fn fee_rule(
+    payer: &str,
+    operation: FeeOperation,
+    amount: f64,
+) -> Result<Option<(String, f64)>, Box<dyn std::error::Error>> {
+    let Some(config) = CONFIG.get() else {
+        return Ok(None);
+    };
+    let ledger = crate::ledger::ledger_of(payer)?;
+    let Some(rule) = config.rule(&ledger, operation) else {
+        return Ok(None);
+    };
+    let fee = rule.fee_of(amount);
+    if fee <= 0.0 || payer == rule.account {
+        return Ok(None);
+    }
+    if !crate::db::user_exists(&rule.account)? || crate::ledger::ledger_of(&rule.account)? != ledger
+    {
+        return Err(format!(
+            "Fee account '{}' is not a user of ledger '{}'.",
+            rule.account, ledger
+        )
+        .into());
+    }
+    Ok(Some((rule.account.clone(), fee)))
+}
+
+/// Records the fees of an operation
+pub fn charge(
+    fees: &[Fee],
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    for fee in fees {
+        crate::db::create_transaction(
+            &fee.payer,
+            &fee.account,
+            fee.amount,
+            &fee.lamport,
+            source_node,
+            "Fee",
+            vector_clock,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = FeeConfig::parse(
+            r#"
+            [[fees]]
+            operation = "transfer"
+            flat = 0.10
+            account = "fees"
+
+            [[fees]]
+            ledger = "club-bar"
+            operation = "pay"
+            flat = 0.5
+            percent = 2.5
+            account = "bar-fees"
+            "#,
+        )
+        .unwrap();
+        let transfer = config
+            .rule(crate::ledger::DEFAULT_LEDGER, FeeOperation::Transfer)
+            .unwrap();
+        assert_eq!(transfer.fee_of(42.0), 0.1);
+        let pay = config.rule("club-bar", FeeOperation::Pay).unwrap();
+        assert_eq!(pay.fee_of(10.0), 0.75);
+        assert_eq!(pay.fee_of(0.3), 0.51);
+        assert!(config.rule("club-bar", FeeOperation::Transfer).is_none());
+
+        assert!(FeeConfig::parse("[[fees]]\noperation = \"pay\"\naccount = \"@cash-in\"").is_err());
+        assert!(
+            FeeConfig::parse("[[fees]]\noperation = \"pay\"\npercent = -1\naccount = \"fees\"")
+                .is_err()
+        );
+        assert!(
+            FeeConfig::parse(
+                "[[fees]]\noperation = \"pay\"\naccount = \"fees\"\n\
+                 [[fees]]\noperation = \"pay\"\naccount = \"other\""
+            )
+            .is_err()
+        );
+    }
+}
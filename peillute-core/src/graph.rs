@@ -0,0 +1,182 @@
+//! Causal graph of the transactions in the DOT format
+//!
+//! `peillute graph` and the `/api/transaction_graph` endpoint write the
+//! transactions of a site as a Graphviz graph, to see the causality chains
+//! when debugging an ordering issue:
+//!
+//! ```sh
+//! peillute graph --db-path peillute_0.db --user alice | dot -Tsvg > graph.svg
+//! ```
+//!
+//! Each transaction is a node, named after its source site and Lamport time.
+//! A solid edge goes from each transaction to the transactions it directly
+//! precedes, given by the vector clocks, or the Lamport times for the
+//! transactions without one: the edges implied by other ones are not drawn.
+//! A dashed edge goes from a refunded transaction to its refund. Given a user,
+//! only the transactions of this user are drawn, and the edges between them.
+
+#![cfg(feature = "server")]
+
+use crate::db::Transaction;
+
+/// Returns the name of the node of a transaction
+fn node_id(transaction: &Transaction) -> String {
+    format!("{}-{}", transaction.source_node, transaction.lamport_time)
+}
+
+/// Escapes a string for a quoted DOT identifier
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Returns the label of the node of a transaction
+fn label(transaction: &Transaction) -> String {
+    let mut clock: Vec<_> = transaction.vector_clock.iter().collect();
+    clock.sort();
+    let clock = clock
+        .iter()
+        .map(|(site, time)| format!("{}:{}", site, time))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "{}\\n{} → {}\\n{:.2} €\\n{}",
+        escape(&node_id(transaction)),
+        escape(&transaction.from_user),
+        escape(&transaction.to_user),
+        transaction.amount,
+        escape(&clock)
+    )
+}
+
+/// Returns the causal edges of the transactions, as pairs of indices from
+/// the cause to the effect, without the edges implied by other ones
+fn causal_edges(transactions: &[Transaction]) -> Vec<(usize, usize)> {
+    use crate::replay::happens_before;
+
+    let mut edges = Vec::new();
+    for (b, effect) in transactions.iter().enumerate() {
+        let causes: Vec<usize> = (0..transactions.len())
+            .filter(|&a| a != b && happens_before(&transactions[a], effect))
+            .collect();
+        for &a in &causes {
+            let implied = causes
+                .iter()
+                .any(|&c| c != a && happens_before(&transactions[a], &transactions[c]));
+            if !implied {
+                edges.push((a, b));
+            }
+        }
+    }
+    edges
+}
+
+/// Returns the refund edges of the transactions, as pairs of indices from the
+/// refunded transaction to its refund
+fn refund_edges(transactions: &[Transaction]) -> Vec<(usize, usize)> {
+    transactions
+        .iter()
+        .enumerate()
+        .filter_map(|(refund, transaction)| {
+            let (node, time) =
+                crate::db::refunded_transaction(transaction.optional_msg.as_deref()?)?;
+            let refunded = transactions
+                .iter()
+                .position(|t| t.source_node == node && t.lamport_time == time)?;
+            Some((refunded, refund))
+        })
+        .collect()
+}
+
+/// Writes the causal graph of transactions in the DOT format, limited to the
+/// transactions of a user if given
+pub fn to_dot(transactions: &[Transaction], user: Option<&str>) -> String {
+    use std::fmt::Write;
+
+    let transactions: Vec<Transaction> = transactions
+        .iter()
+        .filter(|t| user.is_none_or(|user| t.from_user == user || t.to_user == user))
+        .cloned()
+        .collect();
+
+    let mut dot = String::from("digraph transactions {\n    rankdir=LR;\n    node [shape=box];\n");
+    for transaction in &transactions {
+        let _ = writeln!(
+            dot,
+            "    \"{}\" [label=\"{}\"];",
+            escape(&node_id(transaction)),
+            label(transaction)
+        );
+    }
+    for (a, b) in causal_edges(&transactions) {
+        let _ = writeln!(
+            dot,
+            "    \"{}\" -> \"{}\";",
+            escape(&node_id(&transactions[a])),
+            escape(&node_id(&transactions[b]))
+        );
+    }
+    for (a, b) in refund_edges(&transactions) {
+        let _ = writeln!(
+            dot,
+            "    \"{}\" -> \"{}\" [style=dashed, label=\"refund\"];",
+            escape(&node_id(&transactions[a])),
+            escape(&node_id(&transactions[b]))
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Writes the causal graph of the transactions of the site in the DOT format,
+/// limited to the transactions of a user if given
+pub fn export(user: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(user) = user
+        && !crate::db::user_exists(user)?
+    {
+        return Err(format!("User '{}' does not exist.", user).into());
+    }
+    let transactions = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        crate::replay::load_transactions(&conn)?
+    };
+    Ok(to_dot(&transactions, user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(from: &str, to: &str, node: &str, time: i64, clock: &[(&str, i64)]) -> Transaction {
+        Transaction {
+            from_user: from.to_string(),
+            to_user: to.to_string(),
+            amount: 10.0,
+            lamport_time: time,
+            source_node: node.to_string(),
+            optional_msg: None,
+            vector_clock: clock.iter().map(|(s, v)| (s.to_string(), *v)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let deposit = tx("NULL", "alice", "A", 1, &[("A", 1)]);
+        let transfer = tx("alice", "bob", "A", 2, &[("A", 2)]);
+        let concurrent = tx("NULL", "carol", "B", 1, &[("B", 1)]);
+        let mut refund = tx("bob", "alice", "B", 3, &[("A", 2), ("B", 3)]);
+        refund.optional_msg = Some("Refund transaction A-2".to_string());
+        let transactions = vec![deposit, transfer, concurrent, refund];
+
+        // A-1 -> A-2 -> B-3 is drawn, A-1 -> B-3 is implied
+        assert_eq!(causal_edges(&transactions), vec![(0, 1), (1, 3), (2, 3)]);
+        assert_eq!(refund_edges(&transactions), vec![(1, 3)]);
+
+        let dot = to_dot(&transactions, Some("bob"));
+        assert!(dot.starts_with("digraph transactions {\n"));
+        assert!(dot.contains("\"A-2\" [label=\"A-2\\nalice → bob\\n10.00 €\\nA:2\"];"));
+        assert!(dot.contains("\"A-2\" -> \"B-3\";"));
+        assert!(dot.contains("\"A-2\" -> \"B-3\" [style=dashed, label=\"refund\"];"));
+        assert!(!dot.contains("carol") && !dot.contains("\"A-1\""));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,444 @@
+//! GraphQL API of the web server
+//!
+//! `/graphql` serves a schema covering the users, the transactions, with the
+//! filters and pages of the search, the information of the site, and
+//! mutations for the money operations, so that a frontend or an integration
+//! fetches exactly the data it needs in a single round trip:
+//!
+//! ```graphql
+//! {
+//!   site { siteId lamport }
+//!   user(name: "alice") {
+//!     balance
+//!     transactions(pageSize: 5) { total transactions { toUser amount } }
+//!   }
+//! }
+//! ```
+//!
+//! The schema is explored with GraphiQL by opening `/graphql` in a browser.
+//! API tokens are accepted, with the same rights as on the other endpoints: a
+//! token transacting as a user only reads and operates on the account of its
+//! user, and the mutations are refused to read-only and admin tokens.
+
+#![cfg(feature = "server")]
+
+use crate::api_tokens::{Access, TokenScope};
+use async_graphql::{Context, EmptySubscription, InputObject, Object, Schema, SimpleObject};
+
+/// Depth above which a query is refused
+const MAX_QUERY_DEPTH: usize = 8;
+
+/// Schema of the GraphQL API
+pub type PeilluteSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+lazy_static::lazy_static! {
+    /// Schema served on `/graphql`
+    static ref SCHEMA: PeilluteSchema = schema();
+}
+
+/// Builds the schema of the GraphQL API
+pub fn schema() -> PeilluteSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+/// Checks that the token of the request, if any, may read or operate on the
+/// account of a user
+fn authorize(ctx: &Context<'_>, user: &str) -> async_graphql::Result<()> {
+    match ctx.data_opt::<TokenScope>() {
+        Some(scope) => {
+            crate::api_tokens::authorize_user(scope, user).map_err(|e| e.to_string().into())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Checks that the token of the request, if any, may submit operations on the
+/// account of a user
+fn authorize_operation(ctx: &Context<'_>, user: &str) -> async_graphql::Result<()> {
+    if let Some(scope) = ctx.data_opt::<TokenScope>()
+        && !crate::api_tokens::allows(scope, Access::Transact)
+    {
+        return Err(format!("A {} token cannot submit operations", scope).into());
+    }
+    authorize(ctx, user)
+}
+
+/// Checks an amount against the limits of the site, rounding it to the cent
+fn check_amount(amount: f64) -> async_graphql::Result<f64> {
+    crate::amount::check(amount).map_err(Into::into)
+}
+
+/// Submits a critical command, returning its ticket
+async fn submit(cmd: crate::control::CriticalCommands) -> async_graphql::Result<u64> {
+    crate::control::enqueue_critical(cmd)
+        .await
+        .map_err(|e| e.to_string().into())
+}
+
+/// User of the site
+pub struct User {
+    /// Unique name of the user
+    name: String,
+}
+
+#[Object]
+impl User {
+    /// Unique name of the user
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Ledger of the user
+    async fn ledger(&self) -> async_graphql::Result<String> {
+        Ok(crate::ledger::ledger_of(&self.name)?)
+    }
+
+    /// False once the user is archived
+    async fn active(&self) -> async_graphql::Result<bool> {
+        Ok(crate::db::is_user_active(&self.name)?)
+    }
+
+    /// Current balance of the user
+    async fn balance(&self, ctx: &Context<'_>) -> async_graphql::Result<f64> {
+        authorize(ctx, &self.name)?;
+        Ok(crate::db::calculate_solde(&self.name)?)
+    }
+
+    /// Transactions of the user, most recent first
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] page: usize,
+        #[graphql(default)] page_size: usize,
+    ) -> async_graphql::Result<TransactionPage> {
+        authorize(ctx, &self.name)?;
+        let query = crate::db::TransactionQuery {
+            user: Some(self.name.clone()),
+            page,
+            page_size,
+            ..Default::default()
+        };
+        Ok(crate::db::search_transactions(&query)?.into())
+    }
+}
+
+/// Transaction recorded by the site
+#[derive(SimpleObject)]
+pub struct Transaction {
+    /// Source user of the transaction
+    from_user: String,
+    /// Destination user of the transaction
+    to_user: String,
+    /// Transaction amount
+    amount: f64,
+    /// Lamport timestamp of the transaction
+    lamport_time: i64,
+    /// ID of the node that created the transaction
+    source_node: String,
+    /// Optional message associated with the transaction
+    message: Option<String>,
+}
+
+impl From<crate::db::Transaction> for Transaction {
+    fn from(tx: crate::db::Transaction) -> Self {
+        Self {
+            from_user: tx.from_user,
+            to_user: tx.to_user,
+            amount: tx.amount,
+            lamport_time: tx.lamport_time,
+            source_node: tx.source_node,
+            message: tx.optional_msg,
+        }
+    }
+}
+
+/// Page of transactions
+#[derive(SimpleObject)]
+pub struct TransactionPage {
+    /// Transactions of the page, most recent first
+    transactions: Vec<Transaction>,
+    /// Number of transactions matching the filters
+    total: usize,
+    /// Page returned, starting at 0
+    page: usize,
+    /// Number of transactions per page
+    page_size: usize,
+}
+
+impl From<crate::db::TransactionPage> for TransactionPage {
+    fn from(page: crate::db::TransactionPage) -> Self {
+        Self {
+            transactions: page.transactions.into_iter().map(Into::into).collect(),
+            total: page.total,
+            page: page.page,
+            page_size: page.page_size,
+        }
+    }
+}
+
+/// Filters of a transaction search, every filter is optional
+#[derive(InputObject, Default)]
+pub struct TransactionFilter {
+    /// User on either side of the transaction
+    user: Option<String>,
+    /// Minimum amount, inclusive
+    min_amount: Option<f64>,
+    /// Maximum amount, inclusive
+    max_amount: Option<f64>,
+    /// Substring of the transaction message
+    message: Option<String>,
+    /// ID of the node that created the transaction
+    node: Option<String>,
+    /// Minimum Lamport time, inclusive
+    min_lamport: Option<i64>,
+    /// Maximum Lamport time, inclusive
+    max_lamport: Option<i64>,
+}
+
+/// Entry of a vector clock
+#[derive(SimpleObject)]
+pub struct ClockEntry {
+    /// ID of the site
+    site_id: String,
+    /// Number of events of the site known
+    value: i64,
+}
+
+/// Information of the site answering the query
+#[derive(SimpleObject)]
+pub struct Site {
+    /// Unique identifier of the site
+    site_id: String,
+    /// Human-friendly name of the site, if any
+    alias: Option<String>,
+    /// Address the site listens on for its peers
+    address: String,
+    /// Lamport time of the site
+    lamport: i64,
+    /// Vector clock of the site
+    vector_clock: Vec<ClockEntry>,
+    /// Addresses of the neighbours connected
+    connected_peers: Vec<String>,
+}
+
+/// Queries of the GraphQL API
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Users of a ledger, or of every ledger, active unless `archived` is set
+    async fn users(
+        &self,
+        ledger: Option<String>,
+        #[graphql(default)] archived: bool,
+    ) -> async_graphql::Result<Vec<User>> {
+        let ledgers = match ledger {
+            Some(ledger) => vec![ledger],
+            None => crate::ledger::get_ledgers()?,
+        };
+        let mut users = Vec::new();
+        for ledger in ledgers {
+            for name in crate::ledger::get_users(&ledger, !archived)? {
+                users.push(User { name });
+            }
+        }
+        Ok(users)
+    }
+
+    /// User of a name or alias, null if it does not exist
+    async fn user(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<Option<User>> {
+        let name = crate::db::resolve_user(&name)?;
+        authorize(ctx, &name)?;
+        if !crate::db::user_exists(&name)? {
+            return Ok(None);
+        }
+        Ok(Some(User { name }))
+    }
+
+    /// Transactions matching the filters, most recent first
+    ///
+    /// A token transacting as a user must filter on its user.
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: TransactionFilter,
+        #[graphql(default)] page: usize,
+        #[graphql(default)] page_size: usize,
+    ) -> async_graphql::Result<TransactionPage> {
+        match (&filter.user, ctx.data_opt::<TokenScope>()) {
+            (Some(user), _) => authorize(ctx, user)?,
+            (None, Some(TokenScope::TransactAs(_))) => {
+                return Err("A token transacting as a user must filter on its user".into());
+            }
+            (None, _) => {}
+        }
+        let query = crate::db::TransactionQuery {
+            user: filter.user,
+            min_amount: filter.min_amount,
+            max_amount: filter.max_amount,
+            message: filter.message,
+            node: filter.node,
+            min_lamport: filter.min_lamport,
+            max_lamport: filter.max_lamport,
+            page,
+            page_size,
+        };
+        Ok(crate::db::search_transactions(&query)?.into())
+    }
+
+    /// Information of the site
+    async fn site(&self) -> Site {
+        let (site_id, alias, address) = {
+            let site = crate::state::SITE_INFO.read().await;
+            (
+                site.get_site_id(),
+                site.get_site_alias(),
+                site.get_site_addr_as_string(),
+            )
+        };
+        let clock = crate::state::get_clock().await;
+        let mut vector_clock: Vec<ClockEntry> = clock
+            .get_vector_clock_map()
+            .iter()
+            .map(|(site_id, value)| ClockEntry {
+                site_id: site_id.clone(),
+                value: *value,
+            })
+            .collect();
+        vector_clock.sort_by(|a, b| a.site_id.cmp(&b.site_id));
+        Site {
+            site_id,
+            alias,
+            address,
+            lamport: *clock.get_lamport(),
+            vector_clock,
+            connected_peers: crate::state::PEERS
+                .read()
+                .await
+                .get_connected_nei_addr_string(),
+        }
+    }
+}
+
+/// Mutations of the GraphQL API, each returning the ticket of its operation
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Deposits money on the account of a user
+    async fn deposit(
+        &self,
+        ctx: &Context<'_>,
+        user: String,
+        amount: f64,
+    ) -> async_graphql::Result<u64> {
+        authorize_operation(ctx, &user)?;
+        let amount = check_amount(amount)?;
+        submit(crate::control::CriticalCommands::Deposit { name: user, amount }).await
+    }
+
+    /// Withdraws money from the account of a user
+    async fn withdraw(
+        &self,
+        ctx: &Context<'_>,
+        user: String,
+        amount: f64,
+    ) -> async_graphql::Result<u64> {
+        authorize_operation(ctx, &user)?;
+        let amount = check_amount(amount)?;
+        submit(crate::control::CriticalCommands::Withdraw { name: user, amount }).await
+    }
+
+    /// Makes a payment, with a tip in percent, split with other users if any
+    async fn pay(
+        &self,
+        ctx: &Context<'_>,
+        user: String,
+        amount: f64,
+        #[graphql(default)] tip_percent: f64,
+        #[graphql(default)] split_with: Vec<String>,
+    ) -> async_graphql::Result<u64> {
+        authorize_operation(ctx, &user)?;
+        let amount = check_amount(amount)?;
+        if !tip_percent.is_finite() || tip_percent < 0.0 {
+            return Err("Tip cannot be negative.".into());
+        }
+        submit(crate::control::CriticalCommands::Pay {
+            name: user,
+            amount,
+            tip_percent,
+            split_with,
+        })
+        .await
+    }
+
+    /// Transfers money between two users
+    async fn transfer(
+        &self,
+        ctx: &Context<'_>,
+        from_user: String,
+        to_user: String,
+        amount: f64,
+    ) -> async_graphql::Result<u64> {
+        authorize_operation(ctx, &from_user)?;
+        let amount = check_amount(amount)?;
+        submit(crate::control::CriticalCommands::Transfer {
+            from: from_user,
+            to: to_user,
+            amount,
+        })
+        .await
+    }
+}
+
+/// Executes a GraphQL request, with the scope of its API token if any
+pub async fn handler(
+    scope: Option<axum::Extension<TokenScope>>,
+    axum::Json(request): axum::Json<async_graphql::Request>,
+) -> axum::Json<async_graphql::Response> {
+    let request = match scope {
+        Some(axum::Extension(scope)) => request.data(scope),
+        None => request,
+    };
+    axum::Json(SCHEMA.execute(request).await)
+}
+
+/// Serves GraphiQL, to explore the schema from a browser
+pub async fn graphiql() -> axum::response::Html<String> {
+    axum::response::Html(
+        async_graphql::http::GraphiQLSource::build()
+            .endpoint("/graphql")
+            .finish(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema() {
+        let sdl = schema().sdl();
+        for field in ["users(", "transactions(", "site:", "deposit(", "transfer("] {
+            assert!(sdl.contains(field), "missing {} in the schema", field);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mutation_scopes() {
+        let mutation = r#"mutation { deposit(user: "alice", amount: 5.0) }"#;
+        for scope in [TokenScope::ReadOnly, TokenScope::Admin] {
+            let response = schema()
+                .execute(async_graphql::Request::new(mutation).data(scope))
+                .await;
+            assert_eq!(response.errors.len(), 1);
+        }
+
+        let response = schema()
+            .execute(r#"mutation { withdraw(user: "alice", amount: -1.0) }"#)
+            .await;
+        assert_eq!(response.errors[0].message, "Amount cannot be negative.");
+    }
+}
@@ -0,0 +1,465 @@
+//! gRPC transport between the sites
+//!
+//! Alternative to the raw TCP transport of [`crate::network`], selected with
+//! `--transport grpc`. The service is generated from `proto/peillute.proto`
+//! and has one RPC per family of [`NetworkMessageCode`]. Every call has a
+//! deadline and is retried a few times before the peer is considered
+//! unreachable.
+//!
+//! Received messages are queued per sending site and handled in order by
+//! [`crate::network::process_message`], as they are with the TCP transport.
+
+#![cfg(feature = "server")]
+
+use crate::message::{Message, MessageInfo, NetworkMessageCode};
+
+/// Code generated from `proto/peillute.proto`
+pub mod proto {
+    tonic::include_proto!("peillute");
+}
+
+use proto::peer_client::PeerClient;
+use proto::peer_server::{Peer, PeerServer};
+use proto::{Ack, MessageCode, PeerMessage};
+
+/// Maximum time to connect to a peer and deliver a message to it
+const RPC_DEADLINE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Number of attempts to deliver a message before giving up
+const RPC_ATTEMPTS: u32 = 3;
+
+/// Number of messages of a peer waiting to be handled
+const INBOX_SIZE: usize = 1024;
+
+lazy_static::lazy_static! {
+    /// Clients of the peers, by address
+    static ref CLIENTS: tokio::sync::Mutex<
+        std::collections::HashMap<std::net::SocketAddr, PeerClient<tonic::transport::Channel>>,
+    > = tokio::sync::Mutex::new(std::collections::HashMap::new());
+    /// Queues of the messages received from each peer, by address
+    static ref INBOXES: tokio::sync::Mutex<
+        std::collections::HashMap<std::net::SocketAddr, tokio::sync::mpsc::Sender<Message>>,
+    > = tokio::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+/// RPC of the service carrying a type of message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rpc {
+    Transaction,
+    Mutex,
+    Snapshot,
+    Membership,
+}
+
+/// Returns the RPC carrying a type of message
+fn rpc_for(code: &NetworkMessageCode) -> Rpc {
+    match code {
+        NetworkMessageCode::Transaction
+        | NetworkMessageCode::TransactionAcknowledgement
+        | NetworkMessageCode::ForwardCommand
+        | NetworkMessageCode::ForwardResult => Rpc::Transaction,
+        NetworkMessageCode::AcquireMutex
+        | NetworkMessageCode::ReleaseGlobalMutex
+        | NetworkMessageCode::AckGlobalMutex
+        | NetworkMessageCode::AckReleaseGlobalMutex
+        | NetworkMessageCode::ForceReleaseMutex
+        | NetworkMessageCode::AckForceReleaseMutex => Rpc::Mutex,
+        NetworkMessageCode::SnapshotRequest
+        | NetworkMessageCode::SnapshotResponse
+        | NetworkMessageCode::ConsistencyRequest
+        | NetworkMessageCode::ConsistencyResponse
+        | NetworkMessageCode::ClusterStatsRequest
+        | NetworkMessageCode::ClusterStatsResponse => Rpc::Snapshot,
+        NetworkMessageCode::Discovery
+        | NetworkMessageCode::Acknowledgment
+        | NetworkMessageCode::Error
+        | NetworkMessageCode::Disconnect
+        | NetworkMessageCode::Heartbeat
+        | NetworkMessageCode::HeartbeatAck => Rpc::Membership,
+    }
+}
+
+/// Returns the code of the service matching a type of message
+fn proto_code(code: &NetworkMessageCode) -> MessageCode {
+    match code {
+        NetworkMessageCode::Discovery => MessageCode::Discovery,
+        NetworkMessageCode::Transaction => MessageCode::Transaction,
+        NetworkMessageCode::TransactionAcknowledgement => MessageCode::TransactionAcknowledgement,
+        NetworkMessageCode::Acknowledgment => MessageCode::Acknowledgment,
+        NetworkMessageCode::Error => MessageCode::Error,
+        NetworkMessageCode::Disconnect => MessageCode::Disconnect,
+        NetworkMessageCode::SnapshotRequest => MessageCode::SnapshotRequest,
+        NetworkMessageCode::SnapshotResponse => MessageCode::SnapshotResponse,
+        NetworkMessageCode::AcquireMutex => MessageCode::AcquireMutex,
+        NetworkMessageCode::ReleaseGlobalMutex => MessageCode::ReleaseGlobalMutex,
+        NetworkMessageCode::AckGlobalMutex => MessageCode::AckGlobalMutex,
+        NetworkMessageCode::AckReleaseGlobalMutex => MessageCode::AckReleaseGlobalMutex,
+        NetworkMessageCode::ConsistencyRequest => MessageCode::ConsistencyRequest,
+        NetworkMessageCode::ConsistencyResponse => MessageCode::ConsistencyResponse,
+        NetworkMessageCode::ForceReleaseMutex => MessageCode::ForceReleaseMutex,
+        NetworkMessageCode::AckForceReleaseMutex => MessageCode::AckForceReleaseMutex,
+        NetworkMessageCode::ForwardCommand => MessageCode::ForwardCommand,
+        NetworkMessageCode::ForwardResult => MessageCode::ForwardResult,
+        NetworkMessageCode::Heartbeat => MessageCode::Heartbeat,
+        NetworkMessageCode::HeartbeatAck => MessageCode::HeartbeatAck,
+        NetworkMessageCode::ClusterStatsRequest => MessageCode::ClusterStatsRequest,
+        NetworkMessageCode::ClusterStatsResponse => MessageCode::ClusterStatsResponse,
+    }
+}
+
+/// Wraps a message in the envelope of the service
+fn to_peer_message(
+    message: &Message,
+    version: u8,
+    format: crate::codec::WireFormat,
+) -> Result<PeerMessage, String> {
+    Ok(PeerMessage {
+        code: proto_code(&message.code) as i32,
+        sender_id: message.sender_id.clone(),
+        sender_addr: message.sender_addr.to_string(),
+        initiator_id: message.message_initiator_id.clone(),
+        initiator_addr: message.message_initiator_addr.to_string(),
+        lamport: *message.clock.get_lamport(),
+        vector_clock: message.clock.get_vector_clock_map().clone(),
+        payload: format.encode(message)?,
+        protocol_version: version as u32,
+        format: format.to_byte() as u32,
+    })
+}
+
+/// Extracts the message from the envelope of the service
+#[allow(clippy::result_large_err)]
+fn from_peer_message(peer_message: PeerMessage) -> Result<Message, tonic::Status> {
+    let version = u8::try_from(peer_message.protocol_version).unwrap_or(u8::MAX);
+    if !crate::codec::is_supported(version) {
+        return Err(tonic::Status::failed_precondition(format!(
+            "unsupported protocol version {}, supported versions are {:?}",
+            peer_message.protocol_version,
+            crate::codec::PROTOCOL_VERSIONS
+        )));
+    }
+    let format = u8::try_from(peer_message.format)
+        .ok()
+        .and_then(crate::codec::WireFormat::from_byte)
+        .ok_or_else(|| {
+            tonic::Status::invalid_argument(format!("unknown format {}", peer_message.format))
+        })?;
+    let message: Message = format
+        .decode(&peer_message.payload)
+        .map_err(|e| tonic::Status::invalid_argument(format!("cannot decode the message: {e}")))?;
+    if proto_code(&message.code) as i32 != peer_message.code {
+        return Err(tonic::Status::invalid_argument(
+            "the code of the envelope does not match the message",
+        ));
+    }
+    Ok(message)
+}
+
+/// Spawns the task handling the messages received from a peer, in order
+fn spawn_inbox(sender_addr: std::net::SocketAddr) -> tokio::sync::mpsc::Sender<Message> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(INBOX_SIZE);
+    tokio::spawn(async move {
+        let mut chunks = crate::message::SnapshotChunkAssembler::default();
+        while let Some(mut message) = rx.recv().await {
+            // A large snapshot response is received in several chunks
+            if let MessageInfo::SnapshotChunk(chunk) = message.info {
+                match chunks.push(message.sender_addr, chunk) {
+                    Some(response) => message.info = MessageInfo::SnapshotResponse(response),
+                    None => continue,
+                }
+            }
+            if let MessageInfo::CompressedSnapshot(compressed) = &message.info {
+                match compressed.decompress() {
+                    Ok(response) => message.info = MessageInfo::SnapshotResponse(response),
+                    Err(e) => {
+                        log::error!("Error decompressing a snapshot response: {}", e);
+                        continue;
+                    }
+                }
+            }
+            if let Err(e) = crate::network::process_message(message, sender_addr).await {
+                log::error!("Error handling a message from {}: {}", sender_addr, e);
+            }
+        }
+    });
+    tx
+}
+
+/// Queues a received message for the protocol
+async fn deliver(message: Message) -> Result<(), tonic::Status> {
+    let sender_addr = message.sender_addr;
+    let inbox = {
+        let mut inboxes = INBOXES.lock().await;
+        let inbox = inboxes
+            .entry(sender_addr)
+            .or_insert_with(|| spawn_inbox(sender_addr));
+        if inbox.is_closed() {
+            *inbox = spawn_inbox(sender_addr);
+        }
+        inbox.clone()
+    };
+    inbox
+        .send(message)
+        .await
+        .map_err(|_| tonic::Status::unavailable("the site is shutting down"))
+}
+
+/// Implementation of the peer service
+struct PeerService;
+
+impl PeerService {
+    /// Checks that a message is carried by the right RPC and queues it
+    async fn receive(
+        &self,
+        request: tonic::Request<PeerMessage>,
+        rpc: Rpc,
+    ) -> Result<tonic::Response<Ack>, tonic::Status> {
+        let remote_addr = request.remote_addr();
+        let proof = request
+            .metadata()
+            .get(crate::peer_auth::PROOF_METADATA)
+            .and_then(|proof| proof.to_str().ok())
+            .map(str::to_string);
+        let peer_message = request.into_inner();
+        crate::peer_auth::check_request(proof.as_deref(), &peer_message.payload).map_err(|e| {
+            log::warn!("Refused a gRPC message from {:?}: {}", remote_addr, e);
+            tonic::Status::unauthenticated(e)
+        })?;
+        let size = peer_message.payload.len();
+        let message = from_peer_message(peer_message)?;
+        if rpc_for(&message.code) != rpc {
+            return Err(tonic::Status::invalid_argument(format!(
+                "{:?} messages are not accepted by the {:?} RPC",
+                message.code, rpc
+            )));
+        }
+        crate::peer_auth::admit(&message, remote_addr.unwrap_or(message.sender_addr)).map_err(
+            |e| {
+                log::warn!("Refused a gRPC message from {:?}: {}", remote_addr, e);
+                tonic::Status::permission_denied(e)
+            },
+        )?;
+        log::debug!(
+            "Received {:?} from {} over gRPC",
+            message.code,
+            message.sender_addr
+        );
+        crate::network::NETWORK_MANAGER
+            .lock()
+            .await
+            .record_received(message.sender_addr, size);
+        deliver(message).await?;
+        Ok(tonic::Response::new(Ack {}))
+    }
+}
+
+#[tonic::async_trait]
+impl Peer for PeerService {
+    async fn transaction(
+        &self,
+        request: tonic::Request<PeerMessage>,
+    ) -> Result<tonic::Response<Ack>, tonic::Status> {
+        self.receive(request, Rpc::Transaction).await
+    }
+
+    async fn mutex(
+        &self,
+        request: tonic::Request<PeerMessage>,
+    ) -> Result<tonic::Response<Ack>, tonic::Status> {
+        self.receive(request, Rpc::Mutex).await
+    }
+
+    async fn snapshot(
+        &self,
+        request: tonic::Request<PeerMessage>,
+    ) -> Result<tonic::Response<Ack>, tonic::Status> {
+        self.receive(request, Rpc::Snapshot).await
+    }
+
+    async fn membership(
+        &self,
+        request: tonic::Request<PeerMessage>,
+    ) -> Result<tonic::Response<Ack>, tonic::Status> {
+        self.receive(request, Rpc::Membership).await
+    }
+}
+
+/// Serves the peer service on the listener of the site
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)?;
+    // the envelope repeats the clock of the message next to its payload
+    let service = PeerServer::new(PeerService)
+        .max_decoding_message_size(2 * crate::network::max_message_size());
+    tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_incoming(incoming)
+        .await?;
+    Ok(())
+}
+
+/// Returns the client of a peer, the connection is established on first use
+async fn client(
+    addr: std::net::SocketAddr,
+) -> Result<PeerClient<tonic::transport::Channel>, Box<dyn std::error::Error>> {
+    let mut clients = CLIENTS.lock().await;
+    if let Some(client) = clients.get(&addr) {
+        return Ok(client.clone());
+    }
+    let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))?
+        .connect_timeout(RPC_DEADLINE)
+        .connect_lazy();
+    let client = PeerClient::new(channel);
+    clients.insert(addr, client.clone());
+    Ok(client)
+}
+
+/// Calls the RPC carrying a message, with a deadline
+async fn call(
+    client: &mut PeerClient<tonic::transport::Channel>,
+    rpc: Rpc,
+    message: PeerMessage,
+) -> Result<tonic::Response<Ack>, tonic::Status> {
+    // every attempt carries a fresh proof, the receiver refusing replayed ones
+    let proof = crate::peer_auth::request_proof(&message.payload);
+    let mut request = tonic::Request::new(message);
+    request.set_timeout(RPC_DEADLINE);
+    if let Some(proof) = proof {
+        let proof = proof
+            .parse()
+            .map_err(|_| tonic::Status::internal("invalid proof of the request"))?;
+        request
+            .metadata_mut()
+            .insert(crate::peer_auth::PROOF_METADATA, proof);
+    }
+    match rpc {
+        Rpc::Transaction => client.transaction(request).await,
+        Rpc::Mutex => client.mutex(request).await,
+        Rpc::Snapshot => client.snapshot(request).await,
+        Rpc::Membership => client.membership(request).await,
+    }
+}
+
+/// Sends a message to a peer through its gRPC service
+///
+/// Calls that fail because the peer is unreachable or too slow are retried.
+/// The messages of the protocol are idempotent on the receiving side, so a
+/// message delivered twice after a lost answer is harmless.
+pub async fn send_message(
+    recipient_address: std::net::SocketAddr,
+    message: &Message,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let version = crate::network::NETWORK_MANAGER
+        .lock()
+        .await
+        .protocol_version(&recipient_address);
+    let peer_message = to_peer_message(message, version, crate::codec::wire_format())?;
+    if peer_message.payload.len() > crate::network::max_message_size() {
+        return Err(format!(
+            "cannot send to {}: message of {} bytes exceeds the limit of {} bytes",
+            recipient_address,
+            peer_message.payload.len(),
+            crate::network::max_message_size()
+        )
+        .into());
+    }
+
+    let rpc = rpc_for(&message.code);
+    let mut attempt = 1;
+    loop {
+        let mut peer = client(recipient_address).await?;
+        match call(&mut peer, rpc, peer_message.clone()).await {
+            Ok(_) => {
+                crate::network::NETWORK_MANAGER
+                    .lock()
+                    .await
+                    .record_sent(recipient_address, peer_message.payload.len());
+                log::debug!("Sent message {:?} to {}", message, recipient_address);
+                return Ok(());
+            }
+            Err(status)
+                if attempt < RPC_ATTEMPTS
+                    && matches!(
+                        status.code(),
+                        tonic::Code::Unavailable | tonic::Code::DeadlineExceeded
+                    ) =>
+            {
+                let delay = crate::network::backoff_delay(attempt, attempt as u64);
+                log::debug!(
+                    "Attempt {} to send to {} failed ({}), retrying in {:?}",
+                    attempt,
+                    recipient_address,
+                    status.message(),
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(status) => {
+                CLIENTS.lock().await.remove(&recipient_address);
+                return Err(format!(
+                    "Impossible to send msg to {} due to error : {}",
+                    recipient_address,
+                    status.message()
+                )
+                .into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::WireFormat;
+
+    fn message(code: NetworkMessageCode) -> Message {
+        let addr: std::net::SocketAddr = "127.0.0.1:10000".parse().unwrap();
+        Message {
+            sender_id: "A".to_string(),
+            message_initiator_id: "A".to_string(),
+            message_initiator_addr: addr,
+            sender_addr: addr,
+            clock: crate::clock::Clock::new(),
+            command: None,
+            info: MessageInfo::None,
+            code,
+            trace: None,
+        }
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        for format in [WireFormat::Msgpack, WireFormat::Json] {
+            let peer_message =
+                to_peer_message(&message(NetworkMessageCode::AcquireMutex), 1, format).unwrap();
+            assert_eq!(peer_message.code(), MessageCode::AcquireMutex);
+            assert_eq!(peer_message.sender_addr, "127.0.0.1:10000");
+
+            let decoded = from_peer_message(peer_message).unwrap();
+            assert_eq!(decoded.code, NetworkMessageCode::AcquireMutex);
+            assert_eq!(rpc_for(&decoded.code), Rpc::Mutex);
+        }
+    }
+
+    #[test]
+    fn envelope_version_must_be_supported() {
+        let mut peer_message =
+            to_peer_message(&message(NetworkMessageCode::Discovery), 1, WireFormat::Json).unwrap();
+        peer_message.protocol_version = *crate::codec::PROTOCOL_VERSIONS.end() as u32 + 1;
+        assert!(from_peer_message(peer_message).is_err());
+    }
+
+    #[test]
+    fn envelope_code_must_match() {
+        let mut peer_message = to_peer_message(
+            &message(NetworkMessageCode::Discovery),
+            1,
+            WireFormat::Msgpack,
+        )
+        .unwrap();
+        peer_message.set_code(MessageCode::Transaction);
+        assert!(from_peer_message(peer_message).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Round-trip times of the neighbours, measured with heartbeats
+//!
+//! Every [`HEARTBEAT_INTERVAL`], the site sends a heartbeat to each of its
+//! connected neighbours, carrying the time it was sent and the site IDs of
+//! the neighbours of the site. The neighbour echoes the time in its reply,
+//! with its own neighbours: the site measures the round-trip time (RTT) of
+//! the neighbour, smoothed over the last heartbeats, and learns which sites
+//! the neighbour is connected to.
+//!
+//! The wave diffusion sends to the nearest neighbours first. With
+//! `--slow-neighbour-rtt`, a site relaying a wave also skips the neighbours
+//! slower than the threshold that are neighbours of the initiator of the
+//! wave: the initiator sends them the message itself, and the copy relayed
+//! by the site would only come back as an empty acknowledgement. The site
+//! acknowledges them on their behalf, as for the unreachable neighbours.
+
+#![cfg(feature = "server")]
+
+/// Interval between two heartbeats sent to each neighbour
+pub const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Weight of a new measure in the smoothed round-trip time, as in TCP
+const RTT_WEIGHT: f64 = 0.125;
+
+/// Round-trip times and neighbours of the neighbours of the site
+#[derive(Debug, Default)]
+pub struct Latencies {
+    /// Smoothed round-trip time of each neighbour, by address
+    rtts: std::collections::HashMap<std::net::SocketAddr, std::time::Duration>,
+    /// Site IDs of the neighbours of each neighbour, by address
+    neighbours_of:
+        std::collections::HashMap<std::net::SocketAddr, std::collections::HashSet<String>>,
+}
+
+impl Latencies {
+    /// Records a round-trip time measured for a neighbour
+    pub fn record(&mut self, addr: std::net::SocketAddr, sample: std::time::Duration) {
+        let rtt = match self.rtts.get(&addr) {
+            Some(rtt) => rtt.mul_f64(1.0 - RTT_WEIGHT) + sample.mul_f64(RTT_WEIGHT),
+            None => sample,
+        };
+        self.rtts.insert(addr, rtt);
+    }
+
+    /// Returns the smoothed round-trip time of a neighbour, if measured
+    pub fn rtt(&self, addr: &std::net::SocketAddr) -> Option<std::time::Duration> {
+        self.rtts.get(addr).copied()
+    }
+
+    /// Records the site IDs of the neighbours of a neighbour
+    pub fn set_neighbours(&mut self, addr: std::net::SocketAddr, neighbours: Vec<String>) {
+        self.neighbours_of
+            .insert(addr, neighbours.into_iter().collect());
+    }
+
+    /// Forgets the neighbours that are no longer connected
+    pub fn retain(&mut self, connected: &[std::net::SocketAddr]) {
+        self.rtts.retain(|addr, _| connected.contains(addr));
+        self.neighbours_of
+            .retain(|addr, _| connected.contains(addr));
+    }
+
+    /// Orders neighbours by ascending round-trip time, those not measured yet last
+    pub fn sort_by_latency(&self, addrs: &mut [std::net::SocketAddr]) {
+        addrs.sort_by_key(|addr| self.rtt(addr).unwrap_or(std::time::Duration::MAX));
+    }
+
+    /// Returns true if a wave of an initiator can skip a neighbour slower than a threshold
+    ///
+    /// The neighbour is skipped only if it is the initiator or one of its
+    /// neighbours, which receive the message from the initiator anyway.
+    pub fn can_skip(
+        &self,
+        addr: &std::net::SocketAddr,
+        initiator_id: &str,
+        initiator_addr: &std::net::SocketAddr,
+        threshold: std::time::Duration,
+    ) -> bool {
+        self.rtt(addr).is_some_and(|rtt| rtt > threshold)
+            && (addr == initiator_addr
+                || self
+                    .neighbours_of
+                    .get(addr)
+                    .is_some_and(|neighbours| neighbours.contains(initiator_id)))
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Round-trip times of the neighbours of the site
+    pub static ref LATENCIES: std::sync::Mutex<Latencies> =
+        std::sync::Mutex::new(Latencies::default());
+}
+
+/// Round-trip time above which a neighbour may be skipped by the waves, in milliseconds (0 to disable)
+static SLOW_NEIGHBOUR_RTT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Sets the round-trip time above which a neighbour may be skipped by the waves, in milliseconds
+pub fn set_slow_neighbour_rtt(millis: u64) {
+    SLOW_NEIGHBOUR_RTT.store(millis, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Returns the round-trip time above which a neighbour may be skipped by the waves, if set
+pub fn slow_neighbour_rtt() -> Option<std::time::Duration> {
+    match SLOW_NEIGHBOUR_RTT.load(std::sync::atomic::Ordering::Relaxed) {
+        0 => None,
+        millis => Some(std::time::Duration::from_millis(millis)),
+    }
+}
+
+/// Returns the current time in microseconds since the Unix epoch
+fn now_micros() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Returns the site IDs of the connected neighbours of the site
+async fn neighbour_ids() -> Vec<String> {
+    let peers = crate::state::PEERS.read().await;
+    peers
+        .get_connected_nei_addr()
+        .iter()
+        .filter_map(|addr| peers.site_id_of(addr).map(str::to_string))
+        .collect()
+}
+
+/// Spawns the task periodically sending a heartbeat to each neighbour
+pub fn heartbeat_worker() {
+    tokio::spawn(async {
+        use crate::message::{HeartbeatPayload, MessageInfo, NetworkMessageCode};
+
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let connected = crate::state::PEERS.read().await.get_connected_nei_addr();
+            LATENCIES.lock().unwrap().retain(&connected);
+            if connected.is_empty() {
+                continue;
+            }
+            let (site_id, local_addr) = crate::state::local_site().await;
+            let neighbours = neighbour_ids().await;
+            // heartbeats are no event of the site, they carry its clock as is
+            let clock = crate::state::get_clock().await;
+            for addr in connected {
+                let payload = HeartbeatPayload {
+                    sent_at: now_micros(),
+                    neighbours: neighbours.clone(),
+                };
+                let site_id = site_id.clone();
+                let clock = clock.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = crate::network::send_message(
+                        addr,
+                        MessageInfo::Heartbeat(payload),
+                        None,
+                        NetworkMessageCode::Heartbeat,
+                        local_addr,
+                        &site_id,
+                        &site_id,
+                        local_addr,
+                        clock,
+                    )
+                    .await
+                    {
+                        log::debug!("Cannot send a heartbeat to {}: {}", addr, e);
+                    }
+                });
+            }
+        }
+    });
+}
+
+/// Replies to the heartbeat of a neighbour
+pub async fn on_heartbeat(
+    message: &crate::message::Message,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{HeartbeatPayload, MessageInfo, NetworkMessageCode};
+
+    let MessageInfo::Heartbeat(payload) = &message.info else {
+        return Ok(());
+    };
+    LATENCIES
+        .lock()
+        .unwrap()
+        .set_neighbours(message.sender_addr, payload.neighbours.clone());
+    let (site_id, local_addr) = crate::state::local_site().await;
+    crate::network::send_message(
+        message.sender_addr,
+        MessageInfo::Heartbeat(HeartbeatPayload {
+            sent_at: payload.sent_at,
+            neighbours: neighbour_ids().await,
+        }),
+        None,
+        NetworkMessageCode::HeartbeatAck,
+        local_addr,
+        &site_id,
+        &site_id,
+        local_addr,
+        crate::state::get_clock().await,
+    )
+    .await
+}
+
+/// Records the round-trip time measured by the reply to a heartbeat
+pub fn on_heartbeat_ack(message: &crate::message::Message) {
+    if let crate::message::MessageInfo::Heartbeat(payload) = &message.info {
+        let sample = std::time::Duration::from_micros(now_micros().saturating_sub(payload.sent_at));
+        let mut latencies = LATENCIES.lock().unwrap();
+        latencies.record(message.sender_addr, sample);
+        latencies.set_neighbours(message.sender_addr, payload.neighbours.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latencies() {
+        let near: std::net::SocketAddr = "127.0.0.1:10001".parse().unwrap();
+        let far: std::net::SocketAddr = "127.0.0.1:10002".parse().unwrap();
+        let unknown: std::net::SocketAddr = "127.0.0.1:10003".parse().unwrap();
+        let ms = std::time::Duration::from_millis;
+
+        let mut latencies = Latencies::default();
+        latencies.record(near, ms(80));
+        assert_eq!(latencies.rtt(&near), Some(ms(80)));
+        latencies.record(near, ms(0));
+        assert_eq!(latencies.rtt(&near), Some(ms(70)));
+        latencies.record(far, ms(300));
+
+        let mut addrs = vec![unknown, far, near];
+        latencies.sort_by_latency(&mut addrs);
+        assert_eq!(addrs, vec![near, far, unknown]);
+
+        let initiator: std::net::SocketAddr = "127.0.0.1:10004".parse().unwrap();
+        assert!(!latencies.can_skip(&far, "D", &initiator, ms(200)));
+        latencies.set_neighbours(far, vec!["A".to_string(), "D".to_string()]);
+        assert!(latencies.can_skip(&far, "D", &initiator, ms(200)));
+        assert!(!latencies.can_skip(&far, "D", &initiator, ms(400)));
+        assert!(!latencies.can_skip(&far, "E", &initiator, ms(200)));
+        assert!(!latencies.can_skip(&unknown, "D", &unknown, ms(200)));
+
+        latencies.retain(&[near]);
+        assert_eq!(latencies.rtt(&far), None);
+    }
+}
@@ -0,0 +1,254 @@
+//! Bulk import of users with their opening balance
+//!
+//! An administrator uploads a CSV file with one user per line:
+//!
+//! ```text
+//! name,balance,ledger
+//! alice,50.00,
+//! bob,12.5,club-bar
+//! ```
+//!
+//! The header line is optional, the balance defaults to zero and the ledger to
+//! the default one. The rows are first validated with the rules of the seed
+//! files, then each valid row is submitted as a critical command, so that the
+//! users and their initial deposit are replicated to every site. The rows
+//! rejected by the validation or by the execution of their command are listed
+//! in a report, downloadable as a CSV file to fix and upload again.
+
+/// Row of an import that was not applied
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RejectedRow {
+    /// Line of the row in the uploaded file, starting at 1
+    pub line: usize,
+    /// Content of the row, as uploaded
+    pub content: String,
+    /// Reason of the rejection
+    pub reason: String,
+}
+
+/// Result of an import
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ImportReport {
+    /// Users created
+    pub imported: Vec<String>,
+    /// Rows not applied
+    pub rejected: Vec<RejectedRow>,
+}
+
+impl ImportReport {
+    /// Returns the rejected rows as a CSV file, with the reason of each rejection
+    pub fn error_csv(&self) -> String {
+        let mut csv = String::from("line,content,reason\n");
+        for row in &self.rejected {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                row.line,
+                escape(&row.content),
+                escape(&row.reason)
+            ));
+        }
+        csv
+    }
+}
+
+/// Quotes a CSV field holding a separator, a quote or a line break
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(feature = "server")]
+/// Time waited for the confirmation of a row before importing the next one
+const ROW_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[cfg(feature = "server")]
+/// User to create from a row of an import
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRow {
+    /// Line of the row in the uploaded file, starting at 1
+    pub line: usize,
+    /// Content of the row, as uploaded
+    pub content: String,
+    /// Unique name of the user
+    pub name: String,
+    /// Initial balance of the user
+    pub balance: f64,
+    /// Ledger of the user
+    pub ledger: String,
+}
+
+#[cfg(feature = "server")]
+/// Parses a row, returning the user to create or the reason of its rejection
+fn parse_row(line: usize, content: &str) -> Result<ImportRow, String> {
+    let fields: Vec<&str> = content.split(',').map(str::trim).collect();
+    if fields.len() > 3 {
+        return Err(format!("expected at most 3 fields, found {}", fields.len()));
+    }
+    let name = fields[0];
+    if name.is_empty() || name == crate::db::NULL || crate::db::is_system_account(name) {
+        return Err(format!("invalid user name '{}'", name));
+    }
+    let balance = match fields.get(1) {
+        None | Some(&"") => 0.0,
+        Some(balance) => balance
+            .parse::<f64>()
+            .map_err(|_| format!("invalid balance '{}'", balance))?,
+    };
+    if !balance.is_finite() || balance < 0.0 {
+        return Err(format!("initial balance of '{}' must be positive", name));
+    }
+    let ledger = match fields.get(2) {
+        None | Some(&"") => crate::ledger::DEFAULT_LEDGER.to_string(),
+        Some(ledger) => {
+            crate::ledger::validate_name(ledger)?;
+            ledger.to_string()
+        }
+    };
+    Ok(ImportRow {
+        line,
+        content: content.to_string(),
+        name: name.to_string(),
+        balance,
+        ledger,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Parses and validates the content of a CSV file
+///
+/// Returns the users to create, in order, and the rows rejected. Blank lines
+/// are skipped, and a name defined twice is only imported from its first row.
+pub fn parse(content: &str) -> (Vec<ImportRow>, Vec<RejectedRow>) {
+    let mut rows = Vec::new();
+    let mut rejected = Vec::new();
+    let mut names = std::collections::HashSet::new();
+    for (index, content) in content.lines().enumerate() {
+        let line = index + 1;
+        let content = content.trim();
+        if content.is_empty() || (line == 1 && content.starts_with("name,")) {
+            continue;
+        }
+        let reject = |reason: String| RejectedRow {
+            line,
+            content: content.to_string(),
+            reason,
+        };
+        match parse_row(line, content) {
+            Ok(row) if !names.insert(row.name.clone()) => {
+                rejected.push(reject(format!("user '{}' is defined twice", row.name)))
+            }
+            Ok(row) => rows.push(row),
+            Err(reason) => rejected.push(reject(reason)),
+        }
+    }
+    (rows, rejected)
+}
+
+#[cfg(feature = "server")]
+/// Imports the users of a CSV file with their opening balance
+///
+/// The rows are applied one at a time, each waiting for the confirmation of
+/// the previous one, so that a large file does not fill the queue of the
+/// global mutex.
+pub async fn import(content: &str) -> Result<ImportReport, Box<dyn std::error::Error>> {
+    use crate::confirmation::ConfirmationStatus;
+
+    let (rows, mut rejected) = parse(content);
+    let mut imported = Vec::new();
+    for row in rows {
+        let reject = |reason: String| RejectedRow {
+            line: row.line,
+            content: row.content.clone(),
+            reason,
+        };
+        if crate::db::user_exists(&row.name)? {
+            rejected.push(reject(format!("user '{}' already exists", row.name)));
+            continue;
+        }
+        let ticket =
+            match crate::control::enqueue_critical(crate::control::CriticalCommands::ImportUser {
+                name: row.name.clone(),
+                ledger: row.ledger.clone(),
+                solde: row.balance,
+            })
+            .await
+            {
+                Ok(ticket) => ticket,
+                Err(e) => {
+                    rejected.push(reject(e.to_string()));
+                    continue;
+                }
+            };
+        match crate::confirmation::wait_for_confirmation(ticket, ROW_TIMEOUT).await {
+            ConfirmationStatus::Failed(reason) => rejected.push(reject(reason)),
+            ConfirmationStatus::Queued => rejected.push(reject(format!(
+                "still waiting for the global mutex, check whether '{}' exists before importing the row again",
+                row.name
+            ))),
+            _ => imported.push(row.name),
+        }
+    }
+    rejected.sort_by_key(|row| row.line);
+    log::info!(
+        "Imported {} users, {} rows rejected",
+        imported.len(),
+        rejected.len()
+    );
+    Ok(ImportReport { imported, rejected })
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let (rows, rejected) = parse(
+            "name,balance,ledger\n\
+             alice,50.00\n\
+             \n\
+             bob,,club-bar\n\
+             carol,-3\n\
+             alice,1\n\
+             dave,ten\n\
+             NULL,1\n\
+             erin,1,Club Bar\n\
+             frank",
+        );
+        assert_eq!(
+            rows.iter()
+                .map(|row| (row.name.as_str(), row.balance, row.ledger.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("alice", 50.0, crate::ledger::DEFAULT_LEDGER),
+                ("bob", 0.0, "club-bar"),
+                ("frank", 0.0, crate::ledger::DEFAULT_LEDGER),
+            ]
+        );
+        assert_eq!(
+            rejected.iter().map(|row| row.line).collect::<Vec<_>>(),
+            vec![5, 6, 7, 8, 9]
+        );
+        assert_eq!(rejected[1].reason, "user 'alice' is defined twice");
+    }
+
+    #[test]
+    fn test_error_csv() {
+        let report = ImportReport {
+            imported: vec!["alice".to_string()],
+            rejected: vec![RejectedRow {
+                line: 3,
+                content: "bob,ten".to_string(),
+                reason: "invalid balance 'ten'".to_string(),
+            }],
+        };
+        assert_eq!(
+            report.error_csv(),
+            "line,content,reason\n3,\"bob,ten\",invalid balance 'ten'\n"
+        );
+    }
+}
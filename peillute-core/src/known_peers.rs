@@ -0,0 +1,134 @@
+//! Peers remembered across restarts
+//!
+//! Without `--cli-peers`, a site looks for the other sites by scanning the whole
+//! port range, which is slow on a real network. The sites that acknowledged a
+//! discovery, or discovered this site, are saved in the KnownPeers table with
+//! the time they were last seen. On the next start, the site announces itself
+//! to them first, most recently seen first, and only scans the port range if
+//! none of them can be reached.
+//!
+//! A known peer that cannot be reached at [`MAX_FAILURES`] starts in a row is
+//! forgotten.
+
+#![cfg(feature = "server")]
+
+/// Number of starts in a row a known peer may be unreachable before it is forgotten
+pub const MAX_FAILURES: i64 = 3;
+
+/// Returns the current time, in seconds since the Unix epoch
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Records that a peer was reached at a time, resetting its failures
+fn seen_in(
+    conn: &rusqlite::Connection,
+    addr: std::net::SocketAddr,
+    seen_at: i64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO KnownPeers (addr, last_seen, failures) VALUES (?1, ?2, 0)
+        ON CONFLICT(addr) DO UPDATE SET last_seen = excluded.last_seen, failures = 0",
+        rusqlite::params![addr.to_string(), seen_at],
+    )?;
+    Ok(())
+}
+
+/// Records that a known peer could not be reached, forgetting it after
+/// [`MAX_FAILURES`] failures in a row
+fn unreachable_in(conn: &rusqlite::Connection, addr: std::net::SocketAddr) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE KnownPeers SET failures = failures + 1 WHERE addr = ?1",
+        rusqlite::params![addr.to_string()],
+    )?;
+    conn.execute(
+        "DELETE FROM KnownPeers WHERE addr = ?1 AND failures >= ?2",
+        rusqlite::params![addr.to_string(), MAX_FAILURES],
+    )?;
+    Ok(())
+}
+
+/// Returns the known peers, most recently seen first
+fn known_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<std::net::SocketAddr>> {
+    let mut stmt =
+        conn.prepare("SELECT addr FROM KnownPeers ORDER BY last_seen DESC, failures, addr")?;
+    let addrs = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    // an address that no longer parses is skipped rather than failing the start
+    Ok(addrs.iter().filter_map(|addr| addr.parse().ok()).collect())
+}
+
+/// Records that a peer was reached
+pub fn seen(addr: std::net::SocketAddr) {
+    let saved = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        seen_in(&conn, addr, now())
+    };
+    if let Err(e) = saved {
+        log::error!("Cannot save the known peer {}: {}", addr, e);
+    }
+}
+
+/// Records that a known peer could not be reached
+pub fn unreachable(addr: std::net::SocketAddr) {
+    let saved = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        unreachable_in(&conn, addr)
+    };
+    if let Err(e) = saved {
+        log::error!("Cannot update the known peer {}: {}", addr, e);
+    }
+}
+
+/// Returns the known peers other than the site itself, most recently seen first
+pub fn known(local_addr: std::net::SocketAddr) -> Vec<std::net::SocketAddr> {
+    let known = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        known_in(&conn)
+    };
+    match known {
+        Ok(known) => known
+            .into_iter()
+            .filter(|addr| *addr != local_addr)
+            .collect(),
+        Err(e) => {
+            log::error!("Cannot read the known peers: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_peers() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        let a: std::net::SocketAddr = "192.168.1.10:10000".parse().unwrap();
+        let b: std::net::SocketAddr = "192.168.1.11:10000".parse().unwrap();
+
+        seen_in(&conn, a, 100).unwrap();
+        seen_in(&conn, b, 200).unwrap();
+        assert_eq!(known_in(&conn).unwrap(), vec![b, a]);
+
+        // a peer seen again is not forgotten
+        for _ in 0..MAX_FAILURES - 1 {
+            unreachable_in(&conn, b).unwrap();
+        }
+        seen_in(&conn, b, 300).unwrap();
+        unreachable_in(&conn, b).unwrap();
+        assert_eq!(known_in(&conn).unwrap(), vec![b, a]);
+
+        for _ in 0..MAX_FAILURES {
+            unreachable_in(&conn, a).unwrap();
+        }
+        assert_eq!(known_in(&conn).unwrap(), vec![b]);
+    }
+}
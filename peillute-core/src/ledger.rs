@@ -0,0 +1,197 @@
+//! Independent ledgers hosted by the same cluster
+//!
+//! A cluster can keep several isolated account books, such as the one of a
+//! flatshare and the one of a club bar. Each user belongs to a ledger, chosen
+//! when the user is created and diffused with its creation, and each
+//! transaction is recorded in the ledger of its users: money never moves from
+//! a ledger to another. The users created without a ledger, including those
+//! created before ledgers existed, belong to the default ledger.
+//!
+//! The ledgers share the sites, the global mutex and the clocks. The web
+//! interface shows the users of one ledger at a time, and the snapshots are
+//! also written for each ledger.
+
+/// Ledger of the users created without one
+pub const DEFAULT_LEDGER: &str = "default";
+
+/// Greatest length of the name of a ledger
+pub const MAX_LEDGER_NAME_LEN: usize = 32;
+
+/// Checks the name of a ledger: lowercase letters, digits and dashes
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_LEDGER_NAME_LEN {
+        return Err(format!(
+            "A ledger name has between 1 and {} characters.",
+            MAX_LEDGER_NAME_LEN
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(format!(
+            "Ledger '{}' may only hold lowercase letters, digits and dashes.",
+            name
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the ledger of a user of a database, the default one for unknown users
+pub(crate) fn ledger_of_in(conn: &rusqlite::Connection, user: &str) -> rusqlite::Result<String> {
+    use rusqlite::OptionalExtension;
+
+    let ledger: Option<String> = conn
+        .query_row(
+            "SELECT ledger FROM User WHERE unique_name = ?1",
+            rusqlite::params![user],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(ledger.unwrap_or_else(|| DEFAULT_LEDGER.to_string()))
+}
+
+#[cfg(feature = "server")]
+/// Returns the ledger of a user, the default one for unknown users
+pub fn ledger_of(user: &str) -> rusqlite::Result<String> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    ledger_of_in(&conn, user)
+}
+
+#[cfg(feature = "server")]
+/// Moves a user of a database to a ledger
+pub(crate) fn set_ledger_in(
+    conn: &rusqlite::Connection,
+    user: &str,
+    ledger: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE User SET ledger = ?2 WHERE unique_name = ?1",
+        rusqlite::params![user, ledger],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the ledger of a transaction between two users of a database
+///
+/// The null user, standing for the money entering or leaving Peillute, takes
+/// the ledger of the other user. Returns an error if the users belong to
+/// different ledgers.
+pub(crate) fn transaction_ledger_in(
+    conn: &rusqlite::Connection,
+    from_user: &str,
+    to_user: &str,
+) -> rusqlite::Result<String> {
+    let from_ledger = (from_user != crate::db::NULL)
+        .then(|| ledger_of_in(conn, from_user))
+        .transpose()?;
+    let to_ledger = (to_user != crate::db::NULL)
+        .then(|| ledger_of_in(conn, to_user))
+        .transpose()?;
+    match (from_ledger, to_ledger) {
+        (Some(from_ledger), Some(to_ledger)) if from_ledger != to_ledger => {
+            Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::ErrorCode::Unknown as i32),
+                Some(format!(
+                    "'{}' belongs to ledger '{}' and '{}' to ledger '{}'.",
+                    from_user, from_ledger, to_user, to_ledger
+                )),
+            ))
+        }
+        (Some(ledger), _) | (None, Some(ledger)) => Ok(ledger),
+        (None, None) => Ok(DEFAULT_LEDGER.to_string()),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the ledgers of the site, the default one first
+pub fn get_ledgers() -> rusqlite::Result<Vec<String>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT DISTINCT ledger FROM User ORDER BY ledger")?;
+    let mut ledgers = vec![DEFAULT_LEDGER.to_string()];
+    for ledger in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        let ledger = ledger?;
+        if ledger != DEFAULT_LEDGER {
+            ledgers.push(ledger);
+        }
+    }
+    Ok(ledgers)
+}
+
+#[cfg(feature = "server")]
+/// Returns the active or archived users of a ledger
+pub fn get_users(ledger: &str, active: bool) -> rusqlite::Result<Vec<String>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    let mut stmt =
+        conn.prepare("SELECT unique_name FROM User WHERE ledger = ?1 AND is_active = ?2")?;
+    stmt.query_map(rusqlite::params![ledger, active], |row| row.get(0))?
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the users outside of the default ledger, with their ledger
+///
+/// Sent with the snapshots, so that the sites synchronized by a snapshot put
+/// the users they discover in the right ledger.
+pub fn get_user_ledgers() -> rusqlite::Result<std::collections::HashMap<String, String>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT unique_name, ledger FROM User WHERE ledger != ?1")?;
+    stmt.query_map(rusqlite::params![DEFAULT_LEDGER], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Puts the users of a snapshot in their ledger, creating the missing ones
+pub fn apply_user_ledgers(
+    ledgers: &std::collections::HashMap<String, String>,
+) -> rusqlite::Result<()> {
+    for (user, ledger) in ledgers {
+        crate::db::ensure_user(user)?;
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        set_ledger_in(&conn, user, ledger)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name(DEFAULT_LEDGER).is_ok());
+        assert!(validate_name("club-bar").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("Club Bar").is_err());
+        assert!(validate_name(&"a".repeat(MAX_LEDGER_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_transaction_ledger() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO User (unique_name, solde) VALUES ('alice', 0), ('bob', 0), ('carol', 0)",
+        )
+        .unwrap();
+        set_ledger_in(&conn, "carol", "club-bar").unwrap();
+
+        assert_eq!(ledger_of_in(&conn, "alice").unwrap(), DEFAULT_LEDGER);
+        assert_eq!(ledger_of_in(&conn, "carol").unwrap(), "club-bar");
+        assert_eq!(
+            transaction_ledger_in(&conn, "alice", "bob").unwrap(),
+            DEFAULT_LEDGER
+        );
+        assert_eq!(
+            transaction_ledger_in(&conn, "NULL", "carol").unwrap(),
+            "club-bar"
+        );
+        assert!(transaction_ledger_in(&conn, "alice", "carol").is_err());
+    }
+}
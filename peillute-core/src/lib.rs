@@ -0,0 +1,125 @@
+//! Peillute node - the distributed core of Peillute
+//!
+//! This crate holds everything a Peillute site needs to take part in the
+//! network: the logical clocks, the replicated SQLite database, the peer
+//! network and its protocols, the snapshots and the critical commands. The
+//! `peillute` binary adds the command line and the web interface on top of it,
+//! and other Rust programs can embed a node the same way:
+//!
+//! ```no_run
+//! use peillute_core::{Node, NodeConfig, NodeEvent, control::CriticalCommands};
+//!
+//! # async fn embed() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = NodeConfig {
+//!     port: 10000,
+//!     peers: vec!["127.0.0.1:10001".to_string()],
+//!     ..NodeConfig::default()
+//! };
+//! let node = Node::new(config).start().await?;
+//! let handle = node.handle();
+//!
+//! let mut events = handle.subscribe();
+//! tokio::spawn(async move {
+//!     while let Ok(event) = events.recv().await {
+//!         if let NodeEvent::Transaction(tx) = event {
+//!             println!("{} sent {} to {}", tx.from_user, tx.amount, tx.to_user);
+//!         }
+//!     }
+//! });
+//!
+//! let ticket = handle
+//!     .submit(CriticalCommands::Deposit {
+//!         name: "alice".to_string(),
+//!         amount: 10.0,
+//!     })
+//!     .await?;
+//! handle
+//!     .wait_for_confirmation(ticket, std::time::Duration::from_secs(5))
+//!     .await;
+//!
+//! node.serve().await
+//! # }
+//! ```
+//!
+//! The state of a node is kept in process-wide globals, so a process runs a
+//! single node.
+
+/// Prints a line of the output of a CLI command, on the terminal or in the
+/// admin session running the command
+#[cfg(feature = "server")]
+macro_rules! cli_println {
+    ($($arg:tt)*) => {
+        $crate::console::write_line(format!($($arg)*))
+    };
+}
+
+pub mod admin;
+pub mod alerts;
+pub mod aliases;
+pub mod amount;
+pub mod api;
+pub mod api_tokens;
+pub mod archive;
+pub mod attach;
+pub mod audit_log;
+pub mod balances;
+pub mod bench;
+pub mod causality;
+pub mod chaos;
+pub mod client;
+pub mod clock;
+pub mod cluster_stats;
+pub mod codec;
+pub mod command_log;
+pub mod concurrency;
+pub mod confirmation;
+pub mod consistency;
+pub mod console;
+pub mod control;
+pub mod db;
+pub mod demo;
+pub mod favorites;
+pub mod fees;
+pub mod graph;
+pub mod graphql;
+pub mod grpc;
+pub mod heartbeat;
+pub mod import;
+pub mod known_peers;
+pub mod ledger;
+pub mod live;
+pub mod lock_stats;
+pub mod maintenance;
+pub mod message;
+pub mod metrics;
+pub mod network;
+pub mod node;
+pub mod notifications;
+pub mod offline;
+pub mod operators;
+pub mod peer_auth;
+pub mod policy;
+pub mod preview;
+pub mod quarantine;
+pub mod refund_requests;
+pub mod replay;
+pub mod saga;
+pub mod seed;
+pub mod sessions;
+pub mod settings;
+pub mod setup;
+pub mod sim;
+pub mod snapshot;
+pub mod state;
+pub mod statements;
+pub mod storage;
+pub mod system_accounts;
+pub mod tasks;
+pub mod telemetry;
+pub mod utils;
+pub mod wave;
+pub mod web_address;
+pub mod webhooks;
+
+#[cfg(feature = "server")]
+pub use node::{Node, NodeConfig, NodeEvent, NodeHandle, RunningNode};
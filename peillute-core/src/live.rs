@@ -0,0 +1,176 @@
+//! Live updates pushed to the web interface
+//!
+//! This module keeps a short history of events that the web interface should
+//! display without reloading the page, such as low balance alerts. Clients
+//! long-poll this history through a server function and only receive the
+//! events they have not seen yet.
+
+/// Event displayed live in the web interface
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LiveEvent {
+    /// The balance of a user dropped below its configured alert threshold
+    BalanceAlert {
+        /// Name of the user
+        user: String,
+        /// Balance of the user after the transaction
+        balance: f64,
+        /// Threshold configured by the user
+        threshold: f64,
+    },
+}
+
+#[cfg(feature = "server")]
+impl LiveEvent {
+    /// Returns the user concerned by the event
+    pub fn user(&self) -> &str {
+        match self {
+            LiveEvent::BalanceAlert { user, .. } => user,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Number of events kept for clients that are polling
+const MAX_RECENT_EVENTS: usize = 256;
+
+#[cfg(feature = "server")]
+/// Short history of the live events
+pub struct LiveUpdates {
+    /// Identifier given to the next event
+    next_id: u64,
+    /// Recent events with their identifier, oldest first
+    recent: std::collections::VecDeque<(u64, LiveEvent)>,
+    /// Wakes up the clients waiting for a new event
+    pub notify: std::sync::Arc<tokio::sync::Notify>,
+}
+
+#[cfg(feature = "server")]
+impl Default for LiveUpdates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "server")]
+impl LiveUpdates {
+    /// Creates an empty history
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            recent: std::collections::VecDeque::new(),
+            notify: std::sync::Arc::new(tokio::sync::Notify::new()),
+        }
+    }
+
+    /// Records an event and wakes up the waiting clients
+    pub fn push(&mut self, event: LiveEvent) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.recent.push_back((id, event));
+        while self.recent.len() > MAX_RECENT_EVENTS {
+            self.recent.pop_front();
+        }
+        self.notify.notify_waiters();
+        id
+    }
+
+    /// Returns the identifier of the last recorded event, 0 if none
+    pub fn last_id(&self) -> u64 {
+        self.next_id - 1
+    }
+
+    /// Returns the events of a user recorded after the given identifier
+    pub fn events_after(&self, after_id: u64, user: &str) -> Vec<LiveEvent> {
+        self.recent
+            .iter()
+            .filter(|(id, event)| *id > after_id && event.user() == user)
+            .map(|(_, event)| event.clone())
+            .collect()
+    }
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    pub static ref LIVE_UPDATES: std::sync::Mutex<LiveUpdates> =
+        std::sync::Mutex::new(LiveUpdates::new());
+}
+
+#[cfg(feature = "server")]
+/// Records a live event and sends it to the subscribers of the node
+pub fn publish(event: LiveEvent) {
+    crate::node::emit(crate::node::NodeEvent::Live(event.clone()));
+    LIVE_UPDATES.lock().unwrap().push(event);
+}
+
+#[cfg(feature = "server")]
+/// Waits for the events of a user recorded after the given identifier
+///
+/// Returns the identifier to use for the next call and the events, which is
+/// empty if nothing happened before the timeout.
+pub async fn wait_for_events(
+    after_id: u64,
+    user: &str,
+    timeout: std::time::Duration,
+) -> (u64, Vec<LiveEvent>) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let notify = {
+            let live = LIVE_UPDATES.lock().unwrap();
+            let events = live.events_after(after_id, user);
+            if !events.is_empty() {
+                return (live.last_id(), events);
+            }
+            live.notify.clone()
+        };
+        let notified = notify.notified();
+        if tokio::time::timeout_at(deadline, notified).await.is_err() {
+            let live = LIVE_UPDATES.lock().unwrap();
+            return (live.last_id().max(after_id), Vec::new());
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Builds the alert to emit when a transaction moves a balance
+///
+/// An alert is only emitted when the balance crosses the threshold, so that
+/// a user already below it is not alerted again on every payment.
+pub fn balance_alert(user: &str, before: f64, after: f64, threshold: f64) -> Option<LiveEvent> {
+    if before >= threshold && after < threshold {
+        Some(LiveEvent::BalanceAlert {
+            user: user.to_string(),
+            balance: after,
+            threshold,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alert_only_when_crossing() {
+        assert!(balance_alert("A", 20.0, 5.0, 10.0).is_some());
+        assert!(balance_alert("A", 10.0, 9.99, 10.0).is_some());
+        assert!(balance_alert("A", 8.0, 5.0, 10.0).is_none());
+        assert!(balance_alert("A", 20.0, 15.0, 10.0).is_none());
+    }
+
+    #[test]
+    fn events_are_filtered_by_user_and_id() {
+        let mut live = LiveUpdates::new();
+        assert_eq!(live.last_id(), 0);
+        let first = live.push(balance_alert("A", 20.0, 5.0, 10.0).unwrap());
+        live.push(balance_alert("B", 20.0, 5.0, 10.0).unwrap());
+        live.push(balance_alert("A", 20.0, 1.0, 10.0).unwrap());
+
+        assert_eq!(live.events_after(0, "A").len(), 2);
+        assert_eq!(live.events_after(first, "A").len(), 1);
+        assert_eq!(live.events_after(0, "C").len(), 0);
+        assert_eq!(live.last_id(), 3);
+    }
+}
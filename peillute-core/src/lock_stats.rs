@@ -0,0 +1,285 @@
+//! Hold times of the locks of the global state
+//!
+//! The components of [`crate::state`] are wrapped in [`TrackedMutex`] and
+//! [`TrackedRwLock`], which expose the same `lock`, `read` and `write` methods
+//! as the tokio locks. The guards they return measure how long they are held
+//! and remember the line of code that took them. Each hold is counted in the
+//! histogram of its lock, exported by `GET /metrics`, and in the statistics of
+//! its call site, listed by `/lock_stats`. A hold longer than `--lock-warn-ms`
+//! is logged as a warning: while a component is held, every message or server
+//! fn needing it waits.
+
+#![cfg(feature = "server")]
+
+/// Default hold time above which a warning is logged, in milliseconds
+pub const DEFAULT_WARN_MS: u64 = 100;
+
+/// Upper bounds of the buckets of the hold time histograms, in seconds
+pub const BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0];
+
+/// Hold time above which a warning is logged, in milliseconds (0 to disable)
+static WARN_MS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(DEFAULT_WARN_MS);
+
+/// Sets the hold time above which a warning is logged, in milliseconds
+/// (0 to disable)
+pub fn configure(warn_ms: u64) {
+    WARN_MS.store(warn_ms, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Holds of the locks taken at a line of code
+#[derive(Default, Clone, Copy)]
+struct SiteStats {
+    count: u64,
+    total: std::time::Duration,
+    max: std::time::Duration,
+}
+
+/// Holds of a lock
+#[derive(Default)]
+struct LockStats {
+    /// Number of holds per bucket of [`BUCKETS`], the last one above them all
+    buckets: [u64; BUCKETS.len() + 1],
+    count: u64,
+    total: std::time::Duration,
+    sites: std::collections::HashMap<&'static std::panic::Location<'static>, SiteStats>,
+}
+
+impl LockStats {
+    fn record(&mut self, held: std::time::Duration, site: &'static std::panic::Location<'static>) {
+        let secs = held.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += held;
+        let site = self.sites.entry(site).or_default();
+        site.count += 1;
+        site.total += held;
+        site.max = site.max.max(held);
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Holds of each lock, by name
+    static ref STATS: std::sync::Mutex<std::collections::BTreeMap<&'static str, LockStats>> =
+        std::sync::Mutex::new(std::collections::BTreeMap::new());
+}
+
+/// Records a hold of a lock, and warns if it was too long
+fn record(
+    lock: &'static str,
+    site: &'static std::panic::Location<'static>,
+    held: std::time::Duration,
+) {
+    STATS
+        .lock()
+        .unwrap()
+        .entry(lock)
+        .or_default()
+        .record(held, site);
+    let warn_ms = WARN_MS.load(std::sync::atomic::Ordering::Relaxed);
+    if warn_ms > 0 && held >= std::time::Duration::from_millis(warn_ms) {
+        log::warn!("{} held for {} ms at {}", lock, held.as_millis(), site);
+    }
+}
+
+/// Guard of a tracked lock, recording how long it was held when dropped
+pub struct Held<G> {
+    guard: G,
+    lock: &'static str,
+    site: &'static std::panic::Location<'static>,
+    since: std::time::Instant,
+}
+
+impl<G> Held<G> {
+    fn new(guard: G, lock: &'static str, site: &'static std::panic::Location<'static>) -> Self {
+        Self {
+            guard,
+            lock,
+            site,
+            since: std::time::Instant::now(),
+        }
+    }
+}
+
+impl<G: std::ops::Deref> std::ops::Deref for Held<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: std::ops::DerefMut> std::ops::DerefMut for Held<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for Held<G> {
+    fn drop(&mut self) {
+        record(self.lock, self.site, self.since.elapsed());
+    }
+}
+
+/// Tokio mutex whose holds are measured
+pub struct TrackedMutex<T> {
+    name: &'static str,
+    inner: tokio::sync::Mutex<T>,
+}
+
+impl<T> TrackedMutex<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: tokio::sync::Mutex::new(value),
+        }
+    }
+
+    /// Locks the mutex, the hold being recorded against the caller
+    #[track_caller]
+    pub fn lock(&self) -> impl std::future::Future<Output = Held<tokio::sync::MutexGuard<'_, T>>> {
+        let site = std::panic::Location::caller();
+        async move { Held::new(self.inner.lock().await, self.name, site) }
+    }
+}
+
+/// Tokio read-write lock whose holds are measured
+pub struct TrackedRwLock<T> {
+    name: &'static str,
+    inner: tokio::sync::RwLock<T>,
+}
+
+impl<T> TrackedRwLock<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: tokio::sync::RwLock::new(value),
+        }
+    }
+
+    /// Locks for reading, the hold being recorded against the caller
+    #[track_caller]
+    pub fn read(
+        &self,
+    ) -> impl std::future::Future<Output = Held<tokio::sync::RwLockReadGuard<'_, T>>> {
+        let site = std::panic::Location::caller();
+        async move { Held::new(self.inner.read().await, self.name, site) }
+    }
+
+    /// Locks for writing, the hold being recorded against the caller
+    #[track_caller]
+    pub fn write(
+        &self,
+    ) -> impl std::future::Future<Output = Held<tokio::sync::RwLockWriteGuard<'_, T>>> {
+        let site = std::panic::Location::caller();
+        async move { Held::new(self.inner.write().await, self.name, site) }
+    }
+}
+
+/// Hold time histogram of a lock
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoldHistogram {
+    pub lock: &'static str,
+    /// Cumulative number of holds up to each bound of [`BUCKETS`]
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: std::time::Duration,
+}
+
+/// Returns the hold time histogram of each lock taken at least once
+pub fn histograms() -> Vec<HoldHistogram> {
+    STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&lock, stats)| HoldHistogram {
+            lock,
+            buckets: stats.buckets[..BUCKETS.len()]
+                .iter()
+                .scan(0, |total, count| {
+                    *total += count;
+                    Some(*total)
+                })
+                .collect(),
+            count: stats.count,
+            sum: stats.total,
+        })
+        .collect()
+}
+
+/// Holds of a lock taken at a line of code
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CallSite {
+    pub lock: &'static str,
+    /// File, line and column of the call
+    pub location: String,
+    pub count: u64,
+    pub total: std::time::Duration,
+    pub max: std::time::Duration,
+}
+
+/// Returns the `n` call sites that held a lock the longest at once
+pub fn worst_call_sites(n: usize) -> Vec<CallSite> {
+    let mut sites: Vec<CallSite> = STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|(&lock, stats)| {
+            stats.sites.iter().map(move |(location, site)| CallSite {
+                lock,
+                location: location.to_string(),
+                count: site.count,
+                total: site.total,
+                max: site.max,
+            })
+        })
+        .collect();
+    sites.sort_by(|a, b| b.max.cmp(&a.max).then(b.total.cmp(&a.total)));
+    sites.truncate(n);
+    sites
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let site = std::panic::Location::caller();
+        let mut stats = LockStats::default();
+        stats.record(std::time::Duration::from_micros(500), site);
+        stats.record(std::time::Duration::from_millis(20), site);
+        stats.record(std::time::Duration::from_secs(2), site);
+        assert_eq!(stats.buckets, [1, 0, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(stats.count, 3);
+        let site = stats.sites[&site];
+        assert_eq!(site.count, 3);
+        assert_eq!(site.max, std::time::Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_tracked_lock() {
+        let mutex = TrackedMutex::new("test_tracked_lock", 1);
+        *mutex.lock().await += 1;
+        assert_eq!(*mutex.lock().await, 2);
+
+        let histogram = histograms()
+            .into_iter()
+            .find(|histogram| histogram.lock == "test_tracked_lock")
+            .unwrap();
+        assert_eq!(histogram.count, 2);
+        let sites: Vec<CallSite> = worst_call_sites(usize::MAX)
+            .into_iter()
+            .filter(|site| site.lock == "test_tracked_lock")
+            .collect();
+        assert_eq!(sites.len(), 2);
+        assert!(
+            sites
+                .iter()
+                .all(|site| site.location.contains("lock_stats.rs"))
+        );
+    }
+}
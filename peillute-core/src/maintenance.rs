@@ -0,0 +1,68 @@
+//! Maintenance mode of a site
+//!
+//! During an upgrade or a maintenance of its database, an operator puts the
+//! site in maintenance, with `--maintenance` at startup, the `/maintenance`
+//! command or the **Debug-Info** page. The site then refuses the new
+//! transactions submitted to it, from the web interface, the CLI, the API or
+//! its client sites, and the web interface shows a banner with the message
+//! of the operator. The site keeps receiving and applying the transactions
+//! diffused by the other sites, so that it stays consistent with them.
+//!
+//! The mode is kept in memory: a site restarted after the maintenance leaves
+//! it, unless `--maintenance` is given again.
+
+#![cfg(feature = "server")]
+
+/// Message shown when the operator gives none
+pub const DEFAULT_MESSAGE: &str =
+    "The site is under maintenance: new transactions are refused for now, please try again later.";
+
+/// Message of the maintenance in progress, None outside of maintenance
+static MAINTENANCE: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Puts the site in maintenance, with the message shown to the users
+pub fn enable(message: Option<String>) {
+    let message = message
+        .map(|message| message.trim().to_string())
+        .filter(|message| !message.is_empty())
+        .unwrap_or_else(|| DEFAULT_MESSAGE.to_string());
+    log::warn!("Maintenance mode on: {}", message);
+    *MAINTENANCE.lock().unwrap() = Some(message);
+}
+
+/// Takes the site out of maintenance
+pub fn disable() {
+    if MAINTENANCE.lock().unwrap().take().is_some() {
+        log::warn!("Maintenance mode off");
+    }
+}
+
+/// Returns the message of the maintenance in progress, if any
+pub fn message() -> Option<String> {
+    MAINTENANCE.lock().unwrap().clone()
+}
+
+/// Checks that the site accepts new transactions
+pub fn check() -> Result<(), String> {
+    match message() {
+        Some(message) => Err(message),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance() {
+        assert!(check().is_ok());
+        enable(Some("  ".to_string()));
+        assert_eq!(check(), Err(DEFAULT_MESSAGE.to_string()));
+        enable(Some("Upgrading until 14:00".to_string()));
+        assert_eq!(message().as_deref(), Some("Upgrading until 14:00"));
+        disable();
+        assert!(check().is_ok());
+        assert_eq!(message(), None);
+    }
+}
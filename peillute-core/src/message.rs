@@ -0,0 +1,958 @@
+//! Message handling for distributed transactions
+//!
+//! This module defines the message types and structures used for communication
+//! between nodes in the distributed system, including transaction messages,
+//! network control messages, and various financial operations.
+
+#[cfg(feature = "server")]
+/// Represents a financial transaction in the system
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct Transaction {
+    /// Unique identifier for the transaction
+    pub id: u64,
+    /// ID of the user performing the transaction
+    pub user_id: String,
+    /// Transaction amount
+    pub amount: f64,
+    /// Description of the transaction
+    pub description: String,
+}
+
+#[cfg(feature = "server")]
+/// Types of network messages used for communication between nodes
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub enum NetworkMessageCode {
+    /// Message for discovering other nodes in the network
+    Discovery,
+    /// Message containing a financial transaction
+    Transaction,
+    /// Message acknowledging receipt of a previous transaction
+    TransactionAcknowledgement,
+    /// Message acknowledging receipt of a previous message
+    Acknowledgment,
+    /// Message indicating an error condition
+    Error,
+    /// Message for gracefully disconnecting from the network
+    Disconnect,
+    /// Message requesting a state snapshot
+    SnapshotRequest,
+    /// Message containing a state snapshot
+    SnapshotResponse,
+    /// Request to acquire the global mutex
+    AcquireMutex,
+    /// Request to release the global mutex
+    ReleaseGlobalMutex,
+    /// Acknowledgment of the global mutex acquisition
+    AckGlobalMutex,
+    /// Acknowledgment of the global mutex acquisition
+    AckReleaseGlobalMutex,
+    /// Request for the state digest of the sites
+    ConsistencyRequest,
+    /// State digests of a site and its children in the wave
+    ConsistencyResponse,
+    /// Order to clear the stale global mutex request of a site
+    ForceReleaseMutex,
+    /// Acknowledgment of a forced release of the global mutex
+    AckForceReleaseMutex,
+    /// Critical command forwarded by a client site to its full node
+    ForwardCommand,
+    /// Final status of a forwarded command, sent back to the client site
+    ForwardResult,
+    /// Heartbeat measuring the round-trip time of a neighbour
+    Heartbeat,
+    /// Reply to a heartbeat
+    HeartbeatAck,
+    /// Request for the counters of the sites
+    ClusterStatsRequest,
+    /// Counters of a site and its children in the wave
+    ClusterStatsResponse,
+}
+
+#[cfg(feature = "server")]
+/// Represents a message exchanged between nodes in the network
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Message {
+    /// ID of the sending node
+    pub sender_id: String,
+    /// ID of the node that initiated the message
+    pub message_initiator_id: String,
+    /// Network address of the node that initiated the message
+    pub message_initiator_addr: std::net::SocketAddr,
+    /// Network address of the sending node
+    pub sender_addr: std::net::SocketAddr,
+    /// Logical clock state of the sending node
+    pub clock: crate::clock::Clock,
+    /// Optional command to be executed
+    pub command: Option<crate::control::Command>,
+    /// Message payload containing the actual data
+    pub info: MessageInfo,
+    /// Type of the message
+    pub code: NetworkMessageCode,
+    /// Trace and span of the wave on the sender, when its spans are exported
+    #[serde(default)]
+    pub trace: Option<crate::telemetry::TraceContext>,
+}
+
+#[cfg(feature = "server")]
+/// Types of message payloads for different operations
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub enum MessageInfo {
+    /// Versions of the protocol supported by a site joining the network
+    Discovery(DiscoveryPayload),
+    /// Acknowledgment of a new connection
+    Acknowledge(AcknowledgePayload),
+    /// Create a new user
+    CreateUser(CreateUser),
+    /// Archive a user
+    DeactivateUser(DeactivateUser),
+    /// Restore an archived user
+    ReactivateUser(ReactivateUser),
+    /// Rename a user
+    RenameUser(RenameUser),
+    /// Deposit money into an account
+    Deposit(Deposit),
+    /// Withdraw money from an account
+    Withdraw(Withdraw),
+    /// Transfer money between accounts
+    Transfer(Transfer),
+    /// Make a payment
+    Pay(Pay),
+    /// Process a refund
+    Refund(Refund),
+    /// Ask the recipient of a transaction to approve its refund
+    RequestRefund(RefundRequest),
+    /// Reject a refund request
+    RejectRefund(RefundRejection),
+    /// Split a bill between several accounts
+    BatchTransfer(BatchTransfer),
+    /// Record the transactions of a saga
+    Saga(Saga),
+    /// Add or update a product of the catalog
+    UpsertProduct(UpsertProduct),
+    /// Remove a product from the catalog
+    DeleteProduct(DeleteProduct),
+    /// Comment or react to a transaction
+    Comment(Comment),
+    /// Request of a delta snapshot
+    SnapshotRequest(SnapshotRequest),
+    /// Response to a snapshot request
+    SnapshotResponse(SnapshotResponse),
+    /// Part of a snapshot response too large to fit in a single message
+    SnapshotChunk(SnapshotChunk),
+    /// Snapshot response compressed because of its size
+    CompressedSnapshot(CompressedSnapshot),
+    /// State digests collected by a consistency check
+    ConsistencyDigests(Vec<crate::consistency::SiteDigest>),
+    /// Counters of the sites collected by a cluster statistics wave
+    ClusterStats(Vec<crate::cluster_stats::SiteStats>),
+    /// Reason of an error reported by a peer
+    Error(String),
+    /// Initiate a critical section
+    AcquireMutex(AcquireMutexPayload),
+    /// Release a critical section
+    ReleaseMutex(ReleaseMutexPayload),
+    /// Clear the stale request of a site for the critical section
+    ForceReleaseMutex(ForceReleaseMutexPayload),
+    /// Acknowledge a critical section
+    AckMutex(AckMutexPayload),
+    /// Acknowledge a transaction
+    AckTransaction(AckTransactionPayload),
+    /// Critical command forwarded by a client site
+    ForwardCommand(ForwardCommand),
+    /// Status of a command forwarded by a client site
+    ForwardResult(ForwardResult),
+    /// Heartbeat of a neighbour or its reply
+    Heartbeat(HeartbeatPayload),
+    /// No payload
+    None,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the AcquireMutex message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AcknowledgePayload {
+    /// Logical clock state of the acknowledging node
+    pub global_fifo: std::collections::HashMap<String, crate::state::MutexStamp>,
+    /// Version of the protocol negotiated with the discovering site
+    #[serde(default)]
+    pub protocol_version: u8,
+    /// Alias of the acknowledging site
+    #[serde(default)]
+    pub alias: Option<String>,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the ForwardCommand message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ForwardCommand {
+    /// Ticket of the command on the client site
+    pub ticket: u64,
+    /// Command to execute on the full node
+    pub command: crate::control::CriticalCommands,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the ForwardResult message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ForwardResult {
+    /// Ticket of the command on the client site
+    pub ticket: u64,
+    /// Final status of the command on the full node
+    pub status: crate::confirmation::ConfirmationStatus,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the Heartbeat and HeartbeatAck messages
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct HeartbeatPayload {
+    /// Time the heartbeat was sent, in microseconds since the Unix epoch, echoed by the reply
+    pub sent_at: u64,
+    /// Site IDs of the connected neighbours of the sender
+    pub neighbours: Vec<String>,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the Discovery message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct DiscoveryPayload {
+    /// Lowest version of the protocol supported by the discovering site
+    pub min_version: u8,
+    /// Highest version of the protocol supported by the discovering site
+    pub max_version: u8,
+    /// Alias of the discovering site
+    #[serde(default)]
+    pub alias: Option<String>,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the AcquireMutex message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AcquireMutexPayload;
+
+#[cfg(feature = "server")]
+/// Payload for the ReleaseMutex message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg(feature = "server")]
+/// Payload for the AckMutex message
+pub struct ReleaseMutexPayload;
+
+#[cfg(feature = "server")]
+/// Payload for the ForceReleaseMutex message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ForceReleaseMutexPayload {
+    /// ID of the site whose request is cleared
+    pub site_id: String,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the AckMutex message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AckMutexPayload {
+    pub clock: i64,
+}
+
+#[cfg(feature = "server")]
+/// Payload for the TransactionAcknowledgement message
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AckTransactionPayload {
+    /// Number of sites of the acknowledging subtree that applied the transaction
+    pub sites: i64,
+}
+
+#[cfg(feature = "server")]
+/// Request of a delta snapshot, the full snapshots carrying no payload
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotRequest {
+    /// Greatest Lamport time of the transactions of each site known by the
+    /// initiator, the responders only returning the newer ones
+    pub known: std::collections::HashMap<String, i64>,
+}
+
+#[cfg(feature = "server")]
+/// Response to a state snapshot request
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SnapshotResponse {
+    /// ID of the responding node
+    pub site_id: String,
+    /// Logical clock state of the responding node
+    pub clock: crate::clock::Clock,
+    /// Transaction log summary
+    pub tx_log: Vec<crate::snapshot::TxSummary>,
+    /// True if the log only holds the transactions unknown to the initiator
+    /// of a delta snapshot
+    #[serde(default)]
+    pub partial: bool,
+    /// Users outside of the default ledger known by the responding node,
+    /// with their ledger
+    #[serde(default)]
+    pub ledgers: std::collections::HashMap<String, String>,
+}
+
+#[cfg(feature = "server")]
+/// Part of a snapshot response split over several messages
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SnapshotChunk {
+    /// Position of the chunk, starting at 0
+    pub index: u32,
+    /// Number of chunks of the response
+    pub count: u32,
+    /// Response holding a slice of the transaction log
+    pub response: SnapshotResponse,
+}
+
+#[cfg(feature = "server")]
+/// Snapshot response encoded in MessagePack then compressed with gzip
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct CompressedSnapshot {
+    /// Compressed response
+    pub data: Vec<u8>,
+}
+
+#[cfg(feature = "server")]
+impl CompressedSnapshot {
+    /// Decompresses the response
+    pub fn decompress(&self) -> Result<SnapshotResponse, String> {
+        use crate::codec::WireCodec;
+        use std::io::Read;
+
+        let mut bytes = Vec::new();
+        flate2::read::GzDecoder::new(self.data.as_slice())
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+        crate::codec::MsgpackCodec::decode(&bytes)
+    }
+}
+
+#[cfg(feature = "server")]
+impl SnapshotResponse {
+    /// Compresses the response, to send it in a single message
+    pub fn compress(&self) -> Result<CompressedSnapshot, String> {
+        use crate::codec::WireCodec;
+        use std::io::Write;
+
+        let bytes = crate::codec::MsgpackCodec::encode(self)?;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&bytes).map_err(|e| e.to_string())?;
+        let data = encoder.finish().map_err(|e| e.to_string())?;
+        Ok(CompressedSnapshot { data })
+    }
+
+    /// Splits the response in chunks of at most `txs_per_chunk` transactions
+    pub fn into_chunks(self, txs_per_chunk: usize) -> Vec<SnapshotChunk> {
+        let txs_per_chunk = txs_per_chunk.max(1);
+        let mut parts: Vec<Vec<crate::snapshot::TxSummary>> = self
+            .tx_log
+            .chunks(txs_per_chunk)
+            .map(|c| c.to_vec())
+            .collect();
+        if parts.is_empty() {
+            parts.push(Vec::new());
+        }
+        let count = parts.len() as u32;
+        parts
+            .into_iter()
+            .enumerate()
+            .map(|(index, tx_log)| SnapshotChunk {
+                index: index as u32,
+                count,
+                response: SnapshotResponse {
+                    site_id: self.site_id.clone(),
+                    clock: self.clock.clone(),
+                    tx_log,
+                    partial: self.partial,
+                    // the ledgers travel with the first chunk
+                    ledgers: if index == 0 {
+                        self.ledgers.clone()
+                    } else {
+                        Default::default()
+                    },
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "server")]
+/// Reassembles the snapshot responses received in several chunks
+#[derive(Default)]
+pub struct SnapshotChunkAssembler {
+    /// Chunks received so far, by sender address and responding site
+    pending:
+        std::collections::HashMap<(std::net::SocketAddr, String), Vec<Option<SnapshotResponse>>>,
+}
+
+#[cfg(feature = "server")]
+impl SnapshotChunkAssembler {
+    /// Stores a chunk, returns the whole response once every chunk is received
+    pub fn push(
+        &mut self,
+        sender: std::net::SocketAddr,
+        chunk: SnapshotChunk,
+    ) -> Option<SnapshotResponse> {
+        let key = (sender, chunk.response.site_id.clone());
+        let count = chunk.count.max(1) as usize;
+        let parts = self.pending.entry(key.clone()).or_default();
+        if parts.len() != count {
+            // First chunk of a new response
+            *parts = vec![None; count];
+        }
+        if let Some(slot) = parts.get_mut(chunk.index as usize) {
+            *slot = Some(chunk.response);
+        }
+        if parts.iter().any(|p| p.is_none()) {
+            return None;
+        }
+
+        let parts = self.pending.remove(&key)?;
+        let mut parts = parts.into_iter().flatten();
+        let mut response = parts.next()?;
+        for part in parts {
+            response.tx_log.extend(part.tx_log);
+            response.ledgers.extend(part.ledgers);
+        }
+        Some(response)
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to create a new user
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct CreateUser {
+    /// Name of the user to create
+    pub name: String,
+    /// Ledger of the user, the default one for the sites older than ledgers
+    #[serde(default = "default_ledger")]
+    pub ledger: String,
+    /// Initial balance of the user, deposited with its creation
+    #[serde(default)]
+    pub solde: f64,
+}
+
+#[cfg(feature = "server")]
+/// Ledger of the users created by the sites older than ledgers
+fn default_ledger() -> String {
+    crate::ledger::DEFAULT_LEDGER.to_string()
+}
+
+#[cfg(feature = "server")]
+impl CreateUser {
+    /// Creates a new CreateUser request
+    pub fn new(name: String, ledger: String) -> Self {
+        Self {
+            name,
+            ledger,
+            solde: 0.0,
+        }
+    }
+
+    /// Creates a new CreateUser request with an initial balance
+    pub fn with_solde(name: String, ledger: String, solde: f64) -> Self {
+        Self {
+            name,
+            ledger,
+            solde,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to archive a user
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct DeactivateUser {
+    /// Name of the user to archive
+    pub name: String,
+}
+
+#[cfg(feature = "server")]
+impl DeactivateUser {
+    /// Creates a new DeactivateUser request
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to restore an archived user
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ReactivateUser {
+    /// Name of the user to restore
+    pub name: String,
+}
+
+#[cfg(feature = "server")]
+impl ReactivateUser {
+    /// Creates a new ReactivateUser request
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to rename a user
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct RenameUser {
+    /// Current name of the user
+    pub name: String,
+    /// New name of the user
+    pub new_name: String,
+}
+
+#[cfg(feature = "server")]
+impl RenameUser {
+    /// Creates a new RenameUser request
+    pub fn new(name: String, new_name: String) -> Self {
+        Self { name, new_name }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to add or update a product of the catalog
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct UpsertProduct {
+    /// Name of the product
+    pub name: String,
+    /// Unit price of the product
+    pub price: f64,
+    /// New image of the product as a data URL, None keeps the current one
+    pub image: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl UpsertProduct {
+    /// Creates a new UpsertProduct request
+    pub fn new(name: String, price: f64, image: Option<String>) -> Self {
+        Self { name, price, image }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to remove a product from the catalog
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct DeleteProduct {
+    /// Name of the product to remove
+    pub name: String,
+}
+
+#[cfg(feature = "server")]
+impl DeleteProduct {
+    /// Creates a new DeleteProduct request
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to attach a comment or a reaction to a transaction
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Comment {
+    /// Lamport time of the commented transaction
+    pub lamport_time: i64,
+    /// ID of the node that created the commented transaction
+    pub source_node: String,
+    /// User who wrote the comment
+    pub author: String,
+    /// Text of the comment, or the emoji of the reaction
+    pub text: String,
+    /// Whether the comment is an emoji reaction
+    pub reaction: bool,
+}
+
+#[cfg(feature = "server")]
+impl Comment {
+    /// Creates a new Comment request
+    pub fn new(
+        lamport_time: i64,
+        source_node: String,
+        author: String,
+        text: String,
+        reaction: bool,
+    ) -> Self {
+        Self {
+            lamport_time,
+            source_node,
+            author,
+            text,
+            reaction,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to deposit money into an account
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Deposit {
+    /// Name of the account
+    pub name: String,
+    /// Amount to deposit
+    pub amount: f64,
+}
+
+#[cfg(feature = "server")]
+impl Deposit {
+    /// Creates a new Deposit request
+    pub fn new(name: String, amount: f64) -> Self {
+        Self { name, amount }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to withdraw money from an account
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Withdraw {
+    /// Name of the account
+    pub name: String,
+    /// Amount to withdraw
+    pub amount: f64,
+}
+
+#[cfg(feature = "server")]
+impl Withdraw {
+    /// Creates a new Withdraw request
+    pub fn new(name: String, amount: f64) -> Self {
+        Self { name, amount }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to transfer money between accounts
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Transfer {
+    /// Name of the source account
+    pub name: String,
+    /// Name of the destination account
+    pub beneficiary: String,
+    /// Amount to transfer
+    pub amount: f64,
+    /// Fees paid on the operation, computed by the site that submitted it
+    #[serde(default)]
+    pub fees: Vec<crate::fees::Fee>,
+}
+
+#[cfg(feature = "server")]
+impl Transfer {
+    /// Creates a new Transfer request
+    pub fn new(name: String, beneficiary: String, amount: f64) -> Self {
+        Self {
+            name,
+            beneficiary,
+            amount,
+            fees: Vec::new(),
+        }
+    }
+
+    /// Sets the fees paid on the operation
+    pub fn with_fees(mut self, fees: Vec<crate::fees::Fee>) -> Self {
+        self.fees = fees;
+        self
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to make a payment
+///
+/// A bill split between several users carries the share of each payer,
+/// applied atomically like a [`BatchTransfer`]: each payment gets its own
+/// Lamport time, starting at `first_lamport`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Pay {
+    /// Name of the account
+    pub name: String,
+    /// Amount to pay, tip included
+    pub amount: f64,
+    /// Payers of a split bill with their share, empty if `name` pays alone
+    #[serde(default)]
+    pub shares: Vec<(String, f64)>,
+    /// Lamport time of the first payment of a split bill
+    #[serde(default)]
+    pub first_lamport: i64,
+    /// Fees paid on the operation, computed by the site that submitted it
+    #[serde(default)]
+    pub fees: Vec<crate::fees::Fee>,
+}
+
+#[cfg(feature = "server")]
+impl Pay {
+    /// Creates a new Pay request
+    pub fn new(name: String, amount: f64) -> Self {
+        Self {
+            name,
+            amount,
+            shares: Vec::new(),
+            first_lamport: 0,
+            fees: Vec::new(),
+        }
+    }
+
+    /// Creates the request of a bill split between several payers
+    pub fn split(name: String, shares: Vec<(String, f64)>, first_lamport: i64) -> Self {
+        Self {
+            name,
+            amount: shares.iter().map(|(_, amount)| amount).sum(),
+            shares,
+            first_lamport,
+            fees: Vec::new(),
+        }
+    }
+
+    /// Sets the fees paid on the operation
+    pub fn with_fees(mut self, fees: Vec<crate::fees::Fee>) -> Self {
+        self.fees = fees;
+        self
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to process a refund
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Refund {
+    /// Name of the account
+    pub name: String,
+    /// Timestamp of the original transaction
+    pub transac_time: i64,
+    /// ID of the node that processed the original transaction
+    pub transac_node: String,
+}
+
+#[cfg(feature = "server")]
+impl Refund {
+    /// Creates a new Refund request
+    pub fn new(name: String, transac_time: i64, transac_node: String) -> Self {
+        Self {
+            name,
+            transac_time,
+            transac_node,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request of the sender of a transaction to be refunded, to be approved by
+/// its recipient
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct RefundRequest {
+    /// Name of the account asking for the refund
+    pub name: String,
+    /// Timestamp of the transaction to refund
+    pub transac_time: i64,
+    /// ID of the node that processed the transaction to refund
+    pub transac_node: String,
+}
+
+#[cfg(feature = "server")]
+impl RefundRequest {
+    /// Creates a new RefundRequest request
+    pub fn new(name: String, transac_time: i64, transac_node: String) -> Self {
+        Self {
+            name,
+            transac_time,
+            transac_node,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Rejection of a refund request by the recipient of the transaction
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct RefundRejection {
+    /// Name of the account rejecting the refund
+    pub name: String,
+    /// Timestamp of the refund request
+    pub request_time: i64,
+    /// ID of the node where the refund was requested
+    pub request_node: String,
+}
+
+#[cfg(feature = "server")]
+impl RefundRejection {
+    /// Creates a new RefundRejection request
+    pub fn new(name: String, request_time: i64, request_node: String) -> Self {
+        Self {
+            name,
+            request_time,
+            request_node,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to transfer money from one account to several beneficiaries
+///
+/// The transfers are applied atomically: either every site records all of
+/// them or none. Each transfer gets its own Lamport time, starting at
+/// `first_lamport` and incremented by one for each beneficiary.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct BatchTransfer {
+    /// Name of the payer account
+    pub name: String,
+    /// Beneficiaries with the amount they receive
+    pub transfers: Vec<(String, f64)>,
+    /// Lamport time of the first transfer of the batch
+    pub first_lamport: i64,
+    /// Fees paid on the operation, computed by the site that submitted it
+    #[serde(default)]
+    pub fees: Vec<crate::fees::Fee>,
+}
+
+#[cfg(feature = "server")]
+impl BatchTransfer {
+    /// Creates a new BatchTransfer request
+    pub fn new(name: String, transfers: Vec<(String, f64)>, first_lamport: i64) -> Self {
+        Self {
+            name,
+            transfers,
+            first_lamport,
+            fees: Vec::new(),
+        }
+    }
+
+    /// Sets the fees paid on the operation
+    pub fn with_fees(mut self, fees: Vec<crate::fees::Fee>) -> Self {
+        self.fees = fees;
+        self
+    }
+}
+
+#[cfg(feature = "server")]
+/// Request to record the transactions of a saga, see [`crate::saga`]
+///
+/// The steps were executed by the site that submitted the saga, `failure`
+/// telling which step failed, the steps before it being compensated. Each
+/// recorded transaction gets its own Lamport time, starting at
+/// `first_lamport`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Saga {
+    /// Steps of the saga
+    pub steps: Vec<crate::saga::SagaStep>,
+    /// Step that failed, None if the saga completed
+    pub failure: Option<crate::saga::SagaFailure>,
+    /// Lamport time of the first transaction of the saga
+    pub first_lamport: i64,
+}
+
+#[cfg(feature = "server")]
+impl Saga {
+    /// Creates a new Saga request
+    pub fn new(
+        steps: Vec<crate::saga::SagaStep>,
+        failure: Option<crate::saga::SagaFailure>,
+        first_lamport: i64,
+    ) -> Self {
+        Self {
+            steps,
+            failure,
+            first_lamport,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_debug() {
+        let transaction = Transaction {
+            id: 1,
+            user_id: "test_user".to_string(),
+            amount: 100.0,
+            description: "Test transaction".to_string(),
+        };
+        assert_eq!(
+            format!("{:?}", transaction),
+            "Transaction { id: 1, user_id: \"test_user\", amount: 100.0, description: \"Test transaction\" }"
+        );
+    }
+
+    #[test]
+    fn test_message_debug() {
+        let clock = crate::clock::Clock::new();
+
+        let message = Message {
+            sender_id: "A".to_string(),
+            sender_addr: "127.0.0.1:8080".parse().unwrap(),
+            message_initiator_id: "A".to_string(),
+            message_initiator_addr: "127.0.0.1:8080".parse().unwrap(),
+            clock,
+            command: None,
+            info: MessageInfo::None,
+            code: NetworkMessageCode::Transaction,
+            trace: None,
+        };
+        assert!(format!("{:?}", message).contains("Message { sender_id: \"A\""));
+    }
+
+    #[test]
+    fn test_snapshot_chunks_roundtrip() {
+        let tx_log: Vec<crate::snapshot::TxSummary> = (0..10)
+            .map(|i| crate::snapshot::TxSummary {
+                lamport_time: i,
+                source_node: "A".to_string(),
+                from_user: "Alice".to_string(),
+                to_user: "Bob".to_string(),
+                amount_in_cent: 100,
+            })
+            .collect();
+        let response = SnapshotResponse {
+            site_id: "A".to_string(),
+            clock: crate::clock::Clock::new(),
+            tx_log,
+            partial: false,
+            ledgers: [("Bob".to_string(), "club-bar".to_string())].into(),
+        };
+
+        let chunks = response.into_chunks(3);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c.count == 4));
+
+        let sender: std::net::SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let mut assembler = SnapshotChunkAssembler::default();
+        let mut chunks = chunks.into_iter().rev();
+        for chunk in chunks.by_ref().take(3) {
+            assert!(assembler.push(sender, chunk).is_none());
+        }
+        let whole = assembler.push(sender, chunks.next().unwrap()).unwrap();
+        let times: Vec<i64> = whole.tx_log.iter().map(|t| t.lamport_time).collect();
+        assert_eq!(times, (0..10).collect::<Vec<_>>());
+        assert_eq!(whole.ledgers["Bob"], "club-bar");
+    }
+
+    #[test]
+    fn test_compressed_snapshot_roundtrip() {
+        let tx_log: Vec<crate::snapshot::TxSummary> = (0..1000)
+            .map(|i| crate::snapshot::TxSummary {
+                lamport_time: i,
+                source_node: "A".to_string(),
+                from_user: "Alice".to_string(),
+                to_user: "Bob".to_string(),
+                amount_in_cent: 100,
+            })
+            .collect();
+        let response = SnapshotResponse {
+            site_id: "A".to_string(),
+            clock: crate::clock::Clock::new(),
+            tx_log,
+            partial: true,
+            ledgers: Default::default(),
+        };
+
+        let compressed = response.compress().unwrap();
+        let size = crate::codec::WireFormat::Msgpack
+            .encode(&response)
+            .unwrap()
+            .len();
+        assert!(compressed.data.len() < size / 4);
+
+        let whole = compressed.decompress().unwrap();
+        assert_eq!(whole.tx_log, response.tx_log);
+        assert!(whole.partial);
+        assert!(
+            CompressedSnapshot {
+                data: vec![1, 2, 3]
+            }
+            .decompress()
+            .is_err()
+        );
+    }
+}
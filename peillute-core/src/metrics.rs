@@ -0,0 +1,217 @@
+//! Metrics endpoint of the site
+//!
+//! `GET /metrics` on the web server returns the state of the site in the
+//! Prometheus text format, so that a monitoring system can scrape it:
+//! storage usage, depth of the critical command queue, age of the last
+//! snapshot, clock anomalies, concurrency between the sites, time spent
+//! writing the transactions and time the global state is held.
+
+#![cfg(feature = "server")]
+
+/// Appends a metric with its help line to a Prometheus text document
+fn push_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    use std::fmt::Write;
+
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Appends the hold time histogram of the locks of the global state to a
+/// Prometheus text document
+fn push_lock_holds(out: &mut String) {
+    use std::fmt::Write;
+
+    let name = "peillute_lock_hold_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {} Time the components of the global state are held.",
+        name
+    );
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for histogram in crate::lock_stats::histograms() {
+        for (bound, count) in crate::lock_stats::BUCKETS.iter().zip(&histogram.buckets) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{lock=\"{}\",le=\"{}\"}} {}",
+                name, histogram.lock, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{lock=\"{}\",le=\"+Inf\"}} {}",
+            name, histogram.lock, histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{{lock=\"{}\"}} {}",
+            name,
+            histogram.lock,
+            histogram.sum.as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{{lock=\"{}\"}} {}",
+            name, histogram.lock, histogram.count
+        );
+    }
+}
+
+/// Renders the metrics of the site in the Prometheus text format
+pub async fn render() -> impl axum::response::IntoResponse {
+    let mut out = String::new();
+
+    match crate::storage::usage() {
+        Ok(usage) => {
+            push_metric(
+                &mut out,
+                "peillute_db_size_bytes",
+                "gauge",
+                "Size of the database.",
+                usage.db_size,
+            );
+            push_metric(
+                &mut out,
+                "peillute_free_disk_bytes",
+                "gauge",
+                "Space available on the disk holding the database.",
+                usage.free_space,
+            );
+            push_metric(
+                &mut out,
+                "peillute_min_free_disk_bytes",
+                "gauge",
+                "Free space below which new transactions are refused.",
+                usage.min_free_space,
+            );
+            push_metric(
+                &mut out,
+                "peillute_max_db_size_bytes",
+                "gauge",
+                "Size of the database above which new transactions are refused, 0 if unlimited.",
+                usage.max_db_size,
+            );
+            push_metric(
+                &mut out,
+                "peillute_transactions_refused",
+                "gauge",
+                "1 if new transactions are refused for lack of storage.",
+                u8::from(usage.refusal().is_some()),
+            );
+        }
+        Err(e) => log::warn!("Cannot measure the storage for the metrics: {}", e),
+    }
+
+    let (pending, max_pending) = {
+        let cs = crate::state::CRITICAL_SECTION.lock().await;
+        (cs.pending_commands.len(), cs.max_pending_commands)
+    };
+    push_metric(
+        &mut out,
+        "peillute_pending_critical_commands",
+        "gauge",
+        "Critical commands waiting for the global mutex.",
+        pending,
+    );
+    push_metric(
+        &mut out,
+        "peillute_max_pending_critical_commands",
+        "gauge",
+        "Maximum number of critical commands waiting for the global mutex.",
+        max_pending,
+    );
+
+    match crate::snapshot::last_snapshot_age() {
+        Ok(Some(age)) => push_metric(
+            &mut out,
+            "peillute_last_snapshot_age_seconds",
+            "gauge",
+            "Time elapsed since the last snapshot file was written.",
+            age.as_secs(),
+        ),
+        Ok(None) => {}
+        Err(e) => log::warn!("Cannot read the snapshots for the metrics: {}", e),
+    }
+
+    let clock_metrics = crate::causality::CAUSALITY_MONITOR
+        .lock()
+        .unwrap()
+        .metrics();
+    push_metric(
+        &mut out,
+        "peillute_causality_violations_total",
+        "counter",
+        "Diffused transactions whose clock implies unseen prior events.",
+        clock_metrics.causality_violations,
+    );
+
+    let concurrency = crate::concurrency::metrics();
+    push_metric(
+        &mut out,
+        "peillute_concurrent_transactions_total",
+        "counter",
+        "Diffused transactions whose vector clock is incomparable with the local one.",
+        concurrency.concurrent_transactions,
+    );
+    push_metric(
+        &mut out,
+        "peillute_duplicate_transactions_total",
+        "counter",
+        "Transactions received again and skipped.",
+        concurrency.duplicate_transactions,
+    );
+    push_metric(
+        &mut out,
+        "peillute_overdraft_conflicts_total",
+        "counter",
+        "Received transactions refused for insufficient funds.",
+        concurrency.overdraft_conflicts,
+    );
+    let (waves, wave_time) = crate::concurrency::wave_stats();
+    push_metric(
+        &mut out,
+        "peillute_wave_completion_seconds_sum",
+        "counter",
+        "Time taken by the waves initiated by the site to complete.",
+        wave_time.as_secs_f64(),
+    );
+    push_metric(
+        &mut out,
+        "peillute_wave_completion_seconds_count",
+        "counter",
+        "Waves initiated by the site that completed.",
+        waves,
+    );
+
+    let (writes, write_time) = crate::db::write_stats();
+    push_metric(
+        &mut out,
+        "peillute_db_write_seconds_sum",
+        "counter",
+        "Time spent writing the transactions to the database.",
+        write_time.as_secs_f64(),
+    );
+    push_metric(
+        &mut out,
+        "peillute_db_write_seconds_count",
+        "counter",
+        "Transactions written to the database.",
+        writes,
+    );
+
+    push_lock_holds(&mut out);
+
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        out,
+    )
+}
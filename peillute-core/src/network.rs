@@ -0,0 +1,2896 @@
+//! Network communication and peer management
+//!
+//! This module handles all network-related functionality, including peer discovery,
+//! message sending/receiving, and connection management in the distributed system.
+
+/// State of the connection to a peer, as displayed on the Info page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ConnectionState {
+    /// A writer task is alive for this peer
+    Connected,
+    /// The last connection attempts failed, the next one is delayed
+    Backoff,
+    /// Too many connection attempts failed, the peer is no longer contacted
+    /// until it sends us a message
+    Dead,
+}
+
+/// Traffic exchanged with a peer, as displayed on the Info page
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PeerTraffic {
+    /// Address of the peer
+    pub addr: String,
+    /// Number of bytes sent to the peer
+    pub bytes_sent: u64,
+    /// Number of bytes received from the peer
+    pub bytes_received: u64,
+    /// Number of messages sent to the peer
+    pub messages_sent: u64,
+    /// Number of messages received from the peer
+    pub messages_received: u64,
+    /// Seconds since the last message exchanged with the peer, if any
+    pub idle_secs: Option<u64>,
+}
+
+/// Transport used to exchange messages with the peers
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Length-prefixed frames over raw TCP connections
+    #[default]
+    Tcp,
+    /// gRPC service described in `proto/peillute.proto`
+    Grpc,
+}
+
+#[cfg(feature = "server")]
+/// Delay before the first reconnection attempt to a peer
+const BACKOFF_BASE: std::time::Duration = std::time::Duration::from_millis(200);
+
+#[cfg(feature = "server")]
+/// Maximum delay between two reconnection attempts to a peer
+const BACKOFF_MAX: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[cfg(feature = "server")]
+/// Number of consecutive failed connection attempts before a peer is considered dead
+pub const MAX_CONNECTION_RETRIES: u32 = 8;
+
+#[cfg(feature = "server")]
+/// Maximum time spent establishing a TCP connection to a peer
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[cfg(feature = "server")]
+/// Connections to non-neighbour peers unused for this long are closed
+pub const IDLE_CONNECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+#[cfg(feature = "server")]
+/// Interval between two passes of the connection reaper
+const REAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[cfg(feature = "server")]
+/// Represents a connection to a peer node
+pub struct PeerConnection {
+    /// Channel sender for sending messages to the peer
+    pub sender: tokio::sync::mpsc::Sender<Vec<u8>>,
+    /// Last time a message was queued on this connection
+    pub last_used: std::time::Instant,
+}
+
+#[cfg(feature = "server")]
+/// Traffic counters of a peer, kept across its connections
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrafficCounters {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Last time a message was sent to or received from the peer
+    pub last_activity: Option<std::time::Instant>,
+}
+
+#[cfg(feature = "server")]
+impl TrafficCounters {
+    /// Counts a message of `bytes` sent to the peer
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as u64;
+        self.messages_sent += 1;
+        self.last_activity = Some(std::time::Instant::now());
+    }
+
+    /// Counts a message of `bytes` received from the peer
+    pub fn record_received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
+        self.messages_received += 1;
+        self.last_activity = Some(std::time::Instant::now());
+    }
+}
+
+#[cfg(feature = "server")]
+/// Traffic counters shared between the manager and the writer task of a peer
+pub type SharedTraffic = std::sync::Arc<std::sync::Mutex<TrafficCounters>>;
+
+#[cfg(feature = "server")]
+/// Reconnection state of a peer we failed to connect to
+#[derive(Debug, Clone, Copy)]
+pub struct PeerBackoff {
+    /// Number of consecutive failed connection attempts
+    pub failures: u32,
+    /// No connection attempt is made before this instant
+    pub retry_at: std::time::Instant,
+}
+
+#[cfg(feature = "server")]
+/// Manages network connections and peer communication
+pub struct NetworkManager {
+    /// Number of currently active peer connections
+    pub nb_active_connections: u16,
+    /// Pool of active peer connections
+    pub connection_pool: std::collections::HashMap<std::net::SocketAddr, PeerConnection>,
+    /// Reconnection state of the peers we failed to connect to
+    pub backoff: std::collections::HashMap<std::net::SocketAddr, PeerBackoff>,
+    /// Traffic exchanged with each peer since the start of the site
+    pub traffic: std::collections::HashMap<std::net::SocketAddr, SharedTraffic>,
+    /// Version of the protocol negotiated with each peer during the discovery
+    pub protocol_versions: std::collections::HashMap<std::net::SocketAddr, u8>,
+}
+
+#[cfg(feature = "server")]
+impl Default for NetworkManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "server")]
+impl NetworkManager {
+    /// Creates a new NetworkManager instance
+    pub fn new() -> Self {
+        Self {
+            nb_active_connections: 0,
+            connection_pool: std::collections::HashMap::new(),
+            backoff: std::collections::HashMap::new(),
+            traffic: std::collections::HashMap::new(),
+            protocol_versions: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Adds a new peer connection to the connection pool
+    fn add_connection(
+        &mut self,
+        site_addr: std::net::SocketAddr,
+        sender: tokio::sync::mpsc::Sender<Vec<u8>>,
+    ) {
+        let previous = self.connection_pool.insert(
+            site_addr,
+            PeerConnection {
+                sender,
+                last_used: std::time::Instant::now(),
+            },
+        );
+        if previous.is_none() {
+            self.nb_active_connections += 1;
+        }
+    }
+
+    /// Registers the write half of a connection accepted from a peer
+    ///
+    /// The replies to the peer then reuse its socket instead of dialing a new
+    /// connection. Returns the write half back if a live connection to the
+    /// peer is already in the pool.
+    pub async fn adopt_connection(
+        &mut self,
+        site_addr: std::net::SocketAddr,
+        writer: tokio::net::tcp::OwnedWriteHalf,
+    ) -> Option<tokio::net::tcp::OwnedWriteHalf> {
+        if self.get_sender(&site_addr).is_some() {
+            return Some(writer);
+        }
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+        spawn_writer_task(writer, rx, self.traffic_of(site_addr)).await;
+        self.remove_connection(&site_addr);
+        self.add_connection(site_addr, tx);
+        self.backoff.remove(&site_addr);
+        log::debug!("Reusing the connection accepted from {}", site_addr);
+        None
+    }
+
+    /// Establishes a new connection to a peer
+    ///
+    /// Fails without trying if the peer is in backoff or dead, and records
+    /// the failure of the attempt otherwise. The peer may reply on the same
+    /// connection, so its messages are also read from it.
+    pub async fn create_connection(
+        &mut self,
+        site_addr: std::net::SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        use tokio::net::TcpStream;
+        use tokio::sync::mpsc;
+
+        self.check_retry(&site_addr)?;
+
+        let mut stream =
+            match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(site_addr)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    self.record_failure(site_addr);
+                    return Err(e.into());
+                }
+                Err(_) => {
+                    self.record_failure(site_addr);
+                    return Err(format!("connection to {} timed out", site_addr).into());
+                }
+            };
+        if let Err(e) =
+            crate::peer_auth::handshake(&mut stream, crate::peer_auth::Side::Dialer).await
+        {
+            self.record_failure(site_addr);
+            return Err(format!("connection to {} refused: {}", site_addr, e).into());
+        }
+        let (reader, writer) = stream.into_split();
+        let (tx, rx) = mpsc::channel(256);
+        spawn_writer_task(writer, rx, self.traffic_of(site_addr)).await;
+        spawn_reader_task(reader, site_addr);
+        self.add_connection(site_addr, tx);
+        self.backoff.remove(&site_addr);
+        Ok(())
+    }
+
+    /// Remove and destroy a connection
+    pub fn remove_connection(&mut self, site_addr: &std::net::SocketAddr) {
+        if self.connection_pool.remove(site_addr).is_some() {
+            self.nb_active_connections = self.nb_active_connections.saturating_sub(1);
+        }
+    }
+
+    /// Returns the message sender for a specific peer address
+    ///
+    /// Connections whose writer task has stopped are ignored.
+    pub fn get_sender(
+        &self,
+        addr: &std::net::SocketAddr,
+    ) -> Option<tokio::sync::mpsc::Sender<Vec<u8>>> {
+        self.connection_pool
+            .get(addr)
+            .filter(|p| !p.sender.is_closed())
+            .map(|p| p.sender.clone())
+    }
+
+    /// Returns the version of the protocol to use with a peer
+    ///
+    /// The lowest supported version is used until one is negotiated.
+    pub fn protocol_version(&self, addr: &std::net::SocketAddr) -> u8 {
+        self.protocol_versions
+            .get(addr)
+            .copied()
+            .unwrap_or(*crate::codec::PROTOCOL_VERSIONS.start())
+    }
+
+    /// Marks a connection as used now
+    pub fn touch(&mut self, addr: &std::net::SocketAddr) {
+        if let Some(connection) = self.connection_pool.get_mut(addr) {
+            connection.last_used = std::time::Instant::now();
+        }
+    }
+
+    /// Checks whether a connection attempt to a peer is allowed now
+    pub fn check_retry(&self, addr: &std::net::SocketAddr) -> Result<(), String> {
+        match self.backoff.get(addr) {
+            Some(b) if b.failures >= MAX_CONNECTION_RETRIES => Err(format!(
+                "peer {} considered dead after {} failed attempts",
+                addr, b.failures
+            )),
+            Some(b) if std::time::Instant::now() < b.retry_at => Err(format!(
+                "peer {} in backoff for {} ms",
+                addr,
+                b.retry_at
+                    .saturating_duration_since(std::time::Instant::now())
+                    .as_millis()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a failed connection attempt and schedules the next one
+    pub fn record_failure(&mut self, addr: std::net::SocketAddr) {
+        let failures = self.backoff.get(&addr).map(|b| b.failures).unwrap_or(0) + 1;
+        let delay = backoff_delay(failures, jitter_seed());
+        log::debug!(
+            "Connection to {} failed {} time(s), next attempt in {:?}",
+            addr,
+            failures,
+            delay
+        );
+        self.backoff.insert(
+            addr,
+            PeerBackoff {
+                failures,
+                retry_at: std::time::Instant::now() + delay,
+            },
+        );
+    }
+
+    /// Forgets the failed connection attempts to a peer
+    ///
+    /// Called when the peer shows up again by sending us a message.
+    pub fn reset_backoff(&mut self, addr: &std::net::SocketAddr) {
+        if self.backoff.remove(addr).is_some() {
+            log::debug!("Peer {} is reachable again", addr);
+        }
+    }
+
+    /// Returns the state of the connection to a peer, if we know this peer
+    pub fn connection_state(&self, addr: &std::net::SocketAddr) -> Option<ConnectionState> {
+        if self.get_sender(addr).is_some() {
+            return Some(ConnectionState::Connected);
+        }
+        self.backoff.get(addr).map(|b| {
+            if b.failures >= MAX_CONNECTION_RETRIES {
+                ConnectionState::Dead
+            } else {
+                ConnectionState::Backoff
+            }
+        })
+    }
+
+    /// Returns the state of the connection to every known peer
+    pub fn connection_states(&self) -> Vec<(std::net::SocketAddr, ConnectionState)> {
+        let mut addrs: Vec<std::net::SocketAddr> = self
+            .connection_pool
+            .keys()
+            .chain(self.backoff.keys())
+            .copied()
+            .collect();
+        addrs.sort();
+        addrs.dedup();
+        addrs
+            .into_iter()
+            .filter_map(|addr| self.connection_state(&addr).map(|state| (addr, state)))
+            .collect()
+    }
+
+    /// Returns the traffic counters of a peer, created on first use
+    pub fn traffic_of(&mut self, addr: std::net::SocketAddr) -> SharedTraffic {
+        self.traffic.entry(addr).or_default().clone()
+    }
+
+    /// Counts a message of `bytes` sent to a peer
+    pub fn record_sent(&mut self, addr: std::net::SocketAddr, bytes: usize) {
+        self.traffic_of(addr).lock().unwrap().record_sent(bytes);
+    }
+
+    /// Counts a message of `bytes` received from a peer
+    pub fn record_received(&mut self, addr: std::net::SocketAddr, bytes: usize) {
+        self.traffic_of(addr).lock().unwrap().record_received(bytes);
+    }
+
+    /// Returns the traffic exchanged with every peer, the busiest first
+    pub fn traffic(&self) -> Vec<PeerTraffic> {
+        let mut traffic: Vec<PeerTraffic> = self
+            .traffic
+            .iter()
+            .map(|(addr, counters)| {
+                let counters = *counters.lock().unwrap();
+                PeerTraffic {
+                    addr: addr.to_string(),
+                    bytes_sent: counters.bytes_sent,
+                    bytes_received: counters.bytes_received,
+                    messages_sent: counters.messages_sent,
+                    messages_received: counters.messages_received,
+                    idle_secs: counters.last_activity.map(|at| at.elapsed().as_secs()),
+                }
+            })
+            .collect();
+        traffic.sort_by(|a, b| {
+            (b.bytes_sent + b.bytes_received)
+                .cmp(&(a.bytes_sent + a.bytes_received))
+                .then_with(|| a.addr.cmp(&b.addr))
+        });
+        traffic
+    }
+
+    /// Removes broken connections and idle connections to non-neighbour peers
+    ///
+    /// Connections to neighbours are kept even when idle, closing them would
+    /// make the neighbour think we left the network.
+    /// Returns the addresses of the removed connections.
+    pub fn reap(
+        &mut self,
+        neighbours: &[std::net::SocketAddr],
+        idle_timeout: std::time::Duration,
+    ) -> Vec<std::net::SocketAddr> {
+        let now = std::time::Instant::now();
+        let to_remove: Vec<std::net::SocketAddr> = self
+            .connection_pool
+            .iter()
+            .filter(|(addr, connection)| {
+                connection.sender.is_closed()
+                    || (!neighbours.contains(addr)
+                        && now.duration_since(connection.last_used) > idle_timeout)
+            })
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in &to_remove {
+            self.remove_connection(addr);
+        }
+        to_remove
+    }
+}
+
+#[cfg(feature = "server")]
+/// Computes the delay before the next connection attempt to a peer
+///
+/// The delay doubles with each failure up to `BACKOFF_MAX`, then up to half
+/// of it is added as jitter so that sites do not retry all at once.
+pub fn backoff_delay(failures: u32, jitter_seed: u64) -> std::time::Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    let delay = BACKOFF_BASE
+        .saturating_mul(1u32 << exponent)
+        .min(BACKOFF_MAX);
+    let max_jitter_ms = (delay.as_millis() as u64 / 2).max(1);
+    delay + std::time::Duration::from_millis(jitter_seed % max_jitter_ms)
+}
+
+#[cfg(feature = "server")]
+/// Returns a pseudo random seed for the backoff jitter
+fn jitter_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(feature = "server")]
+/// Spawns the task periodically removing broken and idle connections
+pub fn connection_reaper() {
+    tokio::spawn(async {
+        use crate::state::PEERS;
+
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let neighbours = PEERS.read().await.get_connected_nei_addr();
+            let removed = {
+                let mut manager = NETWORK_MANAGER.lock().await;
+                manager.reap(&neighbours, IDLE_CONNECTION_TIMEOUT)
+            };
+            if !removed.is_empty() {
+                log::debug!("Reaped connections: {:?}", removed);
+            }
+        }
+    });
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    pub static ref NETWORK_MANAGER: std::sync::Arc<tokio::sync::Mutex<NetworkManager>> =
+        std::sync::Arc::new(tokio::sync::Mutex::new(NetworkManager::new()));
+}
+
+#[cfg(feature = "server")]
+/// Default maximum size of a message exchanged between sites, in bytes
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+#[cfg(feature = "server")]
+/// Size of an encoded snapshot response above which it is compressed, in bytes
+const SNAPSHOT_COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+#[cfg(feature = "server")]
+/// Size of the header of each frame, in bytes
+///
+/// The header holds the length of the message on 4 bytes, the version of the
+/// protocol and the format the message is encoded in.
+const FRAME_HEADER_SIZE: usize = 6;
+
+#[cfg(feature = "server")]
+/// Maximum size of a message, set from the command line at startup
+static MAX_MESSAGE_SIZE: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(DEFAULT_MAX_MESSAGE_SIZE);
+
+#[cfg(feature = "server")]
+/// Transport of the site, set from the command line at startup
+static TRANSPORT: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+#[cfg(feature = "server")]
+/// Sets the transport used to exchange messages with the peers
+pub fn set_transport(transport: Transport) {
+    TRANSPORT.store(transport as u8, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Returns the transport used to exchange messages with the peers
+pub fn transport() -> Transport {
+    match TRANSPORT.load(std::sync::atomic::Ordering::Relaxed) {
+        1 => Transport::Grpc,
+        _ => Transport::Tcp,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Sets the maximum size of the messages sent and accepted by this site
+pub fn set_max_message_size(size: usize) {
+    MAX_MESSAGE_SIZE.store(size, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Returns the maximum size of the messages sent and accepted by this site
+pub fn max_message_size() -> usize {
+    MAX_MESSAGE_SIZE.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+#[cfg(feature = "server")]
+/// Prefixes an encoded message with its length, protocol version and format
+///
+/// Fails if the message is larger than `max_size`.
+pub fn encode_frame(
+    payload: &[u8],
+    version: u8,
+    format: crate::codec::WireFormat,
+    max_size: usize,
+) -> Result<Vec<u8>, String> {
+    if payload.len() > max_size || payload.len() > u32::MAX as usize {
+        return Err(format!(
+            "message of {} bytes exceeds the limit of {} bytes",
+            payload.len(),
+            max_size
+        ));
+    }
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.push(version);
+    frame.push(format.to_byte());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+#[cfg(feature = "server")]
+/// Frame read from a peer connection
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    /// Encoded message
+    Message {
+        /// Version of the protocol of the sender
+        version: u8,
+        /// Byte identifying the format of the message
+        format: u8,
+        /// Encoded message, sharing the read buffer of the connection
+        payload: bytes::Bytes,
+    },
+    /// Message larger than the limit, its content was discarded
+    Oversized(usize),
+}
+
+#[cfg(feature = "server")]
+/// Smallest capacity of the read buffer of a peer connection, in bytes
+const MIN_READ_BUFFER: usize = 8 * 1024;
+
+#[cfg(feature = "server")]
+/// Reads the frames of a peer connection into a buffer reused across them
+///
+/// The bytes are read with [`tokio::io::AsyncReadExt::read_buf`] into a
+/// [`bytes::BytesMut`], and each payload is split off the buffer without
+/// being copied. Its memory is reused for the next frames once the payload
+/// is dropped. The buffer is sized after the recent frames: it grows on a
+/// burst of large messages, such as snapshot responses, and shrinks back once
+/// the frames are small again, so that idle connections stay cheap.
+pub struct FrameReader<R> {
+    /// Read half of the connection
+    reader: R,
+    /// Bytes read and not returned yet
+    buffer: bytes::BytesMut,
+    /// Moving average of the size of the frames read, in bytes
+    average_frame: usize,
+}
+
+#[cfg(feature = "server")]
+impl<R> FrameReader<R>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    /// Creates a reader of the frames of a connection
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: bytes::BytesMut::with_capacity(MIN_READ_BUFFER),
+            average_frame: 0,
+        }
+    }
+
+    /// Returns the capacity the buffer should have for the recent frames
+    fn target_capacity(&self) -> usize {
+        (4 * self.average_frame).max(MIN_READ_BUFFER)
+    }
+
+    /// Reads until the buffer holds at least `needed` bytes
+    ///
+    /// Returns false if the connection is closed first.
+    async fn fill(&mut self, needed: usize) -> std::io::Result<bool> {
+        use tokio::io::AsyncReadExt;
+
+        while self.buffer.len() < needed {
+            let missing = needed - self.buffer.len();
+            self.buffer.reserve(missing.max(self.target_capacity()));
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Records the size of a frame and shrinks the buffer if it outgrew the
+    /// recent frames
+    ///
+    /// The few bytes already read for the next frames are moved to the
+    /// smaller buffer.
+    fn adapt(&mut self, len: usize) {
+        self.average_frame = (self.average_frame * 7 + len) / 8;
+        let target = self.target_capacity();
+        if self.buffer.capacity() > 4 * target && self.buffer.len() <= target {
+            let mut smaller = bytes::BytesMut::with_capacity(target);
+            smaller.extend_from_slice(&self.buffer);
+            self.buffer = smaller;
+        }
+    }
+
+    /// Reads the next frame
+    ///
+    /// Returns None when the connection is closed between two frames.
+    /// Oversized frames are skipped without being buffered so the connection
+    /// stays usable.
+    pub async fn read_frame(&mut self, max_size: usize) -> std::io::Result<Option<Frame>> {
+        use bytes::Buf;
+        use tokio::io::AsyncReadExt;
+
+        if !self.fill(FRAME_HEADER_SIZE).await? {
+            return Ok(None);
+        }
+        let header = &self.buffer[..FRAME_HEADER_SIZE];
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let (version, format) = (header[4], header[5]);
+        self.buffer.advance(FRAME_HEADER_SIZE);
+
+        if len > max_size {
+            let buffered = len.min(self.buffer.len());
+            self.buffer.advance(buffered);
+            let rest = (len - buffered) as u64;
+            let skipped =
+                tokio::io::copy(&mut (&mut self.reader).take(rest), &mut tokio::io::sink()).await?;
+            if skipped < rest {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            return Ok(Some(Frame::Oversized(len)));
+        }
+
+        if !self.fill(len).await? {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let payload = self.buffer.split_to(len).freeze();
+        self.adapt(len);
+        Ok(Some(Frame::Message {
+            version,
+            format,
+            payload,
+        }))
+    }
+}
+
+#[cfg(feature = "server")]
+/// Spawns a task to handle writing messages to a peer connection
+pub async fn spawn_writer_task<W>(
+    stream: W,
+    mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    traffic: SharedTraffic,
+) where
+    W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    use tokio::io::AsyncWriteExt;
+
+    tokio::spawn(async move {
+        let mut stream = stream;
+        while let Some(data) = rx.recv().await {
+            if stream.write_all(&data).await.is_err() {
+                log::error!("Failed to send message");
+                break;
+            }
+            traffic.lock().unwrap().record_sent(data.len());
+        }
+        log::debug!("Writer task closed.");
+    });
+}
+
+#[cfg(feature = "server")]
+/// Timeout of the asynchronous DNS lookup of a peer
+const DNS_LOOKUP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+#[cfg(feature = "server")]
+/// Resolves a peer given as `ip:port`, `[ipv6]:port` or `hostname:port`
+///
+/// Literal addresses are parsed directly. Hostnames are resolved with an
+/// asynchronous lookup, falling back to the blocking system resolver if the
+/// lookup fails or times out. Addresses of the same family as `local_ip` are
+/// preferred when the hostname resolves to several addresses.
+pub async fn resolve_peer(peer: &str, local_ip: std::net::IpAddr) -> Option<std::net::SocketAddr> {
+    let peer = peer.trim();
+    if let Ok(addr) = peer.parse::<std::net::SocketAddr>() {
+        return Some(addr);
+    }
+
+    let resolved: Vec<std::net::SocketAddr> =
+        match tokio::time::timeout(DNS_LOOKUP_TIMEOUT, tokio::net::lookup_host(peer)).await {
+            Ok(Ok(addrs)) => addrs.collect(),
+            Ok(Err(e)) => {
+                log::debug!("Async lookup of {} failed: {}, falling back", peer, e);
+                Vec::new()
+            }
+            Err(_) => {
+                log::debug!("Async lookup of {} timed out, falling back", peer);
+                Vec::new()
+            }
+        };
+
+    let resolved = if resolved.is_empty() {
+        let owned_peer = peer.to_string();
+        tokio::task::spawn_blocking(move || {
+            use std::net::ToSocketAddrs;
+            owned_peer
+                .to_socket_addrs()
+                .map(|addrs| addrs.collect::<Vec<_>>())
+                .unwrap_or_default()
+        })
+        .await
+        .unwrap_or_default()
+    } else {
+        resolved
+    };
+
+    resolved
+        .iter()
+        .find(|addr| addr.is_ipv6() == local_ip.is_ipv6())
+        .or(resolved.first())
+        .copied()
+}
+
+#[cfg(feature = "server")]
+/// Resolves all the peers given in arguments
+///
+/// Peers that cannot be resolved are logged and skipped.
+pub async fn resolve_peers(
+    peers: &[String],
+    local_ip: std::net::IpAddr,
+) -> Vec<std::net::SocketAddr> {
+    let mut addrs = Vec::new();
+    for peer in peers {
+        match resolve_peer(peer, local_ip).await {
+            Some(addr) => {
+                log::debug!("Peer {} resolved to {}", peer, addr);
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+            None => log::warn!("Unable to resolve peer {}, skipping it", peer),
+        }
+    }
+    addrs
+}
+
+#[cfg(feature = "server")]
+/// Binds a TCP listener on the given address
+///
+/// When the address is the IPv6 unspecified address (`[::]`), the socket is
+/// made dual-stack so that IPv4 peers can connect as well.
+pub fn bind_listener(addr: std::net::SocketAddr) -> std::io::Result<tokio::net::TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+#[cfg(feature = "server")]
+/// Returns the address to use to reach local peers when scanning the port range
+///
+/// An unspecified bind address (`0.0.0.0` or `::`) cannot be used as a
+/// destination, so the loopback of the same family is used instead.
+pub fn scan_ip(ip: std::net::IpAddr) -> std::net::IpAddr {
+    match ip {
+        std::net::IpAddr::V4(v4) if v4.is_unspecified() => {
+            std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST)
+        }
+        std::net::IpAddr::V6(v6) if v6.is_unspecified() => {
+            std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)
+        }
+        other => other,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Announces this node's presence to potential peers in the network.
+/// If the user gave peers in args, we will only connect to those peers.
+/// If not, we will try the peers known from the previous runs first, see
+/// [`crate::known_peers`], then scan the port range and try connecting to all
+/// sockets if none of them answers.
+pub async fn announce(ip: std::net::IpAddr, start_port: u16, end_port: u16, selected_port: u16) {
+    use crate::state::SITE_INFO;
+
+    let (_, local_addr) = crate::state::local_site().await;
+    let cli_peers = SITE_INFO.read().await.get_cli_peers_addrs();
+
+    let reached = if !cli_peers.is_empty() {
+        log::debug!("Manually connecting to peers based on args");
+        send_discoveries(cli_peers).await
+    } else {
+        let known = crate::known_peers::known(local_addr);
+        let mut reached = Vec::new();
+        if !known.is_empty() {
+            log::debug!("Trying the {} peers known from the last runs", known.len());
+            reached = send_discoveries(known.clone()).await;
+            for addr in known.into_iter().filter(|addr| !reached.contains(addr)) {
+                crate::known_peers::unreachable(addr);
+            }
+        }
+        if reached.is_empty() {
+            log::debug!("Looking for all ports to find potential peers");
+            reached = send_discoveries(
+                (start_port..=end_port)
+                    .filter(|&port| port != selected_port)
+                    .map(|port| std::net::SocketAddr::new(scan_ip(ip), port))
+                    .collect(),
+            )
+            .await;
+        }
+        reached
+    };
+
+    // Update the number of attended neighbours
+    SITE_INFO
+        .write()
+        .await
+        .init_nb_first_attended_neighbours(reached.len() as i64);
+}
+
+#[cfg(feature = "server")]
+/// Sends a discovery message to peers, returning the ones that could be reached
+async fn send_discoveries(peers: Vec<std::net::SocketAddr>) -> Vec<std::net::SocketAddr> {
+    use crate::message::{MessageInfo, NetworkMessageCode};
+    use crate::state::SITE_INFO;
+
+    //If there are no peers, we don't need to do anything
+    if peers.is_empty() {
+        return Vec::new();
+    } else {
+        SITE_INFO.write().await.init_sync(true); // we need to sync with other sites
+    }
+
+    let (site_id, local_addr) = crate::state::local_site().await;
+    let clocks = crate::state::get_clock().await;
+    let alias = SITE_INFO.read().await.get_site_alias();
+
+    // Send discovery messages after the decision logic
+    let mut handles = Vec::new();
+    for addr in peers {
+        let site_id = site_id.clone();
+        let clocks = clocks.clone();
+        let alias = alias.clone();
+
+        let handle = tokio::spawn(async move {
+            let result = send_message(
+                addr,
+                MessageInfo::Discovery(crate::message::DiscoveryPayload {
+                    min_version: *crate::codec::PROTOCOL_VERSIONS.start(),
+                    max_version: *crate::codec::PROTOCOL_VERSIONS.end(),
+                    alias,
+                }),
+                None,
+                NetworkMessageCode::Discovery,
+                local_addr,
+                &site_id,
+                &site_id,
+                local_addr,
+                clocks,
+            )
+            .await;
+            result.is_ok().then_some(addr)
+        });
+        handles.push(handle);
+    }
+
+    // Await all task
+    let mut reached = Vec::new();
+    for handle in handles {
+        if let Ok(Some(addr)) = handle.await {
+            reached.push(addr);
+        }
+    }
+    reached
+}
+
+#[cfg(feature = "server")]
+/// Starts listening for messages from a new peer
+pub async fn start_listening(stream: tokio::net::TcpStream, addr: std::net::SocketAddr) {
+    if !crate::peer_auth::is_allowed(addr.ip()) {
+        log::warn!("Refused a connection from {}, not allowed", addr);
+        return;
+    }
+    log::debug!("Accepted connection from: {}", addr);
+
+    tokio::spawn(async move {
+        if let Err(e) = handle_network_message(stream, addr).await {
+            log::error!("Error handling connection from {}: {}", addr, e);
+        }
+    });
+}
+
+#[cfg(feature = "server")]
+/// Number of transaction waves remembered to drop their duplicate copies
+const SEEN_WAVES_CAPACITY: usize = 4096;
+
+#[cfg(feature = "server")]
+/// Transaction waves received recently, identified by their initiator and
+/// the Lamport time of their message
+///
+/// Every neighbour forwards a wave it receives, so a site gets a copy of a
+/// transaction from each of its neighbours. Only the first one is applied:
+/// the others are acknowledged right away, without locking the state of the
+/// site nor looking the transaction up in the database. The least recently
+/// seen waves are forgotten beyond the capacity; a copy arriving after its
+/// wave was forgotten is still dropped by the database check.
+struct SeenWaves {
+    /// Greatest number of waves remembered
+    capacity: usize,
+    /// Last time each wave was seen
+    last_seen: std::collections::HashMap<(String, i64), u64>,
+    /// Waves by the last time they were seen, the oldest first
+    by_age: std::collections::BTreeMap<u64, (String, i64)>,
+    /// Incremented on each message seen
+    tick: u64,
+}
+
+#[cfg(feature = "server")]
+impl SeenWaves {
+    /// Creates an empty set of waves, remembering at most capacity of them
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            last_seen: std::collections::HashMap::new(),
+            by_age: std::collections::BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Records a message of a wave, returns true if the wave was already seen
+    fn seen(&mut self, initiator_id: &str, lamport: i64) -> bool {
+        self.tick += 1;
+        let key = (initiator_id.to_string(), lamport);
+        if let Some(previous) = self.last_seen.insert(key.clone(), self.tick) {
+            self.by_age.remove(&previous);
+            self.by_age.insert(self.tick, key);
+            return true;
+        }
+        self.by_age.insert(self.tick, key);
+        while self.last_seen.len() > self.capacity {
+            let Some((_, oldest)) = self.by_age.pop_first() else {
+                break;
+            };
+            self.last_seen.remove(&oldest);
+        }
+        false
+    }
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    /// Transaction waves received recently by the site
+    static ref SEEN_WAVES: std::sync::Mutex<SeenWaves> =
+        std::sync::Mutex::new(SeenWaves::new(SEEN_WAVES_CAPACITY));
+}
+
+#[cfg(feature = "server")]
+/// Handles a message (blue) of a wave, see [`crate::wave::WaveEngine::on_blue_message`]
+async fn on_blue_message(message: &crate::message::Message) -> crate::wave::BlueTransition {
+    use crate::state::{WAVES, get_nb_connected_neighbours};
+
+    let nb_neighbours = get_nb_connected_neighbours().await;
+    let transition = WAVES.lock().await.on_blue_message(
+        &message.message_initiator_id,
+        message.sender_addr,
+        nb_neighbours,
+    );
+    let step = match transition {
+        crate::wave::BlueTransition::Diffuse => crate::attach::WaveStep::Diffused,
+        crate::wave::BlueTransition::Acknowledge { .. } => crate::attach::WaveStep::Acknowledged,
+    };
+    crate::attach::wave_step(message, step);
+    transition
+}
+
+#[cfg(feature = "server")]
+/// Returns our parent in the wave of a message
+async fn wave_parent(message: &crate::message::Message) -> std::net::SocketAddr {
+    crate::state::WAVES
+        .lock()
+        .await
+        .get_parent_addr_for_wave(message.message_initiator_id.clone())
+}
+
+#[cfg(feature = "server")]
+/// Handles an acknowledgement (red) of a wave, see [`crate::wave::WaveEngine::on_red_message`]
+async fn on_red_message(
+    message: &crate::message::Message,
+    local_addr: std::net::SocketAddr,
+) -> crate::wave::RedTransition {
+    use crate::state::{WAVES, get_nb_connected_neighbours};
+
+    crate::telemetry::ack_received(message);
+    let nb_neighbours = get_nb_connected_neighbours().await;
+    let transition =
+        WAVES
+            .lock()
+            .await
+            .on_red_message(&message.message_initiator_id, nb_neighbours, local_addr);
+    match transition {
+        crate::wave::RedTransition::Wait => {}
+        crate::wave::RedTransition::Completed => {
+            crate::attach::wave_step(message, crate::attach::WaveStep::Completed)
+        }
+        crate::wave::RedTransition::ForwardToParent(_) => {
+            crate::attach::wave_step(message, crate::attach::WaveStep::Forwarded)
+        }
+    }
+    transition
+}
+
+#[cfg(feature = "server")]
+/// Releases the global mutex if no critical command is left
+async fn release_mutex_if_idle() -> Result<(), Box<dyn std::error::Error>> {
+    let idle = crate::state::CRITICAL_SECTION
+        .lock()
+        .await
+        .pending_commands
+        .is_empty();
+    if idle {
+        // fin de la section critique on peut notifier les pairs
+        crate::state::release_mutex().await?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Handles incoming messages from a peer
+/// Implement our wave diffusion protocol
+///
+/// The peer first proves it knows the cluster secret, if any, see
+/// [`crate::peer_auth::handshake`]. Once the peer identified itself, the
+/// write half of the stream joins the connection pool so that the replies
+/// reuse it.
+pub async fn handle_network_message(
+    mut stream: tokio::net::TcpStream,
+    socket_of_the_sender: std::net::SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    crate::peer_auth::handshake(&mut stream, crate::peer_auth::Side::Acceptor).await?;
+    let (reader, writer) = stream.into_split();
+    read_messages(reader, Some(writer), socket_of_the_sender).await
+}
+
+#[cfg(feature = "server")]
+/// Spawns the task reading the replies of a peer on a connection we opened
+///
+/// Reading a message may open a connection in turn, so the task is boxed to
+/// break the cycle between the futures of [`read_messages`] and
+/// [`NetworkManager::create_connection`].
+fn spawn_reader_task(reader: tokio::net::tcp::OwnedReadHalf, site_addr: std::net::SocketAddr) {
+    let task: std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> =
+        Box::pin(async move {
+            if let Err(e) = read_messages(reader, None, site_addr).await {
+                log::error!("Error handling connection to {}: {}", site_addr, e);
+            }
+        });
+    tokio::spawn(task);
+}
+
+#[cfg(feature = "server")]
+/// Reads the messages of a peer connection until it is closed
+///
+/// `writer` is the write half of a connection accepted from the peer, adopted
+/// by the pool unless a live connection to the peer already exists. It is
+/// kept open otherwise, so that the peer does not see the connection closed.
+async fn read_messages<R>(
+    reader: R,
+    mut writer: Option<tokio::net::tcp::OwnedWriteHalf>,
+    socket_of_the_sender: std::net::SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use crate::message::{Message, MessageInfo, NetworkMessageCode};
+    use crate::state::{get_clock, local_site};
+
+    // Address of the site on the other end, known once it sent a valid message
+    let mut known_sender: Option<std::net::SocketAddr> = None;
+    let mut chunks = crate::message::SnapshotChunkAssembler::default();
+    let mut frames = FrameReader::new(reader);
+
+    loop {
+        let frame = frames.read_frame(max_message_size()).await?;
+
+        let (version, format, buf) = match frame {
+            Some(Frame::Message {
+                version,
+                format,
+                payload,
+            }) => (version, format, payload),
+            Some(Frame::Oversized(len)) => {
+                log::error!(
+                    "Rejected a message of {} bytes from {}, the limit is {} bytes",
+                    len,
+                    socket_of_the_sender,
+                    max_message_size()
+                );
+                if let Some(sender_addr) = known_sender {
+                    let (site_id, local_addr) = local_site().await;
+                    let clock = get_clock().await;
+                    send_message(
+                        sender_addr,
+                        MessageInfo::Error(format!(
+                            "message of {} bytes rejected, the limit is {} bytes",
+                            len,
+                            max_message_size()
+                        )),
+                        None,
+                        NetworkMessageCode::Error,
+                        local_addr,
+                        &site_id,
+                        &site_id,
+                        local_addr,
+                        clock,
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                }
+                continue;
+            }
+            None => {
+                log::warn!("Connection closed by: {}", socket_of_the_sender);
+                // Here we should remove the site from the network in the app state
+                log::debug!("Removing {} from the peers", socket_of_the_sender);
+                crate::state::remove_peer_from_socket_closed(socket_of_the_sender).await;
+                return Ok(());
+            }
+        };
+
+        log::debug!("Received {} bytes from {}", buf.len(), socket_of_the_sender);
+
+        if !crate::codec::is_supported(version) {
+            log::error!(
+                "Rejected a message of protocol version {} from {}, supported versions are {:?}",
+                version,
+                socket_of_the_sender,
+                crate::codec::PROTOCOL_VERSIONS
+            );
+            continue;
+        }
+        let Some(format) = crate::codec::WireFormat::from_byte(format) else {
+            log::error!(
+                "Rejected a message in unknown format {} from {}",
+                format,
+                socket_of_the_sender
+            );
+            continue;
+        };
+        let mut message: Message = match format.decode(&buf) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::error!("Error decoding message: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = crate::peer_auth::admit(&message, socket_of_the_sender) {
+            log::warn!(
+                "Closing the connection from {}: {}",
+                socket_of_the_sender,
+                e
+            );
+            return Err(e.into());
+        }
+        known_sender = Some(message.sender_addr);
+        {
+            let mut manager = NETWORK_MANAGER.lock().await;
+            manager.record_received(message.sender_addr, FRAME_HEADER_SIZE + buf.len());
+            if let Some(half) = writer.take() {
+                writer = manager.adopt_connection(message.sender_addr, half).await;
+            }
+        }
+
+        // A large snapshot response is received in several chunks
+        if let MessageInfo::SnapshotChunk(chunk) = message.info {
+            match chunks.push(message.sender_addr, chunk) {
+                Some(response) => message.info = MessageInfo::SnapshotResponse(response),
+                None => continue,
+            }
+        }
+        if let MessageInfo::CompressedSnapshot(compressed) = &message.info {
+            match compressed.decompress() {
+                Ok(response) => message.info = MessageInfo::SnapshotResponse(response),
+                Err(e) => {
+                    log::error!("Error decompressing a snapshot response: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        process_message(message, socket_of_the_sender)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+}
+
+#[cfg(feature = "server")]
+/// Handles a message received from a peer
+///
+/// `socket_of_the_sender` identifies the connection the message came from,
+/// so that the peer can be removed when it is closed.
+pub async fn process_message(
+    message: crate::message::Message,
+    socket_of_the_sender: std::net::SocketAddr,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{MessageInfo, NetworkMessageCode};
+    use crate::state::{CRITICAL_SECTION, PEERS, SITE_INFO, WAVES, get_clock, local_site};
+    use crate::wave::{BlueTransition, RedTransition};
+
+    log::debug!(
+        "Message received from site {} : {:?}",
+        message.sender_addr,
+        message.clone()
+    );
+
+    {
+        // The sender is alive, allow reconnecting to it right away
+        let mut manager = NETWORK_MANAGER.lock().await;
+        manager.reset_backoff(&message.sender_addr);
+    }
+
+    PEERS.write().await.add_site_id(
+        message.message_initiator_id.clone(),
+        message.message_initiator_addr,
+    );
+
+    let (local_site_id, local_site_addr) = local_site().await;
+
+    match message.code {
+        NetworkMessageCode::AcquireMutex => {
+            // We store the request
+            CRITICAL_SECTION.lock().await.global_mutex_fifo.insert(
+                message.message_initiator_id.clone(),
+                crate::state::MutexStamp {
+                    tag: crate::state::MutexTag::Request,
+                    date: *message.clock.get_lamport(),
+                },
+            );
+            // wave diffusion
+            match on_blue_message(&message).await {
+                BlueTransition::Diffuse => {
+                    let mut snd_msg = message.clone();
+                    snd_msg.sender_id = local_site_id.to_string();
+                    snd_msg.sender_addr = local_site_addr;
+                    diffuse_message(&snd_msg).await?;
+                }
+                BlueTransition::Acknowledge { .. } => {
+                    // Acquit message to parent
+                    log::debug!(
+                        "Réception d'un message de d'acquisition de mutex, on est sur une feuille, on acquite, envoie à {}",
+                        message.sender_addr.to_string().as_str()
+                    );
+                    send_message(
+                        message.sender_addr,
+                        MessageInfo::AckMutex(crate::message::AckMutexPayload {
+                            clock: *message.clock.get_lamport(),
+                        }),
+                        None,
+                        NetworkMessageCode::AckGlobalMutex,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        message.clock.clone(),
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        NetworkMessageCode::AckGlobalMutex => {
+            // Message rouge
+            match on_red_message(&message, local_site_addr).await {
+                RedTransition::Wait => {}
+                RedTransition::Completed => {
+                    // on est chez le parent
+                    // diffusion terminée
+                    // Réinitialisation
+
+                    println!("\x1b[1;31mDiffusion terminée et réussie !\x1b[0m");
+                    let refused = CRITICAL_SECTION.lock().await.try_enter_sc(&local_site_id);
+                    if let Some(report) = refused {
+                        println!("{}", report);
+                    }
+                }
+                RedTransition::ForwardToParent(parent_addr) => {
+                    log::debug!(
+                        "On est de le noeud {}. On a reçu un rouge de tous nos fils: on acquite au parent {}",
+                        local_site_addr,
+                        parent_addr.to_string().as_str()
+                    );
+                    send_message(
+                        parent_addr,
+                        MessageInfo::AckMutex(crate::message::AckMutexPayload {
+                            clock: *message.clock.get_lamport(),
+                        }),
+                        None,
+                        NetworkMessageCode::AckGlobalMutex,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        get_clock().await,
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        NetworkMessageCode::AckReleaseGlobalMutex => {
+            // Message rouge
+            match on_red_message(&message, local_site_addr).await {
+                RedTransition::Wait => {}
+                RedTransition::Completed => {
+                    // on est chez le parent
+                    // diffusion terminée
+                    // Réinitialisation
+
+                    println!("\x1b[1;31mDiffusion terminée et réussie !\x1b[0m");
+                    // On vient de release la section critique, on peut essayer d'y entrer à nouveau
+                    let refused = CRITICAL_SECTION.lock().await.try_enter_sc(&local_site_id);
+                    if let Some(report) = refused {
+                        println!("{}", report);
+                    }
+                }
+                RedTransition::ForwardToParent(parent_addr) => {
+                    log::debug!(
+                        "On est de le noeud {}. On a reçu un rouge de tous nos fils: on acquite au parent {}",
+                        local_site_addr,
+                        parent_addr.to_string().as_str()
+                    );
+                    send_message(
+                        parent_addr,
+                        MessageInfo::None,
+                        None,
+                        NetworkMessageCode::AckReleaseGlobalMutex,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        get_clock().await,
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        NetworkMessageCode::ReleaseGlobalMutex => {
+            // A node is releasing the critical section
+            let refused = {
+                let mut cs = CRITICAL_SECTION.lock().await;
+                cs.global_mutex_fifo.remove(&message.message_initiator_id);
+                cs.try_enter_sc(&local_site_id)
+            };
+            if let Some(report) = refused {
+                println!("{}", report);
+            }
+            // wave diffusion
+            match on_blue_message(&message).await {
+                BlueTransition::Diffuse => {
+                    let mut snd_msg = message.clone();
+                    snd_msg.sender_id = local_site_id.to_string();
+                    snd_msg.sender_addr = local_site_addr;
+                    diffuse_message(&snd_msg).await?;
+                }
+                BlueTransition::Acknowledge { .. } => {
+                    // Acquit message to parent
+                    log::debug!(
+                        "Réception d'un message de relachement de mutex global, on est sur une feuille, on acquite, envoie à {}",
+                        message.sender_addr.to_string().as_str()
+                    );
+                    send_message(
+                        message.sender_addr,
+                        MessageInfo::None,
+                        None,
+                        NetworkMessageCode::AckReleaseGlobalMutex,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        message.clock.clone(),
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        NetworkMessageCode::Discovery => {
+            // Agree on the highest version of the protocol both sites support,
+            // sites predating the negotiation only speaking the first one
+            let (min_version, max_version) = match &message.info {
+                MessageInfo::Discovery(payload) => (payload.min_version, payload.max_version),
+                _ => (1, 1),
+            };
+            if let MessageInfo::Discovery(crate::message::DiscoveryPayload {
+                alias: Some(alias),
+                ..
+            }) = &message.info
+            {
+                crate::aliases::record(&message.message_initiator_id, alias).await;
+            }
+            let Some(protocol_version) = crate::codec::negotiate(min_version, max_version) else {
+                log::error!(
+                    "Site {} supports protocol versions {}..={}, incompatible with {:?}",
+                    message.sender_addr,
+                    min_version,
+                    max_version,
+                    crate::codec::PROTOCOL_VERSIONS
+                );
+                send_message(
+                    message.sender_addr,
+                    MessageInfo::Error(format!(
+                        "no common protocol version, this site supports {:?}",
+                        crate::codec::PROTOCOL_VERSIONS
+                    )),
+                    None,
+                    NetworkMessageCode::Error,
+                    local_site_addr,
+                    local_site_id.as_str(),
+                    local_site_id.as_str(),
+                    local_site_addr,
+                    get_clock().await,
+                )
+                .await?;
+                return Ok(());
+            };
+            NETWORK_MANAGER
+                .lock()
+                .await
+                .protocol_versions
+                .insert(message.sender_addr, protocol_version);
+
+            // Try to add this new site as a new peer
+            crate::state::add_incomming_peer(
+                &message.message_initiator_id,
+                message.message_initiator_addr,
+                socket_of_the_sender,
+                &message.clock,
+            )
+            .await;
+
+            // Return ack message if this we are connected to the site
+            let connected = PEERS
+                .read()
+                .await
+                .get_connected_nei_addr()
+                .contains(&message.sender_addr);
+            if connected {
+                crate::known_peers::seen(message.sender_addr);
+                let global_fifo = CRITICAL_SECTION
+                    .lock()
+                    .await
+                    .get_global_mutex_fifo()
+                    .clone();
+                // Read before sending: a guard in the arguments would be held
+                // until the message is sent
+                let alias = SITE_INFO.read().await.get_site_alias();
+                send_message(
+                    message.sender_addr,
+                    MessageInfo::Acknowledge(crate::message::AcknowledgePayload {
+                        global_fifo,
+                        protocol_version,
+                        alias,
+                    }),
+                    None,
+                    NetworkMessageCode::Acknowledgment,
+                    local_site_addr,
+                    local_site_id.as_str(),
+                    &message.message_initiator_id.clone(),
+                    message.message_initiator_addr,
+                    get_clock().await,
+                )
+                .await?;
+            }
+        }
+
+        NetworkMessageCode::Acknowledgment => {
+            // If the site received an acknoledgement from a site,
+            // It can be a site that is not in the network anymore
+            crate::state::add_incomming_peer(
+                &message.sender_id,
+                message.sender_addr,
+                socket_of_the_sender,
+                &message.clock,
+            )
+            .await;
+            crate::known_peers::seen(message.sender_addr);
+            if message.message_initiator_addr == local_site_addr {
+                let mut waves = WAVES.lock().await;
+                for (site_id, nb_a_i) in waves.get_nb_nei_for_wave() {
+                    waves.set_nb_nei_for_wave(site_id, nb_a_i + 1);
+                }
+            }
+            // If we are in sync mode, we can start the sync process
+            // And we have received all the responses from the first attended neighbours counter
+            // We can start the sync process by starting a snapshot with sync mode
+            let nb_connected = crate::state::get_nb_connected_neighbours().await;
+            let ready_to_sync = {
+                let site = SITE_INFO.read().await;
+                site.get_sync() && site.get_nb_first_attended_neighbours() == nb_connected
+            };
+
+            // Récupérer le global_fifo envoyé dans l'acknowledgment
+            if let MessageInfo::Acknowledge(payload) = &message.info {
+                let global_fifo = payload.global_fifo.clone();
+                CRITICAL_SECTION
+                    .lock()
+                    .await
+                    .set_global_mutex_fifo(global_fifo);
+                if crate::codec::is_supported(payload.protocol_version) {
+                    NETWORK_MANAGER
+                        .lock()
+                        .await
+                        .protocol_versions
+                        .insert(message.sender_addr, payload.protocol_version);
+                }
+                if let Some(alias) = &payload.alias {
+                    crate::aliases::record(&message.sender_id, alias).await;
+                }
+            }
+
+            if ready_to_sync {
+                log::info!("All neighbours have responded, starting synchronization");
+                crate::control::enqueue_critical(crate::control::CriticalCommands::SyncSnapshot)
+                    .await?;
+            }
+        }
+
+        NetworkMessageCode::Transaction => {
+            // messages bleus
+            let duplicate = SEEN_WAVES
+                .lock()
+                .unwrap()
+                .seen(&message.message_initiator_id, *message.clock.get_lamport());
+            if duplicate {
+                // another neighbour already forwarded this wave, nothing to apply
+                // nor to diffuse, the sender only waits for our acknowledgement
+                crate::concurrency::record_duplicate();
+                crate::attach::wave_step(&message, crate::attach::WaveStep::Acknowledged);
+                send_message(
+                    message.sender_addr,
+                    MessageInfo::AckTransaction(crate::message::AckTransactionPayload { sites: 0 }),
+                    None,
+                    NetworkMessageCode::TransactionAcknowledgement,
+                    local_site_addr,
+                    local_site_id.as_str(),
+                    &message.message_initiator_id,
+                    message.message_initiator_addr,
+                    message.clock.clone(),
+                )
+                .await?;
+            } else if message.command.is_some() {
+                // compare the clock with ours before it gets updated
+                let first_visit = WAVES.lock().await.is_idle(&message.message_initiator_id);
+                if first_visit {
+                    let local_clock = get_clock().await;
+                    crate::causality::observe(
+                        &message.message_initiator_id,
+                        &local_clock,
+                        &message.clock,
+                    );
+                    crate::concurrency::observe(&local_clock, &message.clock);
+                }
+                let applied = crate::control::process_network_command(
+                    message.info.clone(),
+                    message.clock.clone(),
+                    message.message_initiator_id.as_str(),
+                )
+                .await
+                .map_err(|e| e.to_string());
+                match applied {
+                    Ok(()) => {
+                        // the operation may be the one a quarantined operation waits for
+                        if crate::quarantine::has_quarantined()
+                            && let Err(e) = crate::quarantine::retry().await
+                        {
+                            log::error!("Cannot retry the quarantined operations: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Error handling command, quarantined:\n{}", e);
+                        crate::concurrency::record_failure(&e);
+                        crate::quarantine::park(
+                            &message.info,
+                            &message.clock,
+                            &message.message_initiator_id,
+                            &e,
+                        );
+                    }
+                }
+                // wave diffusion
+                match on_blue_message(&message).await {
+                    BlueTransition::Diffuse => {
+                        let mut snd_msg = message.clone();
+                        snd_msg.sender_id = local_site_id.to_string();
+                        snd_msg.sender_addr = local_site_addr;
+                        diffuse_message(&snd_msg).await?;
+                    }
+                    BlueTransition::Acknowledge { first_visit } => {
+                        // Acquit message to parent
+                        log::debug!(
+                            "Réception d'un message de transaction, on est sur une feuille, on acquite, envoie à {}",
+                            message.sender_addr.to_string().as_str()
+                        );
+                        // a site reached for the first time applied the transaction
+                        let sites = i64::from(first_visit);
+                        send_message(
+                            message.sender_addr,
+                            MessageInfo::AckTransaction(crate::message::AckTransactionPayload {
+                                sites,
+                            }),
+                            None,
+                            NetworkMessageCode::TransactionAcknowledgement,
+                            local_site_addr,
+                            local_site_id.as_str(),
+                            &message.message_initiator_id.clone(),
+                            message.message_initiator_addr,
+                            message.clock.clone(),
+                        )
+                        .await?;
+                    }
+                }
+            } else {
+                log::error!("Command is None for Transaction message");
+            }
+        }
+        NetworkMessageCode::TransactionAcknowledgement => {
+            // Message rouge
+            if let MessageInfo::AckTransaction(payload) = &message.info {
+                WAVES
+                    .lock()
+                    .await
+                    .add_confirmed_sites(&message.message_initiator_id, payload.sites);
+            }
+            let transition = on_red_message(&message, local_site_addr).await;
+            if transition != RedTransition::Wait {
+                // our children and ourselves applied the transaction
+                let sites = WAVES
+                    .lock()
+                    .await
+                    .take_confirmed_sites(&message.message_initiator_id)
+                    + 1;
+                if let RedTransition::ForwardToParent(parent_addr) = transition {
+                    log::debug!(
+                        "On est dans le noeud {}. On a reçu un rouge de tous nos fils: on acquite au parent {}",
+                        local_site_addr.to_string().as_str(),
+                        parent_addr.to_string().as_str()
+                    );
+                    send_message(
+                        parent_addr,
+                        MessageInfo::AckTransaction(crate::message::AckTransactionPayload {
+                            sites,
+                        }),
+                        None,
+                        NetworkMessageCode::TransactionAcknowledgement,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        get_clock().await,
+                    )
+                    .await?;
+                } else {
+                    // on est chez le parent
+                    // diffusion terminée
+                    // Réinitialisation
+
+                    println!(
+                        "\x1b[1;31mDiffusion terminée et réussie ! Confirmée par {} sites\x1b[0m",
+                        sites
+                    );
+                    let completed = crate::confirmation::CONFIRMATIONS
+                        .lock()
+                        .unwrap()
+                        .wave_completed(sites);
+                    if let Some(lamports) = completed {
+                        crate::db::set_transactions_status(
+                            &local_site_id,
+                            &lamports,
+                            crate::db::TransactionStatus::Confirmed,
+                        )?;
+                    }
+                    release_mutex_if_idle().await?;
+                }
+            }
+        }
+
+        NetworkMessageCode::ForwardCommand => {
+            // command of a client site, which does not take part in the waves
+            if let MessageInfo::ForwardCommand(forwarded) = message.info.clone() {
+                crate::client::submit(&message.sender_id, message.sender_addr, forwarded).await?;
+            }
+        }
+
+        NetworkMessageCode::ForwardResult => {
+            if let MessageInfo::ForwardResult(result) = message.info.clone() {
+                crate::client::handle_result(message.sender_addr, result);
+            }
+        }
+
+        NetworkMessageCode::Heartbeat => {
+            crate::heartbeat::on_heartbeat(&message).await?;
+        }
+
+        NetworkMessageCode::HeartbeatAck => crate::heartbeat::on_heartbeat_ack(&message),
+
+        NetworkMessageCode::Error => {
+            if let MessageInfo::Error(reason) = &message.info {
+                log::error!("Error reported by {}: {}", message.sender_addr, reason);
+            } else {
+                log::debug!("Error message received: {:?}", message);
+            }
+        }
+        NetworkMessageCode::Disconnect => {
+            crate::state::remove_peer(message.message_initiator_addr).await;
+            println!(
+                "\x1b[1;31mSITE {} DISCONNECTED !\x1b[0m",
+                message.message_initiator_id
+            );
+        }
+        NetworkMessageCode::SnapshotRequest => {
+            // messages bleus
+            // wave diffusion
+            // a delta snapshot request carries the transactions known to the initiator
+            let known = match &message.info {
+                MessageInfo::SnapshotRequest(request) => Some(&request.known),
+                _ => None,
+            };
+            match on_blue_message(&message).await {
+                BlueTransition::Diffuse => {
+                    let mut snd_msg = message.clone();
+                    snd_msg.sender_id = local_site_id.to_string();
+                    snd_msg.sender_addr = local_site_addr;
+                    // Here we should diffuse the message because we are not on a leaf
+                    // We should also start a snapshot in network mode
+                    // When this snapshot is done, we should send the global snapshot to the parent
+                    log::debug!(
+                        "We are not on a leaf, we start our own global snapshot construction and diffuse the request to other nodes"
+                    );
+                    crate::snapshot::start_snapshot(
+                        crate::snapshot::SnapshotMode::NetworkMode,
+                        known,
+                        None,
+                    )
+                    .await?;
+                    // When can then diffuse the request to other nodes
+                    diffuse_message(&snd_msg).await?;
+                }
+                BlueTransition::Acknowledge { .. } => {
+                    // Acquit message to parent
+                    log::debug!(
+                        "Réception d'une demande de snapshot, on est sur une feuille, on crée un snapshot local, on envoie à {}",
+                        message.sender_addr.to_string().as_str()
+                    );
+                    // Here we are on a leaf, we can crate a local snapshot and send it to the parent
+                    let txs = crate::db::get_local_transaction_log()?;
+                    let mut summaries: Vec<_> = txs.iter().map(|t| t.into()).collect();
+                    if let Some(known) = known {
+                        summaries = crate::snapshot::newer_than(summaries, known);
+                    }
+
+                    send_snapshot_response(
+                        message.sender_addr,
+                        crate::message::SnapshotResponse {
+                            site_id: local_site_id.clone(),
+                            clock: get_clock().await,
+                            tx_log: summaries,
+                            partial: known.is_some(),
+                            ledgers: crate::ledger::get_user_ledgers()?,
+                        },
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                    )
+                    .await?;
+                }
+            }
+        }
+        NetworkMessageCode::ForceReleaseMutex => {
+            if let MessageInfo::ForceReleaseMutex(payload) = &message.info {
+                let removed = CRITICAL_SECTION
+                    .lock()
+                    .await
+                    .force_release(&payload.site_id, &local_site_id);
+                if removed {
+                    WAVES.lock().await.forget(&payload.site_id);
+                    log::warn!(
+                        "Global mutex request of site {} forcibly released by site {}",
+                        payload.site_id,
+                        message.message_initiator_id
+                    );
+                }
+            }
+            // wave diffusion
+            match on_blue_message(&message).await {
+                BlueTransition::Diffuse => {
+                    let mut snd_msg = message.clone();
+                    snd_msg.sender_id = local_site_id.to_string();
+                    snd_msg.sender_addr = local_site_addr;
+                    diffuse_message(&snd_msg).await?;
+                }
+                BlueTransition::Acknowledge { .. } => {
+                    send_message(
+                        message.sender_addr,
+                        MessageInfo::None,
+                        None,
+                        NetworkMessageCode::AckForceReleaseMutex,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        get_clock().await,
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        NetworkMessageCode::AckForceReleaseMutex => {
+            match on_red_message(&message, local_site_addr).await {
+                RedTransition::Wait => {}
+                RedTransition::Completed => {
+                    log::info!("The stale mutex request was cleared on every site");
+                }
+                RedTransition::ForwardToParent(parent_addr) => {
+                    send_message(
+                        parent_addr,
+                        MessageInfo::None,
+                        None,
+                        NetworkMessageCode::AckForceReleaseMutex,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        get_clock().await,
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        NetworkMessageCode::ConsistencyRequest => match on_blue_message(&message).await {
+            BlueTransition::Diffuse => {
+                let mut snd_msg = message.clone();
+                snd_msg.sender_id = local_site_id.to_string();
+                snd_msg.sender_addr = local_site_addr;
+                crate::consistency::start().await?;
+                diffuse_message(&snd_msg).await?;
+            }
+            BlueTransition::Acknowledge { first_visit } => {
+                // a site reached by several parents sends its digest only once
+                let digests = if first_visit {
+                    vec![crate::consistency::local_digest().await?]
+                } else {
+                    Vec::new()
+                };
+                send_message(
+                    message.sender_addr,
+                    MessageInfo::ConsistencyDigests(digests),
+                    None,
+                    NetworkMessageCode::ConsistencyResponse,
+                    local_site_addr,
+                    &local_site_id,
+                    &message.message_initiator_id,
+                    message.message_initiator_addr,
+                    get_clock().await,
+                )
+                .await?;
+            }
+        },
+        NetworkMessageCode::ConsistencyResponse => {
+            let digests = match &message.info {
+                MessageInfo::ConsistencyDigests(digests) => digests.clone(),
+                _ => {
+                    log::error!("Message de type ConsistencyDigests attendu, mais pas reçu");
+                    Vec::new()
+                }
+            };
+            match on_red_message(&message, local_site_addr).await {
+                RedTransition::Completed => {
+                    crate::consistency::complete(digests).await;
+                    release_mutex_if_idle().await?;
+                }
+                RedTransition::ForwardToParent(parent_addr) => {
+                    crate::consistency::collect(digests).await;
+                    send_message(
+                        parent_addr,
+                        MessageInfo::ConsistencyDigests(crate::consistency::take().await),
+                        None,
+                        NetworkMessageCode::ConsistencyResponse,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        get_clock().await,
+                    )
+                    .await?;
+                }
+                RedTransition::Wait => crate::consistency::collect(digests).await,
+            }
+        }
+        NetworkMessageCode::ClusterStatsRequest => match on_blue_message(&message).await {
+            BlueTransition::Diffuse => {
+                let mut snd_msg = message.clone();
+                snd_msg.sender_id = local_site_id.to_string();
+                snd_msg.sender_addr = local_site_addr;
+                crate::cluster_stats::start().await?;
+                diffuse_message(&snd_msg).await?;
+            }
+            BlueTransition::Acknowledge { first_visit } => {
+                // a site reached by several parents sends its counters only once
+                let stats = if first_visit {
+                    vec![crate::cluster_stats::local_stats().await?]
+                } else {
+                    Vec::new()
+                };
+                send_message(
+                    message.sender_addr,
+                    MessageInfo::ClusterStats(stats),
+                    None,
+                    NetworkMessageCode::ClusterStatsResponse,
+                    local_site_addr,
+                    &local_site_id,
+                    &message.message_initiator_id,
+                    message.message_initiator_addr,
+                    get_clock().await,
+                )
+                .await?;
+            }
+        },
+        NetworkMessageCode::ClusterStatsResponse => {
+            let stats = match &message.info {
+                MessageInfo::ClusterStats(stats) => stats.clone(),
+                _ => {
+                    log::error!("Message de type ClusterStats attendu, mais pas reçu");
+                    Vec::new()
+                }
+            };
+            match on_red_message(&message, local_site_addr).await {
+                RedTransition::Completed => {
+                    crate::cluster_stats::complete(stats).await;
+                    release_mutex_if_idle().await?;
+                }
+                RedTransition::ForwardToParent(parent_addr) => {
+                    crate::cluster_stats::collect(stats).await;
+                    send_message(
+                        parent_addr,
+                        MessageInfo::ClusterStats(crate::cluster_stats::take().await),
+                        None,
+                        NetworkMessageCode::ClusterStatsResponse,
+                        local_site_addr,
+                        &local_site_id,
+                        &message.message_initiator_id,
+                        message.message_initiator_addr,
+                        get_clock().await,
+                    )
+                    .await?;
+                }
+                RedTransition::Wait => crate::cluster_stats::collect(stats).await,
+            }
+        }
+        NetworkMessageCode::SnapshotResponse => {
+            // Message rouge
+            match on_red_message(&message, local_site_addr).await {
+                RedTransition::Completed => {
+                    // on est chez le parent
+                    // diffusion terminée
+                    // Réinitialisation
+                    log::debug!(
+                        "L'initiateur à reçu toutes ses snapshots, il devrait créer sa snapshot globale"
+                    );
+                    if let MessageInfo::SnapshotResponse(resp) = message.info {
+                        let mut mgr = crate::snapshot::LOCAL_SNAPSHOT_MANAGER.lock().await;
+                        if mgr.mode == crate::snapshot::SnapshotMode::FileMode {
+                            log::debug!("La snapshot devrait être sauvegardée");
+                            if let Some(gs) = mgr.push(resp) {
+                                log::info!(
+                                    "Global snapshot ready to save, hold per site : {:#?}",
+                                    gs.missing
+                                );
+                                mgr.path = crate::snapshot::persist(&gs, local_site_id.clone())
+                                    .await
+                                    .unwrap()
+                                    .parse()
+                                    .ok();
+                            }
+                        } else if mgr.mode == crate::snapshot::SnapshotMode::SyncMode {
+                            log::debug!(
+                                "La snapshot devrait être utilisée pour la synchronisation"
+                            );
+                            if let Some(gs) = mgr.push(resp) {
+                                log::info!(
+                                    "Global snapshot ready to be synced, hold per site : {:#?}",
+                                    gs.missing
+                                );
+                                let reconciliation = crate::db::update_db_with_snapshot(
+                                    &gs,
+                                    get_clock().await.get_vector_clock_map(),
+                                );
+                                let notice = if reconciliation.is_empty() {
+                                    "Synchronization with the network completed".to_string()
+                                } else {
+                                    format!(
+                                        "Synchronization with the network completed, {} users created and {} balances corrected",
+                                        reconciliation.created_users.len(),
+                                        reconciliation.changed_balances.len()
+                                    )
+                                };
+                                if let Err(e) = crate::notifications::notify_all(&notice) {
+                                    log::error!("Failed to notify the end of the sync: {}", e);
+                                }
+                            }
+                        }
+                    }
+
+                    println!("\x1b[1;31mDiffusion terminée et réussie !\x1b[0m");
+                    release_mutex_if_idle().await?;
+                }
+                RedTransition::ForwardToParent(parent_addr) => {
+                    log::debug!(
+                        "On est dans le noeud {}. On a reçu un rouge de tous nos fils: on acquite au parent {}",
+                        local_site_addr.to_string().as_str(),
+                        parent_addr.to_string().as_str()
+                    );
+                    log::debug!(
+                        "On devrait pouvoir construire une snapshot globale avec tous nos voisins et l'envoyer à l'adresse de notre parent {}",
+                        parent_addr.to_string().as_str()
+                    );
+                    if let MessageInfo::SnapshotResponse(resp) = message.info {
+                        let mut mgr = crate::snapshot::LOCAL_SNAPSHOT_MANAGER.lock().await;
+                        if mgr.mode == crate::snapshot::SnapshotMode::NetworkMode {
+                            log::debug!("La snapshot devrait être envoyés au père");
+                            let partial = resp.partial || mgr.received.iter().any(|s| s.partial);
+                            if let Some(gs) = mgr.push(resp) {
+                                log::info!(
+                                    "Global snapshot ready to be send to parent, hold per site : {:#?}",
+                                    gs.missing
+                                );
+                                send_snapshot_response(
+                                    parent_addr,
+                                    crate::message::SnapshotResponse {
+                                        site_id: local_site_id.clone(),
+                                        clock: get_clock().await,
+                                        tx_log: gs.all_transactions.into_iter().collect(),
+                                        partial,
+                                        ledgers: gs.ledgers,
+                                    },
+                                    local_site_addr,
+                                    &local_site_id,
+                                    &message.message_initiator_id,
+                                    message.message_initiator_addr,
+                                )
+                                .await?;
+                            } else {
+                                log::error!("Le site aurait du récupérer toutes ses snapshots");
+                            }
+                        }
+                    } else {
+                        log::error!("Message de type SnapshotResponse attendu, mais pas reçu");
+                    }
+                }
+                RedTransition::Wait => {
+                    log::debug!(
+                        "On a reçu un message rouge d'un des fils mais la vague n'est pas encore terminée"
+                    );
+                    // We should add the Snapshot to our manager
+                    if let MessageInfo::SnapshotResponse(resp) = message.info {
+                        let mut mgr = crate::snapshot::LOCAL_SNAPSHOT_MANAGER.lock().await;
+                        log::debug!("La snapshot devrait être ajoutés à l'état du manager");
+                        if mgr.push(resp).is_some() {
+                            log::error!(
+                                "On ne devrait pas encore pouvoir construire une snapshot globale vu que la vague n'est pas terminée"
+                            );
+                        }
+                    } else {
+                        log::error!("Message de type SnapshotResponse attendu, mais pas reçu");
+                    }
+                }
+            }
+        }
+    }
+
+    crate::state::update_clock(Some(&message.clock)).await;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Send a message to a specific peer
+#[allow(clippy::too_many_arguments)]
+pub async fn send_message(
+    recipient_address: std::net::SocketAddr,
+    info: crate::message::MessageInfo,
+    command: Option<crate::control::Command>,
+    code: crate::message::NetworkMessageCode,
+    local_addr: std::net::SocketAddr,
+    local_site: &str,
+    initiator_id: &str,
+    initiator_addr: std::net::SocketAddr,
+    sender_clock: crate::clock::Clock,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::Message;
+
+    if code == crate::message::NetworkMessageCode::Transaction && command.is_none() {
+        log::error!("Command is None for Transaction message");
+        return Err("Command is None for Transaction message".into());
+    }
+
+    // the messages of a wave carry the span of the wave on this site
+    let trace = crate::telemetry::context(initiator_id, *sender_clock.get_lamport());
+    let msg = Message {
+        sender_id: local_site.to_string(),
+        sender_addr: local_addr,
+        message_initiator_id: initiator_id.to_string(),
+        clock: sender_clock,
+        command,
+        info,
+        code,
+        message_initiator_addr: initiator_addr,
+        trace,
+    };
+
+    if recipient_address.ip().is_unspecified() || recipient_address.port() == 0 {
+        log::warn!("Skipping invalid peer address {}", recipient_address);
+        return Ok(());
+    }
+
+    #[cfg(feature = "chaos")]
+    {
+        if crate::chaos::should_drop() {
+            log::warn!("Chaos: dropping {:?} to {}", msg.code, recipient_address);
+            return Ok(());
+        }
+        if let Some(delay) = crate::chaos::delay_for(&recipient_address) {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    if transport() == Transport::Grpc {
+        return crate::grpc::send_message(recipient_address, &msg).await;
+    }
+
+    let format = crate::codec::wire_format();
+    let version = NETWORK_MANAGER
+        .lock()
+        .await
+        .protocol_version(&recipient_address);
+    let buf = encode_frame(&format.encode(&msg)?, version, format, max_message_size())
+        .map_err(|e| format!("cannot send to {}: {}", recipient_address, e))?;
+
+    let sender = {
+        let mut manager = NETWORK_MANAGER.lock().await;
+        match manager.get_sender(&recipient_address) {
+            Some(s) => s,
+            None => {
+                // Drop the connection whose writer task died, if any
+                manager.remove_connection(&recipient_address);
+                if let Err(e) = manager.create_connection(recipient_address).await {
+                    return Err(
+                        format!("error with connection to {}: {}", recipient_address, e).into(),
+                    );
+                }
+                match manager.get_sender(&recipient_address) {
+                    Some(s) => s,
+                    None => {
+                        let err_msg =
+                            format!("Sender not found after connecting to {}", recipient_address);
+                        log::error!("{}", err_msg);
+                        return Err(err_msg.into());
+                    }
+                }
+            }
+        }
+    };
+
+    // The manager is not locked while waiting for room in the queue of a slow
+    // peer, so that the messages to the other peers are not delayed
+    if let Err(e) = sender.send(buf).await {
+        NETWORK_MANAGER
+            .lock()
+            .await
+            .remove_connection(&recipient_address);
+        let err_msg = format!(
+            "Impossible to send msg to {} due to error : {}",
+            recipient_address, e
+        );
+        log::error!("{}", err_msg);
+        return Err(err_msg.into());
+    }
+    NETWORK_MANAGER.lock().await.touch(&recipient_address);
+    log::debug!("Sent message {:?} to {}", &msg, recipient_address);
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Send a snapshot response, compressed or split in several chunks if it is
+/// too large
+///
+/// A response above [`SNAPSHOT_COMPRESSION_THRESHOLD`] is compressed. If it
+/// still does not fit within the maximum message size, it is split instead:
+/// each chunk carries a slice of the transaction log and the receiver
+/// reassembles them.
+pub async fn send_snapshot_response(
+    recipient_address: std::net::SocketAddr,
+    response: crate::message::SnapshotResponse,
+    local_addr: std::net::SocketAddr,
+    local_site: &str,
+    initiator_id: &str,
+    initiator_addr: std::net::SocketAddr,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{MessageInfo, NetworkMessageCode};
+
+    let clock = response.clock.clone();
+    let size = crate::codec::wire_format().encode(&response)?.len();
+    // keep half of the limit for the envelope of the message
+    let budget = (max_message_size() / 2).max(1);
+
+    if size <= budget.min(SNAPSHOT_COMPRESSION_THRESHOLD) {
+        return send_message(
+            recipient_address,
+            MessageInfo::SnapshotResponse(response),
+            None,
+            NetworkMessageCode::SnapshotResponse,
+            local_addr,
+            local_site,
+            initiator_id,
+            initiator_addr,
+            clock,
+        )
+        .await;
+    }
+
+    let compressed = response.compress()?;
+    let compressed_size = crate::codec::wire_format().encode(&compressed)?.len();
+    if compressed_size <= budget {
+        log::debug!(
+            "Snapshot response of {} bytes sent to {} compressed to {} bytes",
+            size,
+            recipient_address,
+            compressed_size
+        );
+        return send_message(
+            recipient_address,
+            MessageInfo::CompressedSnapshot(compressed),
+            None,
+            NetworkMessageCode::SnapshotResponse,
+            local_addr,
+            local_site,
+            initiator_id,
+            initiator_addr,
+            clock,
+        )
+        .await;
+    }
+
+    let nb_chunks = size.div_ceil(budget);
+    let txs_per_chunk = response.tx_log.len().div_ceil(nb_chunks);
+    let chunks = response.into_chunks(txs_per_chunk);
+    log::debug!(
+        "Snapshot response of {} bytes sent to {} in {} chunks",
+        size,
+        recipient_address,
+        chunks.len()
+    );
+    for chunk in chunks {
+        send_message(
+            recipient_address,
+            MessageInfo::SnapshotChunk(chunk),
+            None,
+            NetworkMessageCode::SnapshotResponse,
+            local_addr,
+            local_site,
+            initiator_id,
+            initiator_addr,
+            clock.clone(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Implement our wave diffusion protocol
+///
+/// Read the site, its neighbours and the wave parent, then diffuse a message
+pub async fn diffuse_message(
+    message: &crate::message::Message,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::state::{PEERS, local_site};
+
+    log::debug!(
+        "Début de la diffusion d'un message de type {:?}",
+        message.code
+    );
+
+    let (site_id, local_addr) = local_site().await;
+    let connected_nei_addr = PEERS.read().await.get_connected_nei_addr();
+    let parent_address = wave_parent(message).await;
+    diffuse_message_without_lock(
+        message,
+        local_addr,
+        &site_id,
+        connected_nei_addr,
+        parent_address,
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Deadline of the sending of a wave message to one neighbour
+const DIFFUSION_SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[cfg(feature = "server")]
+/// Implement our wave diffusion protocol
+///
+/// Diffuse a message without locking the state. The message is sent to the
+/// neighbours concurrently, so that a slow neighbour does not delay the
+/// others, the nearest ones first. The neighbours it could not be sent to
+/// will never acknowledge it: the site acknowledges it on their behalf, see
+/// [`unreachable_ack`], as for the slow neighbours it skips, see
+/// [`crate::heartbeat`].
+pub async fn diffuse_message_without_lock(
+    message: &crate::message::Message,
+    local_addr: std::net::SocketAddr,
+    site_id: &str,
+    mut connected_nei_addr: Vec<std::net::SocketAddr>,
+    parent_address: std::net::SocketAddr,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut skipped = Vec::new();
+    {
+        let latencies = crate::heartbeat::LATENCIES.lock().unwrap();
+        latencies.sort_by_latency(&mut connected_nei_addr);
+        // the initiator sends to all its neighbours, the others may skip them
+        let threshold = crate::heartbeat::slow_neighbour_rtt().filter(|_| {
+            message.message_initiator_id != site_id
+                && unreachable_ack(message, parent_address, local_addr, site_id).is_some()
+        });
+        if let Some(threshold) = threshold {
+            connected_nei_addr.retain(|addr| {
+                let skip = *addr != parent_address
+                    && latencies.can_skip(
+                        addr,
+                        &message.message_initiator_id,
+                        &message.message_initiator_addr,
+                        threshold,
+                    );
+                if skip {
+                    skipped.push(*addr);
+                }
+                !skip
+            });
+        }
+    }
+
+    let mut sends = tokio::task::JoinSet::new();
+    for connected_nei in connected_nei_addr {
+        if connected_nei == parent_address {
+            continue;
+        }
+        log::debug!("Sending message to: {}", connected_nei);
+        let message = message.clone();
+        let site_id = site_id.to_string();
+        sends.spawn(async move {
+            let sent = tokio::time::timeout(
+                DIFFUSION_SEND_TIMEOUT,
+                send_message(
+                    connected_nei,
+                    message.info,
+                    message.command,
+                    message.code,
+                    local_addr,
+                    &site_id,
+                    &message.message_initiator_id,
+                    message.message_initiator_addr,
+                    message.clock,
+                ),
+            )
+            .await;
+            let result = match sent {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err(format!("timed out after {:?}", DIFFUSION_SEND_TIMEOUT)),
+            };
+            (connected_nei, result)
+        });
+    }
+
+    let mut unreachable = Vec::new();
+    while let Some(joined) = sends.join_next().await {
+        match joined {
+            Ok((_, Ok(()))) => {}
+            Ok((peer_addr, Err(e))) => {
+                log::error!("❌ Impossible d’envoyer à {} : {}", peer_addr, e);
+                unreachable.push(peer_addr);
+            }
+            Err(e) => log::error!("Diffusion task failed: {}", e),
+        }
+    }
+
+    if !skipped.is_empty() {
+        log::debug!("Skipping the slow neighbours {:?}", skipped);
+    }
+    for peer_addr in skipped.into_iter().chain(unreachable) {
+        if let Some(ack) = unreachable_ack(message, peer_addr, local_addr, site_id) {
+            Box::pin(process_message(ack, local_addr)).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Builds the acknowledgement (red) of a wave message for a neighbour it could not be sent to
+///
+/// The acknowledgement is the one of a neighbour with nothing to report, so
+/// that the wave completes with the other neighbours instead of waiting
+/// forever. Returns None if the message is not part of a wave.
+fn unreachable_ack(
+    message: &crate::message::Message,
+    peer_addr: std::net::SocketAddr,
+    local_addr: std::net::SocketAddr,
+    site_id: &str,
+) -> Option<crate::message::Message> {
+    use crate::message::{Message, MessageInfo, NetworkMessageCode};
+
+    let (code, info) = match message.code {
+        NetworkMessageCode::AcquireMutex => (
+            NetworkMessageCode::AckGlobalMutex,
+            MessageInfo::AckMutex(crate::message::AckMutexPayload {
+                clock: *message.clock.get_lamport(),
+            }),
+        ),
+        NetworkMessageCode::ReleaseGlobalMutex => {
+            (NetworkMessageCode::AckReleaseGlobalMutex, MessageInfo::None)
+        }
+        NetworkMessageCode::Transaction => (
+            NetworkMessageCode::TransactionAcknowledgement,
+            MessageInfo::AckTransaction(crate::message::AckTransactionPayload { sites: 0 }),
+        ),
+        NetworkMessageCode::SnapshotRequest => (
+            NetworkMessageCode::SnapshotResponse,
+            MessageInfo::SnapshotResponse(crate::message::SnapshotResponse {
+                site_id: peer_addr.to_string(),
+                clock: crate::clock::Clock::new(),
+                tx_log: Vec::new(),
+                partial: false,
+                ledgers: Default::default(),
+            }),
+        ),
+        NetworkMessageCode::ConsistencyRequest => (
+            NetworkMessageCode::ConsistencyResponse,
+            MessageInfo::ConsistencyDigests(Vec::new()),
+        ),
+        NetworkMessageCode::ClusterStatsRequest => (
+            NetworkMessageCode::ClusterStatsResponse,
+            MessageInfo::ClusterStats(Vec::new()),
+        ),
+        NetworkMessageCode::ForceReleaseMutex => {
+            (NetworkMessageCode::AckForceReleaseMutex, MessageInfo::None)
+        }
+        _ => return None,
+    };
+    Some(Message {
+        sender_id: site_id.to_string(),
+        sender_addr: local_addr,
+        message_initiator_id: message.message_initiator_id.clone(),
+        message_initiator_addr: message.message_initiator_addr,
+        clock: message.clock.clone(),
+        command: None,
+        info,
+        code,
+        trace: None,
+    })
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_backoff_delay_grows_and_is_capped() {
+        assert_eq!(backoff_delay(1, 0), BACKOFF_BASE);
+        assert_eq!(backoff_delay(2, 0), BACKOFF_BASE * 2);
+        assert_eq!(backoff_delay(3, 0), BACKOFF_BASE * 4);
+        assert_eq!(backoff_delay(100, 0), BACKOFF_MAX);
+
+        // jitter is bounded by half of the delay
+        let jittered = backoff_delay(1, u64::MAX);
+        assert!(jittered >= BACKOFF_BASE);
+        assert!(jittered < BACKOFF_BASE + BACKOFF_BASE / 2);
+    }
+
+    #[test]
+    fn test_backoff_state_transitions() {
+        let addr: std::net::SocketAddr = "127.0.0.1:9999".parse().unwrap();
+        let mut manager = NetworkManager::new();
+        assert_eq!(manager.connection_state(&addr), None);
+        assert!(manager.check_retry(&addr).is_ok());
+
+        manager.record_failure(addr);
+        assert_eq!(
+            manager.connection_state(&addr),
+            Some(ConnectionState::Backoff)
+        );
+        assert!(manager.check_retry(&addr).is_err());
+
+        for _ in 1..MAX_CONNECTION_RETRIES {
+            manager.record_failure(addr);
+        }
+        assert_eq!(manager.connection_state(&addr), Some(ConnectionState::Dead));
+
+        manager.reset_backoff(&addr);
+        assert_eq!(manager.connection_state(&addr), None);
+        assert!(manager.check_retry(&addr).is_ok());
+    }
+
+    #[test]
+    fn test_traffic_accounting() {
+        let quiet: std::net::SocketAddr = "127.0.0.1:9001".parse().unwrap();
+        let chatty: std::net::SocketAddr = "127.0.0.1:9002".parse().unwrap();
+
+        let mut manager = NetworkManager::new();
+        manager.record_received(quiet, 10);
+        manager.record_sent(chatty, 100);
+        manager.record_received(chatty, 50);
+        // the writer task of a connection shares the counters of its peer
+        manager.traffic_of(chatty).lock().unwrap().record_sent(20);
+        manager.remove_connection(&chatty);
+
+        let traffic = manager.traffic();
+        assert_eq!(traffic.len(), 2);
+        assert_eq!(traffic[0].addr, chatty.to_string());
+        assert_eq!(traffic[0].bytes_sent, 120);
+        assert_eq!(traffic[0].messages_sent, 2);
+        assert_eq!(traffic[0].bytes_received, 50);
+        assert_eq!(traffic[0].messages_received, 1);
+        assert_eq!(traffic[1].messages_sent, 0);
+        assert_eq!(traffic[1].idle_secs, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_reap_keeps_neighbours() {
+        let neighbour: std::net::SocketAddr = "127.0.0.1:9001".parse().unwrap();
+        let other: std::net::SocketAddr = "127.0.0.1:9002".parse().unwrap();
+        let broken: std::net::SocketAddr = "127.0.0.1:9003".parse().unwrap();
+
+        let mut manager = NetworkManager::new();
+        let (tx_neighbour, _rx_neighbour) = tokio::sync::mpsc::channel(1);
+        let (tx_other, _rx_other) = tokio::sync::mpsc::channel(1);
+        let (tx_broken, rx_broken) = tokio::sync::mpsc::channel(1);
+        drop(rx_broken);
+        manager.add_connection(neighbour, tx_neighbour);
+        manager.add_connection(other, tx_other);
+        manager.add_connection(broken, tx_broken);
+        assert_eq!(manager.nb_active_connections, 3);
+
+        let mut removed = manager.reap(&[neighbour], std::time::Duration::ZERO);
+        removed.sort();
+        assert_eq!(removed, vec![other, broken]);
+        assert_eq!(
+            manager.connection_states(),
+            vec![(neighbour, ConnectionState::Connected)]
+        );
+        assert_eq!(manager.nb_active_connections, 1);
+    }
+
+    #[tokio::test]
+    async fn test_adopt_connection() -> Result<(), Box<dyn std::error::Error>> {
+        use tokio::io::AsyncReadExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr()?).await?;
+        let (accepted, _) = listener.accept().await?;
+        let (_reader, writer) = accepted.into_split();
+        let peer: std::net::SocketAddr = "127.0.0.1:9004".parse().unwrap();
+
+        let mut manager = NetworkManager::new();
+        assert!(manager.adopt_connection(peer, writer).await.is_none());
+        assert_eq!(manager.nb_active_connections, 1);
+
+        // The replies to the peer go through the accepted socket
+        manager
+            .get_sender(&peer)
+            .unwrap()
+            .send(b"ack".to_vec())
+            .await?;
+        let mut reply = [0u8; 3];
+        client.read_exact(&mut reply).await?;
+        assert_eq!(&reply, b"ack");
+
+        // A second connection from the peer is not adopted
+        let _other = tokio::net::TcpStream::connect(listener.local_addr()?).await?;
+        let (accepted, _) = listener.accept().await?;
+        let (_reader, writer) = accepted.into_split();
+        assert!(manager.adopt_connection(peer, writer).await.is_some());
+        assert_eq!(manager.nb_active_connections, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dialed_connection_replies() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::message::{HeartbeatPayload, Message, MessageInfo, NetworkMessageCode};
+
+        // The peer we dial, and the address it claims to reply to, which the
+        // reader task of the connection has to dial in turn
+        let dialed = TcpListener::bind("127.0.0.1:0").await?;
+        let reply_to = TcpListener::bind("127.0.0.1:0").await?;
+        NETWORK_MANAGER
+            .lock()
+            .await
+            .create_connection(dialed.local_addr()?)
+            .await
+            .map_err(|e| e.to_string())?;
+        let (mut peer, _) = dialed.accept().await?;
+
+        let heartbeat = Message {
+            sender_id: "dialed-peer".to_string(),
+            sender_addr: reply_to.local_addr()?,
+            message_initiator_id: "dialed-peer".to_string(),
+            message_initiator_addr: reply_to.local_addr()?,
+            clock: crate::clock::Clock::new(),
+            command: None,
+            info: MessageInfo::Heartbeat(HeartbeatPayload {
+                sent_at: 42,
+                neighbours: Vec::new(),
+            }),
+            code: NetworkMessageCode::Heartbeat,
+            trace: None,
+        };
+        let format = crate::codec::wire_format();
+        let frame = encode_frame(
+            &format.encode(&heartbeat)?,
+            *crate::codec::PROTOCOL_VERSIONS.start(),
+            format,
+            max_message_size(),
+        )?;
+        tokio::io::AsyncWriteExt::write_all(&mut peer, &frame).await?;
+
+        let (accepted, _) =
+            tokio::time::timeout(std::time::Duration::from_secs(5), reply_to.accept()).await??;
+        let mut frames = FrameReader::new(accepted);
+        let Some(Frame::Message {
+            format, payload, ..
+        }) = frames.read_frame(max_message_size()).await?
+        else {
+            panic!("expected the reply to the heartbeat");
+        };
+        let reply: Message = crate::codec::WireFormat::from_byte(format)
+            .unwrap()
+            .decode(&payload)?;
+        assert_eq!(reply.code, NetworkMessageCode::HeartbeatAck);
+        assert!(matches!(
+            reply.info,
+            MessageInfo::Heartbeat(HeartbeatPayload { sent_at: 42, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_ip_replaces_unspecified() {
+        let v4: std::net::IpAddr = "0.0.0.0".parse().unwrap();
+        let v6: std::net::IpAddr = "::".parse().unwrap();
+        let lan: std::net::IpAddr = "192.168.1.2".parse().unwrap();
+        assert_eq!(
+            scan_ip(v4),
+            "127.0.0.1".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(scan_ip(v6), "::1".parse::<std::net::IpAddr>().unwrap());
+        assert_eq!(scan_ip(lan), lan);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_peers() {
+        let local_ip: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+
+        let v6 = resolve_peer("[::1]:8080", local_ip).await;
+        assert_eq!(v6, Some("[::1]:8080".parse().unwrap()));
+
+        let host = resolve_peer("localhost:8080", local_ip).await.unwrap();
+        assert!(host.ip().is_loopback());
+        assert_eq!(host.port(), 8080);
+
+        let peers = resolve_peers(
+            &[
+                "127.0.0.1:8080".to_string(),
+                "127.0.0.1:8080".to_string(),
+                "not a peer".to_string(),
+            ],
+            local_ip,
+        )
+        .await;
+        assert_eq!(peers, vec!["127.0.0.1:8080".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_frame_roundtrip_and_limit() {
+        use crate::codec::WireFormat;
+
+        let mut data = encode_frame(b"hello", 1, WireFormat::Msgpack, 16).unwrap();
+        data.extend(encode_frame(&[7u8; 32], 1, WireFormat::Msgpack, 64).unwrap());
+        data.extend(encode_frame(b"bye", 2, WireFormat::Json, 16).unwrap());
+        assert!(encode_frame(&[0u8; 17], 1, WireFormat::Msgpack, 16).is_err());
+
+        let mut frames = FrameReader::new(&data[..]);
+        assert_eq!(
+            frames.read_frame(16).await.unwrap(),
+            Some(Frame::Message {
+                version: 1,
+                format: 0,
+                payload: bytes::Bytes::from_static(b"hello")
+            })
+        );
+        assert_eq!(
+            frames.read_frame(16).await.unwrap(),
+            Some(Frame::Oversized(32))
+        );
+        assert_eq!(
+            frames.read_frame(16).await.unwrap(),
+            Some(Frame::Message {
+                version: 2,
+                format: 1,
+                payload: bytes::Bytes::from_static(b"bye")
+            })
+        );
+        assert_eq!(frames.read_frame(16).await.unwrap(), None);
+
+        // connection closed in the middle of a frame
+        let truncated = encode_frame(b"hello", 1, WireFormat::Msgpack, 16).unwrap();
+        let mut frames = FrameReader::new(&truncated[..FRAME_HEADER_SIZE + 2]);
+        assert!(frames.read_frame(16).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_frame_buffer_adapts() {
+        use crate::codec::WireFormat;
+
+        // frames arriving in small pieces are reassembled
+        let (mut client, server) = tokio::io::duplex(1024);
+        let large = vec![7u8; 64 * MIN_READ_BUFFER];
+        let mut data = encode_frame(&large, 1, WireFormat::Msgpack, large.len()).unwrap();
+        for _ in 0..32 {
+            data.extend(encode_frame(b"small", 1, WireFormat::Msgpack, 16).unwrap());
+        }
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            client.write_all(&data).await.unwrap();
+        });
+
+        let mut frames = FrameReader::new(server);
+        let Some(Frame::Message { payload, .. }) = frames.read_frame(large.len()).await.unwrap()
+        else {
+            panic!("expected the large frame");
+        };
+        assert_eq!(payload.len(), large.len());
+        drop(payload);
+        for _ in 0..32 {
+            assert!(matches!(
+                frames.read_frame(16).await.unwrap(),
+                Some(Frame::Message { .. })
+            ));
+        }
+        // the buffer shrank back once the frames were small again
+        assert!(frames.buffer.capacity() <= 4 * MIN_READ_BUFFER);
+        assert_eq!(frames.read_frame(16).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_seen_waves() {
+        let mut waves = SeenWaves::new(2);
+        assert!(!waves.seen("A", 1));
+        assert!(waves.seen("A", 1));
+        assert!(!waves.seen("B", 1));
+        assert!(waves.seen("A", 1));
+        assert!(!waves.seen("A", 2));
+
+        // seeing (A, 1) again kept it, (B, 1) was the least recently seen
+        assert_eq!(waves.last_seen.len(), 2);
+        assert_eq!(waves.by_age.len(), 2);
+        assert!(waves.seen("A", 1));
+        assert!(!waves.seen("B", 1));
+    }
+
+    #[test]
+    fn test_unreachable_ack() {
+        use crate::message::{Message, MessageInfo, NetworkMessageCode};
+
+        let local_addr: std::net::SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let peer_addr: std::net::SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let initiator_addr: std::net::SocketAddr = "127.0.0.1:8082".parse().unwrap();
+        let message = |code| Message {
+            sender_id: "C".to_string(),
+            sender_addr: initiator_addr,
+            message_initiator_id: "C".to_string(),
+            message_initiator_addr: initiator_addr,
+            clock: crate::clock::Clock::new(),
+            command: None,
+            info: MessageInfo::None,
+            code,
+            trace: None,
+        };
+
+        let ack = unreachable_ack(
+            &message(NetworkMessageCode::Transaction),
+            peer_addr,
+            local_addr,
+            "A",
+        )
+        .unwrap();
+        assert_eq!(ack.code, NetworkMessageCode::TransactionAcknowledgement);
+        // the unreachable neighbour did not apply the transaction
+        assert!(matches!(
+            ack.info,
+            MessageInfo::AckTransaction(crate::message::AckTransactionPayload { sites: 0 })
+        ));
+        // the acknowledgement belongs to the wave of the initiator
+        assert_eq!(ack.message_initiator_id, "C");
+        assert_eq!(ack.sender_addr, local_addr);
+
+        assert!(
+            unreachable_ack(
+                &message(NetworkMessageCode::Discovery),
+                peer_addr,
+                local_addr,
+                "A"
+            )
+            .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_message() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::clock::Clock;
+        use crate::message::{MessageInfo, NetworkMessageCode};
+
+        let local_addr: std::net::SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let local_site = "A";
+        let clock = Clock::new();
+
+        // Use an ephemeral port, other tests may put fixed ports in backoff
+        let _listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = _listener.local_addr()?;
+
+        let code = NetworkMessageCode::Discovery;
+
+        let send_result = send_message(
+            address,
+            MessageInfo::None,
+            None,
+            code,
+            local_addr,
+            local_site,
+            local_site,
+            local_addr,
+            clock,
+        )
+        .await;
+        assert!(send_result.is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,749 @@
+//! Embeddable Peillute node
+//!
+//! A [`Node`] is built from a [`NodeConfig`] and started with
+//! [`Node::start`], which opens the database, joins the network and returns a
+//! [`RunningNode`]. Its [`NodeHandle`] submits critical commands and
+//! subscribes to the events of the node, while [`RunningNode::serve`] accepts
+//! the connections of the peers until the process is interrupted.
+//!
+//! A served node can also be stopped, or have its networking restarted on
+//! another port, without access to its terminal: [`shutdown`] and
+//! [`restart_networking`] are called by the admin server functions of the web
+//! interface.
+
+#![cfg(feature = "server")]
+
+/// Lowest port scanned for the peers of the site
+pub const LOW_PORT: u16 = 10000;
+/// Highest port scanned for the peers of the site
+pub const HIGH_PORT: u16 = 11000;
+/// Offset between the port of the site and the port of its web server
+pub const PORT_OFFSET: u16 = HIGH_PORT - LOW_PORT + 1;
+
+/// Number of events kept for the subscribers that are late to receive them
+const EVENT_CAPACITY: usize = 256;
+
+/// Time left to the web server to answer the shutdown request before the
+/// process exits
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Request sent to the serve loop of the node
+enum ServeRequest {
+    /// Leave the network and stop serving the peers
+    Shutdown {
+        done: tokio::sync::oneshot::Sender<()>,
+    },
+    /// Leave the network and join it again, on another port if given
+    RestartNetworking {
+        port: Option<u16>,
+        done: tokio::sync::oneshot::Sender<Result<std::net::SocketAddr, String>>,
+    },
+}
+
+/// Sends the requests to the serve loop, set once the node is served
+static SERVE_REQUESTS: std::sync::OnceLock<tokio::sync::mpsc::UnboundedSender<ServeRequest>> =
+    std::sync::OnceLock::new();
+
+/// Sends a request to the serve loop of the node
+fn send_serve_request(request: ServeRequest) -> Result<(), String> {
+    SERVE_REQUESTS
+        .get()
+        .ok_or("The node is not served")?
+        .send(request)
+        .map_err(|_| "The node is stopping".to_string())
+}
+
+/// Tells the neighbours that the site leaves, then stops serving the peers
+///
+/// Returns once the neighbours were told, the process exiting shortly after.
+pub async fn shutdown() -> Result<(), String> {
+    let (done, wait) = tokio::sync::oneshot::channel();
+    send_serve_request(ServeRequest::Shutdown { done })?;
+    wait.await.map_err(|_| "The node is stopping".to_string())
+}
+
+/// Leaves the network and joins it again, listening for the peers on `port`
+/// if given
+///
+/// Returns the new address of the site. The web server keeps its address.
+pub async fn restart_networking(port: Option<u16>) -> Result<std::net::SocketAddr, String> {
+    let (done, wait) = tokio::sync::oneshot::channel();
+    send_serve_request(ServeRequest::RestartNetworking { port, done })?;
+    wait.await.map_err(|_| "The node is stopping".to_string())?
+}
+
+/// Configuration of a node, the defaults being those of the command line
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Unique identifier of the site, generated from the MAC address if empty
+    pub site_id: String,
+    /// Human-friendly name of the site, shown by the other sites instead of its ID
+    pub site_alias: Option<String>,
+    /// IP address to bind to, IPv4 or IPv6
+    pub ip: std::net::IpAddr,
+    /// Port of the peer-to-peer communication, 0 for the first free one
+    pub port: u16,
+    /// Port of the web server, the port of the site plus [`PORT_OFFSET`] if None
+    pub web_port: Option<u16>,
+    /// Peers to connect to, as `ip:port`, `[ipv6]:port` or `hostname:port`
+    pub peers: Vec<String>,
+    /// ID of the database, the database being `peillute_<id>.db`
+    pub db_id: u16,
+    /// Path of the database, instead of the one derived from `db_id`
+    pub db_path: Option<std::path::PathBuf>,
+    /// Keep the database in memory only
+    pub ephemeral: bool,
+    /// Maximum size of a message exchanged with peers, in bytes
+    pub max_message_size: usize,
+    /// Maximum number of critical commands waiting for the global mutex
+    pub max_pending_commands: usize,
+    /// Free disk space below which new transactions are refused, in bytes
+    pub min_free_space: u64,
+    /// Size of the database above which new transactions are refused, in bytes (0 for no limit)
+    pub max_db_size: u64,
+    /// Interval between two automatic snapshots, in minutes (0 to disable them)
+    pub snapshot_interval: u64,
+    /// Number of snapshot files kept on disk (0 to keep them all)
+    pub snapshot_retention: usize,
+    /// True if the snapshots initiated by the site only fetch the transactions it does not know
+    pub delta_snapshots: bool,
+    /// Time after which a transaction can no longer be refunded, in hours (0 for no limit)
+    pub refund_window: u64,
+    /// Smallest amount of a deposit, withdrawal, payment or transfer
+    pub min_amount: f64,
+    /// Greatest amount of a deposit, withdrawal, payment or transfer
+    pub max_amount: f64,
+    /// Time without news from the holder of the global mutex before it is reported as stuck, in seconds (0 to disable)
+    pub mutex_timeout: u64,
+    /// Time a component of the global state may be held before a warning is logged, in milliseconds (0 to disable)
+    pub lock_warn_ms: u64,
+    /// Round-trip time above which a wave relayed by the site may skip a neighbour of its initiator, in milliseconds (0 to disable)
+    pub slow_neighbour_rtt: u64,
+    /// Logical clock strategy of the site
+    pub clock: crate::clock::ClockStrategy,
+    /// Transport used to exchange messages with the peers
+    pub transport: crate::network::Transport,
+    /// Format of the messages sent to the peers
+    pub wire_format: crate::codec::WireFormat,
+    /// TOML file of users and initial balances, loaded on the first start of the site
+    pub seed_file: Option<String>,
+    /// TOML file of the webhooks notified of every recorded transaction
+    pub webhooks_file: Option<String>,
+    /// TOML file of the channels alerted of the large transactions
+    pub alerts_file: Option<String>,
+    /// TOML file of the operators of the CLI and the admin socket, with their role
+    pub operators_file: Option<String>,
+    /// TOML file of the fees charged on the transfers and payments submitted to the site
+    pub fees_file: Option<String>,
+    /// TOML file naming the system accounts of the deposits, withdrawals and payments
+    pub system_accounts_file: Option<String>,
+    /// Transactions generated per minute by the demo mode (0 to disable it)
+    pub demo_rate: u32,
+    /// Full node to forward the critical commands to, instead of taking part in the protocols
+    pub client_of: Option<String>,
+    /// Site IDs of the client sites allowed to forward commands to this site
+    pub trusted_clients: Vec<String>,
+    /// IP addresses or CIDR blocks allowed to connect to the site, any if empty
+    pub peer_allowlist: Vec<String>,
+    /// IP addresses or CIDR blocks refused, even if allowed
+    pub peer_denylist: Vec<String>,
+    /// File holding the secret shared by the sites of the deployment
+    pub cluster_secret_file: Option<String>,
+    /// Queue the transactions submitted while the site is offline, and send them once it is back
+    pub offline_queue: bool,
+    /// Start in maintenance mode, refusing the new transactions
+    pub maintenance: bool,
+    /// Accept the CLI commands on a UNIX domain socket, on UNIX systems
+    pub admin_socket: bool,
+    /// Path of the admin socket, `peillute_<site-id>.sock` by default
+    pub admin_socket_path: Option<std::path::PathBuf>,
+    /// OpenTelemetry collector the spans of the waves are exported to, with the `otlp` feature
+    pub otlp_endpoint: Option<String>,
+    /// Setup file written by the setup wizard, next to the database by default
+    pub setup_file: Option<std::path::PathBuf>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            site_id: String::new(),
+            site_alias: None,
+            ip: std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+            port: 0,
+            web_port: None,
+            peers: Vec::new(),
+            db_id: 0,
+            db_path: None,
+            ephemeral: false,
+            max_message_size: 1024 * 1024,
+            max_pending_commands: crate::state::DEFAULT_MAX_PENDING_COMMANDS,
+            min_free_space: crate::storage::DEFAULT_MIN_FREE_SPACE,
+            max_db_size: 0,
+            snapshot_interval: 0,
+            snapshot_retention: 0,
+            delta_snapshots: false,
+            refund_window: crate::db::DEFAULT_REFUND_WINDOW_HOURS,
+            min_amount: crate::amount::DEFAULT_MIN_AMOUNT,
+            max_amount: crate::amount::DEFAULT_MAX_AMOUNT,
+            mutex_timeout: 30,
+            lock_warn_ms: crate::lock_stats::DEFAULT_WARN_MS,
+            slow_neighbour_rtt: 0,
+            clock: crate::clock::ClockStrategy::default(),
+            transport: crate::network::Transport::default(),
+            wire_format: crate::codec::WireFormat::default(),
+            seed_file: None,
+            webhooks_file: None,
+            alerts_file: None,
+            operators_file: None,
+            fees_file: None,
+            system_accounts_file: None,
+            demo_rate: 0,
+            client_of: None,
+            trusted_clients: Vec::new(),
+            peer_allowlist: Vec::new(),
+            peer_denylist: Vec::new(),
+            cluster_secret_file: None,
+            offline_queue: false,
+            maintenance: false,
+            admin_socket: true,
+            admin_socket_path: None,
+            otlp_endpoint: None,
+            setup_file: None,
+        }
+    }
+}
+
+/// Event of a node, received by its subscribers
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeEvent {
+    /// A transaction was recorded, whichever site initiated it
+    Transaction(crate::db::Transaction),
+    /// A live event was published for the web interface
+    Live(crate::live::LiveEvent),
+    /// A record was logged, at the info level or above
+    Log(crate::attach::LogRecord),
+    /// A wave went through the site
+    Wave(crate::attach::WaveActivity),
+    /// A command was run by an attached terminal
+    Command(crate::attach::CommandResult),
+}
+
+lazy_static::lazy_static! {
+    static ref EVENTS: tokio::sync::broadcast::Sender<NodeEvent> =
+        tokio::sync::broadcast::channel(EVENT_CAPACITY).0;
+}
+
+/// Sends an event to the subscribers of the node, if any
+pub(crate) fn emit(event: NodeEvent) {
+    // an error only means that nobody is subscribed
+    let _ = EVENTS.send(event);
+}
+
+/// Subscribes to the events of the node
+pub(crate) fn subscribe() -> tokio::sync::broadcast::Receiver<NodeEvent> {
+    EVENTS.subscribe()
+}
+
+/// Peillute node, not started yet
+pub struct Node {
+    config: NodeConfig,
+}
+
+impl Node {
+    /// Creates a node from its configuration
+    pub fn new(config: NodeConfig) -> Self {
+        Self { config }
+    }
+
+    /// Starts the node, then serves the peers until the process is interrupted
+    pub async fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        self.start().await?.serve().await
+    }
+
+    /// Opens the database, starts the workers and joins the network
+    ///
+    /// The peers are only served once [`RunningNode::serve`] is called.
+    pub async fn start(self) -> Result<RunningNode, Box<dyn std::error::Error>> {
+        use crate::state::{SITE_INFO, WAVES};
+        use crate::{aliases, amount, client, clock, codec, db, network, snapshot, state, storage};
+
+        let mut config = self.config;
+        // the options missing from the command line are taken from the setup file
+        if !config.ephemeral {
+            let setup_path = config.setup_file.clone().unwrap_or_else(|| {
+                crate::setup::default_path(
+                    &config
+                        .db_path
+                        .clone()
+                        .unwrap_or_else(|| db::default_path(config.db_id)),
+                )
+            });
+            if setup_path.exists() {
+                crate::setup::SetupFile::load(&setup_path)?.fill(&mut config);
+            }
+            crate::setup::set_path(setup_path);
+        }
+        if !(0.0..=config.max_amount).contains(&config.min_amount) {
+            return Err(format!(
+                "Invalid amount limits: {} to {}",
+                config.min_amount, config.max_amount
+            )
+            .into());
+        }
+
+        // the postings of the transactions replayed on startup use the system accounts
+        if let Some(accounts_path) = &config.system_accounts_file {
+            crate::system_accounts::configure(crate::system_accounts::SystemAccounts::load(
+                accounts_path,
+            )?);
+        }
+
+        if config.ephemeral {
+            db::open_ephemeral(config.db_id)?;
+        } else {
+            let db_path = match &config.db_path {
+                Some(path) => path.clone(),
+                None => db::default_path(config.db_id),
+            };
+            db::open(&db_path)?;
+        }
+        let fresh_db = !db::is_database_initialized()?;
+        if fresh_db {
+            let _ = db::init_db();
+        } else {
+            db::upgrade_db()?;
+        }
+
+        crate::control::control_worker();
+        network::connection_reaper();
+        crate::heartbeat::set_slow_neighbour_rtt(config.slow_neighbour_rtt);
+        crate::heartbeat::heartbeat_worker();
+        db::gc_worker();
+        crate::confirmation::start_reaper();
+        crate::statements::statement_worker();
+        storage::storage_worker();
+        if config.mutex_timeout > 0 {
+            state::mutex_watchdog(std::time::Duration::from_secs(config.mutex_timeout));
+        }
+
+        crate::lock_stats::configure(config.lock_warn_ms);
+        network::set_max_message_size(config.max_message_size);
+        state::set_max_pending_commands(config.max_pending_commands);
+        storage::set_limits(config.min_free_space, config.max_db_size);
+        clock::set_clock_strategy(config.clock);
+        network::set_transport(config.transport);
+        codec::set_wire_format(config.wire_format);
+        snapshot::set_retention(config.snapshot_retention);
+        snapshot::set_delta(config.delta_snapshots);
+        db::set_refund_window(config.refund_window);
+        amount::set_limits(config.min_amount, config.max_amount);
+
+        let bind_ip = config.ip;
+        let selected_port = if config.port == 0 {
+            (LOW_PORT..=HIGH_PORT)
+                .find(|port| std::net::TcpListener::bind((bind_ip, *port)).is_ok())
+                .unwrap_or(LOW_PORT)
+        } else {
+            config.port
+        };
+        let site_addr = std::net::SocketAddr::new(bind_ip, selected_port);
+        crate::web_address::configure(config.web_port);
+        let web_addr =
+            std::net::SocketAddr::new(bind_ip, crate::web_address::port_for(selected_port));
+
+        let peer_addrs = network::resolve_peers(&config.peers, bind_ip).await;
+
+        if let Some(full_node) = &config.client_of {
+            let Some(full_node_addr) = network::resolve_peer(full_node, bind_ip).await else {
+                return Err(format!("cannot resolve the full node {}", full_node).into());
+            };
+            client::set_full_node(full_node_addr);
+        }
+        client::accept_clients(config.trusted_clients.clone());
+        let cluster_secret = match &config.cluster_secret_file {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| format!("cannot read the cluster secret file {}: {}", path, e))?,
+            ),
+            None => None,
+        };
+        crate::peer_auth::configure(
+            &config.peer_allowlist,
+            &config.peer_denylist,
+            cluster_secret,
+        )?;
+        if config.offline_queue {
+            crate::offline::enable();
+        }
+        if config.maintenance {
+            crate::maintenance::enable(None);
+        }
+
+        let (site_id, site_clock, needs_sync) = match crate::utils::reload_existing_site().await {
+            Ok((site_id_from_db, clock_from_db)) => (site_id_from_db, clock_from_db, true),
+            Err(_) => {
+                let generated_site_id = if config.site_id.is_empty() {
+                    crate::utils::get_mac_address().unwrap_or_default()
+                        + "_"
+                        + &std::process::id().to_string()
+                } else {
+                    config.site_id.clone()
+                };
+                (generated_site_id, clock::Clock::new(), false)
+            }
+        };
+
+        if let Some(alias) = &config.site_alias {
+            aliases::validate_alias(alias)?;
+        }
+        {
+            let mut site = SITE_INFO.write().await;
+            site.init_site_id(site_id.clone());
+            site.init_site_addr(site_addr);
+            site.init_cli_peer_addrs(peer_addrs);
+            site.init_sync(needs_sync);
+            site.init_site_alias(config.site_alias.clone());
+        }
+        aliases::load().await?;
+        if let Some(endpoint) = &config.otlp_endpoint {
+            #[cfg(feature = "otlp")]
+            crate::telemetry::init(endpoint, &site_id)?;
+            #[cfg(not(feature = "otlp"))]
+            return Err(format!(
+                "Cannot export the spans to {}: built without the otlp feature",
+                endpoint
+            )
+            .into());
+        }
+        if let Some(alias) = &config.site_alias {
+            aliases::record(&site_id, alias).await;
+        }
+        snapshot::init_snapshot_dir(&site_id);
+        #[cfg(unix)]
+        if config.admin_socket {
+            let path = config
+                .admin_socket_path
+                .clone()
+                .unwrap_or_else(|| crate::admin::default_path(&site_id));
+            crate::admin::start(&path)?;
+        }
+        if config.snapshot_interval > 0 {
+            snapshot::snapshot_scheduler(std::time::Duration::from_secs(
+                config.snapshot_interval * 60,
+            ));
+        }
+        state::init_clock(site_clock).await;
+        // Initialize the parent of the current site as self for the wave protocol
+        WAVES
+            .lock()
+            .await
+            .set_parent_addr(site_id.clone(), site_addr);
+
+        if let Some(webhooks_path) = &config.webhooks_file {
+            crate::webhooks::start(crate::webhooks::WebhookConfig::load(webhooks_path)?);
+        }
+        if let Some(alerts_path) = &config.alerts_file {
+            crate::alerts::start(crate::alerts::AlertConfig::load(alerts_path)?);
+        }
+        if let Some(operators_path) = &config.operators_file {
+            crate::operators::configure(crate::operators::OperatorsConfig::load(operators_path)?);
+        }
+        if let Some(fees_path) = &config.fees_file {
+            crate::fees::configure(crate::fees::FeeConfig::load(fees_path)?);
+        }
+
+        if fresh_db && let Some(seed_path) = &config.seed_file {
+            let seed = crate::seed::SeedFile::load(seed_path)?;
+            crate::seed::apply(&seed).await?;
+        }
+
+        // Create the network listener
+        let listener = network::bind_listener(site_addr)?;
+        log::debug!("Listening on: {}", site_addr);
+        // With gRPC, the peer service owns the listener instead of the node
+        let listener = match config.transport {
+            network::Transport::Tcp => Some(listener),
+            network::Transport::Grpc => {
+                tokio::spawn(async move {
+                    if let Err(e) = crate::grpc::serve(listener).await {
+                        log::error!("gRPC peer service stopped: {}", e);
+                    }
+                });
+                None
+            }
+        };
+
+        // Announce our presence to the network, a client site only talking to its full node
+        if client::full_node().is_none() {
+            network::announce(bind_ip, LOW_PORT, HIGH_PORT, selected_port).await;
+        }
+        let replayed = crate::command_log::replay().await?;
+        if replayed > 0 {
+            log::info!(
+                "Replayed {} command(s) left pending by the last run",
+                replayed
+            );
+        }
+
+        if config.demo_rate > 0 {
+            crate::demo::start(config.demo_rate);
+        }
+
+        if !config.ephemeral {
+            let discovery_path = crate::web_address::default_path(
+                &config
+                    .db_path
+                    .clone()
+                    .unwrap_or_else(|| db::default_path(config.db_id)),
+            );
+            if let Some(former) = crate::web_address::read(&discovery_path)
+                && former != web_addr
+            {
+                tokio::spawn(crate::web_address::redirect(former, web_addr));
+            }
+            if let Err(e) = crate::web_address::write(&discovery_path, web_addr) {
+                log::error!(
+                    "Cannot write the web address to {}: {}",
+                    discovery_path.display(),
+                    e
+                );
+            }
+        }
+
+        Ok(RunningNode {
+            site_id,
+            addr: site_addr,
+            web_addr,
+            listener,
+        })
+    }
+}
+
+/// Peillute node that joined the network
+pub struct RunningNode {
+    /// ID of the site
+    site_id: String,
+    /// Address of the site for its peers
+    addr: std::net::SocketAddr,
+    /// Address of the web server of the site
+    web_addr: std::net::SocketAddr,
+    /// Listener of the peer connections, owned by the gRPC service when it is used
+    listener: Option<tokio::net::TcpListener>,
+}
+
+impl RunningNode {
+    /// Returns the ID of the site
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+
+    /// Returns the address of the site for its peers
+    pub fn addr(&self) -> std::net::SocketAddr {
+        self.addr
+    }
+
+    /// Returns the address reserved for the web server of the site
+    pub fn web_addr(&self) -> std::net::SocketAddr {
+        self.web_addr
+    }
+
+    /// Returns a handle to submit commands to the node and follow its events
+    pub fn handle(&self) -> NodeHandle {
+        NodeHandle {
+            site_id: self.site_id.clone(),
+        }
+    }
+
+    /// Accepts the connections of the peers until the process is interrupted
+    /// or [`shutdown`] is called, then tells the neighbours that the site leaves
+    pub async fn serve(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (sender, mut requests) = tokio::sync::mpsc::unbounded_channel();
+        SERVE_REQUESTS
+            .set(sender)
+            .map_err(|_| "the node is already served")?;
+        loop {
+            tokio::select! {
+                Ok((stream, addr)) = accept(&self.listener) => {
+                    crate::network::start_listening(stream, addr).await;
+                }
+                Some(request) = requests.recv() => match request {
+                    ServeRequest::Shutdown { done } => {
+                        #[cfg(unix)]
+                        crate::admin::remove_socket();
+                        disconnect().await;
+                        #[cfg(feature = "otlp")]
+                        crate::telemetry::shutdown().await;
+                        let _ = done.send(());
+                        tokio::time::sleep(SHUTDOWN_GRACE).await;
+                        return Ok(());
+                    }
+                    ServeRequest::RestartNetworking { port, done } => {
+                        let _ = done.send(self.restart_networking(port).await);
+                    }
+                },
+                _ = tokio::signal::ctrl_c() => {
+                    #[cfg(unix)]
+                    crate::admin::remove_socket();
+                    disconnect().await;
+                    #[cfg(feature = "otlp")]
+                    crate::telemetry::shutdown().await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Leaves the network and joins it again, on another port if given
+    ///
+    /// The new port is bound before leaving, so that a port in use leaves the
+    /// site connected.
+    async fn restart_networking(
+        &mut self,
+        port: Option<u16>,
+    ) -> Result<std::net::SocketAddr, String> {
+        use crate::state::{PEERS, SITE_INFO, WAVES};
+
+        let new_addr = std::net::SocketAddr::new(self.addr.ip(), port.unwrap_or(self.addr.port()));
+        let listener = if new_addr != self.addr {
+            if self.listener.is_none() {
+                return Err("The port cannot be changed with the gRPC transport".to_string());
+            }
+            Some(
+                crate::network::bind_listener(new_addr)
+                    .map_err(|e| format!("Cannot listen on {}: {}", new_addr, e))?,
+            )
+        } else {
+            None
+        };
+
+        log::info!("Restarting the networking of site {}", self.site_id);
+        disconnect().await;
+        let neighbours = PEERS.read().await.get_connected_nei_addr();
+        for addr in neighbours {
+            crate::state::remove_peer(addr).await;
+        }
+        if let Some(listener) = listener {
+            self.listener = Some(listener);
+            self.addr = new_addr;
+            SITE_INFO.write().await.init_site_addr(new_addr);
+            WAVES
+                .lock()
+                .await
+                .set_parent_addr(self.site_id.clone(), new_addr);
+        }
+        if crate::client::full_node().is_none() {
+            crate::network::announce(new_addr.ip(), LOW_PORT, HIGH_PORT, new_addr.port()).await;
+        }
+        Ok(new_addr)
+    }
+}
+
+/// Accepts the next peer connection, never resolves without a listener
+async fn accept(
+    listener: &Option<tokio::net::TcpListener>,
+) -> std::io::Result<(tokio::net::TcpStream, std::net::SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Tells the connected neighbours that the site leaves the network
+async fn disconnect() {
+    use crate::message::{MessageInfo, NetworkMessageCode};
+    use crate::state::{PEERS, local_site};
+
+    let (site_id, local_addr) = local_site().await;
+    let connected_nei_addr = PEERS.read().await.get_connected_nei_addr();
+
+    log::info!("Shutting down site {}.", site_id);
+    for peer_addr in connected_nei_addr {
+        // increment the clock for every deconnection
+        let clock = crate::state::update_clock(None).await;
+
+        if let Err(e) = crate::network::send_message(
+            peer_addr,
+            MessageInfo::None,
+            None,
+            NetworkMessageCode::Disconnect,
+            local_addr,
+            &site_id,
+            &site_id,
+            local_addr,
+            clock.clone(),
+        )
+        .await
+        {
+            log::error!("Error sending message to {}: {}", peer_addr, e);
+        }
+    }
+}
+
+/// Handle to a running node, cheap to clone
+#[derive(Debug, Clone)]
+pub struct NodeHandle {
+    /// ID of the site
+    site_id: String,
+}
+
+impl NodeHandle {
+    /// Returns the ID of the site
+    pub fn site_id(&self) -> &str {
+        &self.site_id
+    }
+
+    /// Submits a critical command, executed once the site holds the global
+    /// mutex, and returns its confirmation ticket
+    pub async fn submit(
+        &self,
+        command: crate::control::CriticalCommands,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        crate::control::enqueue_critical(command).await
+    }
+
+    /// Waits until the command of a ticket is executed and diffused, or the
+    /// timeout expires
+    pub async fn wait_for_confirmation(
+        &self,
+        ticket: u64,
+        timeout: std::time::Duration,
+    ) -> crate::confirmation::ConfirmationStatus {
+        crate::confirmation::wait_for_confirmation(ticket, timeout).await
+    }
+
+    /// Subscribes to the events of the node
+    ///
+    /// A subscriber falling too far behind misses the oldest events, see
+    /// [`tokio::sync::broadcast`].
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<NodeEvent> {
+        subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_events() {
+        let handle = NodeHandle {
+            site_id: "A".to_string(),
+        };
+        let mut events = handle.subscribe();
+        let alert = crate::live::LiveEvent::BalanceAlert {
+            user: "alice".to_string(),
+            balance: 2.0,
+            threshold: 5.0,
+        };
+        crate::live::publish(alert.clone());
+        // other tests may record transactions concurrently
+        let received = std::iter::from_fn(|| events.try_recv().ok()).collect::<Vec<_>>();
+        assert!(received.contains(&NodeEvent::Live(alert)));
+    }
+
+    #[tokio::test]
+    async fn test_serve_requests_need_a_served_node() {
+        assert_eq!(shutdown().await, Err("The node is not served".to_string()));
+        assert!(restart_networking(Some(10007)).await.is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Notifications shown to the users
+//!
+//! The events concerning a user, such as the money they received, the refunds
+//! they were given or the end of the synchronization of the site, are stored
+//! in the Notifications table. They are local to the site: each site notifies
+//! its users of the transactions it records, whether they were created on this
+//! site or received from the network. The web interface shows the number of
+//! unread notifications in the navigation bar and lists them on their page.
+
+/// Event shown to a user
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Notification {
+    /// Identifier of the notification
+    pub id: i64,
+    /// User the notification is for
+    pub user: String,
+    /// Text of the notification
+    pub message: String,
+    /// Local date at which the notification was created
+    pub created_at: String,
+    /// True once the user has read the notification
+    pub read: bool,
+}
+
+#[cfg(feature = "server")]
+/// Returns the notifications to create for a transaction, as (user, message)
+///
+/// The beneficiary of a transfer or a refund and the owner of a deposited
+/// account are notified, withdrawals being made by the users themselves.
+fn transaction_messages(transaction: &crate::db::Transaction) -> Vec<(String, String)> {
+    let crate::db::Transaction {
+        from_user,
+        to_user,
+        amount,
+        optional_msg,
+        ..
+    } = transaction;
+    if to_user == "NULL" {
+        return Vec::new();
+    }
+    let message = if from_user == "NULL" {
+        format!("{:.2} € were deposited on your account", amount)
+    } else if optional_msg
+        .as_deref()
+        .is_some_and(|msg| msg.starts_with("Refund transaction"))
+    {
+        format!("{} refunded you {:.2} €", from_user, amount)
+    } else {
+        format!("You received {:.2} € from {}", amount, from_user)
+    };
+    vec![(to_user.clone(), message)]
+}
+
+#[cfg(feature = "server")]
+/// Stores a notification for a user
+fn notify_in(conn: &rusqlite::Connection, user: &str, message: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO Notifications (unique_name, message, created_at) VALUES (?1, ?2, ?3)",
+        rusqlite::params![
+            user,
+            message,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the notifications, the most recent first
+fn notifications_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<Notification>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, unique_name, message, created_at, is_read
+        FROM Notifications ORDER BY id DESC",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(Notification {
+            id: row.get(0)?,
+            user: row.get(1)?,
+            message: row.get(2)?,
+            created_at: row.get(3)?,
+            read: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the number of unread notifications of each user having some
+fn unread_counts_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<(String, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT unique_name, COUNT(*) FROM Notifications WHERE is_read = 0
+        GROUP BY unique_name ORDER BY unique_name",
+    )?;
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Marks a notification as read, or every notification of a user without `id`
+fn mark_read_in(conn: &rusqlite::Connection, user: &str, id: Option<i64>) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE Notifications SET is_read = 1
+        WHERE unique_name = ?1 AND (?2 IS NULL OR id = ?2)",
+        rusqlite::params![user, id],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Notifies the users concerned by a recorded transaction
+///
+/// A notification that cannot be stored is only logged, the transaction
+/// being recorded anyway.
+pub fn notify_transaction(transaction: &crate::db::Transaction) {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    for (user, message) in transaction_messages(transaction) {
+        if let Err(e) = notify_in(&conn, &user, &message) {
+            log::error!("Failed to notify {}: {}", user, e);
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Notifies every active user of an event of the site
+pub fn notify_all(message: &str) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    conn.execute(
+        "INSERT INTO Notifications (unique_name, message, created_at)
+        SELECT unique_name, ?1, ?2 FROM User WHERE is_active = 1",
+        rusqlite::params![
+            message,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the notifications, the most recent first
+pub fn get_notifications() -> rusqlite::Result<Vec<Notification>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    notifications_in(&conn)
+}
+
+#[cfg(feature = "server")]
+/// Returns the number of unread notifications of each user having some
+pub fn unread_counts() -> rusqlite::Result<Vec<(String, i64)>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    unread_counts_in(&conn)
+}
+
+#[cfg(feature = "server")]
+/// Marks a notification of a user as read, or all of them without `id`
+pub fn mark_read(user: &str, id: Option<i64>) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    mark_read_in(&conn, user, id)
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications() {
+        let transaction = |from: &str, to: &str, msg: Option<&str>| crate::db::Transaction {
+            from_user: from.to_string(),
+            to_user: to.to_string(),
+            amount: 20.0,
+            lamport_time: 1,
+            source_node: "A".to_string(),
+            optional_msg: msg.map(str::to_string),
+            vector_clock: std::collections::HashMap::new(),
+        };
+        assert_eq!(
+            transaction_messages(&transaction("bob", "alice", None)),
+            vec![(
+                "alice".to_string(),
+                "You received 20.00 € from bob".to_string()
+            )]
+        );
+        assert_eq!(
+            transaction_messages(&transaction("bob", "alice", Some("Refund transaction A-1"))),
+            vec![("alice".to_string(), "bob refunded you 20.00 €".to_string())]
+        );
+        assert!(transaction_messages(&transaction("alice", "NULL", None)).is_empty());
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        notify_in(&conn, "alice", "first").unwrap();
+        notify_in(&conn, "alice", "second").unwrap();
+        notify_in(&conn, "bob", "third").unwrap();
+        assert_eq!(
+            unread_counts_in(&conn).unwrap(),
+            vec![("alice".to_string(), 2), ("bob".to_string(), 1)]
+        );
+
+        let notifications = notifications_in(&conn).unwrap();
+        assert_eq!(notifications[0].message, "third");
+        // a user cannot mark the notification of another user
+        mark_read_in(&conn, "alice", Some(notifications[0].id)).unwrap();
+        mark_read_in(&conn, "alice", Some(notifications[1].id)).unwrap();
+        assert_eq!(
+            unread_counts_in(&conn).unwrap(),
+            vec![("alice".to_string(), 1), ("bob".to_string(), 1)]
+        );
+        mark_read_in(&conn, "bob", None).unwrap();
+        assert_eq!(
+            unread_counts_in(&conn).unwrap(),
+            vec![("alice".to_string(), 1)]
+        );
+        assert!(!notifications_in(&conn).unwrap()[2].read);
+    }
+}
@@ -0,0 +1,500 @@
+//! Offline queue of the transactions submitted without network
+//!
+//! With `--offline-queue`, a site that lost the network keeps accepting the
+//! transactions of its users: a client site whose full node is unreachable,
+//! or a full node whose configured peers are all gone. Instead of being
+//! executed, which would make the site diverge from the others, such a
+//! transaction is stored in the PendingLocal table, its ticket stays Offline,
+//! and it is listed on the page of its user as waiting for the network.
+//!
+//! A flusher checks the connectivity every few seconds and, once it is back,
+//! pushes the queued transactions through the usual critical path in their
+//! order of submission. Each one is checked again at flush time: one whose
+//! users were archived or removed meanwhile, or whose payer can no longer
+//! afford it, is rejected and stays listed with the reason until its user
+//! dismisses it.
+
+/// Transaction queued while the site was offline
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PendingLocalCommand {
+    /// Identifier of the queued transaction
+    pub id: i64,
+    /// User who submitted the transaction
+    pub user: String,
+    /// Summary of the transaction
+    pub description: String,
+    /// Local date at which the transaction was queued
+    pub queued_at: String,
+    /// Reason of the rejection of the transaction at flush time, if any
+    pub error: Option<String>,
+}
+
+#[cfg(feature = "server")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "server")]
+/// Interval between two attempts to flush the queue
+const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[cfg(feature = "server")]
+/// Time waited for each status of a flushed transaction
+const FOLLOW_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[cfg(feature = "server")]
+/// True once the transactions are queued when the site is offline
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "server")]
+/// True when the last command forwarded to the full node could not be sent
+static FULL_NODE_UNREACHABLE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    /// Tickets of the transactions queued since the site started, by queued transaction
+    static ref TICKETS: std::sync::Mutex<std::collections::HashMap<i64, u64>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+    /// Serializes the flushes, so that a transaction is not submitted twice
+    static ref FLUSHING: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+#[cfg(feature = "server")]
+/// Queues the transactions submitted while offline, and starts flushing them
+pub fn enable() {
+    if ENABLED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            if !has_pending() {
+                continue;
+            }
+            // a client probes its full node by forwarding the first transaction
+            if crate::client::full_node().is_none() && is_offline().await {
+                continue;
+            }
+            match flush().await {
+                Ok(0) => {}
+                Ok(flushed) => log::info!("Flushed {} transaction(s) queued offline", flushed),
+                Err(e) => log::error!("Cannot flush the offline queue: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(feature = "server")]
+/// Returns true if the transactions are queued when the site is offline
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "server")]
+/// Records whether the full node of this client site could be reached
+pub fn set_full_node_reachable(reachable: bool) {
+    if FULL_NODE_UNREACHABLE.swap(!reachable, Ordering::Relaxed) == reachable {
+        if reachable {
+            log::info!("The full node is reachable again");
+        } else {
+            log::warn!("The full node is unreachable, transactions are queued offline");
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns true if the site cannot reach the network
+///
+/// A client site is offline once a command could not be forwarded to its full
+/// node. A full node is offline when none of the peers it was started with,
+/// nor any other, is connected.
+pub async fn is_offline() -> bool {
+    if crate::client::full_node().is_some() {
+        return FULL_NODE_UNREACHABLE.load(Ordering::Relaxed);
+    }
+    !crate::state::SITE_INFO
+        .read()
+        .await
+        .get_cli_peers_addrs()
+        .is_empty()
+        && crate::state::get_nb_connected_neighbours().await == 0
+}
+
+#[cfg(feature = "server")]
+/// Returns true if a command must be queued instead of being executed
+pub async fn should_queue(cmd: &crate::control::CriticalCommands) -> bool {
+    is_enabled() && cmd.records_transaction() && is_offline().await
+}
+
+#[cfg(feature = "server")]
+/// Returns a summary of a queued transaction, shown to its user
+fn describe(cmd: &crate::control::CriticalCommands) -> String {
+    use crate::control::CriticalCommands;
+
+    match cmd {
+        CriticalCommands::Deposit { amount, .. } => format!("Deposit of {:.2} €", amount),
+        CriticalCommands::Withdraw { amount, .. } => format!("Withdrawal of {:.2} €", amount),
+        CriticalCommands::Transfer { to, amount, .. } => {
+            format!("Transfer of {:.2} € to {}", amount, to)
+        }
+        CriticalCommands::Pay {
+            amount,
+            tip_percent,
+            split_with,
+            ..
+        } if split_with.is_empty() => format!(
+            "Payment of {:.2} €",
+            crate::utils::with_tip(*amount, *tip_percent)
+        ),
+        CriticalCommands::Pay {
+            amount,
+            tip_percent,
+            split_with,
+            ..
+        } => format!(
+            "Payment of {:.2} € split with {}",
+            crate::utils::with_tip(*amount, *tip_percent),
+            split_with.join(", ")
+        ),
+        CriticalCommands::Refund { lamport, node, .. } => {
+            format!("Refund of transaction {}-{}", node, lamport)
+        }
+        CriticalCommands::BatchTransfer { transfers, .. } => format!(
+            "Split bill of {:.2} € between {} users",
+            transfers.iter().map(|(_, amount)| amount).sum::<f64>(),
+            transfers.len()
+        ),
+        CriticalCommands::Saga { steps } => format!("Saga of {} steps", steps.len()),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the money each user spends in a command
+fn spending(cmd: &crate::control::CriticalCommands) -> Vec<(&str, f64)> {
+    use crate::control::CriticalCommands;
+
+    match cmd {
+        CriticalCommands::Withdraw { name, amount } => vec![(name.as_str(), *amount)],
+        CriticalCommands::Transfer { from, amount, .. } => vec![(from.as_str(), *amount)],
+        CriticalCommands::BatchTransfer { from, transfers } => vec![(
+            from.as_str(),
+            transfers.iter().map(|(_, amount)| amount).sum(),
+        )],
+        CriticalCommands::Pay {
+            name,
+            amount,
+            tip_percent,
+            split_with,
+        } => {
+            let payers: Vec<&str> = std::iter::once(name.as_str())
+                .chain(split_with.iter().map(String::as_str))
+                .collect();
+            let shares = crate::utils::split_with_tip(*amount, *tip_percent, payers.len());
+            payers.into_iter().zip(shares).collect()
+        }
+        CriticalCommands::Saga { steps } => steps
+            .iter()
+            .filter_map(|step| {
+                let (from, _) = step.accounts();
+                (from != crate::db::NULL).then_some((from, step.amount()))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Checks that a queued transaction can still be executed
+fn check(cmd: &crate::control::CriticalCommands) -> Result<(), Box<dyn std::error::Error>> {
+    for user in crate::control::command_users(cmd) {
+        if !crate::db::user_exists(user)? {
+            return Err(format!("User '{}' does not exist.", user).into());
+        }
+    }
+    crate::control::ensure_active_users(cmd)?;
+    for (user, amount) in spending(cmd) {
+        if crate::db::calculate_solde(user)? < amount {
+            return Err(format!("Insufficient funds: '{}' has less than {}.", user, amount).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Stores a transaction, returns its identifier
+fn store_in(
+    conn: &rusqlite::Connection,
+    user: &str,
+    command: &str,
+    description: &str,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO PendingLocal (unique_name, command, description, queued_at)
+        VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![
+            user,
+            command,
+            description,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+#[cfg(feature = "server")]
+/// Returns the transactions to flush, as (identifier, command), oldest first
+fn pending_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<(i64, String)>> {
+    let mut stmt =
+        conn.prepare("SELECT id, command FROM PendingLocal WHERE error IS NULL ORDER BY id")?;
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the queued transactions of a user, oldest first
+fn queued_in(
+    conn: &rusqlite::Connection,
+    user: &str,
+) -> rusqlite::Result<Vec<PendingLocalCommand>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, unique_name, description, queued_at, error
+        FROM PendingLocal WHERE unique_name = ?1 ORDER BY id",
+    )?;
+    stmt.query_map(rusqlite::params![user], |row| {
+        Ok(PendingLocalCommand {
+            id: row.get(0)?,
+            user: row.get(1)?,
+            description: row.get(2)?,
+            queued_at: row.get(3)?,
+            error: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Flags a queued transaction as rejected, so that it is not flushed
+fn reject_in(conn: &rusqlite::Connection, id: i64, reason: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE PendingLocal SET error = ?2 WHERE id = ?1",
+        rusqlite::params![id, reason],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Removes a queued transaction, of a given user if any, returns false if there is no such transaction
+fn remove_in(conn: &rusqlite::Connection, id: i64, user: Option<&str>) -> rusqlite::Result<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM PendingLocal WHERE id = ?1 AND (?2 IS NULL OR unique_name = ?2)",
+        rusqlite::params![id, user],
+    )?;
+    Ok(deleted > 0)
+}
+
+#[cfg(feature = "server")]
+/// Stores a transaction submitted offline under the ticket given to its user
+pub fn store(
+    ticket: u64,
+    cmd: &crate::control::CriticalCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let user = crate::control::command_users(cmd)
+        .first()
+        .map(|user| user.to_string())
+        .unwrap_or_default();
+    let id = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        store_in(&conn, &user, &serde_json::to_string(cmd)?, &describe(cmd))?
+    };
+    TICKETS.lock().unwrap().insert(id, ticket);
+    crate::confirmation::CONFIRMATIONS
+        .lock()
+        .unwrap()
+        .resolve(ticket, crate::confirmation::ConfirmationStatus::Offline);
+    log::info!("Queued offline: {}", describe(cmd));
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Queues a transaction submitted offline, returns its ticket
+pub fn queue(cmd: crate::control::CriticalCommands) -> Result<u64, Box<dyn std::error::Error>> {
+    let ticket = crate::confirmation::CONFIRMATIONS
+        .lock()
+        .unwrap()
+        .register();
+    store(ticket, &cmd)?;
+    Ok(ticket)
+}
+
+#[cfg(feature = "server")]
+/// Returns the transactions queued offline by a user, rejected ones included
+pub fn get_queued(user: &str) -> rusqlite::Result<Vec<PendingLocalCommand>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    queued_in(&conn, user)
+}
+
+#[cfg(feature = "server")]
+/// Drops a transaction queued offline by a user, returns false if there is no such transaction
+pub fn dismiss(user: &str, id: i64) -> rusqlite::Result<bool> {
+    let removed = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        remove_in(&conn, id, Some(user))?
+    };
+    if let Some(ticket) = TICKETS.lock().unwrap().remove(&id) {
+        crate::confirmation::CONFIRMATIONS
+            .lock()
+            .unwrap()
+            .failed(ticket, "Dismissed while offline".to_string());
+    }
+    Ok(removed)
+}
+
+#[cfg(feature = "server")]
+/// Returns true if transactions are waiting to be flushed
+pub fn has_pending() -> bool {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM PendingLocal WHERE error IS NULL)",
+        [],
+        |row| row.get(0),
+    )
+    .unwrap_or(false)
+}
+
+#[cfg(feature = "server")]
+/// Gives the status of the ticket of a flushed transaction to the ticket its user holds
+fn follow(ticket: u64, original: u64) {
+    use crate::confirmation::{CONFIRMATIONS, ConfirmationStatus, wait_for_confirmation};
+
+    tokio::spawn(async move {
+        loop {
+            let status = wait_for_confirmation(ticket, FOLLOW_TIMEOUT).await;
+            let done = status.is_final() || status == ConfirmationStatus::Offline;
+            CONFIRMATIONS.lock().unwrap().resolve(original, status);
+            if done {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(feature = "server")]
+/// Pushes the queued transactions through the critical path, oldest first
+///
+/// Stops at the first transaction that cannot be submitted, the network
+/// being lost again. Returns the number of transactions submitted.
+pub async fn flush() -> Result<usize, Box<dyn std::error::Error>> {
+    let _flushing = FLUSHING.lock().await;
+    let pending = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        pending_in(&conn)?
+    };
+    let mut flushed = 0;
+    for (id, encoded) in pending {
+        let checked = serde_json::from_str::<crate::control::CriticalCommands>(&encoded)
+            .map_err(|e| e.to_string())
+            .and_then(|cmd| check(&cmd).map(|()| cmd).map_err(|e| e.to_string()));
+        let cmd = match checked {
+            Ok(cmd) => cmd,
+            Err(reason) => {
+                log::warn!("Rejected the transaction {} queued offline: {}", id, reason);
+                {
+                    let conn = crate::db::DB_CONN.lock().unwrap();
+                    reject_in(&conn, id, &reason)?;
+                }
+                if let Some(ticket) = TICKETS.lock().unwrap().remove(&id) {
+                    crate::confirmation::CONFIRMATIONS
+                        .lock()
+                        .unwrap()
+                        .failed(ticket, reason);
+                }
+                continue;
+            }
+        };
+
+        let original = TICKETS.lock().unwrap().get(&id).copied();
+        if let Some(full_node) = crate::client::full_node() {
+            // the result reported by the full node goes straight to the ticket of the user
+            let ticket = original.unwrap_or_else(|| {
+                crate::confirmation::CONFIRMATIONS
+                    .lock()
+                    .unwrap()
+                    .register()
+            });
+            if let Err(e) = crate::client::send_forward(full_node, ticket, cmd).await {
+                log::debug!("The full node is still unreachable: {}", e);
+                set_full_node_reachable(false);
+                break;
+            }
+            set_full_node_reachable(true);
+        } else {
+            match crate::control::enqueue_critical(cmd).await {
+                Ok(ticket) => {
+                    if let Some(original) = original {
+                        follow(ticket, original);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Cannot flush the offline queue yet: {}", e);
+                    break;
+                }
+            }
+        }
+        {
+            let conn = crate::db::DB_CONN.lock().unwrap();
+            remove_in(&conn, id, None)?;
+        }
+        TICKETS.lock().unwrap().remove(&id);
+        flushed += 1;
+    }
+    Ok(flushed)
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_local() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+
+        let first = store_in(&conn, "alice", "{}", "Deposit of 10.00 €").unwrap();
+        let second = store_in(&conn, "alice", "{}", "Withdrawal of 5.00 €").unwrap();
+        store_in(&conn, "bob", "{}", "Deposit of 1.00 €").unwrap();
+        assert_eq!(pending_in(&conn).unwrap().len(), 3);
+
+        reject_in(&conn, second, "Insufficient funds").unwrap();
+        assert_eq!(pending_in(&conn).unwrap().len(), 2);
+        let queued = queued_in(&conn, "alice").unwrap();
+        assert_eq!(queued.len(), 2);
+        assert_eq!(queued[0].id, first);
+        assert_eq!(queued[1].error.as_deref(), Some("Insufficient funds"));
+
+        // a user only dismisses its own transactions
+        assert!(!remove_in(&conn, first, Some("bob")).unwrap());
+        assert!(remove_in(&conn, first, Some("alice")).unwrap());
+        assert_eq!(queued_in(&conn, "alice").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_spending() {
+        use crate::control::CriticalCommands;
+
+        let pay = CriticalCommands::Pay {
+            name: "alice".to_string(),
+            amount: 20.0,
+            tip_percent: 10.0,
+            split_with: vec!["bob".to_string()],
+        };
+        assert_eq!(spending(&pay), vec![("alice", 11.0), ("bob", 11.0)]);
+        let deposit = CriticalCommands::Deposit {
+            name: "alice".to_string(),
+            amount: 20.0,
+        };
+        assert!(spending(&deposit).is_empty());
+    }
+}
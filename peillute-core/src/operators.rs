@@ -0,0 +1,311 @@
+//! Roles of the operators of the CLI and the admin socket
+//!
+//! By default, whoever reaches the terminal or the admin socket of a site may
+//! run every command. With `--operators-file operators.toml`, each command
+//! needs a role, and the terminal and the connections to the admin socket
+//! start with the default role of the file:
+//!
+//! ```toml
+//! default_role = "viewer"
+//!
+//! [[operators]]
+//! name = "alice"
+//! role = "admin"
+//! token_sha256 = "<hexadecimal SHA-256 hash of the token of alice>"
+//! ```
+//!
+//! An operator gets their role with `/login <token>`, for the terminal or for
+//! the rest of the connection to the admin socket, and gives it up with
+//! `/logout`. Only the hash of the tokens is stored in the file, for example
+//! computed with `printf %s "$TOKEN" | sha256sum`.
+//!
+//! The viewers read the data of the site, the operators also run the money
+//! operations, and the admins also run the commands that cannot be undone or
+//! concern every site, such as the imports, snapshots and forced releases of
+//! the global mutex.
+
+#![cfg(feature = "server")]
+
+use crate::control::Command;
+
+/// What an operator may do, each role allowing what the previous ones do
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Reads the data of the site
+    Viewer,
+    /// Runs the money operations and manages the users
+    Operator,
+    /// Runs the commands that cannot be undone or concern every site
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let role = match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        };
+        f.pad(role)
+    }
+}
+
+/// Operator allowed to log in
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct Operator {
+    /// Name of the operator, shown when they log in
+    pub name: String,
+    /// Role given by the token of the operator
+    pub role: Role,
+    /// Hexadecimal SHA-256 hash of the token of the operator
+    pub token_sha256: String,
+}
+
+/// Content of an operators file
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct OperatorsConfig {
+    /// Role of the terminal and the admin connections before any login
+    #[serde(default = "default_viewer")]
+    pub default_role: Role,
+    /// Operators allowed to log in
+    #[serde(default)]
+    pub operators: Vec<Operator>,
+}
+
+fn default_viewer() -> Role {
+    Role::Viewer
+}
+
+impl OperatorsConfig {
+    /// Parses and validates the content of an operators file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut config: OperatorsConfig =
+            toml::from_str(content).map_err(|e| format!("invalid operators file: {}", e))?;
+        for operator in &mut config.operators {
+            operator.token_sha256 = operator.token_sha256.trim().to_lowercase();
+            if operator.token_sha256.len() != 64
+                || !operator.token_sha256.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return Err(format!(
+                    "invalid token hash of operator '{}', expected 64 hexadecimal digits",
+                    operator.name
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads and validates an operators file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read operators file {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    /// Returns the operator holding a token
+    fn operator_of(&self, token: &str) -> Option<&Operator> {
+        use sha2::Digest;
+
+        let hash: String = sha2::Sha256::digest(token.trim().as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.operators
+            .iter()
+            .find(|operator| operator.token_sha256 == hash)
+    }
+}
+
+/// Operators of the site, set once at startup
+static CONFIG: std::sync::OnceLock<OperatorsConfig> = std::sync::OnceLock::new();
+
+/// Role the terminal logged in with, if any
+static TERMINAL_ROLE: std::sync::Mutex<Option<Role>> = std::sync::Mutex::new(None);
+
+/// Sets the operators of the site
+pub fn configure(config: OperatorsConfig) {
+    if CONFIG.set(config).is_err() {
+        log::warn!("The operators are already set");
+    }
+}
+
+/// Returns the role of the terminal and the admin connections before any
+/// login, admin when no operators file is given
+pub fn default_role() -> Role {
+    CONFIG
+        .get()
+        .map_or(Role::Admin, |config| config.default_role)
+}
+
+/// Returns the role running the current command, the one of its admin
+/// session or of the terminal
+fn current_role() -> Role {
+    crate::console::session_role()
+        .or(*TERMINAL_ROLE.lock().unwrap())
+        .unwrap_or_else(default_role)
+}
+
+/// Gives a role to the current admin session, or to the terminal
+fn set_role(role: Role) {
+    if !crate::console::set_session_role(role) {
+        *TERMINAL_ROLE.lock().unwrap() = Some(role);
+    }
+}
+
+/// Logs the current admin session, or the terminal, in with a token
+pub fn login(token: &str) -> Result<Operator, String> {
+    let Some(config) = CONFIG.get() else {
+        return Err("No operators file is given, every command is allowed".to_string());
+    };
+    let operator = config
+        .operator_of(token)
+        .ok_or_else(|| "Unknown operator token".to_string())?
+        .clone();
+    set_role(operator.role);
+    log::info!("Operator {} logged in as {}", operator.name, operator.role);
+    Ok(operator)
+}
+
+/// Returns the role given by an operator token, without logging in
+pub fn role_of(token: &str) -> Option<Role> {
+    CONFIG
+        .get()?
+        .operator_of(token)
+        .map(|operator| operator.role)
+}
+
+/// Logs the current admin session, or the terminal, out
+pub fn logout() {
+    set_role(default_role());
+}
+
+/// Returns the role needed to run a command
+pub fn required_role(command: &Command) -> Role {
+    match command {
+        Command::Help
+        | Command::Info
+        | Command::WhoAmI
+        | Command::Balance(_)
+        | Command::Peers
+        | Command::UserAccounts
+        | Command::PrintUserTransactions
+        | Command::PrintTransactions
+        | Command::RefundRequests
+        | Command::Sagas
+        | Command::DiffSnapshots(_)
+        | Command::LockStats
+        | Command::Audit
+        | Command::AuditLog
+        | Command::Reconcile(_)
+        | Command::CheckBalanceCache
+        | Command::Login(_)
+        | Command::Logout
+        | Command::Unknown(_)
+        | Command::Error(_) => Role::Viewer,
+        Command::Maintenance(arg) if arg.is_empty() => Role::Viewer,
+        Command::CreateUser
+        | Command::RenameUser
+        | Command::Deposit
+        | Command::Withdraw
+        | Command::Transfer
+        | Command::Pay
+        | Command::SplitPay
+        | Command::Refund
+        | Command::AnswerRefund
+        | Command::BatchTransfer
+        | Command::Saga
+        | Command::UpsertProduct
+        | Command::Comment
+        | Command::CheckConsistency
+        | Command::ClusterStats => Role::Operator,
+        Command::DeactivateUser
+        | Command::ReactivateUser
+        | Command::DeleteProduct
+        | Command::Snapshot(_)
+        | Command::GcDb
+        | Command::Replay(_)
+        | Command::ExportDb(_)
+        | Command::ImportDb(_)
+        | Command::ClosePeriod
+        | Command::ForceRelease(_)
+        | Command::Maintenance(_)
+        | Command::ApiTokens
+        | Command::MintToken
+        | Command::RevokeToken(_) => Role::Admin,
+        #[cfg(feature = "chaos")]
+        Command::Chaos
+        | Command::ChaosDrop
+        | Command::ChaosDelay
+        | Command::ChaosPause
+        | Command::ChaosResume
+        | Command::ChaosReset => Role::Admin,
+    }
+}
+
+/// Checks that the current admin session, or the terminal, may run a command
+pub fn authorize(command: &Command) -> Result<(), String> {
+    let needed = required_role(command);
+    let role = current_role();
+    if role >= needed {
+        return Ok(());
+    }
+    Err(format!(
+        "This command needs the {} role, the current one is {}: log in with /login <token>",
+        needed, role
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        // SHA-256 of "s3cret"
+        let hash = "1ec1c26b50d5d3c58d9583181af8076655fe00756bf7285940ba3670f99fcba0";
+        let config = OperatorsConfig::parse(&format!(
+            "[[operators]]\nname = \"alice\"\nrole = \"admin\"\ntoken_sha256 = \"{}\"",
+            hash.to_uppercase()
+        ))
+        .unwrap();
+        assert_eq!(config.default_role, Role::Viewer);
+        assert_eq!(config.operator_of("s3cret").unwrap().name, "alice");
+        assert_eq!(config.operator_of(" s3cret\n").unwrap().role, Role::Admin);
+        assert!(config.operator_of("other").is_none());
+
+        assert!(
+            OperatorsConfig::parse(
+                "[[operators]]\nname = \"bob\"\nrole = \"admin\"\ntoken_sha256 = \"s3cret\""
+            )
+            .is_err()
+        );
+        assert!(OperatorsConfig::parse("default_role = \"root\"").is_err());
+    }
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(required_role(&Command::Info), Role::Viewer);
+        assert_eq!(required_role(&Command::Deposit), Role::Operator);
+        assert_eq!(
+            required_role(&Command::ImportDb("backup.json".to_string())),
+            Role::Admin
+        );
+        // the archive is written to any path of the site named by the caller
+        assert_eq!(
+            required_role(&Command::ExportDb("backup.json".to_string())),
+            Role::Admin
+        );
+        assert_eq!(
+            required_role(&Command::Maintenance(String::new())),
+            Role::Viewer
+        );
+        assert_eq!(
+            required_role(&Command::Maintenance("on".to_string())),
+            Role::Admin
+        );
+        assert!(Role::Admin > Role::Operator && Role::Operator > Role::Viewer);
+    }
+}
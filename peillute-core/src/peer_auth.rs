@@ -0,0 +1,477 @@
+//! Admission of the peers of a site
+//!
+//! `--peer-allowlist` and `--peer-denylist` take IP addresses or CIDR blocks:
+//! connections from a denied address, or from an address missing from a
+//! non-empty allowlist, are closed as soon as they are accepted. With
+//! `--cluster-secret-file`, the sites of the deployment share a secret, and
+//! prove they know it without sending it:
+//!
+//! - every TCP connection between two sites starts with a challenge: each end
+//!   sends a random nonce, then the HMAC of both nonces and of its side of the
+//!   connection, checked by the other end. Nothing else is read from the
+//!   connection until then, and the connection is closed if a proof is wrong.
+//!   A proof only holds for the nonces of its connection, so recording it is
+//!   of no use to open another one.
+//! - every gRPC request carries the HMAC of its payload, of the time it was
+//!   sent and of a random nonce. Requests older than [`PROOF_MAX_AGE`], or
+//!   whose nonce was already seen, are refused.
+//!
+//! The proofs are compared in constant time.
+
+#![cfg(feature = "server")]
+
+/// IP address or CIDR block of an allow or deny list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddrRule {
+    /// First address of the block
+    network: std::net::IpAddr,
+    /// Number of leading bits of the addresses matched by the block
+    prefix: u8,
+}
+
+impl std::str::FromStr for AddrRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let network: std::net::IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid IP address '{}'", addr))?;
+        let network = network.to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid prefix length '{}' in '{}'", prefix, s))?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl AddrRule {
+    /// Returns true if the address belongs to the block
+    pub fn matches(&self, ip: std::net::IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (std::net::IpAddr::V4(network), std::net::IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (std::net::IpAddr::V6(network), std::net::IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Rules the peers of the site are admitted with
+#[derive(Debug, Default)]
+struct PeerPolicy {
+    /// Addresses allowed to connect, any address if empty
+    allowlist: Vec<AddrRule>,
+    /// Addresses refused, even if allowed
+    denylist: Vec<AddrRule>,
+    /// Secret shared by the sites of the deployment
+    secret: Option<String>,
+}
+
+impl PeerPolicy {
+    /// Returns true if connections from the address are accepted
+    fn is_allowed(&self, ip: std::net::IpAddr) -> bool {
+        if self.denylist.iter().any(|rule| rule.matches(ip)) {
+            return false;
+        }
+        self.allowlist.is_empty() || self.allowlist.iter().any(|rule| rule.matches(ip))
+    }
+}
+
+/// Rules of the site, set once at startup
+static POLICY: std::sync::OnceLock<PeerPolicy> = std::sync::OnceLock::new();
+
+/// Size of the nonces of the challenges, in bytes
+const NONCE_SIZE: usize = 32;
+
+/// Size of the HMAC-SHA256 proofs, in bytes
+const PROOF_SIZE: usize = 32;
+
+/// Maximum time for the two ends of a connection to prove they know the secret
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Maximum age of the proof of a gRPC request, and of the clock skew between
+/// two sites
+pub const PROOF_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Name of the gRPC metadata carrying the proof of a request
+pub const PROOF_METADATA: &str = "x-peillute-proof";
+
+lazy_static::lazy_static! {
+    /// Nonces of the gRPC requests received within [`PROOF_MAX_AGE`], with
+    /// the time they were sent, in seconds since the Unix epoch
+    static ref SEEN_NONCES: std::sync::Mutex<std::collections::HashMap<String, u64>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+/// End of a connection between two sites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The site that opened the connection
+    Dialer,
+    /// The site that accepted it
+    Acceptor,
+}
+
+impl Side {
+    /// Label of the side in the proofs, so that a proof cannot be reflected
+    /// to the end that sent it
+    fn label(self) -> &'static [u8] {
+        match self {
+            Side::Dialer => b"dialer",
+            Side::Acceptor => b"acceptor",
+        }
+    }
+
+    /// The other end of the connection
+    fn other(self) -> Self {
+        match self {
+            Side::Dialer => Side::Acceptor,
+            Side::Acceptor => Side::Dialer,
+        }
+    }
+}
+
+/// Sets the lists and the secret the peers of the site are admitted with
+pub fn configure(
+    allowlist: &[String],
+    denylist: &[String],
+    secret: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let parse = |list: &[String]| {
+        list.iter()
+            .map(|rule| rule.parse::<AddrRule>())
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let policy = PeerPolicy {
+        allowlist: parse(allowlist)?,
+        denylist: parse(denylist)?,
+        secret: secret
+            .map(|secret| secret.trim().to_string())
+            .filter(|secret| !secret.is_empty()),
+    };
+    if policy.allowlist.is_empty() && policy.denylist.is_empty() && policy.secret.is_none() {
+        return Ok(());
+    }
+    if POLICY.set(policy).is_err() {
+        log::warn!("The peer admission rules are already set");
+    }
+    Ok(())
+}
+
+/// Returns true if connections from the address are accepted
+pub fn is_allowed(ip: std::net::IpAddr) -> bool {
+    POLICY.get().is_none_or(|policy| policy.is_allowed(ip))
+}
+
+/// Returns the HMAC of parts of a message with the secret
+fn mac(secret: &str, parts: &[&[u8]]) -> hmac::Hmac<sha2::Sha256> {
+    use hmac::Mac;
+
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(&(part.len() as u64).to_be_bytes());
+        mac.update(part);
+    }
+    mac
+}
+
+/// Generates a nonce from the random source of the system
+fn generate_nonce() -> std::io::Result<[u8; NONCE_SIZE]> {
+    use std::io::Read;
+
+    let mut nonce = [0u8; NONCE_SIZE];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// Proves to the other end of a new connection that this site knows the
+/// cluster secret, and checks that the other end does, if a secret is set
+///
+/// Called on the connection before any message is exchanged.
+pub async fn handshake<S>(stream: &mut S, side: Side) -> Result<(), String>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let Some(secret) = POLICY.get().and_then(|policy| policy.secret.as_deref()) else {
+        return Ok(());
+    };
+    let (site_id, _) = crate::state::local_site().await;
+    tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        handshake_with(secret, &site_id, stream, side),
+    )
+    .await
+    .map_err(|_| "the peer did not prove it knows the cluster secret in time".to_string())?
+}
+
+/// Runs the challenge of a connection with a secret, for the site `site_id`
+///
+/// Each end sends its nonce and site ID, then proves it knows the secret
+/// for the nonce of the other end. The proofs name the site and the side of
+/// the connection they come from, so that a peer cannot get this site to
+/// compute the proof it is asked for, by relaying the nonce of one of its
+/// connections to another one.
+async fn handshake_with<S>(
+    secret: &str,
+    site_id: &str,
+    stream: &mut S,
+    side: Side,
+) -> Result<(), String>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    use hmac::Mac;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let io_error = |e: std::io::Error| format!("cluster secret challenge failed: {}", e);
+    let id = site_id.as_bytes();
+    let id_len = u8::try_from(id.len()).map_err(|_| "the site ID is too long".to_string())?;
+
+    let nonce = generate_nonce().map_err(io_error)?;
+    let mut hello = nonce.to_vec();
+    hello.push(id_len);
+    hello.extend_from_slice(id);
+    stream.write_all(&hello).await.map_err(io_error)?;
+    let mut peer_nonce = [0u8; NONCE_SIZE];
+    stream.read_exact(&mut peer_nonce).await.map_err(io_error)?;
+    let mut peer_id = vec![0u8; stream.read_u8().await.map_err(io_error)? as usize];
+    stream.read_exact(&mut peer_id).await.map_err(io_error)?;
+    if peer_id == id {
+        return Err("the peer claims the site ID of this site".to_string());
+    }
+
+    let proof = mac(secret, &[side.label(), id, &peer_nonce, &nonce])
+        .finalize()
+        .into_bytes();
+    stream.write_all(&proof).await.map_err(io_error)?;
+    let mut peer_proof = [0u8; PROOF_SIZE];
+    stream.read_exact(&mut peer_proof).await.map_err(io_error)?;
+    mac(
+        secret,
+        &[side.other().label(), &peer_id, &nonce, &peer_nonce],
+    )
+    .verify_slice(&peer_proof)
+    .map_err(|_| "the peer did not prove it knows the cluster secret".to_string())
+}
+
+/// Returns the current time, in seconds since the Unix epoch
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Returns the proof to send with a gRPC request carrying a payload, if a
+/// cluster secret is set
+///
+/// The proof is `<time>.<nonce>.<HMAC>`, in decimal and hexadecimal.
+pub fn request_proof(payload: &[u8]) -> Option<String> {
+    let secret = POLICY.get()?.secret.as_deref()?;
+    let nonce = match generate_nonce() {
+        Ok(nonce) => hex(&nonce),
+        Err(e) => {
+            log::error!("Cannot generate the nonce of a gRPC request: {}", e);
+            return None;
+        }
+    };
+    Some(request_proof_with(secret, now_secs(), &nonce, payload))
+}
+
+/// Computes the proof of a gRPC request sent at a time with a nonce
+fn request_proof_with(secret: &str, sent_at: u64, nonce: &str, payload: &[u8]) -> String {
+    use hmac::Mac;
+
+    let sent_at = sent_at.to_string();
+    let digest = mac(secret, &[sent_at.as_bytes(), nonce.as_bytes(), payload])
+        .finalize()
+        .into_bytes();
+    format!("{}.{}.{}", sent_at, nonce, hex(&digest))
+}
+
+/// Checks the proof of a gRPC request, if a cluster secret is set
+pub fn check_request(proof: Option<&str>, payload: &[u8]) -> Result<(), String> {
+    let Some(secret) = POLICY.get().and_then(|policy| policy.secret.as_deref()) else {
+        return Ok(());
+    };
+    check_request_with(secret, proof, payload, now_secs())
+}
+
+/// Checks the proof of a gRPC request received at a time
+fn check_request_with(
+    secret: &str,
+    proof: Option<&str>,
+    payload: &[u8],
+    now: u64,
+) -> Result<(), String> {
+    use hmac::Mac;
+
+    let refused = || "the request does not prove its sender knows the cluster secret".to_string();
+    let mut parts = proof.ok_or_else(refused)?.splitn(3, '.');
+    let (Some(sent_at), Some(nonce), Some(digest)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(refused());
+    };
+    let digest = from_hex(digest).ok_or_else(refused)?;
+    mac(secret, &[sent_at.as_bytes(), nonce.as_bytes(), payload])
+        .verify_slice(&digest)
+        .map_err(|_| refused())?;
+
+    let sent_at: u64 = sent_at.parse().map_err(|_| refused())?;
+    if now.abs_diff(sent_at) > PROOF_MAX_AGE.as_secs() {
+        return Err(format!(
+            "the proof of the request is more than {:?} old",
+            PROOF_MAX_AGE
+        ));
+    }
+    let mut seen = SEEN_NONCES.lock().unwrap();
+    seen.retain(|_, seen_at| now.abs_diff(*seen_at) <= PROOF_MAX_AGE.as_secs());
+    if seen.insert(nonce.to_string(), sent_at).is_some() {
+        return Err("the proof of the request was already used".to_string());
+    }
+    Ok(())
+}
+
+/// Returns the hexadecimal form of bytes
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses the hexadecimal form of bytes
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Checks that a message received from a peer may be processed
+///
+/// Both the address of the connection and the one claimed by the sender must
+/// be allowed. The cluster secret is checked on the connection, see
+/// [`handshake`], or on the gRPC request, see [`check_request`].
+pub fn admit(
+    message: &crate::message::Message,
+    socket_of_the_sender: std::net::SocketAddr,
+) -> Result<(), String> {
+    let Some(policy) = POLICY.get() else {
+        return Ok(());
+    };
+    for addr in [socket_of_the_sender, message.sender_addr] {
+        if !policy.is_allowed(addr.ip()) {
+            return Err(format!("address {} is not allowed", addr.ip()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addr_rules() {
+        let rule: AddrRule = "10.0.0.0/8".parse().unwrap();
+        assert!(rule.matches("10.42.0.7".parse().unwrap()));
+        assert!(rule.matches("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!rule.matches("192.168.1.1".parse().unwrap()));
+        assert!(!rule.matches("fd00::1".parse().unwrap()));
+        let any: AddrRule = "::/0".parse().unwrap();
+        assert!(any.matches("fd00::1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<AddrRule>().is_err());
+        assert!("localhost".parse::<AddrRule>().is_err());
+
+        let policy = PeerPolicy {
+            allowlist: vec!["10.0.0.0/8".parse().unwrap()],
+            denylist: vec!["10.0.0.66".parse().unwrap()],
+            secret: None,
+        };
+        assert!(policy.is_allowed("10.0.0.65".parse().unwrap()));
+        assert!(!policy.is_allowed("10.0.0.66".parse().unwrap()));
+        assert!(!policy.is_allowed("127.0.0.1".parse().unwrap()));
+        assert!(PeerPolicy::default().is_allowed("127.0.0.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        async fn run(dialer: &'static str, acceptor: &'static str) -> (bool, bool) {
+            let (mut a, mut b) = tokio::io::duplex(1024);
+            let (dialed, accepted) = tokio::join!(
+                handshake_with(dialer, "A", &mut a, Side::Dialer),
+                handshake_with(acceptor, "B", &mut b, Side::Acceptor)
+            );
+            (dialed.is_ok(), accepted.is_ok())
+        }
+        assert_eq!(run("s3cret", "s3cret").await, (true, true));
+        assert_eq!(run("s3cret", "other").await, (false, false));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_refuses_relayed_nonces() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // the attacker gets dialed by site A, and relays the nonces of both
+        // connections to forge the proof of the connection it opened to A
+        let (mut site_in, mut attacker_in) = tokio::io::duplex(1024);
+        let (mut site_out, mut attacker_out) = tokio::io::duplex(1024);
+        let hello_size = NONCE_SIZE + 2;
+        let attack = async move {
+            let mut acceptor_hello = vec![0u8; hello_size];
+            attacker_in.read_exact(&mut acceptor_hello).await.unwrap();
+            let mut dialer_hello = vec![0u8; hello_size];
+            attacker_out.read_exact(&mut dialer_hello).await.unwrap();
+            attacker_out.write_all(&acceptor_hello).await.unwrap();
+            let mut dialer_proof = [0u8; PROOF_SIZE];
+            if attacker_out.read_exact(&mut dialer_proof).await.is_ok() {
+                let _ = attacker_in.write_all(&dialer_hello).await;
+                let _ = attacker_in.write_all(&dialer_proof).await;
+            }
+        };
+        // the ends are closed once their challenge fails
+        let (accepted, dialed, ()) = tokio::join!(
+            async move { handshake_with("s3cret", "A", &mut site_in, Side::Acceptor).await },
+            async move { handshake_with("s3cret", "A", &mut site_out, Side::Dialer).await },
+            attack
+        );
+        assert!(accepted.is_err());
+        assert!(dialed.is_err());
+    }
+
+    #[test]
+    fn test_request_proof() {
+        let now = 1_700_000_000;
+        let proof = request_proof_with("s3cret", now, "0a1b", b"payload");
+        assert!(check_request_with("s3cret", Some(&proof), b"payload", now + 1).is_ok());
+        // replayed
+        assert!(check_request_with("s3cret", Some(&proof), b"payload", now + 2).is_err());
+
+        let proof = request_proof_with("s3cret", now, "2c3d", b"payload");
+        assert!(check_request_with("other", Some(&proof), b"payload", now).is_err());
+        assert!(check_request_with("s3cret", Some(&proof), b"altered", now).is_err());
+        assert!(check_request_with("s3cret", None, b"payload", now).is_err());
+        assert!(check_request_with("s3cret", Some("garbage"), b"payload", now).is_err());
+        // too old
+        let late = now + PROOF_MAX_AGE.as_secs() + 1;
+        assert!(check_request_with("s3cret", Some(&proof), b"payload", late).is_err());
+        assert!(check_request_with("s3cret", Some(&proof), b"payload", now).is_ok());
+    }
+}
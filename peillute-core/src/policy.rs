@@ -0,0 +1,394 @@
+//! Per-user transaction limits
+//!
+//! Administrators can set a policy on a user account: a maximum amount for a
+//! single withdrawal, a cap on the amount transferred to other users each day
+//! and a list of forbidden beneficiaries. The policies of a site apply to the
+//! operations submitted on this site, which are rejected when they break a
+//! limit. Operations diffused by the other sites are still applied, so that
+//! every site keeps the same transactions, but those breaking a local policy
+//! are flagged for review.
+//!
+//! Whatever the policy of the user, a transaction older than the refund
+//! window of the site (`--refund-window`, 48 hours by default) is immutable:
+//! its refund is rejected, or flagged when it comes from another site.
+
+/// Limits applied to the operations of a user
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Policy {
+    /// User the policy applies to
+    pub user: String,
+    /// Maximum amount of a single withdrawal
+    pub max_withdrawal: Option<f64>,
+    /// Maximum amount transferred to other users in a day
+    pub daily_transfer_cap: Option<f64>,
+    /// Users the user cannot transfer money to
+    pub forbidden_beneficiaries: Vec<String>,
+}
+
+/// Operation received from another site that breaks a local policy
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PolicyViolation {
+    /// Identifier of the violation
+    pub id: i64,
+    /// User whose policy was broken
+    pub user: String,
+    /// Limit that was broken
+    pub reason: String,
+    /// Lamport time of the operation
+    pub lamport_time: i64,
+    /// ID of the node that created the operation
+    pub source_node: String,
+    /// Local date at which the operation was flagged
+    pub flagged_at: String,
+}
+
+#[cfg(feature = "server")]
+/// Operation checked against the policies
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation<'a> {
+    /// Withdrawal of money from an account
+    Withdraw { user: &'a str, amount: f64 },
+    /// Transfer of money to one or several other users, `amount` being the total
+    Transfer {
+        from: &'a str,
+        to: Vec<&'a str>,
+        amount: f64,
+    },
+    /// Refund of a transaction, or request of its refund
+    Refund {
+        user: &'a str,
+        lamport_time: i64,
+        node: &'a str,
+    },
+}
+
+#[cfg(feature = "server")]
+impl Operation<'_> {
+    /// Returns the user whose policy applies to the operation
+    pub fn user(&self) -> &str {
+        match self {
+            Operation::Withdraw { user, .. } => user,
+            Operation::Transfer { from, .. } => from,
+            Operation::Refund { user, .. } => user,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the current day, as "YYYY-MM-DD"
+fn today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
+#[cfg(feature = "server")]
+/// Reads the policy of a user
+fn policy_in(conn: &rusqlite::Connection, user: &str) -> rusqlite::Result<Option<Policy>> {
+    use rusqlite::{OptionalExtension, params};
+
+    let limits: Option<(Option<f64>, Option<f64>)> = conn
+        .query_row(
+            "SELECT max_withdrawal, daily_transfer_cap FROM Policies WHERE unique_name = ?1",
+            params![user],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((max_withdrawal, daily_transfer_cap)) = limits else {
+        return Ok(None);
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT beneficiary FROM ForbiddenBeneficiary WHERE unique_name = ?1 ORDER BY beneficiary",
+    )?;
+    let forbidden_beneficiaries = stmt
+        .query_map(params![user], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    Ok(Some(Policy {
+        user: user.to_string(),
+        max_withdrawal,
+        daily_transfer_cap,
+        forbidden_beneficiaries,
+    }))
+}
+
+#[cfg(feature = "server")]
+/// Creates or replaces the policy of a user
+fn set_policy_in(conn: &rusqlite::Connection, policy: &Policy) -> rusqlite::Result<()> {
+    use rusqlite::params;
+
+    let sql_tx = conn.unchecked_transaction()?;
+    sql_tx.execute(
+        "INSERT INTO Policies (unique_name, max_withdrawal, daily_transfer_cap) VALUES (?1, ?2, ?3)
+        ON CONFLICT(unique_name) DO UPDATE SET max_withdrawal = excluded.max_withdrawal,
+            daily_transfer_cap = excluded.daily_transfer_cap",
+        params![
+            policy.user,
+            policy.max_withdrawal,
+            policy.daily_transfer_cap
+        ],
+    )?;
+    sql_tx.execute(
+        "DELETE FROM ForbiddenBeneficiary WHERE unique_name = ?1",
+        params![policy.user],
+    )?;
+    for beneficiary in &policy.forbidden_beneficiaries {
+        sql_tx.execute(
+            "INSERT OR IGNORE INTO ForbiddenBeneficiary (unique_name, beneficiary) VALUES (?1, ?2)",
+            params![policy.user, beneficiary],
+        )?;
+    }
+    sql_tx.commit()
+}
+
+#[cfg(feature = "server")]
+/// Returns the amount transferred by a user to other users on a day
+fn transferred_in(conn: &rusqlite::Connection, user: &str, day: &str) -> rusqlite::Result<f64> {
+    use rusqlite::{OptionalExtension, params};
+
+    Ok(conn
+        .query_row(
+            "SELECT amount FROM DailyTransfers WHERE unique_name = ?1 AND day = ?2",
+            params![user, day],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0.0))
+}
+
+#[cfg(feature = "server")]
+/// Returns the limit broken by an operation on a day, if any
+fn violation_in(
+    conn: &rusqlite::Connection,
+    operation: &Operation,
+    day: &str,
+) -> rusqlite::Result<Option<String>> {
+    // the refund window applies to every user
+    if let Operation::Refund {
+        lamport_time, node, ..
+    } = operation
+    {
+        return Ok(crate::db::is_immutable_in(conn, *lamport_time, node)?.then(|| {
+            format!(
+                "Transaction {}-{} is older than the refund window and can no longer be refunded",
+                node, lamport_time
+            )
+        }));
+    }
+
+    let Some(policy) = policy_in(conn, operation.user())? else {
+        return Ok(None);
+    };
+
+    match operation {
+        Operation::Withdraw { user, amount } => {
+            if let Some(max) = policy.max_withdrawal
+                && *amount > max
+            {
+                return Ok(Some(format!(
+                    "Withdrawal of {:.2} € by '{}' exceeds the limit of {:.2} €",
+                    amount, user, max
+                )));
+            }
+        }
+        Operation::Transfer { from, to, amount } => {
+            if let Some(beneficiary) = to.iter().find(|to| {
+                policy
+                    .forbidden_beneficiaries
+                    .iter()
+                    .any(|f| f.as_str() == **to)
+            }) {
+                return Ok(Some(format!(
+                    "'{}' is not allowed to transfer money to '{}'",
+                    from, beneficiary
+                )));
+            }
+            if let Some(cap) = policy.daily_transfer_cap {
+                let transferred = transferred_in(conn, from, day)?;
+                if transferred + amount > cap {
+                    return Ok(Some(format!(
+                        "Transfer of {:.2} € by '{}' exceeds the daily cap of {:.2} € ({:.2} € already transferred today)",
+                        amount, from, cap, transferred
+                    )));
+                }
+            }
+        }
+        Operation::Refund { .. } => {}
+    }
+    Ok(None)
+}
+
+#[cfg(feature = "server")]
+/// Adds an amount to the transfers of a user on a day
+fn record_transfer_in(
+    conn: &rusqlite::Connection,
+    user: &str,
+    amount: f64,
+    day: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO DailyTransfers (unique_name, day, amount) VALUES (?1, ?2, ?3)
+        ON CONFLICT(unique_name, day) DO UPDATE SET amount = amount + excluded.amount",
+        rusqlite::params![user, day, amount],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the policies of every user
+pub fn get_policies() -> rusqlite::Result<Vec<Policy>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    let mut stmt = conn.prepare("SELECT unique_name FROM Policies ORDER BY unique_name")?;
+    let users = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    let mut policies = Vec::new();
+    for user in users {
+        policies.extend(policy_in(&conn, &user)?);
+    }
+    Ok(policies)
+}
+
+#[cfg(feature = "server")]
+/// Creates or replaces the policy of a user
+pub fn set_policy(policy: &Policy) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    set_policy_in(&conn, policy)
+}
+
+#[cfg(feature = "server")]
+/// Removes the policy of a user
+pub fn delete_policy(user: &str) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    conn.execute(
+        "DELETE FROM ForbiddenBeneficiary WHERE unique_name = ?1",
+        rusqlite::params![user],
+    )?;
+    conn.execute(
+        "DELETE FROM Policies WHERE unique_name = ?1",
+        rusqlite::params![user],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the limit broken by an operation today, if any
+pub fn check(operation: &Operation) -> rusqlite::Result<Option<String>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    violation_in(&conn, operation, &today())
+}
+
+#[cfg(feature = "server")]
+/// Counts an applied transfer in the daily transfers of a user
+pub fn record_transfer(user: &str, amount: f64) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    record_transfer_in(&conn, user, amount, &today())
+}
+
+#[cfg(feature = "server")]
+/// Flags an operation received from another site if it breaks a local policy
+///
+/// The operation is applied anyway, the flag only records it for review.
+pub fn review(operation: &Operation, lamport_time: i64, source_node: &str) -> rusqlite::Result<()> {
+    let Some(reason) = check(operation)? else {
+        return Ok(());
+    };
+    log::warn!(
+        "Operation {}:{} breaks a local policy: {}",
+        source_node,
+        lamport_time,
+        reason
+    );
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    conn.execute(
+        "INSERT INTO PolicyViolations (unique_name, reason, lamport_time, source_node, flagged_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            operation.user(),
+            reason,
+            lamport_time,
+            source_node,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the flagged operations, most recent first
+pub fn get_violations() -> rusqlite::Result<Vec<PolicyViolation>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    let mut stmt = conn.prepare(
+        "SELECT id, unique_name, reason, lamport_time, source_node, flagged_at
+        FROM PolicyViolations ORDER BY id DESC",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(PolicyViolation {
+            id: row.get(0)?,
+            user: row.get(1)?,
+            reason: row.get(2)?,
+            lamport_time: row.get(3)?,
+            source_node: row.get(4)?,
+            flagged_at: row.get(5)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_limits() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        let day = "2026-10-16";
+
+        let withdraw = |amount| Operation::Withdraw {
+            user: "alice",
+            amount,
+        };
+        let transfer = |to, amount| Operation::Transfer {
+            from: "alice",
+            to: vec![to],
+            amount,
+        };
+        // without a policy, everything is allowed
+        assert_eq!(violation_in(&conn, &withdraw(1000.0), day).unwrap(), None);
+
+        let policy = Policy {
+            user: "alice".to_string(),
+            max_withdrawal: Some(50.0),
+            daily_transfer_cap: Some(100.0),
+            forbidden_beneficiaries: vec!["mallory".to_string()],
+        };
+        set_policy_in(&conn, &policy).unwrap();
+        assert_eq!(policy_in(&conn, "alice").unwrap(), Some(policy));
+
+        assert_eq!(violation_in(&conn, &withdraw(50.0), day).unwrap(), None);
+        assert!(violation_in(&conn, &withdraw(50.5), day).unwrap().is_some());
+        assert!(
+            violation_in(&conn, &transfer("mallory", 1.0), day)
+                .unwrap()
+                .is_some()
+        );
+
+        // the cap covers the transfers of the day only
+        assert_eq!(
+            violation_in(&conn, &transfer("bob", 80.0), day).unwrap(),
+            None
+        );
+        record_transfer_in(&conn, "alice", 80.0, day).unwrap();
+        assert!(
+            violation_in(&conn, &transfer("bob", 30.0), day)
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            violation_in(&conn, &transfer("bob", 30.0), "2026-10-17").unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Preview of the balances resulting from a transaction
+//!
+//! The withdrawal and transfer forms of the web interface show the balances
+//! the users would have once the operation is applied, while the amount is
+//! typed: the fee of a transfer is included, and the reason the operation
+//! would be rejected, insufficient funds or a limit of the policy of the
+//! payer, is given instead of waiting for the submission. Nothing is recorded.
+//!
+//! The preview reflects the balances known by the site when it is computed:
+//! the operations of the other sites applied before the submission still
+//! change the outcome.
+
+/// Balances of the users of a transaction once applied
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionPreview {
+    /// Balance of the payer after the transaction and its fee
+    pub payer_balance: f64,
+    /// Balance of the beneficiary after the transaction, None for a withdrawal
+    pub beneficiary_balance: Option<f64>,
+    /// Fee charged to the payer
+    pub fee: f64,
+    /// Reason the transaction would be rejected, if any
+    pub rejection: Option<String>,
+}
+
+#[cfg(feature = "server")]
+/// Computes the balances resulting from a transfer to a user, or from a
+/// withdrawal if there is no beneficiary
+pub fn preview(
+    payer: &str,
+    beneficiary: Option<&str>,
+    amount: f64,
+) -> Result<TransactionPreview, Box<dyn std::error::Error>> {
+    use crate::policy::Operation;
+
+    let amount = crate::amount::check(amount)?;
+    for user in std::iter::once(payer).chain(beneficiary) {
+        if !crate::db::user_exists(user)? {
+            return Err(format!("User '{}' does not exist.", user).into());
+        }
+    }
+    let (fee_account, fee) = match beneficiary {
+        Some(_) => crate::fees::fee_rule(payer, crate::fees::FeeOperation::Transfer, amount)?
+            .map_or((None, 0.0), |(account, fee)| (Some(account), fee)),
+        None => (None, 0.0),
+    };
+    let payer_balance = crate::db::calculate_solde(payer)? - amount - fee;
+    let beneficiary_balance = match beneficiary {
+        Some(beneficiary) => {
+            let mut balance = crate::db::calculate_solde(beneficiary)? + amount;
+            if fee_account.as_deref() == Some(beneficiary) {
+                balance += fee;
+            }
+            Some(balance)
+        }
+        None => None,
+    };
+
+    let operation = match beneficiary {
+        Some(beneficiary) => Operation::Transfer {
+            from: payer,
+            to: vec![beneficiary],
+            amount,
+        },
+        None => Operation::Withdraw {
+            user: payer,
+            amount,
+        },
+    };
+    let rejection = if payer_balance < -0.005 {
+        Some(format!(
+            "Insufficient funds: '{}' has less than {:.2}.",
+            payer,
+            amount + fee
+        ))
+    } else {
+        crate::policy::check(&operation)?
+    };
+
+    Ok(TransactionPreview {
+        payer_balance: round_cents(payer_balance),
+        beneficiary_balance: beneficiary_balance.map(round_cents),
+        fee,
+        rejection,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Rounds a balance to the cent, so that no `-0.00` is shown
+fn round_cents(balance: f64) -> f64 {
+    (balance * 100.0).round() / 100.0 + 0.0
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_cents() {
+        assert_eq!(round_cents(12.299999), 12.3);
+        assert_eq!(round_cents(-0.001).to_string(), "0");
+        assert_eq!(round_cents(-4.005000001), -4.01);
+    }
+}
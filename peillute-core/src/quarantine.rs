@@ -0,0 +1,302 @@
+//! Quarantine of the operations received from the network
+//!
+//! An operation diffused by another site can fail to apply on this site, for
+//! instance a transfer to a user whose creation has not been received yet, or
+//! a withdrawal exceeding the balance known locally. Instead of being dropped,
+//! which would make the site diverge from the network, the operation is parked
+//! in the Quarantine table with its clock and the reason of the failure. The
+//! parked operations are applied again, in their Lamport order, every time an
+//! operation received from the network is applied, and those still unresolved
+//! are listed on the Info page, where they can be retried or discarded.
+
+/// Operation received from the network that could not be applied
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct QuarantinedOperation {
+    /// Lamport time of the operation
+    pub lamport_time: i64,
+    /// Site that created the operation
+    pub source_node: String,
+    /// Summary of the operation
+    pub description: String,
+    /// Error of the last attempt to apply the operation
+    pub reason: String,
+    /// Number of attempts to apply the operation
+    pub attempts: i64,
+    /// Local date at which the operation was first parked
+    pub quarantined_at: String,
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    /// Serializes the retries, so that an operation is not applied twice
+    static ref RETRYING: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+#[cfg(feature = "server")]
+/// Returns a summary of an operation shown to the administrator
+fn describe(info: &crate::message::MessageInfo) -> String {
+    use crate::message::MessageInfo;
+
+    match info {
+        MessageInfo::CreateUser(user) => format!("Creation of {}", user.name),
+        MessageInfo::Deposit(deposit) => {
+            format!("Deposit of {:.2} € for {}", deposit.amount, deposit.name)
+        }
+        MessageInfo::Withdraw(withdraw) => {
+            format!(
+                "Withdrawal of {:.2} € by {}",
+                withdraw.amount, withdraw.name
+            )
+        }
+        MessageInfo::Transfer(transfer) => format!(
+            "Transfer of {:.2} € from {} to {}",
+            transfer.amount, transfer.name, transfer.beneficiary
+        ),
+        MessageInfo::Pay(pay) if !pay.shares.is_empty() => format!(
+            "Payment of {:.2} € split between {}",
+            pay.amount,
+            pay.shares
+                .iter()
+                .map(|(user, _)| user.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        MessageInfo::Pay(pay) => format!("Payment of {:.2} € by {}", pay.amount, pay.name),
+        MessageInfo::Refund(refund) => format!(
+            "Refund of transaction {}-{}",
+            refund.transac_node, refund.transac_time
+        ),
+        MessageInfo::BatchTransfer(batch) => format!(
+            "Batch of {} transfers from {}",
+            batch.transfers.len(),
+            batch.name
+        ),
+        MessageInfo::Saga(saga) => format!("Saga of {} steps", saga.steps.len()),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Parks an operation, or records a new failure of an operation already parked
+fn park_in(
+    conn: &rusqlite::Connection,
+    lamport_time: i64,
+    source_node: &str,
+    message: &str,
+    clock: &str,
+    description: &str,
+    reason: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO Quarantine
+            (lamport_time, source_node, message, clock, description, reason, attempts, quarantined_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1, ?7)
+        ON CONFLICT(lamport_time, source_node)
+        DO UPDATE SET reason = excluded.reason, attempts = attempts + 1",
+        rusqlite::params![
+            lamport_time,
+            source_node,
+            message,
+            clock,
+            description,
+            reason,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the parked operations, in their Lamport order
+fn quarantined_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<QuarantinedOperation>> {
+    let mut stmt = conn.prepare(
+        "SELECT lamport_time, source_node, description, reason, attempts, quarantined_at
+        FROM Quarantine ORDER BY lamport_time, source_node",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(QuarantinedOperation {
+            lamport_time: row.get(0)?,
+            source_node: row.get(1)?,
+            description: row.get(2)?,
+            reason: row.get(3)?,
+            attempts: row.get(4)?,
+            quarantined_at: row.get(5)?,
+        })
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the parked operations to apply again, as (lamport time, node, message, clock)
+fn pending_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<(i64, String, String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT lamport_time, source_node, message, clock
+        FROM Quarantine ORDER BY lamport_time, source_node",
+    )?;
+    stmt.query_map([], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+    })?
+    .collect()
+}
+
+#[cfg(feature = "server")]
+/// Removes a parked operation, returns false if there is no such operation
+fn release_in(
+    conn: &rusqlite::Connection,
+    lamport_time: i64,
+    source_node: &str,
+) -> rusqlite::Result<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM Quarantine WHERE lamport_time = ?1 AND source_node = ?2",
+        rusqlite::params![lamport_time, source_node],
+    )?;
+    Ok(deleted > 0)
+}
+
+#[cfg(feature = "server")]
+/// Parks an operation received from the network that could not be applied
+///
+/// An operation that cannot be stored is only logged.
+pub fn park(
+    info: &crate::message::MessageInfo,
+    clock: &crate::clock::Clock,
+    source_node: &str,
+    reason: &str,
+) {
+    let encoded = serde_json::to_string(info)
+        .and_then(|message| serde_json::to_string(clock).map(|clock| (message, clock)));
+    let (message, encoded_clock) = match encoded {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            log::error!("Cannot quarantine an operation of {}: {}", source_node, e);
+            return;
+        }
+    };
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    if let Err(e) = park_in(
+        &conn,
+        *clock.get_lamport(),
+        source_node,
+        &message,
+        &encoded_clock,
+        &describe(info),
+        reason,
+    ) {
+        log::error!("Cannot quarantine an operation of {}: {}", source_node, e);
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the parked operations, in their Lamport order
+pub fn get_quarantined() -> rusqlite::Result<Vec<QuarantinedOperation>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    quarantined_in(&conn)
+}
+
+#[cfg(feature = "server")]
+/// Drops a parked operation, returns false if there is no such operation
+pub fn discard(lamport_time: i64, source_node: &str) -> rusqlite::Result<bool> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    release_in(&conn, lamport_time, source_node)
+}
+
+#[cfg(feature = "server")]
+/// Applies the parked operations again, returns the number of them applied
+///
+/// The operations are retried until none of them succeeds anymore, an
+/// operation applied, such as a user creation, possibly unlocking the next
+/// ones.
+pub async fn retry() -> rusqlite::Result<usize> {
+    let _retrying = RETRYING.lock().await;
+    let mut applied = 0;
+    loop {
+        let pending = {
+            let conn = crate::db::DB_CONN.lock().unwrap();
+            pending_in(&conn)?
+        };
+        let mut progress = false;
+        for (lamport_time, source_node, message, encoded_clock) in pending {
+            let decoded =
+                serde_json::from_str::<crate::message::MessageInfo>(&message).and_then(|info| {
+                    serde_json::from_str::<crate::clock::Clock>(&encoded_clock)
+                        .map(|clock| (info, clock))
+                });
+            let Ok((info, clock)) = decoded else {
+                log::error!(
+                    "Cannot decode the quarantined operation {}-{}",
+                    source_node,
+                    lamport_time
+                );
+                continue;
+            };
+            let result =
+                crate::control::process_network_command(info.clone(), clock.clone(), &source_node)
+                    .await
+                    .map_err(|e| e.to_string());
+            let conn = crate::db::DB_CONN.lock().unwrap();
+            match result {
+                Ok(()) => {
+                    log::info!(
+                        "Applied the quarantined operation {}-{}",
+                        source_node,
+                        lamport_time
+                    );
+                    release_in(&conn, lamport_time, &source_node)?;
+                    applied += 1;
+                    progress = true;
+                }
+                Err(reason) => park_in(
+                    &conn,
+                    lamport_time,
+                    &source_node,
+                    &message,
+                    &encoded_clock,
+                    &describe(&info),
+                    &reason,
+                )?,
+            }
+        }
+        if !progress {
+            return Ok(applied);
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns true if operations are waiting in quarantine
+pub fn has_quarantined() -> bool {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    conn.query_row("SELECT EXISTS(SELECT 1 FROM Quarantine)", [], |row| {
+        row.get(0)
+    })
+    .unwrap_or(false)
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+
+        park_in(&conn, 7, "B", "{}", "{}", "Transfer", "Unknown user bob").unwrap();
+        park_in(&conn, 3, "A", "{}", "{}", "Deposit", "Unknown user alice").unwrap();
+        // a new failure of a parked operation updates it
+        park_in(&conn, 7, "B", "{}", "{}", "Transfer", "Insufficient funds").unwrap();
+
+        let quarantined = quarantined_in(&conn).unwrap();
+        assert_eq!(quarantined.len(), 2);
+        assert_eq!(quarantined[0].source_node, "A");
+        assert_eq!(quarantined[1].reason, "Insufficient funds");
+        assert_eq!(quarantined[1].attempts, 2);
+        assert_eq!(pending_in(&conn).unwrap()[1].0, 7);
+
+        assert!(release_in(&conn, 3, "A").unwrap());
+        assert!(!release_in(&conn, 3, "A").unwrap());
+        assert_eq!(quarantined_in(&conn).unwrap().len(), 1);
+    }
+}
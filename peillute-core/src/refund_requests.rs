@@ -0,0 +1,444 @@
+//! Refund requests
+//!
+//! A refund gives the money of a transaction back to its sender, taking it
+//! from the recipient. The recipient of a transfer can refund it directly,
+//! but its sender has to ask for it: the refund request is diffused to every
+//! site and shown on the user page of the recipient, who approves or rejects
+//! it from any site. Only once approved is the refund executed and diffused
+//! like the other transactions. Deposits, withdrawals and payments, whose
+//! money comes from or goes out of Peillute, are still refunded directly.
+
+/// State of a refund request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum RefundRequestStatus {
+    /// Waiting for the answer of the recipient of the transaction
+    Pending,
+    /// Approved, the transaction was refunded
+    Approved,
+    /// Rejected by the recipient of the transaction
+    Rejected,
+}
+
+impl RefundRequestStatus {
+    /// Returns the value stored in the status column
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RefundRequestStatus::Pending => "Pending",
+            RefundRequestStatus::Approved => "Approved",
+            RefundRequestStatus::Rejected => "Rejected",
+        }
+    }
+
+    /// Parses a value of the status column
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Pending" => Some(RefundRequestStatus::Pending),
+            "Approved" => Some(RefundRequestStatus::Approved),
+            "Rejected" => Some(RefundRequestStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+/// Request of the sender of a transaction to be refunded
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RefundRequest {
+    /// Lamport time of the request
+    pub lamport_time: i64,
+    /// ID of the node where the request was made
+    pub source_node: String,
+    /// Lamport time of the transaction to refund
+    pub transac_time: i64,
+    /// ID of the node that created the transaction to refund
+    pub transac_node: String,
+    /// Sender of the transaction, asking for the refund
+    pub requester: String,
+    /// Recipient of the transaction, who approves the refund
+    pub approver: String,
+    /// Amount of the transaction
+    pub amount: f64,
+    /// State of the request
+    pub status: RefundRequestStatus,
+    /// Local date at which the request was received
+    pub requested_at: String,
+}
+
+/// Returns true if the refund of a transaction asked by a user must be
+/// approved by the recipient of the transaction
+///
+/// The recipient can refund the transaction directly, and so can anyone when
+/// the money left Peillute, the null user receiving it.
+pub fn needs_approval(requester: &str, to_user: &str) -> bool {
+    requester != to_user && to_user != "NULL"
+}
+
+#[cfg(feature = "server")]
+/// Saves a refund request, a request received twice being saved once
+fn insert_in(conn: &rusqlite::Connection, request: &RefundRequest) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO RefundRequests
+            (lamport_time, source_node, transac_lamport, transac_node, requester, approver,
+            amount, status, requested_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![
+            request.lamport_time,
+            request.source_node,
+            request.transac_time,
+            request.transac_node,
+            request.requester,
+            request.approver,
+            request.amount,
+            request.status.as_str(),
+            request.requested_at
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Columns of a refund request, in the order read by [`request_from_row`]
+const REQUEST_COLUMNS: &str = "lamport_time, source_node, transac_lamport, transac_node,
+    requester, approver, amount, status, requested_at";
+
+#[cfg(feature = "server")]
+/// Reads a refund request selected with [`REQUEST_COLUMNS`]
+fn request_from_row(row: &rusqlite::Row) -> rusqlite::Result<RefundRequest> {
+    let status: String = row.get(7)?;
+    Ok(RefundRequest {
+        lamport_time: row.get(0)?,
+        source_node: row.get(1)?,
+        transac_time: row.get(2)?,
+        transac_node: row.get(3)?,
+        requester: row.get(4)?,
+        approver: row.get(5)?,
+        amount: row.get(6)?,
+        status: RefundRequestStatus::parse(&status).unwrap_or(RefundRequestStatus::Pending),
+        requested_at: row.get(8)?,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Returns the refund requests made by a user or addressed to them, most
+/// recent first
+fn requests_in(conn: &rusqlite::Connection, user: &str) -> rusqlite::Result<Vec<RefundRequest>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM RefundRequests WHERE requester = ?1 OR approver = ?1
+        ORDER BY lamport_time DESC, source_node",
+        REQUEST_COLUMNS
+    ))?;
+    stmt.query_map([user], request_from_row)?.collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns a refund request
+fn request_in(
+    conn: &rusqlite::Connection,
+    lamport_time: i64,
+    source_node: &str,
+) -> rusqlite::Result<Option<RefundRequest>> {
+    use rusqlite::OptionalExtension;
+
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM RefundRequests WHERE lamport_time = ?1 AND source_node = ?2",
+            REQUEST_COLUMNS
+        ),
+        rusqlite::params![lamport_time, source_node],
+        request_from_row,
+    )
+    .optional()
+}
+
+#[cfg(feature = "server")]
+/// Returns true if the refund of a transaction is waiting for approval
+fn is_pending_in(
+    conn: &rusqlite::Connection,
+    transac_time: i64,
+    transac_node: &str,
+) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM RefundRequests
+        WHERE transac_lamport = ?1 AND transac_node = ?2 AND status = 'Pending')",
+        rusqlite::params![transac_time, transac_node],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(feature = "server")]
+/// Rejects a pending refund request, returns false if it is not pending
+fn reject_in(
+    conn: &rusqlite::Connection,
+    lamport_time: i64,
+    source_node: &str,
+) -> rusqlite::Result<bool> {
+    let updated = conn.execute(
+        "UPDATE RefundRequests SET status = 'Rejected'
+        WHERE lamport_time = ?1 AND source_node = ?2 AND status = 'Pending'",
+        rusqlite::params![lamport_time, source_node],
+    )?;
+    Ok(updated > 0)
+}
+
+#[cfg(feature = "server")]
+/// Approves the pending refund requests of a transaction that was refunded
+fn settle_in(
+    conn: &rusqlite::Connection,
+    transac_time: i64,
+    transac_node: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE RefundRequests SET status = 'Approved'
+        WHERE transac_lamport = ?1 AND transac_node = ?2 AND status = 'Pending'",
+        rusqlite::params![transac_time, transac_node],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Builds the refund request of a transaction, returns it with the transaction
+fn build(
+    requester: &str,
+    transac_time: i64,
+    transac_node: &str,
+    lamport_time: i64,
+    source_node: &str,
+) -> Result<(RefundRequest, crate::db::Transaction), Box<dyn std::error::Error>> {
+    let Some(tx) = crate::db::get_transaction(transac_time, transac_node)? else {
+        return Err(format!(
+            "No transaction found at time {} from node {}",
+            transac_time, transac_node
+        )
+        .into());
+    };
+    let request = RefundRequest {
+        lamport_time,
+        source_node: source_node.to_string(),
+        transac_time,
+        transac_node: transac_node.to_string(),
+        requester: requester.to_string(),
+        approver: tx.to_user.clone(),
+        amount: tx.amount,
+        status: RefundRequestStatus::Pending,
+        requested_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    Ok((request, tx))
+}
+
+#[cfg(feature = "server")]
+/// Records a refund request made on this site
+///
+/// The requester must be the sender of a transaction that needs the approval
+/// of its recipient, and that was not refunded nor is already waiting for an
+/// approval.
+pub fn request(
+    requester: &str,
+    transac_time: i64,
+    transac_node: &str,
+    lamport_time: i64,
+    source_node: &str,
+) -> Result<RefundRequest, Box<dyn std::error::Error>> {
+    let (request, tx) = build(
+        requester,
+        transac_time,
+        transac_node,
+        lamport_time,
+        source_node,
+    )?;
+    if requester != tx.from_user {
+        return Err(format!("'{}' did not send this transaction.", requester).into());
+    }
+    if !needs_approval(requester, &tx.to_user) {
+        return Err("This transaction can be refunded directly.".into());
+    }
+    if tx
+        .optional_msg
+        .as_deref()
+        .and_then(crate::db::refunded_transaction)
+        .is_some()
+    {
+        return Err("A refund cannot be refunded.".into());
+    }
+    if crate::db::has_been_refunded(transac_time, transac_node)? {
+        return Err(format!(
+            "Transaction {}-{} already refunded",
+            transac_node, transac_time
+        )
+        .into());
+    }
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    if is_pending_in(&conn, transac_time, transac_node)? {
+        return Err("A refund of this transaction is already waiting for approval.".into());
+    }
+    insert_in(&conn, &request)?;
+    Ok(request)
+}
+
+#[cfg(feature = "server")]
+/// Records a refund request received from another site
+pub fn record(
+    requester: &str,
+    transac_time: i64,
+    transac_node: &str,
+    lamport_time: i64,
+    source_node: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (request, _) = build(
+        requester,
+        transac_time,
+        transac_node,
+        lamport_time,
+        source_node,
+    )?;
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    insert_in(&conn, &request)?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Checks that a user can refund a transaction without asking for approval
+///
+/// A missing transaction is reported by the refund itself.
+pub fn ensure_direct_refund(
+    name: &str,
+    transac_time: i64,
+    transac_node: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(tx) = crate::db::get_transaction(transac_time, transac_node)?
+        && needs_approval(name, &tx.to_user)
+    {
+        return Err(format!(
+            "The refund must be approved by {}, request it instead.",
+            tx.to_user
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns a pending refund request addressed to a user
+fn pending_request_for(
+    approver: &str,
+    lamport_time: i64,
+    source_node: &str,
+) -> Result<RefundRequest, Box<dyn std::error::Error>> {
+    let request = {
+        let conn = crate::db::DB_CONN.lock().unwrap();
+        request_in(&conn, lamport_time, source_node)?
+    };
+    match request {
+        None => Err("No such refund request.".into()),
+        Some(request) if request.approver != approver => {
+            Err(format!("This refund request is not addressed to {}.", approver).into())
+        }
+        Some(request) if request.status != RefundRequestStatus::Pending => {
+            Err("This refund request was already answered.".into())
+        }
+        Some(request) => Ok(request),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Checks that a user can approve a refund request, returns the request
+///
+/// The refund itself is then executed by its approver, which settles the
+/// request on every site.
+pub fn approve(
+    approver: &str,
+    lamport_time: i64,
+    source_node: &str,
+) -> Result<RefundRequest, Box<dyn std::error::Error>> {
+    pending_request_for(approver, lamport_time, source_node)
+}
+
+#[cfg(feature = "server")]
+/// Rejects a refund request addressed to a user
+pub fn reject(
+    approver: &str,
+    lamport_time: i64,
+    source_node: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    pending_request_for(approver, lamport_time, source_node)?;
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    reject_in(&conn, lamport_time, source_node)?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Records the rejection of a refund request received from another site
+pub fn record_rejection(lamport_time: i64, source_node: &str) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    reject_in(&conn, lamport_time, source_node)?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Approves the pending refund requests of a transaction that was refunded
+pub fn settle(transac_time: i64, transac_node: &str) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    settle_in(&conn, transac_time, transac_node)
+}
+
+#[cfg(feature = "server")]
+/// Returns the refund requests made by a user or addressed to them, most
+/// recent first
+pub fn get_requests(user: &str) -> rusqlite::Result<Vec<RefundRequest>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    requests_in(&conn, user)
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    fn sample(lamport_time: i64, source_node: &str, transac_time: i64) -> RefundRequest {
+        RefundRequest {
+            lamport_time,
+            source_node: source_node.to_string(),
+            transac_time,
+            transac_node: "A".to_string(),
+            requester: "alice".to_string(),
+            approver: "bob".to_string(),
+            amount: 4.0,
+            status: RefundRequestStatus::Pending,
+            requested_at: "2025-06-01 12:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_needs_approval() {
+        assert!(needs_approval("alice", "bob"));
+        assert!(!needs_approval("bob", "bob"));
+        assert!(!needs_approval("alice", "NULL"));
+    }
+
+    #[test]
+    fn test_refund_requests() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+
+        insert_in(&conn, &sample(5, "A", 1)).unwrap();
+        insert_in(&conn, &sample(6, "B", 2)).unwrap();
+        // a request diffused twice is recorded once
+        insert_in(&conn, &sample(5, "A", 1)).unwrap();
+        assert_eq!(requests_in(&conn, "bob").unwrap().len(), 2);
+        assert!(requests_in(&conn, "carol").unwrap().is_empty());
+        assert!(is_pending_in(&conn, 1, "A").unwrap());
+
+        // the refund of the transaction approves its request
+        settle_in(&conn, 1, "A").unwrap();
+        assert!(!is_pending_in(&conn, 1, "A").unwrap());
+        assert!(!reject_in(&conn, 5, "A").unwrap());
+        assert!(reject_in(&conn, 6, "B").unwrap());
+
+        let requests = requests_in(&conn, "alice").unwrap();
+        assert_eq!(requests[0].status, RefundRequestStatus::Rejected);
+        assert_eq!(requests[1].status, RefundRequestStatus::Approved);
+        assert_eq!(
+            request_in(&conn, 5, "A").unwrap().map(|r| r.transac_time),
+            Some(1)
+        );
+    }
+}
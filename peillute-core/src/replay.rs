@@ -0,0 +1,427 @@
+//! Deterministic replay of the transaction log of a database
+//!
+//! `/replay <db-file>` rebuilds the state of a site from the transactions
+//! stored in its database. They are re-applied in causal order, given by
+//! their vector clocks, into a fresh database next to the original one. The
+//! balances are checked after every transaction, and compared at the end with
+//! the balances stored by the site, to find where two sites diverged.
+
+#![cfg(feature = "server")]
+
+use crate::db::Transaction;
+
+/// Special value representing a null user
+const NULL: &str = "NULL";
+
+/// Tolerance on the comparison of balances
+const EPSILON: f64 = 1e-6;
+
+/// Balance that went negative during a replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct NegativeBalance {
+    /// Position of the transaction in the replay, starting at 1
+    pub step: usize,
+    /// Lamport timestamp of the transaction
+    pub lamport_time: i64,
+    /// ID of the node that created the transaction
+    pub source_node: String,
+    /// User whose balance went negative
+    pub user: String,
+    /// Balance of the user after the transaction
+    pub balance: f64,
+}
+
+/// Balance stored by the site that differs from the replayed one
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceMismatch {
+    /// Name of the user
+    pub user: String,
+    /// Balance stored in the original database
+    pub stored: f64,
+    /// Balance after the replay
+    pub replayed: f64,
+}
+
+/// Result of a replay
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport {
+    /// Path of the rebuilt database
+    pub output: String,
+    /// Number of transactions replayed
+    pub transactions: usize,
+    /// Number of users of the original database
+    pub users: usize,
+    /// Transactions whose clocks contradict each other, ordered by Lamport time instead
+    pub unordered: usize,
+    /// Balances that went negative, in replay order
+    pub negative_balances: Vec<NegativeBalance>,
+    /// Users whose stored balance differs from the replayed one
+    pub mismatches: Vec<BalanceMismatch>,
+}
+
+impl ReplayReport {
+    /// Returns true if no balance went negative and every balance matches
+    pub fn is_consistent(&self) -> bool {
+        self.negative_balances.is_empty() && self.mismatches.is_empty()
+    }
+}
+
+/// Returns true if transaction `a` causally precedes transaction `b`
+///
+/// Vector clocks are compared when both transactions have one, Lamport
+/// timestamps otherwise.
+pub fn happens_before(a: &Transaction, b: &Transaction) -> bool {
+    if a.vector_clock.is_empty() || b.vector_clock.is_empty() {
+        return a.lamport_time < b.lamport_time;
+    }
+    a.vector_clock != b.vector_clock
+        && a.vector_clock
+            .iter()
+            .all(|(site, value)| b.vector_clock.get(site).copied().unwrap_or(0) >= *value)
+}
+
+/// Orders transactions so that each one comes after all its causes
+///
+/// Concurrent transactions are ordered by Lamport timestamp, then by source
+/// node, so that the order is deterministic. Returns the indices of the
+/// transactions in order, and the number of transactions that had to be
+/// ordered by Lamport timestamp because their clocks form a cycle.
+pub fn causal_order(transactions: &[Transaction]) -> (Vec<usize>, usize) {
+    let n = transactions.len();
+    let mut predecessors = vec![0usize; n];
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for (a, tx_a) in transactions.iter().enumerate() {
+        for (b, tx_b) in transactions.iter().enumerate() {
+            if a != b && happens_before(tx_a, tx_b) {
+                successors[a].push(b);
+                predecessors[b] += 1;
+            }
+        }
+    }
+
+    let key = |i: usize| {
+        (
+            transactions[i].lamport_time,
+            transactions[i].source_node.clone(),
+            i,
+        )
+    };
+    let mut ready: std::collections::BTreeSet<_> =
+        (0..n).filter(|&i| predecessors[i] == 0).map(key).collect();
+    let mut done = vec![false; n];
+    let mut order = Vec::with_capacity(n);
+    let mut unordered = 0;
+
+    while order.len() < n {
+        let next = match ready.pop_first() {
+            Some((_, _, i)) => i,
+            None => {
+                // Inconsistent clocks, fall back to the Lamport order
+                unordered += 1;
+                (0..n)
+                    .filter(|&i| !done[i])
+                    .min_by_key(|&i| key(i))
+                    .expect("some transactions are left")
+            }
+        };
+        if done[next] {
+            continue;
+        }
+        done[next] = true;
+        order.push(next);
+        for &succ in &successors[next] {
+            predecessors[succ] -= 1;
+            if predecessors[succ] == 0 && !done[succ] {
+                ready.insert(key(succ));
+            }
+        }
+    }
+    (order, unordered)
+}
+
+/// Loads the transactions of a database, with their vector clocks
+pub(crate) fn load_transactions(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<Transaction>> {
+    let mut stmt = conn.prepare(
+        "SELECT from_user, to_user, amount, lamport_time, source_node, optional_msg, vector_clock_id
+        FROM Transactions ORDER BY lamport_time, source_node",
+    )?;
+    let mut entries =
+        conn.prepare("SELECT site_id, value FROM VectorClockEntry WHERE vector_clock_id = ?1")?;
+
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            Transaction {
+                from_user: row
+                    .get::<_, Option<String>>(0)?
+                    .unwrap_or_else(|| NULL.to_string()),
+                to_user: row.get(1)?,
+                amount: row.get(2)?,
+                lamport_time: row.get(3)?,
+                source_node: row.get(4)?,
+                optional_msg: row.get(5)?,
+                vector_clock: std::collections::HashMap::new(),
+            },
+            row.get::<_, i64>(6)?,
+        ))
+    })?;
+
+    let mut transactions = Vec::new();
+    for row in rows {
+        let (mut tx, vector_clock_id) = row?;
+        tx.vector_clock = entries
+            .query_map([vector_clock_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<std::collections::HashMap<String, i64>>>()?;
+        transactions.push(tx);
+    }
+    Ok(transactions)
+}
+
+/// Loads the users of a database, with their stored balance and status
+pub(crate) fn load_users(
+    conn: &rusqlite::Connection,
+) -> rusqlite::Result<Vec<(String, f64, bool)>> {
+    let has_is_active: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('User') WHERE name = 'is_active')",
+        [],
+        |row| row.get(0),
+    )?;
+    let query = if has_is_active {
+        "SELECT unique_name, solde, is_active FROM User WHERE unique_name != 'NULL' ORDER BY unique_name"
+    } else {
+        "SELECT unique_name, solde, 1 FROM User WHERE unique_name != 'NULL' ORDER BY unique_name"
+    };
+    let mut stmt = conn.prepare(query)?;
+    let users = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(users)
+}
+
+/// Replays the transactions of `source` into the empty database `target`
+pub fn replay_into(
+    source: &rusqlite::Connection,
+    target: &rusqlite::Connection,
+) -> rusqlite::Result<ReplayReport> {
+    use rusqlite::params;
+
+    crate::db::create_schema(target)?;
+    crate::db::upgrade_schema(target)?;
+
+    let users = load_users(source)?;
+    let transactions = load_transactions(source)?;
+    let (order, unordered) = causal_order(&transactions);
+
+    let mut report = ReplayReport {
+        transactions: transactions.len(),
+        users: users.len(),
+        unordered,
+        ..Default::default()
+    };
+    let mut balances: std::collections::BTreeMap<String, f64> = std::collections::BTreeMap::new();
+
+    let db_tx = target.unchecked_transaction()?;
+    for (name, _, active) in &users {
+        db_tx.execute(
+            "INSERT INTO User (unique_name, solde, is_active) VALUES (?1, 0, ?2)",
+            params![name, active],
+        )?;
+        balances.insert(name.clone(), 0.0);
+    }
+
+    for (step, &i) in order.iter().enumerate() {
+        let tx = &transactions[i];
+        for name in [&tx.from_user, &tx.to_user] {
+            if name != NULL {
+                db_tx.execute(
+                    "INSERT OR IGNORE INTO User (unique_name, solde) VALUES (?1, 0)",
+                    [name],
+                )?;
+            }
+        }
+
+        db_tx.execute("INSERT INTO VectorClock DEFAULT VALUES", [])?;
+        let vector_clock_id = db_tx.last_insert_rowid();
+        for (site_id, value) in &tx.vector_clock {
+            db_tx.execute(
+                "INSERT INTO VectorClockEntry (vector_clock_id, site_id, value) VALUES (?1, ?2, ?3)",
+                params![vector_clock_id, site_id, value],
+            )?;
+        }
+        let refund_of = tx
+            .optional_msg
+            .as_deref()
+            .and_then(crate::db::refunded_transaction);
+        db_tx.execute(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg, refund_of_node, refund_of_lamport)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                tx.from_user,
+                tx.to_user,
+                tx.amount,
+                tx.lamport_time,
+                vector_clock_id,
+                tx.source_node,
+                tx.optional_msg,
+                refund_of.map(|(node, _)| node),
+                refund_of.map(|(_, time)| time)
+            ],
+        )?;
+        crate::db::insert_postings(
+            &db_tx,
+            &tx.from_user,
+            &tx.to_user,
+            tx.amount,
+            tx.lamport_time,
+            &tx.source_node,
+            tx.optional_msg.as_deref().unwrap_or_default(),
+        )?;
+
+        if tx.to_user != NULL {
+            *balances.entry(tx.to_user.clone()).or_insert(0.0) += tx.amount;
+        }
+        if tx.from_user != NULL {
+            let balance = balances.entry(tx.from_user.clone()).or_insert(0.0);
+            *balance -= tx.amount;
+            if *balance < -EPSILON {
+                report.negative_balances.push(NegativeBalance {
+                    step: step + 1,
+                    lamport_time: tx.lamport_time,
+                    source_node: tx.source_node.clone(),
+                    user: tx.from_user.clone(),
+                    balance: *balance,
+                });
+            }
+        }
+    }
+
+    for (name, balance) in &balances {
+        db_tx.execute(
+            "UPDATE User SET solde = ?1 WHERE unique_name = ?2",
+            params![balance, name],
+        )?;
+    }
+    db_tx.commit()?;
+
+    for (name, stored, _) in &users {
+        let replayed = balances.get(name).copied().unwrap_or(0.0);
+        if (stored - replayed).abs() > EPSILON {
+            report.mismatches.push(BalanceMismatch {
+                user: name.clone(),
+                stored: *stored,
+                replayed,
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Replays the transactions of a database file into a fresh database
+///
+/// The original database is only read. The rebuilt one is written next to it,
+/// with the `.replay.db` extension, replacing a previous replay.
+pub fn replay_file(path: &str) -> Result<ReplayReport, Box<dyn std::error::Error>> {
+    let source_path = std::path::Path::new(path);
+    if !source_path.is_file() {
+        return Err(format!("database {} not found", path).into());
+    }
+    let output = source_path.with_extension("replay.db");
+    if output.exists() {
+        std::fs::remove_file(&output)?;
+    }
+
+    let source =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let target = rusqlite::Connection::open(&output)?;
+    let mut report = replay_into(&source, &target)?;
+    report.output = output.display().to_string();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(
+        from: &str,
+        to: &str,
+        amount: f64,
+        lamport: i64,
+        node: &str,
+        vc: &[(&str, i64)],
+    ) -> Transaction {
+        Transaction {
+            from_user: from.to_string(),
+            to_user: to.to_string(),
+            amount,
+            lamport_time: lamport,
+            source_node: node.to_string(),
+            optional_msg: None,
+            vector_clock: vc.iter().map(|(s, v)| (s.to_string(), *v)).collect(),
+        }
+    }
+
+    #[test]
+    fn causal_order_follows_vector_clocks() {
+        let transactions = vec![
+            // withdrawal on B after it received the deposit of A
+            tx("alice", NULL, 5.0, 1, "B", &[("A", 1), ("B", 1)]),
+            tx(NULL, "alice", 10.0, 4, "A", &[("A", 1)]),
+            // concurrent with both
+            tx(NULL, "bob", 1.0, 2, "C", &[("C", 1)]),
+        ];
+        let (order, unordered) = causal_order(&transactions);
+        assert_eq!(order, vec![2, 1, 0]);
+        assert_eq!(unordered, 0);
+
+        assert!(happens_before(&transactions[1], &transactions[0]));
+        assert!(!happens_before(&transactions[0], &transactions[1]));
+        assert!(!happens_before(&transactions[2], &transactions[0]));
+    }
+
+    #[test]
+    fn replay_detects_negative_and_divergent_balances() {
+        let source = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&source).unwrap();
+        source
+            .execute_batch(
+                "INSERT INTO User (unique_name, solde) VALUES ('alice', 5.0), ('bob', 3.0);
+                INSERT INTO VectorClock (id) VALUES (1), (2), (3);
+                INSERT INTO VectorClockEntry VALUES (1, 'A', 1), (2, 'A', 1), (2, 'B', 1), (3, 'A', 2);
+                INSERT INTO Transactions VALUES ('NULL', 'alice', 10.0, 1, 1, 'A', NULL);
+                INSERT INTO Transactions VALUES ('alice', 'NULL', 5.0, 2, 2, 'B', NULL);
+                INSERT INTO Transactions VALUES ('alice', 'bob', 8.0, 2, 3, 'A', NULL);",
+            )
+            .unwrap();
+
+        let target = rusqlite::Connection::open_in_memory().unwrap();
+        let report = replay_into(&source, &target).unwrap();
+
+        assert_eq!(report.transactions, 3);
+        assert_eq!(report.users, 2);
+        // the transfer of A and the withdrawal of B are concurrent,
+        // together they overdraw alice
+        assert_eq!(report.negative_balances.len(), 1);
+        assert_eq!(report.negative_balances[0].user, "alice");
+        assert_eq!(report.negative_balances[0].step, 3);
+        assert_eq!(
+            report.mismatches,
+            vec![
+                BalanceMismatch {
+                    user: "alice".to_string(),
+                    stored: 5.0,
+                    replayed: -3.0,
+                },
+                BalanceMismatch {
+                    user: "bob".to_string(),
+                    stored: 3.0,
+                    replayed: 8.0,
+                },
+            ]
+        );
+
+        let replayed: i64 = target
+            .query_row("SELECT COUNT(*) FROM Transactions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(replayed, 3);
+    }
+}
@@ -0,0 +1,473 @@
+//! Sagas, operations made of several steps
+//!
+//! Money never moves from a ledger to another, so an operation spanning
+//! ledgers, such as a withdrawal from an account of one ledger followed by a
+//! deposit to an account of another, is made of several transactions: a
+//! saga. The site that submits a saga executes its steps in order under the
+//! global mutex. When a step fails, the steps already executed are undone by
+//! compensating transactions, in the reverse order: a deposit by a
+//! withdrawal, a withdrawal by a deposit and a transfer by the opposite
+//! transfer. The whole saga is then diffused with the step that failed, and
+//! every site records the same transactions, each at its own Lamport time,
+//! and the outcome of the saga in its `Sagas` table.
+
+#![cfg(feature = "server")]
+
+use crate::db::NULL;
+
+/// Maximum number of steps of a saga
+pub const MAX_STEPS: usize = 16;
+
+/// Step of a saga, recorded as one transaction
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub enum SagaStep {
+    /// Deposit money into an account
+    Deposit { name: String, amount: f64 },
+    /// Withdraw money from an account
+    Withdraw { name: String, amount: f64 },
+    /// Transfer money between two accounts of the same ledger
+    Transfer {
+        from: String,
+        to: String,
+        amount: f64,
+    },
+}
+
+impl SagaStep {
+    /// Returns the sender and the recipient of the transaction of the step
+    pub fn accounts(&self) -> (&str, &str) {
+        match self {
+            SagaStep::Deposit { name, .. } => (NULL, name),
+            SagaStep::Withdraw { name, .. } => (name, NULL),
+            SagaStep::Transfer { from, to, .. } => (from, to),
+        }
+    }
+
+    /// Returns the amount of the step
+    pub fn amount(&self) -> f64 {
+        match self {
+            SagaStep::Deposit { amount, .. }
+            | SagaStep::Withdraw { amount, .. }
+            | SagaStep::Transfer { amount, .. } => *amount,
+        }
+    }
+
+    /// Returns the users taking part in the step
+    pub fn users(&self) -> Vec<&str> {
+        let (from, to) = self.accounts();
+        [from, to]
+            .into_iter()
+            .filter(|user| *user != NULL)
+            .collect()
+    }
+
+    /// Returns the step undoing this one
+    pub fn compensation(&self) -> SagaStep {
+        match self {
+            SagaStep::Deposit { name, amount } => SagaStep::Withdraw {
+                name: name.clone(),
+                amount: *amount,
+            },
+            SagaStep::Withdraw { name, amount } => SagaStep::Deposit {
+                name: name.clone(),
+                amount: *amount,
+            },
+            SagaStep::Transfer { from, to, amount } => SagaStep::Transfer {
+                from: to.clone(),
+                to: from.clone(),
+                amount: *amount,
+            },
+        }
+    }
+
+    /// Returns the operation of the step subject to the policies of its user
+    pub fn policy_operation(&self) -> Option<crate::policy::Operation<'_>> {
+        use crate::policy::Operation;
+
+        match self {
+            SagaStep::Deposit { .. } => None,
+            SagaStep::Withdraw { name, amount } => Some(Operation::Withdraw {
+                user: name,
+                amount: *amount,
+            }),
+            SagaStep::Transfer { from, to, amount } => Some(Operation::Transfer {
+                from,
+                to: vec![to.as_str()],
+                amount: *amount,
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for SagaStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SagaStep::Deposit { name, amount } => write!(f, "deposit {:.2} € to {}", amount, name),
+            SagaStep::Withdraw { name, amount } => {
+                write!(f, "withdraw {:.2} € from {}", amount, name)
+            }
+            SagaStep::Transfer { from, to, amount } => {
+                write!(f, "transfer {:.2} € from {} to {}", amount, from, to)
+            }
+        }
+    }
+}
+
+/// Step of a saga that could not be executed
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct SagaFailure {
+    /// Index of the step, the steps before it being compensated
+    pub step: usize,
+    /// Reason of the failure
+    pub reason: String,
+}
+
+/// Saga recorded by the site
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Saga {
+    /// Lamport time of the first transaction of the saga
+    pub lamport_time: i64,
+    /// ID of the node that executed the saga
+    pub source_node: String,
+    /// Steps of the saga
+    pub steps: Vec<SagaStep>,
+    /// Step that failed, None if the saga completed
+    pub failure: Option<SagaFailure>,
+    /// Local date at which the saga was recorded
+    pub recorded_at: String,
+}
+
+impl Saga {
+    /// Returns the value stored in the status column
+    pub fn status(&self) -> &'static str {
+        match self.failure {
+            None => "Completed",
+            Some(_) => "Compensated",
+        }
+    }
+}
+
+/// Checks the steps of a saga before it is submitted
+pub fn validate(steps: &[SagaStep]) -> Result<(), String> {
+    if steps.is_empty() {
+        return Err("A saga needs at least one step.".to_string());
+    }
+    if steps.len() > MAX_STEPS {
+        return Err(format!("A saga has at most {} steps.", MAX_STEPS));
+    }
+    for step in steps {
+        crate::amount::check(step.amount())?;
+        if let SagaStep::Transfer { from, to, .. } = step
+            && from == to
+        {
+            return Err(format!("'{}' cannot transfer money to itself.", from));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the steps recorded for a saga, each with true if it compensates
+/// another one: the steps executed before the failure, if any, then their
+/// compensations in the reverse order
+pub fn recorded_steps(steps: &[SagaStep], failure: Option<&SagaFailure>) -> Vec<(SagaStep, bool)> {
+    let Some(failure) = failure else {
+        return steps.iter().map(|step| (step.clone(), false)).collect();
+    };
+    let executed = &steps[..failure.step.min(steps.len())];
+    executed
+        .iter()
+        .map(|step| (step.clone(), false))
+        .chain(
+            executed
+                .iter()
+                .rev()
+                .map(|step| (step.compensation(), true)),
+        )
+        .collect()
+}
+
+/// Returns the first step of a saga that cannot be executed, if any
+///
+/// The steps are played on the balances of their users without touching the
+/// database: a step fails if one of its users does not exist or is archived,
+/// if its users belong to different ledgers or if its sender has not enough
+/// money once the steps before it are executed.
+fn plan_in(
+    conn: &rusqlite::Connection,
+    steps: &[SagaStep],
+) -> rusqlite::Result<Option<SagaFailure>> {
+    let mut balances: std::collections::HashMap<&str, i64> = std::collections::HashMap::new();
+    for (i, step) in steps.iter().enumerate() {
+        let failure = |reason: String| Ok(Some(SagaFailure { step: i, reason }));
+        for user in step.users() {
+            let active: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM User WHERE unique_name = ?1 AND is_active = 1)",
+                [user],
+                |row| row.get(0),
+            )?;
+            if !active {
+                return failure(format!("User '{}' does not exist or is archived.", user));
+            }
+        }
+        let (from, to) = step.accounts();
+        if let Err(e) = crate::ledger::transaction_ledger_in(conn, from, to) {
+            return failure(e.to_string());
+        }
+        let cents = (step.amount() * 100.0).round() as i64;
+        if from != NULL {
+            let balance = match balances.get(from) {
+                Some(balance) => *balance,
+                None => crate::db::account_cents(conn, from)?,
+            };
+            if balance < cents {
+                return failure(format!(
+                    "Insufficient funds: '{}' has less than {}.",
+                    from,
+                    step.amount()
+                ));
+            }
+            balances.insert(from, balance - cents);
+        }
+        if to != NULL {
+            let balance = match balances.get(to) {
+                Some(balance) => *balance,
+                None => crate::db::account_cents(conn, to)?,
+            };
+            balances.insert(to, balance + cents);
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the first step of a saga that cannot be executed, if any
+pub fn plan(steps: &[SagaStep]) -> rusqlite::Result<Option<SagaFailure>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    plan_in(&conn, steps)
+}
+
+/// Returns the message of the transaction of a recorded step
+fn step_message(lamport_time: i64, source_node: &str, step: usize, compensation: bool) -> String {
+    if compensation {
+        format!(
+            "Saga {}-{}, compensation of step {}",
+            source_node,
+            lamport_time,
+            step + 1
+        )
+    } else {
+        format!("Saga {}-{}, step {}", source_node, lamport_time, step + 1)
+    }
+}
+
+/// Saves a saga, a saga received twice being saved once
+fn insert_in(conn: &rusqlite::Connection, saga: &Saga) -> rusqlite::Result<()> {
+    let steps = serde_json::to_string(&saga.steps)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    conn.execute(
+        "INSERT OR IGNORE INTO Sagas
+            (lamport_time, source_node, steps, status, failed_step, reason, recorded_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            saga.lamport_time,
+            saga.source_node,
+            steps,
+            saga.status(),
+            saga.failure.as_ref().map(|failure| failure.step as i64),
+            saga.failure.as_ref().map(|failure| failure.reason.as_str()),
+            saga.recorded_at
+        ],
+    )?;
+    Ok(())
+}
+
+/// Returns the sagas recorded by the site, most recent first
+fn sagas_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<Saga>> {
+    let mut stmt = conn.prepare(
+        "SELECT lamport_time, source_node, steps, failed_step, reason, recorded_at
+        FROM Sagas ORDER BY lamport_time DESC, source_node",
+    )?;
+    stmt.query_map([], |row| {
+        let steps: String = row.get(2)?;
+        let failed_step: Option<i64> = row.get(3)?;
+        let reason: Option<String> = row.get(4)?;
+        Ok(Saga {
+            lamport_time: row.get(0)?,
+            source_node: row.get(1)?,
+            steps: serde_json::from_str(&steps).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into())
+            })?,
+            failure: failed_step.map(|step| SagaFailure {
+                step: step as usize,
+                reason: reason.unwrap_or_default(),
+            }),
+            recorded_at: row.get(5)?,
+        })
+    })?
+    .collect()
+}
+
+/// Records the transactions of a saga and its outcome
+///
+/// The recorded steps, see [`recorded_steps`], get successive Lamport times
+/// starting at `first_lamport`. Either all of them are recorded or none.
+pub fn apply(
+    steps: &[SagaStep],
+    failure: Option<&SagaFailure>,
+    first_lamport: i64,
+    source_node: &str,
+    vector_clock: &std::collections::HashMap<String, i64>,
+) -> rusqlite::Result<()> {
+    let mut position = 0;
+    let transactions: Vec<(String, String, f64, String)> = recorded_steps(steps, failure)
+        .into_iter()
+        .map(|(step, compensation)| {
+            // the compensations go back over the executed steps
+            let index = if compensation {
+                position -= 1;
+                position
+            } else {
+                position += 1;
+                position - 1
+            };
+            let (from, to) = step.accounts();
+            (
+                from.to_string(),
+                to.to_string(),
+                step.amount(),
+                step_message(first_lamport, source_node, index, compensation),
+            )
+        })
+        .collect();
+    if !transactions.is_empty() {
+        crate::db::create_transactions(&transactions, first_lamport, source_node, vector_clock)?;
+    }
+    let saga = Saga {
+        lamport_time: first_lamport,
+        source_node: source_node.to_string(),
+        steps: steps.to_vec(),
+        failure: failure.cloned(),
+        recorded_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    insert_in(&conn, &saga)
+}
+
+/// Returns the sagas recorded by the site, most recent first
+pub fn get_sagas() -> rusqlite::Result<Vec<Saga>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    sagas_in(&conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn withdraw(name: &str, amount: f64) -> SagaStep {
+        SagaStep::Withdraw {
+            name: name.to_string(),
+            amount,
+        }
+    }
+
+    fn deposit(name: &str, amount: f64) -> SagaStep {
+        SagaStep::Deposit {
+            name: name.to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn test_plan_across_ledgers() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg)
+            VALUES ('NULL', 'alice', 10.0, 1, 0, 'A', NULL);",
+        )
+        .unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO User (unique_name, solde, ledger) VALUES
+                ('alice', 10.0, 'default'), ('bob', 0.0, 'club-bar'), ('carol', 0.0, 'default');",
+        )
+        .unwrap();
+
+        // money leaves the ledger of alice and enters the one of bob
+        let steps = vec![withdraw("alice", 6.0), deposit("bob", 6.0)];
+        assert_eq!(plan_in(&conn, &steps).unwrap(), None);
+
+        let transfer = SagaStep::Transfer {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 1.0,
+        };
+        let failure = plan_in(&conn, &[transfer]).unwrap().unwrap();
+        assert_eq!(failure.step, 0);
+        assert!(failure.reason.contains("ledger"));
+
+        // the second withdrawal only fails once the first one is executed
+        let steps = vec![
+            withdraw("alice", 6.0),
+            deposit("bob", 6.0),
+            withdraw("alice", 6.0),
+        ];
+        let failure = plan_in(&conn, &steps).unwrap().unwrap();
+        assert_eq!(failure.step, 2);
+        assert!(failure.reason.starts_with("Insufficient funds"));
+        assert_eq!(
+            plan_in(&conn, &[deposit("dave", 1.0)])
+                .unwrap()
+                .unwrap()
+                .reason,
+            "User 'dave' does not exist or is archived."
+        );
+    }
+
+    #[test]
+    fn test_compensations() {
+        let transfer = SagaStep::Transfer {
+            from: "alice".to_string(),
+            to: "carol".to_string(),
+            amount: 2.0,
+        };
+        let steps = vec![withdraw("alice", 6.0), deposit("bob", 6.0), transfer];
+        assert_eq!(
+            recorded_steps(&steps, None),
+            steps
+                .iter()
+                .map(|step| (step.clone(), false))
+                .collect::<Vec<_>>()
+        );
+
+        let failure = SagaFailure {
+            step: 2,
+            reason: "Insufficient funds".to_string(),
+        };
+        assert_eq!(
+            recorded_steps(&steps, Some(&failure)),
+            vec![
+                (withdraw("alice", 6.0), false),
+                (deposit("bob", 6.0), false),
+                (withdraw("bob", 6.0), true),
+                (deposit("alice", 6.0), true),
+            ]
+        );
+        assert_eq!(steps[2].compensation().accounts(), ("carol", "alice"));
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        let saga = Saga {
+            lamport_time: 4,
+            source_node: "A".to_string(),
+            steps,
+            failure: Some(failure),
+            recorded_at: "2026-10-16 12:00:00".to_string(),
+        };
+        insert_in(&conn, &saga).unwrap();
+        insert_in(&conn, &saga).unwrap();
+        assert_eq!(sagas_in(&conn).unwrap(), vec![saga]);
+        assert!(validate(&[]).is_err());
+        assert!(validate(&[deposit("bob", -1.0)]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Seed data loaded on the first start of a site
+//!
+//! A seed file describes the users to create, with their initial balance, when
+//! a site starts with an empty database. It is written in TOML:
+//!
+//! ```toml
+//! [[users]]
+//! name = "alice"
+//! balance = 50.0
+//!
+//! [[users]]
+//! name = "bob"
+//! ```
+//!
+//! Initial balances are recorded as regular deposits, dated with the local
+//! clock, under the node [`SEED_NODE`]. Sites of a cluster seeded from the
+//! same file hold the same transactions, which are not duplicated when the
+//! sites synchronize.
+
+#![cfg(feature = "server")]
+
+/// Source node of the transactions created from a seed file
+pub const SEED_NODE: &str = "seed";
+
+/// User to create from a seed file
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct SeedUser {
+    /// Unique name of the user
+    pub name: String,
+    /// Initial balance of the user
+    #[serde(default)]
+    pub balance: f64,
+}
+
+/// Content of a seed file
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+pub struct SeedFile {
+    /// Users to create, in order
+    #[serde(default)]
+    pub users: Vec<SeedUser>,
+}
+
+impl SeedFile {
+    /// Parses and validates the content of a seed file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let seed: SeedFile =
+            toml::from_str(content).map_err(|e| format!("invalid seed file: {}", e))?;
+        seed.validate()?;
+        Ok(seed)
+    }
+
+    /// Checks the names and initial balances of the users
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = std::collections::HashSet::new();
+        for user in &self.users {
+            if user.name.is_empty()
+                || user.name == "NULL"
+                || crate::db::is_system_account(&user.name)
+            {
+                return Err(format!("invalid user name '{}'", user.name));
+            }
+            if !names.insert(user.name.as_str()) {
+                return Err(format!("user '{}' is defined twice", user.name));
+            }
+            if !user.balance.is_finite() || user.balance < 0.0 {
+                return Err(format!(
+                    "initial balance of '{}' must be positive",
+                    user.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and validates a seed file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read seed file {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+}
+
+/// Creates the users of a seed file with their initial balance
+///
+/// The local clock ticks once per user, as for any local transaction.
+pub async fn apply(seed: &SeedFile) -> Result<(), Box<dyn std::error::Error>> {
+    for user in &seed.users {
+        let clock = crate::state::update_clock(None).await;
+        crate::db::create_user_with_solde(
+            &user.name,
+            user.balance,
+            clock.get_lamport(),
+            SEED_NODE,
+            clock.get_vector_clock_map(),
+        )?;
+    }
+    log::info!("Seeded {} users", seed.users.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seed_file() {
+        let seed = SeedFile::parse(
+            r#"
+            [[users]]
+            name = "alice"
+            balance = 50.0
+
+            [[users]]
+            name = "bob"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(seed.users.len(), 2);
+        assert_eq!(seed.users[0].balance, 50.0);
+        assert_eq!(seed.users[1].balance, 0.0);
+
+        assert_eq!(SeedFile::parse("").unwrap(), SeedFile::default());
+        assert!(SeedFile::parse("[[users]]\nname = \"a\"\n[[users]]\nname = \"a\"").is_err());
+        assert!(SeedFile::parse("[[users]]\nname = \"a\"\nbalance = -1.0").is_err());
+        assert!(SeedFile::parse("[[users]]\nname = \"NULL\"").is_err());
+        assert!(SeedFile::parse("users = 3").is_err());
+    }
+}
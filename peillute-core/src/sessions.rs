@@ -0,0 +1,232 @@
+//! Acting user of the tabs of the browser sessions of the web interface
+//!
+//! The pages of a user carry its name in their URL, and their server
+//! functions take it as an argument: without more, a form of another tab, or
+//! a crafted request, could act as any user. When the pages of a user are
+//! opened in a tab, the site records that user as the acting user of the tab,
+//! in the browser session of its session cookie. The server functions
+//! operating on the account of a user called from the web interface are
+//! refused unless the tab of the request acts as that user.
+//!
+//! The users have no credentials: a session only records which user each tab
+//! is viewing, it does not authenticate anybody. Whoever reaches the web
+//! interface may open the pages of any user. The session keeps the forms of a
+//! tab from acting as the user shown by another one, and, its cookie being
+//! `SameSite=Strict`, the requests crafted by other websites from acting as
+//! any user.
+//!
+//! A browser has a single session, and each of its tabs keeps a random tab ID
+//! in its `sessionStorage`, sent with the server functions acting as a user.
+//! The tabs opening the pages of different users thus do not interfere. The
+//! requests without a tab ID, such as those of `--bench`, share the default
+//! tab of their session. The sessions are kept in memory, and expire after
+//! [`SESSION_IDLE_TIMEOUT`] without request or when the site restarts. The
+//! holders of API tokens are authorized by their token instead, see
+//! [`crate::api_tokens`].
+//!
+//! The administration of the site, such as its shutdown, needs an admin
+//! session: the browser logs in with an admin API token, or the token of an
+//! admin operator, see [`crate::operators`]. The login holds for every tab of
+//! the browser.
+
+#![cfg(feature = "server")]
+
+/// Name of the cookie holding the ID of the session
+pub const SESSION_COOKIE: &str = "peillute_session";
+
+/// Time without request after which a session expires
+pub const SESSION_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(12 * 3600);
+
+/// Browser session of the web interface
+struct Session {
+    /// User whose pages were last opened in each tab of the session, by tab
+    /// ID, the default tab having an empty ID
+    acting_users: std::collections::HashMap<String, String>,
+    /// Whether the session logged in as an administrator
+    admin: bool,
+    /// Time of the last request of the session
+    last_seen: std::time::Instant,
+}
+
+lazy_static::lazy_static! {
+    /// Open sessions, by ID
+    static ref SESSIONS: std::sync::Mutex<std::collections::HashMap<String, Session>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+/// Generates the ID of a new session from the random source of the system
+fn generate_id() -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Returns the ID of the session in the `Cookie` headers of a request
+pub fn session_id(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == SESSION_COOKIE && !value.is_empty()).then(|| value.to_string())
+        })
+}
+
+/// Returns the `Set-Cookie` header value binding a browser to a session
+pub fn cookie(id: &str) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict",
+        SESSION_COOKIE, id
+    )
+}
+
+/// Returns the live session of a request, opening a new one if the request
+/// has none or an expired one
+fn open<'a>(
+    sessions: &'a mut std::collections::HashMap<String, Session>,
+    id: Option<&str>,
+) -> std::io::Result<(String, &'a mut Session)> {
+    sessions.retain(|_, session| session.last_seen.elapsed() < SESSION_IDLE_TIMEOUT);
+    let id = match id.filter(|id| sessions.contains_key(*id)) {
+        Some(id) => id.to_string(),
+        None => generate_id()?,
+    };
+    let session = sessions.entry(id.clone()).or_insert(Session {
+        acting_users: std::collections::HashMap::new(),
+        admin: false,
+        last_seen: std::time::Instant::now(),
+    });
+    session.last_seen = std::time::Instant::now();
+    Ok((id, session))
+}
+
+/// Makes a user the acting user of a tab of a session, opening a new session
+/// if the request has none or an expired one
+///
+/// Returns the ID of the session.
+pub fn enter(id: Option<&str>, tab: Option<&str>, user: &str) -> std::io::Result<String> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let (id, session) = open(&mut sessions, id)?;
+    session
+        .acting_users
+        .insert(tab.unwrap_or_default().to_string(), user.to_string());
+    Ok(id)
+}
+
+/// Logs a session in as an administrator, opening a new session if the
+/// request has none or an expired one
+///
+/// The caller checks the token given by the browser. Returns the ID of the
+/// session.
+pub fn enter_admin(id: Option<&str>) -> std::io::Result<String> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let (id, session) = open(&mut sessions, id)?;
+    session.admin = true;
+    Ok(id)
+}
+
+/// Checks that a tab of a session acts as a user
+pub fn check(id: Option<&str>, tab: Option<&str>, user: &str) -> Result<(), String> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = id
+        .and_then(|id| sessions.get_mut(id))
+        .filter(|session| session.last_seen.elapsed() < SESSION_IDLE_TIMEOUT)
+        .ok_or_else(|| format!("No session acting as '{}': reload the page", user))?;
+    match session.acting_users.get(tab.unwrap_or_default()) {
+        Some(acting_user) if acting_user == user => {}
+        Some(acting_user) => {
+            return Err(format!(
+                "This tab now acts as '{}': reload the page to act as '{}'",
+                acting_user, user
+            ));
+        }
+        None => return Err(format!("No session acting as '{}': reload the page", user)),
+    }
+    session.last_seen = std::time::Instant::now();
+    Ok(())
+}
+
+/// Checks that a session logged in as an administrator
+pub fn check_admin(id: Option<&str>) -> Result<(), String> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = id
+        .and_then(|id| sessions.get_mut(id))
+        .filter(|session| session.last_seen.elapsed() < SESSION_IDLE_TIMEOUT && session.admin)
+        .ok_or_else(|| "The administration needs an admin token: log in first".to_string())?;
+    session.last_seen = std::time::Instant::now();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions() {
+        let id = enter(None, None, "session-alice").unwrap();
+        assert!(check(Some(&id), None, "session-alice").is_ok());
+        assert!(check(Some(&id), None, "session-bob").is_err());
+        assert!(check(None, None, "session-alice").is_err());
+        assert!(check(Some("unknown"), None, "session-alice").is_err());
+
+        // the same tab then opens the pages of bob
+        assert_eq!(enter(Some(&id), None, "session-bob").unwrap(), id);
+        assert!(check(Some(&id), None, "session-alice").is_err());
+        assert!(check(Some(&id), None, "session-bob").is_ok());
+        assert_ne!(enter(Some("unknown"), None, "session-bob").unwrap(), id);
+    }
+
+    #[test]
+    fn test_tabs() {
+        let id = enter(None, Some("tab-1"), "session-erin").unwrap();
+
+        // another tab of the browser opens the pages of frank
+        assert_eq!(
+            enter(Some(&id), Some("tab-2"), "session-frank").unwrap(),
+            id
+        );
+        assert!(check(Some(&id), Some("tab-1"), "session-erin").is_ok());
+        assert!(check(Some(&id), Some("tab-2"), "session-frank").is_ok());
+        assert!(check(Some(&id), Some("tab-1"), "session-frank").is_err());
+        assert!(check(Some(&id), Some("tab-3"), "session-erin").is_err());
+        assert!(check(Some(&id), None, "session-erin").is_err());
+    }
+
+    #[test]
+    fn test_admin_sessions() {
+        let id = enter(None, None, "session-carol").unwrap();
+        assert!(check_admin(Some(&id)).is_err());
+        assert!(check_admin(None).is_err());
+
+        // logging in keeps the acting user, and entering a user keeps the login
+        assert_eq!(enter_admin(Some(&id)).unwrap(), id);
+        assert!(check_admin(Some(&id)).is_ok());
+        assert!(check(Some(&id), None, "session-carol").is_ok());
+        enter(Some(&id), None, "session-dave").unwrap();
+        assert!(check_admin(Some(&id)).is_ok());
+
+        // a session opened by the login acts as no user
+        let admin = enter_admin(None).unwrap();
+        assert!(check_admin(Some(&admin)).is_ok());
+        assert!(check(Some(&admin), None, "session-carol").is_err());
+    }
+
+    #[test]
+    fn test_session_id() {
+        let mut headers = axum::http::HeaderMap::new();
+        assert_eq!(session_id(&headers), None);
+        headers.insert(
+            axum::http::header::COOKIE,
+            "theme=dark; peillute_session=0a1b".parse().unwrap(),
+        );
+        assert_eq!(session_id(&headers).as_deref(), Some("0a1b"));
+        assert_eq!(
+            cookie("0a1b"),
+            "peillute_session=0a1b; Path=/; HttpOnly; SameSite=Strict"
+        );
+    }
+}
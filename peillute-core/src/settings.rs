@@ -0,0 +1,192 @@
+//! UI preferences of the users
+//!
+//! Each user chooses how the web interface looks for them: dark mode, how
+//! amounts are displayed and how many transactions a page of the history
+//! holds. The preferences are stored by the site in the UserSettings table,
+//! next to the low balance alert threshold, so that they follow the user from
+//! one browser to another. Like the alert threshold, they are local to the
+//! site.
+
+/// Default number of transactions per page of the history
+pub const DEFAULT_HISTORY_PAGE_SIZE: u32 = 20;
+
+/// Greatest number of transactions per page of the history
+pub const MAX_HISTORY_PAGE_SIZE: u32 = 200;
+
+/// How amounts are displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CurrencyDisplay {
+    /// Amount followed by the euro sign, `12.50 €`
+    #[default]
+    Symbol,
+    /// Amount followed by the ISO code of the currency, `12.50 EUR`
+    Code,
+}
+
+impl CurrencyDisplay {
+    /// Returns the value stored in the currency column
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CurrencyDisplay::Symbol => "Symbol",
+            CurrencyDisplay::Code => "Code",
+        }
+    }
+
+    /// Parses a value of the currency column
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Symbol" => Some(CurrencyDisplay::Symbol),
+            "Code" => Some(CurrencyDisplay::Code),
+            _ => None,
+        }
+    }
+
+    /// Formats an amount
+    pub fn format(self, amount: f64) -> String {
+        match self {
+            CurrencyDisplay::Symbol => format!("{:.2} €", amount),
+            CurrencyDisplay::Code => format!("{:.2} EUR", amount),
+        }
+    }
+}
+
+/// UI preferences of a user
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UserSettings {
+    /// True to display the pages of the user with dark colors
+    pub dark_mode: bool,
+    /// How amounts are displayed
+    pub currency: CurrencyDisplay,
+    /// Number of transactions per page of the history
+    pub history_page_size: u32,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            dark_mode: false,
+            currency: CurrencyDisplay::default(),
+            history_page_size: DEFAULT_HISTORY_PAGE_SIZE,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the preferences of a user, the default ones if never saved
+fn settings_in(conn: &rusqlite::Connection, user: &str) -> rusqlite::Result<UserSettings> {
+    use rusqlite::OptionalExtension;
+
+    let settings = conn
+        .query_row(
+            "SELECT dark_mode, currency, history_page_size FROM UserSettings
+            WHERE unique_name = ?1",
+            rusqlite::params![user],
+            |row| {
+                let default = UserSettings::default();
+                Ok(UserSettings {
+                    dark_mode: row.get::<_, Option<bool>>(0)?.unwrap_or(default.dark_mode),
+                    currency: row
+                        .get::<_, Option<String>>(1)?
+                        .and_then(|currency| CurrencyDisplay::parse(&currency))
+                        .unwrap_or(default.currency),
+                    history_page_size: row
+                        .get::<_, Option<u32>>(2)?
+                        .unwrap_or(default.history_page_size),
+                })
+            },
+        )
+        .optional()?;
+    Ok(settings.unwrap_or_default())
+}
+
+#[cfg(feature = "server")]
+/// Saves the preferences of a user, keeping its alert threshold
+fn save_settings_in(
+    conn: &rusqlite::Connection,
+    user: &str,
+    settings: &UserSettings,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO UserSettings (unique_name, dark_mode, currency, history_page_size)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(unique_name) DO UPDATE SET dark_mode = excluded.dark_mode,
+            currency = excluded.currency, history_page_size = excluded.history_page_size",
+        rusqlite::params![
+            user,
+            settings.dark_mode,
+            settings.currency.as_str(),
+            settings.history_page_size
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the preferences of a user, the default ones if never saved
+pub fn get_settings(user: &str) -> rusqlite::Result<UserSettings> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    settings_in(&conn, user)
+}
+
+#[cfg(feature = "server")]
+/// Saves the preferences of a user
+pub fn set_settings(user: &str, settings: &UserSettings) -> rusqlite::Result<()> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    save_settings_in(&conn, user, settings)
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+
+        assert_eq!(
+            settings_in(&conn, "alice").unwrap(),
+            UserSettings::default()
+        );
+
+        conn.execute(
+            "INSERT INTO UserSettings (unique_name, alert_threshold) VALUES ('alice', 5.0)",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            settings_in(&conn, "alice").unwrap(),
+            UserSettings::default()
+        );
+
+        let settings = UserSettings {
+            dark_mode: true,
+            currency: CurrencyDisplay::Code,
+            history_page_size: 50,
+        };
+        save_settings_in(&conn, "alice", &settings).unwrap();
+        assert_eq!(settings_in(&conn, "alice").unwrap(), settings);
+        let threshold: f64 = conn
+            .query_row(
+                "SELECT alert_threshold FROM UserSettings WHERE unique_name = 'alice'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(threshold, 5.0);
+        assert_eq!(settings_in(&conn, "bob").unwrap(), UserSettings::default());
+    }
+
+    #[test]
+    fn test_currency_display() {
+        assert_eq!(CurrencyDisplay::Symbol.format(12.5), "12.50 €");
+        assert_eq!(CurrencyDisplay::Code.format(-3.0), "-3.00 EUR");
+        assert_eq!(
+            CurrencyDisplay::parse(CurrencyDisplay::Code.as_str()),
+            Some(CurrencyDisplay::Code)
+        );
+        assert_eq!(CurrencyDisplay::parse("Dollar"), None);
+    }
+}
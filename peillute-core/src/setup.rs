@@ -0,0 +1,272 @@
+//! Setup of a new site from the web interface
+//!
+//! A site started for the first time, with an empty database and no peer,
+//! sends its web interface to a setup wizard instead of the home page. The
+//! wizard chooses the alias of the site, the port it listens on for its
+//! peers, the peers to join and the first users with their initial balance.
+//! The site then restarts its networking on the new port to discover the
+//! peers, writes these choices to its setup file, and creates the users as
+//! regular critical commands, diffused to the network it joined.
+//!
+//! The setup file is written in TOML, next to the database, as
+//! `peillute_<id>.toml` unless `--setup-file` names another one:
+//!
+//! ```toml
+//! site_alias = "Cafeteria"
+//! port = 10001
+//! peers = ["192.168.1.20:10000"]
+//! ```
+//!
+//! On the following starts, it gives the values of the options missing from
+//! the command line. The web server keeps its address until the next start,
+//! where it listens on the new port plus [`crate::node::PORT_OFFSET`], unless
+//! `--web-port` fixes its port, see [`crate::web_address`].
+//! An ephemeral site has no setup file and never shows the wizard.
+
+/// Answers of the setup wizard
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SetupRequest {
+    /// Human-friendly name of the site
+    pub site_alias: String,
+    /// Port listened on for the peers, None to keep the current one
+    pub port: Option<u16>,
+    /// Peers to join, as `ip:port`, `[ipv6]:port` or `hostname:port`
+    pub peers: Vec<String>,
+    /// First users, with their initial balance
+    pub users: Vec<(String, f64)>,
+}
+
+/// State of the site shown by the setup wizard
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SetupStatus {
+    /// True if the site has not been set up yet
+    pub needed: bool,
+    /// ID of the site
+    pub site_id: String,
+    /// Port currently listened on for the peers
+    pub port: u16,
+    /// Setup file written by the wizard, if the site has one
+    pub path: Option<String>,
+}
+
+#[cfg(feature = "server")]
+/// Content of a setup file
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetupFile {
+    /// Human-friendly name of the site
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site_alias: Option<String>,
+    /// Port listened on for the peers, 0 for the first free one
+    #[serde(default)]
+    pub port: u16,
+    /// Peers to join
+    #[serde(default)]
+    pub peers: Vec<String>,
+}
+
+#[cfg(feature = "server")]
+impl SetupFile {
+    /// Parses and validates the content of a setup file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let setup: SetupFile =
+            toml::from_str(content).map_err(|e| format!("invalid setup file: {}", e))?;
+        if let Some(alias) = &setup.site_alias {
+            crate::aliases::validate_alias(alias)?;
+        }
+        validate_port(setup.port)?;
+        Ok(setup)
+    }
+
+    /// Reads and validates a setup file
+    pub fn load(path: &std::path::Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read setup file {}: {}", path.display(), e))?;
+        Self::parse(&content)
+    }
+
+    /// Writes the setup file
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        let content = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, content)
+            .map_err(|e| format!("cannot write setup file {}: {}", path.display(), e))
+    }
+
+    /// Gives the options missing from the command line of a node
+    pub fn fill(&self, config: &mut crate::node::NodeConfig) {
+        if config.site_alias.is_none() {
+            config.site_alias = self.site_alias.clone();
+        }
+        if config.port == 0 {
+            config.port = self.port;
+        }
+        if config.peers.is_empty() {
+            config.peers = self.peers.clone();
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Checks that the web server of a site listening on a port gets a valid port
+fn validate_port(port: u16) -> Result<(), String> {
+    if port > u16::MAX - crate::node::PORT_OFFSET {
+        return Err(format!(
+            "The port cannot exceed {}, the web interface listening {} ports above",
+            u16::MAX - crate::node::PORT_OFFSET,
+            crate::node::PORT_OFFSET
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the default setup file of a database
+pub fn default_path(db_path: &std::path::Path) -> std::path::PathBuf {
+    db_path.with_extension("toml")
+}
+
+#[cfg(feature = "server")]
+/// Setup file of the site, none for an ephemeral site
+static PATH: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+#[cfg(feature = "server")]
+/// Sets the setup file of the site
+pub fn set_path(path: std::path::PathBuf) {
+    if PATH.set(path).is_err() {
+        log::warn!("The setup file is already set");
+    }
+}
+
+#[cfg(feature = "server")]
+/// Returns the state of the site shown by the setup wizard
+///
+/// The setup is needed while the site has no setup file, no user and no
+/// peer, and is not the client of a full node.
+pub async fn status() -> rusqlite::Result<SetupStatus> {
+    let (site_id, addr) = crate::state::local_site().await;
+    let path = PATH.get();
+    let needed = path.is_some_and(|path| !path.exists())
+        && crate::client::full_node().is_none()
+        && crate::state::PEERS
+            .read()
+            .await
+            .get_connected_nei_addr()
+            .is_empty()
+        && crate::db::get_users()?.is_empty();
+    Ok(SetupStatus {
+        needed,
+        site_id,
+        port: addr.port(),
+        path: path.map(|path| path.display().to_string()),
+    })
+}
+
+#[cfg(feature = "server")]
+/// Applies the answers of the setup wizard
+///
+/// Everything is checked first. The setup file is written once the site
+/// restarted its networking, so that the wizard can be run again if the port
+/// cannot be listened on, and the users are created last, to reach the peers
+/// the site joined. Returns the new address of the site.
+pub async fn run(
+    request: SetupRequest,
+) -> Result<std::net::SocketAddr, Box<dyn std::error::Error>> {
+    let status = status().await?;
+    if !status.needed {
+        return Err("The site is already set up".into());
+    }
+    let path = PATH.get().ok_or("The site has no setup file")?;
+
+    let site_alias = request.site_alias.trim().to_string();
+    crate::aliases::validate_alias(&site_alias)?;
+    let port = request.port.unwrap_or(status.port);
+    if port == 0 {
+        return Err("The port cannot be 0".into());
+    }
+    validate_port(port)?;
+    let peers: Vec<String> = request
+        .peers
+        .iter()
+        .map(|peer| peer.trim().to_string())
+        .filter(|peer| !peer.is_empty())
+        .collect();
+    let (_, local_addr) = crate::state::local_site().await;
+    let mut peer_addrs = Vec::new();
+    for peer in &peers {
+        match crate::network::resolve_peer(peer, local_addr.ip()).await {
+            Some(addr) => peer_addrs.push(addr),
+            None => return Err(format!("Cannot resolve the peer {}", peer).into()),
+        }
+    }
+    let seed = crate::seed::SeedFile {
+        users: request
+            .users
+            .iter()
+            .map(|(name, balance)| crate::seed::SeedUser {
+                name: name.trim().to_string(),
+                balance: *balance,
+            })
+            .collect(),
+    };
+    seed.validate()?;
+    for user in seed.users.iter().filter(|user| user.balance > 0.0) {
+        crate::amount::check(user.balance)?;
+    }
+
+    {
+        let mut site = crate::state::SITE_INFO.write().await;
+        site.init_site_alias(Some(site_alias.clone()));
+        site.init_cli_peer_addrs(peer_addrs);
+    }
+    crate::aliases::record(&status.site_id, &site_alias).await;
+    let addr = crate::node::restart_networking(Some(port)).await?;
+
+    SetupFile {
+        site_alias: Some(site_alias),
+        port,
+        peers,
+    }
+    .save(path)?;
+    log::info!("Setup written to {}", path.display());
+
+    for user in seed.users {
+        crate::api::create_user(user.name.clone(), String::new()).await?;
+        if user.balance > 0.0 {
+            crate::api::deposit(user.name, user.balance).await?;
+        }
+    }
+    Ok(addr)
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_file() {
+        let setup = SetupFile {
+            site_alias: Some("Cafeteria".to_string()),
+            port: 10001,
+            peers: vec!["192.168.1.20:10000".to_string()],
+        };
+        let content = toml::to_string(&setup).unwrap();
+        assert_eq!(SetupFile::parse(&content).unwrap(), setup);
+        assert_eq!(SetupFile::parse("").unwrap(), SetupFile::default());
+        assert!(SetupFile::parse("port = 65000").is_err());
+        assert!(SetupFile::parse("site_alias = \" \"").is_err());
+
+        let mut config = crate::node::NodeConfig {
+            port: 10005,
+            ..Default::default()
+        };
+        setup.fill(&mut config);
+        assert_eq!(config.site_alias.as_deref(), Some("Cafeteria"));
+        assert_eq!(config.port, 10005);
+        assert_eq!(config.peers, setup.peers);
+        assert_eq!(
+            default_path(std::path::Path::new("data/peillute_1.db")),
+            std::path::PathBuf::from("data/peillute_1.toml")
+        );
+    }
+}
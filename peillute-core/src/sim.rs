@@ -0,0 +1,1263 @@
+//! Simulation of a network of sites inside a single process
+//!
+//! `--simulate <scenario.toml>` runs the wave diffusion, global mutex and
+//! snapshot algorithms on virtual sites connected by an in-memory bus instead
+//! of sockets. The bus delivers the messages of each link in order after a
+//! random latency, may drop some of them, and can be partitioned. Time is
+//! virtual and every random choice derives from the seed of the scenario, so
+//! a scenario always produces the same run.
+//!
+//! The virtual sites reuse the state machines of the real protocol
+//! ([`WaveEngine`], [`CriticalSection`], [`SnapshotManager`] and vector
+//! [`Clock`]s); the database is replaced by in-memory balances. A scenario is
+//! written in TOML:
+//!
+//! ```toml
+//! seed = 7
+//! nodes = ["A", "B", "C", "D"]
+//! # without links, every pair of sites is connected
+//! links = [["A", "B"], ["B", "C"], ["C", "D"], ["D", "A"]]
+//! # latency of a message, in virtual milliseconds
+//! latency = [1, 20]
+//! drop_rate = 0.0
+//!
+//! [[users]]
+//! name = "alice"
+//! balance = 100.0
+//!
+//! [[events]]
+//! at = 0
+//! action = "transfer"
+//! node = "A"
+//! user = "alice"
+//! to = "bob"
+//! amount = 30.0
+//!
+//! [[events]]
+//! at = 100
+//! action = "partition"
+//! groups = [["A", "B"], ["C", "D"]]
+//!
+//! [[events]]
+//! at = 500
+//! action = "heal"
+//! ```
+//!
+//! The other actions are `deposit` and `withdraw` (with `node`, `user` and
+//! `amount`) and `snapshot` (with `node`). A partition closes the links
+//! between the groups, as if the TCP connections were lost; sites not listed
+//! form one more group.
+
+#![cfg(feature = "server")]
+
+use crate::clock::{Clock, ClockStrategy};
+use crate::snapshot::{SnapshotManager, SnapshotMode, TxSummary};
+use crate::state::{CriticalSection, MutexStamp, MutexTag};
+use crate::wave::{BlueTransition, RedTransition, WaveEngine};
+
+/// Default latency of a message, in virtual milliseconds
+const DEFAULT_LATENCY_MS: (u64, u64) = (1, 10);
+
+/// Default maximum virtual duration of a simulation, in milliseconds
+const DEFAULT_DURATION_MS: u64 = 60_000;
+
+/// Port of the virtual address of the first site
+const BASE_PORT: u16 = 20000;
+
+/// User standing for the outside world in deposits and withdrawals
+const EXTERNAL: &str = "NULL";
+
+fn default_latency() -> (u64, u64) {
+    DEFAULT_LATENCY_MS
+}
+
+fn default_duration() -> u64 {
+    DEFAULT_DURATION_MS
+}
+
+/// Action of a scenario
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Deposit on the account of a user, from a site
+    Deposit {
+        node: String,
+        user: String,
+        amount: f64,
+    },
+    /// Withdrawal from the account of a user, from a site
+    Withdraw {
+        node: String,
+        user: String,
+        amount: f64,
+    },
+    /// Transfer between two users, from a site
+    Transfer {
+        node: String,
+        user: String,
+        to: String,
+        amount: f64,
+    },
+    /// Global snapshot initiated by a site
+    Snapshot { node: String },
+    /// Closes the links between groups of sites
+    Partition { groups: Vec<Vec<String>> },
+    /// Restores every closed link
+    Heal,
+}
+
+/// Action of a scenario, with its date
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct ScenarioEvent {
+    /// Virtual time of the action, in milliseconds
+    pub at: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Content of a scenario file
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct Scenario {
+    /// Seed of the latencies and drops
+    #[serde(default)]
+    pub seed: u64,
+    /// IDs of the sites
+    pub nodes: Vec<String>,
+    /// Links between the sites, every pair of sites is linked if empty
+    #[serde(default)]
+    pub links: Vec<(String, String)>,
+    /// Minimum and maximum latency of a message, in virtual milliseconds
+    #[serde(default = "default_latency")]
+    pub latency: (u64, u64),
+    /// Probability for a message to be lost
+    #[serde(default)]
+    pub drop_rate: f64,
+    /// Virtual time after which the simulation stops, in milliseconds
+    #[serde(default = "default_duration")]
+    pub duration: u64,
+    /// Users known by every site at start, with their balance
+    #[serde(default)]
+    pub users: Vec<crate::seed::SeedUser>,
+    /// Actions to run
+    #[serde(default)]
+    pub events: Vec<ScenarioEvent>,
+}
+
+impl Scenario {
+    /// Parses and validates the content of a scenario file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let scenario: Scenario =
+            toml::from_str(content).map_err(|e| format!("invalid scenario: {}", e))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Reads and validates a scenario file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read scenario {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nodes.is_empty() {
+            return Err("the scenario has no node".to_string());
+        }
+        let mut names = std::collections::HashSet::new();
+        for node in &self.nodes {
+            if node.is_empty() || !names.insert(node.as_str()) {
+                return Err(format!("invalid or duplicated node '{}'", node));
+            }
+        }
+        let known = |node: &String| {
+            if names.contains(node.as_str()) {
+                Ok(())
+            } else {
+                Err(format!("unknown node '{}'", node))
+            }
+        };
+        for (a, b) in &self.links {
+            known(a)?;
+            known(b)?;
+            if a == b {
+                return Err(format!("node '{}' cannot be linked to itself", a));
+            }
+        }
+        if self.latency.0 > self.latency.1 {
+            return Err("the minimum latency exceeds the maximum latency".to_string());
+        }
+        if !(0.0..1.0).contains(&self.drop_rate) {
+            return Err("the drop rate must be in [0, 1)".to_string());
+        }
+        for event in &self.events {
+            match &event.action {
+                Action::Deposit { node, amount, .. }
+                | Action::Withdraw { node, amount, .. }
+                | Action::Transfer { node, amount, .. } => {
+                    known(node)?;
+                    if !amount.is_finite() || *amount <= 0.0 {
+                        return Err(format!("invalid amount {} at {} ms", amount, event.at));
+                    }
+                }
+                Action::Snapshot { node } => known(node)?,
+                Action::Partition { groups } => {
+                    for node in groups.iter().flatten() {
+                        known(node)?;
+                    }
+                }
+                Action::Heal => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot completed during a simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotOutcome {
+    /// Site that initiated the snapshot
+    pub site: String,
+    /// Virtual time at which the snapshot completed
+    pub at: u64,
+    /// Number of transactions in the global snapshot
+    pub transactions: usize,
+    /// False if the local snapshots had to be back-tracked
+    pub consistent: bool,
+}
+
+/// Result of a simulation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Seed of the scenario
+    pub seed: u64,
+    /// Virtual time of the last event, in milliseconds
+    pub duration: u64,
+    /// Messages sent by the sites
+    pub sent: u64,
+    /// Messages delivered to the sites
+    pub delivered: u64,
+    /// Messages lost, dropped by the bus or sent on a closed link
+    pub dropped: u64,
+    /// Transactions applied by the site that initiated them
+    pub applied: u64,
+    /// Transactions refused for lack of balance
+    pub rejected: u64,
+    /// Transactions whose diffusion wave completed
+    pub confirmed: u64,
+    /// Commands still queued or diffusing at the end of the simulation
+    pub unfinished: usize,
+    /// Snapshots completed
+    pub snapshots: Vec<SnapshotOutcome>,
+    /// Sites of a same partition found in critical section together, with the time
+    pub mutex_violations: Vec<(u64, Vec<String>)>,
+    /// Balances in cents of each site at the end, by user
+    pub balances: std::collections::BTreeMap<String, std::collections::BTreeMap<String, i64>>,
+}
+
+impl Report {
+    /// Returns true if every site ends with the same balances
+    pub fn converged(&self) -> bool {
+        let mut balances = self.balances.values();
+        match balances.next() {
+            Some(first) => balances.all(|b| b == first),
+            None => true,
+        }
+    }
+
+    /// Returns the accounts with a negative balance, as `site/user`
+    pub fn negative_balances(&self) -> Vec<String> {
+        self.balances
+            .iter()
+            .flat_map(|(site, users)| {
+                users
+                    .iter()
+                    .filter(|(_, cents)| **cents < 0)
+                    .map(move |(user, _)| format!("{}/{}", site, user))
+            })
+            .collect()
+    }
+
+    /// Returns true if the mutual exclusion was respected and no balance is negative
+    pub fn is_safe(&self) -> bool {
+        self.mutex_violations.is_empty() && self.negative_balances().is_empty()
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Simulation of {} sites (seed {}), {} ms of virtual time",
+            self.balances.len(),
+            self.seed,
+            self.duration
+        )?;
+        writeln!(
+            f,
+            "Messages: {} sent, {} delivered, {} dropped",
+            self.sent, self.delivered, self.dropped
+        )?;
+        writeln!(
+            f,
+            "Transactions: {} applied, {} confirmed, {} rejected, {} commands unfinished",
+            self.applied, self.confirmed, self.rejected, self.unfinished
+        )?;
+        for snapshot in &self.snapshots {
+            writeln!(
+                f,
+                "Snapshot by {} at {} ms: {} transactions, {}",
+                snapshot.site,
+                snapshot.at,
+                snapshot.transactions,
+                if snapshot.consistent {
+                    "consistent"
+                } else {
+                    "back-tracked"
+                }
+            )?;
+        }
+        if self.mutex_violations.is_empty() {
+            writeln!(f, "Mutual exclusion: respected")?;
+        }
+        for (at, sites) in &self.mutex_violations {
+            writeln!(
+                f,
+                "Mutual exclusion: VIOLATED at {} ms by {}",
+                at,
+                sites.join(", ")
+            )?;
+        }
+        for account in self.negative_balances() {
+            writeln!(f, "Negative balance: {}", account)?;
+        }
+        writeln!(
+            f,
+            "Balances: {}",
+            if self.converged() {
+                "identical on every site"
+            } else {
+                "DIVERGENT"
+            }
+        )?;
+        for (site, users) in &self.balances {
+            let accounts: Vec<String> = users
+                .iter()
+                .map(|(user, cents)| format!("{} {:.2}", user, *cents as f64 / 100.0))
+                .collect();
+            writeln!(f, "  {}: {}", site, accounts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Deterministic pseudo-random generator (SplitMix64)
+pub(crate) struct SimRng(pub(crate) u64);
+
+impl SimRng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in `[min, max]`
+    pub(crate) fn range(&mut self, min: u64, max: u64) -> u64 {
+        min + self.next() % (max - min).saturating_add(1)
+    }
+
+    /// Returns true with the given probability
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && (self.next() >> 11) as f64 / ((1u64 << 53) as f64) < probability
+    }
+}
+
+/// Command queued on a virtual site until it holds the global mutex
+#[derive(Debug, Clone)]
+enum SimCommand {
+    /// Moves money between two users, or from/to [`EXTERNAL`]
+    Move {
+        from: String,
+        to: String,
+        cents: i64,
+    },
+    Snapshot,
+}
+
+/// Content of a message of the bus
+#[derive(Debug, Clone)]
+enum Payload {
+    AcquireMutex,
+    AckMutex,
+    ReleaseMutex,
+    AckReleaseMutex,
+    Transaction(TxSummary),
+    AckTransaction { sites: i64 },
+    SnapshotRequest,
+    SnapshotResponse(crate::message::SnapshotResponse),
+}
+
+/// Message of the bus
+#[derive(Debug, Clone)]
+struct Envelope {
+    from: usize,
+    to: usize,
+    initiator: usize,
+    clock: Clock,
+    payload: Payload,
+}
+
+/// Event of the simulation
+enum Event {
+    Action(Action),
+    Deliver(Envelope),
+}
+
+/// Virtual site
+struct SimSite {
+    id: String,
+    addr: std::net::SocketAddr,
+    neighbours: std::collections::BTreeSet<usize>,
+    clock: Clock,
+    waves: WaveEngine,
+    cs: CriticalSection,
+    pending: std::collections::VecDeque<SimCommand>,
+    /// True while a wave initiated by this site is in progress
+    own_wave: bool,
+    snapshot: Option<SnapshotManager>,
+    balances: std::collections::BTreeMap<String, i64>,
+    tx_log: Vec<TxSummary>,
+}
+
+impl SimSite {
+    fn balance(&self, user: &str) -> i64 {
+        self.balances.get(user).copied().unwrap_or(0)
+    }
+
+    /// Applies a transaction, unless it is already known
+    fn apply(&mut self, tx: &TxSummary) {
+        if self.tx_log.contains(tx) {
+            return;
+        }
+        if tx.from_user != EXTERNAL {
+            *self.balances.entry(tx.from_user.clone()).or_insert(0) -= tx.amount_in_cent;
+        }
+        if tx.to_user != EXTERNAL {
+            *self.balances.entry(tx.to_user.clone()).or_insert(0) += tx.amount_in_cent;
+        }
+        self.tx_log.push(tx.clone());
+    }
+
+    fn local_snapshot(&self) -> crate::message::SnapshotResponse {
+        crate::message::SnapshotResponse {
+            site_id: self.id.clone(),
+            clock: self.clock.clone(),
+            tx_log: self.tx_log.clone(),
+            partial: false,
+            ledgers: std::collections::HashMap::new(),
+        }
+    }
+}
+
+/// Returns the virtual address of a site
+fn site_addr(index: usize) -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([127, 0, 0, 1], BASE_PORT + index as u16))
+}
+
+/// Returns the index of a site from its virtual address
+fn site_index(addr: std::net::SocketAddr) -> usize {
+    (addr.port() - BASE_PORT) as usize
+}
+
+fn cents(amount: f64) -> i64 {
+    (amount * 100.0).round() as i64
+}
+
+/// Network of virtual sites driven by a scenario
+pub struct Simulation {
+    sites: Vec<SimSite>,
+    /// Links between sites, as (smallest index, largest index)
+    links: std::collections::BTreeSet<(usize, usize)>,
+    /// Links closed by a partition
+    cut: std::collections::BTreeSet<(usize, usize)>,
+    latency: (u64, u64),
+    drop_rate: f64,
+    duration: u64,
+    rng: SimRng,
+    now: u64,
+    next_seq: u64,
+    /// Events by (time, sequence number), the earliest first
+    queue: std::collections::BinaryHeap<std::cmp::Reverse<(u64, u64)>>,
+    events: std::collections::HashMap<u64, Event>,
+    /// Delivery time of the last message of each directed link, to keep links FIFO
+    link_clock: std::collections::HashMap<(usize, usize), u64>,
+    report: Report,
+}
+
+impl Simulation {
+    /// Builds the virtual network of a scenario
+    pub fn new(scenario: &Scenario) -> Result<Self, String> {
+        scenario.validate()?;
+        let index: std::collections::HashMap<&str, usize> = scenario
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.as_str(), i))
+            .collect();
+
+        let mut links = std::collections::BTreeSet::new();
+        if scenario.links.is_empty() {
+            for i in 0..scenario.nodes.len() {
+                for j in i + 1..scenario.nodes.len() {
+                    links.insert((i, j));
+                }
+            }
+        }
+        for (a, b) in &scenario.links {
+            let (i, j) = (index[a.as_str()], index[b.as_str()]);
+            links.insert((i.min(j), i.max(j)));
+        }
+
+        // Every site knows the initial balances, recorded as seed deposits
+        let seed_log: Vec<TxSummary> = scenario
+            .users
+            .iter()
+            .enumerate()
+            .map(|(i, user)| TxSummary {
+                lamport_time: i as i64 + 1,
+                source_node: crate::seed::SEED_NODE.to_string(),
+                from_user: EXTERNAL.to_string(),
+                to_user: user.name.clone(),
+                amount_in_cent: cents(user.balance),
+            })
+            .collect();
+
+        let sites = scenario
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let mut waves = WaveEngine::default();
+                waves.set_parent_addr(id.clone(), site_addr(i));
+                let mut site = SimSite {
+                    id: id.clone(),
+                    addr: site_addr(i),
+                    neighbours: links
+                        .iter()
+                        .filter_map(|&(a, b)| match (a == i, b == i) {
+                            (true, _) => Some(b),
+                            (_, true) => Some(a),
+                            _ => None,
+                        })
+                        .collect(),
+                    clock: Clock::with_strategy(ClockStrategy::Vector),
+                    waves,
+                    cs: CriticalSection::default(),
+                    pending: std::collections::VecDeque::new(),
+                    own_wave: false,
+                    snapshot: None,
+                    balances: std::collections::BTreeMap::new(),
+                    tx_log: Vec::new(),
+                };
+                for user in &scenario.users {
+                    site.balances.insert(user.name.clone(), 0);
+                }
+                for tx in &seed_log {
+                    site.apply(tx);
+                }
+                site
+            })
+            .collect();
+
+        let mut simulation = Self {
+            sites,
+            links,
+            cut: std::collections::BTreeSet::new(),
+            latency: scenario.latency,
+            drop_rate: scenario.drop_rate,
+            duration: scenario.duration,
+            rng: SimRng(scenario.seed),
+            now: 0,
+            next_seq: 0,
+            queue: std::collections::BinaryHeap::new(),
+            events: std::collections::HashMap::new(),
+            link_clock: std::collections::HashMap::new(),
+            report: Report {
+                seed: scenario.seed,
+                ..Default::default()
+            },
+        };
+        for event in &scenario.events {
+            simulation.schedule(event.at, Event::Action(event.action.clone()));
+        }
+        Ok(simulation)
+    }
+
+    fn schedule(&mut self, at: u64, event: Event) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue.push(std::cmp::Reverse((at, seq)));
+        self.events.insert(seq, event);
+    }
+
+    fn index_of(&self, node: &str) -> usize {
+        self.sites
+            .iter()
+            .position(|site| site.id == node)
+            .expect("nodes are validated with the scenario")
+    }
+
+    /// Runs the scenario until no event is left or its duration is exceeded
+    pub fn run(mut self) -> Report {
+        while let Some(std::cmp::Reverse((at, seq))) = self.queue.pop() {
+            if at > self.duration {
+                break;
+            }
+            self.now = at;
+            match self.events.remove(&seq) {
+                Some(Event::Action(action)) => self.apply_action(action),
+                Some(Event::Deliver(envelope)) => self.deliver(envelope),
+                None => {}
+            }
+            self.check_mutual_exclusion();
+        }
+        self.finish()
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        log::info!("[{} ms] {:?}", self.now, action);
+        match action {
+            Action::Deposit { node, user, amount } => {
+                let command = SimCommand::Move {
+                    from: EXTERNAL.to_string(),
+                    to: user,
+                    cents: cents(amount),
+                };
+                self.enqueue(self.index_of(&node), command);
+            }
+            Action::Withdraw { node, user, amount } => {
+                let command = SimCommand::Move {
+                    from: user,
+                    to: EXTERNAL.to_string(),
+                    cents: cents(amount),
+                };
+                self.enqueue(self.index_of(&node), command);
+            }
+            Action::Transfer {
+                node,
+                user,
+                to,
+                amount,
+            } => {
+                let command = SimCommand::Move {
+                    from: user,
+                    to,
+                    cents: cents(amount),
+                };
+                self.enqueue(self.index_of(&node), command);
+            }
+            Action::Snapshot { node } => self.enqueue(self.index_of(&node), SimCommand::Snapshot),
+            Action::Partition { groups } => {
+                let group_of = |i: usize| {
+                    groups
+                        .iter()
+                        .position(|group| group.contains(&self.sites[i].id))
+                        .unwrap_or(groups.len())
+                };
+                let closed: Vec<(usize, usize)> = self
+                    .links
+                    .iter()
+                    .filter(|&&(a, b)| group_of(a) != group_of(b) && !self.cut.contains(&(a, b)))
+                    .copied()
+                    .collect();
+                for (a, b) in closed {
+                    self.cut.insert((a, b));
+                    self.disconnect(a, b);
+                    self.disconnect(b, a);
+                }
+            }
+            Action::Heal => {
+                for (a, b) in std::mem::take(&mut self.cut) {
+                    self.reconnect(a, b);
+                    self.reconnect(b, a);
+                }
+            }
+        }
+    }
+
+    /// Site `i` sees the connection to site `j` closed
+    fn disconnect(&mut self, i: usize, j: usize) {
+        let removed_id = self.sites[j].id.clone();
+        let site = &mut self.sites[i];
+        site.neighbours.remove(&j);
+        site.cs.global_mutex_fifo.remove(&removed_id);
+        site.waves.forget(&removed_id);
+    }
+
+    /// Site `i` is connected again to site `j` and learns its mutex requests
+    fn reconnect(&mut self, i: usize, j: usize) {
+        let fifo = self.sites[j].cs.get_global_mutex_fifo().clone();
+        let site = &mut self.sites[i];
+        site.neighbours.insert(j);
+        site.cs.set_global_mutex_fifo(fifo);
+    }
+
+    /// Sends a message on the bus
+    fn send(&mut self, envelope: Envelope) {
+        self.report.sent += 1;
+        if self.rng.chance(self.drop_rate) {
+            log::debug!("[{} ms] dropped {:?}", self.now, envelope.payload);
+            self.report.dropped += 1;
+            return;
+        }
+        let latency = self.rng.range(self.latency.0, self.latency.1);
+        let link = (envelope.from, envelope.to);
+        let at = (self.now + latency).max(self.link_clock.get(&link).copied().unwrap_or(0));
+        self.link_clock.insert(link, at);
+        self.schedule(at, Event::Deliver(envelope));
+    }
+
+    /// Sends a message of a wave to the neighbours of a site, except `except`
+    fn broadcast(
+        &mut self,
+        i: usize,
+        initiator: usize,
+        clock: Clock,
+        payload: Payload,
+        except: std::net::SocketAddr,
+    ) {
+        let neighbours: Vec<usize> = self.sites[i].neighbours.iter().copied().collect();
+        for to in neighbours {
+            if site_addr(to) != except {
+                self.send(Envelope {
+                    from: i,
+                    to,
+                    initiator,
+                    clock: clock.clone(),
+                    payload: payload.clone(),
+                });
+            }
+        }
+    }
+
+    fn nb_neighbours(&self, i: usize) -> i64 {
+        self.sites[i].neighbours.len() as i64
+    }
+
+    fn tick(&mut self, i: usize) -> Clock {
+        let site = &mut self.sites[i];
+        site.clock.update_clock(&site.id, None);
+        site.clock.clone()
+    }
+
+    /// Starts a wave initiated by a site, returns true if it must be diffused
+    fn start_own_wave(&mut self, i: usize) -> bool {
+        let nb_neighbours = self.nb_neighbours(i);
+        let site = &mut self.sites[i];
+        site.own_wave = site.waves.start_wave(&site.id, site.addr, nb_neighbours);
+        site.own_wave
+    }
+
+    fn enqueue(&mut self, i: usize, command: SimCommand) {
+        self.sites[i].pending.push_back(command);
+        self.drive(i);
+    }
+
+    /// Plays the role of the control worker of a site
+    fn drive(&mut self, i: usize) {
+        loop {
+            let site = &mut self.sites[i];
+            if site.own_wave {
+                return;
+            }
+            if site.cs.in_sc {
+                match site.pending.pop_front() {
+                    Some(command) => self.execute(i, command),
+                    None => self.release(i),
+                }
+            } else if !site.pending.is_empty() && site.cs.start_request() {
+                self.acquire(i);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn acquire(&mut self, i: usize) {
+        let clock = self.tick(i);
+        let site = &mut self.sites[i];
+        site.cs.global_mutex_fifo.insert(
+            site.id.clone(),
+            MutexStamp {
+                tag: MutexTag::Request,
+                date: *clock.get_lamport(),
+            },
+        );
+        if self.start_own_wave(i) {
+            let addr = self.sites[i].addr;
+            self.broadcast(i, i, clock, Payload::AcquireMutex, addr);
+        } else {
+            let cs = &mut self.sites[i].cs;
+            cs.in_sc = true;
+            cs.waiting_sc = false;
+        }
+    }
+
+    fn release(&mut self, i: usize) {
+        let clock = self.tick(i);
+        let site = &mut self.sites[i];
+        site.cs.global_mutex_fifo.remove(&site.id);
+        site.cs.in_sc = false;
+        site.cs.waiting_sc = false;
+        if self.start_own_wave(i) {
+            let addr = self.sites[i].addr;
+            self.broadcast(i, i, clock, Payload::ReleaseMutex, addr);
+        }
+    }
+
+    fn execute(&mut self, i: usize, command: SimCommand) {
+        let clock = self.tick(i);
+        match command {
+            SimCommand::Move { from, to, cents } => {
+                let site = &mut self.sites[i];
+                if from != EXTERNAL && site.balance(&from) < cents {
+                    log::warn!(
+                        "[{} ms] {} refused: insufficient balance",
+                        self.now,
+                        site.id
+                    );
+                    self.report.rejected += 1;
+                    return;
+                }
+                let tx = TxSummary {
+                    lamport_time: *clock.get_lamport(),
+                    source_node: site.id.clone(),
+                    from_user: from,
+                    to_user: to,
+                    amount_in_cent: cents,
+                };
+                site.apply(&tx);
+                self.report.applied += 1;
+                if self.start_own_wave(i) {
+                    let addr = self.sites[i].addr;
+                    self.broadcast(i, i, clock, Payload::Transaction(tx), addr);
+                } else {
+                    self.report.confirmed += 1;
+                }
+            }
+            SimCommand::Snapshot => {
+                let mut manager = SnapshotManager::new(self.sites[i].neighbours.len() + 1);
+                manager.mode = SnapshotMode::FileMode;
+                if let Some(snapshot) = manager.push(self.sites[i].local_snapshot()) {
+                    self.snapshot_completed(i, snapshot);
+                    return;
+                }
+                self.sites[i].snapshot = Some(manager);
+                if self.start_own_wave(i) {
+                    let addr = self.sites[i].addr;
+                    self.broadcast(i, i, clock, Payload::SnapshotRequest, addr);
+                }
+            }
+        }
+    }
+
+    fn snapshot_completed(&mut self, i: usize, snapshot: crate::snapshot::GlobalSnapshot) {
+        log::info!(
+            "[{} ms] snapshot of {} completed",
+            self.now,
+            self.sites[i].id
+        );
+        self.report.snapshots.push(SnapshotOutcome {
+            site: self.sites[i].id.clone(),
+            at: self.now,
+            transactions: snapshot.all_transactions.len(),
+            consistent: snapshot.consistent,
+        });
+    }
+
+    /// Diffuses a message of a wave to the neighbours of a site, except its parent
+    fn forward(&mut self, i: usize, envelope: &Envelope) {
+        let initiator_id = self.sites[envelope.initiator].id.clone();
+        let parent = self.sites[i].waves.get_parent_addr_for_wave(initiator_id);
+        self.broadcast(
+            i,
+            envelope.initiator,
+            envelope.clock.clone(),
+            envelope.payload.clone(),
+            parent,
+        );
+    }
+
+    /// Acknowledges a message of a wave to its sender
+    fn acknowledge(&mut self, i: usize, envelope: &Envelope, payload: Payload) {
+        self.send(Envelope {
+            from: i,
+            to: envelope.from,
+            initiator: envelope.initiator,
+            clock: envelope.clock.clone(),
+            payload,
+        });
+    }
+
+    /// Sends an acknowledgement to the parent of a site in a wave
+    fn acknowledge_parent(
+        &mut self,
+        i: usize,
+        parent: std::net::SocketAddr,
+        initiator: usize,
+        payload: Payload,
+    ) {
+        let clock = self.sites[i].clock.clone();
+        self.send(Envelope {
+            from: i,
+            to: site_index(parent),
+            initiator,
+            clock,
+            payload,
+        });
+    }
+
+    /// Handles a message (blue) of a wave on a site
+    fn on_blue_message(&mut self, i: usize, envelope: &Envelope) -> BlueTransition {
+        let initiator_id = self.sites[envelope.initiator].id.clone();
+        let nb_neighbours = self.nb_neighbours(i);
+        self.sites[i]
+            .waves
+            .on_blue_message(&initiator_id, site_addr(envelope.from), nb_neighbours)
+    }
+
+    /// Handles an acknowledgement (red) of a wave on a site
+    fn on_red_message(&mut self, i: usize, envelope: &Envelope) -> RedTransition {
+        let initiator_id = self.sites[envelope.initiator].id.clone();
+        let nb_neighbours = self.nb_neighbours(i);
+        let site = &mut self.sites[i];
+        site.waves
+            .on_red_message(&initiator_id, nb_neighbours, site.addr)
+    }
+
+    /// Handles a message delivered by the bus, as `network::process_message` does
+    fn deliver(&mut self, envelope: Envelope) {
+        let i = envelope.to;
+        let link = (envelope.from.min(i), envelope.from.max(i));
+        if self.cut.contains(&link) || !self.sites[i].neighbours.contains(&envelope.from) {
+            self.report.dropped += 1;
+            return;
+        }
+        self.report.delivered += 1;
+        log::debug!(
+            "[{} ms] {} -> {}: {:?}",
+            self.now,
+            self.sites[envelope.from].id,
+            self.sites[i].id,
+            envelope.payload
+        );
+
+        let own_id = self.sites[i].id.clone();
+        let initiator_id = self.sites[envelope.initiator].id.clone();
+
+        match envelope.payload.clone() {
+            Payload::AcquireMutex => {
+                self.sites[i].cs.global_mutex_fifo.insert(
+                    initiator_id,
+                    MutexStamp {
+                        tag: MutexTag::Request,
+                        date: *envelope.clock.get_lamport(),
+                    },
+                );
+                match self.on_blue_message(i, &envelope) {
+                    BlueTransition::Diffuse => self.forward(i, &envelope),
+                    BlueTransition::Acknowledge { .. } => {
+                        self.acknowledge(i, &envelope, Payload::AckMutex)
+                    }
+                }
+            }
+            Payload::ReleaseMutex => {
+                let cs = &mut self.sites[i].cs;
+                cs.global_mutex_fifo.remove(&initiator_id);
+                cs.try_enter_sc(&own_id);
+                match self.on_blue_message(i, &envelope) {
+                    BlueTransition::Diffuse => self.forward(i, &envelope),
+                    BlueTransition::Acknowledge { .. } => {
+                        self.acknowledge(i, &envelope, Payload::AckReleaseMutex)
+                    }
+                }
+            }
+            Payload::AckMutex | Payload::AckReleaseMutex => {
+                match self.on_red_message(i, &envelope) {
+                    RedTransition::Wait => {}
+                    RedTransition::Completed => {
+                        let site = &mut self.sites[i];
+                        site.own_wave = false;
+                        site.cs.try_enter_sc(&own_id);
+                    }
+                    RedTransition::ForwardToParent(parent) => {
+                        let payload = envelope.payload.clone();
+                        self.acknowledge_parent(i, parent, envelope.initiator, payload);
+                    }
+                }
+            }
+            Payload::Transaction(tx) => {
+                if self.sites[i].waves.is_idle(&initiator_id) {
+                    self.sites[i].apply(&tx);
+                }
+                match self.on_blue_message(i, &envelope) {
+                    BlueTransition::Diffuse => self.forward(i, &envelope),
+                    BlueTransition::Acknowledge { first_visit } => {
+                        // a site reached for the first time applied the transaction
+                        let sites = i64::from(first_visit);
+                        self.acknowledge(i, &envelope, Payload::AckTransaction { sites });
+                    }
+                }
+            }
+            Payload::AckTransaction { sites } => {
+                self.sites[i]
+                    .waves
+                    .add_confirmed_sites(&initiator_id, sites);
+                let transition = self.on_red_message(i, &envelope);
+                if transition != RedTransition::Wait {
+                    let sites = self.sites[i].waves.take_confirmed_sites(&initiator_id) + 1;
+                    if let RedTransition::ForwardToParent(parent) = transition {
+                        self.acknowledge_parent(
+                            i,
+                            parent,
+                            envelope.initiator,
+                            Payload::AckTransaction { sites },
+                        );
+                    } else {
+                        log::info!(
+                            "[{} ms] transaction of {} confirmed by {} sites",
+                            self.now,
+                            own_id,
+                            sites
+                        );
+                        self.report.confirmed += 1;
+                        self.sites[i].own_wave = false;
+                    }
+                }
+            }
+            Payload::SnapshotRequest => match self.on_blue_message(i, &envelope) {
+                BlueTransition::Diffuse => {
+                    let mut manager = SnapshotManager::new(self.sites[i].neighbours.len());
+                    manager.mode = SnapshotMode::NetworkMode;
+                    manager.push(self.sites[i].local_snapshot());
+                    self.sites[i].snapshot = Some(manager);
+                    self.forward(i, &envelope);
+                }
+                BlueTransition::Acknowledge { .. } => {
+                    let local = self.sites[i].local_snapshot();
+                    self.acknowledge(i, &envelope, Payload::SnapshotResponse(local));
+                }
+            },
+            Payload::SnapshotResponse(response) => {
+                let transition = self.on_red_message(i, &envelope);
+                let global = match self.sites[i].snapshot.as_mut() {
+                    Some(manager) => manager.push(response),
+                    None => {
+                        log::error!("[{} ms] {} has no snapshot in progress", self.now, own_id);
+                        None
+                    }
+                };
+                match (transition, global) {
+                    (RedTransition::Completed, Some(global)) => {
+                        self.sites[i].snapshot = None;
+                        self.sites[i].own_wave = false;
+                        self.snapshot_completed(i, global);
+                    }
+                    (RedTransition::ForwardToParent(parent), Some(global)) => {
+                        self.sites[i].snapshot = None;
+                        let response = crate::message::SnapshotResponse {
+                            site_id: own_id,
+                            clock: self.sites[i].clock.clone(),
+                            tx_log: global.all_transactions.into_iter().collect(),
+                            partial: false,
+                            ledgers: global.ledgers,
+                        };
+                        self.acknowledge_parent(
+                            i,
+                            parent,
+                            envelope.initiator,
+                            Payload::SnapshotResponse(response),
+                        );
+                    }
+                    (RedTransition::Wait, _) => {}
+                    (_, None) => {
+                        log::error!("[{} ms] {} misses local snapshots", self.now, own_id);
+                    }
+                }
+            }
+        }
+
+        let site = &mut self.sites[i];
+        site.clock.update_clock(&site.id, Some(&envelope.clock));
+        self.drive(i);
+    }
+
+    /// Records the sites of a same partition that are in critical section together
+    fn check_mutual_exclusion(&mut self) {
+        let mut visited = vec![false; self.sites.len()];
+        while let Some(start) = visited.iter().position(|seen| !seen) {
+            let mut in_sc = Vec::new();
+            let mut stack = vec![start];
+            visited[start] = true;
+            while let Some(i) = stack.pop() {
+                if self.sites[i].cs.in_sc {
+                    in_sc.push(self.sites[i].id.clone());
+                }
+                for &j in &self.sites[i].neighbours {
+                    if !visited[j] {
+                        visited[j] = true;
+                        stack.push(j);
+                    }
+                }
+            }
+            in_sc.sort();
+            let already_reported = self
+                .report
+                .mutex_violations
+                .last()
+                .is_some_and(|(_, sites)| *sites == in_sc);
+            if in_sc.len() > 1 && !already_reported {
+                log::error!("[{} ms] mutual exclusion violated by {:?}", self.now, in_sc);
+                self.report.mutex_violations.push((self.now, in_sc));
+            }
+        }
+    }
+
+    fn finish(mut self) -> Report {
+        self.report.duration = self.now;
+        self.report.unfinished = self
+            .sites
+            .iter()
+            .map(|site| site.pending.len() + usize::from(site.own_wave))
+            .sum();
+        self.report.balances = self
+            .sites
+            .iter()
+            .map(|site| (site.id.clone(), site.balances.clone()))
+            .collect();
+        self.report
+    }
+}
+
+/// Runs the scenario of a file and returns its report
+pub fn run_file(path: &str) -> Result<Report, String> {
+    let scenario = Scenario::load(path)?;
+    Ok(Simulation::new(&scenario)?.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RING: &str = r#"
+        seed = 3
+        nodes = ["A", "B", "C", "D"]
+        links = [["A", "B"], ["B", "C"], ["C", "D"], ["D", "A"]]
+        latency = [1, 10]
+
+        [[users]]
+        name = "alice"
+        balance = 100.0
+
+        [[users]]
+        name = "bob"
+
+        [[events]]
+        at = 0
+        action = "transfer"
+        node = "A"
+        user = "alice"
+        to = "bob"
+        amount = 30.0
+
+        [[events]]
+        at = 1000
+        action = "withdraw"
+        node = "C"
+        user = "alice"
+        amount = 50.0
+
+        [[events]]
+        at = 2000
+        action = "withdraw"
+        node = "B"
+        user = "alice"
+        amount = 50.0
+
+        [[events]]
+        at = 3000
+        action = "snapshot"
+        node = "D"
+    "#;
+
+    #[test]
+    fn sequential_commands_reach_every_site() {
+        let report = Simulation::new(&Scenario::parse(RING).unwrap())
+            .unwrap()
+            .run();
+
+        assert!(report.is_safe());
+        assert!(report.converged());
+        assert_eq!(report.applied, 2);
+        assert_eq!(report.confirmed, 2);
+        assert_eq!(report.rejected, 1);
+        assert_eq!(report.unfinished, 0);
+        assert_eq!(report.balances["B"]["alice"], 2000);
+        assert_eq!(report.balances["D"]["bob"], 3000);
+        assert_eq!(report.snapshots.len(), 1);
+        assert_eq!(report.snapshots[0].site, "D");
+    }
+
+    #[test]
+    fn runs_are_deterministic() {
+        let scenario = Scenario::parse(&RING.replace("at = 1000", "at = 0")).unwrap();
+        let first = Simulation::new(&scenario).unwrap().run();
+        let second = Simulation::new(&scenario).unwrap().run();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn partitioned_sites_diverge() {
+        let report = Simulation::new(
+            &Scenario::parse(
+                r#"
+                nodes = ["A", "B"]
+
+                [[events]]
+                at = 0
+                action = "partition"
+                groups = [["A"], ["B"]]
+
+                [[events]]
+                at = 10
+                action = "deposit"
+                node = "A"
+                user = "alice"
+                amount = 5.0
+
+                [[events]]
+                at = 100
+                action = "heal"
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+        .run();
+
+        assert!(report.is_safe());
+        assert!(!report.converged());
+        assert_eq!(report.balances["A"]["alice"], 500);
+        assert_eq!(report.unfinished, 0);
+    }
+
+    #[test]
+    fn invalid_scenarios_are_rejected() {
+        assert!(Scenario::parse("nodes = []").is_err());
+        assert!(Scenario::parse("nodes = [\"A\", \"A\"]").is_err());
+        assert!(Scenario::parse("nodes = [\"A\"]\nlinks = [[\"A\", \"B\"]]").is_err());
+        assert!(Scenario::parse("nodes = [\"A\"]\nlatency = [5, 1]").is_err());
+        assert!(Scenario::parse("nodes = [\"A\"]\ndrop_rate = 1.0").is_err());
+    }
+}
@@ -0,0 +1,1877 @@
+//! Snapshot management for distributed state consistency
+//!
+//! This module implements a distributed snapshot algorithm for ensuring
+//! consistency across nodes in the distributed system. It handles snapshot
+//! creation, consistency checking, and persistence.
+//!
+//! In delta mode, the initiator sends with its request the greatest Lamport
+//! time of the transactions of each site it knows, and the other sites only
+//! return the newer transactions, which are merged onto the log of the
+//! initiator. A transaction older than the greatest known one of its site, but
+//! missing on the initiator, is not returned; a full snapshot recovers it.
+//!
+//! The responses also carry the ledger of the users outside of the default
+//! one. When the site hosts several ledgers, a snapshot saved to a file is
+//! also saved for each ledger, holding the transactions of its users only.
+//!
+//! A snapshot saved to a file holds its metadata: the label given by the
+//! operator, such as `before-upgrade`, the initiator, the sites that
+//! responded and the greatest time of each site in their clocks. Two saved
+//! snapshots, designated by file name or label, are compared with
+//! [`diff_snapshot_files`], which lists the transactions of each site found in
+//! one snapshot only.
+//!
+//! A snapshot saved to a file also holds its consistency certificate: the
+//! vector clock of each local snapshot, the transactions it listed, and the
+//! cut the local snapshots were trimmed to when they were not consistent.
+//! [`verify`] recomputes the consistency, the cut, the union of the
+//! transactions and the missing transactions of each site from the
+//! certificate, and compares them with the snapshot, for audits.
+
+/// Summary of a transaction for snapshot purposes
+#[derive(Clone, serde::Serialize, serde::Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct TxSummary {
+    /// Lamport timestamp of the transaction
+    pub lamport_time: i64,
+    /// ID of the node that created the transaction
+    pub source_node: String,
+    /// Account debited by the transaction, a user or [`crate::db::CASH_IN`]
+    pub from_user: String,
+    /// Account credited by the transaction, a user or [`crate::db::CASH_OUT`]
+    pub to_user: String,
+    /// Transaction amount
+    pub amount_in_cent: i64,
+}
+
+#[cfg(feature = "server")]
+/// Snapshot mode
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum SnapshotMode {
+    /// When all snapshots are received, we can create a global snapshot and save the file
+    FileMode,
+    /// When all snapshot are received, we can create a global snapshot and send it to the network
+    NetworkMode,
+    /// When all snapshot are received, we can create a global snapshot and apply it to the local state
+    SyncMode,
+}
+
+#[cfg(feature = "server")]
+impl From<&crate::db::Transaction> for TxSummary {
+    fn from(tx: &crate::db::Transaction) -> Self {
+        Self {
+            lamport_time: tx.lamport_time,
+            source_node: tx.source_node.clone(),
+            from_user: crate::db::debit_account(&tx.from_user).to_string(),
+            to_user: crate::db::credit_account(&tx.to_user).to_string(),
+            amount_in_cent: (tx.amount * 100.0) as i64,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Local snapshot of a node's state
+#[derive(Clone)]
+pub struct LocalSnapshot {
+    /// ID of the node taking the snapshot
+    pub site_id: String,
+    /// Vector clock state at the time of the snapshot
+    pub vector_clock: std::collections::HashMap<String, i64>,
+    /// Set of transactions known to this node
+    pub tx_log: std::collections::HashSet<TxSummary>,
+    /// True if the log only holds the transactions unknown to the initiator
+    pub partial: bool,
+}
+
+#[cfg(feature = "server")]
+/// Metadata of a snapshot, saved along with its transactions
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotMetadata {
+    /// Label given by the operator when taking the snapshot
+    pub label: Option<String>,
+    /// Site that initiated the snapshot
+    pub initiator: String,
+    /// Sites whose local snapshot was collected, sorted by ID
+    pub sites: Vec<String>,
+    /// Greatest time of each site in the clocks of the local snapshots
+    pub clock: std::collections::BTreeMap<String, i64>,
+}
+
+#[cfg(feature = "server")]
+/// Global snapshot combining all local snapshots
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct GlobalSnapshot {
+    /// Union of all transactions across nodes
+    pub all_transactions: std::collections::HashSet<TxSummary>,
+    /// Map of missing transactions per node
+    pub missing: std::collections::HashMap<String, std::collections::HashSet<TxSummary>>,
+    /// False if the local snapshots had to be back-tracked to become consistent
+    pub consistent: bool,
+    /// Users outside of the default ledger, with their ledger
+    #[serde(default)]
+    pub ledgers: std::collections::HashMap<String, String>,
+    /// Label, initiator, sites and clocks of the snapshot, empty for the
+    /// files written before they were saved
+    #[serde(default)]
+    pub metadata: SnapshotMetadata,
+    /// Local snapshots the snapshot was built from, to verify it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<ConsistencyCertificate>,
+}
+
+#[cfg(feature = "server")]
+/// Local snapshot of a site as received by the initiator
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CertifiedSite {
+    /// ID of the site
+    pub site_id: String,
+    /// Vector clock of the site when it took its local snapshot
+    pub clock: std::collections::BTreeMap<String, i64>,
+    /// True if the site only sent the transactions unknown to the initiator
+    pub partial: bool,
+    /// Transactions listed by the site, as (source site, Lamport time)
+    pub transactions: Vec<(String, i64)>,
+}
+
+#[cfg(feature = "server")]
+/// Proof of the consistency of a global snapshot
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ConsistencyCertificate {
+    /// Local snapshots received, sorted by site ID
+    pub sites: Vec<CertifiedSite>,
+    /// Greatest time of each site kept in the snapshot, None if the local
+    /// snapshots were consistent as received
+    pub cut: Option<std::collections::BTreeMap<String, i64>>,
+}
+
+#[cfg(feature = "server")]
+impl ConsistencyCertificate {
+    /// Records the local snapshots a global snapshot is built from
+    fn new(snaps: &[LocalSnapshot], cut: Option<std::collections::HashMap<String, i64>>) -> Self {
+        let mut sites: Vec<CertifiedSite> = snaps
+            .iter()
+            .map(|snap| {
+                let mut transactions: Vec<(String, i64)> = snap
+                    .tx_log
+                    .iter()
+                    .map(|tx| (tx.source_node.clone(), tx.lamport_time))
+                    .collect();
+                transactions.sort();
+                CertifiedSite {
+                    site_id: snap.site_id.clone(),
+                    clock: snap.vector_clock.clone().into_iter().collect(),
+                    partial: snap.partial,
+                    transactions,
+                }
+            })
+            .collect();
+        sites.sort_by(|a, b| a.site_id.cmp(&b.site_id));
+        Self {
+            sites,
+            cut: cut.map(|cut| cut.into_iter().collect()),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl GlobalSnapshot {
+    /// Checks if a set of local snapshots is consistent
+    ///
+    /// A set of snapshots is consistent if for any pair of nodes i and j,
+    /// the vector clock value of j in i's snapshot is not greater than
+    /// the vector clock value of j in j's own snapshot.
+    pub fn is_consistent(snaps: &[LocalSnapshot]) -> bool {
+        for si in snaps {
+            for sj in snaps {
+                if let (Some(&cij), Some(&cjj)) = (
+                    si.vector_clock.get(&sj.site_id),
+                    sj.vector_clock.get(&sj.site_id),
+                ) && cij > cjj
+                {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns the ledgers of the users of the snapshot, the default one first
+    ///
+    /// Only the default ledger is returned when every user belongs to it.
+    pub fn ledger_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.ledgers.values().cloned().collect();
+        names.sort();
+        names.dedup();
+        names.retain(|name| name != crate::ledger::DEFAULT_LEDGER);
+        names.insert(0, crate::ledger::DEFAULT_LEDGER.to_string());
+        names
+    }
+
+    /// Returns the part of the snapshot holding the transactions of a ledger
+    pub fn for_ledger(&self, ledger: &str) -> GlobalSnapshot {
+        let in_ledger = |tx: &TxSummary| {
+            [&tx.from_user, &tx.to_user].into_iter().any(|account| {
+                let user = crate::db::user_of_account(account);
+                user != crate::db::NULL
+                    && self
+                        .ledgers
+                        .get(user)
+                        .map_or(crate::ledger::DEFAULT_LEDGER, String::as_str)
+                        == ledger
+            })
+        };
+        GlobalSnapshot {
+            all_transactions: self
+                .all_transactions
+                .iter()
+                .filter(|tx| in_ledger(tx))
+                .cloned()
+                .collect(),
+            missing: self
+                .missing
+                .iter()
+                .map(|(site, txs)| {
+                    let txs: std::collections::HashSet<TxSummary> =
+                        txs.iter().filter(|tx| in_ledger(tx)).cloned().collect();
+                    (site.clone(), txs)
+                })
+                .filter(|(_, txs)| !txs.is_empty())
+                .collect(),
+            consistent: self.consistent,
+            ledgers: self
+                .ledgers
+                .iter()
+                .filter(|(_, user_ledger)| *user_ledger == ledger)
+                .map(|(user, user_ledger)| (user.clone(), user_ledger.clone()))
+                .collect(),
+            metadata: self.metadata.clone(),
+            // the certificate covers the whole snapshot, kept in its main file only
+            certificate: None,
+        }
+    }
+
+    /// Compares the transactions of two snapshots, site by site
+    pub fn diff(&self, other: &GlobalSnapshot) -> SnapshotDiff {
+        let mut diffs: std::collections::BTreeMap<String, SiteDiff> =
+            std::collections::BTreeMap::new();
+        for tx in self.all_transactions.difference(&other.all_transactions) {
+            diffs
+                .entry(tx.source_node.clone())
+                .or_insert_with(|| SiteDiff::new(&tx.source_node))
+                .only_in_a
+                .push(tx.clone());
+        }
+        for tx in other.all_transactions.difference(&self.all_transactions) {
+            diffs
+                .entry(tx.source_node.clone())
+                .or_insert_with(|| SiteDiff::new(&tx.source_node))
+                .only_in_b
+                .push(tx.clone());
+        }
+        let mut sites: Vec<SiteDiff> = diffs.into_values().collect();
+        for site in &mut sites {
+            site.only_in_a.sort_by_key(|tx| tx.lamport_time);
+            site.only_in_b.sort_by_key(|tx| tx.lamport_time);
+        }
+        SnapshotDiff {
+            a: String::new(),
+            b: String::new(),
+            common: self
+                .all_transactions
+                .intersection(&other.all_transactions)
+                .count(),
+            sites,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Computes the minimum vector clock (vmin) of a set of local snapshots, the
+/// smallest time of each site among their clocks
+fn min_cut(snaps: &[LocalSnapshot]) -> std::collections::HashMap<String, i64> {
+    let mut vmin: std::collections::HashMap<String, i64> = std::collections::HashMap::new();
+    for snap in snaps {
+        for (site, &val) in &snap.vector_clock {
+            vmin.entry(site.clone())
+                .and_modify(|m| *m = (*m).min(val))
+                .or_insert(val);
+        }
+    }
+    vmin
+}
+
+#[cfg(feature = "server")]
+/// Manages the snapshot collection process
+pub struct SnapshotManager {
+    /// Number of snapshots expected to be collected
+    pub expected: usize,
+    /// Vector of received local snapshots
+    pub received: Vec<LocalSnapshot>,
+    /// Path to the last snapshot saved
+    pub path: Option<std::path::PathBuf>,
+    /// Snapshot mode
+    pub mode: SnapshotMode,
+    /// Sites expected to respond, known when the snapshot starts
+    pub awaited: Vec<String>,
+    /// Ledgers of the users, merged from the received responses
+    pub ledgers: std::collections::HashMap<String, String>,
+    /// Site that started the snapshot being collected
+    pub initiator: String,
+    /// Label of the snapshot being collected, if any
+    pub label: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl SnapshotManager {
+    /// Creates a new snapshot manager expecting the given number of snapshots
+    pub fn new(expected: usize) -> Self {
+        Self {
+            expected,
+            received: Vec::new(),
+            path: None,
+            mode: SnapshotMode::FileMode,
+            awaited: Vec::new(),
+            ledgers: std::collections::HashMap::new(),
+            initiator: String::new(),
+            label: None,
+        }
+    }
+
+    /// Returns the progress of the snapshot being collected
+    ///
+    /// The sites that responded without being awaited, such as the children
+    /// of a wave initiated elsewhere, are listed as well.
+    pub fn progress(&self) -> SnapshotProgress {
+        let responded = |site: &str| self.received.iter().any(|s| s.site_id == site);
+        let mut sites: Vec<(String, bool)> = self
+            .awaited
+            .iter()
+            .map(|site| (site.clone(), responded(site)))
+            .collect();
+        for snap in &self.received {
+            if !self.awaited.contains(&snap.site_id) {
+                sites.push((snap.site_id.clone(), true));
+            }
+        }
+        SnapshotProgress {
+            expected: self.expected,
+            received: self.received.len(),
+            sites,
+        }
+    }
+
+    /// Adds a snapshot response to the collection
+    ///
+    /// Returns a global snapshot if all expected snapshots have been received
+    /// and they are consistent. If the snapshots are inconsistent, it will
+    /// attempt to find a consistent subset by backtracking to the minimum
+    /// vector clock values.
+    /// all_received is defined by the state of our wave diffusion protocol
+    pub fn push(&mut self, resp: crate::message::SnapshotResponse) -> Option<GlobalSnapshot> {
+        log::debug!("Adding snapshot {} in the manager.", resp.site_id);
+        SNAPSHOT_PROGRESS.notify_waiters();
+        self.received.push(LocalSnapshot {
+            site_id: resp.site_id.clone(),
+            vector_clock: resp.clock.get_vector_clock_map().clone(),
+            tx_log: resp.tx_log.into_iter().collect(),
+            partial: resp.partial,
+        });
+        self.ledgers.extend(resp.ledgers);
+
+        if self.received.len() < self.expected {
+            log::debug!("{}/{} sites received.", self.received.len(), self.expected);
+            return None;
+        }
+
+        log::debug!("All local snapshots received, processing snapshot.");
+
+        // In Sync and Network modes we simply aggregate all received
+        // transactions without enforcing snapshot consistency. This prevents
+        // dropping valid transactions when a node joins and requests a global
+        // snapshot or when an intermediate node aggregates snapshots from its
+        // children
+        if matches!(
+            self.mode,
+            SnapshotMode::SyncMode | SnapshotMode::NetworkMode
+        ) {
+            return Some(self.build_snapshot(&self.received));
+        }
+
+        if GlobalSnapshot::is_consistent(&self.received) {
+            let mut gs = self.build_snapshot(&self.received);
+            gs.certificate = Some(ConsistencyCertificate::new(&self.received, None));
+            return Some(gs);
+        }
+
+        // Back-track to the last consistent snapshot by computing the minimum vector clock
+        // V_j = min_i Ci[j], where Ci[j] is the clock value for site j in snapshot i.
+        // This ensures that we only consider transactions that are consistent across all snapshots.
+        let vmin = min_cut(&self.received);
+
+        // Create a new list of snapshots with trimmed vector clocks and transaction logs.
+        let mut trimmed: Vec<LocalSnapshot> = Vec::new();
+        for mut s in self.received.clone() {
+            // Limit the vector clock for the current site to the minimum value in vmin.
+            let lim = *vmin.get(&s.site_id).unwrap_or(&0);
+            s.vector_clock.insert(s.site_id.clone(), lim);
+
+            // Filter the transaction log to only include transactions that are consistent
+            // with the minimum vector clock for their source node.
+            let tx_keep: std::collections::HashSet<_> = s
+                .tx_log
+                .into_iter()
+                .filter(|t| t.lamport_time <= *vmin.get(&t.source_node).unwrap_or(&0))
+                .collect();
+            s.tx_log = tx_keep;
+
+            trimmed.push(s);
+        }
+
+        let mut gs = self.build_snapshot(&trimmed);
+        gs.consistent = false;
+        gs.certificate = Some(ConsistencyCertificate::new(&self.received, Some(vmin)));
+        Some(gs)
+    }
+
+    /// Builds a global snapshot from a set of local snapshots
+    ///
+    /// Computes the union of all transactions and identifies missing
+    /// transactions for each node whose whole log was received.
+    fn build_snapshot(&self, snaps: &[LocalSnapshot]) -> GlobalSnapshot {
+        let mut union: std::collections::HashSet<TxSummary> = std::collections::HashSet::new();
+        for s in snaps {
+            log::info!(
+                "Adding transactions from site {}, transaction : {:?}",
+                s.site_id,
+                s.tx_log
+            );
+            union.extend(s.tx_log.iter().cloned());
+        }
+
+        let mut miss: std::collections::HashMap<String, std::collections::HashSet<TxSummary>> =
+            std::collections::HashMap::new();
+        for s in snaps.iter().filter(|s| !s.partial) {
+            let diff: std::collections::HashSet<_> = union.difference(&s.tx_log).cloned().collect();
+            if !diff.is_empty() {
+                miss.insert(s.site_id.clone(), diff);
+            }
+        }
+
+        let mut sites: Vec<String> = snaps.iter().map(|s| s.site_id.clone()).collect();
+        sites.sort();
+        let mut clock = std::collections::BTreeMap::new();
+        for (site, &time) in snaps.iter().flat_map(|s| s.vector_clock.iter()) {
+            clock
+                .entry(site.clone())
+                .and_modify(|max: &mut i64| *max = (*max).max(time))
+                .or_insert(time);
+        }
+        GlobalSnapshot {
+            all_transactions: union,
+            missing: miss,
+            consistent: true,
+            ledgers: self.ledgers.clone(),
+            metadata: SnapshotMetadata {
+                label: self.label.clone(),
+                initiator: self.initiator.clone(),
+                sites,
+                clock,
+            },
+            certificate: None,
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// True if the snapshots initiated by this site are delta snapshots, set at startup
+static DELTA_SNAPSHOTS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(feature = "server")]
+/// Sets whether the snapshots initiated by this site are delta snapshots
+pub fn set_delta(enabled: bool) {
+    DELTA_SNAPSHOTS.store(enabled, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Returns the greatest Lamport time of the transactions of each site in a log
+pub fn known_lamports(tx_log: &[TxSummary]) -> std::collections::HashMap<String, i64> {
+    let mut known = std::collections::HashMap::new();
+    for tx in tx_log {
+        known
+            .entry(tx.source_node.clone())
+            .and_modify(|max: &mut i64| *max = (*max).max(tx.lamport_time))
+            .or_insert(tx.lamport_time);
+    }
+    known
+}
+
+#[cfg(feature = "server")]
+/// Keeps the transactions of a log newer than the ones known for their site
+pub fn newer_than(
+    tx_log: Vec<TxSummary>,
+    known: &std::collections::HashMap<String, i64>,
+) -> Vec<TxSummary> {
+    tx_log
+        .into_iter()
+        .filter(|tx| {
+            known
+                .get(&tx.source_node)
+                .is_none_or(|max| tx.lamport_time > *max)
+        })
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Initiates a new snapshot process
+///
+/// Collects the local transaction log and sends snapshot requests to all peers.
+/// `known` is the payload of the delta snapshot request being diffused, if
+/// any, and `label` the label of the snapshot saved to a file. Returns the
+/// payload of the request to send when this site initiates a delta snapshot.
+pub async fn start_snapshot(
+    mode: SnapshotMode,
+    known: Option<&std::collections::HashMap<String, i64>>,
+    label: Option<String>,
+) -> Result<Option<crate::message::SnapshotRequest>, Box<dyn std::error::Error>> {
+    let local_txs = crate::db::get_local_transaction_log()?;
+    let mut summaries: Vec<TxSummary> = local_txs.iter().map(|t| t.into()).collect();
+
+    let request = if mode != SnapshotMode::NetworkMode
+        && DELTA_SNAPSHOTS.load(std::sync::atomic::Ordering::Relaxed)
+    {
+        Some(crate::message::SnapshotRequest {
+            known: known_lamports(&summaries),
+        })
+    } else {
+        None
+    };
+    // the initiator keeps its whole log, onto which the deltas are merged
+    if let Some(known) = known {
+        summaries = newer_than(summaries, known);
+    }
+
+    let (site_id, clock, expected, awaited) = {
+        let neighbours: Vec<String> = {
+            let peers = crate::state::PEERS.read().await;
+            peers
+                .get_connected_nei_addr()
+                .iter()
+                .map(|addr| {
+                    peers
+                        .site_id_of(addr)
+                        .map_or_else(|| addr.to_string(), str::to_string)
+                })
+                .collect()
+        };
+        let nb_neighbours = neighbours.len();
+        // We expect a snapshot from all connected peers
+        // + 1 for self
+        let expected_peers = match mode {
+            SnapshotMode::NetworkMode => {
+                // In NetworkMode, we expect a snapshot from all connected peers except our parent
+                nb_neighbours
+            }
+            _ => {
+                // Default case: expect snapshots from all connected peers including ourselves
+                nb_neighbours + 1
+            }
+        };
+        let site_id = crate::state::local_site().await.0;
+        // in network mode, the parent of this site in the wave does not respond
+        let mut awaited = vec![site_id.clone()];
+        if mode != SnapshotMode::NetworkMode {
+            awaited.extend(neighbours);
+        }
+        (
+            site_id,
+            crate::state::get_clock().await,
+            expected_peers,
+            awaited,
+        )
+    };
+
+    {
+        let mut mgr = LOCAL_SNAPSHOT_MANAGER.lock().await;
+        mgr.expected = expected;
+        mgr.received.clear();
+        mgr.ledgers.clear();
+        mgr.mode = mode.clone();
+        mgr.awaited = awaited;
+        mgr.initiator = site_id.clone();
+        mgr.label = label;
+        if let Some(gs) = mgr.push(crate::message::SnapshotResponse {
+            site_id: site_id.clone(),
+            clock: clock.clone(),
+            tx_log: summaries.clone(),
+            partial: known.is_some(),
+            ledgers: crate::ledger::get_user_ledgers()?,
+        }) {
+            if mode.clone() == SnapshotMode::FileMode {
+                log::info!(
+                    "Global snapshot ready to be saved at start, hold per site : {:#?}",
+                    gs.missing
+                );
+                mgr.path = crate::snapshot::persist(&gs, site_id.clone())
+                    .await
+                    .unwrap()
+                    .parse()
+                    .ok();
+            } else if mode.clone() == SnapshotMode::SyncMode {
+                log::info!("No other site, synchronization done");
+            } else {
+                log::error!(
+                    "Start snapshot is not supposed to be called when there is no neighbours with network mode"
+                );
+            }
+        }
+    }
+
+    Ok(request)
+}
+
+#[cfg(feature = "server")]
+/// Directory of the snapshots of the site, `snapshots/{site_id}`, set at startup
+static SNAPSHOT_DIR: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+#[cfg(feature = "server")]
+/// Number of snapshot files kept on disk, 0 to keep them all, set at startup
+static SNAPSHOT_RETENTION: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(feature = "server")]
+/// Sets the directory of the snapshots of the site, once its ID is known
+pub fn init_snapshot_dir(site_id: &str) {
+    let _ = SNAPSHOT_DIR.set(std::path::Path::new("snapshots").join(site_id));
+}
+
+#[cfg(feature = "server")]
+/// Returns the directory holding the snapshots of the site
+pub fn snapshot_dir() -> std::path::PathBuf {
+    SNAPSHOT_DIR
+        .get()
+        .cloned()
+        .unwrap_or_else(|| std::path::PathBuf::from("snapshots"))
+}
+
+#[cfg(feature = "server")]
+/// Sets the number of snapshot files kept on disk, 0 to keep them all
+pub fn set_retention(keep: usize) {
+    SNAPSHOT_RETENTION.store(keep, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Persists a global snapshot to disk
+///
+/// Saves the snapshot as a gzipped JSON file with a timestamp in the filename,
+/// in the snapshot directory of the site, then removes the snapshots beyond
+/// the retention limit. When the users belong to several ledgers, the part of
+/// the snapshot of each ledger is also saved, in a file whose name holds the
+/// ledger after an `@`.
+pub async fn persist(snapshot: &GlobalSnapshot, site_id: String) -> std::io::Result<String> {
+    let ts = chrono::Local::now().format("%Y%m%d_%H%M%S");
+    let filename = format!("snapshot_{}_{}.json.gz", site_id, ts);
+
+    let dir = snapshot_dir();
+    std::fs::create_dir_all(&dir)?;
+    write_snapshot(&dir.join(&filename), snapshot)?;
+    println!("📸 Snapshot completed successfully at {}", filename);
+
+    let ledgers = snapshot.ledger_names();
+    if ledgers.len() > 1 {
+        for ledger in ledgers {
+            let ledger_filename = format!("snapshot_{}@{}_{}.json.gz", site_id, ledger, ts);
+            write_snapshot(&dir.join(&ledger_filename), &snapshot.for_ledger(&ledger))?;
+        }
+    }
+
+    if let Err(e) = prune_snapshots() {
+        log::warn!("Cannot remove the old snapshots: {}", e);
+    }
+
+    SAVED_SNAPSHOTS.send_replace(Some(filename.clone()));
+    Ok(filename)
+}
+
+#[cfg(feature = "server")]
+/// Takes a snapshot of the network saved to a file, and waits until it is saved
+///
+/// The snapshot goes through the global mutex like the other critical
+/// commands. Returns the name of the file, or an error if it was not saved
+/// within the timeout.
+pub async fn snapshot_and_wait(
+    label: Option<String>,
+    timeout: std::time::Duration,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut saved = SAVED_SNAPSHOTS.subscribe();
+    crate::control::enqueue_critical(crate::control::CriticalCommands::FileSnapshot { label })
+        .await?;
+    match tokio::time::timeout(timeout, saved.changed()).await {
+        Ok(Ok(())) => saved
+            .borrow()
+            .clone()
+            .ok_or_else(|| "The snapshot was not saved".into()),
+        _ => Err(format!("The snapshot was not saved within {:?}", timeout).into()),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Saves a snapshot of the local site only to a file, without the global
+/// mutex nor the other sites
+///
+/// Returns the name of the file.
+pub async fn persist_local(label: Option<String>) -> Result<String, Box<dyn std::error::Error>> {
+    let site_id = crate::state::local_site().await.0;
+    let clock = crate::state::get_clock().await;
+    let snapshot = local_only(&site_id, &clock, label)?;
+    Ok(persist(&snapshot, site_id).await?)
+}
+
+#[cfg(feature = "server")]
+/// Builds a snapshot holding the transactions known by the local site only
+pub fn local_only(
+    site_id: &str,
+    clock: &crate::clock::Clock,
+    label: Option<String>,
+) -> Result<GlobalSnapshot, Box<dyn std::error::Error>> {
+    let tx_log = crate::db::get_local_transaction_log()?
+        .iter()
+        .map(TxSummary::from)
+        .collect();
+
+    let mut manager = SnapshotManager::new(1);
+    manager.initiator = site_id.to_string();
+    manager.label = label;
+    manager.ledgers = crate::ledger::get_user_ledgers()?;
+    let local = [LocalSnapshot {
+        site_id: site_id.to_string(),
+        vector_clock: clock.get_vector_clock_map().clone(),
+        tx_log,
+        partial: false,
+    }];
+    let mut snapshot = manager.build_snapshot(&local);
+    snapshot.certificate = Some(ConsistencyCertificate::new(&local, None));
+    Ok(snapshot)
+}
+
+#[cfg(feature = "server")]
+/// Writes a snapshot to a file of the operator, gzipped if its name ends with `.gz`
+pub fn export_snapshot(path: &std::path::Path, snapshot: &GlobalSnapshot) -> std::io::Result<()> {
+    if path.to_string_lossy().ends_with(".gz") {
+        return write_snapshot(path, snapshot);
+    }
+    use std::io::Write;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(&mut file, snapshot)?;
+    file.flush()
+}
+
+#[cfg(feature = "server")]
+/// Writes a global snapshot as gzipped JSON
+///
+/// The JSON is streamed through the encoder to the file, without building it
+/// in memory first.
+fn write_snapshot(path: &std::path::Path, snapshot: &GlobalSnapshot) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    serde_json::to_writer_pretty(&mut encoder, snapshot)?;
+    encoder.finish()?.flush()
+}
+
+#[cfg(feature = "server")]
+/// Reads the JSON content of a snapshot file, gzipped or not
+fn read_snapshot_path(path: &std::path::Path) -> std::io::Result<String> {
+    use std::io::Read;
+
+    if !path.to_string_lossy().ends_with(".gz") {
+        return std::fs::read_to_string(path);
+    }
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut json = String::new();
+    flate2::read::GzDecoder::new(file).read_to_string(&mut json)?;
+    Ok(json)
+}
+
+#[cfg(feature = "server")]
+/// Returns the snapshot files to remove to keep only the `keep` most recent ones
+///
+/// The whole snapshots and the snapshots of each ledger are kept separately.
+/// Nothing is removed when `keep` is 0.
+fn expired_snapshots(file_names: Vec<String>, keep: usize) -> Vec<String> {
+    if keep == 0 {
+        return Vec::new();
+    }
+    let mut by_ledger: std::collections::BTreeMap<Option<String>, Vec<String>> =
+        std::collections::BTreeMap::new();
+    for file_name in file_names {
+        by_ledger
+            .entry(parse_snapshot_ledger(&file_name))
+            .or_default()
+            .push(file_name);
+    }
+    by_ledger
+        .into_values()
+        .flat_map(|mut file_names| {
+            file_names.sort_by(|a, b| {
+                parse_snapshot_timestamp(b)
+                    .cmp(&parse_snapshot_timestamp(a))
+                    .then_with(|| a.cmp(b))
+            });
+            file_names.into_iter().skip(keep)
+        })
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the directory entries of the snapshot files of the site
+///
+/// No file is returned when no snapshot was taken yet.
+fn snapshot_entries() -> std::io::Result<Vec<std::fs::DirEntry>> {
+    let entries = match std::fs::read_dir(snapshot_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if is_snapshot_file_name(&entry.file_name().to_string_lossy()) {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(feature = "server")]
+/// Removes the snapshot files beyond the retention limit, oldest first
+pub fn prune_snapshots() -> std::io::Result<()> {
+    let keep = SNAPSHOT_RETENTION.load(std::sync::atomic::Ordering::Relaxed);
+    let file_names = snapshot_entries()?
+        .iter()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    for file_name in expired_snapshots(file_names, keep) {
+        log::info!("Removing the expired snapshot {}", file_name);
+        std::fs::remove_file(snapshot_dir().join(file_name))?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the time elapsed since the last snapshot file was written
+pub fn last_snapshot_age() -> std::io::Result<Option<std::time::Duration>> {
+    let mut last = None;
+    for entry in snapshot_entries()? {
+        let modified = entry.metadata()?.modified()?;
+        last = last.max(Some(modified));
+    }
+    Ok(last.map(|modified| modified.elapsed().unwrap_or_default()))
+}
+
+#[cfg(feature = "server")]
+/// Starts the task taking a file snapshot every `interval`
+///
+/// The snapshots go through the critical section like the ones asked from
+/// the CLI or the Info page.
+pub fn snapshot_scheduler(interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // the first tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            log::info!("Taking a scheduled snapshot");
+            if let Err(e) =
+                crate::control::enqueue_critical(crate::control::CriticalCommands::FileSnapshot {
+                    label: None,
+                })
+                .await
+            {
+                log::warn!("Cannot schedule the snapshot: {}", e);
+            }
+        }
+    });
+}
+
+/// Progress of the snapshot collected by the site
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotProgress {
+    /// Number of responses expected, 0 before the first snapshot
+    pub expected: usize,
+    /// Number of responses received, this site included
+    pub received: usize,
+    /// Sites expected to respond, with true once their response is received
+    pub sites: Vec<(String, bool)>,
+}
+
+impl SnapshotProgress {
+    /// Returns true once every expected response is received
+    pub fn is_complete(&self) -> bool {
+        self.received >= self.expected
+    }
+}
+
+/// Summary of a snapshot file persisted on disk
+///
+/// Built from the file name and the JSON content, used by the web interface
+/// to list previous snapshots without sending the whole file.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotFileInfo {
+    /// Name of the file in the snapshot directory of the site
+    pub file_name: String,
+    /// Date of the snapshot parsed from the file name, if any
+    pub timestamp: Option<String>,
+    /// Size of the file in bytes
+    pub size_bytes: u64,
+    /// Number of transactions in the global snapshot
+    pub nb_transactions: usize,
+    /// Number of missing transactions per site
+    pub missing_per_site: Vec<(String, usize)>,
+    /// Whether the snapshot was consistent without back-tracking
+    ///
+    /// None for files written before this information was persisted
+    pub consistent: Option<bool>,
+    /// Ledger whose transactions the file holds, None for a whole snapshot
+    pub ledger: Option<String>,
+    /// Label given to the snapshot, if any
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Site that initiated the snapshot, None for files written before it
+    /// was saved
+    #[serde(default)]
+    pub initiator: Option<String>,
+    /// Sites whose local snapshot was collected
+    #[serde(default)]
+    pub sites: Vec<String>,
+}
+
+/// Transactions of a site found in one of two compared snapshots only
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SiteDiff {
+    /// Site that created the transactions
+    pub site: String,
+    /// Transactions of the site in the first snapshot only
+    pub only_in_a: Vec<TxSummary>,
+    /// Transactions of the site in the second snapshot only
+    pub only_in_b: Vec<TxSummary>,
+}
+
+impl SiteDiff {
+    /// Creates the comparison of a site, without difference yet
+    pub fn new(site: &str) -> Self {
+        Self {
+            site: site.to_string(),
+            only_in_a: Vec::new(),
+            only_in_b: Vec::new(),
+        }
+    }
+}
+
+/// Comparison of the transactions of two snapshots
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotDiff {
+    /// File of the first snapshot
+    pub a: String,
+    /// File of the second snapshot
+    pub b: String,
+    /// Number of transactions in both snapshots
+    pub common: usize,
+    /// Sites whose transactions differ, sorted by ID
+    pub sites: Vec<SiteDiff>,
+}
+
+#[cfg(feature = "server")]
+/// Checks that a file name designates a snapshot written by `persist`
+///
+/// The snapshots written before they were compressed are plain `.json` files.
+/// Used to refuse any path outside of the snapshot directory.
+pub fn is_snapshot_file_name(file_name: &str) -> bool {
+    file_name.starts_with("snapshot_")
+        && (file_name.ends_with(".json") || file_name.ends_with(".json.gz"))
+        && !file_name.contains('/')
+        && !file_name.contains('\\')
+        && !file_name.contains("..")
+}
+
+#[cfg(feature = "server")]
+/// Extracts the date from a snapshot file name
+///
+/// File names follow `snapshot_{site_id}_{%Y%m%d}_{%H%M%S}.json.gz`, the site
+/// id may itself contain underscores so the date is read from the end.
+pub fn parse_snapshot_timestamp(file_name: &str) -> Option<String> {
+    let name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+    let stem = name.strip_prefix("snapshot_")?.strip_suffix(".json")?;
+    let mut parts = stem.rsplitn(3, '_');
+    let time = parts.next()?;
+    let date = parts.next()?;
+    parts.next()?;
+    let parsed =
+        chrono::NaiveDateTime::parse_from_str(&format!("{date}_{time}"), "%Y%m%d_%H%M%S").ok()?;
+    Some(parsed.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+#[cfg(feature = "server")]
+/// Extracts the ledger from the name of the snapshot file of a ledger
+///
+/// Such files are named `snapshot_{site_id}@{ledger}_{%Y%m%d}_{%H%M%S}.json.gz`,
+/// the whole snapshots have no ledger.
+pub fn parse_snapshot_ledger(file_name: &str) -> Option<String> {
+    let name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+    let stem = name.strip_prefix("snapshot_")?.strip_suffix(".json")?;
+    let site = stem.rsplitn(3, '_').nth(2)?;
+    site.rsplit_once('@').map(|(_, ledger)| ledger.to_string())
+}
+
+#[cfg(feature = "server")]
+/// Builds the summary of a snapshot file from its JSON content
+pub fn summarize_snapshot(file_name: &str, size_bytes: u64, json: &str) -> SnapshotFileInfo {
+    let value: serde_json::Value = serde_json::from_str(json).unwrap_or_default();
+
+    let nb_transactions = value["all_transactions"]
+        .as_array()
+        .map(|txs| txs.len())
+        .unwrap_or(0);
+
+    let mut missing_per_site: Vec<(String, usize)> = value["missing"]
+        .as_object()
+        .map(|missing| {
+            missing
+                .iter()
+                .map(|(site, txs)| (site.clone(), txs.as_array().map(|t| t.len()).unwrap_or(0)))
+                .collect()
+        })
+        .unwrap_or_default();
+    missing_per_site.sort();
+
+    let metadata = &value["metadata"];
+    let mut sites: Vec<String> = metadata["sites"]
+        .as_array()
+        .map(|sites| {
+            sites
+                .iter()
+                .filter_map(|site| site.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    sites.sort();
+
+    SnapshotFileInfo {
+        file_name: file_name.to_string(),
+        timestamp: parse_snapshot_timestamp(file_name),
+        ledger: parse_snapshot_ledger(file_name),
+        size_bytes,
+        nb_transactions,
+        missing_per_site,
+        consistent: value["consistent"].as_bool(),
+        label: metadata["label"].as_str().map(str::to_string),
+        initiator: metadata["initiator"]
+            .as_str()
+            .filter(|initiator| !initiator.is_empty())
+            .map(str::to_string),
+        sites,
+    }
+}
+
+#[cfg(feature = "server")]
+/// Lists the snapshot files persisted in the snapshot directory of the site
+///
+/// The most recent snapshots come first.
+pub fn list_snapshot_files() -> std::io::Result<Vec<SnapshotFileInfo>> {
+    let mut infos = Vec::new();
+    for entry in snapshot_entries()? {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let size_bytes = entry.metadata()?.len();
+        let json = read_snapshot_path(&entry.path()).unwrap_or_default();
+        infos.push(summarize_snapshot(&file_name, size_bytes, &json));
+    }
+    infos.sort_by(|a, b| {
+        b.timestamp
+            .cmp(&a.timestamp)
+            .then_with(|| a.file_name.cmp(&b.file_name))
+    });
+    Ok(infos)
+}
+
+#[cfg(feature = "server")]
+/// Reads the JSON content of a persisted snapshot file
+pub fn read_snapshot_file(file_name: &str) -> std::io::Result<String> {
+    if !is_snapshot_file_name(file_name) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a snapshot file", file_name),
+        ));
+    }
+    read_snapshot_path(&snapshot_dir().join(file_name))
+}
+
+#[cfg(feature = "server")]
+/// Loads a persisted global snapshot, to restore it
+pub fn load_snapshot(file_name: &str) -> std::io::Result<GlobalSnapshot> {
+    Ok(serde_json::from_str(&read_snapshot_file(file_name)?)?)
+}
+
+#[cfg(feature = "server")]
+/// Loads a global snapshot from a file of the operator, gzipped if its name ends with `.gz`
+pub fn load_snapshot_path(path: &std::path::Path) -> std::io::Result<GlobalSnapshot> {
+    Ok(serde_json::from_str(&read_snapshot_path(path)?)?)
+}
+
+#[cfg(feature = "server")]
+/// Result of the verification of a snapshot against its certificate
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct SnapshotVerification {
+    /// True if the local snapshots of the certificate were consistent as received
+    pub consistent: bool,
+    /// Number of transactions of the snapshot
+    pub nb_transactions: usize,
+    /// Differences found between the snapshot and what its certificate gives
+    pub problems: Vec<String>,
+}
+
+#[cfg(feature = "server")]
+impl SnapshotVerification {
+    /// Returns true if the snapshot matches its certificate
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[cfg(feature = "server")]
+/// Recomputes a snapshot from its consistency certificate and lists the
+/// differences with the snapshot
+///
+/// The consistency of the local snapshots, the cut they were trimmed to, the
+/// union of their transactions and the transactions missing from each site
+/// are computed again from the clocks and logs of the certificate.
+pub fn verify(snapshot: &GlobalSnapshot) -> SnapshotVerification {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    let mut verification = SnapshotVerification {
+        nb_transactions: snapshot.all_transactions.len(),
+        ..Default::default()
+    };
+    let Some(certificate) = &snapshot.certificate else {
+        verification
+            .problems
+            .push("The snapshot has no consistency certificate".to_string());
+        return verification;
+    };
+    let clocks: Vec<LocalSnapshot> = certificate
+        .sites
+        .iter()
+        .map(|site| LocalSnapshot {
+            site_id: site.site_id.clone(),
+            vector_clock: site.clock.clone().into_iter().collect(),
+            tx_log: std::collections::HashSet::new(),
+            partial: site.partial,
+        })
+        .collect();
+
+    verification.consistent = GlobalSnapshot::is_consistent(&clocks);
+    if verification.consistent != snapshot.consistent {
+        verification.problems.push(format!(
+            "The snapshot is flagged {}, its local snapshots are {}",
+            if snapshot.consistent {
+                "consistent"
+            } else {
+                "back-tracked"
+            },
+            if verification.consistent {
+                "consistent"
+            } else {
+                "not consistent"
+            }
+        ));
+    }
+    let cut: Option<BTreeMap<String, i64>> =
+        (!verification.consistent).then(|| min_cut(&clocks).into_iter().collect());
+    if cut != certificate.cut {
+        verification.problems.push(format!(
+            "The cut should be {:?}, not {:?}",
+            cut, certificate.cut
+        ));
+    }
+
+    let kept = |site: &CertifiedSite| -> BTreeSet<(String, i64)> {
+        site.transactions
+            .iter()
+            .filter(|(node, lamport)| {
+                cut.as_ref()
+                    .is_none_or(|cut| *lamport <= *cut.get(node).unwrap_or(&0))
+            })
+            .cloned()
+            .collect()
+    };
+    let keys = |txs: &std::collections::HashSet<TxSummary>| -> BTreeSet<(String, i64)> {
+        txs.iter()
+            .map(|tx| (tx.source_node.clone(), tx.lamport_time))
+            .collect()
+    };
+    let union: BTreeSet<(String, i64)> = certificate.sites.iter().flat_map(kept).collect();
+    let all = keys(&snapshot.all_transactions);
+    for (node, lamport) in union.difference(&all) {
+        verification.problems.push(format!(
+            "Transaction {}-{} is missing from the snapshot",
+            node, lamport
+        ));
+    }
+    for (node, lamport) in all.difference(&union) {
+        verification.problems.push(format!(
+            "Transaction {}-{} should not be in the snapshot",
+            node, lamport
+        ));
+    }
+
+    let empty = std::collections::HashSet::new();
+    for site in certificate.sites.iter().filter(|site| !site.partial) {
+        let expected: BTreeSet<(String, i64)> = union.difference(&kept(site)).cloned().collect();
+        let recorded = keys(snapshot.missing.get(&site.site_id).unwrap_or(&empty));
+        if expected != recorded {
+            verification.problems.push(format!(
+                "Site {} misses {} transactions, the snapshot records {}",
+                site.site_id,
+                expected.len(),
+                recorded.len()
+            ));
+        }
+    }
+    for site in snapshot.missing.keys() {
+        if !certificate
+            .sites
+            .iter()
+            .any(|certified| certified.site_id == *site && !certified.partial)
+        {
+            verification.problems.push(format!(
+                "Missing transactions are recorded for {}, which sent no full local snapshot",
+                site
+            ));
+        }
+    }
+    verification
+}
+
+/// Greatest length of a snapshot label
+pub const MAX_LABEL_LEN: usize = 64;
+
+/// Checks the label given to a snapshot
+///
+/// A label is a single word, so that it can designate the snapshot in the
+/// CLI commands.
+pub fn validate_label(label: &str) -> Result<(), String> {
+    if label.is_empty() || label.len() > MAX_LABEL_LEN {
+        return Err(format!(
+            "A snapshot label has 1 to {} characters.",
+            MAX_LABEL_LEN
+        ));
+    }
+    if !label
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(format!(
+            "Invalid snapshot label '{}': only letters, digits, '-', '_' and '.' are allowed.",
+            label
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Returns the whole snapshot file designated by a file name or a label
+///
+/// The most recent snapshot is returned when several have the same label.
+pub fn find_snapshot(name: &str) -> std::io::Result<String> {
+    if is_snapshot_file_name(name) {
+        return Ok(name.to_string());
+    }
+    list_snapshot_files()?
+        .into_iter()
+        .find(|info| info.ledger.is_none() && info.label.as_deref() == Some(name))
+        .map(|info| info.file_name)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No snapshot named or labelled '{}'", name),
+            )
+        })
+}
+
+#[cfg(feature = "server")]
+/// Compares two persisted snapshots, designated by file name or label
+pub fn diff_snapshot_files(a: &str, b: &str) -> std::io::Result<SnapshotDiff> {
+    let a = find_snapshot(a)?;
+    let b = find_snapshot(b)?;
+    let mut diff = load_snapshot(&a)?.diff(&load_snapshot(&b)?);
+    diff.a = a;
+    diff.b = b;
+    Ok(diff)
+}
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    pub static ref LOCAL_SNAPSHOT_MANAGER: tokio::sync::Mutex<SnapshotManager> =
+        tokio::sync::Mutex::new(SnapshotManager::new(0));
+    /// Wakes up the clients waiting for the progress of a snapshot
+    static ref SNAPSHOT_PROGRESS: tokio::sync::Notify = tokio::sync::Notify::new();
+    /// Name of the last snapshot file saved
+    static ref SAVED_SNAPSHOTS: tokio::sync::watch::Sender<Option<String>> =
+        tokio::sync::watch::channel(None).0;
+}
+
+#[cfg(feature = "server")]
+/// Waits for the progress of the snapshot to differ from the last one seen
+///
+/// Returns the current progress right away without a last progress, or once
+/// the timeout expires.
+pub async fn wait_for_progress(
+    last: Option<SnapshotProgress>,
+    timeout: std::time::Duration,
+) -> SnapshotProgress {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let notified = SNAPSHOT_PROGRESS.notified();
+        tokio::pin!(notified);
+        // registered before reading the progress, so that no update is missed
+        notified.as_mut().enable();
+        let progress = LOCAL_SNAPSHOT_MANAGER.lock().await.progress();
+        if last.as_ref() != Some(&progress) {
+            return progress;
+        }
+        if tokio::time::timeout_at(deadline, notified).await.is_err() {
+            return progress;
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    fn mk_clock(pairs: &[(&str, i64)]) -> crate::clock::Clock {
+        let mut m = std::collections::HashMap::new();
+        for (id, v) in pairs {
+            m.insert((*id).to_string(), *v);
+        }
+
+        crate::clock::Clock::new_with_values(0, m)
+    }
+
+    fn resp(site: &str, vc: &[(&str, i64)], txs: &[TxSummary]) -> crate::message::SnapshotResponse {
+        crate::message::SnapshotResponse {
+            site_id: site.to_string(),
+            clock: mk_clock(vc),
+            tx_log: txs.to_vec(),
+            partial: false,
+            ledgers: std::collections::HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn consistency_ok() {
+        let s1 = LocalSnapshot {
+            site_id: "A".into(),
+            vector_clock: std::collections::HashMap::from_iter([("A".into(), 1), ("B".into(), 0)]),
+            tx_log: std::collections::HashSet::new(),
+            partial: false,
+        };
+        let s2 = LocalSnapshot {
+            site_id: "B".into(),
+            vector_clock: std::collections::HashMap::from_iter([("A".into(), 1), ("B".into(), 1)]),
+            tx_log: std::collections::HashSet::new(),
+            partial: false,
+        };
+        assert!(GlobalSnapshot::is_consistent(&[s1, s2]));
+    }
+
+    #[test]
+    fn consistency_violation() {
+        let s1 = LocalSnapshot {
+            site_id: "A".into(),
+            vector_clock: std::collections::HashMap::from_iter([("A".into(), 2), ("B".into(), 2)]),
+            tx_log: std::collections::HashSet::new(),
+            partial: false,
+        };
+        let s2 = LocalSnapshot {
+            site_id: "B".into(),
+            vector_clock: std::collections::HashMap::from_iter([("A".into(), 1), ("B".into(), 1)]),
+            tx_log: std::collections::HashSet::new(),
+            partial: false,
+        };
+        assert!(!GlobalSnapshot::is_consistent(&[s1, s2]));
+    }
+
+    #[test]
+    fn push_waits_for_expected() {
+        let mut mgr = SnapshotManager::new(2);
+        let tx = TxSummary {
+            lamport_time: 1,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 100,
+        };
+        let r1 = resp("A", &[("A", 1)], std::slice::from_ref(&tx));
+        assert!(mgr.push(r1).is_none());
+        assert_eq!(mgr.received.len(), 1);
+    }
+
+    #[test]
+    fn push_detects_incoherence() {
+        let mut mgr = SnapshotManager::new(2);
+        let bad_r1 = resp("A", &[("A", 2), ("B", 2)], &[]);
+        let bad_r2 = resp("B", &[("A", 1), ("B", 1)], &[]);
+        assert!(mgr.push(bad_r1).is_none());
+        let snap = mgr.push(bad_r2).expect("back-tracked snapshot");
+        assert!(GlobalSnapshot::is_consistent(&[LocalSnapshot {
+            site_id: "dummy".into(),
+            vector_clock: std::collections::HashMap::new(),
+            tx_log: snap.all_transactions.clone(),
+            partial: false,
+        }]));
+        assert!(snap.missing.is_empty() || !snap.missing.contains_key("A"));
+    }
+
+    #[test]
+    fn push_computes_missing_and_dedup() {
+        let mut mgr = SnapshotManager::new(2);
+        let t1 = TxSummary {
+            lamport_time: 10,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 100,
+        };
+        let t2 = TxSummary {
+            lamport_time: 11,
+            source_node: "B".into(),
+            from_user: "user3".into(),
+            to_user: "user4".into(),
+            amount_in_cent: 200,
+        };
+
+        let r1 = resp("A", &[("A", 1)], std::slice::from_ref(&t1));
+        let r2 = resp("B", &[("B", 1)], &[t1.clone(), t2.clone()]);
+
+        let _ = mgr.push(r1);
+        let gs = mgr.push(r2).expect("snapshot ready");
+
+        assert_eq!(gs.all_transactions.len(), 2);
+        assert_eq!(
+            gs.missing["A"],
+            std::collections::HashSet::from_iter([t2.clone()])
+        );
+        assert!(!gs.missing.contains_key("B"));
+    }
+
+    #[test]
+    fn consistency_handles_missing_columns() {
+        let a = LocalSnapshot {
+            site_id: "A".into(),
+            vector_clock: std::collections::HashMap::from_iter([("A".into(), 3)]),
+            tx_log: std::collections::HashSet::new(),
+            partial: false,
+        };
+        let b = LocalSnapshot {
+            site_id: "B".into(),
+            vector_clock: std::collections::HashMap::from_iter([("B".into(), 1)]),
+            tx_log: std::collections::HashSet::new(),
+            partial: false,
+        };
+        assert!(GlobalSnapshot::is_consistent(&[a, b]));
+    }
+
+    #[test]
+    fn backtrack_trims_future_transactions() {
+        let mut mgr = SnapshotManager::new(2);
+
+        let t1 = TxSummary {
+            lamport_time: 1,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 100,
+        };
+        let t3 = TxSummary {
+            lamport_time: 3,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 300,
+        };
+        let t5 = TxSummary {
+            lamport_time: 5,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 500,
+        };
+
+        let r_a = resp(
+            "A",
+            &[("A", 5), ("B", 2)], // ← incohérence ici
+            &[t1.clone(), t3.clone(), t5.clone()],
+        );
+
+        let r_b = resp("B", &[("A", 2), ("B", 1)], &[]);
+
+        let _ = mgr.push(r_a);
+        let snap = mgr.push(r_b).expect("snapshot after back-track");
+
+        assert!(snap.all_transactions.contains(&t1));
+        assert!(!snap.all_transactions.contains(&t5));
+    }
+
+    #[test]
+    fn certificate_verifies_snapshot() {
+        let tx = |lamport_time, amount_in_cent| TxSummary {
+            lamport_time,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent,
+        };
+        let mut mgr = SnapshotManager::new(2);
+        let _ = mgr.push(resp("A", &[("A", 5), ("B", 2)], &[tx(1, 100), tx(3, 300)]));
+        let snap = mgr.push(resp("B", &[("A", 2), ("B", 1)], &[])).unwrap();
+        let certificate = snap.certificate.clone().unwrap();
+        assert_eq!(certificate.sites.len(), 2);
+        assert_eq!(
+            certificate.cut,
+            Some(std::collections::BTreeMap::from([
+                ("A".to_string(), 2),
+                ("B".to_string(), 1)
+            ]))
+        );
+        // the certificate is saved with the snapshot
+        let snap: GlobalSnapshot =
+            serde_json::from_str(&serde_json::to_string(&snap).unwrap()).unwrap();
+        let verification = verify(&snap);
+        assert!(verification.is_valid(), "{:?}", verification.problems);
+        assert!(!verification.consistent);
+
+        let mut forged = snap.clone();
+        forged.consistent = true;
+        forged.all_transactions.insert(tx(3, 300));
+        forged.missing.remove("B");
+        let verification = verify(&forged);
+        assert_eq!(
+            verification.problems.len(),
+            3,
+            "{:?}",
+            verification.problems
+        );
+
+        forged.certificate = None;
+        assert!(!verify(&forged).is_valid());
+    }
+
+    #[test]
+    fn backtracked_snapshot_is_flagged() {
+        let mut mgr = SnapshotManager::new(2);
+        let _ = mgr.push(resp("A", &[("A", 2), ("B", 2)], &[]));
+        let snap = mgr.push(resp("B", &[("A", 1), ("B", 1)], &[])).unwrap();
+        assert!(!snap.consistent);
+
+        let mut mgr = SnapshotManager::new(2);
+        let _ = mgr.push(resp("A", &[("A", 1)], &[]));
+        let snap = mgr.push(resp("B", &[("B", 1)], &[])).unwrap();
+        assert!(snap.consistent);
+    }
+
+    #[test]
+    fn snapshot_file_names() {
+        assert!(is_snapshot_file_name("snapshot_A_20250101_120000.json"));
+        assert!(is_snapshot_file_name("snapshot_A_20250101_120000.json.gz"));
+        assert!(!is_snapshot_file_name("snapshot_A_20250101_120000.gz"));
+        assert!(!is_snapshot_file_name("peillute_0.db"));
+        assert!(!is_snapshot_file_name("snapshot_../../etc/passwd.json"));
+
+        assert_eq!(
+            parse_snapshot_timestamp("snapshot_aabbcc_1234_20250102_030405.json"),
+            Some("2025-01-02 03:04:05".to_string())
+        );
+        assert_eq!(
+            parse_snapshot_timestamp("snapshot_A_20250102_030405.json.gz"),
+            Some("2025-01-02 03:04:05".to_string())
+        );
+        assert_eq!(parse_snapshot_timestamp("snapshot_A.json"), None);
+
+        assert_eq!(
+            parse_snapshot_ledger("snapshot_A@club-bar_20250102_030405.json.gz"),
+            Some("club-bar".to_string())
+        );
+        assert_eq!(
+            parse_snapshot_timestamp("snapshot_A@club-bar_20250102_030405.json.gz"),
+            Some("2025-01-02 03:04:05".to_string())
+        );
+        assert_eq!(
+            parse_snapshot_ledger("snapshot_A_20250102_030405.json.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn gzipped_snapshot_roundtrip() {
+        let tx = TxSummary {
+            lamport_time: 1,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 100,
+        };
+        let gs = GlobalSnapshot {
+            all_transactions: std::collections::HashSet::from_iter([tx.clone()]),
+            missing: std::collections::HashMap::from_iter([(
+                "B".to_string(),
+                std::collections::HashSet::from_iter([tx]),
+            )]),
+            consistent: true,
+            ledgers: std::collections::HashMap::new(),
+            metadata: SnapshotMetadata::default(),
+            certificate: None,
+        };
+        let path = std::env::temp_dir().join(format!(
+            "peillute_test_snapshot_{}.json.gz",
+            std::process::id()
+        ));
+        write_snapshot(&path, &gs).unwrap();
+        let json = read_snapshot_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let restored: GlobalSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.all_transactions, gs.all_transactions);
+        assert_eq!(restored.missing, gs.missing);
+        assert!(restored.consistent);
+    }
+
+    #[test]
+    fn summarize_snapshot_json() {
+        let mut gs = GlobalSnapshot {
+            all_transactions: std::collections::HashSet::new(),
+            missing: std::collections::HashMap::new(),
+            consistent: false,
+            ledgers: std::collections::HashMap::new(),
+            metadata: SnapshotMetadata {
+                label: Some("before-upgrade".to_string()),
+                initiator: "A".to_string(),
+                sites: vec!["B".to_string(), "A".to_string()],
+                clock: std::collections::BTreeMap::new(),
+            },
+            certificate: None,
+        };
+        let tx = TxSummary {
+            lamport_time: 1,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 100,
+        };
+        gs.all_transactions.insert(tx.clone());
+        gs.missing
+            .insert("B".into(), std::collections::HashSet::from_iter([tx]));
+        let json = serde_json::to_string(&gs).unwrap();
+
+        let info = summarize_snapshot("snapshot_A_20250101_120000.json", 42, &json);
+        assert_eq!(info.nb_transactions, 1);
+        assert_eq!(info.missing_per_site, vec![("B".to_string(), 1)]);
+        assert_eq!(info.consistent, Some(false));
+        assert_eq!(info.size_bytes, 42);
+        assert_eq!(info.label.as_deref(), Some("before-upgrade"));
+        assert_eq!(info.initiator.as_deref(), Some("A"));
+        assert_eq!(info.sites, vec!["A", "B"]);
+
+        let old = summarize_snapshot("snapshot_A_20250101_120000.json", 0, "{}");
+        assert_eq!(old.consistent, None);
+        assert_eq!(old.label, None);
+        assert_eq!(old.initiator, None);
+    }
+
+    #[test]
+    fn snapshot_for_ledger() {
+        use crate::db::{CASH_IN, CASH_OUT};
+
+        let tx = |lamport_time: i64, from_user: &str, to_user: &str| TxSummary {
+            lamport_time,
+            source_node: "A".into(),
+            from_user: from_user.into(),
+            to_user: to_user.into(),
+            amount_in_cent: 100,
+        };
+        let gs = GlobalSnapshot {
+            all_transactions: std::collections::HashSet::from_iter([
+                tx(1, CASH_IN, "alice"),
+                tx(2, CASH_IN, "carol"),
+                tx(3, "carol", CASH_OUT),
+            ]),
+            missing: std::collections::HashMap::from_iter([(
+                "B".to_string(),
+                std::collections::HashSet::from_iter([tx(1, CASH_IN, "alice")]),
+            )]),
+            consistent: true,
+            ledgers: std::collections::HashMap::from_iter([(
+                "carol".to_string(),
+                "club-bar".to_string(),
+            )]),
+            metadata: SnapshotMetadata::default(),
+            certificate: None,
+        };
+        assert_eq!(gs.ledger_names(), vec!["default", "club-bar"]);
+
+        let bar = gs.for_ledger("club-bar");
+        assert_eq!(bar.all_transactions.len(), 2);
+        assert!(bar.missing.is_empty());
+        assert_eq!(bar.ledgers.len(), 1);
+
+        let default = gs.for_ledger(crate::ledger::DEFAULT_LEDGER);
+        assert_eq!(default.all_transactions.len(), 1);
+        assert_eq!(default.missing["B"].len(), 1);
+        assert!(default.ledgers.is_empty());
+    }
+
+    #[test]
+    fn retention_keeps_most_recent() {
+        let names = vec![
+            "snapshot_A_20250101_120000.json".to_string(),
+            "snapshot_A_20250103_080000.json".to_string(),
+            "snapshot_A_20250102_230000.json".to_string(),
+        ];
+        assert!(expired_snapshots(names.clone(), 0).is_empty());
+        assert!(expired_snapshots(names.clone(), 3).is_empty());
+        assert_eq!(
+            expired_snapshots(names.clone(), 1),
+            vec![
+                "snapshot_A_20250102_230000.json".to_string(),
+                "snapshot_A_20250101_120000.json".to_string(),
+            ]
+        );
+
+        // the snapshots of a ledger do not push out the whole snapshots
+        let mut with_ledger = names;
+        with_ledger.push("snapshot_A@club-bar_20250104_080000.json.gz".to_string());
+        assert_eq!(expired_snapshots(with_ledger, 2).len(), 1);
+    }
+
+    #[test]
+    fn union_is_deduplicated() {
+        let mut mgr = SnapshotManager::new(2);
+        let tx = TxSummary {
+            lamport_time: 7,
+            source_node: "A".into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 700,
+        };
+
+        let r1 = resp("A", &[("A", 1)], std::slice::from_ref(&tx));
+        let r2 = resp("B", &[("B", 1)], std::slice::from_ref(&tx));
+
+        let _ = mgr.push(r1);
+        let gs = mgr.push(r2).expect("snapshot ready");
+        assert_eq!(gs.all_transactions.len(), 1);
+    }
+
+    #[test]
+    fn metadata_and_diff() {
+        let tx = |site: &str, lamport_time: i64| TxSummary {
+            lamport_time,
+            source_node: site.into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 100,
+        };
+        let mut mgr = SnapshotManager::new(2);
+        mgr.initiator = "A".to_string();
+        mgr.label = Some("before-upgrade".to_string());
+        let _ = mgr.push(resp("B", &[("A", 1), ("B", 3)], &[tx("A", 1)]));
+        let before = mgr
+            .push(resp("A", &[("A", 2), ("B", 1)], &[tx("A", 1), tx("A", 2)]))
+            .expect("snapshot ready");
+        assert_eq!(before.metadata.label.as_deref(), Some("before-upgrade"));
+        assert_eq!(before.metadata.initiator, "A");
+        assert_eq!(before.metadata.sites, vec!["A", "B"]);
+        assert_eq!(
+            before.metadata.clock,
+            std::collections::BTreeMap::from([("A".to_string(), 2), ("B".to_string(), 3)])
+        );
+
+        let mut after = before.clone();
+        after.all_transactions.remove(&tx("A", 2));
+        after.all_transactions.insert(tx("B", 4));
+        let diff = before.diff(&after);
+        assert_eq!(diff.common, 1);
+        assert_eq!(
+            diff.sites,
+            vec![
+                SiteDiff {
+                    site: "A".to_string(),
+                    only_in_a: vec![tx("A", 2)],
+                    only_in_b: Vec::new(),
+                },
+                SiteDiff {
+                    site: "B".to_string(),
+                    only_in_a: Vec::new(),
+                    only_in_b: vec![tx("B", 4)],
+                },
+            ]
+        );
+        assert!(before.diff(&before).sites.is_empty());
+
+        assert!(validate_label("before-upgrade").is_ok());
+        assert!(validate_label("").is_err());
+        assert!(validate_label("two words").is_err());
+        assert!(validate_label(&"x".repeat(MAX_LABEL_LEN + 1)).is_err());
+    }
+    #[test]
+    fn progress_lists_awaited_sites() {
+        let mut mgr = SnapshotManager::new(3);
+        mgr.awaited = vec!["A".to_string(), "B".to_string(), "C".to_string()];
+        assert_eq!(mgr.progress().received, 0);
+
+        let _ = mgr.push(resp("A", &[("A", 1)], &[]));
+        let _ = mgr.push(resp("C", &[("C", 1)], &[]));
+        let progress = mgr.progress();
+        assert_eq!(progress.expected, 3);
+        assert_eq!(progress.received, 2);
+        assert!(!progress.is_complete());
+        assert_eq!(
+            progress.sites,
+            vec![
+                ("A".to_string(), true),
+                ("B".to_string(), false),
+                ("C".to_string(), true)
+            ]
+        );
+
+        let _ = mgr.push(resp("B", &[("B", 1)], &[]));
+        assert!(mgr.progress().is_complete());
+    }
+
+    #[test]
+    fn delta_merges_onto_initiator_log() {
+        let tx = |site: &str, lamport_time: i64| TxSummary {
+            lamport_time,
+            source_node: site.into(),
+            from_user: "user1".into(),
+            to_user: "user2".into(),
+            amount_in_cent: 100,
+        };
+        let initiator_log = vec![tx("A", 1), tx("B", 2)];
+        let known = known_lamports(&initiator_log);
+        assert_eq!(known["A"], 1);
+        assert_eq!(known["B"], 2);
+
+        let delta = newer_than(vec![tx("A", 1), tx("B", 2), tx("B", 4), tx("C", 1)], &known);
+        assert_eq!(delta, vec![tx("B", 4), tx("C", 1)]);
+
+        let mut mgr = SnapshotManager::new(2);
+        let _ = mgr.push(resp("A", &[("A", 1)], &initiator_log));
+        let mut partial = resp("B", &[("B", 1)], &delta);
+        partial.partial = true;
+        let gs = mgr.push(partial).expect("snapshot ready");
+        assert_eq!(gs.all_transactions.len(), 4);
+        assert_eq!(gs.missing["A"].len(), 2);
+        assert!(!gs.missing.contains_key("B"));
+    }
+}
@@ -0,0 +1,930 @@
+//! Application state management for Peillute
+//!
+//! This module handles the global application state, including site information,
+//! peer management, and logical clock synchronization.
+//!
+//! The state is split into components that are locked independently, so that
+//! handling a message or a server fn only blocks the part of the state it uses:
+//! - [`SITE_INFO`]: identity of the site, set at startup
+//! - [`LOCAL_CLOCK`]: logical clock of the site
+//! - [`PEERS`]: connected neighbours
+//! - [`WAVES`]: parent and expected answers of each wave diffusion
+//! - [`CRITICAL_SECTION`]: global mutex FIFO and pending critical commands
+//!
+//! A component is never locked while another one is held: functions needing
+//! several of them copy what they need from each one in turn. The time each
+//! component is held is measured, see [`crate::lock_stats`].
+
+#[cfg(feature = "server")]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutexTag {
+    Request,
+    Release,
+    #[allow(dead_code)]
+    Ack,
+}
+
+#[cfg(feature = "server")]
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, Debug)]
+pub struct MutexStamp {
+    pub tag: MutexTag,
+    pub date: i64,
+}
+
+#[cfg(feature = "server")]
+/// Identity of the site, set at startup
+pub struct SiteInfo {
+    /// Unique identifier for this site
+    site_id: String,
+    /// Unique address for this site
+    site_addr: std::net::SocketAddr,
+    /// List of peer addresses given in arguments at the launch of the application
+    cli_peer_addrs: Vec<std::net::SocketAddr>,
+    /// Synchronization boolean
+    sync_needed: bool,
+    /// Number of attended neighbours at launch, for the discovery phase
+    nb_first_attended_neighbours: i64,
+    /// Human-friendly name of this site, sent to the other sites
+    site_alias: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl SiteInfo {
+    /// Creates a new SiteInfo instance with the given configuration
+    pub fn new(
+        site_id: String,
+        peer_addrs: Vec<std::net::SocketAddr>,
+        local_addr: std::net::SocketAddr,
+    ) -> Self {
+        Self {
+            site_id,
+            site_addr: local_addr,
+            cli_peer_addrs: peer_addrs,
+            sync_needed: false,
+            nb_first_attended_neighbours: 0,
+            site_alias: None,
+        }
+    }
+
+    /// Sets the site ID at initialization
+    pub fn init_site_id(&mut self, site_id: String) {
+        self.site_id = site_id;
+    }
+
+    /// Sets the site address at initialization
+    pub fn init_site_addr(&mut self, site_addr: std::net::SocketAddr) {
+        self.site_addr = site_addr;
+    }
+
+    /// Sets the list of CLI peer addresses at initialization
+    pub fn init_cli_peer_addrs(&mut self, cli_peer_addrs: Vec<std::net::SocketAddr>) {
+        self.cli_peer_addrs = cli_peer_addrs;
+    }
+
+    /// Set the sync boolean at initialization
+    pub fn init_sync(&mut self, sync_needed: bool) {
+        if sync_needed {
+            log::info!("Local site need to be in synchronized");
+        }
+        self.sync_needed = sync_needed;
+    }
+
+    /// Get the sync boolean
+    pub fn get_sync(&self) -> bool {
+        self.sync_needed
+    }
+
+    /// Set the number of attended neighbours at initialization
+    pub fn init_nb_first_attended_neighbours(&mut self, nb: i64) {
+        log::debug!("We will wait for {} attended neighbours", nb);
+        self.nb_first_attended_neighbours = nb;
+    }
+
+    /// Get the number of attended neighbours
+    pub fn get_nb_first_attended_neighbours(&self) -> i64 {
+        self.nb_first_attended_neighbours
+    }
+
+    /// Returns the local address
+    pub fn get_site_addr(&self) -> std::net::SocketAddr {
+        self.site_addr
+    }
+
+    /// Returns the local address as a string
+    pub fn get_site_addr_as_string(&self) -> String {
+        self.site_addr.to_string()
+    }
+
+    /// Returns the site ID
+    pub fn get_site_id(&self) -> String {
+        self.site_id.clone()
+    }
+
+    /// Sets the alias of the site at initialization
+    pub fn init_site_alias(&mut self, site_alias: Option<String>) {
+        self.site_alias = site_alias;
+    }
+
+    /// Returns the alias of the site, if it has one
+    pub fn get_site_alias(&self) -> Option<String> {
+        self.site_alias.clone()
+    }
+
+    /// Returns a list of all peer addresses
+    pub fn get_cli_peers_addrs(&self) -> Vec<std::net::SocketAddr> {
+        self.cli_peer_addrs.clone()
+    }
+
+    /// Returns a list of all peer addresses as strings
+    pub fn get_cli_peers_addrs_as_string(&self) -> Vec<String> {
+        self.cli_peer_addrs.iter().map(|x| x.to_string()).collect()
+    }
+}
+
+#[cfg(feature = "server")]
+/// Result of adding a neighbour to the peers of the site
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerUpdate {
+    /// The neighbour was already connected at this address
+    AlreadyConnected,
+    /// The neighbour was not connected
+    Added,
+    /// The neighbour was connected at an other address, which it replaces
+    Moved(std::net::SocketAddr),
+}
+
+#[cfg(feature = "server")]
+/// Neighbours of the site
+#[derive(Default)]
+pub struct PeerState {
+    /// List of deg(1) neighbours connected addresses
+    connected_neighbours_addrs: Vec<std::net::SocketAddr>,
+    /// Hashmap of sockets for each deg(1) neighbours
+    neighbours_socket: std::collections::HashMap<std::net::SocketAddr, std::net::SocketAddr>,
+    /// Site ID of each known site address
+    site_ids_to_adr: std::collections::HashMap<std::net::SocketAddr, String>,
+    /// Last time a message initiated by each site was received
+    last_heard: std::collections::HashMap<String, std::time::Instant>,
+    /// Alias of each site that sent one
+    site_aliases: std::collections::HashMap<String, String>,
+}
+
+#[cfg(feature = "server")]
+impl PeerState {
+    /// Records the site ID of an address, and that the site was just heard from
+    ///
+    /// A site that restarted elsewhere keeps its former address until it is
+    /// replaced by `add_incomming_peer`.
+    pub fn add_site_id(&mut self, site_id: String, addr: std::net::SocketAddr) {
+        self.last_heard
+            .insert(site_id.clone(), std::time::Instant::now());
+        self.site_ids_to_adr.insert(addr, site_id);
+    }
+
+    /// Returns the site ID of a known site address
+    pub fn site_id_of(&self, addr: &std::net::SocketAddr) -> Option<&str> {
+        self.site_ids_to_adr.get(addr).map(String::as_str)
+    }
+
+    /// Records the alias of a site
+    pub fn set_site_alias(&mut self, site_id: String, alias: String) {
+        self.site_aliases.insert(site_id, alias);
+    }
+
+    /// Returns the alias of a site, if it sent one
+    pub fn alias_of(&self, site_id: &str) -> Option<&str> {
+        self.site_aliases.get(site_id).map(String::as_str)
+    }
+
+    /// Returns the last time a message initiated by a site was received
+    pub fn last_heard(&self, site_id: &str) -> Option<std::time::Instant> {
+        self.last_heard.get(site_id).copied()
+    }
+
+    /// Adds a neighbour, replacing the address it was connected at if it restarted elsewhere
+    pub fn add_incomming_peer(
+        &mut self,
+        site_id: &str,
+        new_addr: std::net::SocketAddr,
+        new_socket: std::net::SocketAddr,
+    ) -> PeerUpdate {
+        self.add_site_id(site_id.to_string(), new_addr);
+        self.neighbours_socket.insert(new_socket, new_addr);
+        if self.connected_neighbours_addrs.contains(&new_addr) {
+            return PeerUpdate::AlreadyConnected;
+        }
+        let old_pos = self
+            .connected_neighbours_addrs
+            .iter()
+            .position(|addr| self.site_ids_to_adr.get(addr).map(String::as_str) == Some(site_id));
+        let Some(pos) = old_pos else {
+            self.connected_neighbours_addrs.push(new_addr);
+            return PeerUpdate::Added;
+        };
+        let old_addr = std::mem::replace(&mut self.connected_neighbours_addrs[pos], new_addr);
+        self.neighbours_socket.retain(|_, addr| *addr != old_addr);
+        self.site_ids_to_adr.remove(&old_addr);
+        PeerUpdate::Moved(old_addr)
+    }
+
+    /// Removes a neighbour, returns its site ID if it was connected and known
+    ///
+    /// The site ID stays associated to its address, so that the site is
+    /// recognised if it reconnects.
+    pub fn remove_neighbour(&mut self, addr_to_remove: std::net::SocketAddr) -> Option<String> {
+        let pos = self
+            .connected_neighbours_addrs
+            .iter()
+            .position(|x| *x == addr_to_remove)?;
+        self.connected_neighbours_addrs.remove(pos);
+        self.neighbours_socket
+            .retain(|_, addr| *addr != addr_to_remove);
+        self.site_ids_to_adr.get(&addr_to_remove).cloned()
+    }
+
+    /// Returns the site address of a neighbour from the socket it connected with
+    pub fn get_addr_from_socket(
+        &self,
+        socket: std::net::SocketAddr,
+    ) -> Option<std::net::SocketAddr> {
+        self.neighbours_socket.get(&socket).copied()
+    }
+
+    /// Returns a list of conncted neibhours
+    pub fn get_connected_nei_addr(&self) -> Vec<std::net::SocketAddr> {
+        self.connected_neighbours_addrs.clone()
+    }
+
+    /// Returns a list of conncted neibhours as strings
+    pub fn get_connected_nei_addr_string(&self) -> Vec<String> {
+        self.connected_neighbours_addrs
+            .iter()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    /// Returns the number of deg(1) neighbors connected
+    pub fn get_nb_connected_neighbours(&self) -> i64 {
+        self.connected_neighbours_addrs.len() as i64
+    }
+
+    /// For tokyo test, set manually the number of connected neighbours
+    /// DO NOT USE IN PRODUCTION
+    #[cfg(test)]
+    pub fn set_nb_connected_neighbours(&mut self, nb: i64) {
+        self.connected_neighbours_addrs.clear();
+        for _ in 0..nb {
+            self.connected_neighbours_addrs
+                .push("127.0.0.1:8081".parse().unwrap());
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Default maximum number of critical commands waiting for the global mutex
+pub const DEFAULT_MAX_PENDING_COMMANDS: usize = 1024;
+
+#[cfg(feature = "server")]
+/// Maximum number of pending critical commands, set from the command line at startup
+static MAX_PENDING_COMMANDS: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(DEFAULT_MAX_PENDING_COMMANDS);
+
+#[cfg(feature = "server")]
+/// Sets the maximum number of critical commands waiting for the global mutex
+pub fn set_max_pending_commands(limit: usize) {
+    MAX_PENDING_COMMANDS.store(limit, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// State of the global mutex protecting the critical commands
+pub struct CriticalSection {
+    pub global_mutex_fifo: std::collections::HashMap<String, MutexStamp>,
+    pub waiting_sc: bool,
+    pub in_sc: bool,
+    pub notify_sc: std::sync::Arc<tokio::sync::Notify>,
+    /// Commands waiting for the global mutex, with their confirmation ticket
+    pub pending_commands: std::collections::VecDeque<(u64, crate::control::CriticalCommands)>,
+    /// Maximum length of `pending_commands`
+    pub max_pending_commands: usize,
+}
+
+#[cfg(feature = "server")]
+impl Default for CriticalSection {
+    fn default() -> Self {
+        Self {
+            global_mutex_fifo: std::collections::HashMap::new(),
+            waiting_sc: false,
+            in_sc: false,
+            notify_sc: std::sync::Arc::new(tokio::sync::Notify::new()),
+            pending_commands: std::collections::VecDeque::new(),
+            max_pending_commands: MAX_PENDING_COMMANDS.load(std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl CriticalSection {
+    pub fn get_global_mutex_fifo(&self) -> &std::collections::HashMap<String, MutexStamp> {
+        &self.global_mutex_fifo
+    }
+
+    pub fn set_global_mutex_fifo(
+        &mut self,
+        global_mutex_fifo: std::collections::HashMap<String, MutexStamp>,
+    ) {
+        if self.global_mutex_fifo.len() >= global_mutex_fifo.len() {
+            return; // Do not overwrite if the new FIFO is smaller or equal
+        }
+        self.global_mutex_fifo = global_mutex_fifo;
+    }
+
+    /// Returns the site whose request comes first in the FIFO, the one holding the mutex
+    pub fn holder(&self) -> Option<&str> {
+        self.global_mutex_fifo
+            .iter()
+            .filter(|(_, stamp)| stamp.tag == MutexTag::Request)
+            .min_by_key(|(id, stamp)| (stamp.date, *id))
+            .map(|(id, _)| id.as_str())
+    }
+
+    /// Clears the request of a site that will never release the mutex
+    ///
+    /// Returns false if the site had no request. The next site in the FIFO,
+    /// possibly this one, can then enter the critical section.
+    pub fn force_release(&mut self, site_id: &str, local_site_id: &str) -> bool {
+        let removed = self.global_mutex_fifo.remove(site_id).is_some();
+        if removed && self.waiting_sc {
+            self.try_enter_sc(local_site_id);
+        }
+        removed
+    }
+
+    /// Returns true if no more critical command can be queued
+    pub fn is_full(&self) -> bool {
+        self.pending_commands.len() >= self.max_pending_commands
+    }
+
+    /// Returns true if the pending commands run without the global mutex
+    ///
+    /// A site without any connected neighbour, that neither holds nor waits
+    /// for the mutex, commits its commands directly. Once a peer connects,
+    /// the next commands go through the mutex again.
+    pub fn runs_alone(&self, nb_connected_neighbours: i64) -> bool {
+        nb_connected_neighbours == 0 && !self.in_sc && !self.waiting_sc
+    }
+
+    /// Marks the site as waiting for the critical section
+    ///
+    /// Returns false if the site is already in or waiting for the critical
+    /// section, in which case no new request must be diffused.
+    pub fn start_request(&mut self) -> bool {
+        if self.in_sc || self.waiting_sc {
+            return false;
+        }
+        self.waiting_sc = true;
+        true
+    }
+
+    /// Enters the critical section if the request of the site is the next one
+    ///
+    /// Returns the FIFO order when the entry is refused, for the caller to
+    /// print once the critical section component is released.
+    pub fn try_enter_sc(&mut self, site_id: &str) -> Option<String> {
+        // MUST BE CALLED ONLY AFTER A SUCCESSFUL WAVE AFTER ACQUIRE MUTEX
+        // This function checks if the site can enter the critical section
+        // It checks if the site is waiting for the critical section and if it can enter
+        // based on the FIFO order of requests in the global mutex FIFO.
+
+        // Pour respecter l'algo du poly il faut que la vague soit complete
+        // c'est à dire que tout le monde ait répondu ACK pour appeller cette fonction
+        // sinon on va entrer en section critique à un moment sans qu'un des peers ait noté notre demande
+        let my_stamp = match self.global_mutex_fifo.get(site_id) {
+            Some(s) => *s,
+            None => return None, // No local request found
+        };
+        let me = (my_stamp.date, site_id.to_string());
+
+        // ici on compara les stamps des autres demandes, est-ce qu'on est le suivant dans la FIFO ?
+        // si oui on peut entrer en section critique
+        let ok = self.global_mutex_fifo.iter().all(|(id, stamp)| {
+            if id == site_id {
+                true
+            } else {
+                match stamp.tag {
+                    MutexTag::Request => me <= (stamp.date, id.clone()),
+                    _ => true,
+                }
+            }
+        });
+
+        if ok {
+            self.waiting_sc = false;
+            self.in_sc = true;
+            // All other sites are notified that we are in critical section
+            self.notify_sc.notify_waiters(); // notifies worker to execute pending commands
+            // We remove obsolete Releases
+            self.global_mutex_fifo
+                .retain(|_, s| s.tag != MutexTag::Release);
+            None
+        } else {
+            use std::fmt::Write;
+
+            let mut report = String::from("\x1b[1;31mSECTION CRITIQUE REFUSEE !\x1b[0m\n");
+            // fifo order
+            report.push_str("FIFO order:\n");
+            for (id, stamp) in &self.global_mutex_fifo {
+                let _ = writeln!(report, "{}: {:?} - {:?}", id, stamp.tag, stamp.date);
+            }
+            // my stamp
+            let _ = write!(
+                report,
+                "{}: {:?} - {:?}",
+                site_id, my_stamp.tag, my_stamp.date
+            );
+            Some(report)
+        }
+    }
+}
+
+// Singletons
+#[cfg(feature = "server")]
+use crate::lock_stats::{TrackedMutex, TrackedRwLock};
+
+#[cfg(feature = "server")]
+lazy_static::lazy_static! {
+    pub static ref SITE_INFO: TrackedRwLock<SiteInfo> = TrackedRwLock::new(
+        "site_info",
+        SiteInfo::new(
+            "".to_string(), // empty site id at start
+            Vec::new(),
+            "0.0.0.0:0".parse().unwrap(),
+        ),
+    );
+    pub static ref LOCAL_CLOCK: TrackedMutex<crate::clock::Clock> =
+        TrackedMutex::new("local_clock", crate::clock::Clock::new());
+    pub static ref PEERS: TrackedRwLock<PeerState> =
+        TrackedRwLock::new("peers", PeerState::default());
+    pub static ref WAVES: TrackedMutex<crate::wave::WaveEngine> =
+        TrackedMutex::new("waves", crate::wave::WaveEngine::default());
+    pub static ref CRITICAL_SECTION: TrackedMutex<CriticalSection> =
+        TrackedMutex::new("critical_section", CriticalSection::default());
+}
+
+#[cfg(feature = "server")]
+/// Returns the ID and the address of the site
+pub async fn local_site() -> (String, std::net::SocketAddr) {
+    let site = SITE_INFO.read().await;
+    (site.get_site_id(), site.get_site_addr())
+}
+
+#[cfg(feature = "server")]
+/// Returns the number of deg(1) neighbors connected
+pub async fn get_nb_connected_neighbours() -> i64 {
+    PEERS.read().await.get_nb_connected_neighbours()
+}
+
+#[cfg(feature = "server")]
+/// Set the clock at initialization
+pub async fn init_clock(clock: crate::clock::Clock) {
+    *LOCAL_CLOCK.lock().await = clock;
+}
+
+#[cfg(feature = "server")]
+/// Returns the clock of the site
+pub async fn get_clock() -> crate::clock::Clock {
+    LOCAL_CLOCK.lock().await.clone()
+}
+
+#[cfg(feature = "server")]
+/// Update the clock of the site and returns its new value
+pub async fn update_clock(received_vc: Option<&crate::clock::Clock>) -> crate::clock::Clock {
+    let site_id = SITE_INFO.read().await.get_site_id();
+    let mut clock = LOCAL_CLOCK.lock().await;
+    // this wrapper is needed to ensure that the clock is saved
+    // each time it is updated
+    // please DO NOT call the `update_clock` method directly from the clock
+    clock.update_clock(&site_id, received_vc);
+    // the clock stays locked while saved, so that saves are not reordered
+    let _ = crate::db::update_local_state(&site_id, clock.clone());
+    clock.clone()
+}
+
+#[cfg(feature = "server")]
+/// Adds a new peer to the network and updates the logical clock
+///
+/// This function should be safe to call multiple times
+///
+/// If a new site appear on the netword, every peers will launch a wave diffusion to announce the presence of this new site
+pub async fn add_incomming_peer(
+    site_id: &str,
+    new_addr: std::net::SocketAddr,
+    new_socket: std::net::SocketAddr,
+    received_clock: &crate::clock::Clock,
+) {
+    let update = PEERS
+        .write()
+        .await
+        .add_incomming_peer(site_id, new_addr, new_socket);
+    match update {
+        PeerUpdate::AlreadyConnected => return,
+        PeerUpdate::Added => {}
+        PeerUpdate::Moved(old_addr) => {
+            log::info!("Site {site_id} reconnected from {new_addr}, was at {old_addr}");
+            {
+                let mut net_manager = crate::network::NETWORK_MANAGER.lock().await;
+                net_manager.remove_connection(&old_addr);
+            }
+            // Its clock entry and its place in the mutex queue are keyed by site ID
+            // and are kept, only the waves it is the parent of must follow it
+            WAVES.lock().await.reparent(old_addr, new_addr);
+        }
+    }
+    update_clock(Some(received_clock)).await;
+}
+
+#[cfg(feature = "server")]
+/// Removes a peer from the network
+///
+/// This function should be safe to call multiple times
+///
+/// If a site disappear from the network, every neighbours will detected the closing of the tcp connection and will launch a wave diffusion to announce the disappearance of this site
+///
+/// If a site is closed properly, it will send a disconnect message to all its neighbours
+pub async fn remove_peer(addr_to_remove: std::net::SocketAddr) {
+    {
+        let mut net_manager = crate::network::NETWORK_MANAGER.lock().await;
+        net_manager.remove_connection(&addr_to_remove);
+    }
+    forget_peer(addr_to_remove).await;
+}
+
+#[cfg(feature = "server")]
+/// Removes a peer from the network with only an address
+///
+/// This function should be safe to call multiple times
+///
+/// If a site disappear from the network, every neighbours will detected the closing of the tcp connection and will launch a wave diffusion to announce the disappearance of this site
+///
+/// If a site is closed properly, it will send a disconnect message to all its neighbours
+pub async fn remove_peer_from_socket_closed(socket_to_remove: std::net::SocketAddr) {
+    // Find the site adress based on the socket
+    let Some(addr_to_remove) = PEERS.read().await.get_addr_from_socket(socket_to_remove) else {
+        log::debug!("Site not found in the neighbours socket");
+        return;
+    };
+    forget_peer(addr_to_remove).await;
+}
+
+#[cfg(feature = "server")]
+/// Removes a neighbour and its pending requests and waves
+async fn forget_peer(addr_to_remove: std::net::SocketAddr) {
+    let Some(site_id) = PEERS.write().await.remove_neighbour(addr_to_remove) else {
+        return;
+    };
+    CRITICAL_SECTION
+        .lock()
+        .await
+        .global_mutex_fifo
+        .remove(&site_id);
+    WAVES.lock().await.forget(&site_id);
+
+    // We can keep the clock value for the site we want to remove
+    // if the site re-appears, it will be updated with the new clock value
+}
+
+#[cfg(feature = "server")]
+/// Starts the wave of a message initiated by this site, returns true if it
+/// must be diffused
+async fn start_own_wave(msg: &crate::message::Message) -> bool {
+    // initialisation des paramètres avant la diffusion d'un message
+    let nb_neighbours = get_nb_connected_neighbours().await;
+    crate::attach::wave_step(msg, crate::attach::WaveStep::Started);
+    WAVES
+        .lock()
+        .await
+        .start_wave(&msg.message_initiator_id, msg.sender_addr, nb_neighbours)
+}
+
+#[cfg(feature = "server")]
+/// Requests the global mutex to the other sites
+///
+/// The caller must have marked the site as waiting with
+/// [`CriticalSection::start_request`].
+pub async fn acquire_mutex() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{Message, MessageInfo, NetworkMessageCode};
+    use crate::network::diffuse_message_without_lock;
+
+    let clock = update_clock(None).await;
+    let (site_id, site_addr) = local_site().await;
+
+    CRITICAL_SECTION.lock().await.global_mutex_fifo.insert(
+        site_id.clone(),
+        MutexStamp {
+            tag: MutexTag::Request,
+            date: *clock.get_lamport(),
+        },
+    );
+
+    let msg = Message {
+        sender_id: site_id.clone(),
+        sender_addr: site_addr,
+        message_initiator_id: site_id.clone(),
+        message_initiator_addr: site_addr,
+        clock,
+        command: None,
+        info: MessageInfo::AcquireMutex(crate::message::AcquireMutexPayload),
+        code: NetworkMessageCode::AcquireMutex,
+        trace: None,
+    };
+
+    if start_own_wave(&msg).await {
+        {
+            let mut cs = CRITICAL_SECTION.lock().await;
+            cs.notify_sc.notify_waiters();
+            cs.in_sc = false;
+            cs.waiting_sc = true;
+        }
+        log::info!("Début de la diffusion d'une acquisition de mutex");
+        let connected_nei_addr = PEERS.read().await.get_connected_nei_addr();
+        diffuse_message_without_lock(&msg, site_addr, &site_id, connected_nei_addr, site_addr)
+            .await?;
+    } else {
+        log::info!("Il n'y a pas de voisins, on prends la section critique");
+        let mut cs = CRITICAL_SECTION.lock().await;
+        cs.in_sc = true;
+        cs.waiting_sc = false;
+        cs.notify_sc.notify_waiters();
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Releases the global mutex and notifies the other sites
+pub async fn release_mutex() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{Message, MessageInfo, NetworkMessageCode};
+    use crate::network::diffuse_message_without_lock;
+
+    let clock = update_clock(None).await;
+    let (site_id, site_addr) = local_site().await;
+
+    let msg = Message {
+        sender_id: site_id.clone(),
+        sender_addr: site_addr,
+        message_initiator_id: site_id.clone(),
+        message_initiator_addr: site_addr,
+        clock,
+        command: None,
+        info: MessageInfo::ReleaseMutex(crate::message::ReleaseMutexPayload),
+        code: NetworkMessageCode::ReleaseGlobalMutex,
+        trace: None,
+    };
+
+    {
+        let mut cs = CRITICAL_SECTION.lock().await;
+        cs.global_mutex_fifo.remove(&site_id);
+        cs.in_sc = false;
+        cs.waiting_sc = false;
+    }
+
+    if start_own_wave(&msg).await {
+        log::info!("Début de la diffusion d'un relachement de mutex");
+        let connected_nei_addr = PEERS.read().await.get_connected_nei_addr();
+        diffuse_message_without_lock(&msg, site_addr, &site_id, connected_nei_addr, site_addr)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Clears the stale global mutex request of a site on every site
+///
+/// Used by an administrator when the site holding the mutex crashed, which
+/// would otherwise stall the critical commands of every other site. The
+/// state of the site is saved first, see [`crate::audit_log`].
+pub async fn force_release_mutex(target_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::message::{ForceReleaseMutexPayload, Message, MessageInfo, NetworkMessageCode};
+    use crate::network::diffuse_message_without_lock;
+
+    let (site_id, site_addr) = local_site().await;
+    if target_id == site_id {
+        return Err("The mutex of the local site is released when its commands are done".into());
+    }
+    if !CRITICAL_SECTION
+        .lock()
+        .await
+        .global_mutex_fifo
+        .contains_key(target_id)
+    {
+        return Err(format!("Site {} has no request for the global mutex", target_id).into());
+    }
+    crate::audit_log::before(crate::audit_log::RiskyOperation::ForceRelease, target_id).await?;
+    {
+        let mut cs = CRITICAL_SECTION.lock().await;
+        if cs.in_sc {
+            return Err(
+                "The local site holds the global mutex, retry once its commands are done".into(),
+            );
+        }
+        if !cs.force_release(target_id, &site_id) {
+            return Err(format!("Site {} has no request for the global mutex", target_id).into());
+        }
+    }
+    WAVES.lock().await.forget(target_id);
+    log::warn!(
+        "Global mutex request of site {} forcibly released by site {}",
+        target_id,
+        site_id
+    );
+
+    let clock = update_clock(None).await;
+    let msg = Message {
+        sender_id: site_id.clone(),
+        sender_addr: site_addr,
+        message_initiator_id: site_id.clone(),
+        message_initiator_addr: site_addr,
+        clock,
+        command: None,
+        info: MessageInfo::ForceReleaseMutex(ForceReleaseMutexPayload {
+            site_id: target_id.to_string(),
+        }),
+        code: NetworkMessageCode::ForceReleaseMutex,
+        trace: None,
+    };
+
+    if start_own_wave(&msg).await {
+        let connected_nei_addr = PEERS.read().await.get_connected_nei_addr();
+        diffuse_message_without_lock(&msg, site_addr, &site_id, connected_nei_addr, site_addr)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Interval between two checks of the site holding the global mutex
+const MUTEX_WATCHDOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[cfg(feature = "server")]
+/// Spawns the task warning when the site holding the global mutex stops answering
+///
+/// While this site waits for the mutex, the holder is suspected once no
+/// message initiated by it was received for `timeout`. The mutex is not
+/// released automatically: a slow holder would then break the mutual
+/// exclusion, so the administrator decides with `/force_release`.
+pub fn mutex_watchdog(timeout: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MUTEX_WATCHDOG_INTERVAL);
+        // Holder we are waiting for, since when, and whether it was reported
+        let mut waiting_for: Option<(String, std::time::Instant, bool)> = None;
+        loop {
+            interval.tick().await;
+            let local_site_id = SITE_INFO.read().await.get_site_id();
+            let holder = {
+                let cs = CRITICAL_SECTION.lock().await;
+                match cs.holder() {
+                    Some(holder) if cs.waiting_sc && holder != local_site_id => {
+                        Some(holder.to_string())
+                    }
+                    _ => None,
+                }
+            };
+            let Some(holder) = holder else {
+                waiting_for = None;
+                continue;
+            };
+            if waiting_for.as_ref().map(|(id, _, _)| id) != Some(&holder) {
+                waiting_for = Some((holder.clone(), std::time::Instant::now(), false));
+            }
+            let Some((_, since, reported)) = waiting_for.as_mut() else {
+                continue;
+            };
+            let last_heard = PEERS.read().await.last_heard(&holder);
+            let silent_since = last_heard.map_or(*since, |heard| heard.max(*since));
+            if silent_since.elapsed() < timeout {
+                *reported = false;
+                continue;
+            }
+            if *reported {
+                continue;
+            }
+            *reported = true;
+            log::warn!(
+                "Site {} holds the global mutex and was not heard from for {} s",
+                holder,
+                silent_since.elapsed().as_secs()
+            );
+            println!(
+                "⚠️  Site {} holds the global mutex but does not answer, `/force_release {}` releases it",
+                holder, holder
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_state() {
+        let cli_site_id = "A".to_string();
+        let num_sites = 2;
+        let peer_addrs = vec![
+            "127.0.0.1:8081".parse().unwrap(),
+            "127.0.0.1:8082".parse().unwrap(),
+        ];
+        let local_addr: std::net::SocketAddr = format!("127.0.0.1:{}", 8080).parse().unwrap();
+        let site = SiteInfo::new(cli_site_id.clone(), peer_addrs.clone(), local_addr);
+
+        assert_eq!(site.site_id, cli_site_id);
+        assert_eq!(site.cli_peer_addrs.len() as i64, num_sites);
+        assert_eq!(site.cli_peer_addrs, peer_addrs);
+        assert_eq!(PeerState::default().get_nb_connected_neighbours(), 0); // Initially empty
+    }
+
+    #[test]
+    fn test_peer_reconnects_from_new_address() {
+        let old_addr: std::net::SocketAddr = "127.0.0.1:8081".parse().unwrap();
+        let new_addr: std::net::SocketAddr = "127.0.0.1:9081".parse().unwrap();
+        let old_socket: std::net::SocketAddr = "127.0.0.1:50001".parse().unwrap();
+        let new_socket: std::net::SocketAddr = "127.0.0.1:50002".parse().unwrap();
+        let mut peers = PeerState::default();
+
+        assert_eq!(
+            peers.add_incomming_peer("B", old_addr, old_socket),
+            PeerUpdate::Added
+        );
+        assert_eq!(
+            peers.add_incomming_peer("B", old_addr, old_socket),
+            PeerUpdate::AlreadyConnected
+        );
+        assert_eq!(
+            peers.add_incomming_peer("B", new_addr, new_socket),
+            PeerUpdate::Moved(old_addr)
+        );
+        assert_eq!(peers.get_connected_nei_addr(), vec![new_addr]);
+        assert_eq!(peers.get_addr_from_socket(old_socket), None);
+        assert_eq!(peers.remove_neighbour(old_addr), None);
+
+        assert_eq!(peers.remove_neighbour(new_addr), Some("B".to_string()));
+        assert_eq!(peers.get_nb_connected_neighbours(), 0);
+    }
+
+    #[test]
+    fn test_force_release_stale_holder() {
+        let mut cs = CriticalSection::default();
+        for (site_id, date) in [("B", 3), ("A", 5)] {
+            cs.global_mutex_fifo.insert(
+                site_id.to_string(),
+                MutexStamp {
+                    tag: MutexTag::Request,
+                    date,
+                },
+            );
+        }
+        cs.waiting_sc = true;
+        assert_eq!(cs.holder(), Some("B"));
+
+        assert!(cs.force_release("B", "A"));
+        assert!(cs.in_sc);
+        assert_eq!(cs.holder(), Some("A"));
+        assert!(!cs.force_release("B", "A"));
+    }
+
+    #[test]
+    fn test_bounded_pending_commands() {
+        use crate::control::CriticalCommands;
+
+        let mut cs = CriticalSection {
+            max_pending_commands: 2,
+            ..Default::default()
+        };
+        assert!(!cs.is_full());
+        cs.pending_commands
+            .push_back((1, CriticalCommands::FileSnapshot { label: None }));
+        assert!(!cs.is_full());
+        cs.pending_commands
+            .push_back((2, CriticalCommands::SyncSnapshot));
+        assert!(cs.is_full());
+
+        cs.pending_commands.pop_front();
+        assert!(!cs.is_full());
+    }
+
+    #[test]
+    fn test_single_node_fast_path() {
+        let mut cs = CriticalSection::default();
+        assert!(cs.runs_alone(0));
+        assert!(!cs.runs_alone(1));
+
+        // a request started with a peer is completed through the mutex
+        assert!(cs.start_request());
+        assert!(!cs.runs_alone(0));
+        cs.waiting_sc = false;
+        cs.in_sc = true;
+        assert!(!cs.runs_alone(0));
+        cs.in_sc = false;
+        assert!(cs.runs_alone(0));
+    }
+}
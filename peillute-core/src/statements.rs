@@ -0,0 +1,518 @@
+//! Accounting periods and account statements
+//!
+//! This module closes accounting periods: for each user, the transactions not
+//! covered by a previous statement are frozen into a statement with the
+//! opening balance, the totals in and out and the closing balance of the
+//! period. Statements are generated on demand or at the end of every month,
+//! and can be exported as PDF documents.
+
+/// Account statement of a user for an accounting period
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Statement {
+    /// Identifier of the statement
+    pub id: i64,
+    /// User of the statement
+    pub user: String,
+    /// Accounting period, as "YYYY-MM"
+    pub period: String,
+    /// Local date at which the period was closed
+    pub closed_at: String,
+    /// Balance at the end of the previous statement
+    pub opening_balance: f64,
+    /// Sum of the amounts received during the period
+    pub total_in: f64,
+    /// Sum of the amounts sent during the period
+    pub total_out: f64,
+    /// Balance at the end of the period
+    pub closing_balance: f64,
+    /// Number of transactions covered by the statement
+    pub nb_transactions: usize,
+}
+
+#[cfg(feature = "server")]
+/// Transaction covered by a statement, as printed on the PDF export
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+    /// Lamport timestamp of the transaction
+    pub lamport_time: i64,
+    /// ID of the node that created the transaction
+    pub source_node: String,
+    /// Source user of the transaction
+    pub from_user: String,
+    /// Destination user of the transaction
+    pub to_user: String,
+    /// Transaction amount
+    pub amount: f64,
+    /// Optional message associated with the transaction
+    pub optional_msg: Option<String>,
+}
+
+#[cfg(feature = "server")]
+/// Interval between two checks for the end of the month
+const STATEMENT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+#[cfg(feature = "server")]
+/// Number of text lines on a page of the PDF export
+const PDF_LINES_PER_PAGE: usize = 50;
+
+#[cfg(feature = "server")]
+/// Returns the current accounting period, as "YYYY-MM"
+pub fn current_period() -> String {
+    chrono::Local::now().format("%Y-%m").to_string()
+}
+
+#[cfg(feature = "server")]
+/// Reads a statement from a row of the statement queries
+fn statement_from_row(row: &rusqlite::Row) -> rusqlite::Result<Statement> {
+    Ok(Statement {
+        id: row.get(0)?,
+        user: row.get(1)?,
+        period: row.get(2)?,
+        closed_at: row.get(3)?,
+        opening_balance: row.get(4)?,
+        total_in: row.get(5)?,
+        total_out: row.get(6)?,
+        closing_balance: row.get(7)?,
+        nb_transactions: row.get::<_, i64>(8)? as usize,
+    })
+}
+
+#[cfg(feature = "server")]
+/// Columns of a statement, in the order read by [`statement_from_row`]
+const STATEMENT_COLUMNS: &str = "id, unique_name, period, closed_at, opening_balance, total_in,
+    total_out, closing_balance,
+    (SELECT COUNT(*) FROM StatementTransaction WHERE statement_id = Statements.id)";
+
+#[cfg(feature = "server")]
+/// Freezes the transactions of a user not covered by a previous statement
+/// into a new statement
+fn close_period_in(
+    conn: &rusqlite::Connection,
+    user: &str,
+    period: &str,
+    closed_at: &str,
+) -> rusqlite::Result<Statement> {
+    use rusqlite::{OptionalExtension, params};
+
+    let sql_tx = conn.unchecked_transaction()?;
+
+    let opening_balance: f64 = sql_tx
+        .query_row(
+            "SELECT closing_balance FROM Statements WHERE unique_name = ?1
+            ORDER BY id DESC LIMIT 1",
+            params![user],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0.0);
+
+    // Transactions received late from the network are taken by the next
+    // statement, since they are not linked to any statement of the user
+    let transactions = {
+        let mut stmt = sql_tx.prepare(
+            "SELECT t.lamport_time, t.source_node, t.from_user, t.to_user, t.amount
+            FROM Transactions t
+            WHERE (t.from_user = ?1 OR t.to_user = ?1)
+            AND NOT EXISTS (
+                SELECT 1 FROM StatementTransaction st
+                JOIN Statements s ON s.id = st.statement_id
+                WHERE s.unique_name = ?1
+                AND st.lamport_time = t.lamport_time
+                AND st.source_node = t.source_node
+            )
+            ORDER BY t.lamport_time, t.source_node",
+        )?;
+        let rows = stmt.query_map(params![user], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, f64>(4)?,
+            ))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+
+    let mut total_in = 0.0;
+    let mut total_out = 0.0;
+    for (_, _, from_user, to_user, amount) in &transactions {
+        if to_user == user {
+            total_in += amount;
+        }
+        if from_user == user {
+            total_out += amount;
+        }
+    }
+    let closing_balance = opening_balance + total_in - total_out;
+
+    sql_tx.execute(
+        "INSERT INTO Statements (unique_name, period, closed_at, opening_balance, total_in,
+            total_out, closing_balance)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            user,
+            period,
+            closed_at,
+            opening_balance,
+            total_in,
+            total_out,
+            closing_balance
+        ],
+    )?;
+    let id = sql_tx.last_insert_rowid();
+    for (lamport_time, source_node, _, _, _) in &transactions {
+        sql_tx.execute(
+            "INSERT INTO StatementTransaction (statement_id, lamport_time, source_node)
+            VALUES (?1, ?2, ?3)",
+            params![id, lamport_time, source_node],
+        )?;
+    }
+    sql_tx.commit()?;
+
+    Ok(Statement {
+        id,
+        user: user.to_string(),
+        period: period.to_string(),
+        closed_at: closed_at.to_string(),
+        opening_balance,
+        total_in,
+        total_out,
+        closing_balance,
+        nb_transactions: transactions.len(),
+    })
+}
+
+#[cfg(feature = "server")]
+/// Returns the statements of a user, most recent first
+fn statements_in(conn: &rusqlite::Connection, user: &str) -> rusqlite::Result<Vec<Statement>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM Statements WHERE unique_name = ?1 ORDER BY id DESC",
+        STATEMENT_COLUMNS
+    ))?;
+    let statements = stmt
+        .query_map(rusqlite::params![user], statement_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(statements)
+}
+
+#[cfg(feature = "server")]
+/// Returns a statement and the transactions it covers
+fn statement_with_lines_in(
+    conn: &rusqlite::Connection,
+    id: i64,
+) -> rusqlite::Result<Option<(Statement, Vec<StatementLine>)>> {
+    use rusqlite::{OptionalExtension, params};
+
+    let statement = conn
+        .query_row(
+            &format!("SELECT {} FROM Statements WHERE id = ?1", STATEMENT_COLUMNS),
+            params![id],
+            statement_from_row,
+        )
+        .optional()?;
+    let Some(statement) = statement else {
+        return Ok(None);
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT t.lamport_time, t.source_node, t.from_user, t.to_user, t.amount, t.optional_msg
+        FROM StatementTransaction st
+        JOIN Transactions t
+            ON t.lamport_time = st.lamport_time AND t.source_node = st.source_node
+        WHERE st.statement_id = ?1
+        ORDER BY t.lamport_time, t.source_node",
+    )?;
+    let lines = stmt
+        .query_map(params![id], |row| {
+            Ok(StatementLine {
+                lamport_time: row.get(0)?,
+                source_node: row.get(1)?,
+                from_user: row.get(2)?,
+                to_user: row.get(3)?,
+                amount: row.get(4)?,
+                optional_msg: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(Some((statement, lines)))
+}
+
+#[cfg(feature = "server")]
+/// Closes the accounting period of a user
+pub fn close_period(user: &str, period: &str) -> rusqlite::Result<Statement> {
+    let closed_at = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    close_period_in(&conn, user, period, &closed_at)
+}
+
+#[cfg(feature = "server")]
+/// Closes the accounting period of every active user
+pub fn close_period_for_all(period: &str) -> rusqlite::Result<Vec<Statement>> {
+    crate::db::get_users()?
+        .iter()
+        .map(|user| close_period(user, period))
+        .collect()
+}
+
+#[cfg(feature = "server")]
+/// Returns the statements of a user, most recent first
+pub fn get_statements(user: &str) -> rusqlite::Result<Vec<Statement>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    statements_in(&conn, user)
+}
+
+#[cfg(feature = "server")]
+/// Renders a statement as a PDF document, or None if it does not exist
+pub fn statement_pdf(id: i64) -> rusqlite::Result<Option<(Statement, Vec<u8>)>> {
+    let conn = crate::db::DB_CONN.lock().unwrap();
+    Ok(
+        statement_with_lines_in(&conn, id)?.map(|(statement, lines)| {
+            let pdf = render_pdf(&statement, &lines);
+            (statement, pdf)
+        }),
+    )
+}
+
+#[cfg(feature = "server")]
+/// Spawns a task that closes the accounting period of every user at the end
+/// of each month
+pub fn statement_worker() {
+    tokio::spawn(async {
+        let mut period = current_period();
+        let mut interval = tokio::time::interval(STATEMENT_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = current_period();
+            if now == period {
+                continue;
+            }
+            match close_period_for_all(&period) {
+                Ok(statements) => log::info!(
+                    "Closed the period {} with {} statements",
+                    period,
+                    statements.len()
+                ),
+                Err(e) => log::error!("Failed to close the period {}: {}", period, e),
+            }
+            period = now;
+        }
+    });
+}
+
+#[cfg(feature = "server")]
+/// Encodes a text for a PDF string using the WinAnsi encoding of the
+/// standard fonts
+fn pdf_text(text: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push(b'\\');
+                out.push(c as u8);
+            }
+            ' '..='~' => out.push(c as u8),
+            '€' => out.extend_from_slice(b"\\200"),
+            '\u{a0}'..='\u{ff}' => out.extend_from_slice(format!("\\{:03o}", c as u32).as_bytes()),
+            _ => out.push(b'?'),
+        }
+    }
+    out
+}
+
+#[cfg(feature = "server")]
+/// Renders a statement and its transactions as a PDF document
+///
+/// The document only uses the standard Helvetica font, so that no font has
+/// to be embedded; the transactions continue on as many pages as needed.
+pub fn render_pdf(statement: &Statement, lines: &[StatementLine]) -> Vec<u8> {
+    let mut text = vec![
+        "Peillute - Account statement".to_string(),
+        String::new(),
+        format!("User: {}", statement.user),
+        format!("Period: {}", statement.period),
+        format!("Closed at: {}", statement.closed_at),
+        String::new(),
+        format!("Opening balance: {:.2} €", statement.opening_balance),
+        format!("Total in: {:.2} €", statement.total_in),
+        format!("Total out: {:.2} €", statement.total_out),
+        format!("Closing balance: {:.2} €", statement.closing_balance),
+        String::new(),
+        format!("Transactions ({}):", lines.len()),
+    ];
+    for line in lines {
+        let mut entry = format!(
+            "[{} @ {}] {} -> {} : {:.2} €",
+            line.lamport_time, line.source_node, line.from_user, line.to_user, line.amount
+        );
+        if let Some(msg) = line.optional_msg.as_ref().filter(|m| !m.is_empty()) {
+            entry.push_str(&format!(" ({})", msg));
+        }
+        text.push(entry);
+    }
+
+    let pages: Vec<&[String]> = text.chunks(PDF_LINES_PER_PAGE).collect();
+
+    // Objects 1 to 3 are the catalog, the page tree and the font, then each
+    // page is followed by its content stream
+    let kids = (0..pages.len())
+        .map(|i| format!("{} 0 R", 4 + 2 * i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids, pages.len()).into_bytes(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    ];
+    for (i, page) in pages.iter().enumerate() {
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] \
+                /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                5 + 2 * i
+            )
+            .into_bytes(),
+        );
+
+        let mut content = b"BT\n/F1 10 Tf\n14 TL\n50 800 Td\n".to_vec();
+        for line in page.iter() {
+            content.push(b'(');
+            content.extend_from_slice(&pdf_text(line));
+            content.extend_from_slice(b") Tj\nT*\n");
+        }
+        content.extend_from_slice(b"ET");
+
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(&content);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref_offset = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref_offset
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    fn insert_transaction(
+        conn: &rusqlite::Connection,
+        from: &str,
+        to: &str,
+        amount: f64,
+        time: i64,
+    ) {
+        conn.execute(
+            "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node)
+            VALUES (?1, ?2, ?3, ?4, 0, 'A')",
+            rusqlite::params![from, to, amount, time],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_close_period() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        insert_transaction(&conn, "NULL", "alice", 100.0, 1);
+        insert_transaction(&conn, "alice", "bob", 30.0, 2);
+
+        let first = close_period_in(&conn, "alice", "2026-09", "2026-09-30 23:00:00").unwrap();
+        assert_eq!(first.opening_balance, 0.0);
+        assert_eq!(first.total_in, 100.0);
+        assert_eq!(first.total_out, 30.0);
+        assert_eq!(first.closing_balance, 70.0);
+        assert_eq!(first.nb_transactions, 2);
+
+        // Only the transactions after the first statement are covered
+        insert_transaction(&conn, "bob", "alice", 5.0, 3);
+        let second = close_period_in(&conn, "alice", "2026-10", "2026-10-31 23:00:00").unwrap();
+        assert_eq!(second.opening_balance, 70.0);
+        assert_eq!(second.total_in, 5.0);
+        assert_eq!(second.total_out, 0.0);
+        assert_eq!(second.closing_balance, 75.0);
+        assert_eq!(second.nb_transactions, 1);
+
+        let statements = statements_in(&conn, "alice").unwrap();
+        assert_eq!(statements, vec![second.clone(), first]);
+
+        let (statement, lines) = statement_with_lines_in(&conn, second.id).unwrap().unwrap();
+        assert_eq!(statement, second);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].from_user, "bob");
+        assert!(statement_with_lines_in(&conn, 42).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_render_pdf() {
+        let statement = Statement {
+            id: 1,
+            user: "alice (test)".to_string(),
+            period: "2026-10".to_string(),
+            closed_at: "2026-10-31 23:00:00".to_string(),
+            opening_balance: 0.0,
+            total_in: 10.0,
+            total_out: 0.0,
+            closing_balance: 10.0,
+            nb_transactions: 60,
+        };
+        let lines: Vec<StatementLine> = (0..60)
+            .map(|i| StatementLine {
+                lamport_time: i,
+                source_node: "A".to_string(),
+                from_user: "NULL".to_string(),
+                to_user: "alice".to_string(),
+                amount: 1.0 / 6.0,
+                optional_msg: None,
+            })
+            .collect();
+
+        let pdf = render_pdf(&statement, &lines);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("(User: alice \\(test\\)) Tj"));
+        assert!(text.contains("Closing balance: 10.00 \\200"));
+
+        // The cross-reference table points to the start of each object
+        let xref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(text[xref..].starts_with("xref\n0 8\n"));
+        let first_offset: usize = text[xref..].lines().nth(3).unwrap()[..10].parse().unwrap();
+        assert!(text[first_offset..].starts_with("1 0 obj\n"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Disk space and database size guardrails
+//!
+//! A site whose disk is full cannot record the transactions diffused by the
+//! other sites anymore, and diverges from them. New transactions are refused
+//! as soon as the free space of the disk holding the database falls below a
+//! threshold, or the database grows beyond a maximum size, so that some room
+//! is always left for the transactions of the other sites, which are still
+//! applied. The space is checked when the site starts, then every minute, and
+//! before each new transaction.
+
+/// Storage numbers displayed on the Info page and the metrics endpoint
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StorageUsage {
+    /// Size of the database, in bytes
+    pub db_size: u64,
+    /// Space available on the disk holding the database, in bytes
+    pub free_space: u64,
+    /// Free space below which new transactions are refused, in bytes
+    pub min_free_space: u64,
+    /// Size of the database above which new transactions are refused, in
+    /// bytes, 0 if unlimited
+    pub max_db_size: u64,
+}
+
+impl StorageUsage {
+    /// Returns the reason why new transactions are refused, if they are
+    pub fn refusal(&self) -> Option<String> {
+        if self.free_space < self.min_free_space {
+            Some(format!(
+                "Not enough disk space: {} bytes free, at least {} bytes required, new transactions are refused",
+                self.free_space, self.min_free_space
+            ))
+        } else if self.max_db_size > 0 && self.db_size >= self.max_db_size {
+            Some(format!(
+                "Database too large: {} bytes for a limit of {} bytes, new transactions are refused",
+                self.db_size, self.max_db_size
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Default free space below which new transactions are refused (100 MiB)
+pub const DEFAULT_MIN_FREE_SPACE: u64 = 100 * 1024 * 1024;
+
+#[cfg(feature = "server")]
+/// Free space below which new transactions are refused, set from the command line at startup
+static MIN_FREE_SPACE: std::sync::atomic::AtomicU64 =
+    std::sync::atomic::AtomicU64::new(DEFAULT_MIN_FREE_SPACE);
+
+#[cfg(feature = "server")]
+/// Maximum size of the database, 0 if unlimited, set from the command line at startup
+static MAX_DB_SIZE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+#[cfg(feature = "server")]
+/// Interval between two checks of the storage
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[cfg(feature = "server")]
+/// Sets the free space and the database size limits of the site
+pub fn set_limits(min_free_space: u64, max_db_size: u64) {
+    MIN_FREE_SPACE.store(min_free_space, std::sync::atomic::Ordering::Relaxed);
+    MAX_DB_SIZE.store(max_db_size, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "server")]
+/// Measures the size of the database and the free space of its disk
+pub fn usage() -> Result<StorageUsage, Box<dyn std::error::Error>> {
+    let (path, db_size) = crate::db::database_file()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => std::path::PathBuf::from("."),
+    };
+    let free_space = fs2::available_space(&dir)
+        .map_err(|e| format!("cannot read the free space of {}: {}", dir.display(), e))?;
+
+    Ok(StorageUsage {
+        db_size: db_size.max(0) as u64,
+        free_space,
+        min_free_space: MIN_FREE_SPACE.load(std::sync::atomic::Ordering::Relaxed),
+        max_db_size: MAX_DB_SIZE.load(std::sync::atomic::Ordering::Relaxed),
+    })
+}
+
+#[cfg(feature = "server")]
+/// Returns an error if new transactions must be refused
+pub fn check() -> Result<(), Box<dyn std::error::Error>> {
+    match usage()?.refusal() {
+        Some(reason) => Err(reason.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "server")]
+/// Starts the task checking the storage at startup and every minute
+///
+/// Logs an error when new transactions start being refused, and once they
+/// are accepted again.
+pub fn storage_worker() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut refused = false;
+        loop {
+            interval.tick().await;
+            let usage = match usage() {
+                Ok(usage) => usage,
+                Err(e) => {
+                    log::warn!("Cannot check the storage: {}", e);
+                    continue;
+                }
+            };
+            match usage.refusal() {
+                Some(reason) if !refused => {
+                    log::error!("{}", reason);
+                    refused = true;
+                }
+                None if refused => {
+                    log::info!("Enough storage again, new transactions are accepted");
+                    refused = false;
+                }
+                _ => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+#[cfg(feature = "server")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refusal() {
+        let mut usage = StorageUsage {
+            db_size: 4096,
+            free_space: 200,
+            min_free_space: 100,
+            max_db_size: 0,
+        };
+        assert_eq!(usage.refusal(), None);
+
+        usage.max_db_size = 4096;
+        assert!(usage.refusal().unwrap().starts_with("Database too large"));
+
+        usage.free_space = 99;
+        assert!(
+            usage
+                .refusal()
+                .unwrap()
+                .starts_with("Not enough disk space")
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! System accounts of the ledger and their reconciliation
+//!
+//! The transactions record the money entering and leaving Peillute as coming
+//! from or going to the null user. In the postings, the null user is replaced
+//! by a named system account for each kind of flow: the deposits, the
+//! withdrawals and the payments. By default, the deposits are debited from
+//! `@cash-in` and the withdrawals and payments are credited to `@cash-out`;
+//! a site may name them in a TOML file:
+//!
+//! ```toml
+//! deposit = "@cash"
+//! withdraw = "@cash"
+//! pay = "@merchant"
+//! ```
+//!
+//! All the money entering Peillute, initial balances and refunds included, is a
+//! deposit. The names of the system accounts start with `@` and no user can
+//! take them. The postings are local to the site: the snapshots still name
+//! the flows `@cash-in` and `@cash-out`, so that sites with different files
+//! stay consistent.
+//!
+//! `/reconcile` compares the total balance of the users with the balances of
+//! the system accounts, now or at a point in time: as every transaction
+//! debits an account and credits another, they sum to zero.
+
+#![cfg(feature = "server")]
+
+/// Returns the default account of the deposits
+fn default_deposit() -> String {
+    crate::db::CASH_IN.to_string()
+}
+
+/// Returns the default account of the withdrawals and payments
+fn default_cash_out() -> String {
+    crate::db::CASH_OUT.to_string()
+}
+
+/// Content of a system account configuration file
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SystemAccounts {
+    /// Account debited by the money entering Peillute
+    #[serde(default = "default_deposit")]
+    pub deposit: String,
+    /// Account credited by the withdrawals
+    #[serde(default = "default_cash_out")]
+    pub withdraw: String,
+    /// Account credited by the payments
+    #[serde(default = "default_cash_out")]
+    pub pay: String,
+}
+
+impl Default for SystemAccounts {
+    fn default() -> Self {
+        SystemAccounts {
+            deposit: default_deposit(),
+            withdraw: default_cash_out(),
+            pay: default_cash_out(),
+        }
+    }
+}
+
+impl SystemAccounts {
+    /// Parses and validates the content of a system account configuration file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let accounts: SystemAccounts =
+            toml::from_str(content).map_err(|e| format!("invalid system account file: {}", e))?;
+        for name in [&accounts.deposit, &accounts.withdraw, &accounts.pay] {
+            let valid = name.strip_prefix('@').is_some_and(|rest| {
+                !rest.is_empty()
+                    && rest
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+            if !valid {
+                return Err(format!(
+                    "invalid system account '{}': expected '@' followed by letters, digits, '-' or '_'",
+                    name
+                ));
+            }
+        }
+        Ok(accounts)
+    }
+
+    /// Reads and validates a system account configuration file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read system account file {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    /// Returns true if a name is the one of a configured system account
+    pub fn contains(&self, name: &str) -> bool {
+        [&self.deposit, &self.withdraw, &self.pay]
+            .into_iter()
+            .any(|account| account == name)
+    }
+
+    /// Returns the accounts debited and credited by a transaction between two
+    /// users, the null user being replaced by the system account of the flow
+    pub fn posting_accounts<'a>(
+        &'a self,
+        from_user: &'a str,
+        to_user: &'a str,
+        optional_msg: &str,
+    ) -> (&'a str, &'a str) {
+        let debit = if from_user == crate::db::NULL {
+            self.deposit.as_str()
+        } else {
+            from_user
+        };
+        let credit = if to_user != crate::db::NULL {
+            to_user
+        } else if optional_msg == crate::db::WITHDRAW_MSG {
+            self.withdraw.as_str()
+        } else {
+            self.pay.as_str()
+        };
+        (debit, credit)
+    }
+}
+
+/// System accounts of the site, set at startup
+static ACCOUNTS: std::sync::OnceLock<SystemAccounts> = std::sync::OnceLock::new();
+
+/// Sets the system accounts of the postings recorded by the site
+pub fn configure(accounts: SystemAccounts) {
+    if ACCOUNTS.set(accounts).is_err() {
+        log::warn!("System accounts are already configured");
+    }
+}
+
+/// Returns the system accounts of the site, the default ones if not configured
+pub fn current() -> &'static SystemAccounts {
+    ACCOUNTS.get_or_init(SystemAccounts::default)
+}
+
+/// Comparison of the balances of the users with those of the system accounts
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct Reconciliation {
+    /// Point in time of the balances, in seconds since the Unix epoch, None for now
+    pub at: Option<i64>,
+    /// Sum of the balances of the users
+    pub users_total: f64,
+    /// Balance of every system account
+    pub system_accounts: Vec<(String, f64)>,
+    /// Sum of the balances of the system accounts
+    pub system_total: f64,
+}
+
+impl Reconciliation {
+    /// Returns true if the users and the system accounts sum to zero
+    pub fn is_reconciled(&self) -> bool {
+        (self.users_total + self.system_total).abs() < 0.005
+    }
+}
+
+/// Reconciles the balances of a database, counting the transactions recorded
+/// up to a point in time, or all of them
+///
+/// Every account which is not a user is a system account, so that the
+/// accounts of a former configuration are still counted. The transactions
+/// recorded without time, by a replay, are counted at any point in time.
+fn reconcile_in(conn: &rusqlite::Connection, at: Option<i64>) -> rusqlite::Result<Reconciliation> {
+    let mut stmt = conn.prepare(
+        "SELECT p.account,
+            SUM(CASE p.side WHEN 'credit' THEN p.amount_in_cent ELSE -p.amount_in_cent END),
+            EXISTS(SELECT 1 FROM User u WHERE u.unique_name = p.account)
+        FROM Postings p
+        JOIN Transactions t ON t.lamport_time = p.lamport_time AND t.source_node = p.source_node
+        WHERE ?1 IS NULL OR IFNULL(t.created_at, 0) <= ?1
+        GROUP BY p.account ORDER BY p.account",
+    )?;
+    let rows = stmt
+        .query_map([at], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, bool>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut users_cents = 0;
+    let mut system_cents = 0;
+    let mut system_accounts = Vec::new();
+    for (account, cents, is_user) in rows {
+        if is_user {
+            users_cents += cents;
+        } else {
+            system_cents += cents;
+            system_accounts.push((account, cents as f64 / 100.0));
+        }
+    }
+    Ok(Reconciliation {
+        at,
+        users_total: users_cents as f64 / 100.0,
+        system_accounts,
+        system_total: system_cents as f64 / 100.0,
+    })
+}
+
+/// Reconciles the balances of the site, up to a point in time or now
+pub fn reconcile(at: Option<i64>) -> rusqlite::Result<Reconciliation> {
+    reconcile_in(&crate::db::DB_CONN.lock().unwrap(), at)
+}
+
+/// Parses a point in time in local time, `YYYY-MM-DD HH:MM:SS` or
+/// `YYYY-MM-DD` for the end of the day, into seconds since the Unix epoch
+pub fn parse_time(value: &str) -> Result<i64, String> {
+    use chrono::TimeZone;
+
+    let value = value.trim();
+    let time = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(23, 59, 59).unwrap())
+        })
+        .map_err(|_| {
+            format!(
+                "invalid time '{}': expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS",
+                value
+            )
+        })?;
+    chrono::Local
+        .from_local_datetime(&time)
+        .latest()
+        .map(|time| time.timestamp())
+        .ok_or_else(|| format!("time '{}' does not exist in the local time zone", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let accounts = SystemAccounts::parse("deposit = \"@cash\"\npay = \"@merchant\"").unwrap();
+        assert_eq!(accounts.deposit, "@cash");
+        assert_eq!(accounts.withdraw, crate::db::CASH_OUT);
+        assert_eq!(
+            accounts.posting_accounts("NULL", "alice", "Deposit"),
+            ("@cash", "alice")
+        );
+        assert_eq!(
+            accounts.posting_accounts("alice", "NULL", crate::db::WITHDRAW_MSG),
+            ("alice", crate::db::CASH_OUT)
+        );
+        assert_eq!(
+            accounts.posting_accounts("alice", "NULL", "Split payment"),
+            ("alice", "@merchant")
+        );
+        assert!(SystemAccounts::parse("pay = \"merchant\"").is_err());
+        assert!(SystemAccounts::parse("pay = \"@\"").is_err());
+        assert!(SystemAccounts::parse("fees = \"@fees\"").is_err());
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        let accounts = SystemAccounts::parse("deposit = \"@cash\"\npay = \"@merchant\"").unwrap();
+        for (lamport_time, from, to, amount, msg, at) in [
+            (1, "NULL", "alice", 10.0, "Deposit", 100),
+            (2, "alice", "bob", 4.0, "", 200),
+            (3, "alice", "NULL", 2.5, "", 300),
+        ] {
+            conn.execute(
+                "INSERT INTO Transactions (from_user, to_user, amount, lamport_time, vector_clock_id, source_node, optional_msg, created_at)
+                VALUES (?1, ?2, ?3, ?4, 0, 'A', ?5, ?6)",
+                rusqlite::params![from, to, amount, lamport_time, msg, at],
+            )
+            .unwrap();
+            let (debit, credit) = accounts.posting_accounts(from, to, msg);
+            for (account, side) in [(debit, "debit"), (credit, "credit")] {
+                conn.execute(
+                    "INSERT INTO Postings (lamport_time, source_node, account, side, amount_in_cent)
+                    VALUES (?1, 'A', ?2, ?3, ?4)",
+                    rusqlite::params![lamport_time, account, side, (amount * 100.0) as i64],
+                )
+                .unwrap();
+            }
+        }
+        conn.execute_batch(
+            "INSERT INTO User (unique_name, solde) VALUES ('alice', 3.5), ('bob', 4.0)",
+        )
+        .unwrap();
+
+        let now = reconcile_in(&conn, None).unwrap();
+        assert!(now.is_reconciled());
+        assert_eq!(now.users_total, 7.5);
+        assert_eq!(
+            now.system_accounts,
+            vec![("@cash".to_string(), -10.0), ("@merchant".to_string(), 2.5)]
+        );
+
+        let before_payment = reconcile_in(&conn, Some(250)).unwrap();
+        assert!(before_payment.is_reconciled());
+        assert_eq!(before_payment.users_total, 10.0);
+        assert_eq!(
+            before_payment.system_accounts,
+            vec![("@cash".to_string(), -10.0)]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Maintenance tasks run from the command line without starting a site
+//!
+//! The `snapshot`, `verify-db`, `export` and `keygen` subcommands open the
+//! database of a site, run their task and exit: the site does not join the
+//! network nor serve the web interface, so that they can run on a stopped
+//! site, from a cron job or a backup script. They refuse to run on a
+//! database that does not exist rather than creating an empty one.
+
+#![cfg(feature = "server")]
+
+/// Result of the verification of a database
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct DbVerification {
+    /// Problems reported by the integrity check of SQLite
+    pub integrity_errors: Vec<String>,
+    /// Audit of the postings of every account
+    pub audit: crate::db::LedgerAudit,
+}
+
+impl DbVerification {
+    /// Returns true if the database is intact and every account balances
+    pub fn is_sound(&self) -> bool {
+        self.integrity_errors.is_empty() && self.audit.is_balanced()
+    }
+}
+
+/// Balance and transactions of a user, as exported by `peillute export`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct UserExport {
+    /// Name of the user
+    pub user: String,
+    /// Current balance of the user
+    pub balance: f64,
+    /// Transactions of the user
+    pub transactions: Vec<crate::db::Transaction>,
+}
+
+/// Opens the database of a site for a task, which must already exist
+pub fn open_database(path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Err(format!("No database at {}", path.display()).into());
+    }
+    crate::db::open(path)?;
+    if !crate::db::is_database_initialized()? {
+        return Err(format!("{} is not a Peillute database", path.display()).into());
+    }
+    crate::db::upgrade_db()?;
+    Ok(())
+}
+
+/// Writes a snapshot of the transactions known by the site to a file
+///
+/// Returns the number of transactions written.
+pub fn snapshot(
+    output: &std::path::Path,
+    label: Option<String>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    if let Some(label) = &label {
+        crate::snapshot::validate_label(label)?;
+    }
+    let (site_id, clock) = crate::db::get_local_state()?;
+    let snapshot = crate::snapshot::local_only(&site_id, &clock, label)?;
+    crate::snapshot::export_snapshot(output, &snapshot)?;
+    Ok(snapshot.all_transactions.len())
+}
+
+/// Returns the problems reported by the integrity check of a database
+fn integrity_errors_in(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut errors = Vec::new();
+    for row in rows {
+        let row = row?;
+        if row != "ok" {
+            errors.push(row);
+        }
+    }
+    Ok(errors)
+}
+
+/// Checks the integrity of the database and the postings of every account
+pub fn verify_db() -> rusqlite::Result<DbVerification> {
+    let integrity_errors = integrity_errors_in(&crate::db::DB_CONN.lock().unwrap())?;
+    Ok(DbVerification {
+        integrity_errors,
+        audit: crate::db::audit_ledger()?,
+    })
+}
+
+/// Returns the balance and transactions of a user
+pub fn export_user(user: &str) -> Result<UserExport, Box<dyn std::error::Error>> {
+    if crate::db::is_system_account(user) || !crate::db::user_exists(user)? {
+        return Err(format!("User '{}' does not exist.", user).into());
+    }
+    Ok(UserExport {
+        user: user.to_string(),
+        balance: crate::db::calculate_solde(user)?,
+        transactions: crate::db::get_transactions_for_user(user)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrity_errors() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        crate::db::upgrade_schema(&conn).unwrap();
+        assert!(integrity_errors_in(&conn).unwrap().is_empty());
+
+        let verification = DbVerification {
+            integrity_errors: vec!["row 3 missing from index".to_string()],
+            ..Default::default()
+        };
+        assert!(!verification.is_sound());
+        assert!(DbVerification::default().is_sound());
+    }
+}
@@ -0,0 +1,270 @@
+//! Tracing of the waves across the sites
+//!
+//! Each wave going through a site is covered by a `wave` span of the
+//! [`tracing`] crate, opened when the site initiates the wave or first
+//! receives it, and closed when the site answers its parent or, on the
+//! initiator, when the wave ends. The steps of the wave on the site, see
+//! [`crate::attach::WaveStep`], and the acknowledgements it receives are
+//! recorded as events of the span.
+//!
+//! A site built with the `otlp` feature and started with `--otlp-endpoint`
+//! exports the spans to an OpenTelemetry collector, such as Jaeger:
+//!
+//! ```sh
+//! cargo run --features otlp -- --otlp-endpoint http://localhost:4317
+//! ```
+//!
+//! The messages of a wave then carry the trace and span IDs of the wave on
+//! their sender, see [`TraceContext`], and the span of a site is the child of
+//! the span of its parent in the wave, so that the propagation of a
+//! transaction to every site shows as one trace. Without an exporter, the
+//! messages carry no IDs.
+
+#![cfg(feature = "server")]
+
+/// Time after which the span of a wave that did not end on the site is closed
+const WAVE_SPAN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Trace and span IDs of the wave on the sender of a message, in the W3C
+/// trace context format
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TraceContext {
+    /// ID of the trace of the wave, 32 hexadecimal digits
+    pub trace_id: String,
+    /// ID of the span of the wave on the sender, 16 hexadecimal digits
+    pub span_id: String,
+}
+
+/// Span of a wave open on the site
+struct WaveSpan {
+    /// Span, closed once every handle is dropped
+    span: tracing::Span,
+    /// Time the span was opened
+    opened: std::time::Instant,
+}
+
+lazy_static::lazy_static! {
+    /// Spans of the waves open on the site, by initiator and Lamport time of the wave
+    static ref WAVE_SPANS: std::sync::Mutex<std::collections::HashMap<(String, i64), WaveSpan>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+/// Returns the ID of the wave of a message, its initiator and Lamport time
+fn wave_id(message: &crate::message::Message) -> (String, i64) {
+    (
+        message.message_initiator_id.clone(),
+        *message.clock.get_lamport(),
+    )
+}
+
+/// Opens the span of the wave of a message, child of the span of its sender
+fn open(message: &crate::message::Message) -> tracing::Span {
+    let span = tracing::info_span!(
+        "wave",
+        initiator = %message.message_initiator_id,
+        lamport = *message.clock.get_lamport(),
+        code = ?message.code,
+        parent_site = %message.sender_id,
+    );
+    #[cfg(feature = "otlp")]
+    if let Some(context) = &message.trace {
+        otlp::set_parent(&span, context);
+    }
+    span
+}
+
+/// Records the step of the wave of a message in the span of the wave
+///
+/// The span is opened on the first step of the wave on the site, and closed
+/// when the site answers its parent or the wave ends.
+pub fn wave_step(message: &crate::message::Message, step: crate::attach::WaveStep) {
+    use crate::attach::WaveStep;
+
+    let id = wave_id(message);
+    let mut spans = WAVE_SPANS.lock().unwrap();
+    spans.retain(|_, wave| wave.opened.elapsed() < WAVE_SPAN_TIMEOUT);
+    let opened = !spans.contains_key(&id);
+    let span = spans
+        .entry(id.clone())
+        .or_insert_with(|| WaveSpan {
+            span: open(message),
+            opened: std::time::Instant::now(),
+        })
+        .span
+        .clone();
+    tracing::info!(parent: &span, ?step, from = %message.sender_id, "wave step");
+
+    // a leaf acknowledges the wave as soon as it receives it
+    let ended = match step {
+        WaveStep::Forwarded | WaveStep::Completed => true,
+        WaveStep::Acknowledged => opened,
+        WaveStep::Started | WaveStep::Diffused => false,
+    };
+    if ended {
+        spans.remove(&id);
+    }
+}
+
+/// Records an acknowledgement of a wave in the span of the wave, if open
+pub fn ack_received(message: &crate::message::Message) {
+    if let Some(wave) = WAVE_SPANS.lock().unwrap().get(&wave_id(message)) {
+        tracing::info!(
+            parent: &wave.span,
+            from = %message.sender_id,
+            child_span = message.trace.as_ref().map(|trace| trace.span_id.as_str()),
+            "acknowledgement"
+        );
+    }
+}
+
+#[cfg(feature = "otlp")]
+/// Returns the trace context carried by the messages of a wave sent by the
+/// site, None if the wave has no span open or its spans are not exported
+pub fn context(initiator_id: &str, lamport: i64) -> Option<TraceContext> {
+    let spans = WAVE_SPANS.lock().unwrap();
+    let wave = spans.get(&(initiator_id.to_string(), lamport))?;
+    otlp::context(&wave.span)
+}
+
+#[cfg(not(feature = "otlp"))]
+/// Returns the trace context carried by the messages of a wave sent by the
+/// site, always None as the spans are not exported
+pub fn context(_initiator_id: &str, _lamport: i64) -> Option<TraceContext> {
+    None
+}
+
+#[cfg(feature = "otlp")]
+/// Exports the spans of the waves to the OpenTelemetry collector listening on
+/// an endpoint, with the gRPC transport of OTLP
+///
+/// Each site is a service of its own, `peillute-<site-id>`.
+pub fn init(endpoint: &str, site_id: &str) -> Result<(), String> {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| format!("cannot export the spans to {}: {}", endpoint, e))?;
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new([
+            opentelemetry::KeyValue::new("service.name", format!("peillute-{}", site_id)),
+            opentelemetry::KeyValue::new("service.instance.id", site_id.to_string()),
+        ]))
+        .build();
+    let tracer = provider.tracer("peillute");
+    opentelemetry::global::set_tracer_provider(provider);
+
+    // only the spans of the waves are exported, not those of the libraries
+    let waves = tracing_subscriber::filter::Targets::new()
+        .with_target(module_path!(), tracing::Level::INFO);
+    tracing_subscriber::registry()
+        .with(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(waves),
+        )
+        .try_init()
+        .map_err(|e| format!("cannot install the tracing subscriber: {}", e))?;
+    log::info!("Exporting the spans of the waves to {}", endpoint);
+    Ok(())
+}
+
+#[cfg(feature = "otlp")]
+/// Exports the spans not sent yet, before the site stops
+pub async fn shutdown() {
+    // the exporter blocks until the last batch is sent
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+#[cfg(feature = "otlp")]
+/// Conversion between the spans of the waves and their OpenTelemetry context
+mod otlp {
+    use super::TraceContext;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    /// Makes a span the child of the span of the sender of a message
+    pub fn set_parent(span: &tracing::Span, context: &TraceContext) {
+        let (Ok(trace_id), Ok(span_id)) = (
+            TraceId::from_hex(&context.trace_id),
+            SpanId::from_hex(&context.span_id),
+        ) else {
+            log::warn!("Invalid trace context {:?}", context);
+            return;
+        };
+        let remote = SpanContext::new(
+            trace_id,
+            span_id,
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        span.set_parent(opentelemetry::Context::new().with_remote_span_context(remote));
+    }
+
+    /// Returns the trace context of a span, None if it is not exported
+    pub fn context(span: &tracing::Span) -> Option<TraceContext> {
+        let context = span.context();
+        let span_ref = context.span();
+        let span_context = span_ref.span_context();
+        span_context.is_valid().then(|| TraceContext {
+            trace_id: span_context.trace_id().to_string(),
+            span_id: span_context.span_id().to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attach::WaveStep;
+    use crate::message::{Message, MessageInfo, NetworkMessageCode};
+
+    fn message(initiator: &str, sender: &str) -> Message {
+        let addr: std::net::SocketAddr = "127.0.0.1:10000".parse().unwrap();
+        Message {
+            sender_id: sender.to_string(),
+            sender_addr: addr,
+            message_initiator_id: initiator.to_string(),
+            message_initiator_addr: addr,
+            clock: crate::clock::Clock::new(),
+            command: None,
+            info: MessageInfo::None,
+            code: NetworkMessageCode::Transaction,
+            trace: None,
+        }
+    }
+
+    fn is_open(initiator: &str) -> bool {
+        WAVE_SPANS
+            .lock()
+            .unwrap()
+            .contains_key(&(initiator.to_string(), 0))
+    }
+
+    #[test]
+    fn test_wave_spans() {
+        // the wave reaches the site, which diffuses it
+        wave_step(&message("span-A", "span-A"), WaveStep::Diffused);
+        assert!(is_open("span-A"));
+        // a copy from another neighbour is acknowledged within the span
+        wave_step(&message("span-A", "span-C"), WaveStep::Acknowledged);
+        ack_received(&message("span-A", "span-C"));
+        assert!(is_open("span-A"));
+        wave_step(&message("span-A", "span-C"), WaveStep::Forwarded);
+        assert!(!is_open("span-A"));
+
+        // a leaf acknowledges the wave right away
+        wave_step(&message("span-B", "span-A"), WaveStep::Acknowledged);
+        assert!(!is_open("span-B"));
+        assert_eq!(context("span-B", 0), None);
+    }
+}
@@ -0,0 +1,140 @@
+#[cfg(feature = "server")]
+pub fn get_mac_address() -> Option<String> {
+    use pnet::datalink;
+    let interfaces = datalink::interfaces();
+    for iface in interfaces {
+        // Ignore loopback et interfaces sans MAC
+        if iface.is_up()
+            && !iface.is_loopback()
+            && let Some(mac) = iface.mac
+            && mac.octets() != [0, 0, 0, 0, 0, 0]
+        {
+            return Some(mac.to_string().replace(":", ""));
+        }
+    }
+    None
+}
+#[cfg(feature = "server")]
+pub async fn reload_existing_site() -> Result<(String, crate::clock::Clock), String> {
+    use log::info;
+    match crate::db::get_local_state() {
+        Ok((site_id, clock)) => {
+            info!("Existing site state reloaded");
+            Ok((site_id, clock))
+        }
+        Err(e) => {
+            info!("No existing site state found, creating a new one.");
+            Err(format!("Failed to reload existing site: {}", e))
+        }
+    }
+}
+
+/// Splits an amount equally between a number of beneficiaries
+///
+/// Shares are rounded to the cent and the remaining cents go to the first
+/// beneficiaries, so that the shares always sum to the total.
+pub fn split_equally(total: f64, nb: usize) -> Vec<f64> {
+    if nb == 0 {
+        return Vec::new();
+    }
+    let total_cents = (total * 100.0).round() as i64;
+    let base = total_cents / nb as i64;
+    let remainder = (total_cents % nb as i64) as usize;
+    (0..nb)
+        .map(|i| {
+            let cents = if i < remainder { base + 1 } else { base };
+            cents as f64 / 100.0
+        })
+        .collect()
+}
+
+/// Adds a tip, a percentage of the amount rounded to the cent, to an amount
+pub fn with_tip(amount: f64, tip_percent: f64) -> f64 {
+    amount + (amount * tip_percent).round() / 100.0
+}
+
+/// Adds a tip to a bill and splits it equally between a number of payers
+pub fn split_with_tip(amount: f64, tip_percent: f64, nb: usize) -> Vec<f64> {
+    split_equally(with_tip(amount, tip_percent), nb)
+}
+
+/// Encodes bytes in standard base64, with padding
+pub fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Encodes an uploaded image as a data URL, to store it along its product
+///
+/// Returns None if the file extension is not a known image type.
+pub fn image_data_url(file_name: &str, bytes: &[u8]) -> Option<String> {
+    let extension = file_name.rsplit('.').next()?.to_ascii_lowercase();
+    let mime = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        _ => return None,
+    };
+    Some(format!("data:{};base64,{}", mime, base64_encode(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_equally() {
+        assert_eq!(split_equally(30.0, 3), vec![10.0, 10.0, 10.0]);
+        assert_eq!(split_equally(10.0, 3), vec![3.34, 3.33, 3.33]);
+        assert_eq!(split_equally(0.01, 2), vec![0.01, 0.0]);
+        assert!(split_equally(10.0, 0).is_empty());
+
+        let shares = split_equally(100.0, 7);
+        let sum: f64 = shares.iter().sum();
+        assert!((sum - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_split_with_tip() {
+        assert_eq!(split_with_tip(20.0, 0.0, 1), vec![20.0]);
+        assert_eq!(split_with_tip(20.0, 15.0, 1), vec![23.0]);
+        assert_eq!(split_with_tip(10.0, 12.5, 3), vec![3.75, 3.75, 3.75]);
+        assert_eq!(split_with_tip(9.99, 10.0, 2), vec![5.5, 5.49]);
+    }
+
+    #[test]
+    fn test_image_data_url() {
+        assert_eq!(
+            image_data_url("a.PNG", b"Man").as_deref(),
+            Some("data:image/png;base64,TWFu")
+        );
+        assert_eq!(
+            image_data_url("a.jpg", b"Ma").as_deref(),
+            Some("data:image/jpeg;base64,TWE=")
+        );
+        assert_eq!(
+            image_data_url("a.gif", b"M").as_deref(),
+            Some("data:image/gif;base64,TQ==")
+        );
+        assert_eq!(image_data_url("notes.txt", b"Man"), None);
+    }
+}
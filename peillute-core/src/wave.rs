@@ -0,0 +1,447 @@
+//! Wave diffusion state machine
+//!
+//! Operations, global mutex requests and snapshot requests are diffused with
+//! an echo wave: the initiator sends a blue message to its neighbours, each
+//! site reached for the first time adopts the sender as its parent and
+//! forwards the message to its other neighbours, and every site answers with
+//! a red message once all its children answered. The wave ends when the
+//! initiator received a red message from each of its neighbours.
+//!
+//! [`WaveEngine`] holds the progress of the waves going through a site, one
+//! per initiator, and turns each received message into the action the site
+//! must take: [`WaveEngine::on_blue_message`] and
+//! [`WaveEngine::on_red_message`]. The network layer and the simulator only
+//! decide what to send, never how the wave progresses.
+
+#![cfg(feature = "server")]
+
+/// Address used as parent of a wave that is not in progress
+fn no_parent() -> std::net::SocketAddr {
+    "0.0.0.0:0".parse().unwrap()
+}
+
+/// Action to take on a message (blue) of a wave
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlueTransition {
+    /// First message of the wave on this site: diffuse it to every neighbour
+    /// but the parent
+    Diffuse,
+    /// Acknowledge the message to its sender
+    Acknowledge {
+        /// True if the wave reached this site with this message, so it is a
+        /// leaf of the wave, false if the wave already went through it
+        first_visit: bool,
+    },
+}
+
+/// Action to take on an acknowledgement (red) of a wave
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedTransition {
+    /// Some neighbours did not answer yet
+    Wait,
+    /// Every child answered: acknowledge the wave to the parent
+    ForwardToParent(std::net::SocketAddr),
+    /// Every neighbour answered the wave initiated by this site
+    Completed,
+}
+
+/// State of the wave diffusions, per initiator
+#[derive(Default)]
+pub struct WaveEngine {
+    /// Adress of the parent (deg(1) neighbour for this site) for a specific wave from initiator id
+    pub parent_addr_for_transaction_wave: std::collections::HashMap<String, std::net::SocketAddr>,
+    /// Number of response expected from our direct neighbours (deg(1) neighbours for this site) = nb of connected neighbours - 1 (parent) for a specific wave initiator id
+    pub attended_neighbours_nb_for_transaction_wave: std::collections::HashMap<String, i64>,
+    /// Number of sites that acknowledged a transaction wave from initiator id through our children
+    pub confirmed_sites_for_transaction_wave: std::collections::HashMap<String, i64>,
+}
+
+impl WaveEngine {
+    /// Set the number of attended neighbors for the wave from initiator_id
+    pub fn set_nb_nei_for_wave(&mut self, initiator_id: String, n: i64) {
+        self.attended_neighbours_nb_for_transaction_wave
+            .insert(initiator_id, n);
+    }
+
+    /// Get the parent of each wave, per initiator
+    pub fn get_parent_for_wave_map(
+        &self,
+    ) -> std::collections::HashMap<String, std::net::SocketAddr> {
+        self.parent_addr_for_transaction_wave.clone()
+    }
+
+    /// Get the number of attended neighbors of each wave, per initiator
+    pub fn get_nb_nei_for_wave(&self) -> std::collections::HashMap<String, i64> {
+        self.attended_neighbours_nb_for_transaction_wave.clone()
+    }
+
+    /// Get the parent (neighbour deg(1)) address for the wave from initiator_id
+    pub fn get_parent_addr_for_wave(&self, initiator_id: String) -> std::net::SocketAddr {
+        self.parent_addr_for_transaction_wave
+            .get(&initiator_id)
+            .copied()
+            .unwrap_or(no_parent())
+    }
+
+    /// Set the parent (neighbour deg(1)) address for the wave from initiator_id
+    pub fn set_parent_addr(&mut self, initiator_id: String, peer_adr: std::net::SocketAddr) {
+        self.parent_addr_for_transaction_wave
+            .insert(initiator_id, peer_adr);
+    }
+
+    /// Returns true if no wave from initiator_id is in progress on this site
+    pub fn is_idle(&self, initiator_id: &str) -> bool {
+        self.parent_addr_for_transaction_wave
+            .get(initiator_id)
+            .is_none_or(|addr| *addr == no_parent())
+    }
+
+    /// Starts a wave initiated by this site
+    ///
+    /// The site is its own parent and waits for every neighbour. Returns true
+    /// if the wave must be diffused, false if the site has no neighbour.
+    pub fn start_wave(
+        &mut self,
+        site_id: &str,
+        site_addr: std::net::SocketAddr,
+        nb_neighbours: i64,
+    ) -> bool {
+        self.set_parent_addr(site_id.to_string(), site_addr);
+        self.set_nb_nei_for_wave(site_id.to_string(), nb_neighbours);
+        nb_neighbours > 0
+    }
+
+    /// Handles a message (blue) of the wave from initiator_id
+    ///
+    /// A leaf reached for the first time has nothing to wait for, so its
+    /// wave is reset right away: it only has to acknowledge the message.
+    pub fn on_blue_message(
+        &mut self,
+        initiator_id: &str,
+        sender_addr: std::net::SocketAddr,
+        nb_neighbours: i64,
+    ) -> BlueTransition {
+        let first_visit = self.is_idle(initiator_id);
+        if self.enter_wave(initiator_id, sender_addr, nb_neighbours) {
+            return BlueTransition::Diffuse;
+        }
+        if first_visit {
+            // réinitialisation s'il s'agit de la remontée après réception des rouges de tous les fils
+            self.reset_wave(initiator_id, nb_neighbours);
+        }
+        BlueTransition::Acknowledge { first_visit }
+    }
+
+    /// Handles an acknowledgement (red) of the wave from initiator_id
+    pub fn on_red_message(
+        &mut self,
+        initiator_id: &str,
+        nb_neighbours: i64,
+        local_addr: std::net::SocketAddr,
+    ) -> RedTransition {
+        match self.receive_ack(initiator_id, nb_neighbours) {
+            None => RedTransition::Wait,
+            Some(parent) if parent == local_addr => RedTransition::Completed,
+            Some(parent) => RedTransition::ForwardToParent(parent),
+        }
+    }
+
+    /// Records a message (blue) of the wave from initiator_id
+    ///
+    /// On the first message of the wave, the sender becomes our parent.
+    /// Returns true if the message must be diffused to our other neighbours,
+    /// false if we are a leaf and must acknowledge it.
+    fn enter_wave(
+        &mut self,
+        initiator_id: &str,
+        sender_addr: std::net::SocketAddr,
+        nb_neighbours: i64,
+    ) -> bool {
+        if !self.is_idle(initiator_id) {
+            return false;
+        }
+        self.set_parent_addr(initiator_id.to_string(), sender_addr);
+
+        let current_value = self
+            .attended_neighbours_nb_for_transaction_wave
+            .get(initiator_id)
+            .copied()
+            .unwrap_or(nb_neighbours);
+        self.set_nb_nei_for_wave(initiator_id.to_string(), current_value - 1);
+
+        log::debug!("Nombre de voisin : {}", current_value - 1);
+        current_value - 1 > 0
+    }
+
+    /// Records an acknowledgement (red) of the wave from initiator_id
+    ///
+    /// Once every expected neighbour answered, the wave is reset and the
+    /// address of our parent is returned: our own address if we initiated it.
+    fn receive_ack(
+        &mut self,
+        initiator_id: &str,
+        nb_neighbours: i64,
+    ) -> Option<std::net::SocketAddr> {
+        let current_value = self
+            .attended_neighbours_nb_for_transaction_wave
+            .get(initiator_id)
+            .copied()
+            .unwrap_or(nb_neighbours);
+        self.set_nb_nei_for_wave(initiator_id.to_string(), current_value - 1);
+
+        if current_value - 1 != 0 {
+            return None;
+        }
+        let parent = self.get_parent_addr_for_wave(initiator_id.to_string());
+        self.reset_wave(initiator_id, nb_neighbours);
+        Some(parent)
+    }
+
+    /// Adds the sites confirmed by a child to the transaction wave from initiator_id
+    pub fn add_confirmed_sites(&mut self, initiator_id: &str, sites: i64) {
+        *self
+            .confirmed_sites_for_transaction_wave
+            .entry(initiator_id.to_string())
+            .or_insert(0) += sites;
+    }
+
+    /// Returns the sites confirmed by our children for the transaction wave
+    /// from initiator_id, and forgets them
+    pub fn take_confirmed_sites(&mut self, initiator_id: &str) -> i64 {
+        self.confirmed_sites_for_transaction_wave
+            .remove(initiator_id)
+            .unwrap_or(0)
+    }
+
+    /// Resets the wave from initiator_id, once it went back through this site
+    fn reset_wave(&mut self, initiator_id: &str, nb_neighbours: i64) {
+        self.set_nb_nei_for_wave(initiator_id.to_string(), nb_neighbours);
+        self.set_parent_addr(initiator_id.to_string(), no_parent());
+    }
+
+    /// Forgets the waves of a site that left the network
+    pub fn forget(&mut self, site_id: &str) {
+        self.attended_neighbours_nb_for_transaction_wave
+            .remove(site_id);
+        self.parent_addr_for_transaction_wave.remove(site_id);
+        self.confirmed_sites_for_transaction_wave.remove(site_id);
+    }
+
+    /// Sends the responses of the waves whose parent moved to its new address
+    pub fn reparent(&mut self, old_addr: std::net::SocketAddr, new_addr: std::net::SocketAddr) {
+        for parent in self.parent_addr_for_transaction_wave.values_mut() {
+            if *parent == old_addr {
+                *parent = new_addr;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn addr(i: usize) -> std::net::SocketAddr {
+        std::net::SocketAddr::from(([127, 0, 0, 1], 10000 + i as u16))
+    }
+
+    #[test]
+    fn test_wave_engine() {
+        let local = addr(0);
+        let parent = addr(1);
+        let nb = 3;
+
+        // A site with 3 neighbours diffuses to the 2 others and waits for them
+        let mut waves = WaveEngine::default();
+        assert_eq!(
+            waves.on_blue_message("B", parent, nb),
+            BlueTransition::Diffuse
+        );
+        assert!(!waves.is_idle("B"));
+        // the wave already went through this site
+        assert_eq!(
+            waves.on_blue_message("B", addr(2), nb),
+            BlueTransition::Acknowledge { first_visit: false }
+        );
+
+        assert_eq!(waves.on_red_message("B", nb, local), RedTransition::Wait);
+        assert_eq!(
+            waves.on_red_message("B", nb, local),
+            RedTransition::ForwardToParent(parent)
+        );
+        assert!(waves.is_idle("B"));
+        assert_eq!(waves.get_nb_nei_for_wave().get("B"), Some(&3));
+
+        // a leaf acknowledges the first message and is ready for the next wave
+        assert_eq!(
+            waves.on_blue_message("C", parent, 1),
+            BlueTransition::Acknowledge { first_visit: true }
+        );
+        assert!(waves.is_idle("C"));
+
+        // our own wave completes once every neighbour answered
+        assert!(waves.start_wave("A", local, 2));
+        assert_eq!(waves.on_red_message("A", 2, local), RedTransition::Wait);
+        assert_eq!(
+            waves.on_red_message("A", 2, local),
+            RedTransition::Completed
+        );
+        assert!(!waves.start_wave("A", local, 0));
+
+        // the sites confirmed by the children are summed once
+        waves.add_confirmed_sites("B", 2);
+        waves.add_confirmed_sites("B", 1);
+        assert_eq!(waves.take_confirmed_sites("B"), 3);
+        assert_eq!(waves.take_confirmed_sites("B"), 0);
+    }
+
+    /// Message of a wave in transit: (initiator, is blue)
+    type InTransit = (usize, bool);
+
+    /// Runs concurrent waves on a graph, delivering the messages of the
+    /// links in the order given by `schedule`, which must not be empty
+    ///
+    /// Returns the number of times each wave completed, or an error if a
+    /// counter went negative or the waves did not terminate.
+    fn run_waves(
+        neighbours: &[Vec<usize>],
+        initiators: &[usize],
+        schedule: &[usize],
+    ) -> Result<Vec<usize>, String> {
+        let ids: Vec<String> = (0..neighbours.len()).map(|i| format!("S{i}")).collect();
+        let mut engines: Vec<WaveEngine> = (0..neighbours.len())
+            .map(|_| WaveEngine::default())
+            .collect();
+        // FIFO queue of each directed link
+        let mut links: std::collections::BTreeMap<
+            (usize, usize),
+            std::collections::VecDeque<InTransit>,
+        > = std::collections::BTreeMap::new();
+        let mut completed = vec![0; neighbours.len()];
+        let mut diffused = Vec::new();
+
+        for &i in initiators {
+            let nb = neighbours[i].len() as i64;
+            if engines[i].start_wave(&ids[i], addr(i), nb) {
+                for &n in &neighbours[i] {
+                    links.entry((i, n)).or_default().push_back((i, true));
+                }
+                diffused.push(&ids[i]);
+            } else {
+                completed[i] += 1;
+            }
+        }
+
+        for step in 0.. {
+            let busy: Vec<(usize, usize)> = links
+                .iter()
+                .filter(|(_, queue)| !queue.is_empty())
+                .map(|(link, _)| *link)
+                .collect();
+            if busy.is_empty() {
+                break;
+            }
+            if step > 100_000 {
+                return Err("the waves do not terminate".to_string());
+            }
+            let (from, to) = busy[schedule[step % schedule.len()] % busy.len()];
+            let (initiator, blue) = links.get_mut(&(from, to)).unwrap().pop_front().unwrap();
+
+            let id = &ids[initiator];
+            let nb = neighbours[to].len() as i64;
+            let engine = &mut engines[to];
+            let mut send = |dest: usize, blue: bool| {
+                links
+                    .entry((to, dest))
+                    .or_default()
+                    .push_back((initiator, blue))
+            };
+            if blue {
+                match engine.on_blue_message(id, addr(from), nb) {
+                    BlueTransition::Diffuse => {
+                        let parent = engine.get_parent_addr_for_wave(id.clone());
+                        for &n in neighbours[to].iter().filter(|&&n| addr(n) != parent) {
+                            send(n, true);
+                        }
+                    }
+                    BlueTransition::Acknowledge { .. } => send(from, false),
+                }
+            } else {
+                match engine.on_red_message(id, nb, addr(to)) {
+                    RedTransition::Wait => {}
+                    RedTransition::ForwardToParent(parent) => {
+                        let parent = (0..neighbours.len()).find(|&n| addr(n) == parent);
+                        send(parent.ok_or("red message without parent")?, false);
+                    }
+                    RedTransition::Completed => completed[initiator] += 1,
+                }
+            }
+
+            if let Some(n) = engine
+                .attended_neighbours_nb_for_transaction_wave
+                .values()
+                .find(|n| **n < 0)
+            {
+                return Err(format!("site {to} expects {n} answers"));
+            }
+        }
+
+        for (i, engine) in engines.iter().enumerate() {
+            if let Some(id) = diffused.iter().find(|id| !engine.is_idle(id)) {
+                return Err(format!("site {i} is still in the wave of {id}"));
+            }
+        }
+        Ok(completed)
+    }
+
+    /// Connected graph of 1 to 8 sites: a random spanning tree plus random
+    /// extra links
+    fn connected_graph() -> impl Strategy<Value = Vec<Vec<usize>>> {
+        (1usize..=8)
+            .prop_flat_map(|n| {
+                (
+                    Just(n),
+                    proptest::collection::vec(any::<prop::sample::Index>(), n - 1),
+                    proptest::collection::vec((0..n, 0..n), 0..n * 2),
+                )
+            })
+            .prop_map(|(n, tree, extra)| {
+                let mut edges = std::collections::BTreeSet::new();
+                for (i, parent) in tree.iter().enumerate() {
+                    let child = i + 1;
+                    edges.insert((parent.index(child), child));
+                }
+                for (a, b) in extra {
+                    if a != b {
+                        edges.insert((a.min(b), a.max(b)));
+                    }
+                }
+                let mut neighbours = vec![Vec::new(); n];
+                for (a, b) in edges {
+                    neighbours[a].push(b);
+                    neighbours[b].push(a);
+                }
+                neighbours
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_every_wave_terminates(
+            neighbours in connected_graph(),
+            initiators in proptest::collection::btree_set(0usize..8, 1..4),
+            schedule in proptest::collection::vec(any::<usize>(), 1..64),
+        ) {
+            let initiators: Vec<usize> = initiators
+                .into_iter()
+                .filter(|&i| i < neighbours.len())
+                .collect();
+            let completed = run_waves(&neighbours, &initiators, &schedule)
+                .map_err(TestCaseError::fail)?;
+            for (i, nb) in completed.into_iter().enumerate() {
+                prop_assert_eq!(nb, usize::from(initiators.contains(&i)));
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Address of the web interface of a site
+//!
+//! The web server listens on the port of the site plus
+//! [`crate::node::PORT_OFFSET`], unless `--web-port` gives it a fixed port.
+//! A site whose port is chosen at startup may then get another web address
+//! at each start, breaking the bookmarks of its users. To keep it reachable,
+//! the site writes its web address to a discovery file next to its database,
+//! `peillute_<id>.web`, which scripts read to find the running site. When the
+//! address changed since the last start, the site also redirects its former
+//! address to the new one, as long as no other process took the former port.
+//! An ephemeral site has no discovery file.
+
+#![cfg(feature = "server")]
+
+/// Fixed port of the web server, None to follow the port of the site
+static WEB_PORT: std::sync::OnceLock<Option<u16>> = std::sync::OnceLock::new();
+
+/// Sets the fixed port of the web server, if any
+pub fn configure(web_port: Option<u16>) {
+    if WEB_PORT.set(web_port).is_err() {
+        log::warn!("The web port is already set");
+    }
+}
+
+/// Returns the port of the web server of a site listening on `port`
+pub fn port_for(port: u16) -> u16 {
+    WEB_PORT
+        .get()
+        .copied()
+        .flatten()
+        .unwrap_or_else(|| port.saturating_add(crate::node::PORT_OFFSET))
+}
+
+/// Returns the discovery file of the site with the given database
+pub fn default_path(db_path: &std::path::Path) -> std::path::PathBuf {
+    db_path.with_extension("web")
+}
+
+/// Parses the content of a discovery file
+fn parse(content: &str) -> Option<std::net::SocketAddr> {
+    let content = content.trim();
+    content
+        .strip_prefix("http://")
+        .unwrap_or(content)
+        .trim_end_matches('/')
+        .parse()
+        .ok()
+}
+
+/// Returns the web address written in a discovery file, if any
+pub fn read(path: &std::path::Path) -> Option<std::net::SocketAddr> {
+    parse(&std::fs::read_to_string(path).ok()?)
+}
+
+/// Writes the web address of the site to its discovery file
+pub fn write(path: &std::path::Path, addr: std::net::SocketAddr) -> std::io::Result<()> {
+    std::fs::write(path, format!("http://{}\n", addr))
+}
+
+/// Returns the URL a request to the former address is redirected to
+fn redirect_target(to: std::net::SocketAddr, uri: &axum::http::Uri) -> String {
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    format!("http://{}{}", to, path)
+}
+
+/// Redirects the requests received on the former web address of the site to
+/// its current one, until the process exits
+///
+/// Does nothing if the former port cannot be bound.
+pub async fn redirect(from: std::net::SocketAddr, to: std::net::SocketAddr) {
+    let listener = match tokio::net::TcpListener::bind(from).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::debug!("Cannot redirect the former web address {}: {}", from, e);
+            return;
+        }
+    };
+    log::info!("Redirecting the former web address {} to {}", from, to);
+    let router = axum::Router::new().fallback(move |uri: axum::http::Uri| async move {
+        axum::response::Redirect::temporary(&redirect_target(to, &uri))
+    });
+    if let Err(e) = axum::serve(listener, router).await {
+        log::error!("Redirection of {} stopped: {}", from, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovery_file() {
+        let addr: std::net::SocketAddr = "127.0.0.1:11002".parse().unwrap();
+        let path = std::env::temp_dir().join(format!("peillute_test_{}.web", std::process::id()));
+        write(&path, addr).unwrap();
+        assert_eq!(read(&path), Some(addr));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read(&path), None);
+
+        assert_eq!(parse("[::1]:11002/"), "[::1]:11002".parse().ok());
+        assert_eq!(parse("not an address"), None);
+        let uri: axum::http::Uri = "/user/alice?page=2".parse().unwrap();
+        assert_eq!(
+            redirect_target(addr, &uri),
+            "http://127.0.0.1:11002/user/alice?page=2"
+        );
+    }
+}
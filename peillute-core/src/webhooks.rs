@@ -0,0 +1,292 @@
+//! Outgoing webhook notifications
+//!
+//! Every transaction recorded in the local database, whether it was created
+//! on this site or received from the network, is posted as JSON to the
+//! webhooks configured for its kind of event. The webhooks are described in a
+//! TOML file:
+//!
+//! ```toml
+//! secret = "shared secret"
+//!
+//! [[webhooks]]
+//! url = "https://hooks.example.com/peillute"
+//! events = ["deposit", "withdraw"]
+//!
+//! [[webhooks]]
+//! url = "https://ledger.example.com/mirror"
+//! secret = "another secret"
+//! ```
+//!
+//! A webhook without `events` receives every transaction. When a secret is
+//! set, the body is signed with HMAC-SHA256 and the signature is sent in the
+//! [`SIGNATURE_HEADER`] header as `sha256=<hex digest>`.
+
+#![cfg(feature = "server")]
+
+/// Header carrying the signature of the payload
+pub const SIGNATURE_HEADER: &str = "X-Peillute-Signature";
+
+/// Number of attempts to deliver a payload to a webhook
+const DELIVERY_ATTEMPTS: u32 = 3;
+
+/// Deadline of each delivery attempt
+const DELIVERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Maximum number of payloads waiting to be delivered
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Kind of event notified to the webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    /// Money deposited on an account
+    Deposit,
+    /// Money withdrawn from an account
+    Withdraw,
+    /// Money sent from one user to another, including payments and refunds
+    Transfer,
+}
+
+impl EventType {
+    /// Returns the kind of event of a transaction
+    pub fn of(transaction: &crate::db::Transaction) -> Self {
+        if transaction.from_user == "NULL" {
+            EventType::Deposit
+        } else if transaction.to_user == "NULL" {
+            EventType::Withdraw
+        } else {
+            EventType::Transfer
+        }
+    }
+}
+
+/// Target of the notifications
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct Webhook {
+    /// URL the payloads are posted to
+    pub url: String,
+    /// Events sent to the webhook, every event if empty
+    #[serde(default)]
+    pub events: Vec<EventType>,
+    /// Secret signing the payloads, overrides the secret of the file
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+impl Webhook {
+    /// Returns true if the webhook receives this kind of event
+    pub fn accepts(&self, event: EventType) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Content of a webhook configuration file
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+pub struct WebhookConfig {
+    /// Secret signing the payloads of every webhook
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Webhooks to notify
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+}
+
+impl WebhookConfig {
+    /// Parses and validates the content of a webhook configuration file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: WebhookConfig =
+            toml::from_str(content).map_err(|e| format!("invalid webhook file: {}", e))?;
+        for webhook in &config.webhooks {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                return Err(format!("invalid webhook URL '{}'", webhook.url));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads and validates a webhook configuration file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read webhook file {}: {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    /// Returns the secret signing the payloads of a webhook, if any
+    fn secret_of<'a>(&'a self, webhook: &'a Webhook) -> Option<&'a str> {
+        webhook.secret.as_deref().or(self.secret.as_deref())
+    }
+}
+
+/// Body posted to the webhooks
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Payload {
+    /// Kind of event
+    pub event: EventType,
+    /// ID of the site sending the notification
+    pub site_id: String,
+    /// Transaction recorded, with its Lamport time and vector clock
+    pub transaction: crate::db::Transaction,
+}
+
+/// Queue of the transactions to notify, set when webhooks are configured
+static QUEUE: std::sync::OnceLock<tokio::sync::mpsc::Sender<crate::db::Transaction>> =
+    std::sync::OnceLock::new();
+
+/// Starts the task delivering the notifications to the webhooks
+///
+/// Does nothing if the configuration has no webhook.
+pub fn start(config: WebhookConfig) {
+    if config.webhooks.is_empty() {
+        return;
+    }
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(QUEUE_CAPACITY);
+    if QUEUE.set(sender).is_err() {
+        log::warn!("Webhooks are already started");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .expect("cannot build the webhook HTTP client");
+        while let Some(transaction) = receiver.recv().await {
+            let event = EventType::of(&transaction);
+            let payload = Payload {
+                event,
+                site_id: crate::state::SITE_INFO.read().await.get_site_id(),
+                transaction,
+            };
+            let body = match serde_json::to_vec(&payload) {
+                Ok(body) => body,
+                Err(e) => {
+                    log::error!("Cannot encode the webhook payload: {}", e);
+                    continue;
+                }
+            };
+            for webhook in config.webhooks.iter().filter(|w| w.accepts(event)) {
+                deliver(&client, webhook, config.secret_of(webhook), &body).await;
+            }
+        }
+    });
+}
+
+/// Queues a recorded transaction for the webhooks
+///
+/// The notification is dropped if the webhooks are not started or too many
+/// notifications are already waiting, so that recording a transaction never
+/// waits for the webhooks.
+pub fn notify(transaction: crate::db::Transaction) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    if let Err(e) = queue.try_send(transaction) {
+        log::warn!("Webhook notification dropped: {}", e);
+    }
+}
+
+/// Posts a payload to a webhook, retrying when the delivery fails
+async fn deliver(client: &reqwest::Client, webhook: &Webhook, secret: Option<&str>, body: &[u8]) {
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        let mut request = client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        if let Some(secret) = secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, body));
+        }
+
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => format!("status {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        log::warn!(
+            "Webhook {} failed (attempt {}/{}): {}",
+            webhook.url,
+            attempt,
+            DELIVERY_ATTEMPTS,
+            error
+        );
+        if attempt < DELIVERY_ATTEMPTS {
+            tokio::time::sleep(crate::network::backoff_delay(attempt, attempt as u64)).await;
+        }
+    }
+    log::error!("Webhook {} abandoned", webhook.url);
+}
+
+/// Signs a payload with HMAC-SHA256, as `sha256=<hex digest>`
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    use hmac::Mac;
+
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(from: &str, to: &str) -> crate::db::Transaction {
+        crate::db::Transaction {
+            from_user: from.to_string(),
+            to_user: to.to_string(),
+            amount: 10.0,
+            lamport_time: 1,
+            source_node: "A".to_string(),
+            optional_msg: None,
+            vector_clock: std::collections::HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = WebhookConfig::parse(
+            r#"
+            secret = "top"
+
+            [[webhooks]]
+            url = "https://hooks.example.com/a"
+            events = ["deposit"]
+
+            [[webhooks]]
+            url = "http://localhost:8000/b"
+            secret = "own"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.webhooks.len(), 2);
+
+        let deposit = EventType::of(&transaction("NULL", "alice"));
+        let transfer = EventType::of(&transaction("alice", "bob"));
+        assert_eq!(deposit, EventType::Deposit);
+        assert_eq!(
+            EventType::of(&transaction("alice", "NULL")),
+            EventType::Withdraw
+        );
+        assert!(config.webhooks[0].accepts(deposit));
+        assert!(!config.webhooks[0].accepts(transfer));
+        assert!(config.webhooks[1].accepts(transfer));
+
+        assert_eq!(config.secret_of(&config.webhooks[0]), Some("top"));
+        assert_eq!(config.secret_of(&config.webhooks[1]), Some("own"));
+
+        assert!(WebhookConfig::parse("[[webhooks]]\nurl = \"ftp://x\"").is_err());
+        assert!(
+            WebhookConfig::parse("[[webhooks]]\nurl = \"http://x\"\nevents = [\"x\"]").is_err()
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -1,130 +1,449 @@
 //! Peillute - A distributed financial transaction system
 //!
 //! This module serves as the main entry point for the Peillute application, handling both
-//! server and client-side functionality. The application supports distributed transactions
-//! with vector clock synchronization and peer-to-peer communication.
+//! server and client-side functionality. The node itself lives in the `peillute-core` crate;
+//! this binary adds the command line and the web interface on top of it.
 
 #![allow(non_snake_case)]
 
-mod clock;
-mod control;
-mod db;
-mod message;
-mod network;
-mod snapshot;
-mod state;
-mod utils;
+// The modules of the node keep being reached through `crate::` paths
+use peillute_core::*;
+
+/// Command line of the Peillute application
+///
+/// Without subcommand, the arguments start a site as with `run`.
+#[derive(clap::Parser, Debug)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Arguments of the site started without subcommand
+    #[command(flatten)]
+    run: Args,
+
+    /// Task to run instead of the site
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+/// Subcommands of the Peillute application
+#[derive(clap::Subcommand, Debug)]
+enum CliCommand {
+    /// Start a site, joining the network and serving the web interface
+    Run(Box<Args>),
+    /// Write the transactions known by the site to a snapshot file, gzipped if it ends with `.gz`
+    Snapshot {
+        #[command(flatten)]
+        db: DbArgs,
+
+        /// File the snapshot is written to
+        #[arg(long = "output")]
+        cli_output: String,
+
+        /// Label of the snapshot
+        #[arg(long = "label")]
+        cli_label: Option<String>,
+    },
+    /// Check the integrity of the database and the postings of every account
+    VerifyDb {
+        #[command(flatten)]
+        db: DbArgs,
+    },
+    /// Check a snapshot file against its consistency certificate
+    VerifySnapshot {
+        /// Snapshot file to check, gzipped if it ends with `.gz`
+        #[arg(value_name = "FILE")]
+        cli_file: String,
+    },
+    /// Export the balance and transactions of a user as JSON
+    Export {
+        #[command(flatten)]
+        db: DbArgs,
+
+        /// User to export
+        #[arg(long = "user")]
+        cli_user: String,
+
+        /// File the export is written to, the standard output by default
+        #[arg(long = "output")]
+        cli_output: Option<String>,
+    },
+    /// Write the causal graph of the transactions in the DOT format of Graphviz
+    Graph {
+        #[command(flatten)]
+        db: DbArgs,
+
+        /// Only draw the transactions of this user
+        #[arg(long = "user")]
+        cli_user: Option<String>,
+
+        /// File the graph is written to, the standard output by default
+        #[arg(long = "output")]
+        cli_output: Option<String>,
+    },
+    /// Attach the terminal to a running site, following its events and running the lines typed
+    Attach {
+        /// Web server of the site, as `host:port`
+        #[arg(value_name = "HOST:PORT")]
+        cli_addr: String,
+
+        /// Admin API token of the site
+        #[arg(long = "token")]
+        cli_token: String,
+    },
+    /// Mint an API token, admin unless a user or read-only is given
+    Keygen {
+        #[command(flatten)]
+        db: DbArgs,
+
+        /// Name given to the token, usually the script using it
+        #[arg(long = "label")]
+        cli_label: String,
+
+        /// User the token transacts as
+        #[arg(long = "user", conflicts_with = "cli_read_only")]
+        cli_user: Option<String>,
+
+        /// Only allow the token to read the balances and transactions
+        #[arg(long = "read-only")]
+        cli_read_only: bool,
+    },
+}
+
+/// Database of the site a task runs on
+#[derive(clap::Args, Debug)]
+struct DbArgs {
+    /// ID for the database path, the database being `peillute_<id>.db`
+    #[arg(long, default_value_t = 0)]
+    cli_db_id: u16,
+
+    /// Path of the database, instead of the one derived from `--cli-db-id`
+    #[arg(long = "db-path")]
+    cli_db_path: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl DbArgs {
+    /// Returns the path of the database
+    fn path(&self) -> std::path::PathBuf {
+        match &self.cli_db_path {
+            Some(path) => std::path::PathBuf::from(path),
+            None => db::default_path(self.cli_db_id),
+        }
+    }
+}
 
 /// Command-line arguments for configuring the Peillute application
 #[derive(clap::Parser, Debug)]
-#[command(author, version, about, long_about = None)]
 struct Args {
     /// Unique identifier for this site in the network
     #[arg(long, default_value_t = String::new())]
     cli_site_id: String,
 
+    /// Human-friendly name of this site, shown by the other sites instead of its ID
+    #[arg(long = "site-alias")]
+    cli_site_alias: Option<String>,
+
     /// Port number for peer-to-peer communication
     #[arg(long, default_value_t = 0)]
     cli_port: u16,
 
-    /// List of peer addresses to connect to
+    /// Port of the web interface, the peer port plus 1001 by default
+    #[arg(long = "web-port")]
+    cli_web_port: Option<u16>,
+
+    /// List of peers to connect to, as `ip:port`, `[ipv6]:port` or `hostname:port`
     #[arg(long, value_delimiter = ',')]
     cli_peers: Vec<String>,
 
-    /// IP address to bind to
+    /// IP address to bind to, IPv4 or IPv6 (`::` listens on both families)
     #[arg(long, default_value_t = String::from("127.0.0.1"))]
     cli_ip: String,
 
-    /// ID for the batabase path
+    /// ID for the batabase path, the database being `peillute_<id>.db`
     #[arg(long, default_value_t = 0)]
     cli_db_id: u16,
+
+    /// Path of the database, instead of the one derived from `--cli-db-id`
+    #[arg(long = "db-path")]
+    cli_db_path: Option<String>,
+
+    /// Keep the database in memory only, for demo, observer or test sites
+    #[arg(long = "ephemeral", conflicts_with = "cli_db_path")]
+    cli_ephemeral: bool,
+
+    /// Maximum size of a message exchanged with peers, in bytes
+    #[arg(long, default_value_t = 1024 * 1024)]
+    cli_max_message_size: usize,
+
+    /// Maximum number of critical commands waiting for the global mutex
+    #[arg(long, default_value_t = state::DEFAULT_MAX_PENDING_COMMANDS)]
+    cli_max_pending_commands: usize,
+
+    /// Free disk space below which new transactions are refused, in bytes
+    #[arg(long, default_value_t = storage::DEFAULT_MIN_FREE_SPACE)]
+    cli_min_free_space: u64,
+
+    /// Size of the database above which new transactions are refused, in bytes (0 for no limit)
+    #[arg(long, default_value_t = 0)]
+    cli_max_db_size: u64,
+
+    /// Interval between two automatic snapshots, in minutes (0 to disable them)
+    #[arg(long, default_value_t = 0)]
+    cli_snapshot_interval: u64,
+
+    /// Number of snapshot files kept on disk, the oldest ones being removed (0 to keep them all)
+    #[arg(long, default_value_t = 0)]
+    cli_snapshot_retention: usize,
+
+    /// Only fetch the transactions this site does not know when it initiates a snapshot
+    #[arg(long = "delta-snapshots")]
+    cli_delta_snapshots: bool,
+
+    /// Time after which a transaction can no longer be refunded, in hours (0 for no limit)
+    #[arg(long = "refund-window", default_value_t = db::DEFAULT_REFUND_WINDOW_HOURS)]
+    cli_refund_window: u64,
+
+    /// Smallest amount of a deposit, withdrawal, payment or transfer
+    #[arg(long = "min-amount", default_value_t = amount::DEFAULT_MIN_AMOUNT)]
+    cli_min_amount: f64,
+
+    /// Greatest amount of a deposit, withdrawal, payment or transfer
+    #[arg(long = "max-amount", default_value_t = amount::DEFAULT_MAX_AMOUNT)]
+    cli_max_amount: f64,
+
+    /// Time without news from the site holding the global mutex before it is reported as stuck, in seconds (0 to disable)
+    #[arg(long, default_value_t = 30)]
+    cli_mutex_timeout: u64,
+
+    /// Time a component of the global state may be held before a warning is logged, in milliseconds (0 to disable)
+    #[arg(long = "lock-warn-ms", default_value_t = lock_stats::DEFAULT_WARN_MS)]
+    cli_lock_warn_ms: u64,
+
+    /// Round-trip time above which a wave relayed by the site may skip a neighbour of its initiator, in milliseconds (0 to disable)
+    #[arg(long = "slow-neighbour-rtt", default_value_t = 0)]
+    cli_slow_neighbour_rtt: u64,
+
+    /// Logical clock strategy of the site
+    #[arg(long, value_enum, default_value_t = clock::ClockStrategy::Vector)]
+    cli_clock: clock::ClockStrategy,
+
+    /// TOML file of users and initial balances, loaded on the first start of the site
+    #[arg(long = "seed-file")]
+    cli_seed_file: Option<String>,
+
+    /// TOML file of the webhooks notified of every recorded transaction
+    #[arg(long = "webhooks-file")]
+    cli_webhooks_file: Option<String>,
+
+    /// TOML file of the channels alerted of the large transactions
+    #[arg(long = "alerts-file")]
+    cli_alerts_file: Option<String>,
+
+    /// TOML file of the operators of the CLI and the admin socket, with their role
+    #[arg(long = "operators-file")]
+    cli_operators_file: Option<String>,
+
+    /// TOML file of the fees charged on the transfers and payments submitted to the site
+    #[arg(long = "fees-file")]
+    cli_fees_file: Option<String>,
+
+    /// TOML file naming the system accounts of the deposits, withdrawals and payments
+    #[arg(long = "system-accounts-file")]
+    cli_system_accounts_file: Option<String>,
+
+    /// Transport used to exchange messages with the peers
+    #[arg(long = "transport", value_enum, default_value_t = network::Transport::Tcp)]
+    cli_transport: network::Transport,
+
+    /// Format of the messages sent to the peers
+    #[arg(long = "wire-format", value_enum, default_value_t = codec::WireFormat::Msgpack)]
+    cli_wire_format: codec::WireFormat,
+
+    /// Format of the data printed by the CLI commands
+    #[arg(long = "output", value_enum, default_value_t = control::OutputFormat::Text)]
+    cli_output: control::OutputFormat,
+
+    /// Scenario file to run on virtual sites in memory, instead of starting a site
+    #[arg(long = "simulate")]
+    cli_simulate: Option<String>,
+
+    /// Load file to submit to running sites, printing a JSON report instead of starting a site
+    #[arg(long = "bench")]
+    cli_bench: Option<String>,
+
+    /// Generate random transactions between demo users, with an in-memory database unless --db-path is given
+    #[arg(long = "demo")]
+    cli_demo: bool,
+
+    /// Number of transactions generated per minute in demo mode
+    #[arg(long = "demo-rate", default_value_t = demo::DEFAULT_DEMO_RATE, requires = "cli_demo")]
+    cli_demo_rate: u32,
+
+    /// Full node to forward the critical commands to, instead of taking part in the protocols
+    #[arg(long = "client-of", conflicts_with = "cli_peers")]
+    cli_client_of: Option<String>,
+
+    /// Site IDs of the client sites allowed to forward commands to this site
+    #[arg(long = "trusted-clients", value_delimiter = ',')]
+    cli_trusted_clients: Vec<String>,
+
+    /// IP addresses or CIDR blocks allowed to connect to the site, any address if not given
+    #[arg(long = "peer-allowlist", value_delimiter = ',')]
+    cli_peer_allowlist: Vec<String>,
+
+    /// IP addresses or CIDR blocks refused, even if they are in the allowlist
+    #[arg(long = "peer-denylist", value_delimiter = ',')]
+    cli_peer_denylist: Vec<String>,
+
+    /// File holding the secret shared by the sites, which must prove they know it to connect
+    #[arg(long = "cluster-secret-file")]
+    cli_cluster_secret_file: Option<String>,
+
+    /// Queue the transactions submitted while the site is offline, and send them once it is back
+    #[arg(long = "offline-queue")]
+    cli_offline_queue: bool,
+
+    /// Start in maintenance mode, refusing the new transactions until `/maintenance off`
+    #[arg(long = "maintenance")]
+    cli_maintenance: bool,
+
+    /// Path of the admin socket accepting the CLI commands, `peillute_<site-id>.sock` by default
+    #[arg(long = "admin-socket")]
+    cli_admin_socket: Option<String>,
+
+    /// Do not accept the CLI commands on an admin socket
+    #[arg(long = "no-admin-socket", conflicts_with = "cli_admin_socket")]
+    cli_no_admin_socket: bool,
+
+    /// OpenTelemetry collector to export the spans of the waves to, such as `http://localhost:4317`
+    #[arg(long = "otlp-endpoint")]
+    cli_otlp_endpoint: Option<String>,
+
+    /// Setup file written by the setup wizard of the web interface, next to the database by default
+    #[arg(long = "setup-file")]
+    cli_setup_file: Option<String>,
+
+    /// Print the completion script of a shell for the arguments, instead of starting a site
+    #[arg(long = "generate-completion", value_enum)]
+    cli_generate_completion: Option<clap_complete::Shell>,
+}
+
+#[cfg(feature = "server")]
+impl Args {
+    /// Returns the configuration of the node started by the arguments
+    fn node_config(&self) -> Result<NodeConfig, std::net::AddrParseError> {
+        Ok(NodeConfig {
+            site_id: self.cli_site_id.clone(),
+            site_alias: self.cli_site_alias.clone(),
+            ip: self
+                .cli_ip
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()?,
+            port: self.cli_port,
+            web_port: self.cli_web_port,
+            peers: self.cli_peers.clone(),
+            db_id: self.cli_db_id,
+            db_path: self.cli_db_path.as_ref().map(std::path::PathBuf::from),
+            ephemeral: self.cli_ephemeral || (self.cli_demo && self.cli_db_path.is_none()),
+            max_message_size: self.cli_max_message_size,
+            max_pending_commands: self.cli_max_pending_commands,
+            min_free_space: self.cli_min_free_space,
+            max_db_size: self.cli_max_db_size,
+            snapshot_interval: self.cli_snapshot_interval,
+            snapshot_retention: self.cli_snapshot_retention,
+            delta_snapshots: self.cli_delta_snapshots,
+            refund_window: self.cli_refund_window,
+            min_amount: self.cli_min_amount,
+            max_amount: self.cli_max_amount,
+            mutex_timeout: self.cli_mutex_timeout,
+            lock_warn_ms: self.cli_lock_warn_ms,
+            slow_neighbour_rtt: self.cli_slow_neighbour_rtt,
+            clock: self.cli_clock,
+            transport: self.cli_transport,
+            wire_format: self.cli_wire_format,
+            seed_file: self.cli_seed_file.clone(),
+            webhooks_file: self.cli_webhooks_file.clone(),
+            alerts_file: self.cli_alerts_file.clone(),
+            operators_file: self.cli_operators_file.clone(),
+            fees_file: self.cli_fees_file.clone(),
+            system_accounts_file: self.cli_system_accounts_file.clone(),
+            demo_rate: if self.cli_demo { self.cli_demo_rate } else { 0 },
+            client_of: self.cli_client_of.clone(),
+            trusted_clients: self.cli_trusted_clients.clone(),
+            peer_allowlist: self.cli_peer_allowlist.clone(),
+            peer_denylist: self.cli_peer_denylist.clone(),
+            cluster_secret_file: self.cli_cluster_secret_file.clone(),
+            offline_queue: self.cli_offline_queue,
+            maintenance: self.cli_maintenance,
+            admin_socket: !self.cli_no_admin_socket,
+            admin_socket_path: self.cli_admin_socket.as_ref().map(std::path::PathBuf::from),
+            otlp_endpoint: self.cli_otlp_endpoint.clone(),
+            setup_file: self.cli_setup_file.as_ref().map(std::path::PathBuf::from),
+        })
+    }
 }
 
 #[cfg(feature = "server")]
 #[tokio::main]
-async fn main() -> rusqlite::Result<(), Box<dyn std::error::Error>> {
-    use crate::state::LOCAL_APP_STATE;
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
     use clap::Parser;
     use std::io::{self as std_io, Write};
-    use std::net::SocketAddr;
-    use tokio::io::{self as tokio_io, AsyncBufReadExt, BufReader};
-    use tokio::net::TcpListener;
 
-    const LOW_PORT: u16 = 10000;
-    const HIGH_PORT: u16 = 11000;
-    const PORT_OFFSET: u16 = HIGH_PORT - LOW_PORT + 1;
+    // Init the logger, which also streams the records to the attached terminals
+    let logger = env_logger::Builder::from_default_env().build();
+    let filter = logger.filter();
+    attach::install_logger(Box::new(logger), filter)?;
+
+    let cli = Cli::parse();
+    let args = match cli.command {
+        None => cli.run,
+        Some(CliCommand::Run(args)) => *args,
+        Some(CliCommand::Attach {
+            cli_addr,
+            cli_token,
+        }) => return attach::run_client(&cli_addr, &cli_token).await,
+        Some(task) => return run_task(task),
+    };
 
-    if !db::is_database_initialized()? {
-        let _ = db::init_db();
+    if let Some(shell) = args.cli_generate_completion {
+        use clap::CommandFactory;
+        let mut command = Cli::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(shell, &mut command, name, &mut std_io::stdout());
+        return Ok(());
     }
 
-    control::control_worker();
-    // Init the logger
-    env_logger::init();
-
-    let args = Args::parse();
-
-    let port_range = LOW_PORT..=HIGH_PORT;
-    let selected_port = if args.cli_port == 0 {
-        port_range
-            .into_iter()
-            .find(|port| std::net::TcpListener::bind(("127.0.0.1", *port)).is_ok())
-            .unwrap_or(LOW_PORT)
-    } else {
-        args.cli_port
-    };
-
-    let final_site_addr: SocketAddr = format!("{}:{}", &args.cli_ip, selected_port).parse()?;
-    let client_server_interaction_addr: SocketAddr =
-        format!("{}:{}", &args.cli_ip, selected_port + PORT_OFFSET).parse()?;
-
-    let final_cli_peers_addrs: Vec<SocketAddr> = args
-        .cli_peers
-        .into_iter()
-        .filter_map(|peer| peer.parse::<SocketAddr>().ok())
-        .collect();
-
-    let (final_site_id, final_clock, needs_sync) = match utils::reload_existing_site().await {
-        Ok((site_id_from_db, clock_from_db)) => (site_id_from_db, clock_from_db, true),
-        Err(_) => {
-            let generated_site_id = if args.cli_site_id.is_empty() {
-                utils::get_mac_address().unwrap_or_default() + "_" + &std::process::id().to_string()
-            } else {
-                args.cli_site_id.clone()
-            };
-            (generated_site_id, crate::clock::Clock::new(), false)
+    if let Some(scenario) = &args.cli_simulate {
+        let report = sim::run_file(scenario)?;
+        println!("{}", report);
+        if !report.is_safe() {
+            return Err("the simulation violated a safety property".into());
         }
-    };
+        return Ok(());
+    }
 
-    {
-        let mut state = LOCAL_APP_STATE.lock().await;
-        state.init_site_id(final_site_id.clone());
-        state.init_site_addr(final_site_addr);
-        state.init_clock(final_clock);
-        state.init_parent_addr_for_transaction_wave();
-        state.init_cli_peer_addrs(final_cli_peers_addrs);
-        state.init_sync(needs_sync);
+    if let Some(load) = &args.cli_bench {
+        let report = bench::run_file(load).await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
     }
 
-    // Create the network listener
-    let network_listener_local_addr = final_site_addr.clone();
-    let listener: TcpListener = TcpListener::bind(network_listener_local_addr).await?;
-    log::debug!("Listening on: {}", network_listener_local_addr);
+    control::set_output_format(args.cli_output);
+    let node = Node::new(args.node_config()?).start().await?;
 
     // Create the web app listener
-    let router = axum::Router::new().serve_dioxus_application(ServeConfigBuilder::default(), App);
+    let router = axum::Router::new()
+        .route("/metrics", axum::routing::get(metrics::render))
+        .route(
+            "/graphql",
+            axum::routing::get(graphql::graphiql).post(graphql::handler),
+        )
+        .merge(attach::router())
+        .serve_dioxus_application(ServeConfigBuilder::default(), App)
+        .layer(axum::middleware::from_fn(api_tokens::authenticate_request));
     let router = router.into_make_service();
-    let backend_listener = tokio::net::TcpListener::bind(client_server_interaction_addr)
-        .await
-        .unwrap();
-
-    // Create the stdin listener for the CLI
-    let stdin: tokio_io::Stdin = tokio_io::stdin();
-    let reader: BufReader<tokio_io::Stdin> = BufReader::new(stdin);
-    let mut lines: tokio_io::Lines<_> = reader.lines();
-
-    // Announce our presence to the network
-    network::announce(&args.cli_ip, LOW_PORT, HIGH_PORT, selected_port).await;
+    let client_server_interaction_addr = node.web_addr();
+    let backend_listener = network::bind_listener(client_server_interaction_addr)?;
 
     println!(
         "\n\
@@ -141,91 +460,137 @@ async fn main() -> rusqlite::Result<(), Box<dyn std::error::Error>> {
     print!("> ");
     std_io::stdout().flush().unwrap();
 
-    let main_loop_app_state = LOCAL_APP_STATE.clone();
-
     // Spawn the web server
-    let server_task = tokio::spawn(async move {
+    tokio::spawn(async move {
         axum::serve(backend_listener, router).await.unwrap();
     });
 
-    main_loop(main_loop_app_state, &mut lines, listener).await;
-
-    // Ensure the server task finishes cleanly if ever reached
-    server_task.await?;
+    // Spawn the CLI
+    tokio::spawn(cli_loop());
 
-    Ok(())
+    node.serve().await?;
+    // The CLI may still be reading stdin, which would keep the runtime alive
+    std::process::exit(0);
 }
 
 #[cfg(feature = "server")]
-async fn main_loop(
-    _state: std::sync::Arc<tokio::sync::Mutex<crate::state::AppState>>,
-    lines: &mut tokio::io::Lines<tokio::io::BufReader<tokio::io::Stdin>>,
-    listener: tokio::net::TcpListener,
-) {
-    use crate::control::{parse_command, process_cli_command};
-    use std::io::{self as std_io, Write};
-    use tokio::select;
-
-    loop {
-        select! {
-            line = lines.next_line() => {
-                let command = parse_command(line);
-                if let Err(e) = process_cli_command(command).await{
-                    log::error!("Error handling a cli command:\n{}", e);
-                }
-                print!("> ");
-                std_io::stdout().flush().unwrap();
+/// Runs a maintenance task on the database of a site, without starting it
+fn run_task(task: CliCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match task {
+        CliCommand::Run(_) | CliCommand::Attach { .. } => {
+            unreachable!("the sites are started and attached to by main")
+        }
+        CliCommand::Snapshot {
+            db,
+            cli_output,
+            cli_label,
+        } => {
+            tasks::open_database(&db.path())?;
+            let nb_transactions = tasks::snapshot(std::path::Path::new(&cli_output), cli_label)?;
+            println!(
+                "📸 Wrote {} transactions to {}",
+                nb_transactions, cli_output
+            );
+        }
+        CliCommand::VerifyDb { db } => {
+            tasks::open_database(&db.path())?;
+            let verification = tasks::verify_db()?;
+            for error in &verification.integrity_errors {
+                println!("❌ {}", error);
+            }
+            for user in &verification.audit.mismatches {
+                println!(
+                    "❌ The stored balance of '{}' differs from its postings",
+                    user
+                );
+            }
+            for (lamport, node) in &verification.audit.unbalanced {
+                println!("❌ Transaction {}-{} does not balance", node, lamport);
             }
-            Ok((stream, addr)) = listener.accept() => {
-                let _ = crate::network::start_listening(stream, addr).await;
+            if !verification.is_sound() {
+                return Err("the database failed the verification".into());
             }
-            _ = tokio::signal::ctrl_c() => {
-                disconnect().await;
-                std::process::exit(0);
+            println!("✅ The database is intact and every account balances");
+        }
+        CliCommand::VerifySnapshot { cli_file } => {
+            let snapshot = snapshot::load_snapshot_path(std::path::Path::new(&cli_file))?;
+            let verification = snapshot::verify(&snapshot);
+            for problem in &verification.problems {
+                println!("❌ {}", problem);
+            }
+            if !verification.is_valid() {
+                return Err("the snapshot failed the verification".into());
+            }
+            println!(
+                "✅ The {} transactions of the snapshot match its certificate{}",
+                verification.nb_transactions,
+                if verification.consistent {
+                    ""
+                } else {
+                    ", back-tracked to a consistent cut"
+                }
+            );
+        }
+        CliCommand::Export {
+            db,
+            cli_user,
+            cli_output,
+        } => {
+            tasks::open_database(&db.path())?;
+            let export = serde_json::to_string_pretty(&tasks::export_user(&cli_user)?)?;
+            match cli_output {
+                Some(path) => std::fs::write(path, export)?,
+                None => println!("{}", export),
             }
         }
+        CliCommand::Graph {
+            db,
+            cli_user,
+            cli_output,
+        } => {
+            tasks::open_database(&db.path())?;
+            let dot = graph::export(cli_user.as_deref())?;
+            match cli_output {
+                Some(path) => std::fs::write(path, dot)?,
+                None => print!("{}", dot),
+            }
+        }
+        CliCommand::Keygen {
+            db,
+            cli_label,
+            cli_user,
+            cli_read_only,
+        } => {
+            use api_tokens::TokenScope;
+
+            tasks::open_database(&db.path())?;
+            let scope = match cli_user {
+                Some(user) => TokenScope::TransactAs(user),
+                None if cli_read_only => TokenScope::ReadOnly,
+                None => TokenScope::Admin,
+            };
+            let secret = api_tokens::mint(&cli_label, &scope)?;
+            println!("🔑 Token '{}' ({}): {}", cli_label, scope, secret);
+        }
     }
+    Ok(())
 }
 
 #[cfg(feature = "server")]
-async fn disconnect() {
-    use crate::message::{MessageInfo, NetworkMessageCode};
-    use crate::state::LOCAL_APP_STATE;
-    use log::{error, info};
-
-    let (local_addr, site_id, connected_nei_addr) = {
-        let state = LOCAL_APP_STATE.lock().await;
-        (
-            state.get_site_addr(),
-            state.get_site_id().to_string(),
-            state.get_connected_nei_addr(),
-        )
-    };
+/// Executes the commands written on the standard input
+async fn cli_loop() {
+    use crate::control::{parse_command, process_cli_command};
+    use std::io::{self as std_io, Write};
+    use tokio::io::AsyncBufReadExt;
 
-    info!("Shutting down site {}.", site_id);
-    for peer_addr in connected_nei_addr {
-        // increment the clock for every deconnection
-        let clock = {
-            let mut state = LOCAL_APP_STATE.lock().await;
-            state.update_clock(None).await;
-            state.get_clock().clone()
-        };
-
-        if let Err(e) = crate::network::send_message(
-            peer_addr,
-            MessageInfo::None,
-            None,
-            NetworkMessageCode::Disconnect,
-            local_addr,
-            &site_id,
-            &site_id,
-            local_addr,
-            clock.clone(),
-        )
-        .await
-        {
-            error!("Error sending message to {}: {}", peer_addr, e);
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        let command = parse_command(lines.next_line().await);
+        if let Err(e) = process_cli_command(command).await {
+            log::error!("Error handling a cli command:\n{}", e);
         }
+        print!("> ");
+        std_io::stdout().flush().unwrap();
     }
 }
 
@@ -262,6 +627,28 @@ enum Route {
         Home {},
         #[route("/info")]
         Info {},
+        #[route("/setup")]
+        Setup {},
+        #[route("/search")]
+        Search {},
+        #[route("/products")]
+        Products {},
+        #[route("/policies")]
+        Policies {},
+        #[route("/tokens")]
+        Tokens {},
+        #[route("/import")]
+        Import {},
+        #[route("/snapshots")]
+        Snapshots {},
+        #[route("/cluster")]
+        Cluster {},
+        #[route("/notifications")]
+        Notifications {},
+        #[route("/snapshots/:name")]
+        SnapshotDetail {
+            name: String,
+        },
         #[nest("/:name")]
         #[layout(User)]
             #[route("/history")]
@@ -288,6 +675,14 @@ enum Route {
             Deposit {
                 name: String,
             },
+            #[route("/split")]
+            SplitBill {
+                name: String,
+            },
+            #[route("/statements")]
+            Statements {
+                name: String,
+            },
 }
 
 #[cfg(test)]
@@ -310,6 +705,8 @@ mod tests {
         assert_eq!(args.cli_peers.len(), 2);
         assert_eq!(args.cli_peers[0], "127.0.0.1:8081");
         assert_eq!(args.cli_peers[1], "127.0.0.1:8082");
+        let args = Args::parse_from(vec!["my_program", "--web-port", "8000"]);
+        assert_eq!(args.cli_web_port, Some(8000));
     }
 
     #[test]
@@ -324,6 +721,229 @@ mod tests {
         ]);
         assert_eq!(args.cli_site_id, "A");
         assert_eq!(args.cli_port, 8080);
+        assert_eq!(args.cli_web_port, None);
         assert_eq!(args.cli_peers.len(), 0);
+        assert_eq!(args.cli_clock, super::clock::ClockStrategy::Vector);
+        assert_eq!(args.cli_seed_file, None);
+        assert_eq!(args.cli_db_path, None);
+        assert!(!args.cli_ephemeral);
+        assert_eq!(args.cli_mutex_timeout, 30);
+        assert_eq!(args.cli_lock_warn_ms, super::lock_stats::DEFAULT_WARN_MS);
+        assert_eq!(args.cli_slow_neighbour_rtt, 0);
+        assert_eq!(args.cli_webhooks_file, None);
+        assert_eq!(args.cli_alerts_file, None);
+        assert_eq!(args.cli_operators_file, None);
+        assert_eq!(args.cli_fees_file, None);
+        assert_eq!(args.cli_system_accounts_file, None);
+        assert_eq!(args.cli_transport, super::network::Transport::Tcp);
+        assert_eq!(args.cli_wire_format, super::codec::WireFormat::Msgpack);
+        assert_eq!(args.cli_simulate, None);
+        assert_eq!(args.cli_bench, None);
+        assert_eq!(args.cli_site_alias, None);
+        assert_eq!(args.cli_generate_completion, None);
+        assert_eq!(args.cli_client_of, None);
+        assert!(args.cli_trusted_clients.is_empty());
+        assert!(args.cli_peer_allowlist.is_empty());
+        assert!(args.cli_peer_denylist.is_empty());
+        assert_eq!(args.cli_cluster_secret_file, None);
+        assert_eq!(args.cli_admin_socket, None);
+        assert!(!args.cli_no_admin_socket);
+        assert!(!args.cli_maintenance);
+        assert_eq!(args.cli_otlp_endpoint, None);
+        assert_eq!(args.cli_setup_file, None);
+        assert_eq!(args.cli_output, super::control::OutputFormat::Text);
+        assert_eq!(
+            args.cli_max_pending_commands,
+            super::state::DEFAULT_MAX_PENDING_COMMANDS
+        );
+        assert_eq!(
+            args.cli_min_free_space,
+            super::storage::DEFAULT_MIN_FREE_SPACE
+        );
+        assert_eq!(args.cli_max_db_size, 0);
+        assert_eq!(args.cli_snapshot_interval, 0);
+        assert_eq!(args.cli_snapshot_retention, 0);
+        assert!(!args.cli_delta_snapshots);
+        assert!(!args.cli_demo);
+        assert_eq!(
+            args.cli_refund_window,
+            super::db::DEFAULT_REFUND_WINDOW_HOURS
+        );
+        assert_eq!(args.cli_max_amount, super::amount::DEFAULT_MAX_AMOUNT);
+    }
+
+    #[test]
+    fn test_args_parsing_clock_strategy() {
+        use super::Args;
+        let args = Args::parse_from(vec!["my_program", "--cli-clock", "hybrid"]);
+        assert_eq!(args.cli_clock, super::clock::ClockStrategy::Hybrid);
+    }
+
+    #[test]
+    fn test_args_parsing_ephemeral() {
+        use super::Args;
+        let args = Args::parse_from(vec!["my_program", "--ephemeral"]);
+        assert!(args.cli_ephemeral);
+        assert!(
+            Args::try_parse_from(vec!["my_program", "--ephemeral", "--db-path", "a.db"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_args_parsing_output() {
+        use super::Args;
+        let args = Args::parse_from(vec!["my_program", "--output", "json"]);
+        assert_eq!(args.cli_output, super::control::OutputFormat::Json);
+    }
+
+    #[test]
+    fn test_args_parsing_transport() {
+        use super::Args;
+        let args = Args::parse_from(vec!["my_program", "--transport", "grpc"]);
+        assert_eq!(args.cli_transport, super::network::Transport::Grpc);
+    }
+
+    #[test]
+    fn test_args_parsing_client() {
+        use super::Args;
+        let args = Args::parse_from(vec!["my_program", "--client-of", "127.0.0.1:10000"]);
+        assert_eq!(args.cli_client_of.as_deref(), Some("127.0.0.1:10000"));
+        let args = Args::parse_from(vec!["my_program", "--trusted-clients", "kiosk1,kiosk2"]);
+        assert_eq!(args.cli_trusted_clients, vec!["kiosk1", "kiosk2"]);
+        let args = Args::parse_from(vec![
+            "my_program",
+            "--peer-allowlist",
+            "10.0.0.0/8,192.168.1.4",
+            "--peer-denylist",
+            "10.0.0.66",
+        ]);
+        assert_eq!(args.cli_peer_allowlist, vec!["10.0.0.0/8", "192.168.1.4"]);
+        assert_eq!(args.cli_peer_denylist, vec!["10.0.0.66"]);
+        assert!(
+            Args::try_parse_from(vec![
+                "my_program",
+                "--client-of",
+                "127.0.0.1:10000",
+                "--cli-peers",
+                "127.0.0.1:10001"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn test_node_config() {
+        use super::Args;
+        let args = Args::parse_from(vec![
+            "my_program",
+            "--cli-ip",
+            "[::1]",
+            "--cli-port",
+            "8080",
+            "--db-path",
+            "a.db",
+            "--web-port",
+            "8000",
+        ]);
+        let config = args.node_config().unwrap();
+        assert_eq!(
+            config.ip,
+            std::net::IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)
+        );
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.web_port, Some(8000));
+        assert_eq!(config.db_path, Some(std::path::PathBuf::from("a.db")));
+        assert_eq!(
+            config.mutex_timeout,
+            super::NodeConfig::default().mutex_timeout
+        );
+
+        let args = Args::parse_from(vec!["my_program", "--cli-ip", "localhost"]);
+        assert!(args.node_config().is_err());
+    }
+
+    #[test]
+    fn test_subcommands() {
+        use super::{Cli, CliCommand};
+        let cli = Cli::parse_from(vec!["my_program", "--cli-port", "8080"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.run.cli_port, 8080);
+
+        let cli = Cli::parse_from(vec!["my_program", "run", "--cli-port", "8080"]);
+        match cli.command {
+            Some(CliCommand::Run(args)) => assert_eq!(args.cli_port, 8080),
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        let cli = Cli::parse_from(vec![
+            "my_program",
+            "snapshot",
+            "--db-path",
+            "a.db",
+            "--output",
+            "x.json",
+        ]);
+        match cli.command {
+            Some(CliCommand::Snapshot { db, cli_output, .. }) => {
+                assert_eq!(db.cli_db_path.as_deref(), Some("a.db"));
+                assert_eq!(cli_output, "x.json");
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Cli::try_parse_from(vec!["my_program", "export", "--user", "alice"]).is_ok());
+        match Cli::parse_from(vec!["my_program", "graph", "--user", "alice"]).command {
+            Some(CliCommand::Graph {
+                cli_user,
+                cli_output,
+                ..
+            }) => {
+                assert_eq!(cli_user.as_deref(), Some("alice"));
+                assert_eq!(cli_output, None);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        match Cli::parse_from(vec!["my_program", "verify-snapshot", "snap.json"]).command {
+            Some(CliCommand::VerifySnapshot { cli_file }) => assert_eq!(cli_file, "snap.json"),
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(
+            Cli::try_parse_from(vec![
+                "my_program",
+                "keygen",
+                "--label",
+                "ops",
+                "--user",
+                "alice",
+                "--read-only"
+            ])
+            .is_err()
+        );
+        assert!(
+            Cli::try_parse_from(vec!["my_program", "--cli-port", "8080", "verify-db"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_generate_completion() {
+        use super::{Args, Cli};
+        use clap::CommandFactory;
+        let args = Args::parse_from(vec!["my_program", "--generate-completion", "zsh"]);
+        assert_eq!(
+            args.cli_generate_completion,
+            Some(clap_complete::Shell::Zsh)
+        );
+
+        let mut script = Vec::new();
+        clap_complete::generate(
+            clap_complete::Shell::Bash,
+            &mut Cli::command(),
+            "peillute",
+            &mut script,
+        );
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("--cli-site-id"));
+        assert!(script.contains("verify-db"));
+        assert!(script.contains("--generate-completion"));
     }
 }
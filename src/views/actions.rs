@@ -2,27 +2,80 @@
 //!
 //! This module provides components for various financial transactions in the system,
 //! including viewing transaction history, making deposits, withdrawals, payments,
-//! refunds, and transfers between users.
+//! refunds, transfers between users and bills split between several users.
 
+use super::api::*;
+use crate::amount::AmountLimits;
+use crate::confirmation::ConfirmationStatus;
+use crate::db::{HistoryOrder, REACTIONS, TransactionComment};
+use crate::settings::UserSettings;
 use dioxus::prelude::*;
 
 // show all transactions as vertical card list
 /// Transaction history component
 ///
 /// Displays a list of all transactions for a specific user, showing details such as
-/// the source and destination users, amount, and any associated messages.
+/// the source and destination users, amount, and any associated messages, with
+/// the comments and reactions of each transaction. The transactions of this
+/// site whose diffusion is not confirmed yet are flagged Pending or Failed.
+///
+/// The transactions are split in pages, of the size chosen in the preferences
+/// of the user. They are listed in the order the site recorded them, or sorted
+/// by Lamport time, by source site or in the causal order of their vector
+/// clocks.
 #[component]
 pub fn History(name: String) -> Element {
+    let settings = use_context::<Signal<UserSettings>>();
+    let mut page = use_signal(|| 0usize);
+    let mut order = use_signal(HistoryOrder::default);
     let name = std::rc::Rc::new(name);
     let name_for_future = name.clone();
+    let name_for_statuses = name.clone();
 
     let transactions_resource = use_resource(move || {
         let name_clone = name_for_future.clone();
-        async move { get_transactions_for_user_server(name_clone.to_string()).await }
+        let order = order();
+        async move { get_transactions_sorted_server(name_clone.to_string(), order, tab_id()).await }
+    });
+    let statuses_resource = use_resource(move || {
+        let name_clone = name_for_statuses.clone();
+        async move { get_unsettled_transactions_server(name_clone.to_string(), tab_id()).await }
     });
+    let statuses = statuses_resource
+        .read()
+        .as_ref()
+        .and_then(|statuses| statuses.as_ref().ok())
+        .cloned()
+        .unwrap_or_default();
+    let aliases_resource = use_resource(super::api::get_site_aliases_server);
+    let aliases = aliases_resource
+        .read()
+        .as_ref()
+        .and_then(|aliases| aliases.as_ref().ok())
+        .cloned()
+        .unwrap_or_default();
 
     rsx! {
         div { id: "history-page",
+            div { class: "history-order",
+                label { r#for: "history-order", "Sort by:" }
+                select {
+                    id: "history-order",
+                    value: order().as_str(),
+                    onchange: move |event| {
+                        order.set(HistoryOrder::parse(&event.value()).unwrap_or_default());
+                        page.set(0);
+                    },
+                    for history_order in HistoryOrder::ALL {
+                        option {
+                            key: "{history_order.as_str()}",
+                            value: history_order.as_str(),
+                            selected: order() == history_order,
+                            "{history_order.label()}"
+                        }
+                    }
+                }
+            }
             match &*transactions_resource.read() {
                 None => rsx! {
                     p { "Loading history..." }
@@ -33,9 +86,12 @@ pub fn History(name: String) -> Element {
                             p { "No transactions found for {name}." }
                         }
                     } else {
+                        let page_size = settings().history_page_size.max(1) as usize;
+                        let nb_pages = transactions.len().div_ceil(page_size);
+                        let current = page().min(nb_pages - 1);
                         rsx! {
                             ul { class: "transactions-list",
-                                for transaction in transactions.iter() {
+                                for transaction in transactions.iter().skip(current * page_size).take(page_size) {
                                     li {
                                         key: "{transaction.lamport_time}-{transaction.source_node}",
                                         class: "transaction-card",
@@ -49,7 +105,33 @@ pub fn History(name: String) -> Element {
                                         }
                                         p {
                                             strong { "Amount:" }
-                                            " {transaction.amount:.2}"
+                                            " {settings().currency.format(transaction.amount)}"
+                                        }
+                                        {
+                                            let site = crate::aliases::display_site(&transaction.source_node, &aliases);
+                                            rsx! {
+                                                p {
+                                                    strong { "Site:" }
+                                                    " {site}"
+                                                }
+                                            }
+                                        }
+                                        {
+                                            let status = statuses
+                                                .iter()
+                                                .find(|(lamport_time, source_node, _)| {
+                                                    *lamport_time == transaction.lamport_time
+                                                        && *source_node == transaction.source_node
+                                                })
+                                                .map(|(_, _, status)| status.as_str());
+                                            rsx! {
+                                                if let Some(status) = status {
+                                                    p { class: "transaction-status",
+                                                        strong { "Status:" }
+                                                        " {status}"
+                                                    }
+                                                }
+                                            }
                                         }
                                         if let Some(msg) = &transaction.optional_msg {
                                             if !msg.is_empty() {
@@ -59,6 +141,28 @@ pub fn History(name: String) -> Element {
                                                 }
                                             }
                                         }
+                                        TransactionComments {
+                                            name: name.to_string(),
+                                            lamport_time: transaction.lamport_time,
+                                            source_node: transaction.source_node.clone(),
+                                        }
+                                    }
+                                }
+                            }
+                            if nb_pages > 1 {
+                                div { class: "search-pagination",
+                                    button {
+                                        r#type: "button",
+                                        disabled: current == 0,
+                                        onclick: move |_| page.set(current.saturating_sub(1)),
+                                        "Previous"
+                                    }
+                                    span { "Page {current + 1} / {nb_pages}" }
+                                    button {
+                                        r#type: "button",
+                                        disabled: current + 1 >= nb_pages,
+                                        onclick: move |_| page.set(current + 1),
+                                        "Next"
                                     }
                                 }
                             }
@@ -73,17 +177,231 @@ pub fn History(name: String) -> Element {
     }
 }
 
+/// Comments and reactions of a transaction card
+///
+/// Shows the reactions grouped by emoji and the comments of the transaction,
+/// and lets the user of the page react or write a comment. Comments and
+/// reactions are diffused to every site.
+#[component]
+fn TransactionComments(name: String, lamport_time: i64, source_node: String) -> Element {
+    let node_for_resource = source_node.clone();
+    let mut comments_resource = use_resource(move || {
+        let node = node_for_resource.clone();
+        async move { get_comments_server(lamport_time, node).await }
+    });
+    let mut comment_input = use_signal(|| "".to_string());
+    let mut error_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
+
+    let comments: Vec<TransactionComment> = match &*comments_resource.read() {
+        Some(Ok(comments)) => comments.clone(),
+        _ => Vec::new(),
+    };
+    let name_for_comment = name.clone();
+    let node_for_comment = source_node.clone();
+
+    rsx! {
+        div { class: "transaction-comments",
+            div { class: "reactions",
+                for emoji in REACTIONS.iter() {
+                    {
+                        let count = comments
+                            .iter()
+                            .filter(|comment| comment.reaction && comment.text == *emoji)
+                            .count();
+                        let name = name.clone();
+                        let source_node = source_node.clone();
+                        rsx! {
+                            button {
+                                key: "{emoji}",
+                                r#type: "button",
+                                class: "reaction-btn",
+                                onclick: move |_| {
+                                    let name = name.clone();
+                                    let source_node = source_node.clone();
+                                    async move {
+                                        match comment_transaction_server(
+                                                lamport_time,
+                                                source_node,
+                                                name,
+                                                emoji.to_string(),
+                                                true,
+                                                tab_id(),
+                                            )
+                                            .await
+                                        {
+                                            Ok(ticket) => {
+                                                error_signal.set(None);
+                                                ticket_signal.set(Some(ticket));
+                                            }
+                                            Err(e) => error_signal.set(Some(format!("{e}"))),
+                                        }
+                                    }
+                                },
+                                if count > 0 {
+                                    "{emoji} {count}"
+                                } else {
+                                    "{emoji}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            ul { class: "comments-list",
+                for comment in comments.iter().filter(|comment| !comment.reaction) {
+                    li { key: "{comment.lamport_time}-{comment.source_node}",
+                        strong { "{comment.author}:" }
+                        " {comment.text}"
+                    }
+                }
+            }
+            form {
+                input {
+                    r#type: "text",
+                    placeholder: "Add a comment",
+                    maxlength: "{crate::db::MAX_COMMENT_LEN}",
+                    value: comment_input,
+                    oninput: move |event| comment_input.set(event.value()),
+                }
+                button {
+                    r#type: "submit",
+                    onclick: move |_| {
+                        let name = name_for_comment.clone();
+                        let source_node = node_for_comment.clone();
+                        let text = comment_input.read().trim().to_string();
+                        async move {
+                            match comment_transaction_server(lamport_time, source_node, name, text, false, tab_id())
+                                .await
+                            {
+                                Ok(ticket) => {
+                                    comment_input.set("".to_string());
+                                    error_signal.set(None);
+                                    ticket_signal.set(Some(ticket));
+                                }
+                                Err(e) => error_signal.set(Some(format!("{e}"))),
+                            }
+                        }
+                    },
+                    "Comment"
+                }
+            }
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation {
+                    ticket,
+                    on_final: move |_| comments_resource.restart(),
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+    }
+}
+
+/// Fetches the amount limits of the site, the default ones until they are known
+fn use_amount_limits() -> Memo<AmountLimits> {
+    let limits_resource = use_resource(get_amount_limits);
+    use_memo(move || match &*limits_resource.read() {
+        Some(Ok(limits)) => *limits,
+        _ => AmountLimits::default(),
+    })
+}
+
+/// Amounts offered as quick-amount buttons, in euros
+const QUICK_AMOUNTS: [u32; 4] = [5, 10, 20, 50];
+
+/// Quick amounts component
+///
+/// Shows a button for each of [`QUICK_AMOUNTS`], filling the amount input
+/// when clicked, and the result of the amount typed when it is an arithmetic
+/// expression such as `12.5+3`.
+#[component]
+fn QuickAmounts(mut amount: Signal<String>) -> Element {
+    let limits = use_amount_limits();
+    let result = crate::amount::is_expression(&amount.read())
+        .then(|| crate::amount::evaluate(&amount.read(), &limits()));
+
+    rsx! {
+        div { class: "quick-amounts",
+            for quick in QUICK_AMOUNTS {
+                button {
+                    r#type: "button",
+                    key: "{quick}",
+                    onclick: move |_| amount.set(quick.to_string()),
+                    "{quick} €"
+                }
+            }
+        }
+        match result {
+            Some(Ok(value)) => rsx! {
+                p { class: "amount-result", "= {value:.2} €" }
+            },
+            Some(Err(e)) => rsx! {
+                p { class: "amount-result invalid", "{e}" }
+            },
+            None => rsx! {},
+        }
+    }
+}
+
+/// Balance preview component
+///
+/// Shows the balances after a withdrawal, or a transfer to `to_user` if not
+/// empty, as the amount is typed, with the fee and the reason the operation
+/// would be rejected. Nothing is shown while the amount is not valid.
+#[component]
+fn BalancePreview(name: String, to_user: String, amount: String) -> Element {
+    let limits = use_amount_limits();
+    let preview_resource = use_resource(use_reactive(
+        (&name, &to_user, &amount),
+        move |(name, to_user, amount)| {
+            let valid = crate::amount::evaluate(&amount, &limits()).is_ok();
+            async move {
+                if !valid {
+                    return None;
+                }
+                preview_transaction_server(name, to_user, amount, tab_id())
+                    .await
+                    .ok()
+            }
+        },
+    ));
+
+    match &*preview_resource.read() {
+        Some(Some(preview)) => rsx! {
+            div { class: "balance-preview",
+                p { "Balance after: {preview.payer_balance:.2} €" }
+                if preview.fee > 0.0 {
+                    p { "Fee: {preview.fee:.2} €" }
+                }
+                if let Some(balance) = preview.beneficiary_balance {
+                    p { "Balance of {to_user} after: {balance:.2} €" }
+                }
+                if let Some(rejection) = &preview.rejection {
+                    p { class: "error-message", "{rejection}" }
+                }
+            }
+        },
+        _ => rsx! {},
+    }
+}
+
 // take the username and collect the an amount (float from form) to make a withdrawal
 /// Withdrawal component
 ///
 /// Provides a form for users to withdraw money from their account, with input
-/// validation to ensure positive amounts and sufficient funds.
+/// validation to ensure positive amounts and sufficient funds, and a preview
+/// of the balance after the withdrawal. The amount can be picked from the
+/// quick amounts or typed as an expression such as `12.5+3`.
 #[component]
 pub fn Withdraw(name: String) -> Element {
-    let mut withdraw_amount = use_signal(|| 0f64);
+    let mut withdraw_amount = use_signal(String::new);
+    let limits = use_amount_limits();
     let name = std::rc::Rc::new(name);
 
     let mut error_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
 
     let name_for_future = name.clone();
 
@@ -92,41 +410,41 @@ pub fn Withdraw(name: String) -> Element {
             form {
                 label { r#for: "fwithdraw", "Withdraw amount :" }
                 input {
-                    r#type: "number",
+                    r#type: "text",
+                    inputmode: "decimal",
                     id: "form-withdraw",
                     r#name: "fwithdraw",
-                    step: 0.01,
+                    placeholder: "0.00",
                     value: "{withdraw_amount}",
-                    oninput: move |event| {
-                        if let Ok(as_number) = event.value().parse::<f64>() {
-                            withdraw_amount.set(as_number);
-                        }
-                    },
+                    oninput: move |event| withdraw_amount.set(event.value()),
                 }
+                QuickAmounts { amount: withdraw_amount }
                 button {
                     r#type: "submit",
                     onclick: move |_| {
                         let name = name_for_future.clone();
-                        let amount = *withdraw_amount.read();
+                        let amount = withdraw_amount.read().clone();
+                        let checked = crate::amount::evaluate(&amount, &limits());
                         async move {
-                            if amount >= 0.0 {
-                                if let Ok(_) = withdraw_for_user_server(name.to_string(), amount).await {
-                                    withdraw_amount.set(0.0);
-                                    error_signal.set(None);
+                            match checked {
+                                Ok(_) => {
+                                    if let Ok(ticket) = withdraw_for_user_server(name.to_string(), amount, tab_id()).await {
+                                        withdraw_amount.set(String::new());
+                                        error_signal.set(None);
+                                        ticket_signal.set(Some(ticket));
+                                    }
                                 }
-                            } else {
-                                error_signal
-                                    .set(
-                                        Some(
-                                            format!("Please enter a positive amount, you gave {amount}."),
-                                        ),
-                                    );
+                                Err(e) => error_signal.set(Some(e)),
                             }
                         }
                     },
                     "Submit"
                 }
             }
+            BalancePreview { name: name.to_string(), to_user: String::new(), amount: withdraw_amount() }
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation { ticket }
+            }
             if let Some(error) = &*error_signal.read() {
                 p { class: "error-message", "{error}" }
             }
@@ -139,43 +457,111 @@ const CHIPS_IMG: Asset = asset!("/assets/images/chips.png");
 const SANDWICH_IMG: Asset = asset!("/assets/images/sandwich.png");
 const COFFEE_IMG: Asset = asset!("/assets/images/coffee.png");
 
-const PRODUCTS: &[(&str, f64, Asset)] = &[
-    ("Coca", 1.50, COCA_IMG),
-    ("Chips", 2.00, CHIPS_IMG),
-    ("Sandwich", 4.50, SANDWICH_IMG),
-    ("Coffee", 1.20, COFFEE_IMG),
+/// Images of the default products, used until another image is uploaded
+const DEFAULT_PRODUCT_IMAGES: &[(&str, Asset)] = &[
+    ("Coca", COCA_IMG),
+    ("Chips", CHIPS_IMG),
+    ("Sandwich", SANDWICH_IMG),
+    ("Coffee", COFFEE_IMG),
 ];
 
+/// Returns the image to display for a product, if any
+fn product_image(product: &crate::db::Product) -> Option<String> {
+    product.image.clone().or_else(|| {
+        DEFAULT_PRODUCT_IMAGES
+            .iter()
+            .find(|(name, _)| *name == product.name)
+            .map(|(_, image)| image.to_string())
+    })
+}
+
 // take the username and collect the an amount (float from form) to make a payment
 /// Payment component
 ///
 /// Implements a product catalog interface where users can select items to purchase,
 /// with a running total and order summary. Supports multiple products with
-/// individual quantity selection.
+/// individual quantity selection. The catalog is shared by all the sites and
+/// managed from the products page. A tip can be added to the order, and the
+/// bill split equally with other users of the ledger.
 #[component]
 pub fn Pay(name: String) -> Element {
-    let mut product_quantities = use_signal(|| vec![0u32; PRODUCTS.len()]);
+    let products_resource = use_resource(super::products::get_products_server);
+    let mut product_quantities = use_signal(std::collections::HashMap::<String, u32>::new);
+    let mut tip_percent = use_signal(|| 0f64);
+    let mut co_payers = use_signal(Vec::<String>::new);
     let name_for_payment = std::rc::Rc::new(name.clone());
+    let name_for_future = std::rc::Rc::new(name.clone());
 
     let mut error_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
 
-    let handle_pay = move |_| {
-        let current_quantities = product_quantities.read().clone();
-        let name_clone = name_for_payment.clone();
+    let current_total_display = use_memo(move || {
+        let quantities_read = product_quantities.read();
+        match &*products_resource.read() {
+            Some(Ok(products)) => products
+                .iter()
+                .map(|p| p.price * quantities_read.get(&p.name).copied().unwrap_or(0) as f64)
+                .sum(),
+            _ => 0.0,
+        }
+    });
 
-        let mut total_amount = 0.0;
-        for (i, &(_, price, _)) in PRODUCTS.iter().enumerate() {
-            if let Some(&quantity) = current_quantities.get(i) {
-                total_amount += price * quantity as f64;
-            }
+    let users_resource = use_resource(move || {
+        let current_user = name_for_future.clone();
+        async move {
+            get_users_server(current_user.to_string())
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|u| u != current_user.as_ref())
+                .collect::<Vec<_>>()
         }
+    });
+
+    // Share of each payer, the current user first
+    let shares = use_memo(move || {
+        crate::utils::split_with_tip(
+            current_total_display(),
+            tip_percent(),
+            co_payers.read().len() + 1,
+        )
+    });
+    let total_with_tip = use_memo(move || shares.read().iter().sum::<f64>());
+
+    let handle_pay = move |_| {
+        let name_clone = name_for_payment.clone();
+        let total_amount = current_total_display();
+        let tip = tip_percent();
+        let split_with = co_payers();
 
         spawn(async move {
             if total_amount > 0.0 {
-                if let Ok(_) = pay_for_user_server(name_clone.to_string(), total_amount).await {
-                    log::info!("Payment successful.");
-                    product_quantities.set(vec![0u32; PRODUCTS.len()]);
-                    error_signal.set(None);
+                let result = if tip == 0.0 && split_with.is_empty() {
+                    pay_for_user_server(
+                        name_clone.to_string(),
+                        format!("{total_amount:.2}"),
+                        tab_id(),
+                    )
+                    .await
+                } else {
+                    split_pay_for_user_server(
+                        name_clone.to_string(),
+                        format!("{total_amount:.2}"),
+                        tip,
+                        split_with,
+                        tab_id(),
+                    )
+                    .await
+                };
+                match result {
+                    Ok(ticket) => {
+                        log::info!("Payment successful.");
+                        product_quantities.set(std::collections::HashMap::new());
+                        co_payers.set(Vec::new());
+                        error_signal.set(None);
+                        ticket_signal.set(Some(ticket));
+                    }
+                    Err(e) => error_signal.set(Some(format!("Payment failed: {e}"))),
                 }
             } else {
                 log::warn!("Attempted to pay with a total of 0.0. No action taken.");
@@ -186,57 +572,121 @@ pub fn Pay(name: String) -> Element {
         });
     };
 
-    let current_total_display = use_memo(move || {
-        let mut total = 0.0;
-        let quantities_read = product_quantities.read();
-        for (i, &(_, price, _)) in PRODUCTS.iter().enumerate() {
-            if let Some(&quantity) = quantities_read.get(i) {
-                total += price * quantity as f64;
-            }
-        }
-        total
-    });
-
     rsx! {
         div { id: "pay-page",
-            div {
-                for (index , (product_name , price , image_path)) in PRODUCTS.iter().enumerate() {
-                    div { key: "{product_name}-{index}",
-                        img { src: "{image_path}", alt: "{product_name}" }
-                        div { class: "product-info",
-                            h3 { "{product_name}" }
-                            p { "€{price:.2}" }
-                            div {
-                                label { r#for: "qty-{index}", "Quantity:" }
-                                input {
-                                    r#type: "number",
-                                    id: "qty-{index}",
-                                    min: "0",
-                                    value: "{product_quantities.read()[index]}",
-                                    oninput: move |event| {
-                                        let mut pq_signal_for_input = product_quantities;
-                                        if let Ok(new_quantity) = event.value().parse::<u32>() {
-                                            let mut quantities_writer = pq_signal_for_input.write();
-                                            if index < quantities_writer.len() {
-                                                quantities_writer[index] = new_quantity;
-                                            }
-                                        } else if event.value().is_empty() {
-                                            let mut quantities_writer = pq_signal_for_input.write();
-                                            if index < quantities_writer.len() {
-                                                quantities_writer[index] = 0;
+            match &*products_resource.read() {
+                None => rsx! {
+                    p { "Loading products..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading products: {e}" }
+                },
+                Some(Ok(products)) if products.is_empty() => rsx! {
+                    p { class: "no-data-message", "No products in the catalog." }
+                },
+                Some(Ok(products)) => rsx! {
+                    div {
+                        for (index , product) in products.iter().enumerate() {
+                            div { key: "{product.name}",
+                                if let Some(image_path) = product_image(product) {
+                                    img { src: "{image_path}", alt: "{product.name}" }
+                                }
+                                div { class: "product-info",
+                                    h3 { "{product.name}" }
+                                    p { "€{product.price:.2}" }
+                                    div {
+                                        label { r#for: "qty-{index}", "Quantity:" }
+                                        {
+                                            let product_name = product.name.clone();
+                                            let quantity = product_quantities
+                                                .read()
+                                                .get(&product_name)
+                                                .copied()
+                                                .unwrap_or(0);
+                                            rsx! {
+                                                input {
+                                                    r#type: "number",
+                                                    id: "qty-{index}",
+                                                    min: "0",
+                                                    value: "{quantity}",
+                                                    oninput: move |event| {
+                                                        let quantity = if event.value().is_empty() {
+                                                            Some(0)
+                                                        } else {
+                                                            event.value().parse::<u32>().ok()
+                                                        };
+                                                        if let Some(quantity) = quantity {
+                                                            product_quantities.write().insert(product_name.clone(), quantity);
+                                                        }
+                                                    },
+                                                }
                                             }
                                         }
-                                    },
+                                    }
                                 }
                             }
                         }
                     }
-                }
+                },
             }
 
             div { class: "cart-summary",
                 h2 { "Order Summary" }
-                h3 { "Total: €{current_total_display():.2}" }
+                label { r#for: "pay-tip", "Tip (%):" }
+                input {
+                    r#type: "number",
+                    id: "pay-tip",
+                    min: "0",
+                    step: 0.5,
+                    value: "{tip_percent}",
+                    oninput: move |evt| {
+                        if let Ok(val) = evt.value().parse::<f64>() {
+                            tip_percent.set(val.max(0.0));
+                        }
+                    },
+                }
+                if let Some(users) = &*users_resource.read() {
+                    if !users.is_empty() {
+                        label { "Split with:" }
+                        ul { class: "pay-split-users",
+                            for user in users.iter().cloned() {
+                                li { key: "{user}",
+                                    label {
+                                        input {
+                                            r#type: "checkbox",
+                                            checked: co_payers.read().contains(&user),
+                                            onchange: {
+                                                let user = user.clone();
+                                                move |evt: Event<FormData>| {
+                                                    let mut selected = co_payers.write();
+                                                    if evt.checked() {
+                                                        if !selected.contains(&user) {
+                                                            selected.push(user.clone());
+                                                        }
+                                                    } else {
+                                                        selected.retain(|u| u != &user);
+                                                    }
+                                                }
+                                            },
+                                        }
+                                        " {user}"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                h3 { "Total: €{total_with_tip():.2}" }
+                if !co_payers.read().is_empty() {
+                    ul { class: "pay-shares",
+                        for (payer , share) in std::iter::once(name.clone())
+                            .chain(co_payers.read().iter().cloned())
+                            .zip(shares.read().iter().copied())
+                        {
+                            li { key: "{payer}", "{payer}: €{share:.2}" }
+                        }
+                    }
+                }
                 form {
                     button {
                         r#type: "submit",
@@ -247,6 +697,9 @@ pub fn Pay(name: String) -> Element {
                 }
             }
 
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation { ticket }
+            }
             if let Some(error) = &*error_signal.read() {
                 p { class: "error-message", "{error}" }
             }
@@ -260,21 +713,33 @@ pub fn Pay(name: String) -> Element {
 ///
 /// Displays a list of transactions that can be refunded, allowing users to
 /// reverse previous transactions. Shows transaction details and provides
-/// refund functionality.
+/// refund functionality. The refund of a transfer sent by the user is only
+/// requested, its recipient approving it from their user page.
 #[component]
 pub fn Refund(name: String) -> Element {
     let name = std::rc::Rc::new(name);
     let name_for_future = name.clone();
 
     let mut error_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
 
     let transactions_resource = use_resource(move || {
         let name_clone = name_for_future.clone();
-        async move { get_transactions_for_user_server(name_clone.to_string()).await }
+        async move { get_refundable_transactions_server(name_clone.to_string(), tab_id()).await }
     });
+    let aliases_resource = use_resource(super::api::get_site_aliases_server);
+    let aliases = aliases_resource
+        .read()
+        .as_ref()
+        .and_then(|aliases| aliases.as_ref().ok())
+        .cloned()
+        .unwrap_or_default();
 
     rsx! {
         div { id: "refund-page",
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation { ticket }
+            }
             match &*transactions_resource.read() {
                 None => rsx! {
                     p { "Loading history..." }
@@ -288,7 +753,7 @@ pub fn Refund(name: String) -> Element {
                     } else {
                         rsx! {
                             ul { class: "transactions-list",
-                                for transaction in transactions.iter() {
+                                for (transaction, blocked_by) in transactions.iter().map(|refundable| (&refundable.transaction, &refundable.blocked_by)) {
                                     li {
                                         key: "{transaction.lamport_time}-{transaction.source_node}",
                                         class: "transaction-card",
@@ -304,6 +769,15 @@ pub fn Refund(name: String) -> Element {
                                             strong { "Amount:" }
                                             " {transaction.amount:.2}"
                                         }
+                                        {
+                                            let site = crate::aliases::display_site(&transaction.source_node, &aliases);
+                                            rsx! {
+                                                p {
+                                                    strong { "Site:" }
+                                                    " {site}"
+                                                }
+                                            }
+                                        }
                                         if let Some(msg) = &transaction.optional_msg {
                                             if !msg.is_empty() {
                                                 p {
@@ -315,36 +789,47 @@ pub fn Refund(name: String) -> Element {
                                         {
                                             let transaction_for_refund = transaction.clone();
                                             let name_for_refund = name.clone();
-                                            let mut resource_to_refresh = transactions_resource.clone();
+                                            let mut resource_to_refresh = transactions_resource;
+                                            let label = if crate::refund_requests::needs_approval(&name, &transaction.to_user) {
+                                                "Request refund"
+                                            } else {
+                                                "Refund"
+                                            };
                                             rsx! {
                                                 button {
                                                     r#type: "submit",
+                                                    disabled: blocked_by.is_some(),
+                                                    title: blocked_by.clone().unwrap_or_default(),
                                                     onclick: move |_| {
                                                         let name_for_future = name_for_refund.clone();
                                                         let transaction_for_future = transaction_for_refund.clone();
                                                         async move {
-                                                            if let Ok(_) = refund_transaction_server(
+                                                            if let Ok(ticket) = refund_transaction_server(
                                                                     name_for_future.to_string(),
                                                                     transaction_for_future.lamport_time,
                                                                     transaction_for_future.source_node,
+                                                                    tab_id(),
                                                                 )
                                                                 .await
-                                                            {
-                                                                if let Ok(_) = get_transactions_for_user_server(
+                                                                && let Ok(_) = get_transactions_for_user_server(
                                                                         name_for_future.to_string(),
+                                                                        tab_id(),
                                                                     )
                                                                     .await
                                                                 {
                                                                     error_signal.set(None);
+                                                                    ticket_signal.set(Some(ticket));
                                                                     resource_to_refresh.restart();
                                                                 }
-                                                            }
                                                         }
                                                     },
-                                                    "Refund"
+                                                    "{label}"
                                                 }
                                             }
                                         }
+                                        if let Some(reason) = blocked_by {
+                                            p { class: "refund-blocked", "{reason}" }
+                                        }
                                     }
                                 }
                             }
@@ -369,24 +854,31 @@ pub fn Refund(name: String) -> Element {
 ///
 /// Enables users to transfer money to other users in the system, with features for:
 /// - Selecting the recipient from a list of available users
-/// - Specifying the transfer amount
+/// - Specifying the transfer amount, picked from the quick amounts or typed as
+///   an expression such as `12.5+3`
 /// - Adding an optional message to the transaction
 /// - Generating random messages for fun
+/// - Previewing the balances after the transfer
 #[component]
 pub fn Transfer(name: String) -> Element {
-    let mut transfer_amount = use_signal(|| 0f64);
+    let mut transfer_amount = use_signal(String::new);
+    let limits = use_amount_limits();
     let mut transfer_message = use_signal(String::new);
     let mut selected_user = use_signal(String::new);
     let name = std::rc::Rc::new(name);
     let name_for_future = name.clone();
+    let name_for_preview = name.clone();
 
     let mut error_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
 
     let users_resource = use_resource({
         move || {
             let current_user = name_for_future.clone();
             async move {
-                let all_users = get_users_server().await.unwrap_or_default();
+                let all_users = get_users_server(current_user.to_string())
+                    .await
+                    .unwrap_or_default();
                 all_users
                     .into_iter()
                     .filter(|u| u != current_user.as_ref())
@@ -421,16 +913,14 @@ pub fn Transfer(name: String) -> Element {
                         }
                         label { r#for: "transfer-amount", "Amount to transfer:" }
                         input {
-                            r#type: "number",
+                            r#type: "text",
+                            inputmode: "decimal",
                             id: "transfer-amount",
-                            step: 0.01,
+                            placeholder: "0.00",
                             value: "{transfer_amount}",
-                            oninput: move |evt| {
-                                if let Ok(val) = evt.value().parse::<f64>() {
-                                    transfer_amount.set(val);
-                                }
-                            },
+                            oninput: move |evt| transfer_amount.set(evt.value()),
                         }
+                        QuickAmounts { amount: transfer_amount }
                         label { r#for: "transfer-message", "Message (optional):" }
                         input {
                             r#type: "text",
@@ -455,32 +945,34 @@ pub fn Transfer(name: String) -> Element {
                             r#type: "submit",
                             onclick: move |_| {
                                 let to_user = selected_user.read().clone();
-                                let amount = *transfer_amount.read();
+                                let amount = transfer_amount.read().clone();
+                                let checked = crate::amount::evaluate(&amount, &limits());
                                 let message = transfer_message.read().clone();
                                 let from_user = name.clone();
                                 async move {
-                                    if !to_user.is_empty() && amount > 0.0 {
-                                        if let Ok(_) = transfer_from_user_to_user_server(
-                                                from_user.to_string(),
-                                                to_user,
-                                                amount,
-                                                message,
-                                            )
-                                            .await
-                                        {
-                                            transfer_amount.set(0.0);
-                                            transfer_message.set(String::new());
-                                            selected_user.set(String::new());
-                                            error_signal.set(None);
+                                    if to_user.is_empty() {
+                                        error_signal.set(Some("Please select a user.".to_string()));
+                                        return;
+                                    }
+                                    match checked {
+                                        Ok(_) => {
+                                            if let Ok(ticket) = transfer_from_user_to_user_server(
+                                                    from_user.to_string(),
+                                                    to_user,
+                                                    amount,
+                                                    message,
+                                                    tab_id(),
+                                                )
+                                                .await
+                                            {
+                                                transfer_amount.set(String::new());
+                                                transfer_message.set(String::new());
+                                                selected_user.set(String::new());
+                                                error_signal.set(None);
+                                                ticket_signal.set(Some(ticket));
+                                            }
                                         }
-                                    } else {
-                                        error_signal
-                                            .set(
-                                                Some(
-                                                    "Please select a user and enter a positive amount."
-                                                        .to_string(),
-                                                ),
-                                            );
+                                        Err(e) => error_signal.set(Some(e)),
                                     }
                                 }
                             },
@@ -489,6 +981,221 @@ pub fn Transfer(name: String) -> Element {
                     }
                 },
             }
+            if !selected_user.read().is_empty() {
+                BalancePreview {
+                    name: name_for_preview.to_string(),
+                    to_user: selected_user(),
+                    amount: transfer_amount(),
+                }
+            }
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation { ticket }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+    }
+}
+
+/// Split bill component
+///
+/// Enables a user to pay for several other users at once, with features for:
+/// - Selecting the beneficiaries from a list of available users
+/// - Splitting a total amount equally, or entering a custom amount per beneficiary
+/// - Previewing the share of each beneficiary before submitting
+///
+/// All the transfers are applied atomically on every site.
+#[component]
+pub fn SplitBill(name: String) -> Element {
+    let mut selected_users = use_signal(Vec::<String>::new);
+    let mut equal_split = use_signal(|| true);
+    let mut total_amount = use_signal(|| 0f64);
+    let mut custom_amounts = use_signal(std::collections::HashMap::<String, f64>::new);
+    let limits = use_amount_limits();
+    let name = std::rc::Rc::new(name);
+    let name_for_future = name.clone();
+
+    let mut error_signal = use_signal(|| None::<String>);
+    let mut success_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
+
+    let users_resource = use_resource({
+        move || {
+            let current_user = name_for_future.clone();
+            async move {
+                let all_users = get_users_server(current_user.to_string())
+                    .await
+                    .unwrap_or_default();
+                all_users
+                    .into_iter()
+                    .filter(|u| u != current_user.as_ref())
+                    .collect::<Vec<_>>()
+            }
+        }
+    });
+
+    // Share of each selected beneficiary, in selection order
+    let transfers = use_memo(move || {
+        let selected = selected_users.read().clone();
+        if equal_split() {
+            let shares = crate::utils::split_equally(total_amount(), selected.len());
+            selected.into_iter().zip(shares).collect::<Vec<_>>()
+        } else {
+            let amounts = custom_amounts.read();
+            selected
+                .into_iter()
+                .map(|u| {
+                    let amount = amounts.get(&u).copied().unwrap_or(0.0);
+                    (u, amount)
+                })
+                .collect::<Vec<_>>()
+        }
+    });
+
+    rsx! {
+        div { id: "split-bill-page",
+            match &*users_resource.read() {
+                None => rsx! {
+                    p { "Loading users..." }
+                },
+                Some(users) => rsx! {
+                    form {
+                        label { "Beneficiaries:" }
+                        ul { class: "split-bill-users",
+                            for user in users.iter().cloned() {
+                                li { key: "{user}",
+                                    label {
+                                        input {
+                                            r#type: "checkbox",
+                                            checked: selected_users.read().contains(&user),
+                                            onchange: {
+                                                let user = user.clone();
+                                                move |evt: Event<FormData>| {
+                                                    let mut selected = selected_users.write();
+                                                    if evt.checked() {
+                                                        if !selected.contains(&user) {
+                                                            selected.push(user.clone());
+                                                        }
+                                                    } else {
+                                                        selected.retain(|u| u != &user);
+                                                    }
+                                                }
+                                            },
+                                        }
+                                        " {user}"
+                                    }
+                                }
+                            }
+                        }
+                        label { r#for: "split-mode", "Split mode:" }
+                        select {
+                            id: "split-mode",
+                            onchange: move |evt| {
+                                equal_split.set(evt.value() == "equal");
+                            },
+                            option { value: "equal", selected: equal_split(), "Equal split" }
+                            option { value: "custom", selected: !equal_split(), "Custom amounts" }
+                        }
+                        if equal_split() {
+                            label { r#for: "split-total", "Total amount:" }
+                            input {
+                                r#type: "number",
+                                id: "split-total",
+                                step: 0.01,
+                                value: "{total_amount}",
+                                oninput: move |evt| {
+                                    if let Ok(val) = evt.value().parse::<f64>() {
+                                        total_amount.set(val);
+                                    }
+                                },
+                            }
+                        } else {
+                            for user in selected_users.read().iter().cloned() {
+                                label { key: "{user}", r#for: "split-{user}", "Amount for {user}:" }
+                                input {
+                                    r#type: "number",
+                                    id: "split-{user}",
+                                    step: 0.01,
+                                    value: "{custom_amounts.read().get(&user).copied().unwrap_or(0.0)}",
+                                    oninput: {
+                                        let user = user.clone();
+                                        move |evt: Event<FormData>| {
+                                            if let Ok(val) = evt.value().parse::<f64>() {
+                                                custom_amounts.write().insert(user.clone(), val);
+                                            }
+                                        }
+                                    },
+                                }
+                            }
+                        }
+                        if !transfers.read().is_empty() {
+                            ul { class: "split-bill-preview",
+                                for (user , amount) in transfers.read().iter() {
+                                    li { key: "{user}", "{user}: {amount} €" }
+                                }
+                            }
+                        }
+                        button {
+                            r#type: "submit",
+                            onclick: move |_| {
+                                let from_user = name.clone();
+                                let transfers = transfers
+                                    .read()
+                                    .iter()
+                                    .map(|(user, amount)| {
+                                        crate::amount::validate(*amount, &limits())
+                                            .map(|amount| (user.clone(), amount))
+                                            .map_err(|e| format!("{user}: {e}"))
+                                    })
+                                    .collect::<Result<Vec<_>, _>>();
+                                async move {
+                                    let transfers = match transfers {
+                                        Ok(transfers) if !transfers.is_empty() => transfers,
+                                        Ok(_) => {
+                                            success_signal.set(None);
+                                            error_signal
+                                                .set(Some("Please select at least one user.".to_string()));
+                                            return;
+                                        }
+                                        Err(e) => {
+                                            success_signal.set(None);
+                                            error_signal.set(Some(e));
+                                            return;
+                                        }
+                                    };
+                                    let total: f64 = transfers.iter().map(|(_, amount)| amount).sum();
+                                    let transfers = transfers
+                                        .into_iter()
+                                        .map(|(user, amount)| (user, format!("{amount:.2}")))
+                                        .collect();
+                                    match split_bill_server(from_user.to_string(), transfers, tab_id()).await {
+                                        Ok(ticket) => {
+                                            ticket_signal.set(Some(ticket));
+                                            selected_users.set(Vec::new());
+                                            custom_amounts.set(std::collections::HashMap::new());
+                                            total_amount.set(0.0);
+                                            error_signal.set(None);
+                                            success_signal.set(Some(format!("Bill of {total} € split.")));
+                                        }
+                                        Err(e) => {
+                                            success_signal.set(None);
+                                            error_signal.set(Some(format!("{e}")));
+                                        }
+                                    }
+                                }
+                            },
+                            "Split bill"
+                        }
+                    }
+                },
+            }
+            if let Some(msg) = &*success_signal.read() {
+                p { class: "no-data-message", "{msg}" }
+            }
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation { ticket }
+            }
             if let Some(error) = &*error_signal.read() {
                 p { class: "error-message", "{error}" }
             }
@@ -500,13 +1207,16 @@ pub fn Transfer(name: String) -> Element {
 /// Deposit component
 ///
 /// Provides a form for users to deposit money into their account, with input
-/// validation to ensure positive amounts.
+/// validation to ensure positive amounts. The amount can be picked from the
+/// quick amounts or typed as an expression such as `12.5+3`.
 #[component]
 pub fn Deposit(name: String) -> Element {
-    let mut deposit_amount = use_signal(|| 0f64);
+    let mut deposit_amount = use_signal(String::new);
+    let limits = use_amount_limits();
     let name = std::rc::Rc::new(name);
 
     let mut error_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
 
     let name_for_future = name.clone();
 
@@ -515,41 +1225,40 @@ pub fn Deposit(name: String) -> Element {
             form {
                 label { r#for: "fdeposit", "Deposit amount :" }
                 input {
-                    r#type: "number",
+                    r#type: "text",
+                    inputmode: "decimal",
                     id: "form-deposit",
                     r#name: "fdeposit",
-                    step: 0.01,
+                    placeholder: "0.00",
                     value: "{deposit_amount}",
-                    oninput: move |event| {
-                        if let Ok(as_number) = event.value().parse::<f64>() {
-                            deposit_amount.set(as_number);
-                        }
-                    },
+                    oninput: move |event| deposit_amount.set(event.value()),
                 }
+                QuickAmounts { amount: deposit_amount }
                 button {
                     r#type: "submit",
                     onclick: move |_| {
                         let name = name_for_future.clone();
-                        let amount = *deposit_amount.read();
+                        let amount = deposit_amount.read().clone();
+                        let checked = crate::amount::evaluate(&amount, &limits());
                         async move {
-                            if amount >= 0.0 {
-                                if let Ok(_) = deposit_for_user_server(name.to_string(), amount).await {
-                                    deposit_amount.set(0.0);
-                                    error_signal.set(None);
+                            match checked {
+                                Ok(_) => {
+                                    if let Ok(ticket) = deposit_for_user_server(name.to_string(), amount, tab_id()).await {
+                                        deposit_amount.set(String::new());
+                                        error_signal.set(None);
+                                        ticket_signal.set(Some(ticket));
+                                    }
                                 }
-                            } else {
-                                error_signal
-                                    .set(
-                                        Some(
-                                            format!("Please enter a positive amount, you gave {amount}."),
-                                        ),
-                                    );
+                                Err(e) => error_signal.set(Some(e)),
                             }
                         }
                     },
                     "Submit"
                 }
             }
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation { ticket }
+            }
             if let Some(error) = &*error_signal.read() {
                 p { class: "error-message", "{error}" }
             }
@@ -557,182 +1266,56 @@ pub fn Deposit(name: String) -> Element {
     }
 }
 
-#[cfg(feature = "server")]
-const RANDOM_MESSAGE: &[&str] = &[
-    "Prend tes 200 balles et va te payer des cours de theatre",
-    "C'est pour toi bb",
-    "Love sur toi",
-    "Phrase non aléatoire",
-    "Votre argent messire",
-    "Acompte sur livraison cocaine",
-    "Votre argent seigneur",
-    "Pour tout ce que tu fais pour moi",
-    "Remboursement horny.com",
-    "Puta, où tu étais quand j'mettais des sept euros d'essence",
-    "Parce que l'argent n'est pas un problème pour moi",
-    "Tiens le rat",
-    "Pour le rein",
-    "Abonnement OnlyFans",
-    "Pour notre dernière nuit, pourboire non compris",
-    "ça fait beaucoup la non ?",
-    "Chantage SexTape",
-    "Argent sale",
-    "Adhésion front national",
-    "Ce que tu sais...",
-    "Remboursement dot de ta soeur",
-    "Rien à ajouter",
-    "Téléphone rose",
-    "Raison : \"GnaGnaGna moi je paye pas pour vous\"",
-    "Fond de tiroir",
-    "Epilation des zones intimes",
-    "Pour m'avoir gratouillé le dos",
-    "La reine Babeth vous offre cet argent",
-    "Nan t'inquiete",
-];
-
-#[cfg(feature = "server")]
-fn get_seed() -> u64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos() as u64
-}
-
-#[cfg(feature = "server")]
-fn lcg(seed: u64) -> u64 {
-    const A: u64 = 6364136223846793005;
-    const C: u64 = 1;
-    seed.wrapping_mul(A).wrapping_add(C)
-}
-
-#[server]
-async fn get_random_message_server() -> Result<String, ServerFnError> {
-    let seed = get_seed();
-    let random_number = lcg(seed);
-    let message = RANDOM_MESSAGE[random_number as usize % RANDOM_MESSAGE.len()];
-    Ok(message.to_string())
-}
-
-#[server]
-async fn get_users_server() -> Result<Vec<String>, ServerFnError> {
-    use crate::db;
-    let users = db::get_users()?;
-    Ok(users)
-}
-
-#[server]
-async fn deposit_for_user_server(user: String, amount: f64) -> Result<(), ServerFnError> {
-    if amount < 0.0 {
-        return Err(ServerFnError::new("Amount cannot be negative."));
-    }
-
-    if let Err(e) = crate::control::enqueue_critical(crate::control::CriticalCommands::Deposit {
-        name: user,
-        amount: amount,
-    })
-    .await
-    {
-        return Err(ServerFnError::new(format!(
-            "[SERVER] Failed to diffuse deposit : {e}"
-        )));
-    }
-
-    Ok(())
-}
-
-#[server]
-async fn withdraw_for_user_server(user: String, amount: f64) -> Result<(), ServerFnError> {
-    if amount < 0.0 {
-        return Err(ServerFnError::new("Amount cannot be negative."));
-    }
-
-    if let Err(e) = crate::control::enqueue_critical(crate::control::CriticalCommands::Withdraw {
-        name: user,
-        amount: amount,
-    })
-    .await
-    {
-        return Err(ServerFnError::new(format!(
-            "[SERVER] Failed to withdraw : {e}"
-        )));
-    }
-
-    Ok(())
-}
-
-#[server]
-async fn pay_for_user_server(user: String, amount: f64) -> Result<(), ServerFnError> {
-    if amount < 0.0 {
-        return Err(ServerFnError::new("Amount cannot be negative."));
-    }
-
-    if let Err(e) = crate::control::enqueue_critical(crate::control::CriticalCommands::Pay {
-        name: user,
-        amount: amount,
-    })
-    .await
-    {
-        return Err(ServerFnError::new(format!("[SERVER] Failed to pay : {e}")));
-    }
-
-    Ok(())
-}
-
-#[server]
-async fn transfer_from_user_to_user_server(
-    from_user: String,
-    to_user: String,
-    amount: f64,
-    _optional_message: String,
-) -> Result<(), ServerFnError> {
-    if amount < 0.0 {
-        return Err(ServerFnError::new("Amount cannot be negative."));
-    }
-
-    if let Err(e) = crate::control::enqueue_critical(crate::control::CriticalCommands::Transfer {
-        from: from_user,
-        to: to_user,
-        amount: amount,
-    })
-    .await
-    {
-        return Err(ServerFnError::new(format!(
-            "[SERVER] Failed to make the transfer: {e}"
-        )));
-    }
-
-    Ok(())
-}
+/// Transaction confirmation component
+///
+/// Follows a transaction submitted from this site until every reachable site
+/// acknowledged it, and shows how many sites confirmed it. `on_final` is
+/// called once the status of the transaction will not change anymore.
+#[component]
+pub fn TransactionConfirmation(
+    ticket: ReadOnlySignal<u64>,
+    on_final: Option<EventHandler>,
+) -> Element {
+    let mut status = use_signal(|| ConfirmationStatus::Queued);
 
-#[server]
-async fn get_transactions_for_user_server(
-    name: String,
-) -> Result<Vec<crate::db::Transaction>, ServerFnError> {
-    if let Ok(data) = crate::db::get_transactions_for_user(&name) {
-        Ok(data)
-    } else {
-        Err(ServerFnError::new("User not found."))
-    }
-}
+    // Long-poll the server until the status of the ticket is final
+    use_resource(move || async move {
+        let ticket = ticket();
+        status.set(ConfirmationStatus::Queued);
+        while let Ok(current) = wait_for_confirmation_server(ticket).await {
+            let done = current.is_final();
+            status.set(current);
+            if done {
+                if let Some(on_final) = on_final {
+                    on_final.call(());
+                }
+                break;
+            }
+        }
+    });
 
-#[server]
-async fn refund_transaction_server(
-    name: String,
-    lamport_time: i64,
-    transac_node: String,
-) -> Result<(), ServerFnError> {
-    if let Err(e) = crate::control::enqueue_critical(crate::control::CriticalCommands::Refund {
-        name: name,
-        lamport: lamport_time,
-        node: transac_node,
-    })
-    .await
-    {
-        return Err(ServerFnError::new(format!(
-            "[SERVER] Failed to refund : {e}"
-        )));
+    rsx! {
+        match &*status.read() {
+            ConfirmationStatus::Queued => rsx! {
+                p { class: "no-data-message", "⏳ Waiting for the critical section..." }
+            },
+            ConfirmationStatus::Offline => rsx! {
+                p { class: "no-data-message",
+                    "📴 Saved offline, it will be sent once the network is back."
+                }
+            },
+            ConfirmationStatus::Diffusing => rsx! {
+                p { class: "no-data-message", "📡 Applied locally, waiting for the other sites..." }
+            },
+            ConfirmationStatus::Confirmed { sites } => rsx! {
+                p { class: "no-data-message", "✅ Confirmed by {sites} site(s)" }
+            },
+            ConfirmationStatus::Failed(reason) => rsx! {
+                p { class: "error-message", "❌ Transaction failed: {reason}" }
+            },
+            ConfirmationStatus::Unknown => rsx! {
+                p { class: "error-message", "Transaction status unknown." }
+            },
+        }
     }
-
-    Ok(())
 }
@@ -0,0 +1,751 @@
+//! Server functions of the web interface
+//!
+//! The components call these functions, which run on the site serving the web
+//! interface. Those with a fixed endpoint are also called by `--bench` and by
+//! the holders of API tokens. The operations are checked and submitted by
+//! [`crate::api`], shared with the CLI.
+
+use crate::confirmation::ConfirmationStatus;
+use crate::db::TransactionComment;
+use dioxus::prelude::*;
+
+/// ID of the browser tab, once read from its sessionStorage
+static TAB_ID: GlobalSignal<Option<String>> = Signal::global(|| None);
+
+/// Reads the ID of the browser tab from its sessionStorage, generating it
+/// the first time, so that it survives the reloads of the tab
+///
+/// Returns None outside of a browser. See [`crate::sessions`].
+pub async fn load_tab_id() -> Option<String> {
+    if let Some(id) = TAB_ID.cloned() {
+        return Some(id);
+    }
+    let id: String = document::eval(
+        r#"let id = sessionStorage.getItem("peillute_tab");
+        if (!id) {
+            id = Date.now().toString(36) + Math.random().toString(36).slice(2);
+            sessionStorage.setItem("peillute_tab", id);
+        }
+        return id;"#,
+    )
+    .join()
+    .await
+    .ok()?;
+    *TAB_ID.write() = Some(id.clone());
+    Some(id)
+}
+
+/// Returns the ID of the browser tab, sent with the server functions acting
+/// as a user
+pub fn tab_id() -> Option<String> {
+    TAB_ID.cloned()
+}
+
+#[cfg(feature = "server")]
+/// Checks that the current request may operate on the account of a user:
+/// its API token if any, the tab of its browser session otherwise
+///
+/// To be called by the server functions taking a user, with the tab ID they
+/// received. The web interface opens the session with [`enter_user_server`],
+/// see [`crate::sessions`].
+pub async fn authorize_user(
+    user: &str,
+    tab: Option<&str>,
+) -> Result<(), dioxus::prelude::ServerFnError> {
+    use dioxus::prelude::ServerFnError;
+
+    let scope: Option<axum::Extension<crate::api_tokens::TokenScope>> = dioxus::prelude::extract()
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))?;
+    match scope {
+        Some(axum::Extension(scope)) => crate::api_tokens::authorize_user(&scope, user)
+            .map_err(|e| ServerFnError::new(e.to_string())),
+        // the web interface
+        None => {
+            let headers: axum::http::HeaderMap = dioxus::prelude::extract()
+                .await
+                .map_err(|e| ServerFnError::new(e.to_string()))?;
+            crate::sessions::check(crate::sessions::session_id(&headers).as_deref(), tab, user)
+                .map_err(ServerFnError::new)
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Checks that the current request may administer the site: its API token if
+/// any, the admin login of its browser session otherwise
+///
+/// To be called by the server functions shutting the site down or changing
+/// its configuration. The web interface logs in with [`admin_login_server`].
+pub async fn authorize_admin() -> Result<(), dioxus::prelude::ServerFnError> {
+    use dioxus::prelude::ServerFnError;
+
+    let scope: Option<axum::Extension<crate::api_tokens::TokenScope>> = dioxus::prelude::extract()
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))?;
+    match scope {
+        Some(axum::Extension(crate::api_tokens::TokenScope::Admin)) => Ok(()),
+        Some(axum::Extension(scope)) => Err(ServerFnError::new(format!(
+            "A {scope} token cannot administer the site"
+        ))),
+        // the web interface
+        None => {
+            let headers: axum::http::HeaderMap = dioxus::prelude::extract()
+                .await
+                .map_err(|e| ServerFnError::new(e.to_string()))?;
+            crate::sessions::check_admin(crate::sessions::session_id(&headers).as_deref())
+                .map_err(ServerFnError::new)
+        }
+    }
+}
+
+/// Server function to log the browser session in as an administrator, with
+/// an admin API token or the token of an admin operator
+#[server]
+pub async fn admin_login_server(token: String) -> Result<(), ServerFnError> {
+    let is_admin = crate::api_tokens::authenticate(&token)?
+        == Some(crate::api_tokens::TokenScope::Admin)
+        || crate::operators::role_of(&token) == Some(crate::operators::Role::Admin);
+    if !is_admin {
+        log::warn!("Refused an admin login of the web interface");
+        return Err(ServerFnError::new("Not an admin token."));
+    }
+    let headers: axum::http::HeaderMap = extract()
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))?;
+    let id = crate::sessions::enter_admin(crate::sessions::session_id(&headers).as_deref())?;
+    let cookie = axum::http::HeaderValue::from_str(&crate::sessions::cookie(&id))?;
+    server_context()
+        .response_parts_mut()
+        .headers
+        .insert(axum::http::header::SET_COOKIE, cookie);
+    Ok(())
+}
+
+/// Server function to make a user the acting user of a tab of the browser
+/// session, called when the pages of the user are opened
+///
+/// This only records which user the tab is viewing: the users have no
+/// credentials, see [`crate::sessions`]. Its endpoint is fixed, for `--bench`.
+#[server(endpoint = "enter_user")]
+pub async fn enter_user_server(name: String, tab: Option<String>) -> Result<(), ServerFnError> {
+    if !crate::db::user_exists(&name)? {
+        return Err(ServerFnError::new(format!("User '{name}' does not exist.")));
+    }
+    let headers: axum::http::HeaderMap = extract()
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))?;
+    let id = crate::sessions::enter(
+        crate::sessions::session_id(&headers).as_deref(),
+        tab.as_deref(),
+        &name,
+    )?;
+    let cookie = axum::http::HeaderValue::from_str(&crate::sessions::cookie(&id))?;
+    server_context()
+        .response_parts_mut()
+        .headers
+        .insert(axum::http::header::SET_COOKIE, cookie);
+    Ok(())
+}
+
+/// Server function to retrieve the ledgers of the site
+#[server]
+pub async fn get_ledgers() -> Result<Vec<String>, ServerFnError> {
+    let ledgers = crate::ledger::get_ledgers()?;
+    Ok(ledgers)
+}
+
+/// Server function to retrieve the list of users of a ledger
+#[server]
+pub async fn get_users(ledger: String) -> Result<Vec<String>, ServerFnError> {
+    let users = crate::ledger::get_users(&ledger, true)?;
+    Ok(users)
+}
+
+/// Server function to add a new user to a ledger
+///
+/// Creates a user in the local database and broadcasts the creation
+/// to all nodes in the network. An unknown ledger is created with its first
+/// user.
+#[server]
+pub async fn add_user(name: String, ledger: String) -> Result<(), ServerFnError> {
+    crate::api::create_user(name, ledger).await.map_err(|e| {
+        ServerFnError::new(format!("Failed to diffuse the create user message: {e}"))
+    })?;
+    Ok(())
+}
+
+/// Server function to retrieve the list of archived users of a ledger
+#[server]
+pub async fn get_archived_users(ledger: String) -> Result<Vec<String>, ServerFnError> {
+    let users = crate::ledger::get_users(&ledger, false)?;
+    Ok(users)
+}
+
+/// Server function to archive a user
+///
+/// The user keeps its transactions, the archiving is broadcast to all nodes
+/// in the network.
+#[server]
+pub async fn archive_user(name: String) -> Result<(), ServerFnError> {
+    crate::api::archive_user(name).await.map_err(|e| {
+        ServerFnError::new(format!("Failed to diffuse the archive user message: {e}"))
+    })?;
+    Ok(())
+}
+
+/// Server function to restore an archived user
+///
+/// The restoration is broadcast to all nodes in the network.
+#[server]
+pub async fn restore_user(name: String) -> Result<(), ServerFnError> {
+    crate::api::restore_user(name).await.map_err(|e| {
+        ServerFnError::new(format!("Failed to diffuse the restore user message: {e}"))
+    })?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+const RANDOM_MESSAGE: &[&str] = &[
+    "Prend tes 200 balles et va te payer des cours de theatre",
+    "C'est pour toi bb",
+    "Love sur toi",
+    "Phrase non aléatoire",
+    "Votre argent messire",
+    "Acompte sur livraison cocaine",
+    "Votre argent seigneur",
+    "Pour tout ce que tu fais pour moi",
+    "Remboursement horny.com",
+    "Puta, où tu étais quand j'mettais des sept euros d'essence",
+    "Parce que l'argent n'est pas un problème pour moi",
+    "Tiens le rat",
+    "Pour le rein",
+    "Abonnement OnlyFans",
+    "Pour notre dernière nuit, pourboire non compris",
+    "ça fait beaucoup la non ?",
+    "Chantage SexTape",
+    "Argent sale",
+    "Adhésion front national",
+    "Ce que tu sais...",
+    "Remboursement dot de ta soeur",
+    "Rien à ajouter",
+    "Téléphone rose",
+    "Raison : \"GnaGnaGna moi je paye pas pour vous\"",
+    "Fond de tiroir",
+    "Epilation des zones intimes",
+    "Pour m'avoir gratouillé le dos",
+    "La reine Babeth vous offre cet argent",
+    "Nan t'inquiete",
+];
+
+#[cfg(feature = "server")]
+fn get_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+#[cfg(feature = "server")]
+fn lcg(seed: u64) -> u64 {
+    const A: u64 = 6364136223846793005;
+    const C: u64 = 1;
+    seed.wrapping_mul(A).wrapping_add(C)
+}
+
+#[server]
+pub async fn get_random_message_server() -> Result<String, ServerFnError> {
+    let seed = get_seed();
+    let random_number = lcg(seed);
+    let message = RANDOM_MESSAGE[random_number as usize % RANDOM_MESSAGE.len()];
+    Ok(message.to_string())
+}
+
+/// Returns the active users of the ledger of a user, the only ones it can send money to
+#[server]
+pub async fn get_users_server(name: String) -> Result<Vec<String>, ServerFnError> {
+    let ledger = crate::ledger::ledger_of(&name)?;
+    let users = crate::ledger::get_users(&ledger, true)?;
+    Ok(users)
+}
+
+/// Server function to get the bounds of the amounts of the operations
+#[server]
+pub async fn get_amount_limits() -> Result<crate::amount::AmountLimits, ServerFnError> {
+    Ok(crate::amount::limits())
+}
+
+#[cfg(feature = "server")]
+/// Evaluates an amount typed by a user, which may be an expression, against
+/// the limits of the site
+fn evaluate_amount(amount: &str) -> Result<f64, ServerFnError> {
+    crate::amount::evaluate(amount, &crate::amount::limits()).map_err(ServerFnError::new)
+}
+
+// The money operations, transactions and confirmations have fixed endpoints,
+// called by `--bench` and by the holders of API tokens
+#[server(endpoint = "deposit")]
+pub async fn deposit_for_user_server(
+    user: String,
+    amount: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&user, tab.as_deref()).await?;
+    let amount = evaluate_amount(&amount)?;
+    crate::api::deposit(user, amount)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to diffuse deposit : {e}")))
+}
+
+#[server(endpoint = "withdraw")]
+pub async fn withdraw_for_user_server(
+    user: String,
+    amount: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&user, tab.as_deref()).await?;
+    let amount = evaluate_amount(&amount)?;
+    crate::api::withdraw(user, amount)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to withdraw : {e}")))
+}
+
+#[server(endpoint = "pay")]
+pub async fn pay_for_user_server(
+    user: String,
+    amount: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&user, tab.as_deref()).await?;
+    let amount = evaluate_amount(&amount)?;
+    crate::api::pay(user, amount, 0.0, Vec::new())
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to pay : {e}")))
+}
+
+#[server(endpoint = "split_pay")]
+pub async fn split_pay_for_user_server(
+    user: String,
+    amount: String,
+    tip_percent: f64,
+    split_with: Vec<String>,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&user, tab.as_deref()).await?;
+    let amount = evaluate_amount(&amount)?;
+    crate::api::pay(user, amount, tip_percent, split_with)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to pay : {e}")))
+}
+
+#[server(endpoint = "transfer")]
+pub async fn transfer_from_user_to_user_server(
+    from_user: String,
+    to_user: String,
+    amount: String,
+    _optional_message: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&from_user, tab.as_deref()).await?;
+    let amount = evaluate_amount(&amount)?;
+    crate::api::transfer(from_user, to_user, amount)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to make the transfer: {e}")))
+}
+
+/// Server function returning the balances resulting from a transfer, or from
+/// a withdrawal if `to_user` is empty, without submitting it
+#[server]
+pub async fn preview_transaction_server(
+    from_user: String,
+    to_user: String,
+    amount: String,
+    tab: Option<String>,
+) -> Result<crate::preview::TransactionPreview, ServerFnError> {
+    authorize_user(&from_user, tab.as_deref()).await?;
+    let amount = evaluate_amount(&amount)?;
+    let to_user = Some(to_user.as_str()).filter(|to_user| !to_user.is_empty());
+    crate::preview::preview(&from_user, to_user, amount)
+        .map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+#[server]
+pub async fn split_bill_server(
+    from_user: String,
+    transfers: Vec<(String, String)>,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&from_user, tab.as_deref()).await?;
+    let transfers = transfers
+        .into_iter()
+        .map(|(to_user, amount)| Ok((to_user, evaluate_amount(&amount)?)))
+        .collect::<Result<Vec<_>, ServerFnError>>()?;
+    crate::api::split_bill(from_user, transfers)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to split the bill: {e}")))
+}
+
+#[server(endpoint = "transactions")]
+pub async fn get_transactions_for_user_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Vec<crate::db::Transaction>, ServerFnError> {
+    authorize_user(&name, tab.as_deref()).await?;
+    if let Ok(data) = crate::db::get_transactions_for_user(&name) {
+        Ok(data)
+    } else {
+        Err(ServerFnError::new("User not found."))
+    }
+}
+
+/// Server function to retrieve the causal graph of the transactions in the
+/// DOT format, limited to the transactions of a user if given
+#[server(endpoint = "transaction_graph")]
+pub async fn get_transaction_graph_server(user: Option<String>) -> Result<String, ServerFnError> {
+    if let Some(user) = &user {
+        authorize_user(user, None).await?;
+    }
+    crate::graph::export(user.as_deref()).map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+#[server]
+pub async fn get_transactions_sorted_server(
+    name: String,
+    order: crate::db::HistoryOrder,
+    tab: Option<String>,
+) -> Result<Vec<crate::db::Transaction>, ServerFnError> {
+    authorize_user(&name, tab.as_deref()).await?;
+    crate::db::get_transactions_sorted(&name, order).map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+#[server]
+pub async fn get_unsettled_transactions_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Vec<(i64, String, crate::db::TransactionStatus)>, ServerFnError> {
+    authorize_user(&name, tab.as_deref()).await?;
+    crate::db::get_unsettled_transactions(&name).map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+/// Server function to get the transactions of a user with the reason each
+/// one cannot be refunded, if any
+#[server]
+pub async fn get_refundable_transactions_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Vec<crate::db::RefundableTransaction>, ServerFnError> {
+    authorize_user(&name, tab.as_deref()).await?;
+    crate::db::get_refundable_transactions(&name).map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+#[server]
+pub async fn refund_transaction_server(
+    name: String,
+    lamport_time: i64,
+    transac_node: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&name, tab.as_deref()).await?;
+    crate::api::refund(name, lamport_time, transac_node)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to refund : {e}")))
+}
+
+/// Server function to wait for the confirmation of a transaction
+///
+/// Returns as soon as the status of the ticket is final, or its current
+/// status after a while so that the client polls again.
+#[server(endpoint = "wait_for_confirmation")]
+pub async fn wait_for_confirmation_server(
+    ticket: u64,
+) -> Result<ConfirmationStatus, ServerFnError> {
+    const CONFIRMATION_POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(25);
+    Ok(crate::confirmation::wait_for_confirmation(ticket, CONFIRMATION_POLL_TIMEOUT).await)
+}
+
+/// Server function to retrieve the comments and reactions of a transaction
+#[server]
+pub async fn get_comments_server(
+    lamport_time: i64,
+    source_node: String,
+) -> Result<Vec<TransactionComment>, ServerFnError> {
+    Ok(crate::db::get_comments(lamport_time, &source_node)?)
+}
+
+/// Server function to comment or react to a transaction
+///
+/// The comment is broadcast to all nodes in the network.
+#[server]
+pub async fn comment_transaction_server(
+    lamport_time: i64,
+    source_node: String,
+    author: String,
+    text: String,
+    reaction: bool,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    authorize_user(&author, tab.as_deref()).await?;
+    crate::api::comment(lamport_time, source_node, author, text, reaction)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to diffuse the comment: {e}")))
+}
+
+/// Server function to retrieve the local network address
+#[server]
+pub async fn get_local_addr() -> Result<String, ServerFnError> {
+    use crate::state::SITE_INFO;
+    let site = SITE_INFO.read().await;
+    Ok(site.get_site_addr_as_string())
+}
+
+/// Server function to retrieve the current site ID
+#[server]
+pub async fn get_site_id() -> Result<String, ServerFnError> {
+    use crate::state::SITE_INFO;
+    let site = SITE_INFO.read().await;
+    Ok(site.get_site_id())
+}
+
+/// Server function to retrieve the list of connected peers
+#[server]
+pub async fn get_peers() -> Result<Vec<String>, ServerFnError> {
+    use crate::state::SITE_INFO;
+    let site = SITE_INFO.read().await;
+    Ok(site.get_cli_peers_addrs_as_string())
+}
+
+/// Server function to retrieve the current Lamport clock value
+#[server]
+pub async fn get_lamport() -> Result<i64, ServerFnError> {
+    Ok(*crate::state::get_clock().await.get_lamport())
+}
+
+/// Server function to retrieve the current vector clock state
+#[server]
+pub async fn get_vector_clock() -> Result<String, ServerFnError> {
+    let vector_clock = crate::state::get_clock().await.get_vector_clock_values();
+    let vector_clock_string = vector_clock
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    Ok(vector_clock_string)
+}
+
+/// Server function to retrieve the database path
+#[server]
+pub async fn get_db_path() -> Result<String, ServerFnError> {
+    Ok(crate::db::database_name())
+}
+
+/// Server function to retrieve the number of neighbours in the network
+#[server]
+pub async fn get_nb_connected_neighbours() -> Result<i64, ServerFnError> {
+    Ok(crate::state::get_nb_connected_neighbours().await)
+}
+
+/// Server function to retrieve the number of cli peers
+#[server]
+pub async fn get_nb_cli_peers() -> Result<i64, ServerFnError> {
+    use crate::state::SITE_INFO;
+    let site = SITE_INFO.read().await;
+    Ok(site.get_cli_peers_addrs().len() as i64)
+}
+
+/// Server function to retrieve the list of connected neighbours
+#[server]
+pub async fn get_connected_neighbours() -> Result<Vec<String>, ServerFnError> {
+    use crate::state::PEERS;
+    let peers = PEERS.read().await;
+    Ok(peers.get_connected_nei_addr_string())
+}
+
+/// Server function to retrieve the state of the connection to each known peer
+#[server]
+pub async fn get_connection_states()
+-> Result<Vec<(String, crate::network::ConnectionState)>, ServerFnError> {
+    use crate::network::NETWORK_MANAGER;
+    let manager = NETWORK_MANAGER.lock().await;
+    Ok(manager
+        .connection_states()
+        .into_iter()
+        .map(|(addr, state)| (addr.to_string(), state))
+        .collect())
+}
+
+/// Server function to retrieve the traffic exchanged with each peer
+#[server]
+pub async fn get_peer_traffic() -> Result<Vec<crate::network::PeerTraffic>, ServerFnError> {
+    use crate::network::NETWORK_MANAGER;
+    Ok(NETWORK_MANAGER.lock().await.traffic())
+}
+
+/// Server function to retrieve the alias of this site
+#[server]
+pub async fn get_site_alias() -> Result<Option<String>, ServerFnError> {
+    use crate::state::SITE_INFO;
+    Ok(SITE_INFO.read().await.get_site_alias())
+}
+
+/// Server function to retrieve the known aliases of the sites, by site ID
+#[server]
+pub async fn get_site_aliases_server()
+-> Result<std::collections::HashMap<String, String>, ServerFnError> {
+    Ok(crate::aliases::get_aliases()?)
+}
+
+/// Server function to retrieve the users created concurrently by several sites
+#[server]
+pub async fn get_user_conflicts() -> Result<Vec<crate::db::UserConflict>, ServerFnError> {
+    Ok(crate::db::get_user_conflicts()?)
+}
+
+/// Server function to retrieve the operations of the network waiting in quarantine
+#[server]
+pub async fn get_quarantined() -> Result<Vec<crate::quarantine::QuarantinedOperation>, ServerFnError>
+{
+    Ok(crate::quarantine::get_quarantined()?)
+}
+
+/// Server function to apply the quarantined operations again
+///
+/// Returns the operations still in quarantine.
+#[server]
+pub async fn retry_quarantined()
+-> Result<Vec<crate::quarantine::QuarantinedOperation>, ServerFnError> {
+    crate::quarantine::retry().await?;
+    Ok(crate::quarantine::get_quarantined()?)
+}
+
+/// Server function to drop a quarantined operation, which will never be applied
+///
+/// Returns the operations still in quarantine.
+#[server]
+pub async fn discard_quarantined(
+    lamport_time: i64,
+    source_node: String,
+) -> Result<Vec<crate::quarantine::QuarantinedOperation>, ServerFnError> {
+    crate::quarantine::discard(lamport_time, &source_node)?;
+    Ok(crate::quarantine::get_quarantined()?)
+}
+
+/// Server function to retrieve the clock drift and causality metrics
+#[server]
+pub async fn get_clock_metrics() -> Result<crate::causality::ClockMetrics, ServerFnError> {
+    use crate::causality::CAUSALITY_MONITOR;
+    let monitor = CAUSALITY_MONITOR.lock().unwrap();
+    Ok(monitor.metrics())
+}
+
+/// Server function to retrieve the statistics about the concurrency between the sites
+#[server]
+pub async fn get_concurrency_metrics()
+-> Result<crate::concurrency::ConcurrencyMetrics, ServerFnError> {
+    Ok(crate::concurrency::metrics())
+}
+
+/// Server function to retrieve the number of critical commands waiting for the
+/// global mutex, and the maximum size of the queue
+#[server]
+pub async fn get_pending_commands() -> Result<(usize, usize), ServerFnError> {
+    let cs = crate::state::CRITICAL_SECTION.lock().await;
+    Ok((cs.pending_commands.len(), cs.max_pending_commands))
+}
+
+/// Server function to retrieve the size of the database and the free disk space
+#[server]
+pub async fn get_storage_usage() -> Result<crate::storage::StorageUsage, ServerFnError> {
+    crate::storage::usage().map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+/// Server function to retrieve the list of peer addresses
+#[server]
+pub async fn get_peer_addrs() -> Result<Vec<String>, ServerFnError> {
+    use crate::state::SITE_INFO;
+    let site = SITE_INFO.read().await;
+    Ok(site.get_cli_peers_addrs_as_string())
+}
+
+/// Ask for a snapshot
+#[server]
+pub async fn ask_for_snapshot() -> Result<(), ServerFnError> {
+    crate::api::snapshot(None)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed make the local snapshot: {e}")))?;
+    Ok(())
+}
+
+/// Server function to tell the neighbours that the site leaves, then stop it
+#[server(endpoint = "shutdown_node")]
+pub async fn shutdown_node_server() -> Result<String, ServerFnError> {
+    authorize_admin().await?;
+    let (site_id, _) = crate::state::local_site().await;
+    crate::node::shutdown().await.map_err(ServerFnError::new)?;
+    Ok(format!("Site {site_id} left the network and is stopping."))
+}
+
+/// Server function to get the message of the maintenance in progress, if any
+#[server]
+pub async fn get_maintenance_server() -> Result<Option<String>, ServerFnError> {
+    Ok(crate::maintenance::message())
+}
+
+/// Server function to put the site in maintenance with a message, or to take
+/// it out of maintenance if there is none
+#[server(endpoint = "set_maintenance")]
+pub async fn set_maintenance_server(message: Option<String>) -> Result<String, ServerFnError> {
+    authorize_admin().await?;
+    match message {
+        Some(message) => {
+            crate::maintenance::enable(Some(message));
+            Ok("The site is in maintenance and refuses the new transactions.".to_string())
+        }
+        None => {
+            crate::maintenance::disable();
+            Ok("The site left the maintenance.".to_string())
+        }
+    }
+}
+
+/// Server function to leave the network and join it again, listening for the
+/// peers on another port if given
+#[server(endpoint = "restart_networking")]
+pub async fn restart_networking_server(port: Option<u16>) -> Result<String, ServerFnError> {
+    authorize_admin().await?;
+    let addr = crate::node::restart_networking(port)
+        .await
+        .map_err(ServerFnError::new)?;
+    Ok(format!(
+        "Networking restarted, listening for the peers on {addr}."
+    ))
+}
+
+/// Get the latest snapshot content if any
+#[server]
+pub async fn get_snapshot_content() -> Result<Option<String>, ServerFnError> {
+    use crate::snapshot::LOCAL_SNAPSHOT_MANAGER;
+    use tokio::fs::File;
+    use tokio::io::AsyncReadExt;
+
+    let maybe_filename = {
+        let state = LOCAL_SNAPSHOT_MANAGER.lock().await;
+        state.path.clone()
+    };
+
+    if let Some(filename) = maybe_filename {
+        let mut file = File::open(crate::snapshot::snapshot_dir().join(&filename)).await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        Ok(Some(contents))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Server function to retrieve the time elapsed since the last snapshot, in
+/// seconds, if any snapshot was taken
+#[server]
+pub async fn get_last_snapshot_age() -> Result<Option<u64>, ServerFnError> {
+    Ok(crate::snapshot::last_snapshot_age()?.map(|age| age.as_secs()))
+}
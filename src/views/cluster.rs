@@ -0,0 +1,142 @@
+//! Cluster statistics component for the Peillute application
+//!
+//! This module provides a component collecting the counters of every site of
+//! the network and displaying them in a single table.
+
+use crate::cluster_stats::ClusterStats;
+use dioxus::prelude::*;
+
+/// Cluster statistics component
+///
+/// Displays the counters of the last collection, one row per site, with:
+/// - The number of users and transactions of the site
+/// - Its last Lamport time
+/// - The size of its database
+///
+/// The sites holding fewer transactions than the others are flagged, and a
+/// last row sums up the whole network. A button collects the counters again.
+#[component]
+pub fn Cluster() -> Element {
+    let mut stats_resource = use_resource(get_cluster_stats);
+    let mut in_progress = use_signal(|| false);
+    let mut error_signal = use_signal(|| None::<String>);
+
+    rsx! {
+        div { class: "info-panel", id: "cluster-page",
+            h2 { "Cluster" }
+
+            div { class: "info-item",
+                button {
+                    r#type: "button",
+                    disabled: in_progress(),
+                    onclick: move |_| async move {
+                        in_progress.set(true);
+                        match refresh_cluster_stats().await {
+                            Ok(Some(_)) => error_signal.set(None),
+                            Ok(None) => {
+                                error_signal.set(Some(
+                                    "The sites are still answering, refresh the page later."
+                                        .to_string(),
+                                ))
+                            }
+                            Err(e) => {
+                                error_signal.set(Some(format!("Error collecting the counters: {e}")))
+                            }
+                        }
+                        in_progress.set(false);
+                        stats_resource.restart();
+                    },
+                    if in_progress() {
+                        "Collecting..."
+                    } else {
+                        "Collect the counters"
+                    }
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+
+            match &*stats_resource.read() {
+                None => rsx! {
+                    p { class: "loading-message", "Loading counters..." }
+                },
+                Some(Ok(None)) => rsx! {
+                    p { class: "no-data-message", "No counters collected yet." }
+                },
+                Some(Ok(Some(stats))) => {
+                    let lagging = stats.lagging();
+                    let lagging_sites = lagging.join(", ");
+                    rsx! {
+                        p { class: "no-data-message",
+                            "Collected by {stats.initiator} at {stats.collected_at}"
+                        }
+                        table { class: "cluster-table",
+                            thead {
+                                tr {
+                                    th { "Site" }
+                                    th { "Users" }
+                                    th { "Transactions" }
+                                    th { "Last Lamport" }
+                                    th { "Database" }
+                                }
+                            }
+                            tbody {
+                                for site in stats.sites.iter() {
+                                    tr {
+                                        key: "{site.site_id}",
+                                        class: if lagging.contains(&site.site_id.as_str()) { "lagging" },
+                                        td {
+                                            if let Some(alias) = &site.alias {
+                                                "{alias} ({site.site_id})"
+                                            } else {
+                                                "{site.site_id}"
+                                            }
+                                        }
+                                        td { "{site.nb_users}" }
+                                        td { "{site.nb_transactions}" }
+                                        td { "{site.last_lamport}" }
+                                        td { {format!("{:.2} MiB", site.db_size as f64 / (1024.0 * 1024.0))} }
+                                    }
+                                }
+                            }
+                            tfoot {
+                                tr {
+                                    th { "{stats.sites.len()} sites" }
+                                    th {}
+                                    th { "{stats.max_transactions()}" }
+                                    th { "{stats.last_lamport()}" }
+                                    th { {format!("{:.2} MiB", stats.total_db_size() as f64 / (1024.0 * 1024.0))} }
+                                }
+                            }
+                        }
+                        if !lagging.is_empty() {
+                            p { class: "error-message", "⏳ Lagging behind: {lagging_sites}" }
+                        }
+                    }
+                }
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading counters: {e}" }
+                },
+            }
+        }
+    }
+}
+
+/// Server function to get the counters of the last collection, if any
+#[server]
+async fn get_cluster_stats() -> Result<Option<ClusterStats>, ServerFnError> {
+    Ok(crate::cluster_stats::last())
+}
+
+/// Server function to collect the counters of the sites and wait for them
+///
+/// Returns None if the wave did not complete in time.
+#[server]
+async fn refresh_cluster_stats() -> Result<Option<ClusterStats>, ServerFnError> {
+    const REFRESH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+    crate::cluster_stats::refresh(REFRESH_TIMEOUT)
+        .await
+        .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to collect the counters: {e}")))
+}
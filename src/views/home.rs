@@ -1,29 +1,77 @@
 //! Home page component for the Peillute application
 //!
 //! This component provides the main user interface for managing users in the system,
-//! including listing existing users, adding new users, and deleting users.
+//! including listing existing users, adding new users, and archiving or
+//! restoring users. The users are shown one ledger at a time.
 
+use super::api::*;
 use crate::Route;
+use crate::ledger::DEFAULT_LEDGER;
 use dioxus::prelude::*;
 
 /// Home page component
 ///
 /// Renders the main user management interface with the following features:
+/// - A selector of the ledger whose users are shown
 /// - List of existing users with links to their transaction history
-/// - Form for adding new users
-/// - Delete buttons for removing users
+/// - Form for adding new users, to the selected ledger or a new one
+/// - Archive buttons for hiding users
+/// - A toggle showing the archived users, which can be restored
+///
+/// A site that was never set up leads to the setup wizard instead.
 #[component]
 pub fn Home() -> Element {
     let mut user_input = use_signal(|| "".to_string());
-    let mut users = use_signal(|| Vec::new());
+    let mut ledger_input = use_signal(|| DEFAULT_LEDGER.to_string());
+    let mut ledger = use_signal(|| DEFAULT_LEDGER.to_string());
+    let mut ledgers = use_signal(|| vec![DEFAULT_LEDGER.to_string()]);
+    let mut users = use_signal(Vec::new);
+    let mut archived_users = use_signal(Vec::new);
+    let mut show_archived = use_signal(|| false);
+    let mut error_message = use_signal(|| None::<String>);
 
     use_future(move || async move {
-        if let Ok(data) = get_users().await {
+        // a new site is set up first
+        if let Ok(status) = super::setup::get_setup_status_server().await
+            && status.needed
+        {
+            navigator().push(Route::Setup {});
+            return;
+        }
+        if let Ok(data) = get_ledgers().await {
+            ledgers.set(data);
+        }
+        if let Ok(data) = get_users(ledger()).await {
             users.set(data);
         }
     });
 
     rsx! {
+        div { id: "ledger-select",
+            label { r#for: "ledger", "Ledger:" }
+            select {
+                id: "ledger",
+                value: ledger(),
+                onchange: move |event| async move {
+                    ledger.set(event.value());
+                    ledger_input.set(event.value());
+                    if let Ok(data) = get_users(ledger()).await {
+                        users.set(data);
+                    }
+                    if let Ok(data) = get_archived_users(ledger()).await {
+                        archived_users.set(data);
+                    }
+                },
+                for name in ledgers.iter() {
+                    option {
+                        key: "{name}",
+                        value: "{name}",
+                        selected: *name == ledger(),
+                        "{name}"
+                    }
+                }
+            }
+        }
         div { id: "users-list",
             for item in users.iter() {
                 div { class: "user-card",
@@ -41,13 +89,17 @@ pub fn Home() -> Element {
                             button {
                                 r#type: "button",
                                 class: "delete-btn",
+                                title: "Archive user",
                                 onclick: move |_| {
                                     let username = item_for_delete.clone();
                                     spawn(async move {
-                                        if let Ok(_) = delete_user(username).await {
-                                            if let Ok(data) = get_users().await {
+                                        if archive_user(username).await.is_ok() {
+                                            if let Ok(data) = get_users(ledger()).await {
                                                 users.set(data);
                                             }
+                                            if let Ok(data) = get_archived_users(ledger()).await {
+                                                archived_users.set(data);
+                                            }
                                         }
                                     });
                                 },
@@ -58,6 +110,65 @@ pub fn Home() -> Element {
                 }
             }
         }
+        div { id: "archived-users-toggle",
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: show_archived(),
+                    onchange: move |evt: Event<FormData>| async move {
+                        show_archived.set(evt.checked());
+                        if evt.checked()
+                            && let Ok(data) = get_archived_users(ledger()).await
+                        {
+                            archived_users.set(data);
+                        }
+                    },
+                }
+                " Show archived users"
+            }
+        }
+        if show_archived() {
+            div { id: "archived-users-list",
+                if archived_users.read().is_empty() {
+                    p { class: "no-data-message", "No archived user." }
+                }
+                for item in archived_users.iter() {
+                    div { class: "user-card archived",
+                        div { class: "user-content",
+                            Link {
+                                to: Route::History {
+                                    name: item.to_string(),
+                                },
+                                span { class: "user-name", "{item}" }
+                            }
+                        }
+                        {
+                            let item_for_restore = item.clone();
+                            rsx! {
+                                button {
+                                    r#type: "button",
+                                    class: "restore-btn",
+                                    onclick: move |_| {
+                                        let username = item_for_restore.clone();
+                                        spawn(async move {
+                                            if restore_user(username).await.is_ok() {
+                                                if let Ok(data) = get_users(ledger()).await {
+                                                    users.set(data);
+                                                }
+                                                if let Ok(data) = get_archived_users(ledger()).await {
+                                                    archived_users.set(data);
+                                                }
+                                            }
+                                        });
+                                    },
+                                    "Restore"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
         div { id: "add-user-form",
             form {
                 label { r#for: "fusername", "Enter a new user:" }
@@ -69,61 +180,39 @@ pub fn Home() -> Element {
                     value: user_input,
                     oninput: move |event| user_input.set(event.value()),
                 }
+                label { r#for: "form-ledger", "Ledger:" }
+                input {
+                    r#type: "text",
+                    id: "form-ledger",
+                    placeholder: DEFAULT_LEDGER,
+                    value: ledger_input,
+                    oninput: move |event| ledger_input.set(event.value()),
+                }
                 button {
                     id: "submit",
                     r#type: "submit",
                     onclick: move |_| async move {
-                        if let Ok(_) = add_user(user_input.to_string()).await {
-                            user_input.set("".to_string());
+                        match add_user(user_input.to_string(), ledger_input.to_string()).await {
+                            Ok(()) => {
+                                user_input.set("".to_string());
+                                error_message.set(None);
+                                ledger.set(ledger_input());
+                                if let Ok(data) = get_ledgers().await {
+                                    ledgers.set(data);
+                                }
+                            }
+                            Err(e) => error_message.set(Some(e.to_string())),
                         }
-                        if let Ok(data) = get_users().await {
+                        if let Ok(data) = get_users(ledger()).await {
                             users.set(data);
                         }
                     },
                     "Submit"
                 }
             }
+            if let Some(e) = error_message() {
+                p { class: "error-message", "{e}" }
+            }
         }
     }
 }
-
-/// Server function to retrieve the list of users
-#[server]
-async fn get_users() -> Result<Vec<String>, ServerFnError> {
-    use crate::db;
-    let users = db::get_users()?;
-    Ok(users)
-}
-
-/// Server function to add a new user
-///
-/// Creates a user in the local database and broadcasts the creation
-/// to all nodes in the network.
-#[server]
-async fn add_user(name: String) -> Result<(), ServerFnError> {
-    if name == "" {
-        return Err(ServerFnError::new("User name cannot be empty."));
-    }
-
-    if let Err(e) = crate::control::enqueue_critical(crate::control::CriticalCommands::CreateUser {
-        name: name,
-    })
-    .await
-    {
-        return Err(ServerFnError::new(format!(
-            "Failed to diffuse the create user message: {e}"
-        )));
-    }
-
-    Ok(())
-}
-
-/// Server function to delete a user
-///
-/// Removes a user from the local database.
-#[server]
-async fn delete_user(name: String) -> Result<(), ServerFnError> {
-    use crate::db;
-    db::delete_user(&name)?;
-    Ok(())
-}
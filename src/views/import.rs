@@ -0,0 +1,119 @@
+//! Bulk user import for the Peillute application
+//!
+//! This module provides the admin page used to create many users at once,
+//! with their opening balance, from a CSV file. See [`crate::import`] for the
+//! format of the file.
+
+use crate::import::ImportReport;
+use dioxus::prelude::*;
+
+/// Bulk user import component
+///
+/// Provides:
+/// - A file input to upload a CSV of users, with their balance and ledger
+/// - The users created and the rows rejected, with the reason of each
+/// - A link to download the rejected rows as a CSV error report
+#[component]
+pub fn Import() -> Element {
+    let mut content = use_signal(|| None::<String>);
+    let mut report = use_signal(|| None::<ImportReport>);
+    let mut importing = use_signal(|| false);
+    let mut error_signal = use_signal(|| None::<String>);
+
+    rsx! {
+        div { id: "import-page",
+            p { "One user per line: name,balance,ledger. The balance and the ledger are optional." }
+            form {
+                label { r#for: "import-file", "CSV file:" }
+                input {
+                    r#type: "file",
+                    id: "import-file",
+                    accept: ".csv,text/csv",
+                    onchange: move |event: Event<FormData>| async move {
+                        content.set(None);
+                        if let Some(engine) = event.files() {
+                            for file_name in engine.files() {
+                                match engine.read_file_to_string(&file_name).await {
+                                    Some(text) => content.set(Some(text)),
+                                    None => {
+                                        error_signal.set(Some(format!("{file_name} is not a text file.")))
+                                    }
+                                }
+                            }
+                        }
+                    },
+                }
+                button {
+                    r#type: "submit",
+                    disabled: content.read().is_none() || importing(),
+                    onclick: move |_| async move {
+                        let Some(text) = content() else {
+                            return;
+                        };
+                        importing.set(true);
+                        match import_users_server(text).await {
+                            Ok(result) => {
+                                error_signal.set(None);
+                                report.set(Some(result));
+                            }
+                            Err(e) => error_signal.set(Some(format!("{e}"))),
+                        }
+                        importing.set(false);
+                    },
+                    if importing() {
+                        "Importing..."
+                    } else {
+                        "Import"
+                    }
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+            if let Some(report) = &*report.read() {
+                p { class: "no-data-message",
+                    "{report.imported.len()} users imported, {report.rejected.len()} rows rejected."
+                }
+                if !report.rejected.is_empty() {
+                    table { class: "import-errors",
+                        thead {
+                            tr {
+                                th { "Line" }
+                                th { "Row" }
+                                th { "Reason" }
+                            }
+                        }
+                        tbody {
+                            for row in report.rejected.iter() {
+                                tr { key: "{row.line}",
+                                    td { "{row.line}" }
+                                    td { "{row.content}" }
+                                    td { "{row.reason}" }
+                                }
+                            }
+                        }
+                    }
+                    {
+                        let url = format!(
+                            "data:text/csv;base64,{}",
+                            crate::utils::base64_encode(report.error_csv().as_bytes()),
+                        );
+                        rsx! {
+                            a { href: "{url}", download: "import_errors.csv", "Download the error report" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Server function to import users with their opening balance from a CSV file
+///
+/// Each valid row is applied as a critical command, replicated to every site.
+#[server]
+async fn import_users_server(content: String) -> Result<ImportReport, ServerFnError> {
+    crate::import::import(&content)
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))
+}
@@ -3,130 +3,10 @@
 //! This module provides a component for displaying system-wide information,
 //! including network details, logical clock states, and peer connections.
 
+use super::api::*;
+use crate::Route;
 use dioxus::prelude::*;
 
-/// Server function to retrieve the local network address
-#[server]
-async fn get_local_addr() -> Result<String, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(state.get_site_addr_as_string())
-}
-
-/// Server function to retrieve the current site ID
-#[server]
-async fn get_site_id() -> Result<String, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(state.get_site_id().to_string())
-}
-
-/// Server function to retrieve the list of connected peers
-#[server]
-async fn get_peers() -> Result<Vec<String>, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(state.get_cli_peers_addrs_as_string())
-}
-
-/// Server function to retrieve the current Lamport clock value
-#[server]
-async fn get_lamport() -> Result<i64, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(*state.get_clock().get_lamport())
-}
-
-/// Server function to retrieve the current vector clock state
-#[server]
-async fn get_vector_clock() -> Result<String, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    let vector_clock = state.get_clock().get_vector_clock_values();
-    let vector_clock_string = vector_clock
-        .iter()
-        .map(|x| x.to_string())
-        .collect::<Vec<String>>()
-        .join(", ");
-    Ok(vector_clock_string)
-}
-
-/// Server function to retrieve the database path
-#[server]
-async fn get_db_path() -> Result<String, ServerFnError> {
-    let conn = crate::db::DB_CONN.lock().unwrap();
-    let path = conn.path().unwrap();
-    //keep only the name of the file (after the last "/")
-    Ok(path.to_string().split("/").last().unwrap().to_string())
-}
-
-/// Server function to retrieve the number of neighbours in the network
-#[server]
-async fn get_nb_connected_neighbours() -> Result<i64, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(state.get_nb_connected_neighbours())
-}
-
-/// Server function to retrieve the number of cli peers
-#[server]
-async fn get_nb_cli_peers() -> Result<i64, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(state.get_cli_peers_addrs().len() as i64)
-}
-
-/// Server function to retrieve the list of connected neighbours
-#[server]
-async fn get_connected_neighbours() -> Result<Vec<String>, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(state.get_connected_nei_addr_string())
-}
-
-/// Server function to retrieve the list of peer addresses
-#[server]
-async fn get_peer_addrs() -> Result<Vec<String>, ServerFnError> {
-    use crate::state::LOCAL_APP_STATE;
-    let state = LOCAL_APP_STATE.lock().await;
-    Ok(state.get_cli_peers_addrs_as_string())
-}
-
-/// Ask for a snapshot
-#[server]
-async fn ask_for_snapshot() -> Result<(), ServerFnError> {
-    if let Err(e) =
-        crate::control::enqueue_critical(crate::control::CriticalCommands::FileSnapshot).await
-    {
-        return Err(ServerFnError::new(format!(
-            "[SERVER] Failed make the local snapshot: {e}"
-        )));
-    }
-    Ok(())
-}
-
-/// Get the latest snapshot content if any
-#[server]
-async fn get_snapshot_content() -> Result<Option<String>, ServerFnError> {
-    use crate::snapshot::LOCAL_SNAPSHOT_MANAGER;
-    use tokio::fs::File;
-    use tokio::io::AsyncReadExt;
-
-    let maybe_filename = {
-        let state = LOCAL_SNAPSHOT_MANAGER.lock().await;
-        state.path.clone()
-    };
-
-    if let Some(filename) = maybe_filename {
-        let mut file = File::open(&filename).await?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).await?;
-        Ok(Some(contents))
-    } else {
-        Ok(None)
-    }
-}
-
 /// System information component
 ///
 /// Displays real-time information about the distributed system, including:
@@ -137,19 +17,40 @@ async fn get_snapshot_content() -> Result<Option<String>, ServerFnError> {
 /// - Vector clock state
 /// - Number of connected sites
 /// - List of connected peers
-/// - Snapshot button
+/// - Traffic exchanged with each peer
+/// - Clock drift and causality anomalies
+/// - Depth of the critical command queue
+/// - Database size and free disk space
+/// - Age of the last snapshot and snapshot button
+/// - Administration of the site: networking, maintenance and shutdown
 #[component]
 pub fn Info() -> Element {
     let mut local_addr = use_signal(|| "".to_string());
     let mut site_id = use_signal(|| "".to_string());
-    let mut peers_addr = use_signal(|| Vec::new());
-    let mut connected_neighbours = use_signal(|| Vec::new());
+    let mut site_alias = use_signal(|| None::<String>);
+    let mut site_aliases = use_signal(std::collections::HashMap::<String, String>::new);
+    let mut peers_addr = use_signal(Vec::new);
+    let mut connected_neighbours = use_signal(Vec::new);
+    let mut connection_states = use_signal(Vec::new);
+    let mut peer_traffic = use_signal(Vec::new);
     let mut lamport = use_signal(|| 0i64);
     let mut vector_clock = use_signal(|| "".to_string());
     let mut nb_neighbours = use_signal(|| 0i64);
     let mut nb_peers = use_signal(|| 0i64);
     let mut db_path = use_signal(|| "".to_string());
     let mut snapshot_content = use_signal(|| None::<String>);
+    let mut clock_metrics = use_signal(crate::causality::ClockMetrics::default);
+    let mut concurrency_metrics = use_signal(crate::concurrency::ConcurrencyMetrics::default);
+    let mut pending_commands = use_signal(|| (0usize, 0usize));
+    let mut storage_usage = use_signal(crate::storage::StorageUsage::default);
+    let mut last_snapshot_age = use_signal(|| None::<u64>);
+    let mut user_conflicts = use_signal(Vec::new);
+    let mut quarantined = use_signal(Vec::new);
+    let mut admin_token = use_signal(String::new);
+    let mut restart_port = use_signal(String::new);
+    let mut admin_status = use_signal(|| None::<Result<String, String>>);
+    let mut maintenance = use_signal(|| None::<String>);
+    let mut maintenance_message = use_signal(String::new);
 
     use_future(move || async move {
         // Fetch local address
@@ -167,6 +68,16 @@ pub fn Info() -> Element {
             site_id.set("Error fetching site ID".to_string());
         }
 
+        // Fetch site alias
+        if let Ok(data) = get_site_alias().await {
+            site_alias.set(data);
+        } // else: site_alias remains None or handle error
+
+        // Fetch the aliases of the known sites
+        if let Ok(data) = get_site_aliases_server().await {
+            site_aliases.set(data);
+        } // else: site_aliases remains empty or handle error
+
         // Fetch peers
         if let Ok(data) = get_peers().await {
             peers_addr.set(data);
@@ -177,6 +88,16 @@ pub fn Info() -> Element {
             connected_neighbours.set(data);
         } // else: connected_neighbours remains empty or you could set an error state if needed
 
+        // Fetch peer connection states
+        if let Ok(data) = get_connection_states().await {
+            connection_states.set(data);
+        } // else: connection_states remains empty or handle error
+
+        // Fetch traffic per peer
+        if let Ok(data) = get_peer_traffic().await {
+            peer_traffic.set(data);
+        } // else: peer_traffic remains empty or handle error
+
         // Fetch Lamport clock
         if let Ok(data) = get_lamport().await {
             lamport.set(data);
@@ -202,12 +123,61 @@ pub fn Info() -> Element {
             db_path.set(data);
         } // else: db_path remains "" or handle error
 
+        // Fetch clock drift metrics
+        if let Ok(data) = get_clock_metrics().await {
+            clock_metrics.set(data);
+        } // else: clock_metrics remains empty or handle error
+
+        // Fetch concurrency statistics
+        if let Ok(data) = get_concurrency_metrics().await {
+            concurrency_metrics.set(data);
+        } // else: concurrency_metrics remains empty or handle error
+
+        // Fetch critical command queue depth
+        if let Ok(data) = get_pending_commands().await {
+            pending_commands.set(data);
+        } // else: pending_commands remains (0, 0) or handle error
+
+        // Fetch maintenance mode
+        if let Ok(data) = get_maintenance_server().await {
+            maintenance.set(data);
+        } // else: maintenance remains None or handle error
+
+        // Fetch storage usage
+        if let Ok(data) = get_storage_usage().await {
+            storage_usage.set(data);
+        } // else: storage_usage remains empty or handle error
+
+        // Fetch the age of the last snapshot
+        if let Ok(data) = get_last_snapshot_age().await {
+            last_snapshot_age.set(data);
+        } // else: last_snapshot_age remains None or handle error
+
+        // Fetch the concurrent creations of users
+        if let Ok(data) = get_user_conflicts().await {
+            user_conflicts.set(data);
+        } // else: user_conflicts remains empty or handle error
+
+        // Fetch the operations waiting in quarantine
+        if let Ok(data) = get_quarantined().await {
+            quarantined.set(data);
+        } // else: quarantined remains empty or handle error
+
         // Fetch snapshot content
         if let Ok(data) = get_snapshot_content().await {
             snapshot_content.set(data);
         } // else: snapshot_content remains None or handle error
     });
 
+    let db_size_mib = storage_usage.read().db_size as f64 / (1024.0 * 1024.0);
+    let free_space_mib = storage_usage.read().free_space as f64 / (1024.0 * 1024.0);
+    let min_free_space_mib = storage_usage.read().min_free_space as f64 / (1024.0 * 1024.0);
+    let db_limit = match storage_usage.read().max_db_size {
+        0 => "no limit".to_string(),
+        max => format!("limit {:.2} MiB", max as f64 / (1024.0 * 1024.0)),
+    };
+    let storage_refusal = storage_usage.read().refusal();
+
     rsx! {
         div { class: "info-panel", // You can style this class with CSS
             h2 { "System Information" }
@@ -225,6 +195,22 @@ pub fn Info() -> Element {
                 strong { "🆔 Site ID: " }
                 span { "{site_id}" }
             }
+            if let Some(alias) = &*site_alias.read() {
+                div { class: "info-item",
+                    strong { "🏷️ Site Alias: " }
+                    span { "{alias}" }
+                }
+            }
+            if !site_aliases.read().is_empty() {
+                div { class: "info-item",
+                    strong { "📇 Known Sites: " }
+                    ul { class: "peer-list",
+                        for (id , alias) in site_aliases.read().iter() {
+                            li { key: "{id}", "{alias} ({id})" }
+                        }
+                    }
+                }
+            }
             div { class: "info-item",
                 strong { "⏰ Lamport Timestamp: " }
                 span { "{lamport}" }
@@ -233,6 +219,115 @@ pub fn Info() -> Element {
                 strong { "⏱️ Vector Clock : " }
                 span { "{vector_clock}" }
             }
+            div { class: "info-item",
+                strong { "📈 Clock Drift: " }
+                span {
+                    "{clock_metrics.read().messages_observed} diffusions checked, {clock_metrics.read().causality_violations} causality anomalies, Lamport drift {clock_metrics.read().last_lamport_drift} (max {clock_metrics.read().max_lamport_drift})"
+                }
+                if !clock_metrics.read().unseen_events_per_site.is_empty() {
+                    ul { class: "peer-list",
+                        for (site , nb) in clock_metrics.read().unseen_events_per_site.iter() {
+                            li { key: "{site}", "{site}: {nb} unseen event(s)" }
+                        }
+                    }
+                }
+                if !clock_metrics.read().recent_anomalies.is_empty() {
+                    ul { class: "peer-list",
+                        for (i , anomaly) in clock_metrics.read().recent_anomalies.iter().enumerate() {
+                            li { key: "{i}", "{anomaly}" }
+                        }
+                    }
+                }
+            }
+            div { class: "info-item",
+                strong { "🔀 Consistency: " }
+                {
+                    let metrics = concurrency_metrics.read();
+                    let average_wave = match metrics.average_wave_millis {
+                        Some(millis) => format!("{:.1} ms", millis),
+                        None => "no wave completed yet".to_string(),
+                    };
+                    rsx! {
+                        ul { class: "peer-list",
+                            li { "{metrics.concurrent_transactions} transaction(s) received with a concurrent vector clock" }
+                            li { "{metrics.duplicate_transactions} duplicate transaction(s) skipped" }
+                            li { "{metrics.overdraft_conflicts} overdraft conflict(s)" }
+                            li { "Average wave completion time: {average_wave} ({metrics.completed_waves} wave(s))" }
+                        }
+                    }
+                }
+            }
+            div { class: "info-item",
+                strong { "📥 Pending Critical Commands: " }
+                span { "{pending_commands.read().0} / {pending_commands.read().1}" }
+            }
+            div { class: "info-item",
+                strong { "🗄️ Storage: " }
+                span {
+                    "database {db_size_mib:.2} MiB ({db_limit}), {free_space_mib:.2} MiB free on disk (minimum {min_free_space_mib:.2} MiB)"
+                }
+                if let Some(reason) = storage_refusal {
+                    p { class: "error-message", "{reason}" }
+                }
+            }
+            if !user_conflicts.read().is_empty() {
+                div { class: "info-item",
+                    strong { "⚠️ Users Created Concurrently: " }
+                    ul { class: "peer-list",
+                        for conflict in user_conflicts.read().iter() {
+                            {
+                                let winner = crate::aliases::display_site(&conflict.winner_node, &site_aliases.read());
+                                let loser = crate::aliases::display_site(&conflict.loser_node, &site_aliases.read());
+                                rsx! {
+                                    li { key: "{conflict.user}-{conflict.loser_node}-{conflict.loser_lamport}",
+                                        "{conflict.resolved_at}: '{conflict.user}' was created by {winner} (time {conflict.winner_lamport}) and {loser} (time {conflict.loser_lamport}), merged into the account of {winner}"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if !quarantined.read().is_empty() {
+                div { class: "info-item",
+                    strong { "⏸️ Quarantined Operations: " }
+                    button {
+                        r#type: "button",
+                        onclick: move |_| async move {
+                            if let Ok(data) = retry_quarantined().await {
+                                quarantined.set(data);
+                            }
+                        },
+                        "Retry now"
+                    }
+                    ul { class: "peer-list",
+                        for operation in quarantined.read().iter() {
+                            {
+                                let lamport_time = operation.lamport_time;
+                                let source_node = operation.source_node.clone();
+                                let site = crate::aliases::display_site(&operation.source_node, &site_aliases.read());
+                                rsx! {
+                                    li { key: "{operation.source_node}-{operation.lamport_time}",
+                                        "{operation.quarantined_at}: {operation.description} from {site} (time {operation.lamport_time}), {operation.attempts} attempt(s): {operation.reason}"
+                                        button {
+                                            r#type: "button",
+                                            onclick: move |_| {
+                                                let source_node = source_node.clone();
+                                                async move {
+                                                    if let Ok(data) = discard_quarantined(lamport_time, source_node).await {
+                                                        quarantined.set(data);
+                                                    }
+                                                }
+                                            },
+                                            "Discard"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
             div { class: "info-item",
                 strong { "🌍 Number of connected neighbours: " }
                 span { "{nb_neighbours}" }
@@ -251,6 +346,56 @@ pub fn Info() -> Element {
                 }
             }
 
+            div { class: "info-item",
+                strong { "🔌 Peer Connections: " }
+                if connection_states.read().is_empty() {
+                    span { "No connection opened yet." }
+                } else {
+                    ul { class: "peer-list",
+                        for (adr , state) in connection_states.read().iter() {
+                            li { key: "{adr}", "{adr} - {state:?}" }
+                        }
+                    }
+                }
+            }
+
+            div { class: "info-item",
+                strong { "📶 Peer Traffic: " }
+                if peer_traffic.read().is_empty() {
+                    span { "No message exchanged yet." }
+                } else {
+                    table { class: "traffic-table",
+                        thead {
+                            tr {
+                                th { "Peer" }
+                                th { "Sent" }
+                                th { "Received" }
+                                th { "Messages out/in" }
+                                th { "Last activity" }
+                            }
+                        }
+                        tbody {
+                            for traffic in peer_traffic.read().iter() {
+                                tr { key: "{traffic.addr}",
+                                    td { "{traffic.addr}" }
+                                    td { "{traffic.bytes_sent} B" }
+                                    td { "{traffic.bytes_received} B" }
+                                    td { "{traffic.messages_sent} / {traffic.messages_received}" }
+                                    match traffic.idle_secs {
+                                        Some(idle) => rsx! {
+                                            td { "{idle} s ago" }
+                                        },
+                                        None => rsx! {
+                                            td { "never" }
+                                        },
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
             div { class: "info-item",
                 strong { "🌍 Number of CLI peers: " }
                 span { "{nb_peers}" }
@@ -269,6 +414,18 @@ pub fn Info() -> Element {
                 }
             }
 
+            div { class: "info-item",
+                strong { "📸 Last snapshot: " }
+                match last_snapshot_age() {
+                    Some(age) => rsx! {
+                        span { "{age / 60} min {age % 60} s ago" }
+                    },
+                    None => rsx! {
+                        span { "never" }
+                    },
+                }
+            }
+
             div {
                 class: "info-item",
                 style: "display: flex; justify-content: center;",
@@ -286,6 +443,121 @@ pub fn Info() -> Element {
                         "Take a snapshot"
                     }
                 }
+                Link { to: Route::Snapshots {}, "Browse snapshots" }
+            }
+
+            div { class: "info-item", id: "node-admin",
+                strong { "🛠️ Administration: " }
+                form {
+                    label { r#for: "admin-token", "Admin token:" }
+                    input {
+                        r#type: "password",
+                        id: "admin-token",
+                        value: admin_token,
+                        oninput: move |event| admin_token.set(event.value()),
+                    }
+                    button {
+                        r#type: "button",
+                        onclick: move |_| async move {
+                            let token = admin_token.read().trim().to_string();
+                            let status = admin_login_server(token)
+                                .await
+                                .map(|()| "Logged in as an administrator.".to_string())
+                                .map_err(|e| e.to_string());
+                            if status.is_ok() {
+                                admin_token.set(String::new());
+                            }
+                            admin_status.set(Some(status));
+                        },
+                        "Log in"
+                    }
+                    label { r#for: "restart-port", "Peer port:" }
+                    input {
+                        r#type: "number",
+                        id: "restart-port",
+                        min: "1",
+                        max: "65535",
+                        placeholder: "Unchanged",
+                        value: restart_port,
+                        oninput: move |event| restart_port.set(event.value()),
+                    }
+                    button {
+                        r#type: "button",
+                        onclick: move |_| async move {
+                            let port = restart_port.read().trim().to_string();
+                            let port = if port.is_empty() {
+                                None
+                            } else {
+                                match port.parse::<u16>() {
+                                    Ok(port) => Some(port),
+                                    Err(_) => {
+                                        admin_status.set(Some(Err(format!("Invalid port '{port}'"))));
+                                        return;
+                                    }
+                                }
+                            };
+                            let status = restart_networking_server(port).await.map_err(|e| e.to_string());
+                            if status.is_ok() {
+                                restart_port.set(String::new());
+                            }
+                            admin_status.set(Some(status));
+                        },
+                        "Restart networking"
+                    }
+                    if maintenance.read().is_some() {
+                        button {
+                            r#type: "button",
+                            onclick: move |_| async move {
+                                let status = set_maintenance_server(None).await.map_err(|e| e.to_string());
+                                if status.is_ok() {
+                                    maintenance.set(None);
+                                }
+                                admin_status.set(Some(status));
+                            },
+                            "Leave maintenance"
+                        }
+                    } else {
+                        label { r#for: "maintenance-message", "Maintenance message:" }
+                        input {
+                            r#type: "text",
+                            id: "maintenance-message",
+                            placeholder: "Default message",
+                            value: maintenance_message,
+                            oninput: move |event| maintenance_message.set(event.value()),
+                        }
+                        button {
+                            r#type: "button",
+                            onclick: move |_| async move {
+                                let message = maintenance_message.read().clone();
+                                let status = set_maintenance_server(Some(message)).await.map_err(|e| e.to_string());
+                                if status.is_ok() {
+                                    maintenance.set(get_maintenance_server().await.unwrap_or_default());
+                                    maintenance_message.set(String::new());
+                                }
+                                admin_status.set(Some(status));
+                            },
+                            "Enter maintenance"
+                        }
+                    }
+                    button {
+                        r#type: "button",
+                        class: "shutdown",
+                        onclick: move |_| async move {
+                            let status = shutdown_node_server().await.map_err(|e| e.to_string());
+                            admin_status.set(Some(status));
+                        },
+                        "Shut down"
+                    }
+                }
+                match &*admin_status.read() {
+                    Some(Ok(status)) => rsx! {
+                        p { class: "no-data-message", "✅ {status}" }
+                    },
+                    Some(Err(error)) => rsx! {
+                        p { class: "error-message", "{error}" }
+                    },
+                    None => rsx! {},
+                }
             }
 
             div { class: "info-item",
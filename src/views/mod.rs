@@ -3,6 +3,9 @@
 //! This module contains the Dioxus components that make up the web interface,
 //! including navigation, home page, user management, and transaction actions.
 
+/// Server functions of the web interface
+mod api;
+
 /// Navigation bar component
 mod navbar;
 pub use navbar::Navbar;
@@ -15,10 +18,50 @@ pub use home::Home;
 mod info;
 pub use info::Info;
 
+/// Cluster statistics component
+mod cluster;
+pub use cluster::Cluster;
+
+/// Snapshot browsing components
+mod snapshots;
+pub use snapshots::{SnapshotDetail, Snapshots};
+
+/// Transaction search component
+mod search;
+pub use search::Search;
+
+/// Product catalog administration component
+mod products;
+pub use products::Products;
+
+/// Transaction policy administration component
+mod policies;
+pub use policies::Policies;
+
+/// API token administration component
+mod tokens;
+pub use tokens::Tokens;
+
+/// Setup wizard of a new site
+mod setup;
+pub use setup::Setup;
+
+/// Bulk user import component
+mod import;
+pub use import::Import;
+
+/// Notification center component
+mod notifications;
+pub use notifications::Notifications;
+
 /// User management component
 mod user;
 pub use user::User;
 
 /// Transaction action components
 mod actions;
-pub use actions::{Deposit, History, Pay, Refund, Transfer, Withdraw};
+pub use actions::{Deposit, History, Pay, Refund, SplitBill, Transfer, Withdraw};
+
+/// Account statement components
+mod statements;
+pub use statements::Statements;
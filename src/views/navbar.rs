@@ -1,7 +1,9 @@
 //! Navigation bar component for the Peillute application
 //!
 //! This component provides the main navigation interface, including links to
-//! the home page and debug information, along with the application title.
+//! the home page and debug information, along with the application title, the
+//! bell showing the number of unread notifications and the banner of the
+//! maintenance in progress.
 
 use crate::Route;
 use dioxus::prelude::*;
@@ -11,13 +13,50 @@ use dioxus::prelude::*;
 /// Renders a navigation bar with links to different sections of the application
 /// and displays the application title. The component also includes an outlet
 /// for rendering child routes.
+///
+/// The unread notifications and the maintenance mode are fetched again on
+/// every navigation.
 #[component]
 pub fn Navbar() -> Element {
+    let route = use_route::<Route>();
+    let unread_resource = use_resource(use_reactive((&route,), |_| {
+        super::notifications::get_unread_counts_server()
+    }));
+    let unread = match &*unread_resource.read() {
+        Some(Ok(counts)) => counts.iter().map(|(_, count)| count).sum(),
+        _ => 0i64,
+    };
+    let maintenance_resource = use_resource(use_reactive((&route,), |_| {
+        super::api::get_maintenance_server()
+    }));
+    let maintenance = match &*maintenance_resource.read() {
+        Some(Ok(message)) => message.clone(),
+        _ => None,
+    };
+
     rsx! {
         div { id: "navbar",
             Link { to: Route::Home {}, "Home" }
             h1 { "Peillute" }
+            Link { to: Route::Search {}, "Search" }
+            Link { to: Route::Products {}, "Products" }
+            Link { to: Route::Policies {}, "Policies" }
+            Link { to: Route::Tokens {}, "Tokens" }
+            Link { to: Route::Import {}, "Import" }
+            Link { to: Route::Snapshots {}, "Snapshots" }
+            Link { to: Route::Cluster {}, "Cluster" }
             Link { to: Route::Info {}, "Debug-Info" }
+            Link {
+                to: Route::Notifications {},
+                class: "notification-bell",
+                "🔔"
+                if unread > 0 {
+                    span { class: "unread-count", "{unread}" }
+                }
+            }
+        }
+        if let Some(message) = maintenance {
+            div { class: "maintenance-banner", "🚧 {message}" }
         }
         Outlet::<Route> {}
     }
@@ -0,0 +1,119 @@
+//! Notification center of the Peillute application
+//!
+//! This module provides the page listing the notifications of the users of the
+//! site, such as the money they received or the end of the synchronization,
+//! and the server functions used by the bell of the navigation bar.
+
+use crate::notifications::Notification;
+use dioxus::prelude::*;
+
+/// Notification center component
+///
+/// Displays:
+/// - The number of unread notifications of each user, with a button to mark
+///   all of them as read
+/// - The notifications, the most recent first, unread ones being highlighted
+#[component]
+pub fn Notifications() -> Element {
+    let mut notifications_resource = use_resource(get_notifications_server);
+    let mut unread_resource = use_resource(get_unread_counts_server);
+    let mut error_signal = use_signal(|| None::<String>);
+
+    let mark_read = move |user: String, id: Option<i64>| async move {
+        match mark_read_server(user, id).await {
+            Ok(()) => {
+                error_signal.set(None);
+                notifications_resource.restart();
+                unread_resource.restart();
+            }
+            Err(e) => error_signal.set(Some(format!("{e}"))),
+        }
+    };
+
+    rsx! {
+        div { id: "notifications-page",
+            if let Some(Ok(counts)) = &*unread_resource.read() {
+                if !counts.is_empty() {
+                    ul { class: "unread-counts",
+                        for (user , count) in counts.iter() {
+                            {
+                                let user_for_mark = user.clone();
+                                rsx! {
+                                    li { key: "{user}",
+                                        span { "{user}: {count} unread" }
+                                        button {
+                                            r#type: "button",
+                                            onclick: move |_| mark_read(user_for_mark.clone(), None),
+                                            "Mark all as read"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+            match &*notifications_resource.read() {
+                None => rsx! {
+                    p { "Loading notifications..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading notifications: {e}" }
+                },
+                Some(Ok(notifications)) if notifications.is_empty() => rsx! {
+                    p { class: "no-data-message", "No notification yet." }
+                },
+                Some(Ok(notifications)) => rsx! {
+                    ul { class: "notifications-list",
+                        for notification in notifications.iter() {
+                            li {
+                                key: "{notification.id}",
+                                class: "notification",
+                                class: if !notification.read { "unread" },
+                                span { class: "notification-date", "{notification.created_at}" }
+                                span { class: "notification-user", "{notification.user}" }
+                                span { "{notification.message}" }
+                                if !notification.read {
+                                    {
+                                        let user_for_mark = notification.user.clone();
+                                        let id = notification.id;
+                                        rsx! {
+                                            button {
+                                                r#type: "button",
+                                                onclick: move |_| mark_read(user_for_mark.clone(), Some(id)),
+                                                "Mark as read"
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Server function to retrieve the notifications, the most recent first
+#[server]
+async fn get_notifications_server() -> Result<Vec<Notification>, ServerFnError> {
+    Ok(crate::notifications::get_notifications()?)
+}
+
+/// Server function to retrieve the number of unread notifications of each user
+#[server]
+pub async fn get_unread_counts_server() -> Result<Vec<(String, i64)>, ServerFnError> {
+    Ok(crate::notifications::unread_counts()?)
+}
+
+/// Server function to mark a notification of a user as read
+///
+/// Without `id`, every notification of the user is marked as read.
+#[server]
+async fn mark_read_server(user: String, id: Option<i64>) -> Result<(), ServerFnError> {
+    Ok(crate::notifications::mark_read(&user, id)?)
+}
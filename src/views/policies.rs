@@ -0,0 +1,253 @@
+//! Transaction policy administration for the Peillute application
+//!
+//! This module provides the page used to set the limits of the user accounts.
+//! Policies are local to the site: they are enforced on the operations
+//! submitted here, and the operations diffused by other sites that break them
+//! are listed for review.
+
+use crate::policy::{Policy, PolicyViolation};
+use dioxus::prelude::*;
+
+/// Parses an optional limit typed in a form, an empty field meaning no limit
+fn parse_limit(value: &str) -> Result<Option<f64>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    crate::amount::parse(value, &crate::amount::AmountLimits::UNBOUNDED).map(Some)
+}
+
+/// Transaction policy administration component
+///
+/// Lists the policies of the site and provides:
+/// - A form to set the limits of a user, replacing their current policy
+/// - Delete buttons to remove the policy of a user
+/// - The operations of other sites that broke a local policy
+#[component]
+pub fn Policies() -> Element {
+    let mut policies_resource = use_resource(get_policies_server);
+    let violations_resource = use_resource(get_violations_server);
+    let aliases_resource = use_resource(super::api::get_site_aliases_server);
+    let aliases = aliases_resource
+        .read()
+        .as_ref()
+        .and_then(|aliases| aliases.as_ref().ok())
+        .cloned()
+        .unwrap_or_default();
+    let mut user_input = use_signal(|| "".to_string());
+    let mut max_withdrawal_input = use_signal(|| "".to_string());
+    let mut daily_cap_input = use_signal(|| "".to_string());
+    let mut forbidden_input = use_signal(|| "".to_string());
+    let mut error_signal = use_signal(|| None::<String>);
+
+    rsx! {
+        div { id: "policies-page",
+            match &*policies_resource.read() {
+                None => rsx! {
+                    p { "Loading policies..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading policies: {e}" }
+                },
+                Some(Ok(policies)) if policies.is_empty() => rsx! {
+                    p { class: "no-data-message", "No policy on this site." }
+                },
+                Some(Ok(policies)) => rsx! {
+                    ul { class: "policies-list",
+                        for policy in policies.iter() {
+                            li { key: "{policy.user}", class: "policy-card",
+                                span { class: "policy-user", "{policy.user}" }
+                                if let Some(max) = policy.max_withdrawal {
+                                    span { "Max withdrawal: €{max:.2}" }
+                                }
+                                if let Some(cap) = policy.daily_transfer_cap {
+                                    span { "Daily transfer cap: €{cap:.2}" }
+                                }
+                                if !policy.forbidden_beneficiaries.is_empty() {
+                                    span { "Forbidden beneficiaries: {policy.forbidden_beneficiaries.join(\", \")}" }
+                                }
+                                {
+                                    let user_for_delete = policy.user.clone();
+                                    rsx! {
+                                        button {
+                                            r#type: "button",
+                                            class: "delete-btn",
+                                            title: "Delete policy",
+                                            onclick: move |_| {
+                                                let user = user_for_delete.clone();
+                                                async move {
+                                                    match delete_policy_server(user).await {
+                                                        Ok(()) => {
+                                                            error_signal.set(None);
+                                                            policies_resource.restart();
+                                                        }
+                                                        Err(e) => error_signal.set(Some(format!("{e}"))),
+                                                    }
+                                                }
+                                            },
+                                            "X"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+            form {
+                label { r#for: "policy-user", "User:" }
+                input {
+                    r#type: "text",
+                    id: "policy-user",
+                    value: user_input,
+                    oninput: move |event| user_input.set(event.value()),
+                }
+                label { r#for: "policy-max-withdrawal", "Max withdrawal:" }
+                input {
+                    r#type: "text",
+                    inputmode: "decimal",
+                    id: "policy-max-withdrawal",
+                    placeholder: "No limit",
+                    value: max_withdrawal_input,
+                    oninput: move |event| max_withdrawal_input.set(event.value()),
+                }
+                label { r#for: "policy-daily-cap", "Daily transfer cap:" }
+                input {
+                    r#type: "text",
+                    inputmode: "decimal",
+                    id: "policy-daily-cap",
+                    placeholder: "No limit",
+                    value: daily_cap_input,
+                    oninput: move |event| daily_cap_input.set(event.value()),
+                }
+                label { r#for: "policy-forbidden", "Forbidden beneficiaries:" }
+                input {
+                    r#type: "text",
+                    id: "policy-forbidden",
+                    placeholder: "alice, bob",
+                    value: forbidden_input,
+                    oninput: move |event| forbidden_input.set(event.value()),
+                }
+                button {
+                    r#type: "submit",
+                    onclick: move |_| async move {
+                        let limits = parse_limit(&max_withdrawal_input.read())
+                            .and_then(|max| Ok((max, parse_limit(&daily_cap_input.read())?)));
+                        let (max_withdrawal, daily_transfer_cap) = match limits {
+                            Ok(limits) => limits,
+                            Err(e) => {
+                                error_signal.set(Some(e));
+                                return;
+                            }
+                        };
+                        let policy = Policy {
+                            user: user_input.read().trim().to_string(),
+                            max_withdrawal,
+                            daily_transfer_cap,
+                            forbidden_beneficiaries: forbidden_input
+                                .read()
+                                .split(',')
+                                .map(|name| name.trim().to_string())
+                                .filter(|name| !name.is_empty())
+                                .collect(),
+                        };
+                        match set_policy_server(policy).await {
+                            Ok(()) => {
+                                user_input.set("".to_string());
+                                max_withdrawal_input.set("".to_string());
+                                daily_cap_input.set("".to_string());
+                                forbidden_input.set("".to_string());
+                                error_signal.set(None);
+                                policies_resource.restart();
+                            }
+                            Err(e) => error_signal.set(Some(format!("{e}"))),
+                        }
+                    },
+                    "Save policy"
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+            h2 { "Flagged operations" }
+            match &*violations_resource.read() {
+                None => rsx! {
+                    p { "Loading flagged operations..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading flagged operations: {e}" }
+                },
+                Some(Ok(violations)) if violations.is_empty() => rsx! {
+                    p { class: "no-data-message", "No operation broke a policy of this site." }
+                },
+                Some(Ok(violations)) => rsx! {
+                    table { class: "violations-table",
+                        thead {
+                            tr {
+                                th { "Flagged at" }
+                                th { "User" }
+                                th { "Operation" }
+                                th { "Reason" }
+                            }
+                        }
+                        tbody {
+                            for violation in violations.iter() {
+                                tr { key: "{violation.id}",
+                                    td { "{violation.flagged_at}" }
+                                    td { "{violation.user}" }
+                                    {
+                                        let site = crate::aliases::display_site(&violation.source_node, &aliases);
+                                        rsx! {
+                                            td { title: "{violation.source_node}", "{site}:{violation.lamport_time}" }
+                                        }
+                                    }
+                                    td { "{violation.reason}" }
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Server function to retrieve the policies of the site
+#[server]
+async fn get_policies_server() -> Result<Vec<Policy>, ServerFnError> {
+    Ok(crate::policy::get_policies()?)
+}
+
+/// Server function to set the policy of a user
+///
+/// Policies are local to the site, so the change is not broadcast.
+#[server]
+async fn set_policy_server(policy: Policy) -> Result<(), ServerFnError> {
+    if !crate::db::user_exists(&policy.user)? {
+        return Err(ServerFnError::new(format!(
+            "User {} does not exist.",
+            policy.user
+        )));
+    }
+    for limit in [policy.max_withdrawal, policy.daily_transfer_cap]
+        .into_iter()
+        .flatten()
+    {
+        crate::amount::validate(limit, &crate::amount::AmountLimits::UNBOUNDED)
+            .map_err(ServerFnError::new)?;
+    }
+
+    Ok(crate::policy::set_policy(&policy)?)
+}
+
+/// Server function to remove the policy of a user
+#[server]
+async fn delete_policy_server(user: String) -> Result<(), ServerFnError> {
+    Ok(crate::policy::delete_policy(&user)?)
+}
+
+/// Server function to retrieve the operations that broke a local policy
+#[server]
+async fn get_violations_server() -> Result<Vec<PolicyViolation>, ServerFnError> {
+    Ok(crate::policy::get_violations()?)
+}
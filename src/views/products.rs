@@ -0,0 +1,209 @@
+//! Product catalog administration for the Peillute application
+//!
+//! This module provides the page used to manage the products sold on the
+//! payment page. Changes to the catalog are diffused to every site, so that
+//! all nodes show the same menu.
+
+use super::actions::TransactionConfirmation;
+use crate::db::Product;
+use dioxus::prelude::*;
+
+/// Product catalog administration component
+///
+/// Lists the products of the catalog and provides:
+/// - A form to add a product, or change the price and image of an existing one
+/// - An image upload for the product, shown on the payment page
+/// - Delete buttons to remove products from the catalog
+#[component]
+pub fn Products() -> Element {
+    let mut products_resource = use_resource(get_products_server);
+    let mut name_input = use_signal(|| "".to_string());
+    let mut price_input = use_signal(|| 0f64);
+    let mut image = use_signal(|| None::<String>);
+    let mut error_signal = use_signal(|| None::<String>);
+    let mut ticket_signal = use_signal(|| None::<u64>);
+
+    rsx! {
+        div { id: "products-page",
+            match &*products_resource.read() {
+                None => rsx! {
+                    p { "Loading products..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading products: {e}" }
+                },
+                Some(Ok(products)) if products.is_empty() => rsx! {
+                    p { class: "no-data-message", "No products in the catalog." }
+                },
+                Some(Ok(products)) => rsx! {
+                    ul { class: "products-list",
+                        for product in products.iter() {
+                            li { key: "{product.name}", class: "product-card",
+                                if let Some(src) = &product.image {
+                                    img { src: "{src}", alt: "{product.name}" }
+                                }
+                                span { class: "product-name", "{product.name}" }
+                                span { class: "product-price", "€{product.price:.2}" }
+                                {
+                                    let name_for_delete = product.name.clone();
+                                    rsx! {
+                                        button {
+                                            r#type: "button",
+                                            class: "delete-btn",
+                                            title: "Delete product",
+                                            onclick: move |_| {
+                                                let name = name_for_delete.clone();
+                                                async move {
+                                                    match delete_product_server(name).await {
+                                                        Ok(ticket) => {
+                                                            error_signal.set(None);
+                                                            ticket_signal.set(Some(ticket));
+                                                        }
+                                                        Err(e) => error_signal.set(Some(format!("{e}"))),
+                                                    }
+                                                }
+                                            },
+                                            "X"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+            form {
+                label { r#for: "product-name", "Product name:" }
+                input {
+                    r#type: "text",
+                    id: "product-name",
+                    placeholder: "New or existing product",
+                    value: name_input,
+                    oninput: move |event| name_input.set(event.value()),
+                }
+                label { r#for: "product-price", "Price:" }
+                input {
+                    r#type: "number",
+                    id: "product-price",
+                    step: 0.01,
+                    min: "0",
+                    value: "{price_input}",
+                    oninput: move |event| {
+                        if let Ok(as_number) = event.value().parse::<f64>() {
+                            price_input.set(as_number);
+                        }
+                    },
+                }
+                label { r#for: "product-image", "Image:" }
+                input {
+                    r#type: "file",
+                    id: "product-image",
+                    accept: "image/*",
+                    onchange: move |event: Event<FormData>| async move {
+                        image.set(None);
+                        if let Some(engine) = event.files() {
+                            for file_name in engine.files() {
+                                if let Some(bytes) = engine.read_file(&file_name).await {
+                                    match crate::utils::image_data_url(&file_name, &bytes) {
+                                        Some(url) => image.set(Some(url)),
+                                        None => {
+                                            error_signal
+                                                .set(Some(format!("{file_name} is not a supported image.")))
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                }
+                if let Some(src) = image() {
+                    img { class: "product-preview", src: "{src}", alt: "Preview" }
+                }
+                button {
+                    r#type: "submit",
+                    onclick: move |_| async move {
+                        let name = name_input.read().trim().to_string();
+                        let price = *price_input.read();
+                        match upsert_product_server(name, price, image()).await {
+                            Ok(ticket) => {
+                                name_input.set("".to_string());
+                                price_input.set(0.0);
+                                image.set(None);
+                                error_signal.set(None);
+                                ticket_signal.set(Some(ticket));
+                            }
+                            Err(e) => error_signal.set(Some(format!("{e}"))),
+                        }
+                    },
+                    "Save product"
+                }
+            }
+            if let Some(ticket) = ticket_signal() {
+                TransactionConfirmation {
+                    ticket,
+                    on_final: move |_| products_resource.restart(),
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+/// Maximum size of the data URL of a product image, so that the catalog
+/// changes fit in a network message
+const MAX_PRODUCT_IMAGE_LEN: usize = 256 * 1024;
+
+/// Server function to retrieve the products of the catalog
+#[server]
+pub async fn get_products_server() -> Result<Vec<Product>, ServerFnError> {
+    Ok(crate::db::get_products()?)
+}
+
+/// Server function to add a product or update its price and image
+///
+/// Without image, the current image of the product is kept. The change is
+/// broadcast to all nodes in the network.
+#[server]
+async fn upsert_product_server(
+    name: String,
+    price: f64,
+    image: Option<String>,
+) -> Result<u64, ServerFnError> {
+    if name.is_empty() {
+        return Err(ServerFnError::new("Product name cannot be empty."));
+    }
+    let price = crate::amount::validate(price, &crate::amount::AmountLimits::UNBOUNDED)
+        .map_err(ServerFnError::new)?;
+    if let Some(image) = &image {
+        if !image.starts_with("data:image/") {
+            return Err(ServerFnError::new("The image must be a data URL."));
+        }
+        if image.len() > MAX_PRODUCT_IMAGE_LEN {
+            return Err(ServerFnError::new(format!(
+                "The image is too large, the limit is {} KiB.",
+                MAX_PRODUCT_IMAGE_LEN / 1024
+            )));
+        }
+    }
+
+    crate::control::enqueue_critical(crate::control::CriticalCommands::UpsertProduct {
+        name,
+        price,
+        image,
+    })
+    .await
+    .map_err(|e| ServerFnError::new(format!("Failed to diffuse the product: {e}")))
+}
+
+/// Server function to remove a product from the catalog
+///
+/// The removal is broadcast to all nodes in the network.
+#[server]
+async fn delete_product_server(name: String) -> Result<u64, ServerFnError> {
+    crate::control::enqueue_critical(crate::control::CriticalCommands::DeleteProduct { name })
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to diffuse the product removal: {e}")))
+}
@@ -0,0 +1,208 @@
+//! Transaction search component for the Peillute application
+//!
+//! This module provides an admin page to search the transactions of all users
+//! by user, amount range, message, node and Lamport time range.
+
+use crate::Route;
+use crate::db::{TransactionPage, TransactionQuery};
+use dioxus::prelude::*;
+
+/// Transaction search component
+///
+/// Displays a search form and the matching transactions in a paginated table,
+/// most recent first.
+#[component]
+pub fn Search() -> Element {
+    let mut user = use_signal(String::new);
+    let mut message = use_signal(String::new);
+    let mut node = use_signal(String::new);
+    let mut min_amount = use_signal(String::new);
+    let mut max_amount = use_signal(String::new);
+    let mut min_lamport = use_signal(String::new);
+    let mut max_lamport = use_signal(String::new);
+
+    let mut query = use_signal(TransactionQuery::default);
+    let results = use_resource(move || async move { search_transactions_server(query()).await });
+
+    let build_query = move || TransactionQuery {
+        user: Some(user()).filter(|v| !v.is_empty()),
+        message: Some(message()).filter(|v| !v.is_empty()),
+        node: Some(node()).filter(|v| !v.is_empty()),
+        min_amount: min_amount().trim().parse().ok(),
+        max_amount: max_amount().trim().parse().ok(),
+        min_lamport: min_lamport().trim().parse().ok(),
+        max_lamport: max_lamport().trim().parse().ok(),
+        page: 0,
+        page_size: 0,
+    };
+
+    rsx! {
+        div { class: "info-panel", id: "search-page",
+            h2 { "Transaction search" }
+            form { id: "search-form",
+                label { r#for: "search-user", "User:" }
+                input {
+                    id: "search-user",
+                    r#type: "text",
+                    value: "{user}",
+                    oninput: move |evt| user.set(evt.value()),
+                }
+                label { r#for: "search-message", "Message contains:" }
+                input {
+                    id: "search-message",
+                    r#type: "text",
+                    value: "{message}",
+                    oninput: move |evt| message.set(evt.value()),
+                }
+                label { r#for: "search-node", "Node:" }
+                input {
+                    id: "search-node",
+                    r#type: "text",
+                    value: "{node}",
+                    oninput: move |evt| node.set(evt.value()),
+                }
+                label { r#for: "search-min-amount", "Amount between:" }
+                div { class: "search-range",
+                    input {
+                        id: "search-min-amount",
+                        r#type: "number",
+                        step: 0.01,
+                        placeholder: "min",
+                        value: "{min_amount}",
+                        oninput: move |evt| min_amount.set(evt.value()),
+                    }
+                    input {
+                        r#type: "number",
+                        step: 0.01,
+                        placeholder: "max",
+                        value: "{max_amount}",
+                        oninput: move |evt| max_amount.set(evt.value()),
+                    }
+                }
+                label { r#for: "search-min-lamport", "Lamport time between:" }
+                div { class: "search-range",
+                    input {
+                        id: "search-min-lamport",
+                        r#type: "number",
+                        placeholder: "min",
+                        value: "{min_lamport}",
+                        oninput: move |evt| min_lamport.set(evt.value()),
+                    }
+                    input {
+                        r#type: "number",
+                        placeholder: "max",
+                        value: "{max_lamport}",
+                        oninput: move |evt| max_lamport.set(evt.value()),
+                    }
+                }
+                button {
+                    r#type: "submit",
+                    onclick: move |evt: Event<MouseData>| {
+                        evt.prevent_default();
+                        query.set(build_query());
+                    },
+                    "Search"
+                }
+            }
+
+            match &*results.read() {
+                None => rsx! {
+                    p { class: "loading-message", "Searching..." }
+                },
+                Some(Ok(page)) => rsx! {
+                    SearchResults { page: page.clone(), query }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error searching transactions: {e}" }
+                },
+            }
+        }
+    }
+}
+
+/// Table of the transactions of a search page, with the pagination controls
+#[component]
+fn SearchResults(page: TransactionPage, query: Signal<TransactionQuery>) -> Element {
+    let aliases_resource = use_resource(super::api::get_site_aliases_server);
+    let aliases = aliases_resource
+        .read()
+        .as_ref()
+        .and_then(|aliases| aliases.as_ref().ok())
+        .cloned()
+        .unwrap_or_default();
+    let nb_pages = page.total.div_ceil(page.page_size.max(1)).max(1);
+    let current = page.page;
+
+    if page.transactions.is_empty() {
+        return rsx! {
+            p { class: "no-data-message", "No transaction matches the search." }
+        };
+    }
+
+    rsx! {
+        p { "{page.total} transaction(s) found" }
+        table { class: "search-results",
+            thead {
+                tr {
+                    th { "Lamport" }
+                    th { "Node" }
+                    th { "From" }
+                    th { "To" }
+                    th { "Amount" }
+                    th { "Message" }
+                }
+            }
+            tbody {
+                for tx in page.transactions.iter() {
+                    tr { key: "{tx.lamport_time}-{tx.source_node}",
+                        td { "{tx.lamport_time}" }
+                        td { title: "{tx.source_node}",
+                            {crate::aliases::display_site(&tx.source_node, &aliases)}
+                        }
+                        td {
+                            Link {
+                                to: Route::History {
+                                    name: tx.from_user.clone(),
+                                },
+                                "{tx.from_user}"
+                            }
+                        }
+                        td {
+                            Link {
+                                to: Route::History {
+                                    name: tx.to_user.clone(),
+                                },
+                                "{tx.to_user}"
+                            }
+                        }
+                        td { "{tx.amount:.2} €" }
+                        td { "{tx.optional_msg.clone().unwrap_or_default()}" }
+                    }
+                }
+            }
+        }
+        div { class: "search-pagination",
+            button {
+                r#type: "button",
+                disabled: current == 0,
+                onclick: move |_| query.write().page = current.saturating_sub(1),
+                "Previous"
+            }
+            span { "Page {current + 1} / {nb_pages}" }
+            button {
+                r#type: "button",
+                disabled: current + 1 >= nb_pages,
+                onclick: move |_| query.write().page = current + 1,
+                "Next"
+            }
+        }
+    }
+}
+
+/// Server function to search the transactions of all users
+#[server]
+async fn search_transactions_server(
+    query: TransactionQuery,
+) -> Result<TransactionPage, ServerFnError> {
+    Ok(crate::db::search_transactions(&query)?)
+}
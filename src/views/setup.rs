@@ -0,0 +1,221 @@
+//! Setup wizard of a new site for the Peillute application
+//!
+//! This module provides the page shown on the first start of a site, with an
+//! empty database and no peer, instead of the home page. See
+//! [`crate::setup`] for what the answers change on the site.
+
+use crate::Route;
+use crate::setup::{SetupRequest, SetupStatus};
+use dioxus::prelude::*;
+
+/// Steps of the wizard, in order
+const STEPS: [&str; 3] = ["Site", "Network", "Users"];
+
+/// Setup wizard component
+///
+/// Walks through three steps:
+/// - The alias of the site
+/// - The port listened on for the peers and the peers to join, one per line
+/// - The first users, one per line with an optional initial balance
+///
+/// The last step applies the answers and leads to the home page.
+#[component]
+pub fn Setup() -> Element {
+    let status_resource = use_resource(get_setup_status_server);
+    let mut step = use_signal(|| 0usize);
+    let mut alias_input = use_signal(|| "".to_string());
+    let mut port_input = use_signal(|| "".to_string());
+    let mut peers_input = use_signal(|| "".to_string());
+    let mut users_input = use_signal(|| "".to_string());
+    let mut running = use_signal(|| false);
+    let mut result_signal = use_signal(|| None::<String>);
+    let mut error_signal = use_signal(|| None::<String>);
+
+    let status = match &*status_resource.read() {
+        None => {
+            return rsx! {
+                p { "Loading..." }
+            };
+        }
+        Some(Err(e)) => {
+            return rsx! {
+                p { class: "error-message", "Error loading the state of the site: {e}" }
+            };
+        }
+        Some(Ok(status)) => status.clone(),
+    };
+    if !status.needed && result_signal.read().is_none() {
+        return rsx! {
+            div { id: "setup-page",
+                p { class: "no-data-message", "This site is already set up." }
+                Link { to: Route::Home {}, "Go to the home page" }
+            }
+        };
+    }
+
+    rsx! {
+        div { id: "setup-page",
+            h1 { "Set up site {status.site_id}" }
+            ol { class: "setup-steps",
+                for (i , name) in STEPS.iter().enumerate() {
+                    li { key: "{name}", class: if i == step() { "current" } else { "" }, "{name}" }
+                }
+            }
+            if let Some(result) = &*result_signal.read() {
+                p { class: "no-data-message", "✅ {result}" }
+                Link { to: Route::Home {}, "Go to the home page" }
+            } else {
+                form {
+                    match step() {
+                        0 => rsx! {
+                            label { r#for: "setup-alias", "Name of the site:" }
+                            input {
+                                id: "setup-alias",
+                                placeholder: "Cafeteria",
+                                value: alias_input,
+                                oninput: move |event| alias_input.set(event.value()),
+                            }
+                        },
+                        1 => rsx! {
+                            label { r#for: "setup-port", "Port for the peers:" }
+                            input {
+                                r#type: "number",
+                                id: "setup-port",
+                                min: "1",
+                                max: "65535",
+                                placeholder: "{status.port}",
+                                value: port_input,
+                                oninput: move |event| port_input.set(event.value()),
+                            }
+                            label { r#for: "setup-peers", "Peers to join, one ip:port per line, none to look for them:" }
+                            textarea {
+                                id: "setup-peers",
+                                rows: "4",
+                                value: peers_input,
+                                oninput: move |event| peers_input.set(event.value()),
+                            }
+                        },
+                        _ => rsx! {
+                            label { r#for: "setup-users", "First users, one name,balance per line:" }
+                            textarea {
+                                id: "setup-users",
+                                rows: "6",
+                                placeholder: "alice,50\nbob",
+                                value: users_input,
+                                oninput: move |event| users_input.set(event.value()),
+                            }
+                        },
+                    }
+                    div { class: "setup-buttons",
+                        if step() > 0 {
+                            button {
+                                r#type: "button",
+                                onclick: move |_| step -= 1,
+                                "Back"
+                            }
+                        }
+                        if step() + 1 < STEPS.len() {
+                            button {
+                                r#type: "button",
+                                disabled: step() == 0 && alias_input.read().trim().is_empty(),
+                                onclick: move |_| step += 1,
+                                "Next"
+                            }
+                        } else {
+                            button {
+                                r#type: "submit",
+                                disabled: running(),
+                                onclick: move |event| async move {
+                                    event.prevent_default();
+                                    let request = match parse_answers(
+                                        &alias_input.read(),
+                                        &port_input.read(),
+                                        &peers_input.read(),
+                                        &users_input.read(),
+                                    ) {
+                                        Ok(request) => request,
+                                        Err(e) => {
+                                            error_signal.set(Some(e));
+                                            return;
+                                        }
+                                    };
+                                    running.set(true);
+                                    match run_setup_server(request).await {
+                                        Ok(result) => {
+                                            error_signal.set(None);
+                                            result_signal.set(Some(result));
+                                        }
+                                        Err(e) => error_signal.set(Some(format!("{e}"))),
+                                    }
+                                    running.set(false);
+                                },
+                                if running() {
+                                    "Setting up..."
+                                } else {
+                                    "Set up the site"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+    }
+}
+
+/// Turns the fields of the wizard into the answers sent to the site
+fn parse_answers(
+    alias: &str,
+    port: &str,
+    peers: &str,
+    users: &str,
+) -> Result<SetupRequest, String> {
+    let port = match port.trim() {
+        "" => None,
+        port => Some(
+            port.parse::<u16>()
+                .map_err(|_| format!("Invalid port '{port}'"))?,
+        ),
+    };
+    let mut parsed_users = Vec::new();
+    for line in users.lines().filter(|line| !line.trim().is_empty()) {
+        let (name, balance) = line.split_once(',').unwrap_or((line, ""));
+        let balance = match balance.trim() {
+            "" => 0.0,
+            balance => balance
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid balance '{balance}' of '{}'", name.trim()))?,
+        };
+        parsed_users.push((name.trim().to_string(), balance));
+    }
+    Ok(SetupRequest {
+        site_alias: alias.trim().to_string(),
+        port,
+        peers: peers.lines().map(str::to_string).collect(),
+        users: parsed_users,
+    })
+}
+
+/// Server function returning the state of the site shown by the wizard
+#[server]
+pub async fn get_setup_status_server() -> Result<SetupStatus, ServerFnError> {
+    crate::setup::status()
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+/// Server function applying the answers of the wizard
+#[server]
+async fn run_setup_server(request: SetupRequest) -> Result<String, ServerFnError> {
+    let users = request.users.len();
+    let addr = crate::setup::run(request)
+        .await
+        .map_err(|e| ServerFnError::new(e.to_string()))?;
+    Ok(format!(
+        "Listening for the peers on {addr}, {users} users submitted. The web interface listens on port {} from the next start.",
+        crate::web_address::port_for(addr.port())
+    ))
+}
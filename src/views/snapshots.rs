@@ -0,0 +1,448 @@
+//! Snapshot browsing components for the Peillute application
+//!
+//! This module provides components for taking a global snapshot from the web
+//! interface, browsing the snapshot files previously persisted on disk and
+//! comparing two of them.
+
+use crate::Route;
+use crate::snapshot::{SnapshotDiff, SnapshotFileInfo, SnapshotProgress};
+use dioxus::prelude::*;
+
+/// Snapshot list component
+///
+/// Displays the snapshot files persisted by this site, most recent first, with:
+/// - The date and size of the file
+/// - The number of transactions and the missing transactions per site
+/// - The consistency status of the snapshot
+///
+/// Also provides a button to take a new snapshot, labelled if a label is
+/// typed, follows the responses of the sites while it is collected and reports
+/// its result. Two snapshots of the list can be compared, see
+/// [`SnapshotDiffView`].
+#[component]
+pub fn Snapshots() -> Element {
+    let mut snapshots_resource = use_resource(get_snapshot_files);
+    let mut report = use_signal(|| None::<String>);
+    let mut error_signal = use_signal(|| None::<String>);
+    let mut in_progress = use_signal(|| false);
+    let mut snapshot_progress = use_signal(|| None::<SnapshotProgress>);
+    let mut label = use_signal(String::new);
+    let mut compare_a = use_signal(String::new);
+    let mut compare_b = use_signal(String::new);
+    let mut diff = use_signal(|| None::<Result<SnapshotDiff, String>>);
+
+    rsx! {
+        div { class: "info-panel", id: "snapshots-page",
+            h2 { "Snapshots" }
+
+            div {
+                class: "info-item",
+                style: "display: flex; justify-content: center; gap: 0.5em;",
+                input {
+                    r#type: "text",
+                    placeholder: "Label (optional), e.g. before-upgrade",
+                    value: "{label}",
+                    oninput: move |evt| label.set(evt.value()),
+                }
+                button {
+                    class: "snapshot",
+                    r#type: "button",
+                    disabled: in_progress(),
+                    onclick: move |_| async move {
+                        in_progress.set(true);
+                        snapshot_progress.set(None);
+                        // Long-poll the responses of the sites until the snapshot is taken
+                        spawn(async move {
+                            let mut last = None;
+                            while in_progress() {
+                                match wait_for_snapshot_progress(last.clone()).await {
+                                    Ok(data) => {
+                                        snapshot_progress.set(Some(data.clone()));
+                                        last = Some(data);
+                                    }
+                                    Err(_) => break,
+                                }
+                            }
+                        });
+                        let typed = label().trim().to_string();
+                        let typed = Some(typed).filter(|typed| !typed.is_empty());
+                        match take_snapshot(typed).await {
+                            Ok(Some(info)) => {
+                                report.set(Some(format!(
+                                    "Snapshot saved at {} ({})",
+                                    info.file_name,
+                                    consistency_label(info.consistent),
+                                )));
+                                error_signal.set(None);
+                                label.set(String::new());
+                            }
+                            Ok(None) => {
+                                report.set(None);
+                                error_signal.set(Some(
+                                    "The snapshot is still in progress, refresh the list later."
+                                        .to_string(),
+                                ));
+                            }
+                            Err(e) => {
+                                report.set(None);
+                                error_signal.set(Some(format!("Error taking snapshot: {e}")));
+                            }
+                        }
+                        in_progress.set(false);
+                        snapshots_resource.restart();
+                    },
+                    if in_progress() {
+                        "Snapshot in progress..."
+                    } else {
+                        "Take a snapshot"
+                    }
+                }
+            }
+
+            if let Some(data) = &*snapshot_progress.read() {
+                div { class: "info-item", id: "snapshot-progress",
+                    progress { max: "{data.expected}", value: "{data.received}" }
+                    p { "{data.received}/{data.expected} responses received" }
+                    ul { class: "peer-list",
+                        for (site , responded) in data.sites.iter() {
+                            li { key: "{site}",
+                                if *responded {
+                                    "✅ {site}"
+                                } else {
+                                    "⏳ {site}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(msg) = &*report.read() {
+                p { class: "no-data-message", "{msg}" }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+
+            match &*snapshots_resource.read() {
+                None => rsx! {
+                    p { class: "loading-message", "Loading snapshots..." }
+                },
+                Some(Ok(snapshots)) => {
+                    if snapshots.is_empty() {
+                        rsx! {
+                            p { class: "no-data-message", "No snapshot available." }
+                        }
+                    } else {
+                        let whole: Vec<SnapshotFileInfo> = snapshots
+                            .iter()
+                            .filter(|info| info.ledger.is_none())
+                            .cloned()
+                            .collect();
+                        rsx! {
+                            div { class: "info-item", id: "snapshot-compare",
+                                strong { "🔍 Compare two snapshots:" }
+                                for (side , mut selected) in [("First", compare_a), ("Second", compare_b)] {
+                                    select {
+                                        key: "{side}",
+                                        value: "{selected}",
+                                        onchange: move |evt| selected.set(evt.value()),
+                                        option { value: "", "{side} snapshot..." }
+                                        for info in whole.iter() {
+                                            option {
+                                                key: "{info.file_name}",
+                                                value: "{info.file_name}",
+                                                {snapshot_title(info)}
+                                            }
+                                        }
+                                    }
+                                }
+                                button {
+                                    r#type: "button",
+                                    disabled: compare_a().is_empty() || compare_b().is_empty(),
+                                    onclick: move |_| async move {
+                                        let result = diff_snapshots(compare_a(), compare_b())
+                                            .await
+                                            .map_err(|e| e.to_string());
+                                        diff.set(Some(result));
+                                    },
+                                    "Compare"
+                                }
+                            }
+                            match &*diff.read() {
+                                Some(Ok(result)) => rsx! {
+                                    SnapshotDiffView { diff: result.clone() }
+                                },
+                                Some(Err(e)) => rsx! {
+                                    p { class: "error-message", "Error comparing snapshots: {e}" }
+                                },
+                                None => rsx! {},
+                            }
+                            ul { class: "transactions-list",
+                                for info in snapshots.iter() {
+                                    li { key: "{info.file_name}", class: "transaction-card",
+                                        SnapshotSummary { info: info.clone() }
+                                        Link {
+                                            to: Route::SnapshotDetail {
+                                                name: info.file_name.clone(),
+                                            },
+                                            "Details"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading snapshots: {e}" }
+                },
+            }
+        }
+    }
+}
+
+/// Snapshot detail component
+///
+/// Displays the summary of a single snapshot file followed by its raw JSON content.
+#[component]
+pub fn SnapshotDetail(name: String) -> Element {
+    let name_for_future = name.clone();
+    let detail_resource = use_resource(move || {
+        let name = name_for_future.clone();
+        async move { get_snapshot_detail(name).await }
+    });
+
+    rsx! {
+        div { class: "info-panel", id: "snapshot-detail-page",
+            h2 { "Snapshot {name}" }
+            Link { to: Route::Snapshots {}, "Back to snapshots" }
+            match &*detail_resource.read() {
+                None => rsx! {
+                    p { class: "loading-message", "Loading snapshot..." }
+                },
+                Some(Ok((info, content))) => rsx! {
+                    SnapshotSummary { info: info.clone() }
+                    div { class: "info-item",
+                        strong { "📄 Content:" }
+                        pre { "{content}" }
+                    }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading snapshot: {e}" }
+                },
+            }
+        }
+    }
+}
+
+/// Comparison of two snapshots
+///
+/// Lists, for each site whose transactions differ, the transactions found in
+/// one of the snapshots only.
+#[component]
+pub fn SnapshotDiffView(diff: SnapshotDiff) -> Element {
+    rsx! {
+        div { class: "info-item", id: "snapshot-diff",
+            p {
+                strong { "{diff.a} → {diff.b}:" }
+                " {diff.common} transaction(s) in both"
+            }
+            if diff.sites.is_empty() {
+                p { class: "no-data-message", "The snapshots hold the same transactions." }
+            }
+            for site in diff.sites.iter() {
+                div { key: "{site.site}",
+                    p {
+                        strong { "Site {site.site}:" }
+                        " {site.only_in_a.len()} only in the first, {site.only_in_b.len()} only in the second"
+                    }
+                    ul { class: "peer-list",
+                        for tx in site.only_in_a.iter() {
+                            li { key: "a-{tx.lamport_time}-{tx.from_user}-{tx.to_user}",
+                                {diff_line('−', tx)}
+                            }
+                        }
+                        for tx in site.only_in_b.iter() {
+                            li { key: "b-{tx.lamport_time}-{tx.from_user}-{tx.to_user}",
+                                {diff_line('+', tx)}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Line of a transaction found in one of the compared snapshots only
+fn diff_line(sign: char, tx: &crate::snapshot::TxSummary) -> String {
+    format!(
+        "{} ({}, {}) {} → {}: {:.2}",
+        sign,
+        tx.lamport_time,
+        tx.source_node,
+        tx.from_user,
+        tx.to_user,
+        tx.amount_in_cent as f64 / 100.0
+    )
+}
+
+/// Title of a snapshot in the lists, its label and date if it has a label
+fn snapshot_title(info: &SnapshotFileInfo) -> String {
+    match (&info.label, &info.timestamp) {
+        (Some(label), Some(timestamp)) => format!("{} ({})", label, timestamp),
+        (Some(label), None) => label.clone(),
+        (None, _) => info.file_name.clone(),
+    }
+}
+
+/// Summary of a snapshot file, shared by the list and detail views
+#[component]
+fn SnapshotSummary(info: SnapshotFileInfo) -> Element {
+    let timestamp = info.timestamp.clone().unwrap_or("Unknown date".to_string());
+    let sites = info.sites.join(", ");
+
+    rsx! {
+        if let Some(label) = &info.label {
+            p {
+                strong { "Label:" }
+                " {label}"
+            }
+        }
+        p {
+            strong { "File:" }
+            " {info.file_name}"
+        }
+        p {
+            strong { "Date:" }
+            " {timestamp}"
+        }
+        if let Some(ledger) = &info.ledger {
+            p {
+                strong { "Ledger:" }
+                " {ledger}"
+            }
+        }
+        if let Some(initiator) = &info.initiator {
+            p {
+                strong { "Initiator:" }
+                " {initiator}"
+            }
+        }
+        if !info.sites.is_empty() {
+            p {
+                strong { "Sites:" }
+                " {sites}"
+            }
+        }
+        p {
+            strong { "Size:" }
+            " {info.size_bytes} bytes"
+        }
+        p {
+            strong { "Transactions:" }
+            " {info.nb_transactions}"
+        }
+        p {
+            strong { "Status:" }
+            " {consistency_label(info.consistent)}"
+        }
+        if info.missing_per_site.is_empty() {
+            p {
+                strong { "Missing:" }
+                " none, every site is up to date"
+            }
+        } else {
+            p { strong { "Missing per site:" } }
+            ul { class: "peer-list",
+                for (site , nb) in info.missing_per_site.iter() {
+                    li { key: "{site}", "{site}: {nb} transaction(s)" }
+                }
+            }
+        }
+    }
+}
+
+/// Human readable consistency status of a snapshot
+fn consistency_label(consistent: Option<bool>) -> &'static str {
+    match consistent {
+        Some(true) => "consistent",
+        Some(false) => "back-tracked to a consistent cut",
+        None => "unknown",
+    }
+}
+
+/// Server function to list the persisted snapshot files
+#[server]
+async fn get_snapshot_files() -> Result<Vec<SnapshotFileInfo>, ServerFnError> {
+    Ok(crate::snapshot::list_snapshot_files()?)
+}
+
+/// Server function to retrieve the summary and content of a snapshot file
+#[server]
+async fn get_snapshot_detail(name: String) -> Result<(SnapshotFileInfo, String), ServerFnError> {
+    let content = crate::snapshot::read_snapshot_file(&name)?;
+    let info = crate::snapshot::summarize_snapshot(&name, content.len() as u64, &content);
+    Ok((info, content))
+}
+
+/// Server function to compare two persisted snapshots
+#[server]
+async fn diff_snapshots(a: String, b: String) -> Result<SnapshotDiff, ServerFnError> {
+    Ok(crate::snapshot::diff_snapshot_files(&a, &b)?)
+}
+
+/// Server function to wait for the progress of the snapshot being collected
+///
+/// Without `last`, returns the current progress immediately.
+#[server]
+async fn wait_for_snapshot_progress(
+    last: Option<SnapshotProgress>,
+) -> Result<SnapshotProgress, ServerFnError> {
+    const PROGRESS_POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(25);
+    Ok(crate::snapshot::wait_for_progress(last, PROGRESS_POLL_TIMEOUT).await)
+}
+
+/// Server function to take a snapshot and wait for its result
+///
+/// Returns the summary of the persisted file, with its path and consistency status,
+/// or None if the snapshot wave did not complete in time.
+#[server]
+async fn take_snapshot(label: Option<String>) -> Result<Option<SnapshotFileInfo>, ServerFnError> {
+    use crate::snapshot::LOCAL_SNAPSHOT_MANAGER;
+
+    const POLL_INTERVAL_MS: u64 = 100;
+    const MAX_POLLS: u32 = 100;
+
+    let previous_path = {
+        let mgr = LOCAL_SNAPSHOT_MANAGER.lock().await;
+        mgr.path.clone()
+    };
+
+    if let Err(e) = crate::api::snapshot(label).await {
+        return Err(ServerFnError::new(format!(
+            "[SERVER] Failed make the local snapshot: {e}"
+        )));
+    }
+
+    for _ in 0..MAX_POLLS {
+        tokio::time::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS)).await;
+        let path = {
+            let mgr = LOCAL_SNAPSHOT_MANAGER.lock().await;
+            mgr.path.clone()
+        };
+        if let Some(path) = path
+            && Some(&path) != previous_path.as_ref()
+        {
+            let file_name = path.to_string_lossy().to_string();
+            let content = crate::snapshot::read_snapshot_file(&file_name)?;
+            return Ok(Some(crate::snapshot::summarize_snapshot(
+                &file_name,
+                content.len() as u64,
+                &content,
+            )));
+        }
+    }
+
+    Ok(None)
+}
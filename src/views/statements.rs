@@ -0,0 +1,174 @@
+//! Account statement components for the Peillute application
+//!
+//! This module provides a component for closing the accounting period of a
+//! user and browsing the statements of the previous periods, with PDF export.
+
+use crate::statements::Statement;
+use dioxus::prelude::*;
+
+/// Account statements component
+///
+/// Displays the statements of a user, most recent first, with:
+/// - The accounting period and the date it was closed
+/// - The opening balance, the totals in and out and the closing balance
+/// - A button to export the statement as a PDF document
+///
+/// Also provides a button to close the current period on demand.
+#[component]
+pub fn Statements(name: String) -> Element {
+    let name = std::rc::Rc::new(name);
+    let name_for_resource = name.clone();
+    let name_for_close = name.clone();
+
+    let mut statements_resource = use_resource(move || {
+        let name = name_for_resource.clone();
+        async move { get_statements_server(name.to_string(), super::api::tab_id()).await }
+    });
+    let mut message = use_signal(|| None::<String>);
+    let mut error_signal = use_signal(|| None::<String>);
+    // Statement id, file name and data URL of the last exported PDF
+    let mut pdf_link = use_signal(|| None::<(i64, String, String)>);
+
+    rsx! {
+        div { id: "statements-page",
+            button {
+                r#type: "button",
+                onclick: move |_| {
+                    let name = name_for_close.clone();
+                    async move {
+                        match close_period_server(name.to_string(), super::api::tab_id()).await {
+                            Ok(statement) => {
+                                message.set(Some(format!(
+                                    "Period {} closed with a balance of {:.2} €",
+                                    statement.period,
+                                    statement.closing_balance,
+                                )));
+                                error_signal.set(None);
+                            }
+                            Err(e) => {
+                                message.set(None);
+                                error_signal.set(Some(format!("Error closing the period: {e}")));
+                            }
+                        }
+                        statements_resource.restart();
+                    }
+                },
+                "Close the current period"
+            }
+
+            if let Some(msg) = &*message.read() {
+                p { class: "no-data-message", "{msg}" }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+
+            match &*statements_resource.read() {
+                None => rsx! {
+                    p { class: "loading-message", "Loading statements..." }
+                },
+                Some(Ok(statements)) => {
+                    if statements.is_empty() {
+                        rsx! {
+                            p { class: "no-data-message", "No statement yet for {name}." }
+                        }
+                    } else {
+                        rsx! {
+                            ul { class: "transactions-list",
+                                for statement in statements.iter().cloned() {
+                                    li { key: "{statement.id}", class: "transaction-card",
+                                        p {
+                                            strong { "Period:" }
+                                            " {statement.period} (closed at {statement.closed_at})"
+                                        }
+                                        p {
+                                            strong { "Opening balance:" }
+                                            " {statement.opening_balance:.2} €"
+                                        }
+                                        p {
+                                            strong { "In:" }
+                                            " {statement.total_in:.2} € "
+                                            strong { "Out:" }
+                                            " {statement.total_out:.2} €"
+                                        }
+                                        p {
+                                            strong { "Closing balance:" }
+                                            " {statement.closing_balance:.2} €"
+                                        }
+                                        p {
+                                            strong { "Transactions:" }
+                                            " {statement.nb_transactions}"
+                                        }
+                                        button {
+                                            r#type: "button",
+                                            onclick: move |_| async move {
+                                                match get_statement_pdf_server(statement.id).await {
+                                                    Ok((file_name, url)) => {
+                                                        pdf_link.set(Some((statement.id, file_name, url)));
+                                                        error_signal.set(None);
+                                                    }
+                                                    Err(e) => {
+                                                        error_signal.set(Some(format!("Error exporting the statement: {e}")));
+                                                    }
+                                                }
+                                            },
+                                            "Export PDF"
+                                        }
+                                        if let Some((id, file_name, url)) = &*pdf_link.read() {
+                                            if *id == statement.id {
+                                                a { href: "{url}", download: "{file_name}", "Download {file_name}" }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading statements: {e}" }
+                },
+            }
+        }
+    }
+}
+
+/// Server function to retrieve the statements of a user
+#[server]
+async fn get_statements_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Vec<Statement>, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    Ok(crate::statements::get_statements(&name)?)
+}
+
+/// Server function to close the current accounting period of a user
+#[server]
+async fn close_period_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Statement, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    if !crate::db::user_exists(&name)? {
+        return Err(ServerFnError::new(format!("Unknown user: {name}")));
+    }
+    let period = crate::statements::current_period();
+    Ok(crate::statements::close_period(&name, &period)?)
+}
+
+/// Server function to export a statement as a PDF document
+///
+/// Returns the file name of the document and its content as a data URL.
+#[server]
+async fn get_statement_pdf_server(id: i64) -> Result<(String, String), ServerFnError> {
+    let Some((statement, pdf)) = crate::statements::statement_pdf(id)? else {
+        return Err(ServerFnError::new(format!("Unknown statement: {id}")));
+    };
+    let file_name = format!("statement_{}_{}.pdf", statement.user, statement.period);
+    let url = format!(
+        "data:application/pdf;base64,{}",
+        crate::utils::base64_encode(&pdf)
+    );
+    Ok((file_name, url))
+}
@@ -0,0 +1,178 @@
+//! API token administration for the Peillute application
+//!
+//! This module provides the page used to mint and revoke the tokens of the
+//! machine clients. Tokens are local to the site: they only give access to
+//! the web server of the site that minted them.
+
+use crate::api_tokens::{ApiToken, TokenScope};
+use dioxus::prelude::*;
+
+/// API token administration component
+///
+/// Lists the tokens of the site and provides:
+/// - A form to mint a read-only token, a token transacting as a user, or an
+///   admin token managing the site
+/// - The secret of the token just minted, shown only once
+/// - Revoke buttons to disable a token
+#[component]
+pub fn Tokens() -> Element {
+    let mut tokens_resource = use_resource(get_tokens_server);
+    let mut label_input = use_signal(|| "".to_string());
+    let mut user_input = use_signal(|| "".to_string());
+    let mut admin_input = use_signal(|| false);
+    let mut secret_signal = use_signal(|| None::<String>);
+    let mut error_signal = use_signal(|| None::<String>);
+
+    rsx! {
+        div { id: "tokens-page",
+            match &*tokens_resource.read() {
+                None => rsx! {
+                    p { "Loading tokens..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading tokens: {e}" }
+                },
+                Some(Ok(tokens)) if tokens.is_empty() => rsx! {
+                    p { class: "no-data-message", "No API token on this site." }
+                },
+                Some(Ok(tokens)) => rsx! {
+                    table { class: "tokens-table",
+                        thead {
+                            tr {
+                                th { "Label" }
+                                th { "Scope" }
+                                th { "Created" }
+                                th { "Last used" }
+                                th { "" }
+                            }
+                        }
+                        tbody {
+                            for token in tokens.iter() {
+                                tr { key: "{token.id}",
+                                    td { "{token.label}" }
+                                    td { "{token.scope}" }
+                                    td { "{token.created_at}" }
+                                    {
+                                        let last_used = token.last_used_at.as_deref().unwrap_or("never");
+                                        rsx! {
+                                            td { "{last_used}" }
+                                        }
+                                    }
+                                    td {
+                                        if token.revoked {
+                                            "Revoked"
+                                        } else {
+                                            {
+                                                let id = token.id;
+                                                rsx! {
+                                                    button {
+                                                        r#type: "button",
+                                                        onclick: move |_| async move {
+                                                            match revoke_token_server(id).await {
+                                                                Ok(()) => {
+                                                                    error_signal.set(None);
+                                                                    tokens_resource.restart();
+                                                                }
+                                                                Err(e) => error_signal.set(Some(format!("{e}"))),
+                                                            }
+                                                        },
+                                                        "Revoke"
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+            form {
+                label { r#for: "token-label", "Label:" }
+                input {
+                    r#type: "text",
+                    id: "token-label",
+                    placeholder: "Vending machine",
+                    value: label_input,
+                    oninput: move |event| label_input.set(event.value()),
+                }
+                label { r#for: "token-user", "Transact as:" }
+                input {
+                    r#type: "text",
+                    id: "token-user",
+                    placeholder: "Nobody, read-only",
+                    value: user_input,
+                    disabled: admin_input(),
+                    oninput: move |event| user_input.set(event.value()),
+                }
+                label { r#for: "token-admin", "Admin:" }
+                input {
+                    r#type: "checkbox",
+                    id: "token-admin",
+                    checked: admin_input(),
+                    oninput: move |event| admin_input.set(event.checked()),
+                }
+                button {
+                    r#type: "submit",
+                    onclick: move |_| async move {
+                        let user = user_input.read().trim().to_string();
+                        let scope = if admin_input() {
+                            TokenScope::Admin
+                        } else if user.is_empty() {
+                            TokenScope::ReadOnly
+                        } else {
+                            TokenScope::TransactAs(user)
+                        };
+                        match mint_token_server(label_input.read().clone(), scope).await {
+                            Ok(secret) => {
+                                label_input.set("".to_string());
+                                user_input.set("".to_string());
+                                admin_input.set(false);
+                                error_signal.set(None);
+                                secret_signal.set(Some(secret));
+                                tokens_resource.restart();
+                            }
+                            Err(e) => error_signal.set(Some(format!("{e}"))),
+                        }
+                    },
+                    "Mint token"
+                }
+            }
+            if let Some(secret) = &*secret_signal.read() {
+                div { class: "minted-token",
+                    p { "Copy this token now, it will not be shown again:" }
+                    code { "{secret}" }
+                }
+            }
+            if let Some(error) = &*error_signal.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+    }
+}
+
+/// Server function to retrieve the API tokens of the site
+#[server]
+async fn get_tokens_server() -> Result<Vec<ApiToken>, ServerFnError> {
+    super::api::authorize_admin().await?;
+    Ok(crate::api_tokens::get_tokens()?)
+}
+
+/// Server function to mint an API token, returns its secret
+#[server]
+async fn mint_token_server(label: String, scope: TokenScope) -> Result<String, ServerFnError> {
+    super::api::authorize_admin().await?;
+    crate::api_tokens::mint(&label, &scope).map_err(|e| ServerFnError::new(e.to_string()))
+}
+
+/// Server function to revoke an API token
+#[server]
+async fn revoke_token_server(id: i64) -> Result<(), ServerFnError> {
+    super::api::authorize_admin().await?;
+    if crate::api_tokens::revoke(id)? {
+        Ok(())
+    } else {
+        Err(ServerFnError::new(format!("No token {id}.")))
+    }
+}
@@ -5,6 +5,11 @@
 //! various transaction operations.
 
 use crate::Route;
+use crate::favorites::Favorite;
+use crate::live::LiveEvent;
+use crate::offline::PendingLocalCommand;
+use crate::refund_requests::{RefundRequest, RefundRequestStatus};
+use crate::settings::{CurrencyDisplay, MAX_HISTORY_PAGE_SIZE, UserSettings};
 use dioxus::prelude::*;
 
 /// User management component
@@ -17,24 +22,164 @@ use dioxus::prelude::*;
 /// - Processing refunds
 /// - Transferring money
 /// - Making deposits
+/// - Splitting a bill between several users
+/// - Viewing and exporting account statements
+///
+/// Also lets the user configure a low balance alert threshold and their UI
+/// preferences, rename the account, save favorite transfers and execute them
+/// in one click, answer the refund requests of the transfers they received,
+/// and displays the alerts received through the live-update channel. The
+/// transactions queued while the site was offline are listed until they are
+/// sent, with the reason of those rejected when the network came back.
+///
+/// The UI preferences are provided as a context to the pages of the user.
+///
+/// The tab of the browser session is made to act as the user before the
+/// pages are shown, see [`crate::sessions`].
 #[component]
 pub fn User(name: String) -> Element {
+    let session_resource = use_resource(use_reactive((&name,), |(name,)| async move {
+        let tab = super::api::load_tab_id().await;
+        super::api::enter_user_server(name, tab).await
+    }));
+
+    match &*session_resource.read() {
+        None => rsx! {
+            p { "Loading..." }
+        },
+        Some(Err(e)) => rsx! {
+            p { class: "error-message", "Cannot open the pages of {name}: {e}" }
+        },
+        Some(Ok(())) => rsx! {
+            UserPages { key: "{name}", name: name.clone() }
+        },
+    }
+}
+
+/// Pages of the acting user of the session
+#[component]
+fn UserPages(name: String) -> Element {
     let mut solde = use_signal(|| 0f64);
+    let mut threshold = use_signal(|| None::<f64>);
+    let mut threshold_input = use_signal(|| "".to_string());
+    let mut settings_message = use_signal(|| None::<String>);
+    let mut alerts = use_signal(Vec::<LiveEvent>::new);
+    let mut rename_input = use_signal(|| "".to_string());
+    let mut renamed = use_signal(|| None::<(String, u64)>);
+    let mut rename_error = use_signal(|| None::<String>);
+    let mut favorite_beneficiary = use_signal(|| "".to_string());
+    let mut favorite_amount = use_signal(|| "".to_string());
+    let mut favorite_message = use_signal(|| "".to_string());
+    let mut favorite_ticket = use_signal(|| None::<u64>);
+    let mut favorite_error = use_signal(|| None::<String>);
+    let mut refund_ticket = use_signal(|| None::<u64>);
+    let mut refund_error = use_signal(|| None::<String>);
+    let mut settings = use_context_provider(|| Signal::new(UserSettings::default()));
+    let mut page_size_input = use_signal(|| "".to_string());
+    let mut preferences_message = use_signal(|| None::<String>);
 
     let name = std::rc::Rc::new(name);
     let name_for_future = name.clone();
+    let name_for_threshold = name.clone();
+    let name_for_live = name.clone();
+    let name_for_save = name.clone();
+    let name_for_clear = name.clone();
+    let name_for_rename = name.clone();
+    let name_for_favorites = name.clone();
+    let name_for_new_favorite = name.clone();
+    let name_for_refunds = name.clone();
+    let name_for_offline = name.clone();
+    let name_for_settings = name.clone();
+    let name_for_dark_mode = name.clone();
+    let name_for_currency = name.clone();
+    let name_for_page_size = name.clone();
+
+    let mut favorites_resource = use_resource(move || {
+        let name = name_for_favorites.clone();
+        async move { get_favorites_server(name.to_string(), super::api::tab_id()).await }
+    });
+
+    let mut refund_requests_resource = use_resource(move || {
+        let name = name_for_refunds.clone();
+        async move { get_refund_requests_server(name.to_string(), super::api::tab_id()).await }
+    });
+
+    let mut offline_resource = use_resource(move || {
+        let name = name_for_offline.clone();
+        async move { get_offline_queue_server(name.to_string(), super::api::tab_id()).await }
+    });
 
     {
         use_future(move || {
             let name = name_for_future.clone();
             async move {
-                if let Ok(data) = get_solde(name.to_string()).await {
+                if let Ok(data) = get_solde(name.to_string(), super::api::tab_id()).await {
                     solde.set(data);
                 }
             }
         });
     }
 
+    {
+        use_future(move || {
+            let name = name_for_settings.clone();
+            async move {
+                if let Ok(data) = get_settings_server(name.to_string(), super::api::tab_id()).await
+                {
+                    settings.set(data);
+                    page_size_input.set(data.history_page_size.to_string());
+                }
+            }
+        });
+        // The dark colors are applied to the whole document while the pages
+        // of the user are open
+        use_effect(move || {
+            let dark_mode = settings().dark_mode;
+            document::eval(&format!(
+                "document.documentElement.classList.toggle('dark-theme', {dark_mode});"
+            ));
+        });
+        use_drop(|| {
+            document::eval("document.documentElement.classList.remove('dark-theme');");
+        });
+    }
+
+    {
+        use_future(move || {
+            let name = name_for_threshold.clone();
+            async move {
+                if let Ok(data) =
+                    get_alert_threshold_server(name.to_string(), super::api::tab_id()).await
+                {
+                    threshold.set(data);
+                    threshold_input.set(data.map(|t| t.to_string()).unwrap_or_default());
+                }
+            }
+        });
+    }
+
+    {
+        // Long-poll the live-update channel for the alerts of this user
+        use_future(move || {
+            let name = name_for_live.clone();
+            async move {
+                let mut last_id = None;
+                while let Ok((id, events)) =
+                    wait_for_live_events_server(name.to_string(), last_id, super::api::tab_id())
+                        .await
+                {
+                    last_id = Some(id);
+                    if !events.is_empty() {
+                        if let Ok(data) = get_solde(name.to_string(), super::api::tab_id()).await {
+                            solde.set(data);
+                        }
+                        alerts.write().extend(events);
+                    }
+                }
+            }
+        });
+    }
+
     let history_route = Route::History {
         name: name.to_string(),
     };
@@ -53,11 +198,463 @@ pub fn User(name: String) -> Element {
     let deposit_route = Route::Deposit {
         name: name.to_string(),
     };
+    let split_bill_route = Route::SplitBill {
+        name: name.to_string(),
+    };
+    let statements_route = Route::Statements {
+        name: name.to_string(),
+    };
 
     rsx! {
         div { id: "user-info",
             h1 { "Welcome {name}!" }
-            h2 { "{solde()} €" }
+            h2 { "{settings().currency.format(solde())}" }
+            for (i , alert) in alerts.read().iter().enumerate() {
+                match alert {
+                    LiveEvent::BalanceAlert { balance, threshold, .. } => rsx! {
+                        p { key: "{i}", class: "error-message",
+                            "⚠️ Low balance: {balance} € left, below your alert threshold of {threshold} €"
+                        }
+                    },
+                }
+            }
+        }
+        div { id: "user-settings",
+            label { r#for: "alert-threshold", "Low balance alert (€):" }
+            input {
+                id: "alert-threshold",
+                r#type: "text",
+                inputmode: "decimal",
+                placeholder: "No alert",
+                value: "{threshold_input}",
+                oninput: move |event| threshold_input.set(event.value()),
+            }
+            button {
+                r#type: "button",
+                onclick: move |_| {
+                    let name = name_for_save.clone();
+                    async move {
+                        let input = threshold_input();
+                        let value = match crate::amount::parse(
+                            &input,
+                            &crate::amount::AmountLimits::UNBOUNDED,
+                        ) {
+                            Ok(value) => value,
+                            Err(e) => {
+                                settings_message.set(Some(format!("Invalid threshold: {e}")));
+                                return;
+                            }
+                        };
+                        match set_alert_threshold_server(name.to_string(), Some(value), super::api::tab_id()).await {
+                            Ok(()) => {
+                                threshold.set(Some(value));
+                                settings_message.set(Some(format!("Alert set below {value} €")));
+                            }
+                            Err(e) => settings_message.set(Some(format!("Error: {e}"))),
+                        }
+                    }
+                },
+                "Save"
+            }
+            if threshold().is_some() {
+                button {
+                    r#type: "button",
+                    onclick: move |_| {
+                        let name = name_for_clear.clone();
+                        async move {
+                            match set_alert_threshold_server(name.to_string(), None, super::api::tab_id()).await {
+                                Ok(()) => {
+                                    threshold.set(None);
+                                    threshold_input.set("".to_string());
+                                    settings_message.set(Some("Alert disabled".to_string()));
+                                }
+                                Err(e) => settings_message.set(Some(format!("Error: {e}"))),
+                            }
+                        }
+                    },
+                    "Disable"
+                }
+            }
+            if let Some(msg) = &*settings_message.read() {
+                span { "{msg}" }
+            }
+        }
+        div { id: "user-preferences",
+            label { r#for: "dark-mode", "Dark mode:" }
+            input {
+                id: "dark-mode",
+                r#type: "checkbox",
+                checked: settings().dark_mode,
+                onchange: move |event| {
+                    let name = name_for_dark_mode.clone();
+                    let new_settings = UserSettings {
+                        dark_mode: event.checked(),
+                        ..settings()
+                    };
+                    async move {
+                        save_settings(name.to_string(), new_settings, settings, preferences_message)
+                            .await;
+                    }
+                },
+            }
+            label { r#for: "currency-display", "Amounts:" }
+            select {
+                id: "currency-display",
+                value: settings().currency.as_str(),
+                onchange: move |event| {
+                    let name = name_for_currency.clone();
+                    let currency = CurrencyDisplay::parse(&event.value()).unwrap_or_default();
+                    let new_settings = UserSettings {
+                        currency,
+                        ..settings()
+                    };
+                    async move {
+                        save_settings(name.to_string(), new_settings, settings, preferences_message)
+                            .await;
+                    }
+                },
+                option { value: CurrencyDisplay::Symbol.as_str(), "12.50 €" }
+                option { value: CurrencyDisplay::Code.as_str(), "12.50 EUR" }
+            }
+            label { r#for: "history-page-size", "Transactions per page:" }
+            input {
+                id: "history-page-size",
+                r#type: "number",
+                min: "1",
+                max: "{MAX_HISTORY_PAGE_SIZE}",
+                value: "{page_size_input}",
+                oninput: move |event| page_size_input.set(event.value()),
+            }
+            button {
+                r#type: "button",
+                onclick: move |_| {
+                    let name = name_for_page_size.clone();
+                    async move {
+                        let input = page_size_input();
+                        let Ok(history_page_size) = input.trim().parse::<u32>() else {
+                            preferences_message.set(Some(format!("Invalid number: {input}")));
+                            return;
+                        };
+                        let new_settings = UserSettings {
+                            history_page_size,
+                            ..settings()
+                        };
+                        save_settings(name.to_string(), new_settings, settings, preferences_message)
+                            .await;
+                    }
+                },
+                "Save"
+            }
+            if let Some(msg) = &*preferences_message.read() {
+                span { "{msg}" }
+            }
+        }
+        div { id: "user-rename",
+            label { r#for: "rename-user", "Rename:" }
+            input {
+                id: "rename-user",
+                r#type: "text",
+                placeholder: "New user name",
+                value: "{rename_input}",
+                oninput: move |event| rename_input.set(event.value()),
+            }
+            button {
+                r#type: "button",
+                onclick: move |_| {
+                    let name = name_for_rename.clone();
+                    async move {
+                        let new_name = rename_input().trim().to_string();
+                        match rename_user_server(name.to_string(), new_name.clone(), super::api::tab_id()).await {
+                            Ok(ticket) => {
+                                rename_input.set("".to_string());
+                                rename_error.set(None);
+                                renamed.set(Some((new_name, ticket)));
+                            }
+                            Err(e) => rename_error.set(Some(format!("Error: {e}"))),
+                        }
+                    }
+                },
+                "Rename"
+            }
+            if let Some((new_name, ticket)) = renamed() {
+                super::actions::TransactionConfirmation { ticket }
+                Link {
+                    to: Route::History {
+                        name: new_name.clone(),
+                    },
+                    "Go to {new_name}"
+                }
+            }
+            if let Some(error) = &*rename_error.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+        div { id: "user-favorites",
+            h3 { "Favorites" }
+            match &*favorites_resource.read() {
+                None => rsx! {
+                    p { "Loading favorites..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading favorites: {e}" }
+                },
+                Some(Ok(favorites)) if favorites.is_empty() => rsx! {
+                    p { class: "no-data-message", "No favorite transfer yet." }
+                },
+                Some(Ok(favorites)) => rsx! {
+                    ul { class: "favorites-list",
+                        for favorite in favorites.iter() {
+                            {
+                                let favorite_for_send = favorite.clone();
+                                let favorite_for_delete = favorite.clone();
+                                rsx! {
+                                    li { key: "{favorite.id}",
+                                        button {
+                                            r#type: "button",
+                                            onclick: move |_| {
+                                                let favorite = favorite_for_send.clone();
+                                                async move {
+                                                    match execute_favorite_server(favorite.user, favorite.id, super::api::tab_id()).await {
+                                                        Ok(ticket) => {
+                                                            favorite_error.set(None);
+                                                            favorite_ticket.set(Some(ticket));
+                                                        }
+                                                        Err(e) => favorite_error.set(Some(format!("Error: {e}"))),
+                                                    }
+                                                }
+                                            },
+                                            "Send {favorite.amount:.2} € to {favorite.beneficiary}"
+                                        }
+                                        if let Some(message) = &favorite.message {
+                                            span { "{message}" }
+                                        }
+                                        button {
+                                            r#type: "button",
+                                            class: "delete-btn",
+                                            title: "Delete favorite",
+                                            onclick: move |_| {
+                                                let favorite = favorite_for_delete.clone();
+                                                async move {
+                                                    match delete_favorite_server(favorite.user, favorite.id, super::api::tab_id()).await {
+                                                        Ok(()) => {
+                                                            favorite_error.set(None);
+                                                            favorites_resource.restart();
+                                                        }
+                                                        Err(e) => favorite_error.set(Some(format!("Error: {e}"))),
+                                                    }
+                                                }
+                                            },
+                                            "X"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+            form {
+                label { r#for: "favorite-beneficiary", "Beneficiary:" }
+                input {
+                    id: "favorite-beneficiary",
+                    r#type: "text",
+                    value: "{favorite_beneficiary}",
+                    oninput: move |event| favorite_beneficiary.set(event.value()),
+                }
+                label { r#for: "favorite-amount", "Amount (€):" }
+                input {
+                    id: "favorite-amount",
+                    r#type: "text",
+                    inputmode: "decimal",
+                    placeholder: "0.00",
+                    value: "{favorite_amount}",
+                    oninput: move |event| favorite_amount.set(event.value()),
+                }
+                label { r#for: "favorite-message", "Message (optional):" }
+                input {
+                    id: "favorite-message",
+                    r#type: "text",
+                    value: "{favorite_message}",
+                    oninput: move |event| favorite_message.set(event.value()),
+                }
+                button {
+                    r#type: "submit",
+                    onclick: move |_| {
+                        let name = name_for_new_favorite.clone();
+                        async move {
+                            let input = favorite_amount();
+                            let amount = match crate::amount::parse(
+                                &input,
+                                &crate::amount::AmountLimits::default(),
+                            ) {
+                                Ok(amount) => amount,
+                                Err(e) => {
+                                    favorite_error.set(Some(e));
+                                    return;
+                                }
+                            };
+                            let message = favorite_message().trim().to_string();
+                            let favorite = Favorite {
+                                id: 0,
+                                user: name.to_string(),
+                                beneficiary: favorite_beneficiary().trim().to_string(),
+                                amount,
+                                message: (!message.is_empty()).then_some(message),
+                            };
+                            match add_favorite_server(favorite, super::api::tab_id()).await {
+                                Ok(()) => {
+                                    favorite_beneficiary.set("".to_string());
+                                    favorite_amount.set("".to_string());
+                                    favorite_message.set("".to_string());
+                                    favorite_error.set(None);
+                                    favorites_resource.restart();
+                                }
+                                Err(e) => favorite_error.set(Some(format!("Error: {e}"))),
+                            }
+                        }
+                    },
+                    "Save favorite"
+                }
+            }
+            if let Some(ticket) = favorite_ticket() {
+                super::actions::TransactionConfirmation { ticket }
+            }
+            if let Some(error) = &*favorite_error.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+        div { id: "user-refund-requests",
+            h3 { "Refund requests" }
+            match &*refund_requests_resource.read() {
+                None => rsx! {
+                    p { "Loading refund requests..." }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error-message", "Error loading refund requests: {e}" }
+                },
+                Some(Ok(requests)) if requests.is_empty() => rsx! {
+                    p { class: "no-data-message", "No refund request." }
+                },
+                Some(Ok(requests)) => rsx! {
+                    ul { class: "refund-requests-list",
+                        for request in requests.iter() {
+                            {
+                                let status = request.status.as_str();
+                                let to_answer = request.approver == *name
+                                    && request.status == RefundRequestStatus::Pending;
+                                let request_for_approve = request.clone();
+                                let request_for_reject = request.clone();
+                                rsx! {
+                                    li { key: "{request.source_node}-{request.lamport_time}",
+                                        if request.approver == *name {
+                                            span {
+                                                "{request.requester} asks you to refund {request.amount:.2} € (transaction {request.transac_node}-{request.transac_time}): {status}"
+                                            }
+                                        } else {
+                                            span {
+                                                "You asked {request.approver} to refund {request.amount:.2} € (transaction {request.transac_node}-{request.transac_time}): {status}"
+                                            }
+                                        }
+                                        if to_answer {
+                                            button {
+                                                r#type: "button",
+                                                onclick: move |_| {
+                                                    let request = request_for_approve.clone();
+                                                    async move {
+                                                        match approve_refund_server(
+                                                                request.approver,
+                                                                request.lamport_time,
+                                                                request.source_node,
+                                                                super::api::tab_id(),
+                                                            )
+                                                            .await
+                                                        {
+                                                            Ok(ticket) => {
+                                                                refund_error.set(None);
+                                                                refund_ticket.set(Some(ticket));
+                                                                refund_requests_resource.restart();
+                                                            }
+                                                            Err(e) => refund_error.set(Some(format!("Error: {e}"))),
+                                                        }
+                                                    }
+                                                },
+                                                "Approve"
+                                            }
+                                            button {
+                                                r#type: "button",
+                                                class: "delete-btn",
+                                                onclick: move |_| {
+                                                    let request = request_for_reject.clone();
+                                                    async move {
+                                                        match reject_refund_server(
+                                                                request.approver,
+                                                                request.lamport_time,
+                                                                request.source_node,
+                                                                super::api::tab_id(),
+                                                            )
+                                                            .await
+                                                        {
+                                                            Ok(ticket) => {
+                                                                refund_error.set(None);
+                                                                refund_ticket.set(Some(ticket));
+                                                                refund_requests_resource.restart();
+                                                            }
+                                                            Err(e) => refund_error.set(Some(format!("Error: {e}"))),
+                                                        }
+                                                    }
+                                                },
+                                                "Reject"
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+            }
+            if let Some(ticket) = refund_ticket() {
+                super::actions::TransactionConfirmation { ticket }
+            }
+            if let Some(error) = &*refund_error.read() {
+                p { class: "error-message", "{error}" }
+            }
+        }
+        if let Some(Ok(queued)) = &*offline_resource.read() {
+            if !queued.is_empty() {
+                div { id: "user-offline-queue",
+                    h3 { "Waiting for the network" }
+                    ul { class: "offline-queue-list",
+                        for pending in queued.iter() {
+                            {
+                                let pending_for_dismiss = pending.clone();
+                                rsx! {
+                                    li { key: "{pending.id}",
+                                        class: if pending.error.is_some() { "offline-rejected" } else { "offline-pending" },
+                                        span { "📴 {pending.description}, queued at {pending.queued_at}" }
+                                        if let Some(error) = &pending.error {
+                                            span { class: "error-message", " Rejected: {error}" }
+                                        }
+                                        button {
+                                            r#type: "button",
+                                            class: "delete-btn",
+                                            onclick: move |_| {
+                                                let pending = pending_for_dismiss.clone();
+                                                async move {
+                                                    if dismiss_offline_server(pending.user, pending.id, super::api::tab_id()).await.is_ok() {
+                                                        offline_resource.restart();
+                                                    }
+                                                }
+                                            },
+                                            "Dismiss"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
         }
         div { id: "user-page",
             Link { to: history_route, "History" }
@@ -66,15 +663,292 @@ pub fn User(name: String) -> Element {
             Link { to: refund_route, "Refund" }
             Link { to: transfer_route, "Transfer" }
             Link { to: deposit_route, "Deposit" }
+            Link { to: split_bill_route, "Split bill" }
+            Link { to: statements_route, "Statements" }
         }
         Outlet::<Route> {}
     }
 }
 
 /// Server function to retrieve a user's current balance
-#[server]
-async fn get_solde(name: String) -> Result<f64, ServerFnError> {
+///
+/// Its endpoint is fixed, for the holders of API tokens.
+#[server(endpoint = "balance")]
+async fn get_solde(name: String, tab: Option<String>) -> Result<f64, ServerFnError> {
     use crate::db;
+    super::api::authorize_user(&name, tab.as_deref()).await?;
     let solde = db::calculate_solde(&name)?;
     Ok(solde)
 }
+
+/// Server function to retrieve the refund requests made by a user or
+/// addressed to them
+#[server]
+async fn get_refund_requests_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Vec<RefundRequest>, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    Ok(crate::refund_requests::get_requests(&name)?)
+}
+
+/// Server function to approve a refund request addressed to a user
+///
+/// The refund is then executed and broadcast like the other transactions.
+#[server]
+async fn approve_refund_server(
+    name: String,
+    lamport_time: i64,
+    source_node: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    let request = crate::refund_requests::approve(&name, lamport_time, &source_node)
+        .map_err(|e| ServerFnError::new(e.to_string()))?;
+    crate::control::enqueue_critical(crate::control::CriticalCommands::Refund {
+        name,
+        lamport: request.transac_time,
+        node: request.transac_node,
+    })
+    .await
+    .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to refund : {e}")))
+}
+
+/// Server function to reject a refund request addressed to a user
+#[server]
+async fn reject_refund_server(
+    name: String,
+    lamport_time: i64,
+    source_node: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    crate::control::enqueue_critical(crate::control::CriticalCommands::RejectRefund {
+        name,
+        lamport: lamport_time,
+        node: source_node,
+    })
+    .await
+    .map_err(|e| ServerFnError::new(format!("[SERVER] Failed to reject the refund : {e}")))
+}
+
+/// Server function to retrieve the transactions queued offline by a user
+#[server]
+async fn get_offline_queue_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Vec<PendingLocalCommand>, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    Ok(crate::offline::get_queued(&name)?)
+}
+
+/// Server function to drop a transaction queued offline by a user
+#[server]
+async fn dismiss_offline_server(
+    name: String,
+    id: i64,
+    tab: Option<String>,
+) -> Result<(), ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    if !crate::offline::dismiss(&name, id)? {
+        return Err(ServerFnError::new("Queued transaction not found."));
+    }
+    Ok(())
+}
+
+/// Server function to rename a user
+///
+/// The rename is applied once the site holds the global mutex, then
+/// broadcast to all nodes in the network.
+#[server]
+async fn rename_user_server(
+    name: String,
+    new_name: String,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    if new_name.is_empty() {
+        return Err(ServerFnError::new("User name cannot be empty."));
+    }
+    if crate::db::user_exists(&new_name)? {
+        return Err(ServerFnError::new(format!(
+            "User '{new_name}' already exists."
+        )));
+    }
+    crate::control::enqueue_critical(crate::control::CriticalCommands::RenameUser {
+        name,
+        new_name,
+    })
+    .await
+    .map_err(|e| ServerFnError::new(format!("Failed to diffuse the rename user message: {e}")))
+}
+
+/// Server function to set the low balance alert threshold of a user
+///
+/// A None threshold disables the alert.
+#[server]
+async fn set_alert_threshold_server(
+    name: String,
+    threshold: Option<f64>,
+    tab: Option<String>,
+) -> Result<(), ServerFnError> {
+    use crate::db;
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    if let Some(t) = threshold
+        && !t.is_finite()
+    {
+        return Err(ServerFnError::new("Threshold must be a finite number."));
+    }
+    db::set_alert_threshold(&name, threshold)?;
+    Ok(())
+}
+
+/// Server function to retrieve the low balance alert threshold of a user
+#[server]
+async fn get_alert_threshold_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Option<f64>, ServerFnError> {
+    use crate::db;
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    Ok(db::get_alert_threshold(&name)?)
+}
+
+/// Saves the UI preferences of a user and applies them to its pages
+async fn save_settings(
+    name: String,
+    new_settings: UserSettings,
+    mut settings: Signal<UserSettings>,
+    mut message: Signal<Option<String>>,
+) {
+    match set_settings_server(name, new_settings, super::api::tab_id()).await {
+        Ok(()) => {
+            settings.set(new_settings);
+            message.set(Some("Preferences saved".to_string()));
+        }
+        Err(e) => message.set(Some(format!("Error: {e}"))),
+    }
+}
+
+/// Server function to save the UI preferences of a user
+#[server]
+async fn set_settings_server(
+    name: String,
+    settings: UserSettings,
+    tab: Option<String>,
+) -> Result<(), ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    if !crate::db::user_exists(&name)? {
+        return Err(ServerFnError::new(format!("User '{name}' does not exist.")));
+    }
+    if settings.history_page_size == 0 || settings.history_page_size > MAX_HISTORY_PAGE_SIZE {
+        return Err(ServerFnError::new(format!(
+            "The number of transactions per page must be between 1 and {MAX_HISTORY_PAGE_SIZE}."
+        )));
+    }
+    crate::settings::set_settings(&name, &settings)?;
+    Ok(())
+}
+
+/// Server function to retrieve the UI preferences of a user
+#[server]
+async fn get_settings_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<UserSettings, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    Ok(crate::settings::get_settings(&name)?)
+}
+
+/// Server function to wait for the live events of a user
+///
+/// Without `after_id`, returns immediately with the identifier to poll from,
+/// so that a freshly opened page does not replay older alerts.
+#[server]
+async fn wait_for_live_events_server(
+    name: String,
+    after_id: Option<u64>,
+    tab: Option<String>,
+) -> Result<(u64, Vec<LiveEvent>), ServerFnError> {
+    const LIVE_POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(25);
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    match after_id {
+        None => Ok((
+            crate::live::LIVE_UPDATES.lock().unwrap().last_id(),
+            Vec::new(),
+        )),
+        Some(after_id) => {
+            Ok(crate::live::wait_for_events(after_id, &name, LIVE_POLL_TIMEOUT).await)
+        }
+    }
+}
+
+/// Server function to retrieve the favorite transfers of a user
+#[server]
+async fn get_favorites_server(
+    name: String,
+    tab: Option<String>,
+) -> Result<Vec<Favorite>, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    Ok(crate::favorites::get_favorites(&name)?)
+}
+
+/// Server function to save a favorite transfer
+///
+/// Favorites are local to the site, so the new favorite is not broadcast.
+#[server]
+async fn add_favorite_server(favorite: Favorite, tab: Option<String>) -> Result<(), ServerFnError> {
+    super::api::authorize_user(&favorite.user, tab.as_deref()).await?;
+    if !favorite.amount.is_finite() || favorite.amount <= 0.0 {
+        return Err(ServerFnError::new("Amount must be positive."));
+    }
+    if favorite.beneficiary == favorite.user {
+        return Err(ServerFnError::new(
+            "A user cannot transfer money to itself.",
+        ));
+    }
+    for user in [&favorite.user, &favorite.beneficiary] {
+        if crate::db::is_system_account(user) || !crate::db::user_exists(user)? {
+            return Err(ServerFnError::new(format!("User {user} does not exist.")));
+        }
+    }
+    crate::favorites::add_favorite(&favorite)?;
+    Ok(())
+}
+
+/// Server function to remove a favorite transfer of a user
+#[server]
+async fn delete_favorite_server(
+    name: String,
+    id: i64,
+    tab: Option<String>,
+) -> Result<(), ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    if !crate::favorites::delete_favorite(&name, id)? {
+        return Err(ServerFnError::new("Favorite not found."));
+    }
+    Ok(())
+}
+
+/// Server function to execute a favorite transfer of a user
+///
+/// The transfer is applied once the site holds the global mutex, then
+/// broadcast to all nodes in the network, like a transfer made from the form.
+#[server]
+async fn execute_favorite_server(
+    name: String,
+    id: i64,
+    tab: Option<String>,
+) -> Result<u64, ServerFnError> {
+    super::api::authorize_user(&name, tab.as_deref()).await?;
+    let Some(favorite) = crate::favorites::get_favorite(&name, id)? else {
+        return Err(ServerFnError::new("Favorite not found."));
+    };
+    crate::control::enqueue_critical(crate::control::CriticalCommands::Transfer {
+        from: favorite.user,
+        to: favorite.beneficiary,
+        amount: favorite.amount,
+    })
+    .await
+    .map_err(|e| ServerFnError::new(format!("Failed to make the transfer: {e}")))
+}